@@ -0,0 +1,150 @@
+use crate::config::{AlertAction, AlertRule, Alerts};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// ---------------------------------------------------------------------------
+// Thresholds
+// ---------------------------------------------------------------------------
+
+/// An alert that has just fired.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Firing {
+    pub action: AlertAction,
+    /// What went over what, e.g. `cpu 97.2% > 90% for 5m`.
+    pub detail: String,
+}
+
+/// How long one process has been over each of its thresholds. Each alert
+/// fires once per stretch over its threshold and again only after usage
+/// has dropped back below it.
+#[derive(Debug, Default)]
+pub struct Watch {
+    cpu: Breach,
+    memory: Breach,
+}
+
+#[derive(Debug, Default)]
+struct Breach {
+    since: Option<Instant>,
+    fired: bool,
+}
+
+impl Breach {
+    /// Whether usage, now `over` or not, has just stayed over for
+    /// `duration`.
+    fn observe(&mut self, over: bool, duration: Duration, now: Instant) -> bool {
+        if !over {
+            *self = Breach::default();
+            return false;
+        }
+        let since = *self.since.get_or_insert(now);
+        if self.fired || now.saturating_duration_since(since) < duration {
+            return false;
+        }
+        self.fired = true;
+        true
+    }
+}
+
+impl Watch {
+    /// Check a sample against `alerts`. Until there are two samples to tell
+    /// CPU use from, `cpu_percent` is `None` and counts as below.
+    pub fn observe(
+        &mut self,
+        alerts: &Alerts,
+        cpu_percent: Option<f64>,
+        rss_bytes: u64,
+        now: Instant,
+    ) -> Vec<Firing> {
+        let mut firing = Vec::new();
+        if let (Some(rule), Some(above)) = (&alerts.cpu, alerts.cpu_above()) {
+            let cpu = cpu_percent.unwrap_or_default();
+            let over = cpu_percent.is_some_and(|cpu| cpu > above);
+            if self.cpu.observe(over, rule.duration(), now) {
+                firing.push(Firing {
+                    action: rule.action,
+                    detail: format!("cpu {cpu:.1}% > {above}%{}", lasting(rule)),
+                });
+            }
+        }
+        if let (Some(rule), Some(above)) = (&alerts.memory, alerts.memory_above())
+            && self.memory.observe(rss_bytes > above, rule.duration(), now)
+        {
+            firing.push(Firing {
+                action: rule.action,
+                detail: format!(
+                    "memory {:.1}M > {}{}",
+                    rss_bytes as f64 / (1024.0 * 1024.0),
+                    rule.above,
+                    lasting(rule)
+                ),
+            });
+        }
+        firing
+    }
+}
+
+/// ` for 5m`, or nothing for an alert without `for`.
+fn lasting(rule: &AlertRule) -> String {
+    rule.for_
+        .as_ref()
+        .map(|duration| format!(" for {duration}"))
+        .unwrap_or_default()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alerts(toml: &str) -> Alerts {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_fires_once_usage_stays_above() {
+        let alerts = alerts(r#"cpu = { above = 90, for = "5m", action = "restart" }"#);
+        let mut watch = Watch::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // The first sample has no CPU use to compare yet
+        assert!(watch.observe(&alerts, None, 0, at(0)).is_empty());
+        assert!(watch.observe(&alerts, Some(95.0), 0, at(10)).is_empty());
+        assert!(watch.observe(&alerts, Some(99.0), 0, at(200)).is_empty());
+        assert_eq!(
+            watch.observe(&alerts, Some(97.24), 0, at(310)),
+            [Firing {
+                action: AlertAction::Restart,
+                detail: "cpu 97.2% > 90% for 5m".to_string(),
+            }]
+        );
+        // Still above: it has fired already
+        assert!(watch.observe(&alerts, Some(97.0), 0, at(700)).is_empty());
+
+        // Dropping below starts the clock over
+        assert!(watch.observe(&alerts, Some(50.0), 0, at(710)).is_empty());
+        assert!(watch.observe(&alerts, Some(95.0), 0, at(720)).is_empty());
+        assert!(watch.observe(&alerts, Some(95.0), 0, at(900)).is_empty());
+        assert_eq!(watch.observe(&alerts, Some(95.0), 0, at(1020)).len(), 1);
+    }
+
+    #[test]
+    fn test_memory_without_for_fires_at_once() {
+        let alerts = alerts(r#"memory = { above = "1M" }"#);
+        let mut watch = Watch::default();
+        let now = Instant::now();
+
+        assert!(watch.observe(&alerts, None, 1 << 20, now).is_empty());
+        assert_eq!(
+            watch.observe(&alerts, None, 3 << 19, now),
+            [Firing {
+                action: AlertAction::Notify,
+                detail: "memory 1.5M > 1M".to_string(),
+            }]
+        );
+    }
+}
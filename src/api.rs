@@ -0,0 +1,651 @@
+use crate::config::ProcessConfig;
+use crate::daemon::{self, LogQuery};
+use crate::log::{LogLevel, LogStream};
+use crate::paths::Paths;
+use crate::process::{Processes, Services};
+use crate::protocol::{self, ProcessEvent, Request, Response};
+use crate::remote;
+use axum::extract::connect_info::Connected;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::serve::{IncomingStream, Listener};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tracing::{error, warn};
+
+// ---------------------------------------------------------------------------
+// HTTP API
+// ---------------------------------------------------------------------------
+//
+// With `listen_http` the daemon also answers HTTP, so dashboards, scripts
+// with curl and other languages can drive it without the pm3 binary. Each
+// route is a socket request under another name: it is carried out, logged
+// and audited the same way, and answered with the same JSON response. Every
+// call sends the daemon's `auth_token` as `Authorization: Bearer`, so the API
+// speaks TLS with `tls_cert` and `tls_key`, and listens only on a loopback
+// address without them.
+//
+// The `/ws/` routes upgrade to a WebSocket and stream instead: a process's log
+// lines, or every lifecycle event as it is recorded, one JSON text frame each.
+// Browsers cannot set headers on a WebSocket, so these also take `?token=`.
+
+type HttpResponse = axum::response::Response;
+
+/// What the API's handlers share with the daemon.
+#[derive(Clone)]
+pub struct Api {
+    pub processes: Processes,
+    pub paths: Paths,
+    pub services: Services,
+    pub shutdown_tx: watch::Sender<bool>,
+    pub started_at: Instant,
+    /// The `auth_token` every call must carry.
+    pub token: Arc<str>,
+}
+
+impl Api {
+    async fn answer(&self, request: Request, client: SocketAddr) -> HttpResponse {
+        let response = daemon::answer(
+            request,
+            client,
+            &self.shutdown_tx,
+            &self.processes,
+            &self.paths,
+            &self.services,
+            self.started_at,
+        )
+        .await;
+        reply(response)
+    }
+}
+
+/// Serve the API on `listener` until the daemon stops, over TLS when
+/// `tls_cert` and `tls_key` give an acceptor.
+pub async fn serve(listener: TcpListener, tls: Option<TlsAcceptor>, api: Api) {
+    let app = router(api).into_make_service_with_connect_info::<Peer>();
+    let served = match tls {
+        Some(acceptor) => match TlsListener::new(listener, acceptor) {
+            Ok(listener) => axum::serve(listener, app).await,
+            Err(e) => Err(e),
+        },
+        None => axum::serve(listener, app).await,
+    };
+    if let Err(e) = served {
+        error!("http api stopped: {e}");
+    }
+}
+
+/// How long a client gets to finish its TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The API's listener with TLS: connections reach axum once their handshake
+/// is done. Handshakes run on tasks of their own, so a client that stalls
+/// one holds up no other.
+struct TlsListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    fn new(listener: TcpListener, acceptor: TlsAcceptor) -> std::io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (handshaken, accepted) = mpsc::channel(64);
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = tokio::select! {
+                    // The API stopped serving
+                    _ = handshaken.closed() => return,
+                    accept = listener.accept() => match accept {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!("http api accept failed: {e}");
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    },
+                };
+                let (acceptor, handshaken) = (acceptor.clone(), handshaken.clone());
+                tokio::spawn(async move {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+                    {
+                        Ok(Ok(stream)) => {
+                            let _ = handshaken.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => warn!("tls handshake with {addr} failed: {e}"),
+                        Err(_) => {
+                            warn!("no tls handshake from {addr} within {TLS_HANDSHAKE_TIMEOUT:?}")
+                        }
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            local_addr,
+            accepted,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.accepted.recv().await {
+            Some(accepted) => accepted,
+            // The accepting task only stops once this listener is gone
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// The address of the client on the other end, over plain HTTP or TLS.
+#[derive(Debug, Clone, Copy)]
+struct Peer(SocketAddr);
+
+impl Connected<IncomingStream<'_, TcpListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Peer(*stream.remote_addr())
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        Peer(*stream.remote_addr())
+    }
+}
+
+fn router(api: Api) -> Router {
+    Router::new()
+        .route("/ping", get(ping))
+        .route("/status", get(status))
+        .route("/processes", get(list).post(start))
+        .route("/processes/{name}", get(info))
+        .route("/processes/{name}/stop", post(stop))
+        .route("/processes/{name}/restart", post(restart))
+        .route("/processes/{name}/reload", post(reload))
+        .route("/processes/{name}/signal", post(signal))
+        .route("/processes/{name}/scale", post(scale))
+        .route("/processes/{name}/flush", post(flush))
+        .route("/processes/{name}/logs", get(logs))
+        .route("/processes/{name}/events", get(process_events))
+        .route("/processes/{name}/history", get(history))
+        .route("/processes/{name}/samples", get(samples))
+        .route("/events", get(events))
+        .route("/audit", get(audit))
+        .route("/save", post(save))
+        .route("/resurrect", post(resurrect))
+        .route("/ws/logs/{name}", get(ws_logs))
+        .route("/ws/events", get(ws_events))
+        .layer(middleware::from_fn_with_state(api.clone(), authorize))
+        .with_state(api)
+}
+
+/// The socket protocol's response as the body; errors are a 400.
+fn reply(response: Response) -> HttpResponse {
+    let status = match response {
+        Response::Error { .. } => StatusCode::BAD_REQUEST,
+        _ => StatusCode::OK,
+    };
+    (status, Json(response)).into_response()
+}
+
+/// Turn away calls without the bearer token before they reach a handler.
+async fn authorize(
+    State(api): State<Api>,
+    request: axum::extract::Request,
+    next: Next,
+) -> HttpResponse {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let token = bearer.or_else(|| {
+        request
+            .uri()
+            .path()
+            .starts_with("/ws/")
+            .then(|| Query::<TokenParam>::try_from_uri(request.uri()).ok())
+            .flatten()
+            .and_then(|Query(param)| param.token)
+    });
+    match token {
+        Some(token) if remote::token_matches(&api.token, &token) => next.run(request).await,
+        _ => {
+            let response = Response::Error {
+                message: "authentication required: send the daemon's auth_token as \
+                          `Authorization: Bearer <token>`"
+                    .to_string(),
+            };
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                Json(response),
+            )
+                .into_response()
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parameters
+// ---------------------------------------------------------------------------
+
+type Client = ConnectInfo<Peer>;
+
+/// `?since=` in unix milliseconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Since {
+    since: Option<i64>,
+}
+
+/// `?token=`, for WebSocket clients that cannot send a header.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TokenParam {
+    token: Option<String>,
+}
+
+/// `?name=` to only hear about one process.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct EventParams {
+    name: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct InfoParams {
+    tree: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StopParams {
+    cascade: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RestartParams {
+    rolling: bool,
+    cascade: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LogParams {
+    lines: Option<usize>,
+    grep: Option<String>,
+    level: Option<LogLevel>,
+    since: Option<i64>,
+    stream: Option<LogStream>,
+    timestamps: bool,
+    color: bool,
+}
+
+impl LogParams {
+    fn query(self, name: String, follow: bool) -> LogQuery {
+        LogQuery {
+            name: Some(name),
+            group: None,
+            lines: self.lines.unwrap_or(protocol::default_log_lines()),
+            follow,
+            timestamps: self.timestamps,
+            grep: self.grep,
+            level: self.level,
+            since: self.since,
+            stream: self.stream,
+            color: self.color,
+        }
+    }
+}
+
+/// The body of `POST /processes`: a `Start` request.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StartBody {
+    configs: HashMap<String, ProcessConfig>,
+    #[serde(default)]
+    names: Option<Vec<String>>,
+    #[serde(default)]
+    env: Option<String>,
+    #[serde(default)]
+    strict: bool,
+    #[serde(default)]
+    stagger: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignalBody {
+    signal: String,
+    #[serde(default)]
+    group_leader: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScaleBody {
+    instances: u32,
+}
+
+// ---------------------------------------------------------------------------
+// Handlers
+// ---------------------------------------------------------------------------
+
+async fn ping(State(api): State<Api>, ConnectInfo(Peer(client)): Client) -> HttpResponse {
+    api.answer(Request::Ping, client).await
+}
+
+async fn status(State(api): State<Api>, ConnectInfo(Peer(client)): Client) -> HttpResponse {
+    api.answer(Request::DaemonStatus, client).await
+}
+
+async fn list(State(api): State<Api>, ConnectInfo(Peer(client)): Client) -> HttpResponse {
+    api.answer(Request::List, client).await
+}
+
+async fn start(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Json(body): Json<StartBody>,
+) -> HttpResponse {
+    let request = Request::Start {
+        configs: body.configs,
+        names: body.names,
+        env: body.env,
+        strict: body.strict,
+        stagger: body.stagger,
+        progress: false,
+    };
+    api.answer(request, client).await
+}
+
+async fn info(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Query(params): Query<InfoParams>,
+) -> HttpResponse {
+    let request = Request::Info {
+        name,
+        tree: params.tree,
+    };
+    api.answer(request, client).await
+}
+
+async fn stop(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Query(params): Query<StopParams>,
+) -> HttpResponse {
+    let request = Request::Stop {
+        names: Some(vec![name]),
+        group: None,
+        cascade: params.cascade,
+    };
+    api.answer(request, client).await
+}
+
+async fn restart(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Query(params): Query<RestartParams>,
+) -> HttpResponse {
+    let request = Request::Restart {
+        names: Some(vec![name]),
+        group: None,
+        rolling: params.rolling,
+        stagger: None,
+        cascade: params.cascade,
+    };
+    api.answer(request, client).await
+}
+
+async fn reload(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+) -> HttpResponse {
+    let request = Request::Reload {
+        names: Some(vec![name]),
+    };
+    api.answer(request, client).await
+}
+
+async fn signal(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Json(body): Json<SignalBody>,
+) -> HttpResponse {
+    let request = Request::Signal {
+        name,
+        signal: body.signal,
+        group_leader: body.group_leader,
+    };
+    api.answer(request, client).await
+}
+
+async fn scale(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Json(body): Json<ScaleBody>,
+) -> HttpResponse {
+    let request = Request::Scale {
+        name,
+        instances: body.instances,
+    };
+    api.answer(request, client).await
+}
+
+async fn flush(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+) -> HttpResponse {
+    let request = Request::Flush {
+        names: Some(vec![name]),
+    };
+    api.answer(request, client).await
+}
+
+/// The last lines of a process's logs as an array of `log_line` responses.
+async fn logs(
+    State(api): State<Api>,
+    Path(name): Path<String>,
+    Query(params): Query<LogParams>,
+) -> HttpResponse {
+    match daemon::log_lines(params.query(name, false), &api.processes, &api.paths).await {
+        Ok(mut responses) => match responses.first() {
+            Some(Response::Error { .. }) => reply(responses.remove(0)),
+            _ => Json(responses).into_response(),
+        },
+        Err(e) => reply(Response::Error {
+            message: e.to_string(),
+        }),
+    }
+}
+
+async fn process_events(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Query(params): Query<Since>,
+) -> HttpResponse {
+    let request = Request::Events {
+        name: Some(name),
+        since: params.since,
+    };
+    api.answer(request, client).await
+}
+
+async fn history(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Query(params): Query<Since>,
+) -> HttpResponse {
+    let request = Request::History {
+        name: Some(name),
+        since: params.since,
+    };
+    api.answer(request, client).await
+}
+
+async fn samples(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Path(name): Path<String>,
+    Query(params): Query<Since>,
+) -> HttpResponse {
+    let request = Request::Samples {
+        name,
+        since: params.since,
+    };
+    api.answer(request, client).await
+}
+
+async fn events(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Query(params): Query<Since>,
+) -> HttpResponse {
+    let request = Request::Events {
+        name: None,
+        since: params.since,
+    };
+    api.answer(request, client).await
+}
+
+async fn audit(
+    State(api): State<Api>,
+    ConnectInfo(Peer(client)): Client,
+    Query(params): Query<Since>,
+) -> HttpResponse {
+    let request = Request::Audit {
+        since: params.since,
+    };
+    api.answer(request, client).await
+}
+
+async fn save(State(api): State<Api>, ConnectInfo(Peer(client)): Client) -> HttpResponse {
+    api.answer(Request::Save, client).await
+}
+
+async fn resurrect(State(api): State<Api>, ConnectInfo(Peer(client)): Client) -> HttpResponse {
+    api.answer(Request::Resurrect, client).await
+}
+
+// ---------------------------------------------------------------------------
+// WebSockets
+// ---------------------------------------------------------------------------
+
+/// Follow a process's logs: its last lines, then new ones as they are
+/// written, each a `log_line` response. A bad request gets one `error`
+/// frame; the socket closes once the process is scaled away.
+async fn ws_logs(
+    State(api): State<Api>,
+    Path(name): Path<String>,
+    Query(params): Query<LogParams>,
+    upgrade: WebSocketUpgrade,
+) -> HttpResponse {
+    let query = params.query(name, true);
+    upgrade.on_upgrade(move |socket| follow_logs(socket, api, query))
+}
+
+async fn follow_logs(mut socket: WebSocket, api: Api, query: LogQuery) {
+    // The follow loop writes response lines; each one becomes a frame
+    let (mut writer, reader) = tokio::io::duplex(64 * 1024);
+    let follower = tokio::spawn(async move {
+        if let Err(e) = daemon::handle_log(query, &api.processes, &api.paths, &mut writer).await {
+            error!("failed to follow logs over websocket: {e}");
+        }
+    });
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    if socket.send(Message::Text(line.into())).await.is_err() {
+                        break;
+                    }
+                }
+                _ => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    follower.abort();
+}
+
+/// Every lifecycle event from now on, each a `ProcessEvent` as JSON.
+async fn ws_events(
+    State(api): State<Api>,
+    Query(params): Query<EventParams>,
+    upgrade: WebSocketUpgrade,
+) -> HttpResponse {
+    // Subscribe before answering, so no event slips by during the upgrade
+    let events = api.services.events.subscribe();
+    upgrade.on_upgrade(move |socket| stream_events(socket, events, params.name))
+}
+
+async fn stream_events(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<ProcessEvent>,
+    name: Option<String>,
+) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if name.as_ref().is_none_or(|name| *name == event.name) => {
+                    let Ok(json) = serde_json::to_string(&event) else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(json.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::paths::Paths;
+use crate::protocol::{AuditEntry, Request, Response, ResultStatus, StepStatus};
+use std::io::{self, Write};
+use tokio::sync::Mutex;
+
+// ---------------------------------------------------------------------------
+// Audit log
+// ---------------------------------------------------------------------------
+//
+// Every request that acts on the daemon (anything but a query) is appended to
+// `audit.jsonl` in the data directory with who sent it and how it went, so a
+// daemon shared by several people can answer "who stopped this?". The file is
+// only ever appended to; nothing in pm3 prunes or rewrites it.
+
+/// Serializes appends, so concurrent requests never interleave their lines.
+static AUDIT_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The request's type and its arguments. Process configs are cut down to
+/// their names, since their env often carries secrets.
+pub fn describe(request: &Request) -> (String, serde_json::Value) {
+    let mut value = serde_json::to_value(request).unwrap_or_default();
+    let kind = value["type"].as_str().unwrap_or_default().to_string();
+    if let Some(args) = value.as_object_mut() {
+        args.remove("type");
+        if let Some(configs) = args.get_mut("configs")
+            && let Some(names) = configs
+                .as_object()
+                .map(|c| c.keys().cloned().map(serde_json::Value::String).collect())
+        {
+            *configs = serde_json::Value::Array(names);
+        }
+    }
+    (kind, value)
+}
+
+/// `ok`, or what went wrong: the error, or the processes that failed.
+pub fn outcome(response: &Response) -> String {
+    let failed: Vec<&str> = match response {
+        Response::Error { message } => return format!("error: {message}"),
+        Response::Results { results, .. } => results
+            .iter()
+            .filter(|r| r.status == ResultStatus::Failed)
+            .map(|r| r.name.as_str())
+            .collect(),
+        Response::PipelineDone { steps, .. } => steps
+            .iter()
+            .filter(|s| s.status == StepStatus::Failed)
+            .map(|s| s.name.as_str())
+            .collect(),
+        _ => Vec::new(),
+    };
+    if failed.is_empty() {
+        "ok".to_string()
+    } else {
+        format!("failed: {}", failed.join(", "))
+    }
+}
+
+pub async fn append(paths: &Paths, entry: &AuditEntry) -> io::Result<()> {
+    let line = serde_json::to_string(entry)?;
+    let path = paths.audit_log();
+    let _appending = AUDIT_LOCK.lock().await;
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{line}")
+    })
+    .await?
+}
+
+/// Entries recorded at or after `since` (ms since the epoch), oldest first.
+/// Lines that do not parse are skipped.
+pub async fn read(paths: &Paths, since: Option<i64>) -> io::Result<Vec<AuditEntry>> {
+    let content = match tokio::fs::read_to_string(paths.audit_log()).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+        .filter(|entry| since.is_none_or(|since| entry.at >= since))
+        .collect())
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessConfig;
+    use crate::protocol::ProcessResult;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_describe_keeps_config_names_only() {
+        let config = ProcessConfig {
+            command: "server".to_string(),
+            env: Some(HashMap::from([(
+                "TOKEN".to_string(),
+                "hunter2".to_string(),
+            )])),
+            ..Default::default()
+        };
+        let (kind, args) = describe(&Request::Start {
+            configs: HashMap::from([("web".to_string(), config)]),
+            names: Some(vec!["web".to_string()]),
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: true,
+        });
+        assert_eq!(kind, "start");
+        assert_eq!(args["configs"], serde_json::json!(["web"]));
+        assert_eq!(args["names"], serde_json::json!(["web"]));
+        assert!(!args.to_string().contains("hunter2"));
+
+        let (kind, args) = describe(&Request::Kill);
+        assert_eq!(kind, "kill");
+        assert_eq!(args, serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(outcome(&Response::Success { message: None }), "ok");
+        assert_eq!(
+            outcome(&Response::Error {
+                message: "process not found: api".to_string()
+            }),
+            "error: process not found: api"
+        );
+        let results = Response::Results {
+            action: "started".to_string(),
+            results: vec![
+                ProcessResult::succeeded("web", None),
+                ProcessResult::failed("db", "exited"),
+            ],
+            warnings: Vec::new(),
+        };
+        assert_eq!(outcome(&results), "failed: db");
+    }
+
+    #[tokio::test]
+    async fn test_append_and_read_since() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        assert!(read(&paths, None).await.unwrap().is_empty());
+
+        for at in [1_000, 2_000] {
+            let entry = AuditEntry {
+                at,
+                uid: Some(1000),
+                pid: None,
+                remote: None,
+                request: "stop".to_string(),
+                args: serde_json::json!({"names": ["web"]}),
+                outcome: "ok".to_string(),
+            };
+            append(&paths, &entry).await.unwrap();
+        }
+        assert_eq!(read(&paths, None).await.unwrap().len(), 2);
+        let recent = read(&paths, Some(1_500)).await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].at, 2_000);
+    }
+}
@@ -0,0 +1,459 @@
+use crate::paths::Paths;
+use crate::pid;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Bumped when the archive layout changes incompatibly.
+pub const BACKUP_VERSION: u32 = 1;
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Top-level entries of the data directory that make up pm3's state. Logs are
+/// only included on request since they tend to dominate the archive size.
+const STATE_ENTRIES: &[&str] = &[
+    "dump.json",
+    "daemon.toml",
+    "history",
+    "events",
+    "samples",
+    "pm3.db",
+];
+const LOGS_ENTRY: &str = "logs";
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("backup I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to snapshot storage database: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("invalid backup manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("not a pm3 backup: {0}")]
+    InvalidArchive(String),
+    #[error("backup format version {0} is newer than this pm3 supports")]
+    UnsupportedVersion(u32),
+    #[error("the daemon is running; run `pm3 kill` before restoring")]
+    DaemonRunning,
+}
+
+/// Written as the first archive entry so a restore can validate the file
+/// before touching the data directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub version: u32,
+    /// Unix timestamp in milliseconds.
+    pub created_at: i64,
+    pub entries: Vec<String>,
+    pub logs: bool,
+}
+
+// ---------------------------------------------------------------------------
+// Backup
+// ---------------------------------------------------------------------------
+
+/// Archive the pm3 state directory into a zstd-compressed tarball at `dest`.
+///
+/// Safe to run against a live daemon: the SQLite database is copied through
+/// `VACUUM INTO`, which yields a consistent snapshot, and the remaining files
+/// are either rewritten atomically or append-only.
+pub fn create(
+    paths: &Paths,
+    dest: &Path,
+    include_logs: bool,
+) -> Result<BackupManifest, BackupError> {
+    let data_dir = paths.data_dir();
+    let mut entries: Vec<String> = STATE_ENTRIES
+        .iter()
+        .filter(|name| data_dir.join(name).exists())
+        .map(|name| name.to_string())
+        .collect();
+    if include_logs && paths.log_dir().exists() {
+        entries.push(LOGS_ENTRY.to_string());
+    }
+
+    let manifest = BackupManifest {
+        version: BACKUP_VERSION,
+        created_at: chrono::Utc::now().timestamp_millis(),
+        entries,
+        logs: include_logs,
+    };
+
+    let partial = partial_path(dest);
+    let result = write_archive(paths, &partial, &manifest);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result?;
+    std::fs::rename(&partial, dest)?;
+    Ok(manifest)
+}
+
+fn write_archive(paths: &Paths, dest: &Path, manifest: &BackupManifest) -> Result<(), BackupError> {
+    let encoder = zstd::Encoder::new(File::create(dest)?, 0)?;
+    let mut builder = tar::Builder::new(encoder);
+
+    let manifest_json = serde_json::to_vec_pretty(manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime((manifest.created_at / 1000).max(0) as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_NAME, manifest_json.as_slice())?;
+
+    for name in &manifest.entries {
+        let source = paths.data_dir().join(name);
+        if name == "pm3.db" {
+            let snapshot = snapshot_database(paths.data_dir(), &source)?;
+            builder.append_path_with_name(&snapshot, name)?;
+        } else if source.is_dir() {
+            builder.append_dir_all(name, &source)?;
+        } else {
+            builder.append_path_with_name(&source, name)?;
+        }
+    }
+
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// Copy the database to a temporary file in `dir` while other connections
+/// may be writing to it. The file is created exclusively and private to this
+/// user, and removed when the returned path is dropped.
+fn snapshot_database(dir: &Path, source: &Path) -> Result<tempfile::TempPath, BackupError> {
+    let snapshot = tempfile::Builder::new()
+        .prefix(".backup-")
+        .suffix(".db")
+        .tempfile_in(dir)?
+        .into_temp_path();
+    // VACUUM INTO accepts an existing file as long as it is empty
+    let conn = rusqlite::Connection::open(source)?;
+    conn.execute("VACUUM INTO ?1", [snapshot.to_string_lossy()])?;
+    Ok(snapshot)
+}
+
+fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    dest.with_file_name(name)
+}
+
+// ---------------------------------------------------------------------------
+// Restore
+// ---------------------------------------------------------------------------
+
+/// Replace the pm3 state directory with the contents of a backup. The daemon
+/// must not be running, since it would overwrite the restored files.
+///
+/// The archive is unpacked and checked in a staging directory next to the
+/// data directory first; the current state is only swapped out once every
+/// entry made it, and is put back if the swap fails part way.
+pub fn restore(paths: &Paths, source: &Path) -> Result<BackupManifest, BackupError> {
+    if pid::is_daemon_running_sync(paths)? {
+        return Err(BackupError::DaemonRunning);
+    }
+
+    let decoder = zstd::Decoder::new(File::open(source)?)?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut entries = archive.entries()?;
+
+    let manifest = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()?.as_ref() != Path::new(MANIFEST_NAME) {
+                return Err(BackupError::InvalidArchive(format!(
+                    "first entry is not {MANIFEST_NAME}"
+                )));
+            }
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            serde_json::from_str::<BackupManifest>(&content)?
+        }
+        None => return Err(BackupError::InvalidArchive("archive is empty".to_string())),
+    };
+    if manifest.version > BACKUP_VERSION {
+        return Err(BackupError::UnsupportedVersion(manifest.version));
+    }
+
+    // Staged beside the data directory so the swap is a rename on one file
+    // system; dropping it on any error below leaves the live state untouched
+    let data_dir = paths.data_dir();
+    let parent = data_dir.parent().unwrap_or(data_dir);
+    std::fs::create_dir_all(parent)?;
+    let staging = tempfile::Builder::new()
+        .prefix(".pm3-restore-")
+        .tempdir_in(parent)?;
+    let restored = staging.path().join("restored");
+    let replaced = staging.path().join("replaced");
+    std::fs::create_dir(&restored)?;
+    std::fs::create_dir(&replaced)?;
+
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let top = path
+            .components()
+            .next()
+            .and_then(|c| c.as_os_str().to_str())
+            .unwrap_or_default()
+            .to_string();
+        if !STATE_ENTRIES.contains(&top.as_str()) && top != LOGS_ENTRY {
+            return Err(BackupError::InvalidArchive(format!(
+                "unexpected entry `{}`",
+                path.display()
+            )));
+        }
+        entry.unpack_in(&restored)?;
+    }
+    if let Some(missing) = manifest
+        .entries
+        .iter()
+        .find(|name| !restored.join(name).exists())
+    {
+        return Err(BackupError::InvalidArchive(format!(
+            "`{missing}` is listed in the manifest but missing from the archive"
+        )));
+    }
+
+    std::fs::create_dir_all(data_dir)?;
+    swap_state(data_dir, &restored, &replaced, manifest.logs)?;
+    Ok(manifest)
+}
+
+/// Move the state a restore replaces from `data_dir` into `replaced`, so
+/// entries missing from the backup do not linger from the current
+/// installation, then move everything in `restored` into its place. On
+/// failure, whatever was moved goes back where it came from.
+fn swap_state(data_dir: &Path, restored: &Path, replaced: &Path, logs: bool) -> io::Result<()> {
+    let mut targets: Vec<&str> = STATE_ENTRIES.to_vec();
+    targets.extend(["pm3.db-wal", "pm3.db-shm"]);
+    if logs {
+        targets.push(LOGS_ENTRY);
+    }
+    let staged: Vec<_> = std::fs::read_dir(restored)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<_>>()?;
+
+    let mut moved_out = Vec::new();
+    let mut moved_in = Vec::new();
+    let result = (|| {
+        for name in &targets {
+            match std::fs::rename(data_dir.join(name), replaced.join(name)) {
+                Ok(()) => moved_out.push(*name),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for name in &staged {
+            std::fs::rename(restored.join(name), data_dir.join(name))?;
+            moved_in.push(name);
+        }
+        Ok(())
+    })();
+
+    if result.is_err() {
+        for name in moved_in {
+            let _ = std::fs::rename(data_dir.join(name), restored.join(name));
+        }
+        for name in moved_out {
+            let _ = std::fs::rename(replaced.join(name), data_dir.join(name));
+        }
+    }
+    result
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{SqliteStorage, Storage};
+
+    fn seeded_paths(dir: &Path) -> Paths {
+        let paths = Paths::with_base(dir.to_path_buf());
+        std::fs::create_dir_all(paths.history_dir()).unwrap();
+        std::fs::create_dir_all(paths.log_dir()).unwrap();
+        std::fs::write(paths.dump_file(), "{}").unwrap();
+        std::fs::write(paths.settings_file(), "[daemon]\n").unwrap();
+        std::fs::write(paths.history_file("web"), "{\"x\":1}\n").unwrap();
+        std::fs::write(paths.stdout_log("web"), "hello\n").unwrap();
+        paths
+    }
+
+    #[test]
+    fn test_backup_restore_roundtrip() {
+        let src = tempfile::tempdir().unwrap();
+        let paths = seeded_paths(src.path());
+        let archive = src.path().join("state.tar.zst");
+
+        let manifest = create(&paths, &archive, false).unwrap();
+        assert_eq!(manifest.version, BACKUP_VERSION);
+        assert_eq!(
+            manifest.entries,
+            vec!["dump.json", "daemon.toml", "history"]
+        );
+        assert!(!partial_path(&archive).exists());
+
+        let dst = tempfile::tempdir().unwrap();
+        let target = Paths::with_base(dst.path().join("data"));
+        let restored = restore(&target, &archive).unwrap();
+        assert_eq!(restored, manifest);
+        assert_eq!(std::fs::read_to_string(target.dump_file()).unwrap(), "{}");
+        assert_eq!(
+            std::fs::read_to_string(target.history_file("web")).unwrap(),
+            "{\"x\":1}\n"
+        );
+        assert!(!target.stdout_log("web").exists());
+    }
+
+    #[test]
+    fn test_backup_includes_logs_on_request() {
+        let src = tempfile::tempdir().unwrap();
+        let paths = seeded_paths(src.path());
+        let archive = src.path().join("state.tar.zst");
+        create(&paths, &archive, true).unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let target = Paths::with_base(dst.path().to_path_buf());
+        restore(&target, &archive).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target.stdout_log("web")).unwrap(),
+            "hello\n"
+        );
+    }
+
+    #[test]
+    fn test_backup_snapshots_open_database() {
+        let src = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(src.path().to_path_buf());
+        let live = SqliteStorage::open(&paths.storage_db()).unwrap();
+        live.record_run(&crate::storage::tests::record("web", 7))
+            .unwrap();
+
+        let archive = src.path().join("state.tar.zst");
+        create(&paths, &archive, false).unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let target = Paths::with_base(dst.path().to_path_buf());
+        restore(&target, &archive).unwrap();
+        let restored = SqliteStorage::open(&target.storage_db()).unwrap();
+        assert_eq!(restored.runs(Some("web"), None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_restore_replaces_existing_state() {
+        let src = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(src.path().to_path_buf());
+        std::fs::create_dir_all(paths.data_dir()).unwrap();
+        std::fs::write(paths.dump_file(), "[]").unwrap();
+        let archive = src.path().join("state.tar.zst");
+        create(&paths, &archive, false).unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let target = seeded_paths(dst.path());
+        restore(&target, &archive).unwrap();
+        assert_eq!(std::fs::read_to_string(target.dump_file()).unwrap(), "[]");
+        assert!(!target.history_dir().exists());
+        assert!(!target.settings_file().exists());
+        // Logs were not part of the backup, so they are left alone
+        assert!(target.stdout_log("web").exists());
+    }
+
+    #[test]
+    fn test_failed_restore_leaves_state_untouched() {
+        let src = tempfile::tempdir().unwrap();
+        let paths = seeded_paths(src.path());
+        let archive = src.path().join("state.tar.zst");
+        create(&paths, &archive, true).unwrap();
+
+        // A valid manifest, then an entry that does not belong in the state
+        let smuggled = src.path().join("smuggled.tar.zst");
+        {
+            let encoder = zstd::Encoder::new(File::create(&smuggled).unwrap(), 0).unwrap();
+            let mut builder = tar::Builder::new(encoder.auto_finish());
+            let manifest = serde_json::to_vec(&BackupManifest {
+                version: BACKUP_VERSION,
+                created_at: 0,
+                entries: vec!["dump.json".to_string()],
+                logs: false,
+            })
+            .unwrap();
+            for (name, content) in [
+                (MANIFEST_NAME, manifest.as_slice()),
+                ("dump.json", b"[]".as_slice()),
+                ("notes.txt", b"hi".as_slice()),
+            ] {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(content.len() as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, name, content).unwrap();
+            }
+            builder.finish().unwrap();
+        }
+        // A backup cut off part way through its compressed stream
+        let truncated = src.path().join("truncated.tar.zst");
+        let bytes = std::fs::read(&archive).unwrap();
+        std::fs::write(&truncated, &bytes[..bytes.len() - 16]).unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let target = seeded_paths(&dst.path().join("data"));
+        std::fs::write(target.dump_file(), "current").unwrap();
+        for bad in [&smuggled, &truncated] {
+            assert!(restore(&target, bad).is_err());
+            assert_eq!(
+                std::fs::read_to_string(target.dump_file()).unwrap(),
+                "current"
+            );
+            assert!(target.history_file("web").exists());
+            assert!(target.stdout_log("web").exists());
+        }
+        // Nothing staged is left behind next to the data directory
+        assert_eq!(std::fs::read_dir(dst.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_restore_rejects_foreign_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("other.tar.zst");
+        {
+            let encoder = zstd::Encoder::new(File::create(&archive).unwrap(), 0).unwrap();
+            let mut builder = tar::Builder::new(encoder.auto_finish());
+            let mut header = tar::Header::new_gnu();
+            header.set_size(2);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, "notes.txt", b"hi".as_slice())
+                .unwrap();
+            builder.finish().unwrap();
+        }
+
+        let paths = Paths::with_base(dir.path().join("data"));
+        let err = restore(&paths, &archive).unwrap_err();
+        assert!(matches!(err, BackupError::InvalidArchive(_)), "{err}");
+        assert!(!paths.data_dir().exists());
+    }
+
+    #[test]
+    fn test_restore_rejects_newer_version() {
+        let src = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(src.path().to_path_buf());
+        let archive = src.path().join("state.tar.zst");
+        let manifest = BackupManifest {
+            version: BACKUP_VERSION + 1,
+            created_at: 0,
+            entries: Vec::new(),
+            logs: false,
+        };
+        write_archive(&paths, &archive, &manifest).unwrap();
+
+        let err = restore(&paths, &archive).unwrap_err();
+        assert!(matches!(err, BackupError::UnsupportedVersion(v) if v == BACKUP_VERSION + 1));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::cli::BenchProfile;
+use crate::client;
+use crate::config::{ProcessConfig, RestartPolicy};
+use crate::paths::Paths;
+use crate::process;
+use crate::protocol::{Request, Response, RunRecord};
+use crate::stats;
+use color_eyre::eyre::bail;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// ---------------------------------------------------------------------------
+// Synthetic processes
+// ---------------------------------------------------------------------------
+
+/// How long a crashy process runs before it fails.
+const CRASHY_COMMAND: &str = "sh -c 'echo starting; sleep 0.5; echo failing >&2; exit 1'";
+/// About 100,000 lines a second.
+const CHATTY_COMMAND: &str = "sh -c 'while :; do seq 1 1000; sleep 0.01; done'";
+
+/// Configs for `count` synthetic processes named `bench-0`, `bench-1`, and so
+/// on. Crashy ones restart forever with the shortest backoff.
+pub fn configs(profile: BenchProfile, count: u32) -> HashMap<String, ProcessConfig> {
+    (0..count)
+        .map(|i| {
+            let crashy = match profile {
+                BenchProfile::Crashy => true,
+                BenchProfile::Chatty => false,
+                BenchProfile::Mixed => i % 2 == 0,
+            };
+            let config = if crashy {
+                ProcessConfig {
+                    command: CRASHY_COMMAND.to_string(),
+                    restart: Some(RestartPolicy::Always),
+                    max_restarts: Some(u32::MAX),
+                    min_uptime: Some(0),
+                    ..Default::default()
+                }
+            } else {
+                ProcessConfig {
+                    command: CHATTY_COMMAND.to_string(),
+                    ..Default::default()
+                }
+            };
+            (format!("bench-{i}"), config)
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Report
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+impl LatencySummary {
+    pub fn of(mut latencies: Vec<Duration>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        let at = |fraction: f64| {
+            let index = ((latencies.len() - 1) as f64 * fraction).round() as usize;
+            latencies[index].as_millis() as u64
+        };
+        Some(Self {
+            samples: latencies.len(),
+            p50_ms: at(0.5),
+            p95_ms: at(0.95),
+            max_ms: at(1.0),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchReport {
+    pub processes: u32,
+    pub elapsed_ms: u64,
+    /// Daemon CPU time over the run as a share of one core.
+    pub daemon_cpu_percent: f64,
+    pub daemon_peak_rss_bytes: u64,
+    pub restarts: usize,
+    /// Time from a run's exit to its replacement starting, beyond the
+    /// restart backoff.
+    pub restart_latency: Option<LatencySummary>,
+    pub log_bytes: u64,
+    pub log_bytes_per_sec: u64,
+}
+
+/// Exit-to-respawn delays the daemon added on top of the backoff, from the
+/// runs of each process in order.
+pub fn restart_latencies(runs: &[RunRecord]) -> Vec<Duration> {
+    let mut by_name: HashMap<&str, Vec<&RunRecord>> = HashMap::new();
+    for run in runs {
+        by_name.entry(&run.name).or_default().push(run);
+    }
+    let backoff = process::compute_backoff(0).as_millis() as i64;
+    by_name
+        .into_values()
+        .flat_map(|mut runs| {
+            runs.sort_by_key(|run| run.started_at);
+            runs.windows(2)
+                .map(|pair| {
+                    let gap = pair[1].started_at - pair[0].ended_at - backoff;
+                    Duration::from_millis(gap.max(0) as u64)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Running
+// ---------------------------------------------------------------------------
+
+/// How often the daemon's memory is sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A daemon in a scratch data directory, shut down and cleaned up on drop so
+/// a benchmark never touches the user's processes.
+struct ScratchDaemon {
+    paths: Paths,
+}
+
+impl ScratchDaemon {
+    fn new() -> std::io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("pm3-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            paths: Paths::with_base(dir),
+        })
+    }
+
+    fn request(&self, request: &Request) -> color_eyre::Result<Response> {
+        match client::send_request(&self.paths, request)? {
+            Response::Error { message } => bail!("{message}"),
+            response => Ok(response),
+        }
+    }
+
+    fn pid(&self) -> Option<u32> {
+        std::fs::read_to_string(self.paths.pid_file())
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
+
+impl Drop for ScratchDaemon {
+    fn drop(&mut self) {
+        let _ = client::send_request(&self.paths, &Request::Kill);
+        let _ = std::fs::remove_dir_all(self.paths.data_dir());
+    }
+}
+
+/// Run `processes` synthetic processes of `profile` under a scratch daemon
+/// for `duration` and measure it.
+pub fn run(
+    profile: BenchProfile,
+    processes: u32,
+    duration: Duration,
+) -> color_eyre::Result<BenchReport> {
+    let daemon = ScratchDaemon::new()?;
+    let started = Instant::now();
+    daemon.request(&Request::Start {
+        configs: configs(profile, processes),
+        names: None,
+        env: None,
+        strict: false,
+        stagger: None,
+        progress: false,
+    })?;
+    let Some(pid) = daemon.pid() else {
+        bail!("bench daemon did not write a pid file");
+    };
+
+    let cpu_before = stats::cpu_time_ms(pid).unwrap_or(0);
+    let mut peak_rss = 0;
+    while started.elapsed() < duration {
+        std::thread::sleep(SAMPLE_INTERVAL);
+        peak_rss = peak_rss.max(stats::rss_bytes(pid).unwrap_or(0));
+    }
+    let cpu_used = stats::cpu_time_ms(pid).unwrap_or(0) - cpu_before;
+    let elapsed = started.elapsed();
+
+    // Stopping records the runs still going, so their logs are counted
+    daemon.request(&Request::Stop {
+        names: None,
+        group: None,
+        cascade: false,
+    })?;
+    let runs = match daemon.request(&Request::History {
+        name: None,
+        since: None,
+    })? {
+        Response::History { runs } => runs,
+        other => bail!("unexpected response: {other:?}"),
+    };
+
+    let log_bytes = runs.iter().map(|run| run.snapshot.log_bytes).sum();
+    let elapsed_ms = elapsed.as_millis().max(1) as u64;
+    Ok(BenchReport {
+        processes,
+        elapsed_ms,
+        daemon_cpu_percent: cpu_used as f64 * 100.0 / elapsed_ms as f64,
+        daemon_peak_rss_bytes: peak_rss,
+        restarts: runs.len().saturating_sub(processes as usize),
+        restart_latency: LatencySummary::of(restart_latencies(&runs)),
+        log_bytes,
+        log_bytes_per_sec: log_bytes * 1000 / elapsed_ms,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ProcessStatus, ResourceSnapshot};
+
+    fn run(name: &str, started_at: i64, ended_at: i64) -> RunRecord {
+        RunRecord {
+            name: name.to_string(),
+            generation: 1,
+            started_at,
+            ended_at,
+            status: ProcessStatus::Crashed,
+            exit_code: Some(1),
+            reason: None,
+            snapshot: ResourceSnapshot::default(),
+        }
+    }
+
+    #[test]
+    fn test_configs_follow_profile() {
+        let mixed = configs(BenchProfile::Mixed, 4);
+        assert_eq!(mixed.len(), 4);
+        assert_eq!(mixed["bench-0"].command, CRASHY_COMMAND);
+        assert_eq!(mixed["bench-1"].command, CHATTY_COMMAND);
+        assert_eq!(mixed["bench-0"].restart, Some(RestartPolicy::Always));
+        assert!(
+            configs(BenchProfile::Chatty, 3)
+                .values()
+                .all(|c| c.command == CHATTY_COMMAND)
+        );
+    }
+
+    #[test]
+    fn test_restart_latencies_subtract_backoff() {
+        let runs = [
+            run("a", 1_000, 1_500),
+            run("b", 1_000, 9_000),
+            run("a", 1_650, 2_150),
+            run("a", 2_260, 2_760),
+        ];
+        let mut latencies = restart_latencies(&runs);
+        latencies.sort();
+        assert_eq!(
+            latencies,
+            [Duration::from_millis(10), Duration::from_millis(50)]
+        );
+    }
+
+    #[test]
+    fn test_latency_summary() {
+        assert_eq!(LatencySummary::of(Vec::new()), None);
+        let summary =
+            LatencySummary::of((1..=100).rev().map(Duration::from_millis).collect()).unwrap();
+        assert_eq!(summary.samples, 100);
+        assert_eq!(summary.p50_ms, 51);
+        assert_eq!(summary.p95_ms, 95);
+        assert_eq!(summary.max_ms, 100);
+    }
+}
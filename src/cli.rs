@@ -1,4 +1,7 @@
-use clap::{Parser, Subcommand};
+use crate::log::LogLevel;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::ffi::OsString;
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(name = "pm3", about = "A Rust process manager")]
@@ -9,6 +12,38 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub json: bool,
 
+    /// Talk to the isolated daemon of this profile, started on first use
+    #[arg(long, conflicts_with = "local")]
+    pub profile: Option<String>,
+
+    /// Talk to this project's own daemon, kept in ./.pm3/
+    #[arg(long)]
+    pub local: bool,
+
+    /// Talk to a remote daemon listening on host:port (its `listen_tcp`)
+    #[arg(long, value_name = "HOST:PORT", conflicts_with_all = ["profile", "local"])]
+    pub host: Option<String>,
+
+    /// The remote daemon's auth_token; prefer PM3_TOKEN, which `ps` does not show
+    #[arg(long, env = "PM3_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// Speak TLS to the remote daemon, trusting the bundled public roots
+    #[arg(long)]
+    pub tls: bool,
+
+    /// Speak TLS, trusting the daemon's certificate by this PEM CA file
+    #[arg(long, value_name = "FILE", env = "PM3_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+
+    /// Client certificate (PEM) for mutual TLS, with --tls-key
+    #[arg(long, value_name = "FILE", env = "PM3_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM) of --tls-cert
+    #[arg(long, value_name = "FILE", env = "PM3_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 }
@@ -18,44 +53,383 @@ pub enum Command {
     /// Start processes defined in pm3.toml
     Start {
         names: Vec<String>,
+        /// Target every process in this group
+        #[arg(long, conflicts_with = "names")]
+        group: Option<String>,
         #[arg(long)]
         env: Option<String>,
+        /// Block until every started process is online
+        #[arg(long)]
+        wait: bool,
+        /// How long `--wait` waits before failing, e.g. `90s` or `5m`
+        #[arg(long, default_value = "60s", requires = "wait")]
+        wait_timeout: String,
+        /// Refuse to start if `expect_memory` exceeds available memory
+        #[arg(long)]
+        strict: bool,
+        /// Wait this long between launching each process, e.g. `5s`
+        #[arg(long)]
+        stagger: Option<String>,
     },
     /// Stop running processes
-    Stop { names: Vec<String> },
+    Stop {
+        names: Vec<String>,
+        /// Target every process in this group
+        #[arg(long, conflicts_with = "names")]
+        group: Option<String>,
+        /// Also stop processes that depend on these, dependents first
+        #[arg(long)]
+        cascade: bool,
+    },
     /// Restart running processes
-    Restart { names: Vec<String> },
+    Restart {
+        names: Vec<String>,
+        /// Target every process in this group
+        #[arg(long, conflicts_with = "names")]
+        group: Option<String>,
+        /// Restart instances one at a time, waiting for each to be ready
+        #[arg(long)]
+        rolling: bool,
+        /// Wait this long between restarting each process, e.g. `5s`
+        #[arg(long)]
+        stagger: Option<String>,
+        /// Also restart processes that depend on these, once they are back
+        #[arg(long)]
+        cascade: bool,
+    },
     /// List all managed processes
     #[command(visible_alias = "view")]
-    List,
+    List {
+        /// List the `[jobs]` entries with their schedules and last runs
+        #[arg(long)]
+        jobs: bool,
+    },
     /// Stop all processes and shut down the daemon
     Kill,
-    /// Reload process configuration
+    /// Replace the running daemon with this pm3 binary, keeping its processes running
+    Update,
+    /// Replace processes with fresh instances, waiting for readiness first
     Reload { names: Vec<String> },
     /// Show detailed info about a process
-    Info { name: String },
-    /// Send a signal to a process
-    Signal { name: String, signal: String },
+    Info {
+        name: String,
+        /// List each process in the tree and its share of memory and CPU time
+        #[arg(long)]
+        tree: bool,
+    },
+    /// Send a signal (name like SIGUSR2 or usr2, or number) to a process
+    Signal {
+        name: String,
+        signal: String,
+        /// Signal the process's whole process group
+        #[arg(long)]
+        group_leader: bool,
+    },
+    /// Forward your terminal to a process with `stdin = "pipe"` and show its
+    /// output; Ctrl-] detaches
+    Attach { name: String },
+    /// Start or stop instances of a process with `instances` until N run
+    Scale { name: String, instances: u32 },
+    /// Move a daemon started with PM3_VIRTUAL_TIME=1 forward in time
+    #[command(hide = true)]
+    AdvanceClock {
+        /// How far, e.g. `100ms` or `1d`
+        by: String,
+    },
     /// Save current process list for resurrection
     Save,
     /// Restore previously saved processes
     Resurrect,
     /// Clear log files for processes
     Flush { names: Vec<String> },
+    /// List logs and saved state of processes no longer running under pm3
+    Prune {
+        /// Delete them instead of only listing them
+        #[arg(long)]
+        yes: bool,
+    },
     /// View process logs
     Log {
         name: Option<String>,
+        /// Target every process in this group
+        #[arg(long, conflicts_with = "name")]
+        group: Option<String>,
         #[arg(long, default_value_t = 15)]
         lines: usize,
         #[arg(short, long)]
         follow: bool,
+        /// Prefix followed lines with the time they were captured, for
+        /// processes without a `log_date_format`
+        #[arg(long)]
+        timestamps: bool,
+        /// Only lines matching this regex, e.g. `ERROR|panic`
+        #[arg(long)]
+        grep: Option<String>,
+        /// Only lines logged at this level or above, going by their first
+        /// ERROR, WARN, INFO or DEBUG token
+        #[arg(long, value_enum)]
+        level: Option<LogLevel>,
+        /// Only lines stamped within this window, e.g. `10m`; needs a
+        /// `log_date_format`
+        #[arg(long)]
+        since: Option<String>,
+        /// Only the stderr log
+        #[arg(long, conflicts_with = "out")]
+        err: bool,
+        /// Only the stdout log
+        #[arg(long)]
+        out: bool,
+        /// Keep the colors of processes whose logs keep them
+        /// (`log_strip_ansi = false`)
+        #[arg(long)]
+        color: bool,
+        /// Print lines without any colors (as does setting NO_COLOR)
+        #[arg(long, conflicts_with = "color")]
+        no_color: bool,
+    },
+    /// Live dashboard of processes and the selected one's log
+    Monit,
+    /// Print processes sorted by CPU or memory use
+    Top {
+        #[arg(long, value_enum, default_value = "cpu")]
+        sort: TopSort,
+        /// Keep refreshing until interrupted
+        #[arg(long)]
+        watch: bool,
+        /// Time between refreshes with --watch
+        #[arg(long, default_value = "2s")]
+        interval: String,
+    },
+    /// Check that the daemon answers; exits 1 if it is not running
+    Ping,
+    /// Manage the daemon itself
+    Daemon {
+        #[command(subcommand)]
+        command: DaemonCommand,
+    },
+    /// Read or change daemon settings in daemon.toml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// View the daemon's own log (PM3_LOG=debug on the daemon adds detail)
+    DaemonLog {
+        #[arg(long, default_value_t = 50)]
+        lines: usize,
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Run a task after the tasks its `after` lists lead to
+    Run {
+        /// The task that ends the pipeline
+        #[arg(long)]
+        pipeline: String,
+        #[arg(long)]
+        env: Option<String>,
+    },
+    /// Show how a command string will be split into program and arguments
+    Parse { command: String },
+    /// Show past runs with their final resource usage
+    History {
+        name: Option<String>,
+        /// Only runs that ended within this window, e.g. `1h` or `7d`
+        #[arg(long)]
+        since: Option<String>,
+        /// Show lifecycle events (starts, exits, restarts, failed health
+        /// checks) instead of runs
+        #[arg(long)]
+        events: bool,
+    },
+    /// Summarize a process's recorded memory and CPU use over a window
+    Report {
+        name: String,
+        /// How far back to look, e.g. `1h` or `7d`
+        #[arg(long, default_value = "1h")]
+        last: String,
+    },
+    /// Show who asked the daemon to do what, and how it went
+    Audit {
+        /// Only requests made within this window, e.g. `1h` or `7d`
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Archive the pm3 state directory into a .tar.zst file
+    Backup {
+        file: PathBuf,
+        /// Include process log files
+        #[arg(long)]
+        logs: bool,
+    },
+    /// Replace the pm3 state directory with a backup
+    Restore { file: PathBuf },
+    /// Print the processes in pm3.toml in another format
+    Export {
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+    },
+    /// Show the depends_on relationships in pm3.toml
+    Graph {
+        #[arg(long, value_enum, default_value = "tree")]
+        format: GraphFormat,
+    },
+    /// Convert a docker-compose file, supervisord config or systemd unit into pm3.toml
+    Import {
+        file: PathBuf,
+        /// Run each service's command directly on the host as a process
+        #[arg(long)]
+        services_as_processes: bool,
+        /// Read FILE as a systemd service unit
+        #[arg(long, conflicts_with = "services_as_processes")]
+        systemd: bool,
+        /// Where to write the generated config
+        #[arg(long, default_value = "pm3.toml")]
+        output: PathBuf,
+    },
+    /// Print a control socket client snippet for an app
+    Integrate { language: Language },
+    /// Load a throwaway daemon with synthetic processes and report how it copes
+    #[command(hide = true)]
+    Bench {
+        /// Number of synthetic processes
+        #[arg(long, default_value_t = 20)]
+        processes: u32,
+        /// How long to run, e.g. `30s` or `5m`
+        #[arg(long, default_value = "30s")]
+        duration: String,
+        /// What the synthetic processes do
+        #[arg(long, value_enum, default_value = "mixed")]
+        profile: BenchProfile,
+    },
+    /// Generate a commented pm3.toml for the project in this directory
+    Init {
+        /// Project type; detected from package.json, pyproject.toml or Cargo.toml if omitted
+        #[arg(long, value_enum)]
+        template: Option<Template>,
+        /// Ask about each process instead of detecting them
+        #[arg(long, conflicts_with = "template")]
+        interactive: bool,
+        /// Where to write the generated config
+        #[arg(long, default_value = "pm3.toml")]
+        output: PathBuf,
+    },
+    /// Print a unit or init script that starts the daemon at boot, or install it
+    Startup {
+        /// Init system to generate for
+        #[arg(long, value_enum, default_value_t = Platform::Systemd)]
+        platform: Platform,
+        /// Generate a systemd user unit (the default for systemd)
+        #[arg(long)]
+        user: bool,
+        /// Generate a system service that runs as the current user
+        #[arg(long, conflicts_with = "user")]
+        system: bool,
+        /// Write the unit or script, then enable and start it
+        #[arg(long)]
+        install: bool,
     },
+    /// Disable and remove what `pm3 startup --install` installed
+    Unstartup {
+        #[arg(long, value_enum, default_value_t = Platform::Systemd)]
+        platform: Platform,
+        #[arg(long)]
+        user: bool,
+        #[arg(long, conflicts_with = "user")]
+        system: bool,
+    },
+    /// Run a `pm3-<name>` plugin found on PATH
+    #[command(external_subcommand)]
+    Plugin(Vec<OsString>),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DaemonCommand {
+    /// Show the daemon's version, pid, uptime, process count and paths
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Show one setting, e.g. `daemon.auto_save`
+    Get { key: String },
+    /// Change a setting; most apply to the running daemon straight away
+    Set { key: String, value: String },
+    /// Remove a setting so its default applies again
+    Unset { key: String },
+    /// Show every setting with its value or default
+    List,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Compose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// An indented tree for the terminal
+    Tree,
+    /// Graphviz DOT
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TopSort {
+    /// CPU use between the last two samples
+    Cpu,
+    /// Resident memory of the process tree
+    #[value(alias = "memory")]
+    Mem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Language {
+    Python,
+    Node,
+    Go,
+    Shell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BenchProfile {
+    /// Exit with an error shortly after starting, over and over
+    Crashy,
+    /// Print a steady stream of log lines
+    Chatty,
+    /// Half crashy, half chatty
+    Mixed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Platform {
+    Systemd,
+    Openrc,
+    Sysv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Template {
+    Node,
+    Python,
+    Rust,
+    Static,
 }
 
 impl Command {
     pub fn optional_names(names: Vec<String>) -> Option<Vec<String>> {
         if names.is_empty() { None } else { Some(names) }
     }
+
+    /// Commands that work on the local daemon's files or binary, which
+    /// `--host` cannot reach.
+    pub fn is_local_only(&self) -> bool {
+        matches!(
+            self,
+            Command::Update
+                | Command::DaemonLog { .. }
+                | Command::Backup { .. }
+                | Command::Restore { .. }
+        )
+    }
 }
 
 #[cfg(test)]
@@ -68,9 +442,12 @@ mod tests {
     fn test_start_no_args() {
         let cli = Cli::try_parse_from(["pm3", "start"]).unwrap();
         match cli.command.unwrap() {
-            Command::Start { names, env } => {
+            Command::Start {
+                names, env, wait, ..
+            } => {
                 assert!(names.is_empty());
                 assert!(env.is_none());
+                assert!(!wait);
             }
             _ => panic!("expected Start"),
         }
@@ -80,16 +457,45 @@ mod tests {
     fn test_stop_no_args() {
         let cli = Cli::try_parse_from(["pm3", "stop"]).unwrap();
         match cli.command.unwrap() {
-            Command::Stop { names } => assert!(names.is_empty()),
+            Command::Stop {
+                names,
+                group,
+                cascade,
+            } => {
+                assert!(names.is_empty());
+                assert!(group.is_none());
+                assert!(!cascade);
+            }
             _ => panic!("expected Stop"),
         }
+
+        let cli = Cli::try_parse_from(["pm3", "stop", "db", "--cascade"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Stop { cascade: true, .. }
+        ));
     }
 
     #[test]
     fn test_restart_no_args() {
         let cli = Cli::try_parse_from(["pm3", "restart"]).unwrap();
         match cli.command.unwrap() {
-            Command::Restart { names } => assert!(names.is_empty()),
+            Command::Restart {
+                names,
+                rolling,
+                stagger,
+                ..
+            } => {
+                assert!(names.is_empty());
+                assert!(!rolling);
+                assert!(stagger.is_none());
+            }
+            _ => panic!("expected Restart"),
+        }
+
+        let cli = Cli::try_parse_from(["pm3", "restart", "--stagger", "5s"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Restart { stagger, .. } => assert_eq!(stagger.as_deref(), Some("5s")),
             _ => panic!("expected Restart"),
         }
     }
@@ -97,7 +503,12 @@ mod tests {
     #[test]
     fn test_list() {
         let cli = Cli::try_parse_from(["pm3", "list"]).unwrap();
-        assert!(matches!(cli.command.unwrap(), Command::List));
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::List { jobs: false }
+        ));
+        let cli = Cli::try_parse_from(["pm3", "list", "--jobs"]).unwrap();
+        assert!(matches!(cli.command.unwrap(), Command::List { jobs: true }));
     }
 
     #[test]
@@ -106,6 +517,12 @@ mod tests {
         assert!(matches!(cli.command.unwrap(), Command::Kill));
     }
 
+    #[test]
+    fn test_update() {
+        let cli = Cli::try_parse_from(["pm3", "update"]).unwrap();
+        assert!(matches!(cli.command.unwrap(), Command::Update));
+    }
+
     // Names handling
 
     #[test]
@@ -130,7 +547,7 @@ mod tests {
     fn test_start_with_env() {
         let cli = Cli::try_parse_from(["pm3", "start", "--env", "production"]).unwrap();
         match cli.command.unwrap() {
-            Command::Start { names, env } => {
+            Command::Start { names, env, .. } => {
                 assert!(names.is_empty());
                 assert_eq!(env.as_deref(), Some("production"));
             }
@@ -142,7 +559,7 @@ mod tests {
     fn test_start_with_name_and_env() {
         let cli = Cli::try_parse_from(["pm3", "start", "web", "--env", "staging"]).unwrap();
         match cli.command.unwrap() {
-            Command::Start { names, env } => {
+            Command::Start { names, env, .. } => {
                 assert_eq!(names, vec!["web"]);
                 assert_eq!(env.as_deref(), Some("staging"));
             }
@@ -171,23 +588,140 @@ mod tests {
     fn test_info() {
         let cli = Cli::try_parse_from(["pm3", "info", "web"]).unwrap();
         match cli.command.unwrap() {
-            Command::Info { name } => assert_eq!(name, "web"),
+            Command::Info { name, tree } => {
+                assert_eq!(name, "web");
+                assert!(!tree);
+            }
             _ => panic!("expected Info"),
         }
+
+        let cli = Cli::try_parse_from(["pm3", "info", "web", "--tree"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Info { tree: true, .. }
+        ));
     }
 
     #[test]
     fn test_signal() {
         let cli = Cli::try_parse_from(["pm3", "signal", "web", "SIGHUP"]).unwrap();
         match cli.command.unwrap() {
-            Command::Signal { name, signal } => {
+            Command::Signal {
+                name,
+                signal,
+                group_leader,
+            } => {
                 assert_eq!(name, "web");
                 assert_eq!(signal, "SIGHUP");
+                assert!(!group_leader);
             }
             _ => panic!("expected Signal"),
         }
     }
 
+    #[test]
+    fn test_profile_flags() {
+        let cli = Cli::try_parse_from(["pm3", "--profile", "shop", "list"]).unwrap();
+        assert_eq!(cli.profile.as_deref(), Some("shop"));
+        assert!(!cli.local);
+        let cli = Cli::try_parse_from(["pm3", "--local", "start"]).unwrap();
+        assert!(cli.local);
+        assert!(Cli::try_parse_from(["pm3", "--local", "--profile", "shop", "list"]).is_err());
+        let cli = Cli::try_parse_from(["pm3", "--host", "10.0.0.5:9617", "list"]).unwrap();
+        assert_eq!(cli.host.as_deref(), Some("10.0.0.5:9617"));
+        assert!(
+            Cli::try_parse_from(["pm3", "--host", "10.0.0.5:9617", "--local", "list"]).is_err()
+        );
+        let cli = Cli::try_parse_from([
+            "pm3",
+            "--host",
+            "10.0.0.5:9617",
+            "--token",
+            "s3cret",
+            "--tls-ca",
+            "ca.pem",
+            "--tls-cert",
+            "client.pem",
+            "--tls-key",
+            "client-key.pem",
+            "list",
+        ])
+        .unwrap();
+        assert_eq!(cli.token.as_deref(), Some("s3cret"));
+        assert_eq!(cli.tls_ca, Some(PathBuf::from("ca.pem")));
+        assert_eq!(cli.tls_key, Some(PathBuf::from("client-key.pem")));
+        assert!(Cli::try_parse_from(["pm3", "--tls-cert", "client.pem", "list"]).is_err());
+    }
+
+    #[test]
+    fn test_ping_and_daemon_status() {
+        let cli = Cli::try_parse_from(["pm3", "ping"]).unwrap();
+        assert!(matches!(cli.command.unwrap(), Command::Ping));
+        let cli = Cli::try_parse_from(["pm3", "daemon", "status"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Daemon {
+                command: DaemonCommand::Status
+            }
+        ));
+    }
+
+    #[test]
+    fn test_config() {
+        let cli =
+            Cli::try_parse_from(["pm3", "config", "set", "daemon.auto_save", "true"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Config {
+                command: ConfigCommand::Set { key, value },
+            } => {
+                assert_eq!(key, "daemon.auto_save");
+                assert_eq!(value, "true");
+            }
+            _ => panic!("expected Config Set"),
+        }
+        let cli = Cli::try_parse_from(["pm3", "config", "list"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Config {
+                command: ConfigCommand::List
+            }
+        ));
+        assert!(Cli::try_parse_from(["pm3", "config", "set", "daemon.auto_save"]).is_err());
+    }
+
+    #[test]
+    fn test_daemon_log() {
+        let cli = Cli::try_parse_from(["pm3", "daemon-log", "-f"]).unwrap();
+        match cli.command.unwrap() {
+            Command::DaemonLog { lines, follow } => {
+                assert_eq!(lines, 50);
+                assert!(follow);
+            }
+            _ => panic!("expected DaemonLog"),
+        }
+    }
+
+    #[test]
+    fn test_attach() {
+        let cli = Cli::try_parse_from(["pm3", "attach", "repl"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Attach { name } => assert_eq!(name, "repl"),
+            _ => panic!("expected Attach"),
+        }
+    }
+
+    #[test]
+    fn test_scale() {
+        let cli = Cli::try_parse_from(["pm3", "scale", "web", "4"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Scale { name, instances } => {
+                assert_eq!(name, "web");
+                assert_eq!(instances, 4);
+            }
+            _ => panic!("expected Scale"),
+        }
+    }
+
     #[test]
     fn test_save() {
         let cli = Cli::try_parse_from(["pm3", "save"]).unwrap();
@@ -200,6 +734,49 @@ mod tests {
         assert!(matches!(cli.command.unwrap(), Command::Resurrect));
     }
 
+    #[test]
+    fn test_monit() {
+        let cli = Cli::try_parse_from(["pm3", "monit"]).unwrap();
+        assert!(matches!(cli.command.unwrap(), Command::Monit));
+    }
+
+    #[test]
+    fn test_top() {
+        let cli = Cli::try_parse_from(["pm3", "top"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Top {
+                sort,
+                watch,
+                interval,
+            } => {
+                assert_eq!(sort, TopSort::Cpu);
+                assert!(!watch);
+                assert_eq!(interval, "2s");
+            }
+            _ => panic!("expected Top"),
+        }
+        let cli = Cli::try_parse_from(["pm3", "top", "--sort", "mem", "--watch"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Top {
+                sort: TopSort::Mem,
+                watch: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_prune() {
+        let cli = Cli::try_parse_from(["pm3", "prune"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Prune { yes: false }
+        ));
+        let cli = Cli::try_parse_from(["pm3", "prune", "--yes"]).unwrap();
+        assert!(matches!(cli.command.unwrap(), Command::Prune { yes: true }));
+    }
+
     #[test]
     fn test_flush() {
         let cli = Cli::try_parse_from(["pm3", "flush"]).unwrap();
@@ -223,6 +800,7 @@ mod tests {
                 name,
                 lines,
                 follow,
+                ..
             } => {
                 assert!(name.is_none());
                 assert_eq!(lines, 15);
@@ -234,32 +812,357 @@ mod tests {
 
     #[test]
     fn test_log_with_options() {
-        let cli = Cli::try_parse_from(["pm3", "log", "web", "--lines", "50", "-f"]).unwrap();
+        let cli = Cli::try_parse_from([
+            "pm3",
+            "log",
+            "web",
+            "--lines",
+            "50",
+            "-f",
+            "--timestamps",
+            "--grep",
+            "ERROR|panic",
+            "--level",
+            "warn",
+            "--since",
+            "10m",
+            "--err",
+            "--color",
+        ])
+        .unwrap();
         match cli.command.unwrap() {
             Command::Log {
                 name,
                 lines,
                 follow,
+                timestamps,
+                grep,
+                level,
+                since,
+                err,
+                out,
+                color,
+                ..
             } => {
                 assert_eq!(name.as_deref(), Some("web"));
                 assert_eq!(lines, 50);
                 assert!(follow);
+                assert!(timestamps);
+                assert_eq!(grep.as_deref(), Some("ERROR|panic"));
+                assert_eq!(level, Some(LogLevel::Warn));
+                assert_eq!(since.as_deref(), Some("10m"));
+                assert!(err && !out);
+                assert!(color);
             }
             _ => panic!("expected Log"),
         }
     }
 
+    #[test]
+    fn test_log_no_color_conflicts_with_color() {
+        let cli = Cli::try_parse_from(["pm3", "log", "--no-color"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Log { no_color: true, .. }
+        ));
+        assert!(Cli::try_parse_from(["pm3", "log", "--color", "--no-color"]).is_err());
+    }
+
     #[test]
     fn test_list_view_alias() {
         let cli = Cli::try_parse_from(["pm3", "view"]).unwrap();
-        assert!(matches!(cli.command.unwrap(), Command::List));
+        assert!(matches!(cli.command.unwrap(), Command::List { .. }));
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = Cli::try_parse_from(["pm3", "parse", "bash -c 'echo hi'"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Parse { command } => assert_eq!(command, "bash -c 'echo hi'"),
+            _ => panic!("expected Parse"),
+        }
+    }
+
+    #[test]
+    fn test_audit() {
+        let cli = Cli::try_parse_from(["pm3", "audit", "--since", "1h"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Audit { since } => assert_eq!(since.as_deref(), Some("1h")),
+            _ => panic!("expected Audit"),
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let cli = Cli::try_parse_from(["pm3", "history", "web"]).unwrap();
+        match cli.command.unwrap() {
+            Command::History {
+                name,
+                since,
+                events,
+            } => {
+                assert_eq!(name.as_deref(), Some("web"));
+                assert_eq!(since, None);
+                assert!(!events);
+            }
+            _ => panic!("expected History"),
+        }
+        let cli = Cli::try_parse_from(["pm3", "history"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::History {
+                name: None,
+                since: None,
+                events: false
+            }
+        ));
+        let cli = Cli::try_parse_from(["pm3", "history", "--since", "1h"]).unwrap();
+        match cli.command.unwrap() {
+            Command::History { since, .. } => assert_eq!(since.as_deref(), Some("1h")),
+            _ => panic!("expected History"),
+        }
+        let cli = Cli::try_parse_from(["pm3", "history", "web", "--events"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::History { events: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_report() {
+        let cli = Cli::try_parse_from(["pm3", "report", "web"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Report { name, last } => {
+                assert_eq!(name, "web");
+                assert_eq!(last, "1h");
+            }
+            _ => panic!("expected Report"),
+        }
+        let cli = Cli::try_parse_from(["pm3", "report", "web", "--last", "7d"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Report { last, .. } if last == "7d"
+        ));
+        assert!(Cli::try_parse_from(["pm3", "report"]).is_err());
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let cli = Cli::try_parse_from(["pm3", "backup", "state.tar.zst", "--logs"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Backup { file, logs } => {
+                assert_eq!(file, PathBuf::from("state.tar.zst"));
+                assert!(logs);
+            }
+            _ => panic!("expected Backup"),
+        }
+        let cli = Cli::try_parse_from(["pm3", "restore", "state.tar.zst"]).unwrap();
+        assert!(matches!(cli.command.unwrap(), Command::Restore { .. }));
+        assert!(Cli::try_parse_from(["pm3", "restore"]).is_err());
+    }
+
+    #[test]
+    fn test_start_with_wait() {
+        let cli = Cli::try_parse_from(["pm3", "start", "--wait", "web"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Start {
+                names,
+                wait,
+                wait_timeout,
+                ..
+            } => {
+                assert_eq!(names, vec!["web"]);
+                assert!(wait);
+                assert_eq!(wait_timeout, "60s");
+            }
+            _ => panic!("expected Start"),
+        }
+        let cli = Cli::try_parse_from(["pm3", "start", "--wait", "--wait-timeout", "5m"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Start { wait_timeout, .. } if wait_timeout == "5m"
+        ));
+        assert!(Cli::try_parse_from(["pm3", "start", "--wait-timeout", "5m"]).is_err());
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let cli = Cli::try_parse_from(["pm3", "export", "--format", "compose"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Export {
+                format: ExportFormat::Compose
+            }
+        ));
+        assert!(Cli::try_parse_from(["pm3", "export", "--format", "k8s"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "pm3",
+            "import",
+            "docker-compose.yml",
+            "--services-as-processes",
+        ])
+        .unwrap();
+        match cli.command.unwrap() {
+            Command::Import {
+                file,
+                services_as_processes,
+                systemd,
+                output,
+            } => {
+                assert_eq!(file, PathBuf::from("docker-compose.yml"));
+                assert!(services_as_processes);
+                assert!(!systemd);
+                assert_eq!(output, PathBuf::from("pm3.toml"));
+            }
+            _ => panic!("expected Import"),
+        }
+
+        let cli = Cli::try_parse_from(["pm3", "import", "--systemd", "myapp.service"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Import { systemd: true, .. }
+        ));
+        assert!(
+            Cli::try_parse_from([
+                "pm3",
+                "import",
+                "--systemd",
+                "--services-as-processes",
+                "x.service"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_group_flag() {
+        for sub in ["start", "stop", "restart", "log"] {
+            let cli = Cli::try_parse_from(["pm3", sub, "--group", "backend"]).unwrap();
+            let group = match cli.command.unwrap() {
+                Command::Start { group, .. }
+                | Command::Stop { group, .. }
+                | Command::Restart { group, .. }
+                | Command::Log { group, .. } => group,
+                _ => panic!("unexpected command for {sub}"),
+            };
+            assert_eq!(group.as_deref(), Some("backend"));
+            assert!(Cli::try_parse_from(["pm3", sub, "web", "--group", "backend"]).is_err());
+        }
     }
 
     // Error cases
 
     #[test]
-    fn test_unknown_subcommand() {
-        assert!(Cli::try_parse_from(["pm3", "bogus"]).is_err());
+    fn test_integrate() {
+        let cli = Cli::try_parse_from(["pm3", "integrate", "python"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Integrate {
+                language: Language::Python
+            }
+        ));
+        assert!(Cli::try_parse_from(["pm3", "integrate", "cobol"]).is_err());
+    }
+
+    #[test]
+    fn test_graph() {
+        let cli = Cli::try_parse_from(["pm3", "graph"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Graph {
+                format: GraphFormat::Tree
+            }
+        ));
+        let cli = Cli::try_parse_from(["pm3", "graph", "--format", "dot"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Graph {
+                format: GraphFormat::Dot
+            }
+        ));
+    }
+
+    #[test]
+    fn test_init_template() {
+        let cli = Cli::try_parse_from(["pm3", "init", "--template", "rust"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Init {
+                template,
+                interactive,
+                output,
+            } => {
+                assert_eq!(template, Some(Template::Rust));
+                assert!(!interactive);
+                assert_eq!(output, PathBuf::from("pm3.toml"));
+            }
+            _ => panic!("expected Init"),
+        }
+        assert!(Cli::try_parse_from(["pm3", "init", "--template", "php"]).is_err());
+        assert!(
+            Cli::try_parse_from(["pm3", "init", "--interactive", "--template", "node"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_run_pipeline() {
+        let cli = Cli::try_parse_from(["pm3", "run", "--pipeline", "deploy"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Run { pipeline, env } => {
+                assert_eq!(pipeline, "deploy");
+                assert_eq!(env, None);
+            }
+            _ => panic!("expected Run"),
+        }
+        assert!(Cli::try_parse_from(["pm3", "run"]).is_err());
+    }
+
+    #[test]
+    fn test_startup() {
+        let cli = Cli::try_parse_from(["pm3", "startup"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Startup {
+                platform: Platform::Systemd,
+                user: false,
+                system: false,
+                install: false
+            }
+        ));
+        let cli = Cli::try_parse_from(["pm3", "startup", "--system", "--install"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Startup {
+                system: true,
+                install: true,
+                ..
+            }
+        ));
+        let cli = Cli::try_parse_from(["pm3", "unstartup", "--user"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Unstartup { user: true, .. }
+        ));
+        let cli = Cli::try_parse_from(["pm3", "unstartup", "--platform", "sysv"]).unwrap();
+        assert!(matches!(
+            cli.command.unwrap(),
+            Command::Unstartup {
+                platform: Platform::Sysv,
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["pm3", "startup", "--user", "--system"]).is_err());
+        assert!(Cli::try_parse_from(["pm3", "startup", "--platform", "runit"]).is_err());
+        assert!(Cli::try_parse_from(["pm3", "unstartup", "--install"]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_subcommand_is_plugin() {
+        let cli = Cli::try_parse_from(["pm3", "deploy", "--env", "prod"]).unwrap();
+        match cli.command.unwrap() {
+            Command::Plugin(args) => assert_eq!(args, ["deploy", "--env", "prod"]),
+            _ => panic!("expected Plugin"),
+        }
     }
 
     #[test]
@@ -267,6 +1170,11 @@ mod tests {
         assert!(Cli::try_parse_from(["pm3", "info"]).is_err());
     }
 
+    #[test]
+    fn test_parse_missing_command() {
+        assert!(Cli::try_parse_from(["pm3", "parse"]).is_err());
+    }
+
     #[test]
     fn test_signal_missing_args() {
         assert!(Cli::try_parse_from(["pm3", "signal"]).is_err());
@@ -1,19 +1,194 @@
+use crate::log;
 use crate::paths::Paths;
 use crate::pid;
-use crate::protocol::{self, Request, Response};
+use crate::protocol::{self, ProcessStatus, Request, Response};
+use crate::remote;
 use color_eyre::eyre::{Context, bail};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
 use std::os::unix::net::UnixStream;
 use std::os::unix::process::CommandExt;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
+use tokio_rustls::rustls::{ClientConfig, ClientConnection, StreamOwned};
 
-pub fn send_request(paths: &Paths, request: &Request) -> color_eyre::Result<Response> {
+/// A daemon on another machine, reached over TCP (see the daemon's
+/// `listen_tcp`).
+pub struct Remote {
+    /// `host:port` the daemon listens on.
+    pub host: String,
+    /// The daemon's `auth_token`, sent ahead of every request.
+    pub token: Option<String>,
+    /// Speak TLS with this config, from `remote::client_config`.
+    pub tls: Option<Arc<ClientConfig>>,
+}
+
+static REMOTE: OnceLock<Remote> = OnceLock::new();
+
+/// Send every request to `remote` instead of the local daemon. Set once, at
+/// startup.
+pub fn select_remote(remote: Remote) {
+    let _ = REMOTE.set(remote);
+}
+
+/// The remote daemon selected for this process, if any.
+pub fn active_remote() -> Option<&'static Remote> {
+    REMOTE.get()
+}
+
+/// A connection to the daemon: its unix socket, or TCP to a remote one.
+enum Connection {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Connection {
+    fn shutdown(&mut self, how: Shutdown) -> std::io::Result<()> {
+        match self {
+            Connection::Unix(stream) => stream.shutdown(how),
+            Connection::Tcp(stream) => stream.shutdown(how),
+            Connection::Tls(stream) => {
+                stream.conn.send_close_notify();
+                let closed = (|| {
+                    while stream.conn.wants_write() {
+                        stream.conn.write_tls(&mut stream.sock)?;
+                    }
+                    stream.sock.shutdown(how)
+                })();
+                // The daemon may answer and hang up before reading our
+                // close_notify; its reply is still waiting to be read
+                match closed {
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            std::io::ErrorKind::NotConnected
+                                | std::io::ErrorKind::BrokenPipe
+                                | std::io::ErrorKind::ConnectionReset
+                        ) =>
+                    {
+                        Ok(())
+                    }
+                    closed => closed,
+                }
+            }
+        }
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(match self {
+            Connection::Unix(stream) => Connection::Unix(stream.try_clone()?),
+            Connection::Tcp(stream) => Connection::Tcp(stream.try_clone()?),
+            // A TLS session cannot be read and written from two threads
+            Connection::Tls(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "a TLS connection cannot be cloned",
+                ));
+            }
+        })
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Unix(stream) => stream.read(buf),
+            Connection::Tcp(stream) => stream.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Unix(stream) => stream.write(buf),
+            Connection::Tcp(stream) => stream.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Unix(stream) => stream.flush(),
+            Connection::Tcp(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Open a TCP connection to `remote`, start TLS if it is configured and
+/// send the token, leaving the connection ready for a request.
+fn connect_remote(remote: &Remote) -> std::io::Result<Connection> {
+    let stream = TcpStream::connect(&remote.host)?;
+    stream.set_nodelay(true)?;
+    let mut connection = match &remote.tls {
+        Some(config) => {
+            let name = remote::server_name(&remote.host).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+            })?;
+            let session =
+                ClientConnection::new(Arc::clone(config), name).map_err(std::io::Error::other)?;
+            Connection::Tls(Box::new(StreamOwned::new(session, stream)))
+        }
+        None => Connection::Tcp(stream),
+    };
+    if let Some(token) = &remote.token {
+        let auth = protocol::Auth {
+            token: token.clone(),
+        };
+        connection.write_all(&protocol::encode_auth(&auth).map_err(std::io::Error::other)?)?;
+    }
+    Ok(connection)
+}
+
+/// Connect to the selected daemon, starting the local one if it is not
+/// running. A remote daemon is never started.
+fn connect(paths: &Paths) -> color_eyre::Result<Connection> {
+    if let Some(remote) = active_remote() {
+        return connect_remote(remote)
+            .with_context(|| format!("failed to connect to {}", remote.host));
+    }
     ensure_daemon_running(paths)?;
-    let mut stream = connect_with_retry(paths, 10, Duration::from_millis(200))?;
+    let stream = connect_with_retry(paths, 10, Duration::from_millis(200))?;
+    Ok(Connection::Unix(stream))
+}
+
+pub fn send_request(paths: &Paths, request: &Request) -> color_eyre::Result<Response> {
+    let stream = connect(paths)?;
+    exchange(stream, request)
+}
 
+/// Send `request` only if the daemon is already running, never starting
+/// one; `None` when it is not.
+pub fn send_request_if_running(
+    paths: &Paths,
+    request: &Request,
+) -> color_eyre::Result<Option<Response>> {
+    if let Some(remote) = active_remote() {
+        let stream = match connect_remote(remote) {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to connect to {}", remote.host));
+            }
+        };
+        return exchange(stream, request).map(Some);
+    }
+    if !pid::is_daemon_running_sync(paths)? {
+        return Ok(None);
+    }
+    let stream = UnixStream::connect(paths.socket_file())
+        .with_context(|| format!("failed to connect to {}", paths.socket_file().display()))?;
+    exchange(Connection::Unix(stream), request).map(Some)
+}
+
+fn exchange(mut stream: Connection, request: &Request) -> color_eyre::Result<Response> {
     let encoded = protocol::encode_request(request)?;
     stream.write_all(&encoded)?;
-    stream.shutdown(std::net::Shutdown::Write)?;
+    stream.shutdown(Shutdown::Write)?;
 
     let mut reader = BufReader::new(stream);
     let mut line = String::new();
@@ -31,12 +206,11 @@ pub fn send_request_streaming<F>(
 where
     F: FnMut(&Response),
 {
-    ensure_daemon_running(paths)?;
-    let mut stream = connect_with_retry(paths, 10, Duration::from_millis(200))?;
+    let mut stream = connect(paths)?;
 
     let encoded = protocol::encode_request(request)?;
     stream.write_all(&encoded)?;
-    stream.shutdown(std::net::Shutdown::Write)?;
+    stream.shutdown(Shutdown::Write)?;
 
     let reader = BufReader::new(stream);
     for line_result in reader.lines() {
@@ -51,12 +225,155 @@ where
     Ok(())
 }
 
+/// Key that detaches `pm3 attach`: Ctrl-].
+const DETACH_KEY: u8 = 0x1d;
+
+/// Attach the terminal to the process `name`: forward stdin to it and print
+/// its output with `on_response` until Ctrl-], end of input, or the run ends.
+pub fn attach(paths: &Paths, name: &str, on_response: fn(&Response)) -> color_eyre::Result<()> {
+    if active_remote().is_some_and(|remote| remote.tls.is_some()) {
+        bail!("pm3 attach does not work over TLS");
+    }
+    let mut stream = connect(paths)?;
+    stream.write_all(&protocol::encode_request(&Request::Attach {
+        name: name.to_string(),
+    })?)?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let response = protocol::decode_response(&line)?;
+    on_response(&response);
+    if !matches!(response, Response::Success { .. }) {
+        return Ok(());
+    }
+
+    let terminal = DetachKey::install();
+    let restore = terminal.clone();
+    // The daemon ends the stream when the run does, which leaves nothing
+    // for the input loop to do
+    std::thread::spawn(move || {
+        for line in reader.lines().map_while(Result::ok) {
+            if let Ok(response) = protocol::decode_response(&line) {
+                on_response(&response);
+            }
+        }
+        drop(restore);
+        std::process::exit(0);
+    });
+
+    let mut stdin = std::io::stdin().lock();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stdin.read(&mut buf)?;
+        let chunk = &buf[..n];
+        let detach = chunk.iter().position(|&b| b == DETACH_KEY);
+        let data = &chunk[..detach.unwrap_or(n)];
+        if !data.is_empty() {
+            let input = Request::Input {
+                name: name.to_string(),
+                data: String::from_utf8_lossy(data).into_owned(),
+            };
+            stream.write_all(&protocol::encode_request(&input)?)?;
+        }
+        if n == 0 || detach.is_some() {
+            break;
+        }
+    }
+    drop(terminal);
+    // Before the shutdown, which lets the output thread exit the process
+    eprintln!("detached from {name}");
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+/// Makes Ctrl-] end a line on the terminal, so the input loop sees it as
+/// soon as it is pressed while line editing keeps working. Undone on drop.
+#[derive(Clone)]
+struct DetachKey(Option<nix::sys::termios::Termios>);
+
+impl DetachKey {
+    fn install() -> Self {
+        use nix::sys::termios::{SetArg, SpecialCharacterIndices, tcgetattr, tcsetattr};
+        let stdin = std::io::stdin();
+        let Ok(original) = tcgetattr(&stdin) else {
+            // Not a terminal; input is forwarded as it comes
+            return DetachKey(None);
+        };
+        let mut attrs = original.clone();
+        attrs.control_chars[SpecialCharacterIndices::VEOL as usize] = DETACH_KEY;
+        let _ = tcsetattr(&stdin, SetArg::TCSANOW, &attrs);
+        DetachKey(Some(original))
+    }
+}
+
+impl Drop for DetachKey {
+    fn drop(&mut self) {
+        if let Some(ref original) = self.0 {
+            let _ = nix::sys::termios::tcsetattr(
+                std::io::stdin(),
+                nix::sys::termios::SetArg::TCSANOW,
+                original,
+            );
+        }
+    }
+}
+
+/// Delay between `List` polls while waiting for processes to come online.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Poll the daemon until every named process is online. Fails as soon as one
+/// of them settles in a state it will not leave by itself, or at `timeout`.
+pub fn wait_until_online(
+    paths: &Paths,
+    names: &[String],
+    timeout: Duration,
+) -> color_eyre::Result<()> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        let processes = match send_request(paths, &Request::List)? {
+            Response::ProcessList { processes } => processes,
+            Response::Error { message } => bail!("{message}"),
+            other => bail!("unexpected response: {other:?}"),
+        };
+
+        let mut pending = Vec::new();
+        for name in names {
+            let Some(info) = processes.iter().find(|p| &p.name == name) else {
+                bail!("process '{name}' is not managed by the daemon");
+            };
+            match info.status {
+                ProcessStatus::Online => {}
+                ProcessStatus::Starting => pending.push(name.as_str()),
+                status => bail!("process '{name}' is {status}, not online"),
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        if std::time::Instant::now() >= deadline {
+            bail!(
+                "timed out after {timeout:?} waiting for: {}",
+                pending.join(", ")
+            );
+        }
+        std::thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// Number of bootstrap log lines shown when the daemon fails to come up.
+const BOOTSTRAP_TAIL_LINES: usize = 20;
+
 fn ensure_daemon_running(paths: &Paths) -> color_eyre::Result<()> {
     if pid::is_daemon_running_sync(paths)? {
         return Ok(());
     }
+    // A systemd socket unit accepts connections before its daemon runs
+    if UnixStream::connect(paths.socket_file()).is_ok() {
+        return Ok(());
+    }
 
-    spawn_daemon()?;
+    let daemon = spawn_daemon(paths)?;
 
     // Wait for socket file to appear
     let socket = paths.socket_file();
@@ -64,25 +381,97 @@ fn ensure_daemon_running(paths: &Paths) -> color_eyre::Result<()> {
         if socket.exists() {
             return Ok(());
         }
+        // The daemon is not our child, so its exit status is out of reach
+        if nix::sys::signal::kill(daemon, None).is_err() {
+            bail!("daemon exited during startup{}", bootstrap_tail(paths));
+        }
         std::thread::sleep(Duration::from_millis(100));
     }
 
-    bail!("timed out waiting for daemon to start");
+    bail!(
+        "timed out waiting for daemon to start{}",
+        bootstrap_tail(paths)
+    );
 }
 
-fn spawn_daemon() -> color_eyre::Result<()> {
+/// Start the daemon fully detached from the launching shell and return its
+/// pid once it has exec'd.
+fn spawn_daemon(paths: &Paths) -> color_eyre::Result<nix::unistd::Pid> {
     let exe = std::env::current_exe().context("failed to get current executable path")?;
+    // The daemon runs from `/`, so it must be told where its data lives in
+    // full
+    let data_dir = std::path::absolute(paths.data_dir())
+        .with_context(|| format!("failed to resolve {}", paths.data_dir().display()))?;
 
-    std::process::Command::new(exe)
+    // The daemon's output goes to a bootstrap log so startup failures can be
+    // reported back to the user instead of vanishing with the detached process.
+    std::fs::create_dir_all(&data_dir)
+        .with_context(|| format!("failed to create {}", data_dir.display()))?;
+    let bootstrap_log = std::fs::File::create(paths.bootstrap_log())
+        .with_context(|| format!("failed to create {}", paths.bootstrap_log().display()))?;
+
+    let (mut pid_reader, pid_writer) = std::io::pipe()?;
+    let pid_fd = pid_writer.as_raw_fd();
+    let mut command = std::process::Command::new(exe);
+    command
         .arg("--daemon")
+        .env("PM3_DATA_DIR", &data_dir)
+        // Keep error reports short enough that the cause fits in the tail
+        .env("RUST_LIB_BACKTRACE", "0")
+        .current_dir("/")
         .stdin(std::process::Stdio::null())
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .process_group(0)
-        .spawn()
-        .context("failed to spawn daemon")?;
+        .stdout(bootstrap_log.try_clone()?)
+        .stderr(bootstrap_log);
+    // SAFETY: the closure only makes system calls, and the intermediate
+    // process leaves with _exit without touching the parent's state
+    unsafe {
+        command.pre_exec(move || detach(pid_fd));
+    }
+    let mut intermediate = command.spawn().context("failed to spawn daemon")?;
+    drop(pid_writer);
+    intermediate.wait()?;
 
-    Ok(())
+    let mut pid = [0u8; 4];
+    pid_reader
+        .read_exact(&mut pid)
+        .context("daemon did not report its pid")?;
+    Ok(nix::unistd::Pid::from_raw(i32::from_ne_bytes(pid)))
+}
+
+/// Runs in the spawned process before it execs the daemon. It leaves the
+/// launching shell's session, then forks again and exits, so the daemon
+/// survives the shell and, not being a session leader, can never acquire a
+/// controlling terminal. The daemon's pid is written to `pid_fd` first.
+fn detach(pid_fd: RawFd) -> std::io::Result<()> {
+    use nix::unistd::{ForkResult, fork, setsid};
+    setsid()?;
+    // SAFETY: the forking process is single-threaded after the spawn's fork
+    match unsafe { fork() }? {
+        ForkResult::Parent { child } => {
+            // SAFETY: pid_fd is the write end of the pipe the launcher reads
+            let fd = unsafe { BorrowedFd::borrow_raw(pid_fd) };
+            let _ = nix::unistd::write(fd, &child.as_raw().to_ne_bytes());
+            // SAFETY: _exit skips destructors and atexit handlers that belong
+            // to the launcher
+            unsafe { libc::_exit(0) }
+        }
+        ForkResult::Child => Ok(()),
+    }
+}
+
+/// Format the tail of the daemon's bootstrap log for inclusion in an error.
+fn bootstrap_tail(paths: &Paths) -> String {
+    let lines = log::tail_file(&paths.bootstrap_log(), BOOTSTRAP_TAIL_LINES).unwrap_or_default();
+    if lines.iter().all(|l| l.trim().is_empty()) {
+        return String::new();
+    }
+
+    let mut out = format!("\n\ndaemon output ({}):", paths.bootstrap_log().display());
+    for line in lines {
+        out.push_str("\n  ");
+        out.push_str(&line);
+    }
+    out
 }
 
 fn connect_with_retry(
@@ -97,7 +486,10 @@ fn connect_with_retry(
             Ok(stream) => return Ok(stream),
             Err(e) => {
                 if attempt == retries - 1 {
-                    bail!("failed to connect to daemon after {retries} attempts: {e}");
+                    bail!(
+                        "failed to connect to daemon after {retries} attempts: {e}{}",
+                        bootstrap_tail(paths)
+                    );
                 }
                 std::thread::sleep(delay);
             }
@@ -0,0 +1,113 @@
+use chrono::{DateTime, Local, Utc};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Set to `1` when the daemon starts to run supervision timers (restart
+/// backoff, `min_uptime`, run timeouts, heartbeats, `cron_restart`) on a
+/// clock that only moves when a client advances it. For tests.
+pub const VIRTUAL_TIME_ENV: &str = "PM3_VIRTUAL_TIME";
+
+struct VirtualClock {
+    origin: Instant,
+    wall_origin: DateTime<Utc>,
+    /// Virtual time elapsed since `origin`.
+    elapsed: watch::Sender<Duration>,
+}
+
+static VIRTUAL: OnceLock<VirtualClock> = OnceLock::new();
+
+/// Switch to virtual time if `PM3_VIRTUAL_TIME=1`. Returns whether the clock
+/// is virtual.
+pub fn install_from_env() -> bool {
+    if std::env::var(VIRTUAL_TIME_ENV).is_ok_and(|v| v == "1") {
+        VIRTUAL.get_or_init(|| VirtualClock {
+            origin: Instant::now(),
+            wall_origin: Utc::now(),
+            elapsed: watch::Sender::new(Duration::ZERO),
+        });
+    }
+    is_virtual()
+}
+
+pub fn is_virtual() -> bool {
+    VIRTUAL.get().is_some()
+}
+
+/// Move virtual time forward by `by`, waking every timer that falls due.
+/// Returns the virtual time elapsed since the daemon started, or `None` when
+/// the clock is real.
+pub fn advance(by: Duration) -> Option<Duration> {
+    let clock = VIRTUAL.get()?;
+    clock.elapsed.send_modify(|elapsed| *elapsed += by);
+    Some(*clock.elapsed.borrow())
+}
+
+pub fn now() -> Instant {
+    match VIRTUAL.get() {
+        Some(clock) => clock.origin + *clock.elapsed.borrow(),
+        None => Instant::now(),
+    }
+}
+
+/// Time since `since`, which came from `now`.
+pub fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+pub fn now_utc() -> DateTime<Utc> {
+    match VIRTUAL.get() {
+        Some(clock) => {
+            clock.wall_origin
+                + chrono::Duration::from_std(*clock.elapsed.borrow()).unwrap_or_default()
+        }
+        None => Utc::now(),
+    }
+}
+
+pub fn now_local() -> DateTime<Local> {
+    now_utc().with_timezone(&Local)
+}
+
+pub async fn sleep(duration: Duration) {
+    let Some(clock) = VIRTUAL.get() else {
+        return tokio::time::sleep(duration).await;
+    };
+    let until = *clock.elapsed.borrow() + duration;
+    let mut elapsed = clock.elapsed.subscribe();
+    // The sender lives as long as the process, so this only returns once due
+    let _ = elapsed.wait_for(|elapsed| *elapsed >= until).await;
+}
+
+/// `future`'s output, or `None` if `limit` passes first.
+pub async fn timeout<F: Future>(limit: Duration, future: F) -> Option<F::Output> {
+    tokio::select! {
+        output = future => Some(output),
+        _ = sleep(limit) => None,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_real_clock() {
+        // The unit test binary never installs the virtual clock
+        assert!(!is_virtual());
+        assert_eq!(advance(Duration::from_secs(1)), None);
+        let start = now();
+        sleep(Duration::from_millis(20)).await;
+        assert!(elapsed(start) >= Duration::from_millis(20));
+        assert_eq!(
+            timeout(Duration::from_millis(10), std::future::pending::<()>()).await,
+            None
+        );
+        assert_eq!(timeout(Duration::from_secs(5), async { 7 }).await, Some(7));
+    }
+}
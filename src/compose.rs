@@ -0,0 +1,434 @@
+use crate::config::{ProcessConfig, RestartPolicy};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// ---------------------------------------------------------------------------
+// Compose subset
+// ---------------------------------------------------------------------------
+
+/// pm3 fields that have a Compose equivalent; everything else is reported as
+/// dropped on export.
+const EXPORTED_FIELDS: &[&str] = &[
+    "command",
+    "cwd",
+    "env",
+    "timezone",
+    "locale",
+    "depends_on",
+    "restart",
+    "max_restarts",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ComposeError {
+    #[error("invalid compose file: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("failed to write pm3.toml: {0}")]
+    Toml(#[from] toml::ser::Error),
+    #[error("{0}")]
+    InvalidValue(String),
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ComposeFile {
+    #[serde(default)]
+    services: BTreeMap<String, Service>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Service {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entrypoint: Option<CommandLine>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<CommandLine>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    environment: Option<Environment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    depends_on: Option<DependsOn>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    restart: Option<String>,
+    #[serde(flatten, skip_serializing)]
+    other: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CommandLine {
+    Shell(String),
+    Exec(Vec<String>),
+}
+
+impl CommandLine {
+    fn into_words(self) -> Result<Vec<String>, ComposeError> {
+        match self {
+            CommandLine::Shell(line) => shell_words::split(&line)
+                .map_err(|e| ComposeError::InvalidValue(format!("invalid command `{line}`: {e}"))),
+            CommandLine::Exec(words) => Ok(words),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Environment {
+    Map(BTreeMap<String, Option<serde_yaml::Value>>),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum DependsOn {
+    List(Vec<String>),
+    /// Long syntax: `db: { condition: service_healthy }`.
+    Map(BTreeMap<String, serde_yaml::Value>),
+}
+
+// ---------------------------------------------------------------------------
+// Import
+// ---------------------------------------------------------------------------
+
+/// A Compose file translated into pm3 process definitions.
+#[derive(Debug)]
+pub struct Imported {
+    /// The generated `pm3.toml` content.
+    pub toml: String,
+    pub processes: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Turn every service with a command into a process running directly on the
+/// host. Container-only settings (image, ports, volumes, ...) are dropped with
+/// a warning.
+pub fn import(yaml: &str) -> Result<Imported, ComposeError> {
+    let file: ComposeFile = serde_yaml::from_str(yaml)?;
+    let mut warnings = Vec::new();
+    let mut document = toml::Table::new();
+
+    for (name, service) in file.services {
+        if !service.other.is_empty() {
+            let keys: Vec<&str> = service.other.keys().map(String::as_str).collect();
+            warnings.push(format!("service `{name}`: ignoring {}", keys.join(", ")));
+        }
+
+        let mut words = match service.entrypoint {
+            Some(entrypoint) => entrypoint.into_words()?,
+            None => Vec::new(),
+        };
+        if let Some(command) = service.command {
+            words.extend(command.into_words()?);
+        }
+        if words.is_empty() {
+            warnings.push(format!(
+                "service `{name}`: skipped, no command or entrypoint to run on the host"
+            ));
+            continue;
+        }
+
+        let mut table = toml::Table::new();
+        table.insert("command".into(), shell_words::join(&words).into());
+        if let Some(dir) = service.working_dir {
+            table.insert("cwd".into(), dir.into());
+        }
+        if let Some(environment) = service.environment {
+            let env = import_environment(&name, environment, &mut warnings);
+            if !env.is_empty() {
+                table.insert("env".into(), toml::Value::Table(env));
+            }
+        }
+        if let Some(depends_on) = service.depends_on {
+            let deps: Vec<String> = match depends_on {
+                DependsOn::List(deps) => deps,
+                DependsOn::Map(deps) => deps.into_keys().collect(),
+            };
+            table.insert("depends_on".into(), deps.into());
+        }
+        if let Some(restart) = service.restart {
+            let (policy, max_restarts) = import_restart(&name, &restart)?;
+            table.insert("restart".into(), policy.into());
+            if let Some(max) = max_restarts {
+                table.insert("max_restarts".into(), i64::from(max).into());
+            }
+        }
+        document.insert(name, toml::Value::Table(table));
+    }
+
+    if document.is_empty() {
+        return Err(ComposeError::InvalidValue(
+            "no services with a command to import".to_string(),
+        ));
+    }
+
+    Ok(Imported {
+        processes: document.keys().cloned().collect(),
+        toml: toml::to_string(&document)?,
+        warnings,
+    })
+}
+
+fn import_environment(
+    service: &str,
+    environment: Environment,
+    warnings: &mut Vec<String>,
+) -> toml::Table {
+    let pairs: Vec<(String, Option<String>)> = match environment {
+        Environment::Map(map) => map
+            .into_iter()
+            .map(|(key, value)| {
+                let value = value.map(|v| match v {
+                    serde_yaml::Value::String(s) => s,
+                    other => serde_yaml::to_string(&other)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                });
+                (key, value)
+            })
+            .collect(),
+        Environment::List(list) => list
+            .into_iter()
+            .map(|entry| match entry.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (entry, None),
+            })
+            .collect(),
+    };
+
+    let mut env = toml::Table::new();
+    for (key, value) in pairs {
+        match value {
+            Some(value) => {
+                env.insert(key, value.into());
+            }
+            None => warnings.push(format!(
+                "service `{service}`: `{key}` takes its value from the host, skipped"
+            )),
+        }
+    }
+    env
+}
+
+fn import_restart(
+    service: &str,
+    restart: &str,
+) -> Result<(&'static str, Option<u32>), ComposeError> {
+    let (policy, max) = restart.split_once(':').unwrap_or((restart, ""));
+    let invalid = || {
+        ComposeError::InvalidValue(format!(
+            "service `{service}`: unsupported restart policy `{restart}`"
+        ))
+    };
+    match policy {
+        "no" => Ok(("never", None)),
+        "always" | "unless-stopped" => Ok(("always", None)),
+        "on-failure" if max.is_empty() => Ok(("on_failure", None)),
+        "on-failure" => Ok(("on_failure", Some(max.parse().map_err(|_| invalid())?))),
+        _ => Err(invalid()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Export
+// ---------------------------------------------------------------------------
+
+/// pm3 process definitions rendered as a Compose file.
+#[derive(Debug)]
+pub struct Exported {
+    pub yaml: String,
+    pub warnings: Vec<String>,
+}
+
+pub fn export(configs: &HashMap<String, ProcessConfig>) -> Result<Exported, ComposeError> {
+    let mut file = ComposeFile::default();
+    let mut warnings = Vec::new();
+
+    for (name, config) in configs {
+        let dropped = untranslated_fields(config);
+        if !dropped.is_empty() {
+            warnings.push(format!(
+                "process `{name}`: {} not expressible in compose, dropped",
+                dropped.join(", ")
+            ));
+        }
+
+        // `timezone` and `locale` travel as the TZ / LC_ALL they set
+        let environment = config.effective_env().map(|env| {
+            Environment::Map(
+                env.iter()
+                    .map(|(k, v)| (k.clone(), Some(serde_yaml::Value::String(v.clone()))))
+                    .collect(),
+            )
+        });
+
+        file.services.insert(
+            name.clone(),
+            Service {
+                command: Some(CommandLine::Shell(config.command.clone())),
+                working_dir: config.cwd.clone(),
+                environment,
+                depends_on: config.depends_on.clone().map(DependsOn::List),
+                restart: Some(export_restart(config)),
+                ..Default::default()
+            },
+        );
+    }
+
+    Ok(Exported {
+        yaml: serde_yaml::to_string(&file)?,
+        warnings,
+    })
+}
+
+/// pm3 restarts on failure by default while Compose defaults to `no`, so the
+/// policy is always written out.
+fn export_restart(config: &ProcessConfig) -> String {
+    match config.restart_policy() {
+        RestartPolicy::Never => "no".to_string(),
+        RestartPolicy::Always => "always".to_string(),
+        RestartPolicy::OnFailure => match config.max_restarts {
+            Some(max) => format!("on-failure:{max}"),
+            None => "on-failure".to_string(),
+        },
+    }
+}
+
+/// Names of the set fields of `config` that have no Compose equivalent.
+fn untranslated_fields(config: &ProcessConfig) -> Vec<String> {
+    let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(config) else {
+        return Vec::new();
+    };
+    let mut dropped: Vec<String> = fields
+        .into_iter()
+        .filter(|(key, value)| {
+            !EXPORTED_FIELDS.contains(&key.as_str())
+                && !value.is_null()
+                && value.as_object().is_none_or(|o| !o.is_empty())
+        })
+        .map(|(key, _)| key)
+        .collect();
+    dropped.sort();
+    dropped
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    const COMPOSE: &str = r#"
+services:
+  db:
+    image: postgres:16
+    ports: ["5432:5432"]
+  api:
+    image: node:20
+    working_dir: /srv/api
+    command: ["node", "server.js", "--port", "3000"]
+    environment:
+      NODE_ENV: production
+      PORT: 3000
+      SECRET:
+    depends_on:
+      db:
+        condition: service_healthy
+    restart: on-failure:5
+  worker:
+    entrypoint: python
+    command: worker.py --queue "high priority"
+    environment:
+      - QUEUE=jobs
+    depends_on: [api]
+    restart: unless-stopped
+"#;
+
+    #[test]
+    fn test_import_services() {
+        let imported = import(COMPOSE).unwrap();
+        assert_eq!(imported.processes, vec!["api", "worker"]);
+
+        let configs = parse_config(&imported.toml).unwrap();
+        let api = &configs["api"];
+        assert_eq!(api.command, "node server.js --port 3000");
+        assert_eq!(api.cwd.as_deref(), Some("/srv/api"));
+        let env = api.env.as_ref().unwrap();
+        assert_eq!(env["NODE_ENV"], "production");
+        assert_eq!(env["PORT"], "3000");
+        assert!(!env.contains_key("SECRET"));
+        assert_eq!(api.depends_on, Some(vec!["db".to_string()]));
+        assert_eq!(api.restart, Some(RestartPolicy::OnFailure));
+        assert_eq!(api.max_restarts, Some(5));
+
+        let worker = &configs["worker"];
+        assert_eq!(worker.command, "python worker.py --queue 'high priority'");
+        assert_eq!(worker.env.as_ref().unwrap()["QUEUE"], "jobs");
+        assert_eq!(worker.restart, Some(RestartPolicy::Always));
+    }
+
+    #[test]
+    fn test_import_warnings() {
+        let warnings = import(COMPOSE).unwrap().warnings;
+        assert!(warnings.iter().any(|w| w.contains("`db`: skipped")));
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.contains("`db`: ignoring image, ports"))
+        );
+        assert!(warnings.iter().any(|w| w.contains("`SECRET`")));
+    }
+
+    #[test]
+    fn test_import_rejects_unknown_restart() {
+        let yaml = "services:\n  a:\n    command: x\n    restart: sometimes\n";
+        let err = import(yaml).unwrap_err();
+        assert!(err.to_string().contains("sometimes"), "{err}");
+    }
+
+    #[test]
+    fn test_import_without_commands_errors() {
+        let yaml = "services:\n  db:\n    image: postgres\n";
+        assert!(matches!(import(yaml), Err(ComposeError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let configs = parse_config(
+            r#"
+[web]
+command = "node server.js"
+cwd = "./frontend"
+env = { PORT = "3000" }
+depends_on = ["db"]
+max_restarts = 3
+timezone = "UTC"
+
+[db]
+command = "postgres -D data"
+restart = "never"
+health_check = "http://localhost:5432"
+"#,
+        )
+        .unwrap();
+
+        let exported = export(&configs).unwrap();
+        assert_eq!(
+            exported.warnings,
+            vec!["process `db`: health_check not expressible in compose, dropped"]
+        );
+
+        let reimported = parse_config(&import(&exported.yaml).unwrap().toml).unwrap();
+        let web = &reimported["web"];
+        assert_eq!(web.command, "node server.js");
+        assert_eq!(web.cwd.as_deref(), Some("./frontend"));
+        assert_eq!(web.env.as_ref().unwrap()["PORT"], "3000");
+        assert_eq!(web.env.as_ref().unwrap()["TZ"], "UTC");
+        assert_eq!(web.depends_on, Some(vec!["db".to_string()]));
+        assert_eq!(web.restart, Some(RestartPolicy::OnFailure));
+        assert_eq!(web.max_restarts, Some(3));
+        assert_eq!(reimported["db"].restart, Some(RestartPolicy::Never));
+    }
+}
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -9,6 +9,95 @@ pub enum RestartPolicy {
     Never,
 }
 
+/// Whether a process is meant to keep running or to run to completion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessKind {
+    #[default]
+    Service,
+    /// Runs to completion: not restarted unless `restart` says so, and can
+    /// be ordered into pipelines with `after`.
+    Task,
+    /// Declared under `[jobs]`: runs to completion, and only when its
+    /// `cron_restart` schedule fires.
+    Job,
+}
+
+/// How much a process matters when the host or the daemon has to choose
+/// between processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessClass {
+    /// Stopped last, restarted even under pressure, and the last the kernel
+    /// picks when it runs out of memory.
+    Critical,
+    #[default]
+    Standard,
+    /// Stopped first and the first the kernel picks when it runs out of
+    /// memory.
+    Batch,
+}
+
+impl ProcessClass {
+    /// Position when stopping everything: batch first, critical last.
+    pub fn stop_order(self) -> u8 {
+        match self {
+            ProcessClass::Batch => 0,
+            ProcessClass::Standard => 1,
+            ProcessClass::Critical => 2,
+        }
+    }
+
+    /// `oom_score_adj` given to processes of this class; standard processes
+    /// keep the one they inherit from the daemon.
+    pub fn oom_score_adj(self) -> Option<i32> {
+        match self {
+            ProcessClass::Critical => Some(-500),
+            ProcessClass::Standard => None,
+            ProcessClass::Batch => Some(500),
+        }
+    }
+
+    /// Whether automatic restarts wait while the host is under severe
+    /// pressure.
+    pub fn defers_under_pressure(self) -> bool {
+        self != ProcessClass::Critical
+    }
+}
+
+/// What a `cron_restart` tick does while the previous run is still going.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlapPolicy {
+    /// Leave the running instance alone and drop this run.
+    Skip,
+    /// Start this run as soon as the running instance exits.
+    Queue,
+    /// Stop the running instance and start afresh.
+    #[default]
+    KillPrevious,
+}
+
+/// How often a process's logs are rotated regardless of size; rotated files
+/// are named after the period they cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RotateInterval {
+    Hourly,
+    Daily,
+}
+
+/// What the process reads on stdin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StdinMode {
+    /// `/dev/null`.
+    #[default]
+    Null,
+    /// A pipe the daemon holds, written to by `pm3 attach`.
+    Pipe,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum EnvFile {
@@ -16,6 +105,353 @@ pub enum EnvFile {
     Multiple(Vec<String>),
 }
 
+/// A lifecycle hook: either a bare command or a command with its own timeout
+/// in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Hook {
+    Command(String),
+    Detailed {
+        command: String,
+        #[serde(default)]
+        timeout: Option<u64>,
+    },
+}
+
+impl Hook {
+    pub fn command(&self) -> &str {
+        match self {
+            Hook::Command(command) | Hook::Detailed { command, .. } => command,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<u64> {
+        match self {
+            Hook::Command(_) => None,
+            Hook::Detailed { timeout, .. } => *timeout,
+        }
+    }
+}
+
+/// Resources a `[name.rlimits]` table may limit, named as in `ulimit`'s
+/// `RLIMIT_*` constants.
+pub const RLIMIT_NAMES: &[&str] = &[
+    "as",
+    "core",
+    "cpu",
+    "data",
+    "fsize",
+    "locks",
+    "memlock",
+    "msgqueue",
+    "nice",
+    "nofile",
+    "nproc",
+    "rss",
+    "rtprio",
+    "sigpending",
+    "stack",
+];
+
+/// One limit of `[name.rlimits]`: a number or `"unlimited"` for both the
+/// soft and the hard limit, or `{ soft = ..., hard = ... }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Rlimit {
+    Both(RlimitValue),
+    Split {
+        soft: RlimitValue,
+        hard: RlimitValue,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RlimitValue {
+    Count(u64),
+    /// Only `"unlimited"` is accepted.
+    Keyword(String),
+}
+
+impl RlimitValue {
+    /// The limit, `None` meaning unlimited.
+    fn value(&self) -> Result<Option<u64>, String> {
+        match self {
+            RlimitValue::Count(count) => Ok(Some(*count)),
+            RlimitValue::Keyword(word) if word == "unlimited" => Ok(None),
+            RlimitValue::Keyword(word) => Err(format!(
+                "`{word}` is not a limit; use a number or \"unlimited\""
+            )),
+        }
+    }
+}
+
+impl Rlimit {
+    /// The soft and hard limits, `None` meaning unlimited. The soft limit
+    /// may not exceed the hard one.
+    pub fn limits(&self) -> Result<(Option<u64>, Option<u64>), String> {
+        let (soft, hard) = match self {
+            Rlimit::Both(value) => (value.value()?, value.value()?),
+            Rlimit::Split { soft, hard } => (soft.value()?, hard.value()?),
+        };
+        match (soft, hard) {
+            (None, Some(_)) => Err("soft limit is above the hard limit".to_string()),
+            (Some(soft), Some(hard)) if soft > hard => {
+                Err("soft limit is above the hard limit".to_string())
+            }
+            limits => Ok(limits),
+        }
+    }
+}
+
+/// `[name.alert]`: what to do when resource use stays high.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Alerts {
+    /// Percent of one core, over the whole process tree.
+    pub cpu: Option<AlertRule>,
+    /// Resident memory of the process tree.
+    pub memory: Option<AlertRule>,
+}
+
+/// One alert: `{ above = 90, for = "5m", action = "restart" }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    /// A percentage for `cpu`; a size like `"1G"` or a number of bytes for
+    /// `memory`.
+    pub above: AlertThreshold,
+    /// How long usage must stay above before the alert fires (e.g. `"5m"`);
+    /// by default the first sample over it does.
+    #[serde(rename = "for")]
+    pub for_: Option<String>,
+    #[serde(default)]
+    pub action: AlertAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AlertThreshold {
+    Number(f64),
+    Size(String),
+}
+
+/// What a firing alert does besides recording an `alert` event, which
+/// `notify` can send on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertAction {
+    #[default]
+    Notify,
+    Restart,
+}
+
+impl AlertRule {
+    /// `for`, defaulting to zero.
+    pub fn duration(&self) -> std::time::Duration {
+        self.for_
+            .as_deref()
+            .and_then(|d| parse_duration(d).ok())
+            .unwrap_or_default()
+    }
+}
+
+impl Alerts {
+    /// The CPU threshold in percent, if one is set and valid.
+    pub fn cpu_above(&self) -> Option<f64> {
+        match self.cpu.as_ref()?.above {
+            AlertThreshold::Number(percent) if percent > 0.0 => Some(percent),
+            _ => None,
+        }
+    }
+
+    /// The memory threshold in bytes, if one is set and valid.
+    pub fn memory_above(&self) -> Option<u64> {
+        match &self.memory.as_ref()?.above {
+            AlertThreshold::Number(bytes) if *bytes >= 1.0 => Some(*bytes as u64),
+            AlertThreshold::Size(size) => parse_memory(size).ok(),
+            AlertThreshold::Number(_) => None,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.cpu.is_some() && self.cpu_above().is_none() {
+            return Err("alert.cpu: above must be a percentage greater than 0".to_string());
+        }
+        if let Some(AlertRule {
+            above: AlertThreshold::Size(size),
+            ..
+        }) = &self.memory
+        {
+            parse_memory(size).map_err(|e| format!("alert.memory: {e}"))?;
+        }
+        if self.memory.is_some() && self.memory_above().is_none() {
+            return Err("alert.memory: above must be a size like \"1G\"".to_string());
+        }
+        for (metric, rule) in [("cpu", &self.cpu), ("memory", &self.memory)] {
+            if let Some(duration) = rule.as_ref().and_then(|rule| rule.for_.as_deref()) {
+                parse_duration(duration).map_err(|e| format!("alert.{metric}: {e}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What must hold before a starting process is reported as online.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadyCheck {
+    /// A TCP connection to this port on localhost succeeds.
+    Port(u16),
+    /// This path exists.
+    File(String),
+    /// A GET on this `http://` URL answers 200.
+    Http(String),
+    /// The process writes a line matching this regex.
+    Log(String),
+    /// The process sends `{"type":"ready"}` on its control socket.
+    Control,
+}
+
+/// Where to send a process's notifications: a webhook URL or the name of a
+/// channel set up in `daemon.toml`, a list of those, or `{ urls = [...],
+/// channels = [...], events = [...] }` to choose the events as well.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Notify {
+    Target(String),
+    Targets(Vec<String>),
+    Detailed {
+        #[serde(default)]
+        urls: Vec<String>,
+        #[serde(default)]
+        channels: Vec<Channel>,
+        #[serde(default)]
+        events: Vec<NotifyEvent>,
+    },
+}
+
+/// A notification channel configured under `[notifications]` in
+/// `daemon.toml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Slack,
+    Discord,
+    Email,
+}
+
+pub const CHANNELS: [Channel; 3] = [Channel::Slack, Channel::Discord, Channel::Email];
+
+/// One place a notification goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyTarget<'a> {
+    Webhook(&'a str),
+    Channel(Channel),
+}
+
+/// What a notification can be sent for. An exit is told apart by how it
+/// ended: `crash` and `errored` for failures, `exit` for the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    Start,
+    Exit,
+    /// An exit that will be restarted, or that failed despite its exit code.
+    Crash,
+    /// An exit after which the process gave up restarting.
+    Errored,
+    Restart,
+    Unhealthy,
+    MemoryLimit,
+    FdLimit,
+    Deferred,
+    /// A `[name.alert]` threshold was crossed.
+    Alert,
+}
+
+/// Sent when `notify` doesn't name its events.
+pub const DEFAULT_NOTIFY_EVENTS: &[NotifyEvent] = &[
+    NotifyEvent::Crash,
+    NotifyEvent::Restart,
+    NotifyEvent::Errored,
+    NotifyEvent::Alert,
+];
+
+impl Notify {
+    /// A bare target naming a channel is that channel; any other is a
+    /// webhook URL.
+    pub fn targets(&self) -> Vec<NotifyTarget<'_>> {
+        fn bare(target: &String) -> NotifyTarget<'_> {
+            CHANNELS
+                .into_iter()
+                .find(|channel| channel.to_string() == *target)
+                .map_or(NotifyTarget::Webhook(target), NotifyTarget::Channel)
+        }
+        match self {
+            Notify::Target(target) => vec![bare(target)],
+            Notify::Targets(targets) => targets.iter().map(bare).collect(),
+            Notify::Detailed { urls, channels, .. } => urls
+                .iter()
+                .map(|url| NotifyTarget::Webhook(url))
+                .chain(channels.iter().copied().map(NotifyTarget::Channel))
+                .collect(),
+        }
+    }
+
+    pub fn wants(&self, event: NotifyEvent) -> bool {
+        match self {
+            Notify::Detailed { events, .. } if !events.is_empty() => events.contains(&event),
+            _ => DEFAULT_NOTIFY_EVENTS.contains(&event),
+        }
+    }
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channel::Slack => write!(f, "slack"),
+            Channel::Discord => write!(f, "discord"),
+            Channel::Email => write!(f, "email"),
+        }
+    }
+}
+
+impl std::fmt::Display for AlertThreshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertThreshold::Number(number) => write!(f, "{number}"),
+            AlertThreshold::Size(size) => write!(f, "{size}"),
+        }
+    }
+}
+
+impl std::fmt::Display for AlertAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertAction::Notify => write!(f, "notify"),
+            AlertAction::Restart => write!(f, "restart"),
+        }
+    }
+}
+
+impl std::fmt::Display for NotifyEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotifyEvent::Start => write!(f, "start"),
+            NotifyEvent::Exit => write!(f, "exit"),
+            NotifyEvent::Crash => write!(f, "crash"),
+            NotifyEvent::Errored => write!(f, "errored"),
+            NotifyEvent::Restart => write!(f, "restart"),
+            NotifyEvent::Unhealthy => write!(f, "unhealthy"),
+            NotifyEvent::MemoryLimit => write!(f, "memory_limit"),
+            NotifyEvent::FdLimit => write!(f, "fd_limit"),
+            NotifyEvent::Deferred => write!(f, "deferred"),
+            NotifyEvent::Alert => write!(f, "alert"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Watch {
@@ -23,7 +459,7 @@ pub enum Watch {
     Path(String),
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct ProcessConfig {
     pub command: String,
     pub cwd: Option<String>,
@@ -32,21 +468,207 @@ pub struct ProcessConfig {
     pub health_check: Option<String>,
     pub kill_timeout: Option<u64>,
     pub kill_signal: Option<String>,
+    /// Whether stopping the process signals its whole process group, so
+    /// children it forked go with it; defaults to true.
+    pub kill_tree: Option<bool>,
+    pub reload_signal: Option<String>,
     pub max_restarts: Option<u32>,
     pub max_memory: Option<String>,
+    /// Open file descriptors, over the whole process tree, past which the
+    /// process is restarted.
+    pub fd_limit: Option<u32>,
+    /// Memory the process is expected to need (e.g. `"2G"`), checked against
+    /// the host's available memory before a start.
+    pub expect_memory: Option<String>,
     pub min_uptime: Option<u64>,
+    /// Least time (e.g. `"5s"`) an automatic restart waits after an exit,
+    /// on top of which the crash backoff may add more.
+    pub restart_delay: Option<String>,
+    /// Least time between two restarts; an automatic restart due sooner
+    /// waits out the rest of the window.
+    pub restart_window: Option<String>,
     pub stop_exit_codes: Option<Vec<i32>>,
     pub watch: Option<Watch>,
     pub watch_ignore: Option<Vec<String>>,
     pub depends_on: Option<Vec<String>>,
     pub restart: Option<RestartPolicy>,
+    pub kind: Option<ProcessKind>,
+    /// Tasks that must succeed before this one runs in a pipeline.
+    pub after: Option<Vec<String>>,
     pub group: Option<String>,
-    pub pre_start: Option<String>,
-    pub post_stop: Option<String>,
-    pub notify: Option<String>,
+    /// Run this many copies, named `<name>-0`, `<name>-1`, and so on.
+    pub instances: Option<u32>,
+    /// Restart instances one at a time, each once the previous is ready.
+    pub rolling_restart: Option<bool>,
+    /// Shutdown order, OOM score and pressure throttling; see
+    /// `ProcessClass`.
+    pub class: Option<ProcessClass>,
+    /// Scheduling niceness, from -20 (most favoured) to 19.
+    pub nice: Option<i32>,
+    /// IO scheduling class and level, e.g. `"idle"` or `"best_effort:7"`.
+    pub ionice: Option<String>,
+    /// Resource limits set before the process execs, keyed by
+    /// `RLIMIT_NAMES`.
+    pub rlimits: Option<BTreeMap<String, Rlimit>>,
+    /// Thresholds the resource sampler checks; see `Alerts`.
+    pub alert: Option<Alerts>,
+    pub pre_start: Option<Hook>,
+    pub post_start: Option<Hook>,
+    pub pre_stop: Option<Hook>,
+    pub post_stop: Option<Hook>,
+    /// Webhooks told about crashes, restarts and other lifecycle events.
+    pub notify: Option<Notify>,
     pub cron_restart: Option<String>,
+    pub overlap_policy: Option<OverlapPolicy>,
     pub log_date_format: Option<String>,
+    pub log_per_generation: Option<bool>,
+    pub log_scrub: Option<Vec<String>>,
+    /// Also rotate the logs every hour or day, to files stamped with the
+    /// period.
+    pub log_rotate_interval: Option<RotateInterval>,
+    /// Gzip rotated log files.
+    pub log_compress: Option<bool>,
+    /// `false` keeps ANSI escape sequences (colors) in the log files, which
+    /// otherwise have them stripped.
+    pub log_strip_ansi: Option<bool>,
+    /// Send stdout and stderr down one pipe into the stdout log, so the file
+    /// keeps the order the lines were written in.
+    pub merge_logs: Option<bool>,
+    /// Absolute path to write stdout to instead of the data dir's log.
+    pub out_file: Option<String>,
+    /// Absolute path to write stderr to; the same as `out_file` merges them.
+    pub error_file: Option<String>,
+    /// `false` discards the output instead of logging it.
+    pub log: Option<bool>,
+    /// Run the process on a pseudo-terminal so it keeps colors and line
+    /// buffering; its stderr then goes to the stdout log.
+    pub tty: Option<bool>,
+    pub stdin: Option<StdinMode>,
+    pub ready_check: Option<ReadyCheck>,
+    pub ready_timeout: Option<u64>,
+    pub heartbeat_timeout: Option<u64>,
+    /// Longest a single run may last (e.g. `"10m"`) before it is killed and
+    /// counted as a failure.
+    pub timeout: Option<String>,
+    /// Signal sent when `timeout` expires; defaults to `kill_signal`.
+    pub timeout_signal: Option<String>,
+    /// Output a run must print to count as successful, for tools that exit 0
+    /// whatever happened.
+    pub success_pattern: Option<String>,
+    /// Output that marks a run as failed even if it exits 0.
+    pub failure_pattern: Option<String>,
+    /// Sets `TZ`; must name a zone in the host's tz database.
+    pub timezone: Option<String>,
+    /// Sets `LC_ALL`; must be a locale installed on the host.
+    pub locale: Option<String>,
+    pub policy: Option<String>,
     pub environments: HashMap<String, HashMap<String, String>>,
+    /// The `env_<name>` section applied by `pm3 start --env <name>`; never
+    /// read from the config file.
+    #[serde(skip)]
+    pub active_env: Option<String>,
+}
+
+impl ProcessConfig {
+    /// Whether stdout and stderr share one log file: with `merge_logs`, or
+    /// when `out_file` and `error_file` name the same file.
+    pub fn merges_logs(&self) -> bool {
+        self.merge_logs == Some(true)
+            || (self.out_file.is_some() && self.out_file == self.error_file)
+    }
+
+    /// Select the `env_<name>` section to overlay on `env`. Processes without
+    /// that section are left untouched.
+    pub fn apply_environment(&mut self, name: &str) {
+        if self.environments.contains_key(name) {
+            self.active_env = Some(name.to_string());
+        }
+    }
+
+    /// `restart`, defaulting to `on_failure` for services and `never` for
+    /// tasks.
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.restart
+            .clone()
+            .unwrap_or(match self.kind.unwrap_or_default() {
+                ProcessKind::Service => RestartPolicy::OnFailure,
+                ProcessKind::Task | ProcessKind::Job => RestartPolicy::Never,
+            })
+    }
+
+    /// Whether this is a `kind = "task"` process.
+    pub fn is_task(&self) -> bool {
+        self.kind == Some(ProcessKind::Task)
+    }
+
+    /// Whether this is a `[jobs]` entry.
+    pub fn is_job(&self) -> bool {
+        self.kind == Some(ProcessKind::Job)
+    }
+
+    /// Whether a clean exit means the process is done: tasks and jobs.
+    pub fn runs_to_completion(&self) -> bool {
+        self.is_task() || self.is_job()
+    }
+
+    /// `class`, defaulting to standard.
+    pub fn class(&self) -> ProcessClass {
+        self.class.unwrap_or_default()
+    }
+
+    /// `kill_tree`, defaulting to true.
+    pub fn kill_tree(&self) -> bool {
+        self.kill_tree.unwrap_or(true)
+    }
+
+    /// The run time limit, if one is set and valid.
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout
+            .as_deref()
+            .and_then(|timeout| parse_duration(timeout).ok())
+    }
+
+    /// `ionice` as an `ioprio_set` value, if set and valid.
+    pub fn ionice(&self) -> Option<i32> {
+        self.ionice
+            .as_deref()
+            .and_then(|ionice| parse_ionice(ionice).ok())
+    }
+
+    /// `restart_delay`, if set and valid.
+    pub fn restart_delay(&self) -> Option<std::time::Duration> {
+        self.restart_delay
+            .as_deref()
+            .and_then(|delay| parse_duration(delay).ok())
+    }
+
+    /// `restart_window`, if set and valid.
+    pub fn restart_window(&self) -> Option<std::time::Duration> {
+        self.restart_window
+            .as_deref()
+            .and_then(|window| parse_duration(window).ok())
+    }
+
+    /// `env` with the active `env_<name>` section, if any, layered on top.
+    /// `timezone` and `locale` come first as `TZ` and `LC_ALL`, so an
+    /// explicit entry in either table wins.
+    pub fn effective_env(&self) -> Option<HashMap<String, String>> {
+        let overlay = self
+            .active_env
+            .as_ref()
+            .and_then(|name| self.environments.get(name));
+        let mut merged: HashMap<String, String> =
+            [("TZ", &self.timezone), ("LC_ALL", &self.locale)]
+                .into_iter()
+                .filter_map(|(key, value)| Some((key.to_string(), value.clone()?)))
+                .collect();
+        if merged.is_empty() && self.env.is_none() && overlay.is_none() {
+            return None;
+        }
+        merged.extend(self.env.clone().unwrap_or_default());
+        merged.extend(overlay.cloned().unwrap_or_default());
+        Some(merged)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,20 +680,59 @@ struct RawProcessConfig {
     health_check: Option<String>,
     kill_timeout: Option<u64>,
     kill_signal: Option<String>,
+    kill_tree: Option<bool>,
+    reload_signal: Option<String>,
     max_restarts: Option<u32>,
     max_memory: Option<String>,
+    fd_limit: Option<u32>,
+    expect_memory: Option<String>,
     min_uptime: Option<u64>,
+    restart_delay: Option<String>,
+    restart_window: Option<String>,
     stop_exit_codes: Option<Vec<i32>>,
     watch: Option<Watch>,
     watch_ignore: Option<Vec<String>>,
     depends_on: Option<Vec<String>>,
     restart: Option<RestartPolicy>,
+    kind: Option<ProcessKind>,
+    after: Option<Vec<String>>,
     group: Option<String>,
-    pre_start: Option<String>,
-    post_stop: Option<String>,
-    notify: Option<String>,
+    instances: Option<u32>,
+    rolling_restart: Option<bool>,
+    class: Option<ProcessClass>,
+    nice: Option<i32>,
+    ionice: Option<String>,
+    rlimits: Option<BTreeMap<String, Rlimit>>,
+    alert: Option<Alerts>,
+    pre_start: Option<Hook>,
+    post_start: Option<Hook>,
+    pre_stop: Option<Hook>,
+    post_stop: Option<Hook>,
+    notify: Option<Notify>,
     cron_restart: Option<String>,
+    overlap_policy: Option<OverlapPolicy>,
     log_date_format: Option<String>,
+    log_per_generation: Option<bool>,
+    log_scrub: Option<Vec<String>>,
+    log_rotate_interval: Option<RotateInterval>,
+    log_compress: Option<bool>,
+    log_strip_ansi: Option<bool>,
+    merge_logs: Option<bool>,
+    out_file: Option<String>,
+    error_file: Option<String>,
+    log: Option<bool>,
+    tty: Option<bool>,
+    stdin: Option<StdinMode>,
+    ready_check: Option<ReadyCheck>,
+    ready_timeout: Option<u64>,
+    heartbeat_timeout: Option<u64>,
+    timeout: Option<String>,
+    timeout_signal: Option<String>,
+    success_pattern: Option<String>,
+    failure_pattern: Option<String>,
+    timezone: Option<String>,
+    locale: Option<String>,
+    policy: Option<String>,
     #[serde(flatten)]
     extra: HashMap<String, toml::Value>,
 }
@@ -85,23 +746,244 @@ pub enum ConfigError {
     #[error("unknown field `{field}` in process `{process}`")]
     UnknownField { process: String, field: String },
     #[error("{0}")]
+    InvalidValue(String),
+    #[error("{0}")]
     IoError(String),
 }
 
+/// Table name of instance `index` of a process with `instances` set.
+pub fn instance_name(name: &str, index: u32) -> String {
+    format!("{name}-{index}")
+}
+
+/// The process and index a table entry is an instance of: `web-2` with
+/// `instances` set in its config is instance 2 of `web`.
+pub fn instance_of<'a>(name: &'a str, config: &ProcessConfig) -> Option<(&'a str, u32)> {
+    config.instances?;
+    let (base, index) = name.rsplit_once('-')?;
+    Some((base, index.parse().ok()?))
+}
+
+/// Replace every process with `instances` set by that many instances;
+/// other processes pass through unchanged.
+pub fn expand_instances(
+    configs: impl IntoIterator<Item = (String, ProcessConfig)>,
+) -> Vec<(String, ProcessConfig)> {
+    let mut expanded = Vec::new();
+    for (name, config) in configs {
+        match config.instances {
+            Some(count) => expanded
+                .extend((0..count).map(|index| (instance_name(&name, index), config.clone()))),
+            None => expanded.push((name, config)),
+        }
+    }
+    expanded
+}
+
+/// Parse a human duration such as `"500ms"`, `"30s"`, `"10m"`, `"2h"` or `"1d"`.
+pub fn parse_duration(input: &str) -> Result<std::time::Duration, ConfigError> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let invalid = || ConfigError::InvalidValue(format!("invalid duration `{input}`"));
+
+    let value: u64 = number.parse().map_err(|_| invalid())?;
+    let multiplier: u64 = match unit.trim() {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return Err(invalid()),
+    };
+    let millis = value.checked_mul(multiplier).ok_or_else(invalid)?;
+    Ok(std::time::Duration::from_millis(millis))
+}
+
+/// Parse a memory size such as `"512M"`, `"4G"` or `"100KB"` into bytes.
+/// Units are binary (1K = 1024 bytes); a bare number is taken as bytes.
+pub fn parse_memory(input: &str) -> Result<u64, ConfigError> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let invalid = || ConfigError::InvalidValue(format!("invalid memory size `{input}`"));
+
+    let value: u64 = number.parse().map_err(|_| invalid())?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let unit = unit
+        .strip_suffix("IB")
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(&unit);
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    value.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+/// Parse an IO priority such as `"idle"`, `"best_effort"` or
+/// `"realtime:0"` into the value `ioprio_set` takes: the class in the top
+/// bits and the level (0 highest to 7, default 4) below.
+pub fn parse_ionice(input: &str) -> Result<i32, ConfigError> {
+    let invalid = || {
+        ConfigError::InvalidValue(format!(
+            "invalid ionice `{input}`; expected idle, best_effort or realtime, \
+             optionally followed by :0-7"
+        ))
+    };
+    let (class, level) = match input.trim().split_once(':') {
+        Some((class, level)) => (class, Some(level.parse::<i32>().map_err(|_| invalid())?)),
+        None => (input.trim(), None),
+    };
+    let class = match class {
+        "realtime" => 1,
+        "best_effort" => 2,
+        // The idle class has no levels
+        "idle" if level.is_none() => 3,
+        _ => return Err(invalid()),
+    };
+    let level = level.unwrap_or(if class == 3 { 0 } else { 4 });
+    if !(0..=7).contains(&level) {
+        return Err(invalid());
+    }
+    Ok(class << 13 | level)
+}
+
+/// Check that `zone` is in the host's tz database (`$TZDIR`, by default
+/// `/usr/share/zoneinfo`). An unknown `TZ` is silently treated as UTC.
+fn check_timezone(zone: &str) -> Result<(), ConfigError> {
+    let invalid = || ConfigError::InvalidValue(format!("unknown timezone `{zone}`"));
+    if zone == "UTC" {
+        return Ok(());
+    }
+    let path = std::path::Path::new(zone);
+    if zone.is_empty()
+        || path.is_absolute()
+        || path
+            .components()
+            .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        return Err(invalid());
+    }
+    let tzdir = std::env::var_os("TZDIR").unwrap_or_else(|| "/usr/share/zoneinfo".into());
+    if std::path::Path::new(&tzdir).join(path).is_file() {
+        Ok(())
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Check that `locale` is installed on the host, as listed by `locale -a`.
+/// Unknown locales make programs fall back to `C` without complaint. Hosts
+/// without the `locale` tool are not checked.
+fn check_locale(locale: &str) -> Result<(), ConfigError> {
+    let Ok(output) = std::process::Command::new("locale").arg("-a").output() else {
+        return Ok(());
+    };
+    let installed = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || locale_known(locale, installed.lines()) {
+        Ok(())
+    } else {
+        Err(ConfigError::InvalidValue(format!(
+            "locale `{locale}` is not installed (see `locale -a`)"
+        )))
+    }
+}
+
+/// Whether `locale` is one of `installed`, or one the C library always has.
+/// Codesets compare the way glibc normalizes them, so `en_US.UTF-8` matches
+/// the `en_US.utf8` that `locale -a` lists.
+fn locale_known<'a>(locale: &str, mut installed: impl Iterator<Item = &'a str>) -> bool {
+    fn normalize(locale: &str) -> String {
+        match locale.split_once('.') {
+            Some((language, codeset)) => {
+                format!("{language}.{}", codeset.to_lowercase().replace('-', ""))
+            }
+            None => locale.to_string(),
+        }
+    }
+    let wanted = normalize(locale);
+    ["C", "POSIX", "C.utf8"].contains(&wanted.as_str())
+        || installed.any(|name| normalize(name) == wanted)
+}
+
 pub fn load_config(path: &std::path::Path) -> Result<HashMap<String, ProcessConfig>, ConfigError> {
     let content = std::fs::read_to_string(path)
         .map_err(|e| ConfigError::IoError(format!("{}: {}", path.display(), e)))?;
     parse_config(&content)
 }
 
+/// Anchor each process to `dir`, the directory of its `pm3.toml`: one
+/// without a `cwd` runs there, and a relative `cwd` is taken from there. A
+/// relative `ready_check` file is then taken from the process's `cwd`. The
+/// daemon runs from `/`, so nothing may depend on its working directory.
+pub fn resolve_cwd(configs: &mut HashMap<String, ProcessConfig>, dir: &std::path::Path) {
+    for config in configs.values_mut() {
+        let cwd = match config.cwd {
+            Some(ref cwd) => dir.join(cwd),
+            None => dir.to_path_buf(),
+        };
+        if let Some(ReadyCheck::File(ref mut file)) = config.ready_check {
+            *file = cwd.join(&*file).to_string_lossy().into_owned();
+        }
+        config.cwd = Some(cwd.to_string_lossy().into_owned());
+    }
+}
+
 pub fn parse_config(content: &str) -> Result<HashMap<String, ProcessConfig>, ConfigError> {
-    let table: HashMap<String, toml::Value> =
+    let mut table: HashMap<String, toml::Value> =
         toml::from_str(content).map_err(|e| ConfigError::TomlParse(e.to_string()))?;
 
     if table.is_empty() {
         return Err(ConfigError::Empty);
     }
 
+    // `[jobs.<name>]` entries are processes that only run on their schedule
+    let jobs = match table.remove("jobs") {
+        Some(toml::Value::Table(jobs)) => jobs,
+        Some(_) => {
+            return Err(ConfigError::InvalidValue(
+                "`jobs` must be a table of jobs".to_string(),
+            ));
+        }
+        None => Default::default(),
+    };
+    for (name, value) in jobs {
+        let toml::Value::Table(mut job) = value else {
+            return Err(ConfigError::InvalidValue(format!(
+                "job `{name}` must be a table"
+            )));
+        };
+        if let Some(field) = ["cron_restart", "kind"]
+            .into_iter()
+            .find(|field| job.contains_key(*field))
+        {
+            return Err(ConfigError::UnknownField {
+                process: name,
+                field: field.to_string(),
+            });
+        }
+        let schedule = job
+            .remove("schedule")
+            .ok_or_else(|| ConfigError::InvalidValue(format!("job `{name}` needs a schedule")))?;
+        job.insert("cron_restart".to_string(), schedule);
+        job.insert("kind".to_string(), "job".into());
+        if table.contains_key(&name) {
+            return Err(ConfigError::InvalidValue(format!(
+                "job `{name}` clashes with a process of the same name"
+            )));
+        }
+        table.insert(name, toml::Value::Table(job));
+    }
+
     let mut configs = HashMap::new();
 
     for (name, value) in table {
@@ -126,6 +1008,180 @@ pub fn parse_config(content: &str) -> Result<HashMap<String, ProcessConfig>, Con
             }
         }
 
+        for signal in [&raw.kill_signal, &raw.reload_signal, &raw.timeout_signal]
+            .into_iter()
+            .flatten()
+        {
+            crate::process::parse_signal(signal)
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        }
+
+        for pattern in raw.log_scrub.iter().flatten() {
+            regex::Regex::new(pattern).map_err(|e| {
+                ConfigError::InvalidValue(format!(
+                    "process `{name}`: invalid log_scrub pattern `{pattern}`: {e}"
+                ))
+            })?;
+        }
+
+        for (field, pattern) in [
+            ("success_pattern", &raw.success_pattern),
+            ("failure_pattern", &raw.failure_pattern),
+        ] {
+            if let Some(pattern) = pattern {
+                regex::Regex::new(pattern).map_err(|e| {
+                    ConfigError::InvalidValue(format!(
+                        "process `{name}`: invalid {field} `{pattern}`: {e}"
+                    ))
+                })?;
+            }
+        }
+
+        for (field, file) in [("out_file", &raw.out_file), ("error_file", &raw.error_file)] {
+            match file {
+                Some(file) if !std::path::Path::new(file).is_absolute() => {
+                    return Err(ConfigError::InvalidValue(format!(
+                        "process `{name}`: {field} must be an absolute path"
+                    )));
+                }
+                Some(_) if raw.log == Some(false) => {
+                    return Err(ConfigError::InvalidValue(format!(
+                        "process `{name}`: {field} has nothing to write with log = false"
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        // Output that is thrown away can't be watched either
+        if raw.log == Some(false) {
+            for (field, set) in [
+                ("success_pattern", raw.success_pattern.is_some()),
+                ("failure_pattern", raw.failure_pattern.is_some()),
+                (
+                    "ready_check log",
+                    matches!(raw.ready_check, Some(ReadyCheck::Log(_))),
+                ),
+            ] {
+                if set {
+                    return Err(ConfigError::InvalidValue(format!(
+                        "process `{name}`: {field} reads the output, which log = false discards"
+                    )));
+                }
+            }
+        }
+
+        match raw.ready_check {
+            Some(ReadyCheck::Http(ref url)) if !url.starts_with("http://") => {
+                return Err(ConfigError::InvalidValue(format!(
+                    "process `{name}`: ready_check http url must start with http://"
+                )));
+            }
+            Some(ReadyCheck::Log(ref pattern)) => {
+                regex::Regex::new(pattern).map_err(|e| {
+                    ConfigError::InvalidValue(format!(
+                        "process `{name}`: invalid ready_check log pattern `{pattern}`: {e}"
+                    ))
+                })?;
+            }
+            _ => {}
+        }
+
+        if let Some(ref notify) = raw.notify {
+            let targets = notify.targets();
+            if targets.is_empty() {
+                return Err(ConfigError::InvalidValue(format!(
+                    "process `{name}`: notify needs at least one url or channel"
+                )));
+            }
+            for target in targets {
+                if let NotifyTarget::Webhook(url) = target
+                    && crate::http::parse_url(url).is_none()
+                {
+                    return Err(ConfigError::InvalidValue(format!(
+                        "process `{name}`: notify target `{url}` is neither a channel \
+                         (slack, discord, email) nor an http(s):// URL"
+                    )));
+                }
+            }
+        }
+
+        if let Some(ref schedule) = raw.cron_restart {
+            schedule
+                .parse::<crate::cron::Schedule>()
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        } else if raw.kind == Some(ProcessKind::Job) {
+            return Err(ConfigError::InvalidValue(format!(
+                "process `{name}`: kind = \"job\" needs a cron_restart schedule"
+            )));
+        }
+
+        for duration in [&raw.timeout, &raw.restart_delay, &raw.restart_window]
+            .into_iter()
+            .flatten()
+        {
+            parse_duration(duration)
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        }
+
+        if let Some(nice) = raw.nice
+            && !(-20..=19).contains(&nice)
+        {
+            return Err(ConfigError::InvalidValue(format!(
+                "process `{name}`: nice must be between -20 and 19"
+            )));
+        }
+
+        if let Some(ref ionice) = raw.ionice {
+            parse_ionice(ionice)
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        }
+
+        for (resource, limit) in raw.rlimits.iter().flatten() {
+            if !RLIMIT_NAMES.contains(&resource.as_str()) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "process `{name}`: unknown rlimit `{resource}`; expected one of {}",
+                    RLIMIT_NAMES.join(", ")
+                )));
+            }
+            limit.limits().map_err(|e| {
+                ConfigError::InvalidValue(format!("process `{name}`: rlimit `{resource}`: {e}"))
+            })?;
+        }
+
+        if let Some(ref alert) = raw.alert {
+            alert
+                .validate()
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        }
+
+        if raw.fd_limit == Some(0) {
+            return Err(ConfigError::InvalidValue(format!(
+                "process `{name}`: fd_limit must be at least 1"
+            )));
+        }
+
+        if raw.instances == Some(0) {
+            return Err(ConfigError::InvalidValue(format!(
+                "process `{name}`: instances must be at least 1"
+            )));
+        }
+
+        if let Some(ref timezone) = raw.timezone {
+            check_timezone(timezone)
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        }
+
+        if let Some(ref locale) = raw.locale {
+            check_locale(locale)
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        }
+
+        for size in [&raw.max_memory, &raw.expect_memory].into_iter().flatten() {
+            parse_memory(size)
+                .map_err(|e| ConfigError::InvalidValue(format!("process `{name}`: {e}")))?;
+        }
+
         configs.insert(
             name,
             ProcessConfig {
@@ -136,25 +1192,77 @@ pub fn parse_config(content: &str) -> Result<HashMap<String, ProcessConfig>, Con
                 health_check: raw.health_check,
                 kill_timeout: raw.kill_timeout,
                 kill_signal: raw.kill_signal,
+                kill_tree: raw.kill_tree,
+                reload_signal: raw.reload_signal,
                 max_restarts: raw.max_restarts,
                 max_memory: raw.max_memory,
+                fd_limit: raw.fd_limit,
+                expect_memory: raw.expect_memory,
                 min_uptime: raw.min_uptime,
+                restart_delay: raw.restart_delay,
+                restart_window: raw.restart_window,
                 stop_exit_codes: raw.stop_exit_codes,
                 watch: raw.watch,
                 watch_ignore: raw.watch_ignore,
                 depends_on: raw.depends_on,
                 restart: raw.restart,
+                kind: raw.kind,
+                after: raw.after,
                 group: raw.group,
+                instances: raw.instances,
+                rolling_restart: raw.rolling_restart,
+                class: raw.class,
+                nice: raw.nice,
+                ionice: raw.ionice,
+                rlimits: raw.rlimits,
+                alert: raw.alert,
                 pre_start: raw.pre_start,
+                post_start: raw.post_start,
+                pre_stop: raw.pre_stop,
                 post_stop: raw.post_stop,
                 notify: raw.notify,
                 cron_restart: raw.cron_restart,
+                overlap_policy: raw.overlap_policy,
                 log_date_format: raw.log_date_format,
+                log_per_generation: raw.log_per_generation,
+                log_scrub: raw.log_scrub,
+                log_rotate_interval: raw.log_rotate_interval,
+                log_compress: raw.log_compress,
+                log_strip_ansi: raw.log_strip_ansi,
+                merge_logs: raw.merge_logs,
+                out_file: raw.out_file,
+                error_file: raw.error_file,
+                log: raw.log,
+                tty: raw.tty,
+                stdin: raw.stdin,
+                ready_check: raw.ready_check,
+                ready_timeout: raw.ready_timeout,
+                heartbeat_timeout: raw.heartbeat_timeout,
+                timeout: raw.timeout,
+                timeout_signal: raw.timeout_signal,
+                success_pattern: raw.success_pattern,
+                failure_pattern: raw.failure_pattern,
+                timezone: raw.timezone,
+                locale: raw.locale,
+                policy: raw.policy,
                 environments,
+                active_env: None,
             },
         );
     }
 
+    for (name, config) in &configs {
+        for (instance, _) in expand_instances([(name.clone(), config.clone())]) {
+            if instance != *name && configs.contains_key(&instance) {
+                return Err(ConfigError::InvalidValue(format!(
+                    "process `{instance}` clashes with an instance of `{name}`"
+                )));
+            }
+        }
+    }
+
+    crate::pipeline::check(&configs).map_err(|e| ConfigError::InvalidValue(e.to_string()))?;
+
     Ok(configs)
 }
 
@@ -173,6 +1281,7 @@ env_file = ".env"
 health_check = "http://localhost:3000/health"
 kill_timeout = 5000
 kill_signal = "SIGTERM"
+reload_signal = "SIGHUP"
 max_restarts = 10
 max_memory = "512M"
 min_uptime = 1000
@@ -183,10 +1292,29 @@ depends_on = ["db"]
 restart = "on_failure"
 group = "backend"
 pre_start = "npm run migrate"
+post_start = "curl -s localhost:3000/warm"
+pre_stop = { command = "./drain.sh", timeout = 10000 }
 post_stop = "echo stopped"
 notify = "slack"
 cron_restart = "0 3 * * *"
+overlap_policy = "queue"
 log_date_format = "%Y-%m-%d %H:%M:%S"
+log_per_generation = true
+log_scrub = ["(?i)password=\\S+", "Bearer \\S+"]
+log_rotate_interval = "daily"
+log_compress = true
+log_strip_ansi = false
+merge_logs = true
+ready_check = { http = "http://localhost:3000/health" }
+ready_timeout = 60000
+heartbeat_timeout = 15000
+timeout = "10m"
+timeout_signal = "SIGINT"
+restart_delay = "2s"
+restart_window = "1m"
+success_pattern = "^Backup complete"
+failure_pattern = "(?i)error:"
+policy = "policies/web.wasm"
 
 [web.env_production]
 DATABASE_URL = "postgres://prod/db"
@@ -208,6 +1336,7 @@ DATABASE_URL = "postgres://prod/db"
         );
         assert_eq!(web.kill_timeout, Some(5000));
         assert_eq!(web.kill_signal.as_deref(), Some("SIGTERM"));
+        assert_eq!(web.reload_signal.as_deref(), Some("SIGHUP"));
         assert_eq!(web.max_restarts, Some(10));
         assert_eq!(web.max_memory.as_deref(), Some("512M"));
         assert_eq!(web.min_uptime, Some(1000));
@@ -220,11 +1349,61 @@ DATABASE_URL = "postgres://prod/db"
         assert_eq!(web.depends_on, Some(vec!["db".to_string()]));
         assert_eq!(web.restart, Some(RestartPolicy::OnFailure));
         assert_eq!(web.group.as_deref(), Some("backend"));
-        assert_eq!(web.pre_start.as_deref(), Some("npm run migrate"));
-        assert_eq!(web.post_stop.as_deref(), Some("echo stopped"));
-        assert_eq!(web.notify.as_deref(), Some("slack"));
+        assert_eq!(
+            web.pre_start,
+            Some(Hook::Command("npm run migrate".to_string()))
+        );
+        assert_eq!(
+            web.post_start.as_ref().map(Hook::command),
+            Some("curl -s localhost:3000/warm")
+        );
+        assert_eq!(
+            web.pre_stop,
+            Some(Hook::Detailed {
+                command: "./drain.sh".to_string(),
+                timeout: Some(10000),
+            })
+        );
+        assert_eq!(web.pre_stop.as_ref().and_then(Hook::timeout), Some(10000));
+        assert_eq!(
+            web.post_stop,
+            Some(Hook::Command("echo stopped".to_string()))
+        );
+        assert_eq!(
+            web.notify.as_ref().unwrap().targets(),
+            [NotifyTarget::Channel(Channel::Slack)]
+        );
         assert_eq!(web.cron_restart.as_deref(), Some("0 3 * * *"));
+        assert_eq!(web.overlap_policy, Some(OverlapPolicy::Queue));
         assert_eq!(web.log_date_format.as_deref(), Some("%Y-%m-%d %H:%M:%S"));
+        assert_eq!(web.log_per_generation, Some(true));
+        assert_eq!(web.log_rotate_interval, Some(RotateInterval::Daily));
+        assert_eq!(web.log_compress, Some(true));
+        assert_eq!(web.log_strip_ansi, Some(false));
+        assert_eq!(web.merge_logs, Some(true));
+        assert_eq!(
+            web.log_scrub,
+            Some(vec![
+                "(?i)password=\\S+".to_string(),
+                "Bearer \\S+".to_string()
+            ])
+        );
+        assert_eq!(
+            web.ready_check,
+            Some(ReadyCheck::Http("http://localhost:3000/health".to_string()))
+        );
+        assert_eq!(web.ready_timeout, Some(60000));
+        assert_eq!(web.heartbeat_timeout, Some(15000));
+        assert_eq!(web.timeout(), Some(std::time::Duration::from_secs(600)));
+        assert_eq!(web.restart_delay(), Some(std::time::Duration::from_secs(2)));
+        assert_eq!(
+            web.restart_window(),
+            Some(std::time::Duration::from_secs(60))
+        );
+        assert_eq!(web.timeout_signal.as_deref(), Some("SIGINT"));
+        assert_eq!(web.success_pattern.as_deref(), Some("^Backup complete"));
+        assert_eq!(web.failure_pattern.as_deref(), Some("(?i)error:"));
+        assert_eq!(web.policy.as_deref(), Some("policies/web.wasm"));
         assert_eq!(
             web.environments
                 .get("production")
@@ -286,6 +1465,7 @@ command = "cargo run"
         assert!(api.health_check.is_none());
         assert!(api.kill_timeout.is_none());
         assert!(api.kill_signal.is_none());
+        assert!(api.reload_signal.is_none());
         assert!(api.max_restarts.is_none());
         assert!(api.max_memory.is_none());
         assert!(api.min_uptime.is_none());
@@ -296,10 +1476,23 @@ command = "cargo run"
         assert!(api.restart.is_none());
         assert!(api.group.is_none());
         assert!(api.pre_start.is_none());
+        assert!(api.post_start.is_none());
+        assert!(api.pre_stop.is_none());
         assert!(api.post_stop.is_none());
         assert!(api.notify.is_none());
         assert!(api.cron_restart.is_none());
+        assert!(api.overlap_policy.is_none());
         assert!(api.log_date_format.is_none());
+        assert!(api.log_per_generation.is_none());
+        assert!(api.log_scrub.is_none());
+        assert!(api.ready_check.is_none());
+        assert!(api.ready_timeout.is_none());
+        assert!(api.heartbeat_timeout.is_none());
+        assert!(api.timeout.is_none());
+        assert!(api.timeout_signal.is_none());
+        assert!(api.success_pattern.is_none());
+        assert!(api.failure_pattern.is_none());
+        assert!(api.policy.is_none());
         assert!(api.environments.is_empty());
     }
 
@@ -393,6 +1586,614 @@ restart = "never"
         assert_eq!(configs["c"].restart, Some(RestartPolicy::Never));
     }
 
+    #[test]
+    fn test_parse_duration_units() {
+        use std::time::Duration;
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_duration(" 5 s ").unwrap(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(matches!(
+            parse_duration("30"),
+            Err(ConfigError::InvalidValue(_))
+        ));
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10 minutes").is_err());
+        assert!(parse_duration("").is_err());
+        assert!(matches!(
+            parse_duration("18446744073709551615d"),
+            Err(ConfigError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_memory_units() {
+        assert_eq!(parse_memory("1024").unwrap(), 1024);
+        assert_eq!(parse_memory("100K").unwrap(), 100 * 1024);
+        assert_eq!(parse_memory("512M").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_memory("512mb").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_memory("4G").unwrap(), 4 * 1024 * 1024 * 1024);
+        assert_eq!(parse_memory("2GiB").unwrap(), 2 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_parse_memory_invalid() {
+        assert!(parse_memory("G").is_err());
+        assert!(parse_memory("4X").is_err());
+        assert!(parse_memory("lots").is_err());
+    }
+
+    #[test]
+    fn test_rlimits() {
+        let toml = r#"
+[db]
+command = "postgres"
+
+[db.rlimits]
+nofile = 65536
+core = "unlimited"
+nproc = { soft = 512, hard = 1024 }
+"#;
+        let configs = parse_config(toml).unwrap();
+        let rlimits = configs["db"].rlimits.as_ref().unwrap();
+        assert_eq!(rlimits["nofile"].limits(), Ok((Some(65536), Some(65536))));
+        assert_eq!(rlimits["core"].limits(), Ok((None, None)));
+        assert_eq!(rlimits["nproc"].limits(), Ok((Some(512), Some(1024))));
+
+        for limits in [
+            "files = 10",
+            "nofile = \"lots\"",
+            "nofile = { soft = 10, hard = 5 }",
+            "nofile = { soft = \"unlimited\", hard = 5 }",
+        ] {
+            let toml = format!("[db]\ncommand = \"x\"\n[db.rlimits]\n{limits}\n");
+            let err = parse_config(&toml).unwrap_err();
+            assert!(
+                matches!(err, ConfigError::InvalidValue(_)),
+                "{limits}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_alerts() {
+        let toml = r#"
+[web]
+command = "x"
+
+[web.alert]
+cpu = { above = 90, for = "5m", action = "restart" }
+memory = { above = "1G" }
+"#;
+        let configs = parse_config(toml).unwrap();
+        let alert = configs["web"].alert.as_ref().unwrap();
+        assert_eq!(alert.cpu_above(), Some(90.0));
+        assert_eq!(alert.memory_above(), Some(1 << 30));
+        let cpu = alert.cpu.as_ref().unwrap();
+        assert_eq!(cpu.action, AlertAction::Restart);
+        assert_eq!(cpu.duration(), std::time::Duration::from_secs(300));
+        let memory = alert.memory.as_ref().unwrap();
+        assert_eq!(memory.action, AlertAction::Notify);
+        assert_eq!(memory.duration(), std::time::Duration::ZERO);
+
+        for alert in [
+            r#"cpu = { above = 0 }"#,
+            r#"cpu = { above = "90%" }"#,
+            r#"memory = { above = "lots" }"#,
+            r#"memory = { above = "1G", for = "soon" }"#,
+        ] {
+            let toml = format!("[web]\ncommand = \"x\"\n[web.alert]\n{alert}\n");
+            assert!(
+                matches!(parse_config(&toml), Err(ConfigError::InvalidValue(_))),
+                "{alert}"
+            );
+        }
+        for alert in [
+            r#"disk = { above = 90 }"#,
+            r#"cpu = { above = 90, action = "page" }"#,
+        ] {
+            let toml = format!("[web]\ncommand = \"x\"\n[web.alert]\n{alert}\n");
+            assert!(
+                matches!(parse_config(&toml), Err(ConfigError::TomlParse(_))),
+                "{alert}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_priorities() {
+        assert_eq!(parse_ionice("idle").unwrap(), 3 << 13);
+        assert_eq!(parse_ionice("best_effort").unwrap(), 2 << 13 | 4);
+        assert_eq!(parse_ionice("realtime:0").unwrap(), 1 << 13);
+        for bad in ["idle:3", "best_effort:8", "fast", "realtime:x"] {
+            assert!(parse_ionice(bad).is_err(), "{bad}");
+        }
+
+        let configs =
+            parse_config("[worker]\ncommand = \"x\"\nnice = 10\nionice = \"best_effort:7\"\n")
+                .unwrap();
+        assert_eq!(configs["worker"].nice, Some(10));
+        assert_eq!(configs["worker"].ionice(), Some(2 << 13 | 7));
+        for toml in [
+            "[worker]\ncommand = \"x\"\nnice = 20\n",
+            "[worker]\ncommand = \"x\"\nionice = \"slow\"\n",
+        ] {
+            let err = parse_config(toml).unwrap_err();
+            assert!(matches!(err, ConfigError::InvalidValue(_)), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_cwd() {
+        let mut configs = parse_config(
+            "[web]\ncommand = \"x\"\n[api]\ncommand = \"x\"\ncwd = \"api\"\n\
+             ready_check = { file = \"ready\" }\n\
+             [db]\ncommand = \"x\"\ncwd = \"/var/db\"\n",
+        )
+        .unwrap();
+        resolve_cwd(&mut configs, std::path::Path::new("/srv/app"));
+        assert_eq!(configs["web"].cwd.as_deref(), Some("/srv/app"));
+        assert_eq!(configs["api"].cwd.as_deref(), Some("/srv/app/api"));
+        assert_eq!(
+            configs["api"].ready_check,
+            Some(ReadyCheck::File("/srv/app/api/ready".to_string()))
+        );
+        assert_eq!(configs["db"].cwd.as_deref(), Some("/var/db"));
+    }
+
+    #[test]
+    fn test_tty_and_stdin() {
+        let configs =
+            parse_config("[web]\ncommand = \"x\"\ntty = true\nstdin = \"pipe\"\n").unwrap();
+        assert_eq!(configs["web"].tty, Some(true));
+        assert_eq!(configs["web"].stdin, Some(StdinMode::Pipe));
+        assert!(parse_config("[web]\ncommand = \"x\"\nstdin = \"tty\"\n").is_err());
+    }
+
+    #[test]
+    fn test_kill_tree() {
+        let configs =
+            parse_config("[web]\ncommand = \"x\"\n[worker]\ncommand = \"x\"\nkill_tree = false\n")
+                .unwrap();
+        assert!(configs["web"].kill_tree());
+        assert!(!configs["worker"].kill_tree());
+    }
+
+    #[test]
+    fn test_fd_limit() {
+        let configs = parse_config("[web]\ncommand = \"x\"\nfd_limit = 4096\n").unwrap();
+        assert_eq!(configs["web"].fd_limit, Some(4096));
+        let err = parse_config("[web]\ncommand = \"x\"\nfd_limit = 0\n").unwrap_err();
+        assert!(err.to_string().contains("fd_limit"), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_max_memory_rejected() {
+        let toml = r#"
+[web]
+command = "node server.js"
+max_memory = "a lot"
+"#;
+        let err = parse_config(toml).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("web")),
+            "{err}"
+        );
+
+        let err = parse_config("[web]\ncommand = \"x\"\nexpect_memory = \"2X\"\n").unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("`2X`")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_kill_signal_rejected() {
+        let toml = r#"
+[web]
+command = "node server.js"
+kill_signal = "SIGNOPE"
+"#;
+        let err = parse_config(toml).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("SIGNOPE")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_reload_signal_rejected() {
+        let toml = r#"
+[web]
+command = "nginx"
+reload_signal = "SIGRELOAD"
+"#;
+        let err = parse_config(toml).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("SIGRELOAD")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_cron_restart_rejected() {
+        let toml = r#"
+[job]
+command = "backup.sh"
+cron_restart = "0 25 * * *"
+"#;
+        let err = parse_config(toml).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("job") && m.contains("0 25")),
+            "{err}"
+        );
+
+        let toml = r#"
+[job]
+command = "backup.sh"
+overlap_policy = "pile-up"
+"#;
+        assert!(parse_config(toml).is_err());
+    }
+
+    #[test]
+    fn test_invalid_timeout_rejected() {
+        let toml = r#"
+[job]
+command = "backup.sh"
+timeout = "forever"
+"#;
+        let err = parse_config(toml).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("forever")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_task_kind_and_after() {
+        let toml = r#"
+[migrate]
+command = "./migrate.sh"
+kind = "task"
+
+[deploy]
+command = "./deploy.sh"
+kind = "task"
+after = ["migrate"]
+restart = "on_failure"
+
+[web]
+command = "node server.js"
+"#;
+        let configs = parse_config(toml).unwrap();
+        assert_eq!(configs["migrate"].kind, Some(ProcessKind::Task));
+        assert_eq!(configs["migrate"].restart_policy(), RestartPolicy::Never);
+        assert_eq!(configs["deploy"].after, Some(vec!["migrate".to_string()]));
+        assert_eq!(configs["deploy"].restart_policy(), RestartPolicy::OnFailure);
+        assert_eq!(configs["web"].restart_policy(), RestartPolicy::OnFailure);
+
+        // Services cannot join pipelines, and cycles are rejected
+        for toml in [
+            "[web]\ncommand = \"x\"\nafter = [\"web\"]\n",
+            "[a]\ncommand = \"x\"\nkind = \"task\"\nafter = [\"b\"]\n\
+             [b]\ncommand = \"x\"\nkind = \"task\"\nafter = [\"a\"]\n",
+        ] {
+            let err = parse_config(toml).unwrap_err();
+            assert!(matches!(err, ConfigError::InvalidValue(_)), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_jobs_section() {
+        let toml = r#"
+[web]
+command = "node server.js"
+
+[jobs.rotate]
+schedule = "0 * * * *"
+command = "./rotate.sh"
+cwd = "/var/app"
+
+[jobs.vacuum]
+schedule = "@daily"
+command = "psql -c vacuum"
+restart = "on_failure"
+"#;
+        let configs = parse_config(toml).unwrap();
+        assert_eq!(configs.len(), 3);
+        let rotate = &configs["rotate"];
+        assert!(rotate.is_job() && rotate.runs_to_completion());
+        assert_eq!(rotate.cron_restart.as_deref(), Some("0 * * * *"));
+        assert_eq!(rotate.cwd.as_deref(), Some("/var/app"));
+        assert_eq!(rotate.restart_policy(), RestartPolicy::Never);
+        assert_eq!(configs["vacuum"].restart_policy(), RestartPolicy::OnFailure);
+        assert!(!configs["web"].is_job());
+
+        for toml in [
+            "[jobs.a]\ncommand = \"x\"\n",
+            "[jobs.a]\nschedule = \"not cron\"\ncommand = \"x\"\n",
+            "[a]\ncommand = \"x\"\n[jobs.a]\nschedule = \"@daily\"\ncommand = \"x\"\n",
+            "[a]\ncommand = \"x\"\nkind = \"job\"\n",
+            "jobs = 1\n",
+        ] {
+            let err = parse_config(toml).unwrap_err();
+            assert!(matches!(err, ConfigError::InvalidValue(_)), "{toml}: {err}");
+        }
+        let err =
+            parse_config("[jobs.a]\nschedule = \"@daily\"\ncommand = \"x\"\nkind = \"task\"\n")
+                .unwrap_err();
+        assert!(matches!(err, ConfigError::UnknownField { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_timezone_and_locale() {
+        let toml = r#"
+[web]
+command = "node server.js"
+timezone = "UTC"
+locale = "C.UTF-8"
+env = { LC_ALL = "POSIX" }
+"#;
+        let configs = parse_config(toml).unwrap();
+        let env = configs["web"].effective_env().unwrap();
+        assert_eq!(env["TZ"], "UTC");
+        assert_eq!(env["LC_ALL"], "POSIX");
+
+        for zone in ["Mars/Olympus_Mons", "../../etc/passwd"] {
+            let toml = format!("[web]\ncommand = \"x\"\ntimezone = \"{zone}\"\n");
+            let err = parse_config(&toml).unwrap_err();
+            assert!(
+                matches!(&err, ConfigError::InvalidValue(m) if m.contains("unknown timezone")),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_instances() {
+        let configs =
+            parse_config("[web]\ncommand = \"x\"\ninstances = 3\n[db]\ncommand = \"y\"\n").unwrap();
+        let mut expanded: Vec<String> = expand_instances(configs.clone())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        expanded.sort();
+        assert_eq!(expanded, ["db", "web-0", "web-1", "web-2"]);
+        assert_eq!(instance_of("web-2", &configs["web"]), Some(("web", 2)));
+        assert_eq!(instance_of("web-2", &configs["db"]), None);
+
+        for toml in [
+            "[web]\ncommand = \"x\"\ninstances = 0\n",
+            "[web]\ncommand = \"x\"\ninstances = 2\n[web-1]\ncommand = \"y\"\n",
+        ] {
+            let err = parse_config(toml).unwrap_err();
+            assert!(matches!(err, ConfigError::InvalidValue(_)), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_class() {
+        let configs =
+            parse_config("[db]\ncommand = \"x\"\nclass = \"critical\"\n[web]\ncommand = \"y\"\n")
+                .unwrap();
+        assert_eq!(configs["db"].class(), ProcessClass::Critical);
+        assert!(!configs["db"].class().defers_under_pressure());
+        assert_eq!(configs["web"].class(), ProcessClass::Standard);
+        assert_eq!(configs["web"].class().oom_score_adj(), None);
+        assert!(ProcessClass::Batch.stop_order() < ProcessClass::Critical.stop_order());
+
+        let err = parse_config("[web]\ncommand = \"x\"\nclass = \"urgent\"\n").unwrap_err();
+        assert!(matches!(err, ConfigError::TomlParse(_)), "{err}");
+    }
+
+    #[test]
+    fn test_locale_known_normalizes_codesets() {
+        let installed = ["C", "en_US.utf8", "de_DE@euro"];
+        assert!(locale_known("en_US.UTF-8", installed.into_iter()));
+        assert!(locale_known("de_DE@euro", installed.into_iter()));
+        assert!(locale_known("C.UTF-8", std::iter::empty()));
+        assert!(!locale_known("fr_FR.UTF-8", installed.into_iter()));
+    }
+
+    #[test]
+    fn test_invalid_output_pattern_rejected() {
+        let toml = r#"
+[backup]
+command = "./backup.sh"
+failure_pattern = "ERROR ("
+"#;
+        let err = parse_config(toml).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("invalid failure_pattern")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_log_scrub_rejected() {
+        let toml = r#"
+[web]
+command = "node server.js"
+log_scrub = ["token=(\\S+"]
+"#;
+        let err = parse_config(toml).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::InvalidValue(m) if m.contains("log_scrub")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_log_destinations() {
+        let toml = r#"
+[web]
+command = "node server.js"
+out_file = "/var/log/web.log"
+error_file = "/var/log/web.log"
+
+[quiet]
+command = "./noisy.sh"
+log = false
+"#;
+        let configs = parse_config(toml).unwrap();
+        assert_eq!(configs["web"].out_file.as_deref(), Some("/var/log/web.log"));
+        assert!(configs["web"].merges_logs());
+        assert_eq!(configs["quiet"].log, Some(false));
+        assert!(!configs["quiet"].merges_logs());
+
+        for (extra, message) in [
+            (
+                r#"out_file = "web.log""#,
+                "out_file must be an absolute path",
+            ),
+            (
+                "log = false\nerror_file = \"/var/log/web.log\"",
+                "error_file has nothing to write",
+            ),
+            (
+                "log = false\nsuccess_pattern = \"done\"",
+                "success_pattern reads the output",
+            ),
+        ] {
+            let toml = format!("[web]\ncommand = \"x\"\n{extra}\n");
+            let err = parse_config(&toml).unwrap_err();
+            assert!(
+                matches!(&err, ConfigError::InvalidValue(m) if m.contains(message)),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_ready_check_variants() {
+        let toml = r#"
+[a]
+command = "x"
+ready_check = { port = 8080 }
+
+[b]
+command = "x"
+ready_check = { file = "/tmp/ready" }
+
+[c]
+command = "x"
+ready_check = { log = "listening on \\d+" }
+
+[d]
+command = "x"
+ready_check = "control"
+"#;
+        let configs = parse_config(toml).unwrap();
+        assert_eq!(configs["a"].ready_check, Some(ReadyCheck::Port(8080)));
+        assert_eq!(
+            configs["b"].ready_check,
+            Some(ReadyCheck::File("/tmp/ready".to_string()))
+        );
+        assert!(matches!(configs["c"].ready_check, Some(ReadyCheck::Log(_))));
+        assert_eq!(configs["d"].ready_check, Some(ReadyCheck::Control));
+    }
+
+    #[test]
+    fn test_invalid_ready_check_rejected() {
+        for check in [r#"{ http = "https://example.com" }"#, r#"{ log = "(" }"#] {
+            let toml = format!("[web]\ncommand = \"x\"\nready_check = {check}\n");
+            let err = parse_config(&toml).unwrap_err();
+            assert!(
+                matches!(&err, ConfigError::InvalidValue(m) if m.contains("ready_check")),
+                "{err}"
+            );
+        }
+        let toml = "[web]\ncommand = \"x\"\nready_check = { tcp = 1 }\n";
+        assert!(matches!(
+            parse_config(toml).unwrap_err(),
+            ConfigError::TomlParse(_)
+        ));
+    }
+
+    #[test]
+    fn test_notify_variants() {
+        let toml = r#"
+[a]
+command = "x"
+notify = ["http://one/hook", "https://two:8443/hook", "email"]
+
+[b]
+command = "x"
+notify = { urls = ["http://one/hook"], channels = ["discord"], events = ["crash", "unhealthy"] }
+"#;
+        let configs = parse_config(toml).unwrap();
+        let a = configs["a"].notify.as_ref().unwrap();
+        assert_eq!(
+            a.targets(),
+            [
+                NotifyTarget::Webhook("http://one/hook"),
+                NotifyTarget::Webhook("https://two:8443/hook"),
+                NotifyTarget::Channel(Channel::Email),
+            ]
+        );
+        assert!(a.wants(NotifyEvent::Crash) && a.wants(NotifyEvent::Restart));
+        assert!(!a.wants(NotifyEvent::Start));
+        let b = configs["b"].notify.as_ref().unwrap();
+        assert_eq!(
+            b.targets(),
+            [
+                NotifyTarget::Webhook("http://one/hook"),
+                NotifyTarget::Channel(Channel::Discord),
+            ]
+        );
+        assert!(b.wants(NotifyEvent::Unhealthy));
+        assert!(!b.wants(NotifyEvent::Restart));
+
+        for notify in [r#""teams""#, "[]", "{ urls = [] }"] {
+            let toml = format!("[web]\ncommand = \"x\"\nnotify = {notify}\n");
+            let err = parse_config(&toml).unwrap_err();
+            assert!(
+                matches!(&err, ConfigError::InvalidValue(m) if m.contains("notify")),
+                "{err}"
+            );
+        }
+        for notify in [
+            r#"{ urls = ["http://h/"], events = ["boot"] }"#,
+            r#"{ channels = ["teams"] }"#,
+        ] {
+            let toml = format!("[web]\ncommand = \"x\"\nnotify = {notify}\n");
+            assert!(matches!(
+                parse_config(&toml).unwrap_err(),
+                ConfigError::TomlParse(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_apply_environment_overlays_env() {
+        let input = r#"
+[web]
+command = "node server.js"
+env = { PORT = "3000", NODE_ENV = "development" }
+
+[web.env_production]
+NODE_ENV = "production"
+"#;
+        let mut web = parse_config(input).unwrap().remove("web").unwrap();
+        web.apply_environment("staging");
+        assert_eq!(web.active_env, None);
+        assert_eq!(web.effective_env(), web.env);
+
+        web.apply_environment("production");
+        assert_eq!(web.active_env.as_deref(), Some("production"));
+        let env = web.effective_env().unwrap();
+        assert_eq!(env["NODE_ENV"], "production");
+        assert_eq!(env["PORT"], "3000");
+        assert_eq!(web.env.as_ref().unwrap()["NODE_ENV"], "development");
+    }
+
     #[test]
     fn test_env_environment_sections() {
         let input = r#"
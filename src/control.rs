@@ -0,0 +1,355 @@
+use crate::clock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+// ---------------------------------------------------------------------------
+// Protocol
+// ---------------------------------------------------------------------------
+//
+// Every process gets a unix socket whose path is in `$PM3_CONTROL_SOCKET`.
+// Apps may connect and write newline-delimited JSON; one connection can stay
+// open for the process lifetime or be opened per message. pm3 writes back a
+// `shutdown` line on open connections when a stop begins, and an `error`
+// line for messages it cannot parse.
+
+pub const CONTROL_SOCKET_ENV: &str = "PM3_CONTROL_SOCKET";
+
+/// Longest drain a process may request with a `stopping` message.
+pub const MAX_DRAIN: Duration = Duration::from_secs(300);
+
+/// Messages an app sends to pm3.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlMessage {
+    /// The app is serving; satisfies `ready_check = "control"`.
+    Ready,
+    /// The app is alive; resets the `heartbeat_timeout` clock.
+    Heartbeat,
+    /// A custom gauge, shown with the process's resource usage.
+    Metric { name: String, value: f64 },
+    /// The app received the stop request and is draining; pm3 holds off
+    /// SIGKILL for up to `timeout_ms` more (capped at five minutes).
+    Stopping {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+}
+
+/// Messages pm3 sends to an app.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonMessage {
+    /// pm3 is about to stop the process.
+    Shutdown,
+    Error {
+        message: String,
+    },
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+/// What the app has reported over its control socket during this run.
+#[derive(Debug)]
+pub struct ControlState {
+    ready: watch::Sender<bool>,
+    shutdown: watch::Sender<bool>,
+    last_seen: Mutex<Instant>,
+    metrics: Mutex<BTreeMap<String, f64>>,
+    drain_until: Mutex<Option<Instant>>,
+}
+
+impl Default for ControlState {
+    fn default() -> Self {
+        Self {
+            ready: watch::Sender::new(false),
+            shutdown: watch::Sender::new(false),
+            last_seen: Mutex::new(clock::now()),
+            metrics: Mutex::default(),
+            drain_until: Mutex::default(),
+        }
+    }
+}
+
+impl ControlState {
+    pub fn apply(&self, message: ControlMessage) {
+        match message {
+            ControlMessage::Ready => {
+                self.touch();
+                self.ready.send_replace(true);
+            }
+            ControlMessage::Heartbeat => self.touch(),
+            ControlMessage::Metric { name, value } => {
+                self.metrics.lock().unwrap().insert(name, value);
+            }
+            ControlMessage::Stopping { timeout_ms } => {
+                let drain = Duration::from_millis(timeout_ms.unwrap_or(0)).min(MAX_DRAIN);
+                *self.drain_until.lock().unwrap() = Some(Instant::now() + drain);
+            }
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = clock::now();
+    }
+
+    /// Flips to `true` once the app reports ready.
+    pub fn subscribe_ready(&self) -> watch::Receiver<bool> {
+        self.ready.subscribe()
+    }
+
+    /// Time since the last `ready` or `heartbeat`, or since the socket opened.
+    pub fn silence(&self) -> Duration {
+        clock::elapsed(*self.last_seen.lock().unwrap())
+    }
+
+    pub fn metrics(&self) -> BTreeMap<String, f64> {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    /// Tell connected apps a stop is starting.
+    pub fn request_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// When the drain the app asked for with `stopping` ends.
+    pub fn drain_deadline(&self) -> Option<Instant> {
+        *self.drain_until.lock().unwrap()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Socket
+// ---------------------------------------------------------------------------
+
+/// A bound control socket for one run. Dropping it stops serving and removes
+/// the socket file.
+#[derive(Debug)]
+pub struct ControlChannel {
+    path: PathBuf,
+    pub state: Arc<ControlState>,
+    accept: JoinHandle<()>,
+}
+
+impl ControlChannel {
+    /// Bind a fresh socket for a run of `name` under `dir`. Runs get distinct
+    /// paths so a reload's replacement can listen next to the old instance.
+    pub fn bind(dir: &Path, name: &str) -> std::io::Result<Self> {
+        static NEXT_RUN: AtomicU64 = AtomicU64::new(0);
+        std::fs::create_dir_all(dir)?;
+        let run = NEXT_RUN.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{name}-{run}.sock"));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        let state = Arc::new(ControlState::default());
+        let accept = tokio::spawn(accept_loop(listener, Arc::clone(&state)));
+        Ok(Self {
+            path,
+            state,
+            accept,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlChannel {
+    fn drop(&mut self) {
+        self.accept.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn accept_loop(listener: UnixListener, state: Arc<ControlState>) {
+    // Connections live in the set so aborting the loop closes them too
+    let mut connections = tokio::task::JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    connections.spawn(serve_connection(stream, Arc::clone(&state)));
+                }
+                Err(_) => return,
+            },
+            Some(_) = connections.join_next() => {}
+        }
+    }
+}
+
+async fn serve_connection(stream: UnixStream, state: Arc<ControlState>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut shutdown = state.shutdown.subscribe();
+    let mut shutdown_sent = false;
+
+    loop {
+        if !shutdown_sent && *shutdown.borrow_and_update() {
+            shutdown_sent = true;
+            if send(&mut writer, &DaemonMessage::Shutdown).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else {
+                    return;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<ControlMessage>(&line) {
+                    Ok(message) => state.apply(message),
+                    Err(e) => {
+                        let error = DaemonMessage::Error {
+                            message: format!("invalid control message: {e}"),
+                        };
+                        if send(&mut writer, &error).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            changed = shutdown.changed(), if !shutdown_sent => {
+                if changed.is_err() {
+                    shutdown_sent = true;
+                }
+            }
+        }
+    }
+}
+
+async fn send(
+    writer: &mut tokio::net::unix::OwnedWriteHalf,
+    message: &DaemonMessage,
+) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(message).map_err(std::io::Error::other)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connect(channel: &ControlChannel) -> UnixStream {
+        UnixStream::connect(channel.path()).await.unwrap()
+    }
+
+    async fn wait_until(mut cond: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !cond() {
+            assert!(Instant::now() < deadline, "condition not reached");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[test]
+    fn test_message_format() {
+        let message: ControlMessage =
+            serde_json::from_str(r#"{"type":"metric","name":"queue","value":3}"#).unwrap();
+        assert_eq!(
+            message,
+            ControlMessage::Metric {
+                name: "queue".to_string(),
+                value: 3.0
+            }
+        );
+        let message: ControlMessage = serde_json::from_str(r#"{"type":"stopping"}"#).unwrap();
+        assert_eq!(message, ControlMessage::Stopping { timeout_ms: None });
+        assert_eq!(
+            serde_json::to_string(&DaemonMessage::Shutdown).unwrap(),
+            r#"{"type":"shutdown"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ready_heartbeat_and_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = ControlChannel::bind(dir.path(), "web").unwrap();
+        let mut ready = channel.state.subscribe_ready();
+
+        let mut stream = connect(&channel).await;
+        stream
+            .write_all(
+                b"{\"type\":\"metric\",\"name\":\"queue\",\"value\":7.5}\n{\"type\":\"ready\"}\n",
+            )
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(2), ready.wait_for(|r| *r))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(channel.state.metrics().get("queue"), Some(&7.5));
+        assert!(channel.state.silence() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_message_gets_error_reply() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = ControlChannel::bind(dir.path(), "web").unwrap();
+
+        let mut stream = connect(&channel).await;
+        stream.write_all(b"{\"type\":\"bogus\"}\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let reply: DaemonMessage =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(reply, DaemonMessage::Error { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_is_announced_and_drain_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let channel = ControlChannel::bind(dir.path(), "web").unwrap();
+
+        let stream = connect(&channel).await;
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        channel.state.request_shutdown();
+        let reply: DaemonMessage =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(reply, DaemonMessage::Shutdown);
+
+        writer
+            .write_all(b"{\"type\":\"stopping\",\"timeout_ms\":600000}\n")
+            .await
+            .unwrap();
+        wait_until(|| channel.state.drain_deadline().is_some()).await;
+        let drain = channel.state.drain_deadline().unwrap() - Instant::now();
+        assert!(drain <= MAX_DRAIN);
+        assert!(drain > MAX_DRAIN - Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_drop_removes_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = ControlChannel::bind(dir.path(), "web").unwrap();
+        let second = ControlChannel::bind(dir.path(), "web").unwrap();
+        assert_ne!(first.path(), second.path());
+
+        let path = first.path().to_path_buf();
+        assert!(path.exists());
+        drop(first);
+        assert!(!path.exists());
+        assert!(second.path().exists());
+    }
+}
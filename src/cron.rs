@@ -0,0 +1,274 @@
+use chrono::{Datelike, Days, NaiveDateTime, TimeDelta, Timelike};
+
+// ---------------------------------------------------------------------------
+// Schedule
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("invalid cron expression `{expr}`: {reason}")]
+pub struct CronError {
+    expr: String,
+    reason: String,
+}
+
+/// A parsed cron expression: `minute hour day-of-month month day-of-week`,
+/// optionally preceded by a seconds field. Each field is a bit set of the
+/// values it matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day-of-month / day-of-week were restricted (not `*`); when
+    /// both are, a day matching either one fires, as in classic cron.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+const MONTH_NAMES: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl std::str::FromStr for Schedule {
+    type Err = CronError;
+
+    fn from_str(expr: &str) -> Result<Self, CronError> {
+        let error = |reason: String| CronError {
+            expr: expr.to_string(),
+            reason,
+        };
+        let expanded = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let (seconds, rest) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            n => return Err(error(format!("expected 5 or 6 fields, found {n}"))),
+        };
+
+        let parse = |field: &str, min: u32, max: u32, names: &[&str]| {
+            parse_field(field, min, max, names).map_err(|reason| error(reason.to_string()))
+        };
+        let mut weekdays = parse(rest[4], 0, 7, WEEKDAY_NAMES)?;
+        // Sunday may be written as 0 or 7
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            seconds: parse(seconds, 0, 59, &[])?,
+            minutes: parse(rest[0], 0, 59, &[])?,
+            hours: parse(rest[1], 0, 23, &[])?,
+            days: parse(rest[2], 1, 31, &[])?,
+            months: parse(rest[3], 1, 12, MONTH_NAMES)?,
+            weekdays,
+            days_restricted: rest[2] != "*",
+            weekdays_restricted: rest[4] != "*",
+        })
+    }
+}
+
+impl Schedule {
+    /// Whether the schedule fires during the second `time` falls in.
+    pub fn matches<T: Datelike + Timelike>(&self, time: &T) -> bool {
+        has(self.seconds, time.second())
+            && has(self.minutes, time.minute())
+            && has(self.hours, time.hour())
+            && self.matches_day(time)
+    }
+
+    /// The first second after `after` that the schedule fires in, looking up
+    /// to five years ahead (`0 0 31 2 *` never fires).
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let limit = after + TimeDelta::days(5 * 366);
+        let mut time = after.with_nanosecond(0)? + TimeDelta::seconds(1);
+        while time <= limit {
+            // Skip whole days, hours and minutes that cannot match
+            time = if !self.matches_day(&time) {
+                (time.date() + Days::new(1)).and_hms_opt(0, 0, 0)?
+            } else if !has(self.hours, time.hour()) {
+                time.with_minute(0)?.with_second(0)? + TimeDelta::hours(1)
+            } else if !has(self.minutes, time.minute()) {
+                time.with_second(0)? + TimeDelta::minutes(1)
+            } else if !has(self.seconds, time.second()) {
+                time + TimeDelta::seconds(1)
+            } else {
+                return Some(time);
+            };
+        }
+        None
+    }
+
+    fn matches_day<T: Datelike>(&self, time: &T) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        };
+        has(self.months, time.month()) && day_matches
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parse one field: `*`, `5`, `1-5`, `*/15`, `10-40/10`, names such as `mon`
+/// or `jan`, and comma-separated lists of those.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let lower = text.to_ascii_lowercase();
+        let value = match names.iter().position(|name| *name == lower) {
+            // Month names start at 1, weekday names at 0
+            Some(index) => index as u32 + min,
+            None => text
+                .parse()
+                .map_err(|_| format!("`{text}` is not a number"))?,
+        };
+        if value < min || value > max {
+            return Err(format!("{value} is outside {min}-{max}"));
+        }
+        Ok(value)
+    };
+
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step in `{part}`"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/15` means every 15 starting at 5
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let single = value(range)?;
+                    (single, single)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("range `{range}` is backwards"));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            set |= 1 << v;
+        }
+    }
+    Ok(set)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> chrono::NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d)
+            .unwrap()
+            .and_hms_opt(h, mi, s)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_five_field_schedule() {
+        let nightly: Schedule = "0 3 * * *".parse().unwrap();
+        assert!(nightly.matches(&at(2024, 5, 1, 3, 0, 0)));
+        assert!(!nightly.matches(&at(2024, 5, 1, 3, 0, 1)));
+        assert!(!nightly.matches(&at(2024, 5, 1, 4, 0, 0)));
+
+        let business: Schedule = "*/15 9-17 * * mon-fri".parse().unwrap();
+        // 2024-05-03 is a Friday, 2024-05-04 a Saturday
+        assert!(business.matches(&at(2024, 5, 3, 9, 45, 0)));
+        assert!(!business.matches(&at(2024, 5, 3, 9, 50, 0)));
+        assert!(!business.matches(&at(2024, 5, 4, 9, 45, 0)));
+    }
+
+    #[test]
+    fn test_seconds_field_and_macros() {
+        let every_five: Schedule = "*/5 * * * * *".parse().unwrap();
+        assert!(every_five.matches(&at(2024, 1, 1, 0, 0, 10)));
+        assert!(!every_five.matches(&at(2024, 1, 1, 0, 0, 11)));
+
+        let hourly: Schedule = "@hourly".parse().unwrap();
+        assert_eq!(hourly, "0 * * * *".parse().unwrap());
+    }
+
+    #[test]
+    fn test_day_of_month_or_weekday() {
+        // The 1st of the month, or any Sunday (0 and 7 both mean Sunday)
+        let schedule: Schedule = "0 0 1 * 7".parse().unwrap();
+        assert!(schedule.matches(&at(2024, 5, 1, 0, 0, 0)));
+        assert!(schedule.matches(&at(2024, 5, 5, 0, 0, 0)));
+        assert!(!schedule.matches(&at(2024, 5, 6, 0, 0, 0)));
+
+        let january: Schedule = "0 0 * jan *".parse().unwrap();
+        assert!(january.matches(&at(2024, 1, 20, 0, 0, 0)));
+        assert!(!january.matches(&at(2024, 2, 20, 0, 0, 0)));
+    }
+
+    #[test]
+    fn test_next_after() {
+        let nightly: Schedule = "0 3 * * *".parse().unwrap();
+        assert_eq!(
+            nightly.next_after(at(2024, 5, 1, 3, 0, 0)),
+            Some(at(2024, 5, 2, 3, 0, 0))
+        );
+        assert_eq!(
+            nightly.next_after(at(2024, 5, 1, 2, 59, 59)),
+            Some(at(2024, 5, 1, 3, 0, 0))
+        );
+
+        let business: Schedule = "*/15 9-17 * * mon-fri".parse().unwrap();
+        // Friday evening rolls over to Monday morning
+        assert_eq!(
+            business.next_after(at(2024, 5, 3, 17, 50, 0)),
+            Some(at(2024, 5, 6, 9, 0, 0))
+        );
+
+        let leap_day: Schedule = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(
+            leap_day.next_after(at(2024, 3, 1, 0, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0, 0))
+        );
+        let never: Schedule = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(never.next_after(at(2024, 1, 1, 0, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expr in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            let err = expr.parse::<Schedule>().unwrap_err();
+            assert!(err.to_string().contains("invalid cron expression"), "{err}");
+        }
+    }
+}
@@ -1,17 +1,56 @@
-use crate::config::ProcessConfig;
-use crate::log;
+use crate::alert;
+use crate::api;
+use crate::audit;
+use crate::clock;
+use crate::config::{self, AlertAction, OverlapPolicy, ProcessConfig};
+use crate::cron;
+use crate::dump::{self, Dump};
+use crate::journal;
+use crate::log::{self, LogLevel, LogStream};
+use crate::metrics;
+use crate::notify::Notifier;
 use crate::paths::Paths;
 use crate::pid;
-use crate::process::{self, ProcessTable};
-use crate::protocol::{self, Request, Response};
+use crate::pipeline;
+use crate::process::{
+    self, ManagedProcess, PendingMonitor, ProcessState, ProcessTable, Processes, Services,
+    StopOutcome, Stopper,
+};
+use crate::protocol::{
+    self, AuditEntry, DaemonStatus, EventKind, PipelineStep, ProcessEvent, ProcessResult,
+    ProcessStatus, Request, Response, ResultStatus, RunReason, RunRecord, SettingInfo, StepStatus,
+    TreeMember,
+};
+use crate::ready;
+use crate::remote;
+use crate::settings::{self, DaemonSettings};
+use crate::ship;
+use crate::stats;
+use crate::storage::{self, Storage};
+use crate::systemd;
 use color_eyre::eyre::bail;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tokio::fs;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::UnixListener;
-use tokio::sync::RwLock;
-use tokio::sync::watch;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{RwLock, broadcast, watch};
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+
+/// How long shutdown waits for the log shipper to send what it still holds.
+const SHIPPER_GRACE: Duration = Duration::from_secs(5);
+
+/// How many events a slow `/ws/events` client may fall behind before it
+/// misses some.
+const EVENT_BACKLOG: usize = 256;
 
 pub async fn run(paths: Paths) -> color_eyre::Result<()> {
     fs::create_dir_all(paths.data_dir()).await?;
@@ -20,60 +59,267 @@ pub async fn run(paths: Paths) -> color_eyre::Result<()> {
         bail!("daemon is already running");
     }
 
+    let settings = settings::load_settings(&paths).await?;
+    let auto_exit = settings.auto_exit()?;
+    let stats_interval = settings.stats_interval()?;
+    let socket_mode = settings.socket_mode()?;
+    let listen_tcp = settings.listen_tcp()?;
+    let listen_http = settings.listen_http()?;
+    let tls = match settings.tls()? {
+        Some(files) if listen_tcp.is_some() || listen_http.is_some() => {
+            Some(TlsAcceptor::from(remote::server_config(&files)?))
+        }
+        _ => None,
+    };
+    let auth = RemoteAuth {
+        token: settings.auth_token()?.map(str::to_string),
+        tls: tls.clone().filter(|_| listen_tcp.is_some()),
+    };
+    let retention = settings.storage.retention()?;
+    let sample_interval = settings.storage.sample_interval()?;
+    let exporter = metrics::Exporter::new(&settings.metrics)?;
+    let storage = storage::open(&paths, &settings.storage)?;
+    let plugins = settings.plugins.clone().into();
+    let (shipper, shipping) = ship::start(&paths, &settings.log_ship).unzip();
+    let settings = Arc::new(RwLock::new(settings));
+    let processes = Processes::default();
+    let (events, _) = broadcast::channel(EVENT_BACKLOG);
+    let services = Services {
+        storage,
+        plugins,
+        notifier: Notifier::new(processes.clone(), settings.clone()),
+        shipper,
+        events,
+        settings,
+    };
+
     pid::write_pid_file(&paths).await?;
 
-    // Remove stale socket file if it exists
-    let socket_path = paths.socket_file();
-    if socket_path.exists() {
-        fs::remove_file(&socket_path).await?;
-    }
+    // A socket unit owns the socket file, so it is neither replaced here nor
+    // removed at shutdown
+    let activated = systemd::take_listener()?;
+    let owns_socket = activated.is_none();
+    let listener = match activated {
+        Some(listener) => {
+            info!("listening on the socket passed by systemd");
+            UnixListener::from_std(listener)?
+        }
+        None => {
+            // Remove stale socket file if it exists
+            let socket_path = paths.socket_file();
+            if socket_path.exists() {
+                fs::remove_file(&socket_path).await?;
+            }
+            let listener = UnixListener::bind(&socket_path)?;
+            if let Some(mode) = socket_mode {
+                fs::set_permissions(&socket_path, std::fs::Permissions::from_mode(mode)).await?;
+            }
+            listener
+        }
+    };
+    let tcp = match listen_tcp {
+        Some(addr) => {
+            let tcp = TcpListener::bind(addr).await?;
+            let tls = if auth.tls.is_some() { " with tls" } else { "" };
+            info!("listening on tcp {}{tls}", tcp.local_addr()?);
+            Some(tcp)
+        }
+        None => None,
+    };
+    let http = match listen_http {
+        Some(addr) => {
+            let http = TcpListener::bind(addr).await?;
+            let tls = if tls.is_some() { " with tls" } else { "" };
+            info!("serving the http api on {}{tls}", http.local_addr()?);
+            Some(http)
+        }
+        None => None,
+    };
+    let http_token = auth.token.clone();
+    let listeners = Listeners {
+        unix: listener,
+        tcp,
+        auth: Arc::new(auth),
+    };
+    // Control sockets left behind by a daemon that did not shut down cleanly
+    let _ = fs::remove_dir_all(paths.control_dir()).await;
 
-    let listener = UnixListener::bind(&socket_path)?;
+    if clock::install_from_env() {
+        info!("virtual time: supervision timers only move with `pm3 advance-clock`");
+    }
 
     let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
-    let processes: Arc<RwLock<ProcessTable>> = Arc::new(RwLock::new(HashMap::new()));
+    adopt_orphans(&processes, &paths, &services).await;
+
+    let journal_writer = tokio::spawn(run_journal_writer(processes.clone(), paths.clone()));
+    let sampler = tokio::spawn(run_resource_sampler(
+        processes.clone(),
+        paths.clone(),
+        services.clone(),
+        stats_interval,
+        sample_interval,
+    ));
+    let pruner =
+        retention.map(|retention| tokio::spawn(run_pruner(services.storage.clone(), retention)));
+    let log_budget = tokio::spawn(run_log_budget(
+        processes.clone(),
+        paths.clone(),
+        services.settings.clone(),
+    ));
+    let scheduler = tokio::spawn(run_cron_scheduler(
+        processes.clone(),
+        paths.clone(),
+        services.clone(),
+    ));
+    let exporter = exporter.map(|exporter| tokio::spawn(exporter.run(processes.clone())));
+    let started_at = Instant::now();
+    let api = http.zip(http_token).map(|(listener, token)| {
+        let api = api::Api {
+            processes: processes.clone(),
+            paths: paths.clone(),
+            services: services.clone(),
+            shutdown_tx: shutdown_tx.clone(),
+            started_at,
+            token: token.into(),
+        };
+        tokio::spawn(api::serve(listener, tls, api))
+    });
+    let watchdog = systemd::watchdog_interval()
+        .map(|interval| tokio::spawn(run_watchdog(processes.clone(), interval)));
+    systemd::notify("READY=1");
 
+    let lifetime = Lifetime {
+        started_at,
+        auto_exit,
+    };
     let result = run_accept_loop(
         &paths,
-        &listener,
+        &services,
+        &listeners,
         &shutdown_tx,
         &mut shutdown_rx,
         &processes,
+        lifetime,
     )
     .await;
 
-    // Gracefully stop all managed processes before cleanup
-    {
-        let mut table = processes.write().await;
-        for (_, managed) in table.iter_mut() {
-            let _ = managed.graceful_stop().await;
-        }
+    systemd::notify("STOPPING=1");
+    journal_writer.abort();
+    sampler.abort();
+    scheduler.abort();
+    log_budget.abort();
+    for task in [pruner, watchdog, exporter, api].into_iter().flatten() {
+        task.abort();
     }
 
-    // Cleanup
-    let _ = fs::remove_file(paths.socket_file()).await;
+    // Gracefully stop all managed processes before cleanup, dependents
+    // before what they depend on and critical ones last
+    let table = processes.snapshot().await;
+    let names: Vec<String> = table.keys().cloned().collect();
+    for name in stop_order(&table, &names) {
+        let _ = processes.stop(&name).await;
+    }
+
+    // Cleanup; nothing is left running for a journal to describe
+    journal::remove(&paths).await;
+    log::forget_pruned(&paths.log_dir());
+    if let Some(shipping) = shipping {
+        // Give the last lines a moment to go out (or onto disk)
+        let mut shipper = shipping.close();
+        if tokio::time::timeout(SHIPPER_GRACE, &mut shipper)
+            .await
+            .is_err()
+        {
+            shipper.abort();
+        }
+    }
+    if owns_socket {
+        let _ = fs::remove_file(paths.socket_file()).await;
+    }
+    let _ = fs::remove_dir_all(paths.control_dir()).await;
     pid::remove_pid_file(&paths).await;
 
     result
 }
 
+/// Send systemd's watchdog keepalives for as long as every process's actor
+/// answers, so a daemon with a wedged actor stops sending them and is
+/// restarted.
+async fn run_watchdog(processes: Processes, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        if tokio::time::timeout(interval, processes.snapshot())
+            .await
+            .is_ok()
+        {
+            systemd::notify("WATCHDOG=1");
+        } else {
+            warn!("a process did not answer for {interval:?}, skipping watchdog keepalive");
+        }
+    }
+}
+
+/// When the daemon started, and how long it may go without work before it
+/// exits on its own (`auto_exit`).
+#[derive(Debug, Clone, Copy)]
+struct Lifetime {
+    started_at: Instant,
+    auto_exit: Option<Duration>,
+}
+
 async fn run_accept_loop(
     paths: &Paths,
-    listener: &UnixListener,
+    services: &Services,
+    listeners: &Listeners,
     shutdown_tx: &watch::Sender<bool>,
     shutdown_rx: &mut watch::Receiver<bool>,
-    processes: &Arc<RwLock<ProcessTable>>,
+    processes: &Processes,
+    lifetime: Lifetime,
 ) -> color_eyre::Result<()> {
+    let Lifetime {
+        started_at,
+        auto_exit,
+    } = lifetime;
+    let activity = Arc::new(Activity::new());
+    let mut idle_check = auto_exit.map(|limit| {
+        tokio::time::interval((limit / 4).clamp(Duration::from_millis(50), Duration::from_secs(30)))
+    });
+
     loop {
         tokio::select! {
-            accept_result = listener.accept() => {
-                let (stream, _addr) = accept_result?;
+            accept_result = listeners.accept() => {
+                let accepted = accept_result?;
+                // Read per connection, so `pm3 config set` applies to the next request
+                let defaults = match RequestDefaults::from_settings(&*services.settings.read().await, started_at) {
+                    Ok(defaults) => defaults,
+                    Err(e) => {
+                        error!("dropping connection, daemon settings are invalid: {e}");
+                        continue;
+                    }
+                };
                 let tx = shutdown_tx.clone();
                 let paths = paths.clone();
-                let procs = Arc::clone(processes);
+                let services = services.clone();
+                let procs = processes.clone();
+                let auth = Arc::clone(&listeners.auth);
+                let guard = ConnectionGuard::new(&activity);
                 tokio::spawn(async move {
-                    if let Err(e) = handle_connection(stream, &tx, &procs, &paths).await {
-                        eprintln!("connection error: {e}");
+                    let _guard = guard;
+                    let client = match accepted {
+                        Accepted::Unix(stream) => Client::unix(stream),
+                        Accepted::Tcp(stream, addr) => {
+                            match Client::tcp(stream, addr, &auth, defaults).await {
+                                Ok(client) => client,
+                                Err(e) => {
+                                    warn!("rejected client {addr}: {e}");
+                                    return;
+                                }
+                            }
+                        }
+                    };
+                    if let Err(e) = handle_connection(client, &tx, &procs, &paths, &services, defaults).await {
+                        warn!("connection error: {e}");
                     }
                 });
             }
@@ -85,12 +331,237 @@ async fn run_accept_loop(
             _ = signal_shutdown() => {
                 break;
             }
+            _ = tick(idle_check.as_mut()) => {
+                let limit = auto_exit.expect("idle check only runs with auto_exit");
+                if has_work(processes).await {
+                    activity.touch();
+                } else if activity.idle_for() >= limit {
+                    info!("no processes, schedules or clients for {limit:?}, exiting");
+                    break;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+// ---------------------------------------------------------------------------
+// Connections
+// ---------------------------------------------------------------------------
+
+/// The halves of a client connection, over the unix socket or TCP.
+type ClientReader = BufReader<Box<dyn AsyncRead + Send + Unpin>>;
+type ClientWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// An accepted connection and who is on the other end: the kernel's
+/// credentials for the unix socket, the address for TCP.
+struct Client {
+    reader: ClientReader,
+    writer: ClientWriter,
+    uid: Option<u32>,
+    pid: Option<i32>,
+    remote: Option<String>,
+}
+
+impl Client {
+    fn unix(stream: UnixStream) -> Self {
+        let peer = stream.peer_cred().ok();
+        let (reader, writer) = stream.into_split();
+        Self {
+            reader: BufReader::new(Box::new(reader)),
+            writer: Box::new(writer),
+            uid: peer.map(|cred| cred.uid()),
+            pid: peer.and_then(|cred| cred.pid()),
+            remote: None,
+        }
+    }
+
+    /// Set up a TCP client: finish the TLS handshake, then check its
+    /// certificate or its token. A client turned away gets an error, and no
+    /// request of theirs is read.
+    async fn tcp(
+        stream: TcpStream,
+        addr: SocketAddr,
+        auth: &RemoteAuth,
+        defaults: RequestDefaults,
+    ) -> color_eyre::Result<Self> {
+        let _ = stream.set_nodelay(true);
+        let timeout = defaults.request_timeout;
+        let (reader, mut writer, verified): (Box<dyn AsyncRead + Send + Unpin>, ClientWriter, _) =
+            match &auth.tls {
+                Some(acceptor) => {
+                    let stream = match tokio::time::timeout(timeout, acceptor.accept(stream)).await
+                    {
+                        Ok(stream) => stream?,
+                        Err(_) => bail!("no TLS handshake within {timeout:?}"),
+                    };
+                    // The verifier only lets through certificates the client CA signed
+                    let verified = stream.get_ref().1.peer_certificates().is_some();
+                    let (reader, writer) = tokio::io::split(stream);
+                    (Box::new(reader), Box::new(writer), verified)
+                }
+                None => {
+                    let (reader, writer) = stream.into_split();
+                    (Box::new(reader), Box::new(writer), false)
+                }
+            };
+        let mut reader = BufReader::new(reader);
+        if !verified
+            && let Err(message) = authenticate(&mut reader, auth.token.as_deref(), defaults).await
+        {
+            let response = Response::Error {
+                message: message.clone(),
+            };
+            reply(&mut writer, &response, timeout).await?;
+            // Take in what the client sent after its token, so closing does
+            // not reset the connection before they read the error
+            let mut sink = tokio::io::sink();
+            let _ = tokio::time::timeout(timeout, tokio::io::copy(&mut reader, &mut sink)).await;
+            bail!("{message}");
+        }
+        Ok(Self {
+            reader,
+            writer,
+            uid: None,
+            pid: None,
+            remote: Some(addr.to_string()),
+        })
+    }
+}
+
+/// Read a TCP client's auth line and check its token against `expected`.
+async fn authenticate(
+    reader: &mut ClientReader,
+    expected: Option<&str>,
+    defaults: RequestDefaults,
+) -> Result<(), String> {
+    let Some(expected) = expected else {
+        return Err("authentication failed: a client certificate is required".to_string());
+    };
+    let timeout = defaults.request_timeout;
+    let read = read_limited_line(reader, defaults.max_request_size);
+    let line = match tokio::time::timeout(timeout, read).await {
+        Ok(Ok(Some(line))) => line,
+        Ok(Ok(None)) => return Err("connection closed before authenticating".to_string()),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err(format!("no token received within {timeout:?}")),
+    };
+    match protocol::decode_auth(&line) {
+        Ok(auth) if remote::token_matches(expected, &auth.token) => Ok(()),
+        Ok(_) => Err("authentication failed: wrong token".to_string()),
+        Err(_) => Err(
+            "authentication required: pass the daemon's auth_token with --token or PM3_TOKEN"
+                .to_string(),
+        ),
+    }
+}
+
+/// How TCP clients prove themselves, from `auth_token` and the TLS settings.
+struct RemoteAuth {
+    token: Option<String>,
+    tls: Option<TlsAcceptor>,
+}
+
+/// A connection as accepted, before a TCP client has authenticated.
+enum Accepted {
+    Unix(UnixStream),
+    Tcp(TcpStream, SocketAddr),
+}
+
+/// The unix socket and, with `listen_tcp`, a TCP listener.
+struct Listeners {
+    unix: UnixListener,
+    tcp: Option<TcpListener>,
+    auth: Arc<RemoteAuth>,
+}
+
+impl Listeners {
+    /// The next connection on either listener.
+    async fn accept(&self) -> std::io::Result<Accepted> {
+        let tcp_accept = async {
+            match &self.tcp {
+                Some(tcp) => tcp.accept().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            accepted = self.unix.accept() => accepted.map(|(stream, _)| Accepted::Unix(stream)),
+            accepted = tcp_accept => accepted.map(|(stream, addr)| Accepted::Tcp(stream, addr)),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Idle tracking
+// ---------------------------------------------------------------------------
+
+/// Open client connections and the last moment the daemon had work to do.
+struct Activity {
+    connections: AtomicUsize,
+    last_busy: std::sync::Mutex<Instant>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            connections: AtomicUsize::new(0),
+            last_busy: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_busy.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        if self.connections.load(Ordering::SeqCst) > 0 {
+            self.touch();
+            return Duration::ZERO;
+        }
+        self.last_busy.lock().unwrap().elapsed()
+    }
+}
+
+/// Counts a client connection as activity for as long as it is open.
+struct ConnectionGuard(Arc<Activity>);
+
+impl ConnectionGuard {
+    fn new(activity: &Arc<Activity>) -> Self {
+        activity.connections.fetch_add(1, Ordering::SeqCst);
+        activity.touch();
+        Self(Arc::clone(activity))
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn tick(interval: Option<&mut tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The daemon has work while a process has a live pid, is waiting out a
+/// restart backoff, or has a `cron_restart` schedule (jobs included) that
+/// was not stopped by hand, since exiting would drop its next run.
+async fn has_work(processes: &Processes) -> bool {
+    let table = processes.snapshot().await;
+    table.values().any(|m| {
+        let scheduled = m.config.cron_restart.is_some()
+            && !m.monitor_shutdown.as_ref().is_some_and(|tx| *tx.borrow());
+        m.pid.is_some() || m.status == ProcessStatus::Starting || scheduled
+    })
+}
+
 async fn signal_shutdown() {
     let mut sigterm =
         tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
@@ -104,59 +575,438 @@ async fn signal_shutdown() {
 }
 
 async fn handle_connection(
-    stream: tokio::net::UnixStream,
+    client: Client,
     shutdown_tx: &watch::Sender<bool>,
-    processes: &Arc<RwLock<ProcessTable>>,
+    processes: &Processes,
     paths: &Paths,
+    services: &Services,
+    defaults: RequestDefaults,
 ) -> color_eyre::Result<()> {
-    let (reader, mut writer) = stream.into_split();
-    let mut buf_reader = BufReader::new(reader);
-    let mut line = String::new();
-    buf_reader.read_line(&mut line).await?;
-
-    if line.is_empty() {
-        return Ok(());
-    }
+    let Client {
+        reader: mut buf_reader,
+        mut writer,
+        uid,
+        pid,
+        remote,
+    } = client;
+    let timeout = defaults.request_timeout;
 
-    let request = protocol::decode_request(&line)?;
+    // A client that stalls or sends garbage gets an error instead of holding
+    // the task, or the daemon's memory, hostage
+    let read = read_limited_line(&mut buf_reader, defaults.max_request_size);
+    let rejected = match tokio::time::timeout(timeout, read).await {
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Ok(Some(line))) => match protocol::decode_request(&line) {
+            Ok(request) => Ok(request),
+            Err(e) => Err(format!("malformed request: {e}")),
+        },
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no request received within {timeout:?}")),
+    };
+    let request = match rejected {
+        Ok(request) => request,
+        Err(message) => {
+            warn!("rejected connection: {message}");
+            return reply(&mut writer, &Response::Error { message }, timeout).await;
+        }
+    };
+    let kind = request_kind(&request);
+    let audit = receive(&request, uid, pid, remote);
 
     // Log requests need streaming access to the writer
     if let Request::Log {
         ref name,
         lines,
         follow,
+        ref group,
+        timestamps,
+        ref grep,
+        level,
+        since,
+        stream,
+        color,
     } = request
     {
-        handle_log(name.clone(), lines, follow, processes, paths, &mut writer).await?;
+        let query = LogQuery {
+            name: name.clone(),
+            group: group.clone(),
+            lines,
+            follow,
+            timestamps,
+            grep: grep.clone(),
+            level,
+            since,
+            stream,
+            color,
+        };
+        handle_log(query, processes, paths, &mut writer).await?;
         writer.shutdown().await?;
         return Ok(());
     }
 
-    let response = dispatch(request, shutdown_tx, processes, paths).await;
-    let encoded = protocol::encode_response(&response)?;
-    writer.write_all(&encoded).await?;
-    writer.shutdown().await?;
+    // Attaching keeps reading the connection for input
+    if let Request::Attach { name } = request {
+        let attached = handle_attach(
+            name,
+            buf_reader,
+            defaults.max_request_size,
+            processes,
+            &mut writer,
+        )
+        .await;
+        let outcome = match &attached {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error: {e}"),
+        };
+        record_audit(paths, audit, outcome).await;
+        attached?;
+        writer.shutdown().await?;
+        return Ok(());
+    }
 
-    Ok(())
+    // Updating answers before the exec, which never returns
+    if let Request::Update { exe } = request {
+        return handle_update(PathBuf::from(exe), audit, processes, paths, &mut writer).await;
+    }
+
+    // Pipelines report each task as it starts and finishes
+    if let Request::RunPipeline {
+        configs,
+        target,
+        env,
+    } = request
+    {
+        let done = handle_pipeline(
+            configs,
+            target,
+            env,
+            processes,
+            paths,
+            services,
+            &mut writer,
+        )
+        .await?;
+        record_audit(paths, audit, audit::outcome(&done)).await;
+        writer.shutdown().await?;
+        return Ok(());
+    }
+
+    let save_after = defaults.auto_save && changes_table(&request);
+    let response = match request {
+        // Starts that asked for it report each process as it launches
+        Request::Start {
+            configs,
+            names,
+            env,
+            strict,
+            stagger,
+            progress: true,
+        } => {
+            let options = StartOptions {
+                env,
+                strict,
+                stagger: defaults.stagger(stagger),
+                parallel: defaults.parallel_starts,
+            };
+            let progress = Some(&mut writer);
+            handle_start(
+                configs, names, options, progress, processes, paths, services,
+            )
+            .await
+        }
+        request => dispatch(request, defaults, shutdown_tx, processes, paths, services).await,
+    };
+    conclude(&kind, &response, save_after, audit, processes, paths).await;
+    reply(&mut writer, &response, timeout).await
+}
+
+/// Carry out a request from the HTTP API, logged, saved after and audited
+/// like one from a socket client.
+pub(crate) async fn answer(
+    request: Request,
+    remote: SocketAddr,
+    shutdown_tx: &watch::Sender<bool>,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+    started_at: Instant,
+) -> Response {
+    let defaults =
+        match RequestDefaults::from_settings(&*services.settings.read().await, started_at) {
+            Ok(defaults) => defaults,
+            Err(e) => {
+                return Response::Error {
+                    message: format!("daemon settings are invalid: {e}"),
+                };
+            }
+        };
+    let kind = request_kind(&request);
+    let audit = receive(&request, None, None, Some(remote.to_string()));
+    let save_after = defaults.auto_save && changes_table(&request);
+    let response = dispatch(request, defaults, shutdown_tx, processes, paths, services).await;
+    conclude(&kind, &response, save_after, audit, processes, paths).await;
+    response
+}
+
+/// Log `request` as it arrives and start its audit entry; everything but
+/// queries goes on the record.
+fn receive(
+    request: &Request,
+    uid: Option<u32>,
+    pid: Option<i32>,
+    remote: Option<String>,
+) -> Option<AuditEntry> {
+    let kind = request_kind(request);
+    // Queries are polled (`start --wait` lists five times a second), so
+    // they only show at debug level
+    if is_query(request) {
+        debug!(request = kind, "received");
+    } else {
+        info!(request = kind, "received");
+    }
+    (!is_query(request)).then(|| {
+        let (request, args) = audit::describe(request);
+        AuditEntry {
+            at: chrono::Utc::now().timestamp_millis(),
+            uid,
+            pid,
+            remote,
+            request,
+            args,
+            outcome: String::new(),
+        }
+    })
+}
+
+/// Log how a request went, save the dump after a change to the table and
+/// complete its audit entry.
+async fn conclude(
+    kind: &str,
+    response: &Response,
+    save_after: bool,
+    audit: Option<AuditEntry>,
+    processes: &Processes,
+    paths: &Paths,
+) {
+    match response {
+        Response::Error { message } => warn!(request = kind, "{message}"),
+        Response::Results { results, .. } => {
+            for result in results {
+                if result.status == ResultStatus::Failed {
+                    let reason = result.detail.as_deref().unwrap_or_default();
+                    warn!(request = kind, "{}: {reason}", result.name);
+                }
+            }
+            debug!(request = kind, ?response, "answered");
+        }
+        response => debug!(request = kind, ?response, "answered"),
+    }
+    // Saved before replying so a client that saw the change can rely on it
+    // surviving a daemon restart
+    if save_after && let Err(e) = save_dump(processes, paths).await {
+        error!("auto-save failed: {e}");
+    }
+    record_audit(paths, audit, audit::outcome(response)).await;
+}
+
+/// Append `entry`, if the request is audited, with how it went.
+async fn record_audit(paths: &Paths, entry: Option<AuditEntry>, outcome: String) {
+    let Some(entry) = entry else {
+        return;
+    };
+    let entry = AuditEntry { outcome, ..entry };
+    if let Err(e) = audit::append(paths, &entry).await {
+        error!("failed to write audit log: {e}");
+    }
+}
+
+/// Read a line of at most `limit` bytes, without its newline; `None` when the
+/// client closed the connection without sending anything.
+async fn read_limited_line(
+    reader: &mut (impl tokio::io::AsyncBufRead + Unpin),
+    limit: usize,
+) -> std::io::Result<Option<String>> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(limit as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if line.len() > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("request exceeds {limit} bytes"),
+        ));
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "request is not UTF-8"))
+}
+
+/// Send `response` and close the connection, giving up on a client that
+/// does not take it within `timeout`.
+async fn reply(
+    writer: &mut ClientWriter,
+    response: &Response,
+    timeout: Duration,
+) -> color_eyre::Result<()> {
+    let encoded = protocol::encode_response(response)?;
+    let send = async {
+        writer.write_all(&encoded).await?;
+        writer.shutdown().await
+    };
+    match tokio::time::timeout(timeout, send).await {
+        Ok(sent) => Ok(sent?),
+        Err(_) => bail!("client did not take the response within {timeout:?}"),
+    }
+}
+
+/// Daemon settings that shape how requests are carried out.
+#[derive(Debug, Clone, Copy)]
+struct RequestDefaults {
+    /// Save the dump after every request that changes the table.
+    auto_save: bool,
+    /// Delay between launches for requests that do not set their own.
+    stagger: Option<Duration>,
+    /// When the daemon started, for its uptime in `DaemonStatus`.
+    started_at: Instant,
+    /// How long a client may take to send a request or take a response.
+    request_timeout: Duration,
+    /// Longest request line accepted, in bytes.
+    max_request_size: usize,
+    /// How many processes a start launches at once.
+    parallel_starts: usize,
+}
+
+impl RequestDefaults {
+    fn from_settings(
+        settings: &settings::DaemonSettings,
+        started_at: Instant,
+    ) -> Result<Self, config::ConfigError> {
+        Ok(Self {
+            auto_save: settings.daemon.auto_save,
+            stagger: settings.stagger()?,
+            started_at,
+            request_timeout: settings.request_timeout()?,
+            max_request_size: settings.max_request_size()?,
+            parallel_starts: settings.parallel_starts()?,
+        })
+    }
+
+    fn stagger(&self, requested: Option<u64>) -> Option<Duration> {
+        requested
+            .map(Duration::from_millis)
+            .or(self.stagger)
+            .filter(|delay| !delay.is_zero())
+    }
 }
 
 async fn dispatch(
     request: Request,
+    defaults: RequestDefaults,
     shutdown_tx: &watch::Sender<bool>,
-    processes: &Arc<RwLock<ProcessTable>>,
+    processes: &Processes,
     paths: &Paths,
+    services: &Services,
 ) -> Response {
     match request {
-        Request::Start { configs, names, .. } => {
-            handle_start(configs, names, processes, paths).await
+        Request::Start {
+            configs,
+            names,
+            env,
+            strict,
+            stagger,
+            progress: _,
+        } => {
+            let options = StartOptions {
+                env,
+                strict,
+                stagger: defaults.stagger(stagger),
+                parallel: defaults.parallel_starts,
+            };
+            handle_start(configs, names, options, None, processes, paths, services).await
+        }
+        Request::Ping => Response::Pong,
+        Request::DaemonStatus => {
+            let log_dir = paths.log_dir();
+            let pruned = log::pruned(&log_dir);
+            let log_bytes = tokio::task::spawn_blocking(move || log::disk_usage(&log_dir))
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .unwrap_or_default();
+            let table = processes.snapshot().await;
+            Response::DaemonStatus {
+                status: DaemonStatus {
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    pid: std::process::id(),
+                    uptime_ms: defaults.started_at.elapsed().as_millis() as u64,
+                    processes: table.len(),
+                    running: table.values().filter(|m| m.pid.is_some()).count(),
+                    socket: paths.socket_file().display().to_string(),
+                    data_dir: paths.data_dir().display().to_string(),
+                    log_bytes,
+                    log_budget: services
+                        .settings
+                        .read()
+                        .await
+                        .logs
+                        .total_max()
+                        .unwrap_or_default(),
+                    logs_pruned: pruned.files,
+                    log_bytes_pruned: pruned.bytes,
+                },
+            }
+        }
+        Request::ConfigGet { key } => handle_config_get(key, &*services.settings.read().await),
+        Request::ConfigSet { key, value } => {
+            handle_config_set(key, value, paths, &services.settings).await
         }
         Request::List => {
-            let table = processes.read().await;
-            let infos: Vec<_> = table.values().map(|m| m.to_process_info()).collect();
+            let table = processes.snapshot().await;
+            let infos: Vec<_> = table
+                .values()
+                .filter(|m| !m.config.is_job())
+                .map(|m| m.to_process_info())
+                .collect();
             Response::ProcessList { processes: infos }
         }
-        Request::Stop { names } => handle_stop(names, processes).await,
-        Request::Restart { names } => handle_restart(names, processes, paths).await,
+        Request::Jobs => {
+            let table = processes.snapshot().await;
+            let mut jobs: Vec<_> = table
+                .values()
+                .filter(|m| m.config.is_job())
+                .map(|m| m.to_job_info())
+                .collect();
+            jobs.sort_by(|a, b| a.name.cmp(&b.name));
+            Response::JobList { jobs }
+        }
+        Request::Info { name, tree } => handle_info(&name, tree, processes, paths).await,
+        Request::Save => handle_save(processes, paths).await,
+        Request::Resurrect => handle_resurrect(processes, paths, services).await,
+        Request::Stop {
+            names,
+            group,
+            cascade,
+        } => handle_stop(names, group, cascade, processes).await,
+        Request::Restart {
+            names,
+            group,
+            rolling,
+            stagger,
+            cascade,
+        } => {
+            let options = RestartOptions {
+                rolling,
+                stagger: defaults.stagger(stagger),
+                cascade,
+            };
+            handle_restart(names, group, options, processes, paths, services).await
+        }
         Request::Kill => {
             let _ = shutdown_tx.send(true);
             Response::Success {
@@ -164,23 +1014,82 @@ async fn dispatch(
             }
         }
         Request::Flush { names } => handle_flush(names, processes, paths).await,
-        Request::Log { .. } => {
+        Request::Prune { apply } => handle_prune(apply, processes, paths).await,
+        Request::Log { .. }
+        | Request::RunPipeline { .. }
+        | Request::Attach { .. }
+        | Request::Update { .. } => {
             // Handled in handle_connection directly
             Response::Error {
-                message: "unexpected dispatch for log".to_string(),
+                message: "unexpected dispatch for a streaming request".to_string(),
             }
         }
-        _ => Response::Error {
-            message: "not implemented".to_string(),
+        Request::Reload { names } => handle_reload(names, processes, paths, services).await,
+        Request::History { name, since } => handle_history(name, since, &services.storage).await,
+        Request::Events { name, since } => {
+            match storage::events(&services.storage, name, since).await {
+                Ok(events) => Response::Events { events },
+                Err(e) => Response::Error {
+                    message: format!("failed to read events: {e}"),
+                },
+            }
+        }
+        Request::Samples { name, since } => {
+            match storage::samples(&services.storage, name, since).await {
+                Ok(samples) => Response::Samples { samples },
+                Err(e) => Response::Error {
+                    message: format!("failed to read samples: {e}"),
+                },
+            }
+        }
+        Request::Audit { since } => match audit::read(paths, since).await {
+            Ok(entries) => Response::Audit { entries },
+            Err(e) => Response::Error {
+                message: format!("failed to read audit log: {e}"),
+            },
+        },
+        Request::Signal {
+            name,
+            signal,
+            group_leader,
+        } => handle_signal(&name, &signal, group_leader, processes).await,
+        Request::Input { name, data } => handle_input(&name, data, processes).await,
+        Request::Scale { name, instances } => {
+            handle_scale(&name, instances, processes, paths, services).await
+        }
+        Request::AdvanceClock { by_ms } => match clock::advance(Duration::from_millis(by_ms)) {
+            Some(elapsed) => Response::Success {
+                message: Some(format!("virtual clock at +{}ms", elapsed.as_millis())),
+            },
+            None => Response::Error {
+                message: format!(
+                    "the clock is real; start the daemon with {}=1 to advance it",
+                    clock::VIRTUAL_TIME_ENV
+                ),
+            },
         },
     }
 }
 
+/// How a start goes about launching what it selected.
+struct StartOptions {
+    /// Environment overlay applied to every config.
+    env: Option<String>,
+    /// Refuse to start on a memory shortfall instead of warning.
+    strict: bool,
+    stagger: Option<Duration>,
+    /// Launches under way at once.
+    parallel: usize,
+}
+
 async fn handle_start(
     configs: HashMap<String, ProcessConfig>,
     names: Option<Vec<String>>,
-    processes: &Arc<RwLock<ProcessTable>>,
+    options: StartOptions,
+    mut progress: Option<&mut ClientWriter>,
+    processes: &Processes,
     paths: &Paths,
+    services: &Services,
 ) -> Response {
     let to_start: Vec<(String, ProcessConfig)> = match names {
         Some(ref requested) => {
@@ -199,184 +1108,2226 @@ async fn handle_start(
         }
         None => configs.into_iter().collect(),
     };
+    let StartOptions {
+        env,
+        strict,
+        stagger,
+        parallel,
+    } = options;
+    let mut to_start = config::expand_instances(to_start);
+    if let Some(env) = &env {
+        for (_, config) in &mut to_start {
+            config.apply_environment(env);
+        }
+    }
 
-    let mut started = Vec::new();
-    let mut children_to_monitor = Vec::new();
+    let shortfall = memory_shortfall(
+        &to_start,
+        &processes.snapshot().await,
+        stats::available_memory(),
+    );
+    if strict && let Some(shortfall) = shortfall {
+        return Response::Error {
+            message: format!("not starting: {shortfall}"),
+        };
+    }
 
+    // Processes already running with the same config are left alone, so
+    // they neither launch nor take up a stagger slot
+    let mut results = Vec::new();
     {
-        let mut table = processes.write().await;
-
-        for (name, config) in to_start {
-            if table.contains_key(&name) {
-                continue;
-            }
-
-            match process::spawn_process(name.clone(), config, paths).await {
-                Ok((managed, child)) => {
-                    let pid = managed.pid;
-                    let shutdown_rx = managed
-                        .monitor_shutdown
-                        .as_ref()
-                        .map(|tx| tx.subscribe())
-                        .unwrap();
-                    table.insert(name.clone(), managed);
-                    children_to_monitor.push((name.clone(), child, pid, shutdown_rx));
-                    started.push(name);
-                }
-                Err(e) => {
-                    return Response::Error {
-                        message: format!("failed to start '{}': {}", name, e),
-                    };
-                }
+        let table = processes.snapshot().await;
+        to_start.retain(|(name, config)| match table.get(name) {
+            Some(m) if m.config == *config => {
+                let reason = if m.pid.is_some() {
+                    "already running"
+                } else {
+                    "unchanged"
+                };
+                results.push(ProcessResult::skipped(name.clone(), reason));
+                false
             }
-        }
+            _ => true,
+        });
     }
+    to_start.sort_by(|a, b| a.0.cmp(&b.0));
 
-    // Spawn monitors outside the lock
-    for (name, child, pid, shutdown_rx) in children_to_monitor {
-        process::spawn_monitor(
-            name,
-            child,
-            pid,
-            Arc::clone(processes),
-            paths.clone(),
-            shutdown_rx,
-        );
-    }
+    let dependencies = start_dependencies(&to_start);
+    let mut awaited: HashSet<String> = dependencies.values().flatten().cloned().collect();
+    // Staggered launches go one at a time so the delay means something
+    let parallel = if stagger.is_some() { 1 } else { parallel };
+    let mut pending = to_start;
+    let mut done: HashSet<String> = HashSet::new();
+    let mut failed: HashSet<String> = HashSet::new();
+    let mut launches = JoinSet::new();
+    let mut launching = HashMap::new();
+    let mut launched = false;
 
-    if started.is_empty() {
-        Response::Success {
-            message: Some("everything is already running".to_string()),
-        }
-    } else {
-        Response::Success {
-            message: Some(format!("started: {}", started.join(", "))),
+    loop {
+        // What waits on a process that failed, directly or not, is skipped
+        while let Some(index) = pending
+            .iter()
+            .position(|(name, _)| dependencies[name].iter().any(|dep| failed.contains(dep)))
+        {
+            let (name, _) = pending.remove(index);
+            let dep = dependencies[&name]
+                .iter()
+                .find(|dep| failed.contains(*dep))
+                .cloned()
+                .unwrap_or_default();
+            let result = ProcessResult::skipped(name.clone(), format!("'{dep}' did not start"));
+            send_progress(&mut progress, &result).await;
+            results.push(result);
+            failed.insert(name);
         }
-    }
-}
-
-async fn handle_stop(
-    names: Option<Vec<String>>,
-    processes: &Arc<RwLock<ProcessTable>>,
-) -> Response {
-    let mut table = processes.write().await;
 
-    let targets: Vec<String> = match names {
-        Some(ref requested) => {
-            for name in requested {
-                if !table.contains_key(name) {
-                    return Response::Error {
-                        message: format!("process not found: {name}"),
-                    };
-                }
+        while launches.len() < parallel {
+            // Whatever waits on nothing still pending; with nothing going
+            // and nothing ready, `depends_on` has a cycle, broken by name
+            let ready = pending
+                .iter()
+                .position(|(name, _)| dependencies[name].iter().all(|dep| done.contains(dep)));
+            let Some(index) = ready.or((launches.is_empty() && !pending.is_empty()).then_some(0))
+            else {
+                break;
+            };
+            let (name, config) = pending.remove(index);
+            if let Some(delay) = stagger
+                && launched
+                && !config.is_job()
+            {
+                tokio::time::sleep(delay).await;
             }
-            requested.clone()
+            launched |= !config.is_job();
+            let await_ready = awaited.remove(&name);
+            let processes = processes.clone();
+            let paths = paths.clone();
+            let services = services.clone();
+            let task = launches.spawn({
+                let name = name.clone();
+                async move {
+                    let launch = launch(&name, config, &processes, &paths, &services).await;
+                    // What depends on it starts once it is serving
+                    if await_ready && let Ok(Launch::Started { .. }) = launch {
+                        await_instance_ready(&name, &processes)
+                            .await
+                            .map_err(|e| format!("started but not ready: {e}"))?;
+                    }
+                    launch
+                }
+            });
+            launching.insert(task.id(), name);
         }
-        None => table.keys().cloned().collect(),
-    };
 
-    let mut stopped = Vec::new();
-    for name in &targets {
-        let managed = table.get_mut(name).unwrap();
-        if managed.status == protocol::ProcessStatus::Stopped {
-            continue;
-        }
-        if let Err(e) = managed.graceful_stop().await {
-            return Response::Error {
-                message: format!("failed to stop '{}': {}", name, e),
-            };
+        let Some(joined) = launches.join_next_with_id().await else {
+            break;
+        };
+        let (name, launch) = match joined {
+            Ok((id, launch)) => (launching.remove(&id).unwrap_or_default(), launch),
+            Err(e) => (
+                launching.remove(&e.id()).unwrap_or_default(),
+                Err(format!("launch failed: {e}")),
+            ),
+        };
+        let result = match launch {
+            Ok(Launch::Unchanged) => ProcessResult::skipped(&name, "already running"),
+            Ok(Launch::Scheduled) => ProcessResult::succeeded(&name, Some("scheduled".into())),
+            Ok(Launch::Started { generation }) => {
+                let note = (generation > 1).then(|| format!("generation {generation}"));
+                ProcessResult::succeeded(&name, note)
+            }
+            Err(reason) => ProcessResult::failed(&name, reason),
+        };
+        send_progress(&mut progress, &result).await;
+        if result.status == ResultStatus::Failed {
+            failed.insert(name);
+        } else {
+            done.insert(name);
         }
-        stopped.push(name.clone());
+        results.push(result);
     }
 
-    Response::Success {
-        message: Some(format!("stopped: {}", stopped.join(", "))),
+    // Launches finish in any order
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    Response::Results {
+        action: "started".to_string(),
+        results,
+        warnings: shortfall.into_iter().collect(),
     }
 }
 
-async fn handle_restart(
-    names: Option<Vec<String>>,
-    processes: &Arc<RwLock<ProcessTable>>,
+/// What launching one process of a start came to.
+enum Launch {
+    /// Already running with the same config.
+    Unchanged,
+    Scheduled,
+    Started {
+        generation: u64,
+    },
+}
+
+/// Launch `name` with `config`, retiring a running old generation first and
+/// waiting between messages while it dies. The spawn runs between messages
+/// too, so a slow `pre_start` hook holds up no one else.
+async fn launch(
+    name: &str,
+    config: ProcessConfig,
+    processes: &Processes,
     paths: &Paths,
-) -> Response {
-    let mut restarted = Vec::new();
-    let mut children_to_monitor = Vec::new();
+    services: &Services,
+) -> Result<Launch, String> {
+    let retiring = processes
+        .with(name, {
+            let config = config.clone();
+            move |existing| (existing.config != config).then(|| existing.begin_stop())
+        })
+        .await;
+    let stopper = match retiring {
+        Some(None) => return Ok(Launch::Unchanged),
+        Some(Some(stop)) => stop.map_err(|e| format!("could not stop the old generation: {e}"))?,
+        None => None,
+    };
+    if let Some(stopper) = stopper {
+        await_stop(name, stopper, processes).await;
+    }
+    let generation = processes.state(name).await.map_or(1, |m| m.generation + 1);
 
-    {
-        let mut table = processes.write().await;
+    // Jobs wait for their schedule
+    if config.is_job() {
+        let job = process::schedule_job(name.to_string(), config, generation, paths);
+        put_in_place(job, processes).await?;
+        return Ok(Launch::Scheduled);
+    }
 
-        let targets: Vec<String> = match names {
-            Some(ref requested) => {
-                for name in requested {
-                    if !table.contains_key(name) {
-                        return Response::Error {
-                            message: format!("process not found: {name}"),
-                        };
+    let (mut managed, child) =
+        process::spawn_process(name.to_string(), config, generation, paths, services)
+            .await
+            .map_err(|e| e.to_string())?;
+    let monitor = PendingMonitor::new(&mut managed, child);
+    if let Err(e) = put_in_place(managed, processes).await {
+        monitor.discard().await;
+        return Err(e);
+    }
+    // Spawn the monitor once the run is in place
+    monitor.spawn(processes, paths, services);
+    Ok(Launch::Started { generation })
+}
+
+/// Put a launched run in the table. What another request started under its
+/// name while the launch ran is retired: its actor begins the stop as the
+/// launched run takes its place, and the stop is waited out after.
+async fn put_in_place(mut managed: ManagedProcess, processes: &Processes) -> Result<(), String> {
+    loop {
+        managed = match processes.insert_new(managed) {
+            Ok(()) => return Ok(()),
+            Err(managed) => *managed,
+        };
+        match processes
+            .place(managed, |current, _| retire_replaced(current))
+            .await
+        {
+            Ok(stopper) => {
+                if let Some(stopper) = stopper {
+                    stopper.run().await;
+                }
+                return Ok(());
+            }
+            Err((_, Some(e))) => return Err(e),
+            // Taken out of the table meanwhile, so the name is free again
+            Err((returned, None)) => managed = *returned,
+        }
+    }
+}
+
+/// Begin stopping what another request started as a process while a launch
+/// of it ran, before the launch takes its place.
+fn retire_replaced(existing: &mut ProcessState) -> Result<Option<Stopper>, String> {
+    if existing.pid.is_none() {
+        return Ok(None);
+    }
+    existing
+        .begin_stop()
+        .map_err(|e| format!("could not stop the run started meanwhile: {e}"))
+}
+
+/// The processes of a start each one waits for: those among `to_start` its
+/// `depends_on` names, directly or as instances.
+fn start_dependencies(to_start: &[(String, ProcessConfig)]) -> HashMap<String, Vec<String>> {
+    to_start
+        .iter()
+        .map(|(name, config)| {
+            let deps = config
+                .depends_on
+                .iter()
+                .flatten()
+                .flat_map(|dep| {
+                    to_start.iter().filter_map(move |(other, other_config)| {
+                        let instance = config::instance_of(other, other_config);
+                        (other == dep || instance.is_some_and(|(base, _)| base == dep))
+                            .then(|| other.clone())
+                    })
+                })
+                .filter(|dep| dep != name)
+                .collect();
+            (name.clone(), deps)
+        })
+        .collect()
+}
+
+/// Tell a client that asked for progress how a process of its start went.
+/// A client that went away stops being told; the start carries on.
+async fn send_progress(progress: &mut Option<&mut ClientWriter>, result: &ProcessResult) {
+    let Some(writer) = progress else {
+        return;
+    };
+    let response = Response::StartProgress {
+        result: result.clone(),
+    };
+    let sent = match protocol::encode_response(&response) {
+        Ok(encoded) => writer.write_all(&encoded).await.is_ok() && writer.flush().await.is_ok(),
+        Err(_) => false,
+    };
+    if !sent {
+        *progress = None;
+    }
+}
+
+/// Describe how far the `expect_memory` of the processes a start would spawn
+/// overshoots the memory available, if it does. Processes already running
+/// with the same config are left alone by the start and not counted.
+fn memory_shortfall(
+    to_start: &[(String, ProcessConfig)],
+    table: &ProcessTable,
+    available: Option<u64>,
+) -> Option<String> {
+    let available = available?;
+    let mut total: u64 = 0;
+    let mut expected = Vec::new();
+    for (name, config) in to_start {
+        let Some(size) = config.expect_memory.as_deref() else {
+            continue;
+        };
+        if table.get(name).is_some_and(|m| m.config == *config) {
+            continue;
+        }
+        let Ok(bytes) = config::parse_memory(size) else {
+            continue;
+        };
+        total = total.saturating_add(bytes);
+        expected.push(format!("{name} {size}"));
+    }
+
+    let gib = |bytes: u64| format!("{:.1}G", bytes as f64 / (1u64 << 30) as f64);
+    (total > available).then(|| {
+        format!(
+            "processes expect {} of memory ({}) but only {} is available",
+            gib(total),
+            expected.join(", "),
+            gib(available)
+        )
+    })
+}
+
+async fn handle_info(name: &str, tree: bool, processes: &Processes, paths: &Paths) -> Response {
+    let table = processes.snapshot().await;
+    match table.get(name) {
+        Some(managed) => {
+            let mut info = managed.to_process_detail(paths);
+            if let Some(pid) = managed.pid.filter(|_| tree) {
+                let procs = stats::ProcessTree::read();
+                info.tree = stats::tree_members(&procs, pid)
+                    .into_iter()
+                    .map(|member| TreeMember {
+                        pid: member.pid,
+                        ppid: member.ppid,
+                        command: member.command,
+                        rss_bytes: member.rss_bytes,
+                        cpu_time_ms: member.cpu_time_ms,
+                    })
+                    .collect();
+            }
+            Response::ProcessDetail {
+                info: Box::new(info),
+            }
+        }
+        None => Response::Error {
+            message: format!("process not found: {name}"),
+        },
+    }
+}
+
+/// The `type` a request carries on the wire, naming it in the daemon log
+/// without its configs and their environment.
+fn request_kind(request: &Request) -> String {
+    serde_json::to_value(request)
+        .ok()
+        .and_then(|value| value["type"].as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Requests that only read the daemon's state.
+fn is_query(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::List
+            | Request::Jobs
+            | Request::Info { .. }
+            | Request::Log { .. }
+            | Request::History { .. }
+            | Request::Events { .. }
+            | Request::Samples { .. }
+            | Request::Audit { .. }
+            | Request::Ping
+            | Request::DaemonStatus
+            | Request::ConfigGet { .. }
+    )
+}
+
+/// Requests after which `auto_save` rewrites the dump.
+fn changes_table(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::Start { .. }
+            | Request::Stop { .. }
+            | Request::Restart { .. }
+            | Request::Reload { .. }
+            | Request::Scale { .. }
+            | Request::Resurrect
+    )
+}
+
+/// Serializes writes to the dump file, so an older snapshot can never land
+/// after a newer one.
+static DUMP_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Write the process table to the dump file, returning how many processes
+/// it holds.
+async fn save_dump(processes: &Processes, paths: &Paths) -> Result<usize, String> {
+    let _saving = DUMP_LOCK.lock().await;
+
+    let snapshot = Dump::capture(processes.snapshot().await.values());
+    let count = snapshot.processes.len();
+    let path = paths.dump_file();
+    tokio::task::spawn_blocking(move || dump::write(&path, &snapshot))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+async fn handle_save(processes: &Processes, paths: &Paths) -> Response {
+    let path = paths.dump_file();
+    match save_dump(processes, paths).await {
+        Ok(count) => Response::Success {
+            message: Some(format!(
+                "saved {count} process{} to {}",
+                if count == 1 { "" } else { "es" },
+                path.display()
+            )),
+        },
+        Err(e) => Response::Error {
+            message: format!("failed to save: {e}"),
+        },
+    }
+}
+
+/// Start or stop instances of `name` until `count` run. New instances take
+/// the lowest free indices and the config of the existing ones; the
+/// highest-numbered instances are the ones stopped. A saved dump is updated
+/// to the new set of instances.
+async fn handle_scale(
+    name: &str,
+    count: u32,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) -> Response {
+    if count == 0 {
+        return Response::Error {
+            message: format!("cannot scale '{name}' to 0 instances; use `pm3 stop {name}`"),
+        };
+    }
+
+    let mut results = Vec::new();
+    let mut surplus_instances = Vec::new();
+    let (config, generation, missing) = {
+        let table = processes.snapshot().await;
+        let current = instances_of(&table, name);
+        let Some((_, first)) = current.first() else {
+            let message = if table.contains_key(name) {
+                format!("'{name}' is not a multi-instance process; set `instances` in pm3.toml")
+            } else {
+                format!("process not found: {name}")
+            };
+            return Response::Error { message };
+        };
+        let template = &table[first];
+        let (config, generation) = (template.config.clone(), template.generation);
+
+        // The lowest free instance names, up to the count
+        let missing: Vec<String> = (0..)
+            .map(|index| config::instance_name(name, index))
+            .filter(|instance| !table.contains_key(instance))
+            .take((count as usize).saturating_sub(current.len()))
+            .collect();
+
+        let surplus = current.len().saturating_sub(count as usize);
+        for (_, instance) in current.iter().rev().take(surplus) {
+            if let Some(actor) = processes.remove(instance) {
+                surplus_instances.push((instance.clone(), actor));
+            }
+        }
+        (config, generation, missing)
+    };
+
+    // Out of the table already; their actors still see the stops through
+    for (instance, actor) in surplus_instances {
+        results.push(match actor.stop().await {
+            Ok(outcome) => {
+                let note = outcome
+                    .note()
+                    .map_or("stopped".to_string(), |note| format!("stopped, {note}"));
+                ProcessResult::succeeded(instance, Some(note))
+            }
+            Err(e) => ProcessResult::failed(instance, e.to_string()),
+        });
+    }
+
+    // Spawned between messages, so a slow pre_start hook holds up no one
+    // else; an instance another request started meanwhile is kept. One that
+    // fails to spawn does not keep the rest from starting.
+    for instance in missing {
+        let spawned = process::spawn_process(
+            instance.clone(),
+            config.clone(),
+            generation,
+            paths,
+            services,
+        )
+        .await;
+        let (mut managed, child) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                results.push(ProcessResult::failed(instance, e.to_string()));
+                continue;
+            }
+        };
+        let monitor = PendingMonitor::new(&mut managed, child);
+        if processes.insert_new(managed).is_err() {
+            monitor.discard().await;
+            results.push(ProcessResult::skipped(instance, "started meanwhile"));
+            continue;
+        }
+        monitor.spawn(processes, paths, services);
+        results.push(ProcessResult::succeeded(
+            instance,
+            Some("started".to_string()),
+        ));
+    }
+
+    // Whatever came of each instance, the dump follows what now runs
+    let mut warnings = Vec::new();
+    if let Err(e) = rescale_dump(name, processes, paths).await {
+        warnings.push(format!("failed to update the dump: {e}"));
+    }
+
+    Response::Results {
+        action: format!("scaled {name} to {count}"),
+        results,
+        warnings,
+    }
+}
+
+/// Replace the instances of `name` in a saved dump with the ones now in the
+/// table, leaving the rest of the dump as it was saved.
+async fn rescale_dump(name: &str, processes: &Processes, paths: &Paths) -> Result<(), String> {
+    let _saving = DUMP_LOCK.lock().await;
+    let path = paths.dump_file();
+    if !path.exists() {
+        return Ok(());
+    }
+    let mut saved = dump::read(&path).map_err(|e| e.to_string())?;
+    saved
+        .processes
+        .retain(|p| config::instance_of(&p.name, &p.config).is_none_or(|(base, _)| base != name));
+    {
+        let table = processes.snapshot().await;
+        let instances = instances_of(&table, name);
+        let current = Dump::capture(instances.iter().map(|(_, instance)| &table[instance]));
+        saved.processes.extend(current.processes);
+    }
+    saved.processes.sort_by(|a, b| a.name.cmp(&b.name));
+    tokio::task::spawn_blocking(move || dump::write(&path, &saved))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// Respawn every process the dump recorded as running, with its config,
+/// environment, generation and restart counters. Processes that were stopped
+/// or had given up are skipped, as are ones already running again.
+async fn handle_resurrect(processes: &Processes, paths: &Paths, services: &Services) -> Response {
+    let path = paths.dump_file();
+    if !path.exists() {
+        return Response::Error {
+            message: "nothing to resurrect; run `pm3 save` first".to_string(),
+        };
+    }
+    let saved = match dump::read(&path) {
+        Ok(saved) => saved,
+        Err(e) => {
+            return Response::Error {
+                message: format!("failed to read {}: {e}", path.display()),
+            };
+        }
+    };
+
+    let mut summary = Vec::new();
+    let mut revived = 0;
+    let mut failed = 0;
+
+    for entry in saved.processes {
+        let name = entry.name;
+        let is_job = entry.config.is_job();
+        let mut config = entry.config;
+        if let Some(env) = &entry.environment {
+            config.apply_environment(env);
+        }
+        // Jobs go back on their schedules whatever their last run did
+        if is_job {
+            if !processes.contains(&name) {
+                let job = process::schedule_job(name.clone(), config, entry.generation, paths);
+                if processes.insert_new(job).is_ok() {
+                    summary.push(format!("  {name}: scheduled"));
+                    revived += 1;
+                }
+            }
+            continue;
+        }
+        if !matches!(
+            entry.status,
+            ProcessStatus::Online | ProcessStatus::Starting | ProcessStatus::Unhealthy
+        ) {
+            summary.push(format!("  {name}: skipped (was {})", entry.status));
+            continue;
+        }
+        let running = |managed: &ProcessState| {
+            !matches!(
+                managed.status,
+                ProcessStatus::Stopped | ProcessStatus::Completed
+            )
+        };
+        if processes.state(&name).await.is_some_and(|m| running(&m)) {
+            summary.push(format!("  {name}: skipped (already running)"));
+            continue;
+        }
+
+        // Spawned between messages, so a slow pre_start hook holds up no one
+        // else
+        match process::spawn_process(name.clone(), config, entry.generation, paths, services).await
+        {
+            Ok((mut managed, child)) => {
+                managed.restarts = entry.restarts;
+                managed.memory_restarts = entry.memory_restarts;
+                let monitor = PendingMonitor::new(&mut managed, child);
+                let placed = match processes.insert_new(managed) {
+                    Ok(()) => true,
+                    Err(managed) => processes
+                        .place(*managed, move |current, _| match running(current) {
+                            true => Err(()),
+                            false => Ok(()),
+                        })
+                        .await
+                        .is_ok(),
+                };
+                if !placed {
+                    monitor.discard().await;
+                    summary.push(format!("  {name}: skipped (already running)"));
+                    continue;
+                }
+                monitor.spawn(processes, paths, services);
+                summary.push(format!("  {name}: revived"));
+                revived += 1;
+            }
+            Err(e) => {
+                summary.push(format!("  {name}: failed ({e})"));
+                failed += 1;
+            }
+        }
+    }
+
+    let message = format!(
+        "resurrected {revived} of {} saved processes\n{}",
+        summary.len(),
+        summary.join("\n")
+    );
+    if failed > 0 {
+        Response::Error { message }
+    } else {
+        Response::Success {
+            message: Some(message),
+        }
+    }
+}
+
+/// Resolve the processes a request targets: every member of `group`, the
+/// requested names, or the whole table. The name of a process with
+/// `instances` set stands for all of its instances.
+fn resolve_targets(
+    table: &ProcessTable,
+    names: Option<Vec<String>>,
+    group: Option<String>,
+) -> Result<Vec<String>, String> {
+    if let Some(group) = group {
+        let mut members: Vec<String> = table
+            .values()
+            .filter(|m| m.config.group.as_deref() == Some(group.as_str()))
+            .map(|m| m.name.clone())
+            .collect();
+        if members.is_empty() {
+            return Err(format!("no processes in group: {group}"));
+        }
+        members.sort();
+        return Ok(members);
+    }
+
+    match names {
+        Some(requested) => {
+            let mut targets = Vec::new();
+            for name in requested {
+                if table.contains_key(&name) {
+                    targets.push(name);
+                    continue;
+                }
+                let instances = instances_of(table, &name);
+                if instances.is_empty() {
+                    return Err(format!("process not found: {name}"));
+                }
+                targets.extend(instances.into_iter().map(|(_, instance)| instance));
+            }
+            Ok(targets)
+        }
+        None => Ok(table.keys().cloned().collect()),
+    }
+}
+
+/// Instances of `name` in the table as `(index, table name)`, by index.
+fn instances_of(table: &ProcessTable, name: &str) -> Vec<(u32, String)> {
+    let mut instances: Vec<(u32, String)> = table
+        .values()
+        .filter_map(|m| match config::instance_of(&m.name, &m.config) {
+            Some((base, index)) if base == name => Some((index, m.name.clone())),
+            _ => None,
+        })
+        .collect();
+    instances.sort();
+    instances
+}
+
+async fn handle_stop(
+    names: Option<Vec<String>>,
+    group: Option<String>,
+    cascade: bool,
+    processes: &Processes,
+) -> Response {
+    let mut to_stop = Vec::new();
+    let mut results = Vec::new();
+    {
+        let table = processes.snapshot().await;
+        let mut targets = match resolve_targets(&table, names, group) {
+            Ok(targets) => targets,
+            Err(message) => return Response::Error { message },
+        };
+        if cascade {
+            let dependents = dependents_of(&table, &targets);
+            targets.extend(dependents);
+        }
+
+        for name in stop_order(&table, &targets) {
+            let managed = &table[&name];
+            if matches!(
+                managed.status,
+                protocol::ProcessStatus::Stopped | protocol::ProcessStatus::Completed
+            ) {
+                // A scheduled job between runs is Stopped or Completed too;
+                // mark it stopped by hand so `cron_restart` leaves it alone
+                if let Some(ref tx) = managed.monitor_shutdown {
+                    tx.send_replace(true);
+                }
+                results.push(ProcessResult::skipped(name, "not running"));
+                continue;
+            }
+            to_stop.push(name);
+        }
+    }
+
+    // One at a time, so dependents are gone before what they depend on, and
+    // between messages while each dies. A failure does not keep the rest
+    // running.
+    for name in to_stop {
+        results.push(match stop_one(&name, processes).await {
+            Ok(Some(outcome)) => ProcessResult::succeeded(name, outcome.note()),
+            Ok(None) => ProcessResult::skipped(name, "removed meanwhile"),
+            Err(message) => ProcessResult::failed(name, message),
+        });
+    }
+
+    Response::Results {
+        action: "stopped".to_string(),
+        results,
+        warnings: Vec::new(),
+    }
+}
+
+/// How a restart goes about replacing what it selected.
+struct RestartOptions {
+    /// Replace one at a time, waiting for each to be ready.
+    rolling: bool,
+    stagger: Option<Duration>,
+    /// Restart what depends on the selected processes too.
+    cascade: bool,
+}
+
+async fn handle_restart(
+    names: Option<Vec<String>>,
+    group: Option<String>,
+    options: RestartOptions,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) -> Response {
+    let RestartOptions {
+        rolling,
+        stagger,
+        cascade,
+    } = options;
+    let mut restarted_names = HashSet::new();
+    let mut at_once = Vec::new();
+    let mut one_at_a_time = Vec::new();
+    let dependents;
+
+    {
+        let table = processes.snapshot().await;
+
+        let everything = names.is_none() && group.is_none();
+        let mut targets = match resolve_targets(&table, names, group) {
+            Ok(targets) => targets,
+            Err(message) => return Response::Error { message },
+        };
+        // Jobs keep to their schedules unless restarted by name, which runs
+        // them now
+        if everything {
+            targets.retain(|name| !table[name].config.is_job());
+        }
+        dependents = if cascade {
+            dependency_order(&table, &dependents_of(&table, &targets), false, |_| 0)
+        } else {
+            Vec::new()
+        };
+
+        for name in targets {
+            let config = &table[&name].config;
+            if let Some((base, index)) = config::instance_of(&name, config)
+                && (rolling || config.rolling_restart == Some(true))
+            {
+                one_at_a_time.push(((base.to_string(), index), name));
+            } else {
+                at_once.push(name);
+            }
+        }
+    }
+
+    // A failure does not keep the rest from restarting; only what would
+    // depend on the failed process waits
+    let mut results: Vec<ProcessResult> = Vec::new();
+    // Why what depends on a process that did not come back is left alone
+    let mut held: HashMap<String, String> = HashMap::new();
+    let mut launched = false;
+    for name in at_once {
+        if let Some(delay) = stagger
+            && launched
+        {
+            tokio::time::sleep(delay).await;
+        }
+        launched = true;
+        match restart_one(&name, "manual restart", processes, paths, services).await {
+            Ok(Some(outcome)) => {
+                results.push(ProcessResult::succeeded(&name, outcome.note()));
+                restarted_names.insert(name);
+            }
+            Ok(None) => {}
+            Err(message) => {
+                held.insert(name.clone(), format!("'{name}' failed to restart"));
+                results.push(ProcessResult::failed(name, message));
+            }
+        }
+    }
+
+    // Instances go in index order, one process after another; once one does
+    // not come back, the rest of its process keep their old runs
+    one_at_a_time.sort();
+    let mut halted: HashMap<String, String> = HashMap::new();
+    for ((base, _), name) in one_at_a_time {
+        if let Some(stuck) = halted.get(&base) {
+            let reason = format!("rolling restart stopped at '{stuck}'");
+            results.push(ProcessResult::skipped(name, reason));
+            continue;
+        }
+        if let Some(delay) = stagger
+            && launched
+        {
+            tokio::time::sleep(delay).await;
+        }
+        launched = true;
+        let outcome = match restart_one(&name, "rolling restart", processes, paths, services).await
+        {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) => continue,
+            Err(message) => {
+                held.insert(name.clone(), format!("'{name}' failed to restart"));
+                results.push(ProcessResult::failed(&name, message));
+                halted.insert(base, name);
+                continue;
+            }
+        };
+        if let Err(reason) = await_instance_ready(&name, processes).await {
+            held.insert(name.clone(), format!("'{name}' did not come back"));
+            results.push(ProcessResult::failed(&name, format!("not ready: {reason}")));
+            halted.insert(base, name);
+            continue;
+        }
+        results.push(ProcessResult::succeeded(&name, outcome.note()));
+        restarted_names.insert(name);
+    }
+
+    // Dependents go once everything they depend on is back
+    let mut back = HashSet::new();
+    for name in dependents {
+        let deps = {
+            let table = processes.snapshot().await;
+            if !table.contains_key(&name) {
+                continue;
+            }
+            dependencies(&table, &name)
+        };
+        let mut waiting = deps.iter().find_map(|dep| held.get(dep)).cloned();
+        for dep in &deps {
+            if waiting.is_some() {
+                break;
+            }
+            if !restarted_names.contains(dep) || back.contains(dep) {
+                continue;
+            }
+            match await_instance_ready(dep, processes).await {
+                Ok(()) => {
+                    back.insert(dep.clone());
+                }
+                Err(reason) => {
+                    waiting = Some(format!("'{dep}' did not come back: {reason}"));
+                    held.insert(dep.clone(), format!("'{dep}' did not come back"));
+                }
+            }
+        }
+        if let Some(reason) = waiting {
+            held.insert(name.clone(), reason.clone());
+            results.push(ProcessResult::skipped(name, reason));
+            continue;
+        }
+        if let Some(delay) = stagger
+            && launched
+        {
+            tokio::time::sleep(delay).await;
+        }
+        launched = true;
+        match restart_one(&name, "dependency restarted", processes, paths, services).await {
+            Ok(Some(outcome)) => {
+                results.push(ProcessResult::succeeded(&name, outcome.note()));
+                restarted_names.insert(name);
+            }
+            Ok(None) => {}
+            Err(message) => {
+                held.insert(name.clone(), format!("'{name}' failed to restart"));
+                results.push(ProcessResult::failed(name, message));
+            }
+        }
+    }
+
+    Response::Results {
+        action: "restarted".to_string(),
+        results,
+        warnings: Vec::new(),
+    }
+}
+
+/// Respawn `name`. Returns how the old run ended, or `None` if the process is
+/// no longer in the table.
+async fn restart_one(
+    name: &str,
+    reason: &str,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) -> Result<Option<StopOutcome>, String> {
+    let Some(stopper) = processes.with(name, |managed| begin_respawn(managed)).await else {
+        return Ok(None);
+    };
+    respawn(name, reason, stopper?, processes, paths, services).await
+}
+
+// ---------------------------------------------------------------------------
+// Dependencies
+// ---------------------------------------------------------------------------
+
+/// Table names of the processes `name` lists in `depends_on`. A dependency
+/// with `instances` stands for all of them.
+fn dependencies(table: &ProcessTable, name: &str) -> Vec<String> {
+    let Some(depends_on) = &table[name].config.depends_on else {
+        return Vec::new();
+    };
+    depends_on
+        .iter()
+        .flat_map(|dep| {
+            if table.contains_key(dep) {
+                vec![dep.clone()]
+            } else {
+                instances_of(table, dep)
+                    .into_iter()
+                    .map(|(_, instance)| instance)
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+/// Every process that depends on one of `roots`, directly or through other
+/// processes, leaving out `roots` themselves.
+fn dependents_of(table: &ProcessTable, roots: &[String]) -> Vec<String> {
+    let mut seen: HashSet<&str> = roots.iter().map(String::as_str).collect();
+    let mut found = Vec::new();
+    loop {
+        let before = found.len();
+        for name in table.keys() {
+            if !seen.contains(name.as_str())
+                && dependencies(table, name)
+                    .iter()
+                    .any(|dep| seen.contains(dep.as_str()))
+            {
+                seen.insert(name);
+                found.push(name.clone());
+            }
+        }
+        if found.len() == before {
+            return found;
+        }
+    }
+}
+
+/// `names` ordered so each process comes after the ones in `names` it depends
+/// on, or before them with `dependents_first`. Of the processes free to go
+/// next, the lowest `rank` goes first, then the first by name. A dependency
+/// cycle is broken at the same choice among all that remain.
+fn dependency_order(
+    table: &ProcessTable,
+    names: &[String],
+    dependents_first: bool,
+    rank: impl Fn(&ProcessConfig) -> u8,
+) -> Vec<String> {
+    let edges: HashMap<&str, Vec<String>> = names
+        .iter()
+        .map(|name| (name.as_str(), dependencies(table, name)))
+        .collect();
+    let mut remaining: Vec<&str> = names.iter().map(String::as_str).collect();
+    remaining.sort();
+    remaining.dedup();
+
+    let mut ordered = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let waiting = |name: &str| {
+            remaining.iter().any(|&other| {
+                other != name
+                    && if dependents_first {
+                        edges[other].iter().any(|dep| dep == name)
+                    } else {
+                        edges[name].iter().any(|dep| dep == other)
                     }
+            })
+        };
+        let key = |name: &&str| (rank(&table[*name].config), name.to_string());
+        let next = remaining
+            .iter()
+            .copied()
+            .filter(|name| !waiting(name))
+            .min_by_key(key)
+            .or_else(|| remaining.iter().copied().min_by_key(key))
+            .expect("remaining is not empty");
+        remaining.retain(|&name| name != next);
+        ordered.push(next.to_string());
+    }
+    ordered
+}
+
+/// The order to stop `names` in: dependents before what they depend on, and
+/// otherwise batch processes first and critical ones last.
+fn stop_order(table: &ProcessTable, names: &[String]) -> Vec<String> {
+    dependency_order(table, names, true, |config| config.class().stop_order())
+}
+
+const ROLLING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wait for a freshly restarted instance to prove it is serving: its
+/// `ready_check` passing, or else its HTTP `health_check` answering. An
+/// instance with neither counts as ready once it is running.
+async fn await_instance_ready(name: &str, processes: &Processes) -> Result<(), String> {
+    let (config, timeout_ms) = {
+        let table = processes.snapshot().await;
+        let Some(managed) = table.get(name) else {
+            return Err("it was removed".to_string());
+        };
+        let timeout = managed
+            .config
+            .ready_timeout
+            .unwrap_or(ready::DEFAULT_READY_TIMEOUT_MS);
+        (managed.config.clone(), timeout)
+    };
+
+    if config.ready_check.is_some() {
+        // The monitor runs the check and settles the status
+        let deadline =
+            Instant::now() + Duration::from_millis(timeout_ms) + ROLLING_POLL_INTERVAL * 10;
+        loop {
+            let status = processes.snapshot().await.get(name).map(|m| m.status);
+            match status {
+                Some(ProcessStatus::Online) => return Ok(()),
+                Some(ProcessStatus::Starting) if Instant::now() < deadline => {
+                    tokio::time::sleep(ROLLING_POLL_INTERVAL).await;
+                }
+                Some(ProcessStatus::Starting) => {
+                    return Err("ready check did not settle".to_string());
+                }
+                Some(status) => return Err(format!("it is {status} instead of ready")),
+                None => return Err("it was removed".to_string()),
+            }
+        }
+    }
+
+    match ready::readiness_check(&config) {
+        Some(check) => ready::wait_ready(&check, timeout_ms, ready::ReadySources::default())
+            .await
+            .map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+async fn handle_input(name: &str, data: String, processes: &Processes) -> Response {
+    let stdin = match stdin_of(&processes.snapshot().await, name) {
+        Ok((stdin, _)) => stdin,
+        Err(message) => return Response::Error { message },
+    };
+    match stdin.send(data.into_bytes()).await {
+        Ok(()) => Response::Success { message: None },
+        Err(_) => Response::Error {
+            message: format!("process '{name}' has closed its stdin"),
+        },
+    }
+}
+
+async fn handle_signal(
+    name: &str,
+    signal_name: &str,
+    group_leader: bool,
+    processes: &Processes,
+) -> Response {
+    let signal = match process::parse_signal(signal_name) {
+        Ok(signal) => signal,
+        Err(e) => {
+            return Response::Error {
+                message: e.to_string(),
+            };
+        }
+    };
+
+    let table = processes.snapshot().await;
+    let Some(managed) = table.get(name) else {
+        return Response::Error {
+            message: format!("process not found: {name}"),
+        };
+    };
+    let Some(pid) = managed.pid else {
+        return Response::Error {
+            message: format!("process '{name}' is not running"),
+        };
+    };
+
+    let pid = nix::unistd::Pid::from_raw(pid as i32);
+    let result = if group_leader {
+        nix::sys::signal::killpg(pid, signal)
+    } else {
+        nix::sys::signal::kill(pid, signal)
+    };
+    match result {
+        Ok(()) => Response::Success {
+            message: Some(format!("sent {signal} to {name}")),
+        },
+        Err(nix::errno::Errno::ESRCH) => Response::Error {
+            message: format!("process '{name}' is not running"),
+        },
+        Err(e) => Response::Error {
+            message: format!("failed to signal '{name}': {e}"),
+        },
+    }
+}
+
+async fn handle_reload(
+    names: Option<Vec<String>>,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) -> Response {
+    let targets = {
+        let table = processes.snapshot().await;
+        match resolve_targets(&table, names, None) {
+            Ok(mut targets) => {
+                targets.sort();
+                targets
+            }
+            Err(message) => return Response::Error { message },
+        }
+    };
+
+    // One process at a time, so a group of replicas never goes down together;
+    // once one fails to reload, the rest keep their current runs
+    let mut results: Vec<ProcessResult> = Vec::new();
+    let mut stuck: Option<String> = None;
+    for name in targets {
+        if let Some(stuck) = &stuck {
+            let reason = format!("not attempted, '{stuck}' failed to reload");
+            results.push(ProcessResult::skipped(name, reason));
+            continue;
+        }
+        match reload_one(&name, processes, paths, services).await {
+            Ok(note) => results.push(ProcessResult::succeeded(name, note)),
+            Err(message) => {
+                results.push(ProcessResult::failed(&name, message));
+                stuck = Some(name);
+            }
+        }
+    }
+
+    Response::Results {
+        action: "reloaded".to_string(),
+        results,
+        warnings: Vec::new(),
+    }
+}
+
+/// Send `reload_signal` to a running process that has one. Otherwise start a
+/// replacement next to the running instance, wait for it to pass its
+/// readiness check, then swap it in and stop the old one. Processes without a
+/// check, or not currently running, get a plain restart. `Ok` carries a note
+/// on how the process was reloaded, if it was not swapped in place.
+async fn reload_one(
+    name: &str,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) -> Result<Option<String>, String> {
+    let (config, generation, check) = {
+        let table = processes.snapshot().await;
+        let managed = table
+            .get(name)
+            .ok_or_else(|| format!("process not found: {name}"))?;
+
+        // Processes that reload themselves on a signal keep their pid
+        if let (Some(signal_name), Some(pid)) = (&managed.config.reload_signal, managed.pid) {
+            let signal = process::parse_signal(signal_name).map_err(|e| e.to_string())?;
+            nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), signal)
+                .map_err(|e| format!("failed to signal '{name}': {e}"))?;
+            return Ok(Some(signal_name.clone()));
+        }
+
+        let check = ready::readiness_check(&managed.config).filter(|_| managed.pid.is_some());
+        (managed.config.clone(), managed.generation, check)
+    };
+
+    let Some(check) = check else {
+        let stopper = processes
+            .with(name, |managed| begin_respawn(managed))
+            .await
+            .ok_or_else(|| format!("process not found: {name}"))??;
+        respawn(name, "reload", stopper, processes, paths, services).await?;
+        return Ok(Some("restarted".to_string()));
+    };
+
+    let (mut replacement, mut child) = process::spawn_process(
+        name.to_string(),
+        config.clone(),
+        generation,
+        paths,
+        services,
+    )
+    .await
+    .map_err(|e| format!("failed to reload '{name}': {e}"))?;
+
+    let timeout = config
+        .ready_timeout
+        .unwrap_or(ready::DEFAULT_READY_TIMEOUT_MS);
+    let sources = replacement.ready_sources();
+    let readiness = tokio::select! {
+        result = ready::wait_ready(&check, timeout, sources) => result.map_err(|e| e.to_string()),
+        status = child.wait() => Err(match status.ok().and_then(|s| s.code()) {
+            Some(code) => format!("new instance exited with code {code}"),
+            None => "new instance exited".to_string(),
+        }),
+    };
+    if let Err(reason) = readiness {
+        let _ = child.start_kill();
+        let _ = child.wait().await;
+        return Err(format!(
+            "reload of '{name}' aborted, old instance kept running: {reason}"
+        ));
+    }
+
+    replacement.status = ProcessStatus::Online;
+    replacement.last_restart = Some("reload".to_string());
+    let monitor = PendingMonitor::new(&mut replacement, child);
+    let placed = processes
+        .place(replacement, |current, replacement| {
+            replacement.restarts = current.restarts;
+            replacement.memory_restarts = current.memory_restarts;
+            Ok::<_, ()>(current.begin_stop())
+        })
+        .await;
+    let Ok(retired) = placed else {
+        monitor.discard().await;
+        return Err(format!("process not found: {name}"));
+    };
+    monitor.spawn(processes, paths, services);
+
+    // The old run is out of the table; stop it without holding up its actor
+    let retired = retired.map_err(|e| format!("failed to stop old '{name}': {e}"))?;
+    if let Some(stopper) = retired {
+        stopper.run().await;
+    }
+    Ok(None)
+}
+
+/// Begin the stop of the run `respawn` replaces, unless nothing is running.
+/// Called on the process's actor.
+fn begin_respawn(managed: &mut ProcessState) -> Result<Option<Stopper>, String> {
+    if matches!(
+        managed.status,
+        protocol::ProcessStatus::Stopped | protocol::ProcessStatus::Completed
+    ) {
+        return Ok(None);
+    }
+    managed
+        .begin_stop()
+        .map_err(|e| format!("failed to stop '{}': {}", managed.name, e))
+}
+
+/// Finish the stop `begin_respawn` began and start `name` again with the same
+/// config and generation, carrying its restart counters over and recording
+/// `reason`. The old run dies between messages, so other requests go on
+/// meanwhile. `Ok(None)` if the process left the table in the meantime.
+async fn respawn(
+    name: &str,
+    reason: &str,
+    stopper: Option<Stopper>,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) -> Result<Option<StopOutcome>, String> {
+    let outcome = match stopper {
+        Some(stopper) => await_stop(name, stopper, processes).await,
+        None => StopOutcome::NotRunning,
+    };
+
+    // Started again by another request while the old run was dying
+    let displaced = processes
+        .with(name, |managed| match managed.pid {
+            Some(_) => managed
+                .begin_stop()
+                .map_err(|e| format!("failed to stop '{}': {}", managed.name, e)),
+            None => Ok(None),
+        })
+        .await;
+    let Some(displaced) = displaced else {
+        return Ok(None);
+    };
+    if let Some(stopper) = displaced? {
+        await_stop(name, stopper, processes).await;
+    }
+
+    // Not online until the replacement spawns; a stop or start meanwhile
+    // changes that, and the replacement gives way to it
+    let marked = processes
+        .with(name, |managed| {
+            managed.status = ProcessStatus::Starting;
+            (managed.config.clone(), managed.generation)
+        })
+        .await;
+    let Some((config, generation)) = marked else {
+        return Ok(None);
+    };
+    let pending = move |managed: &ProcessState| {
+        managed.status == ProcessStatus::Starting
+            && managed.pid.is_none()
+            && managed.generation == generation
+    };
+
+    // Spawned between messages, so a slow pre_start hook holds up no one
+    // else
+    let (mut new_managed, child) =
+        match process::spawn_process(name.to_string(), config, generation, paths, services).await {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                processes
+                    .with(name, move |managed| {
+                        if pending(managed) {
+                            managed.status = ProcessStatus::Stopped;
+                        }
+                    })
+                    .await;
+                return Err(format!("failed to restart '{}': {}", name, e));
+            }
+        };
+    new_managed.last_restart = Some(reason.to_string());
+    let monitor = PendingMonitor::new(&mut new_managed, child);
+    let placed = processes
+        .place(new_managed, move |managed, new_managed| {
+            if !pending(managed) {
+                return Err(());
+            }
+            (
+                new_managed.exit_code,
+                new_managed.exit_signal,
+                new_managed.exited_at,
+            ) = (managed.exit_code, managed.exit_signal, managed.exited_at);
+            new_managed.restarts = managed.restarts + 1;
+            new_managed.memory_restarts = managed.memory_restarts;
+            new_managed.queued_runs = managed.queued_runs;
+            Ok(())
+        })
+        .await;
+    if let Err((_, turned_away)) = placed {
+        monitor.discard().await;
+        return match turned_away {
+            None => Ok(None),
+            Some(()) => Err(format!(
+                "'{name}' was stopped or started again while restarting"
+            )),
+        };
+    }
+    process::record_event(
+        paths,
+        services,
+        ProcessEvent {
+            detail: Some(reason.to_string()),
+            ..ProcessEvent::new(EventKind::Restart, name)
+        },
+    )
+    .await;
+    monitor.spawn(processes, paths, services);
+    Ok(Some(outcome))
+}
+
+/// Stop `name`: its actor only begins the stop and records its end, so a
+/// process that is slow to die holds up no other request. `Ok(None)` if it
+/// is not in the table.
+async fn stop_one(name: &str, processes: &Processes) -> Result<Option<StopOutcome>, String> {
+    match processes.stop(name).await {
+        Some(stopped) => stopped
+            .map(Some)
+            .map_err(|e| format!("failed to stop '{}': {}", name, e)),
+        None => Ok(None),
+    }
+}
+
+/// Wait for a begun stop to finish between messages, then have the
+/// process's actor record it.
+async fn await_stop(name: &str, stopper: Stopper, processes: &Processes) -> StopOutcome {
+    let pid = stopper.pid();
+    let outcome = stopper.run().await;
+    processes
+        .with(name, move |managed| managed.finish_stop(pid))
+        .await;
+    outcome
+}
+
+// ---------------------------------------------------------------------------
+// Crash journal
+// ---------------------------------------------------------------------------
+
+const JOURNAL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Keep `journal.json` in step with the running processes so a daemon that
+/// starts after this one crashes can adopt them.
+async fn run_journal_writer(processes: Processes, paths: Paths) {
+    let mut interval = tokio::time::interval(JOURNAL_INTERVAL);
+    let mut written = None;
+    loop {
+        interval.tick().await;
+        let entries = journal::snapshot(&processes.snapshot().await);
+        if written.as_ref() == Some(&entries) {
+            continue;
+        }
+        match journal::write(&paths, &entries).await {
+            Ok(()) => written = Some(entries),
+            Err(e) => error!("failed to write journal: {e}"),
+        }
+    }
+}
+
+/// Re-adopt the processes the previous daemon's journal lists that are still
+/// running. A journal is only left behind when that daemon did not shut down
+/// cleanly.
+async fn adopt_orphans(processes: &Processes, paths: &Paths, services: &Services) {
+    let entries = match journal::read(paths).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("ignoring journal: {e}");
+            return;
+        }
+    };
+
+    let settings = services.settings.read().await.clone();
+    let mut monitors = Vec::new();
+    for entry in entries {
+        if !entry.is_intact() || !entry.is_running() {
+            continue;
+        }
+        let (name, pid) = (entry.name.clone(), entry.pid);
+        let tracked = process::Tracked::Adopted {
+            pid,
+            start_time: entry.identity.start_time,
+        };
+        match process::adopt_process(entry, paths, &settings, services.shipper.clone()) {
+            Ok(mut managed) => {
+                let _ = log::append_event(
+                    &managed.hook_log,
+                    &format!("adopted by a new daemon (pid {pid})"),
+                )
+                .await;
+                monitors.push(PendingMonitor::new(&mut managed, tracked));
+                processes.insert(managed);
+            }
+            Err(e) => warn!("failed to adopt '{name}' (pid {pid}): {e}"),
+        }
+    }
+    for monitor in monitors {
+        monitor.spawn(processes, paths, services);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Updating
+// ---------------------------------------------------------------------------
+
+/// Replace this daemon with `exe` in the same pid. The journal is written
+/// with every process's actor held still, so nothing starts or exits
+/// unrecorded before the exec, and the new daemon adopts every running
+/// process from it. Returns only if the exec fails, leaving this daemon
+/// running.
+async fn handle_update(
+    exe: PathBuf,
+    audit: Option<AuditEntry>,
+    processes: &Processes,
+    paths: &Paths,
+    writer: &mut ClientWriter,
+) -> color_eyre::Result<()> {
+    let held = processes.hold().await;
+    let table = &held.table;
+    let prepared = match update_blockers(table, &exe) {
+        Err(message) => Err(message),
+        Ok(()) => journal::write(paths, &journal::snapshot(table))
+            .await
+            .map_err(|e| format!("failed to write journal: {e}")),
+    };
+    let response = match prepared {
+        Ok(()) => Response::Success {
+            message: Some(format!("updating daemon to {}", exe.display())),
+        },
+        Err(message) => Response::Error { message },
+    };
+    record_audit(paths, audit, audit::outcome(&response)).await;
+    writer
+        .write_all(&protocol::encode_response(&response)?)
+        .await?;
+    writer.shutdown().await?;
+    if let Response::Error { message } = response {
+        warn!(request = "update", "{message}");
+        return Ok(());
+    }
+
+    info!(exe = %exe.display(), "handing {} processes over to a new daemon", table.len());
+    // The new daemon refuses to start while the pid file names a live pid,
+    // which this one still is
+    pid::remove_pid_file(paths).await;
+    if let Err(e) = systemd::keep_listener_across_exec() {
+        warn!("socket passed by systemd will not survive the update: {e}");
+    }
+    let err = std::process::Command::new(&exe)
+        .args(std::env::args_os().skip(1))
+        .exec();
+    error!("update to {} failed: {err}", exe.display());
+    pid::write_pid_file(paths).await?;
+    Ok(())
+}
+
+/// Why the daemon cannot be replaced by `exe` right now, if it cannot.
+fn update_blockers(table: &ProcessTable, exe: &Path) -> Result<(), String> {
+    if !exe.is_file() {
+        return Err(format!("{} is not a file", exe.display()));
+    }
+    nix::unistd::access(exe, nix::unistd::AccessFlags::X_OK)
+        .map_err(|e| format!("cannot execute {}: {e}", exe.display()))?;
+
+    // A terminal or a stdin pipe belongs to this daemon and closes with it
+    let mut held: Vec<&str> = table
+        .values()
+        .filter(|m| m.pid.is_some() && (m.identity.is_none() || m.stdin.is_some()))
+        .map(|m| m.name.as_str())
+        .collect();
+    if held.is_empty() {
+        return Ok(());
+    }
+    held.sort_unstable();
+    Err(format!(
+        "cannot hand over {}: processes with tty = true or stdin = \"pipe\" would lose their \
+         terminal or stdin; stop them first",
+        held.join(", ")
+    ))
+}
+
+// ---------------------------------------------------------------------------
+// Daemon settings
+// ---------------------------------------------------------------------------
+
+fn handle_config_get(key: Option<String>, current: &DaemonSettings) -> Response {
+    let keys = match key {
+        Some(key) => match settings::find_key(&key) {
+            Ok(setting) => vec![setting],
+            Err(e) => {
+                return Response::Error {
+                    message: e.to_string(),
+                };
+            }
+        },
+        None => settings::KEYS.iter().collect(),
+    };
+    let mut infos = Vec::with_capacity(keys.len());
+    for setting in keys {
+        match settings::get(current, setting.key) {
+            Ok(value) => infos.push(SettingInfo {
+                key: setting.key.to_string(),
+                value,
+                default: setting.default.to_string(),
+            }),
+            Err(e) => {
+                return Response::Error {
+                    message: e.to_string(),
+                };
+            }
+        }
+    }
+    Response::Config { settings: infos }
+}
+
+async fn handle_config_set(
+    key: String,
+    value: Option<String>,
+    paths: &Paths,
+    settings: &RwLock<DaemonSettings>,
+) -> Response {
+    // Held while the file is rewritten, so concurrent sets cannot undo each
+    // other
+    let mut current = settings.write().await;
+
+    let path = paths.settings_file();
+    let content = match fs::read_to_string(&path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Response::Error {
+                message: format!("failed to read {}: {e}", path.display()),
+            };
+        }
+    };
+    let (content, updated) = match settings::set(&content, &key, value.as_deref()) {
+        Ok(set) => set,
+        Err(e) => {
+            return Response::Error {
+                message: e.to_string(),
+            };
+        }
+    };
+    if let Err(e) = fs::write(&path, content).await {
+        return Response::Error {
+            message: format!("failed to write {}: {e}", path.display()),
+        };
+    }
+    info!(key, ?value, "daemon setting changed");
+
+    let live = settings::find_key(&key).is_ok_and(|setting| setting.live);
+    *current = updated;
+    let change = match value {
+        Some(value) => format!("{key} = {value}"),
+        None => format!("{key} unset"),
+    };
+    Response::Success {
+        message: Some(if live {
+            change
+        } else {
+            format!("{change} (takes effect when the daemon restarts, e.g. after `pm3 update`)")
+        }),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Resource sampling and memory limits
+// ---------------------------------------------------------------------------
+
+/// Every `every`, sample resource usage for every running process tree,
+/// restart any process that has grown past its `max_memory` or `fd_limit`,
+/// and check
+/// `[name.alert]` thresholds. Every `persist_every` the latest samples are
+/// also written to storage.
+async fn run_resource_sampler(
+    processes: Processes,
+    paths: Paths,
+    services: Services,
+    every: Duration,
+    persist_every: Duration,
+) {
+    let mut interval = tokio::time::interval(every);
+    let mut last_persist = Instant::now();
+    // Per process: the run being watched and how long it has been over
+    let mut watches: HashMap<String, (u32, alert::Watch)> = HashMap::new();
+    loop {
+        interval.tick().await;
+
+        let running: Vec<(String, u32, Option<u64>)> = {
+            let table = processes.snapshot().await;
+            table
+                .values()
+                .filter_map(|m| {
+                    let limit = m
+                        .config
+                        .max_memory
+                        .as_deref()
+                        .and_then(|s| config::parse_memory(s).ok());
+                    Some((m.name.clone(), m.pid?, limit))
+                })
+                .collect()
+        };
+        watches.retain(|name, (pid, _)| running.iter().any(|(n, p, _)| n == name && p == pid));
+        if running.is_empty() {
+            continue;
+        }
+
+        let pids: Vec<u32> = running.iter().map(|(_, pid, _)| *pid).collect();
+        let samples = tokio::task::spawn_blocking(move || {
+            let tree = stats::ProcessTree::read();
+            pids.into_iter()
+                .map(|pid| stats::sample_tree(&tree, pid))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+
+        let persist = last_persist.elapsed() >= persist_every;
+        let mut persisted = Vec::new();
+        let mut over_limit = Vec::new();
+        let mut alerts = Vec::new();
+        let mut health_events = Vec::new();
+        let mut unhealthy = Vec::new();
+        for ((name, pid, limit), sample) in running.into_iter().zip(samples) {
+            let observed = processes
+                .with(&name, move |managed| observe_sample(managed, pid, sample))
+                .await
+                .flatten();
+            let Some(observed) = observed else {
+                continue;
+            };
+            health_events.extend(observed.health_events);
+            unhealthy.extend(observed.unhealthy);
+            if let Some(sample) = sample
+                && persist
+            {
+                persisted.push(protocol::StatsSample {
+                    name: name.clone(),
+                    timestamp: chrono::Utc::now().timestamp_millis(),
+                    rss_bytes: sample.rss_bytes,
+                    cpu_time_ms: sample.cpu_time_ms,
+                    fd_count: sample.fd_count,
+                });
+            }
+            if let (Some(sample), Some(rules)) = (sample, &observed.alert) {
+                let (_, watch) = watches
+                    .entry(name.clone())
+                    .or_insert_with(|| (pid, alert::Watch::default()));
+                for firing in
+                    watch.observe(rules, observed.cpu_percent, sample.rss_bytes, clock::now())
+                {
+                    alerts.push((name.clone(), pid, firing));
+                }
+            }
+            if let (Some(sample), Some(limit)) = (sample, limit)
+                && sample.rss_bytes > limit
+            {
+                over_limit.push((name, pid, Limit::Memory(sample.rss_bytes)));
+            } else if let (Some(sample), Some(limit)) = (sample, observed.fd_limit)
+                && sample.fd_count > limit
+            {
+                over_limit.push((name, pid, Limit::Fds(sample.fd_count)));
+            }
+        }
+
+        if persist {
+            last_persist = Instant::now();
+            if let Err(e) = storage::record_samples(&services.storage, persisted).await {
+                error!("failed to persist resource samples: {e}");
+            }
+        }
+
+        for (hook_log, message) in health_events {
+            let _ = log::append_event(&hook_log, &message).await;
+        }
+        for event in unhealthy {
+            process::record_event(&paths, &services, event).await;
+        }
+
+        for (name, pid, over) in over_limit {
+            restart_over_limit(&name, pid, over, &processes, &paths, &services).await;
+        }
+        for (name, pid, firing) in alerts {
+            raise_alert(&name, pid, firing, &processes, &paths, &services).await;
+        }
+    }
+}
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+const LOG_BUDGET_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keep the log directory under `logs.total_max`, once at startup and then
+/// every minute. The logs processes are writing to stay, as do those of
+/// processes in the dump, which `pm3 resurrect` brings back.
+async fn run_log_budget(processes: Processes, paths: Paths, settings: Arc<RwLock<DaemonSettings>>) {
+    let mut interval = tokio::time::interval(LOG_BUDGET_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Ok(Some(max)) = settings.read().await.logs.total_max() else {
+            continue;
+        };
+
+        let mut keep = HashSet::new();
+        for managed in processes.snapshot().await.values() {
+            keep.insert(managed.stdout_log(&paths));
+            keep.insert(managed.stderr_log(&paths));
+        }
+        if let Ok(saved) = dump::read(&paths.dump_file()) {
+            for dumped in &saved.processes {
+                let (stdout, stderr) =
+                    process::log_paths(&dumped.name, &dumped.config, dumped.generation, &paths);
+                keep.extend([stdout, stderr]);
+            }
+        }
+
+        let log_dir = paths.log_dir();
+        let pass = {
+            let log_dir = log_dir.clone();
+            tokio::task::spawn_blocking(move || log::prune_to_budget(&log_dir, &keep, max)).await
+        };
+        match pass {
+            Ok(Ok(pass)) => {
+                for (path, size) in &pass.pruned {
+                    info!(
+                        "pruned {} ({size} bytes) to keep logs under logs.total_max",
+                        path.display()
+                    );
+                }
+                if pass.used > max {
+                    warn!(
+                        "logs take {} bytes, over logs.total_max ({max}), with nothing left to prune",
+                        pass.used
+                    );
+                }
+                log::record_pruned(&log_dir, &pass);
+            }
+            Ok(Err(e)) => error!("failed to prune logs: {e}"),
+            Err(e) => error!("failed to prune logs: {e}"),
+        }
+    }
+}
+
+/// Drop stored runs and samples older than `retention`, once at startup and
+/// then hourly.
+async fn run_pruner(storage: Arc<dyn Storage>, retention: Duration) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let before = chrono::Utc::now().timestamp_millis() - retention.as_millis() as i64;
+        if let Err(e) = storage::prune(&storage, before).await {
+            error!("failed to prune storage: {e}");
+        }
+    }
+}
+
+/// What the resource sampler learned from a process's actor about the run
+/// it sampled.
+struct Observed {
+    health_events: Vec<(PathBuf, String)>,
+    unhealthy: Vec<ProcessEvent>,
+    cpu_percent: Option<f64>,
+    alert: Option<config::Alerts>,
+    fd_limit: Option<u32>,
+}
+
+/// Record `sample` of run `pid` on the process and update its heartbeat and
+/// policy health. `None` if the run has been replaced or stopped.
+fn observe_sample(
+    managed: &mut ProcessState,
+    pid: u32,
+    sample: Option<stats::ResourceSample>,
+) -> Option<Observed> {
+    if managed.pid != Some(pid) {
+        return None;
+    }
+    let mut health_events = Vec::new();
+    let mut unhealthy = Vec::new();
+    if let Some(sample) = sample {
+        managed.run.record_sample(sample);
+    }
+    managed.memory_bytes = sample.map(|s| s.rss_bytes);
+    if let Some(status) = process::heartbeat_status(managed) {
+        managed.status = status;
+        managed.heartbeat_lost = status == ProcessStatus::Unhealthy;
+        let message = if managed.heartbeat_lost {
+            unhealthy.push(unhealthy_event(managed, "heartbeat"));
+            "no heartbeat on the control socket, marked unhealthy"
+        } else {
+            "heartbeats resumed"
+        };
+        health_events.push((managed.hook_log.clone(), message.to_string()));
+    }
+    if let Some(sample) = sample
+        && managed.config.policy.is_some()
+    {
+        match process::policy_health_status(managed, sample) {
+            Ok(Some(status)) => {
+                managed.status = status;
+                if status == ProcessStatus::Unhealthy {
+                    unhealthy.push(unhealthy_event(managed, "policy"));
                 }
-                requested.clone()
+                health_events.push((
+                    managed.hook_log.clone(),
+                    format!("policy health check: {status}"),
+                ));
             }
-            None => table.keys().cloned().collect(),
-        };
+            Ok(None) => {}
+            Err(e) => warn!("{}: {e}", managed.name),
+        }
+    }
+    Some(Observed {
+        health_events,
+        unhealthy,
+        cpu_percent: managed.run.cpu_percent(),
+        alert: managed.config.alert.clone(),
+        fd_limit: managed.config.fd_limit,
+    })
+}
 
-        for name in &targets {
-            let managed = table.get_mut(name).unwrap();
-            let config = managed.config.clone();
-            let old_restarts = managed.restarts;
+fn unhealthy_event(managed: &ProcessState, check: &str) -> ProcessEvent {
+    ProcessEvent {
+        pid: managed.pid,
+        status: Some(ProcessStatus::Unhealthy),
+        detail: Some(check.to_string()),
+        ..ProcessEvent::new(EventKind::Unhealthy, &managed.name)
+    }
+}
 
-            if managed.status != protocol::ProcessStatus::Stopped
-                && let Err(e) = managed.graceful_stop().await
-            {
-                return Response::Error {
-                    message: format!("failed to stop '{}': {}", name, e),
-                };
+/// A limit a sample found a process over.
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    /// `max_memory`, with the resident memory sampled.
+    Memory(u64),
+    /// `fd_limit`, with the open descriptors counted.
+    Fds(u32),
+}
+
+async fn restart_over_limit(
+    name: &str,
+    pid: u32,
+    over: Limit,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) {
+    let (what, kind) = match over {
+        Limit::Memory(_) => ("memory limit exceeded", EventKind::MemoryLimit),
+        Limit::Fds(_) => ("fd limit exceeded", EventKind::FdLimit),
+    };
+    let paths_for_log = paths.clone();
+    let begun = processes
+        .with(name, move |managed| {
+            // Over the limit again within `restart_window`; a later sample
+            // restarts it once the window has passed
+            if managed.pid != Some(pid) || managed.restart_throttle().is_some() {
+                return None;
             }
 
-            match process::spawn_process(name.clone(), config, paths).await {
-                Ok((mut new_managed, child)) => {
-                    new_managed.restarts = old_restarts + 1;
-                    let pid = new_managed.pid;
-                    let shutdown_rx = new_managed
-                        .monitor_shutdown
-                        .as_ref()
-                        .map(|tx| tx.subscribe())
-                        .unwrap();
-                    table.insert(name.clone(), new_managed);
-                    children_to_monitor.push((name.clone(), child, pid, shutdown_rx));
-                    restarted.push(name.clone());
-                }
-                Err(e) => {
-                    return Response::Error {
-                        message: format!("failed to restart '{}': {}", name, e),
-                    };
+            let usage = match over {
+                Limit::Memory(rss) => format!(
+                    "{:.1}M > {}",
+                    rss as f64 / (1024.0 * 1024.0),
+                    managed.config.max_memory.as_deref().unwrap_or_default()
+                ),
+                Limit::Fds(count) => {
+                    format!("{count} > {}", managed.config.fd_limit.unwrap_or_default())
                 }
+            };
+            let stderr_log = managed.stderr_log(&paths_for_log);
+            Some((usage, stderr_log, begin_respawn(managed)))
+        })
+        .await
+        .flatten();
+    let Some((usage, stderr_log, stopper)) = begun else {
+        return;
+    };
+    let message = format!("{what} ({usage}), restarting");
+    warn!("{name}: {message}");
+    let _ = log::append_event(&stderr_log, &message).await;
+    let stopper = match stopper {
+        Ok(stopper) => stopper,
+        Err(message) => {
+            error!("{message}");
+            return;
+        }
+    };
+    process::record_event(
+        paths,
+        services,
+        ProcessEvent {
+            pid: Some(pid),
+            detail: Some(usage),
+            ..ProcessEvent::new(kind, name)
+        },
+    )
+    .await;
+
+    match respawn(name, what, stopper, processes, paths, services).await {
+        Ok(Some(_)) if matches!(over, Limit::Memory(_)) => {
+            processes
+                .with(name, |managed| managed.memory_restarts += 1)
+                .await;
+        }
+        Ok(_) => {}
+        Err(message) => error!("{message}"),
+    }
+}
+
+/// Record an `alert` event for a threshold `name` stayed over and, with
+/// `action = "restart"`, restart it unless `restart_window` holds it back.
+async fn raise_alert(
+    name: &str,
+    pid: u32,
+    firing: alert::Firing,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) {
+    let restart = firing.action == AlertAction::Restart;
+    let paths_for_log = paths.clone();
+    let begun = processes
+        .with(name, move |managed| {
+            if managed.pid != Some(pid) {
+                return None;
             }
+            let restart = restart && managed.restart_throttle().is_none();
+            let stopper = restart.then(|| begin_respawn(managed));
+            Some((managed.stderr_log(&paths_for_log), stopper))
+        })
+        .await
+        .flatten();
+    let Some((stderr_log, stopper)) = begun else {
+        return;
+    };
+    let message = if stopper.is_some() {
+        format!("alert: {}, restarting", firing.detail)
+    } else {
+        format!("alert: {}", firing.detail)
+    };
+    warn!("{name}: {message}");
+    let _ = log::append_event(&stderr_log, &message).await;
+    let stopper = match stopper {
+        Some(Ok(stopper)) => Some(stopper),
+        Some(Err(message)) => {
+            error!("{message}");
+            None
         }
+        None => None,
+    };
+    process::record_event(
+        paths,
+        services,
+        ProcessEvent {
+            pid: Some(pid),
+            detail: Some(firing.detail),
+            ..ProcessEvent::new(EventKind::Alert, name)
+        },
+    )
+    .await;
+
+    if let Some(stopper) = stopper
+        && let Err(message) = respawn(name, "alert", stopper, processes, paths, services).await
+    {
+        error!("{message}");
     }
+}
 
-    // Spawn monitors outside the lock
-    for (name, child, pid, shutdown_rx) in children_to_monitor {
-        process::spawn_monitor(
-            name,
-            child,
-            pid,
-            Arc::clone(processes),
-            paths.clone(),
-            shutdown_rx,
-        );
+// ---------------------------------------------------------------------------
+// Cron schedules
+// ---------------------------------------------------------------------------
+
+/// Longest stretch of missed seconds the scheduler catches up on after a late
+/// wake-up (e.g. the machine was suspended).
+const CRON_CATCH_UP_SECS: i64 = 60;
+
+/// Fire `cron_restart` schedules in local time. Each pass checks every second
+/// since the previous one, so a slow wake-up does not drop a tick.
+async fn run_cron_scheduler(processes: Processes, paths: Paths, services: Services) {
+    let mut schedules: HashMap<String, Option<cron::Schedule>> = HashMap::new();
+    let mut last_checked = clock::now_local().timestamp();
+    // Virtual time jumps ahead on purpose; every second it skips counts
+    let catch_up = if clock::is_virtual() {
+        i64::MAX / 2
+    } else {
+        CRON_CATCH_UP_SECS
+    };
+    loop {
+        // Wake just after the next second boundary
+        let into_second = clock::now_local().timestamp_subsec_millis() as u64;
+        clock::sleep(Duration::from_millis(1005 - into_second.min(1000))).await;
+
+        let now = clock::now_local().timestamp();
+        let seconds = (last_checked + 1).max(now - catch_up + 1)..=now;
+        last_checked = now;
+
+        let due: Vec<String> = {
+            let table = processes.snapshot().await;
+            table
+                .values()
+                .filter(|managed| {
+                    let Some(expr) = managed.config.cron_restart.as_deref() else {
+                        return false;
+                    };
+                    let schedule = schedules
+                        .entry(expr.to_string())
+                        .or_insert_with(|| expr.parse().ok());
+                    schedule.as_ref().is_some_and(|schedule| {
+                        seconds.clone().any(|second| {
+                            chrono::DateTime::from_timestamp(second, 0)
+                                .is_some_and(|t| schedule.matches(&t.with_timezone(&chrono::Local)))
+                        })
+                    })
+                })
+                .map(|managed| managed.name.clone())
+                .collect()
+        };
+        for name in due {
+            fire_cron_run(&name, &processes, &paths, &services).await;
+        }
     }
+}
 
-    Response::Success {
-        message: Some(format!("restarted: {}", restarted.join(", "))),
+/// Start a scheduled run of `name`. While the previous run is still going,
+/// its `overlap_policy` decides whether to replace it, skip this run or queue
+/// it; skipped and queued runs are recorded in history. Processes stopped
+/// with `pm3 stop` are left alone.
+async fn fire_cron_run(name: &str, processes: &Processes, paths: &Paths, services: &Services) {
+    let decided = processes
+        .with(name, |managed| {
+            if managed
+                .monitor_shutdown
+                .as_ref()
+                .is_some_and(|tx| *tx.borrow())
+            {
+                return None;
+            }
+
+            let running = managed.pid.is_some() || managed.status == ProcessStatus::Starting;
+            let reason = match managed.config.overlap_policy.unwrap_or_default() {
+                _ if !running => None,
+                OverlapPolicy::KillPrevious => None,
+                OverlapPolicy::Skip => Some(RunReason::Skipped),
+                OverlapPolicy::Queue => {
+                    managed.queued_runs += 1;
+                    Some(RunReason::Queued)
+                }
+            };
+            Some(match reason {
+                Some(reason) => {
+                    let now = clock::now_utc().timestamp_millis();
+                    let record = RunRecord {
+                        name: managed.name.clone(),
+                        generation: managed.generation,
+                        started_at: now,
+                        ended_at: now,
+                        status: managed.status,
+                        exit_code: None,
+                        reason: Some(reason),
+                        snapshot: Default::default(),
+                    };
+                    (None, Some(record))
+                }
+                None => match begin_respawn(managed) {
+                    Ok(stopper) => (Some(stopper), None),
+                    Err(message) => {
+                        error!("{message}");
+                        (None, None)
+                    }
+                },
+            })
+        })
+        .await
+        .flatten();
+    let Some((replace, held_back)) = decided else {
+        return;
+    };
+
+    if let Some(stopper) = replace
+        && let Err(message) =
+            respawn(name, "cron schedule", stopper, processes, paths, services).await
+    {
+        error!("{message}");
+    }
+    if let Some(record) = held_back
+        && let Err(e) = storage::record_run(&services.storage, record).await
+    {
+        error!("failed to record history for '{name}': {e}");
+    }
+}
+
+async fn handle_history(
+    name: Option<String>,
+    since: Option<i64>,
+    storage: &Arc<dyn Storage>,
+) -> Response {
+    match storage::runs(storage, name, since).await {
+        Ok(runs) => Response::History { runs },
+        Err(e) => Response::Error {
+            message: format!("failed to read history: {e}"),
+        },
     }
 }
 
 async fn handle_flush(
     names: Option<Vec<String>>,
-    processes: &Arc<RwLock<ProcessTable>>,
+    processes: &Processes,
     paths: &Paths,
 ) -> Response {
-    let table = processes.read().await;
+    let table = processes.snapshot().await;
 
     let targets: Vec<String> = match names {
         Some(ref requested) => {
@@ -392,89 +3343,292 @@ async fn handle_flush(
         None => table.keys().cloned().collect(),
     };
 
-    drop(table);
+    let log_files: Vec<_> = targets
+        .iter()
+        .map(|name| {
+            let managed = &table[name];
+            // Earlier generations' logs are only read back through rotation
+            // history, so they go with it
+            let earlier: Vec<_> = if managed.config.log_per_generation == Some(true) {
+                (0..managed.generation)
+                    .flat_map(|generation| {
+                        [
+                            paths.generation_stdout_log(name, generation),
+                            paths.generation_stderr_log(name, generation),
+                        ]
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            (
+                name,
+                managed.stdout_log(paths),
+                managed.stderr_log(paths),
+                earlier,
+            )
+        })
+        .collect();
 
-    for name in &targets {
-        // Truncate main log files
-        let stdout_path = paths.stdout_log(name);
-        let stderr_path = paths.stderr_log(name);
+    let mut freed = 0;
+    for (name, stdout_path, stderr_path, earlier) in &log_files {
+        // Truncate main log files. The writers append, so they carry on at
+        // the new end, and notice the smaller size before rotating.
+        for (stream, path) in [("stdout", stdout_path), ("stderr", stderr_path)] {
+            let Ok(meta) = fs::metadata(path).await else {
+                continue;
+            };
+            if let Err(e) = fs::write(path, b"").await {
+                return Response::Error {
+                    message: format!("failed to truncate {stream} log for '{name}': {e}"),
+                };
+            }
+            freed += meta.len();
+        }
 
-        if stdout_path.exists()
-            && let Err(e) = fs::write(&stdout_path, b"").await
-        {
+        // Delete rotated files, numbered or dated, and earlier generations
+        let mut stale = Vec::new();
+        for path in [stdout_path, stderr_path].into_iter().chain(earlier) {
+            stale.extend(log::rotated_files(path).unwrap_or_default());
+        }
+        stale.extend(earlier.iter().cloned());
+        for path in stale {
+            if let Ok(meta) = fs::metadata(&path).await
+                && fs::remove_file(&path).await.is_ok()
+            {
+                freed += meta.len();
+            }
+        }
+    }
+
+    Response::Success {
+        message: Some(format!(
+            "flushed logs: {} ({freed} bytes freed)",
+            targets.join(", ")
+        )),
+    }
+}
+
+/// List, and with `apply` remove, what processes no longer in the table
+/// left behind: log files (including earlier generations' logs of ones that
+/// are) and their entries in the dump.
+async fn handle_prune(apply: bool, processes: &Processes, paths: &Paths) -> Response {
+    let _saving = DUMP_LOCK.lock().await;
+    let table = processes.snapshot().await;
+
+    // Current logs and their rotation history stay
+    let mut keep = HashSet::new();
+    for managed in table.values() {
+        for path in [managed.stdout_log(paths), managed.stderr_log(paths)] {
+            keep.extend(log::rotated_files(&path).unwrap_or_default());
+            keep.insert(path);
+        }
+    }
+    let stale_logs = match log::files_except(&paths.log_dir(), &keep) {
+        Ok(files) => files,
+        Err(e) => {
             return Response::Error {
-                message: format!("failed to truncate stdout log for '{}': {}", name, e),
+                message: format!("failed to read {}: {e}", paths.log_dir().display()),
             };
         }
-        if stderr_path.exists()
-            && let Err(e) = fs::write(&stderr_path, b"").await
-        {
+    };
+
+    let dump_path = paths.dump_file();
+    let mut saved = match dump_path.exists() {
+        true => match dump::read(&dump_path) {
+            Ok(saved) => Some(saved),
+            Err(e) => {
+                return Response::Error {
+                    message: format!("failed to read {}: {e}", dump_path.display()),
+                };
+            }
+        },
+        false => None,
+    };
+    let stale_entries: Vec<String> = saved
+        .iter()
+        .flat_map(|saved| &saved.processes)
+        .filter(|dumped| !table.contains_key(&dumped.name))
+        .map(|dumped| dumped.name.clone())
+        .collect();
+
+    if stale_logs.is_empty() && stale_entries.is_empty() {
+        return Response::Success {
+            message: Some("nothing to prune".to_string()),
+        };
+    }
+
+    let mut lines = Vec::new();
+    let mut freed = 0;
+    for (path, size) in &stale_logs {
+        if apply && let Err(e) = fs::remove_file(path).await {
             return Response::Error {
-                message: format!("failed to truncate stderr log for '{}': {}", name, e),
+                message: format!("failed to remove {}: {e}", path.display()),
             };
         }
-
-        // Delete rotated files
-        for i in 1..=log::LOG_ROTATION_KEEP {
-            let _ = fs::remove_file(paths.rotated_stdout_log(name, i)).await;
-            let _ = fs::remove_file(paths.rotated_stderr_log(name, i)).await;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        lines.push(format!("  log {name} ({size} bytes)"));
+        freed += size;
+    }
+    for name in &stale_entries {
+        lines.push(format!("  dump entry '{name}'"));
+    }
+    if apply && let Some(mut saved) = saved.take().filter(|_| !stale_entries.is_empty()) {
+        saved
+            .processes
+            .retain(|dumped| !stale_entries.contains(&dumped.name));
+        let written = tokio::task::spawn_blocking(move || dump::write(&dump_path, &saved)).await;
+        if let Err(e) = written
+            .map_err(|e| e.to_string())
+            .and_then(|written| written.map_err(|e| e.to_string()))
+        {
+            return Response::Error {
+                message: format!("failed to rewrite the dump: {e}"),
+            };
         }
     }
 
+    let message = match apply {
+        true => format!("pruned:\n{}\n({freed} bytes freed)", lines.join("\n")),
+        false => format!(
+            "would prune:\n{}\n(run `pm3 prune --yes` to remove them)",
+            lines.join("\n")
+        ),
+    };
     Response::Success {
-        message: Some(format!("flushed logs: {}", targets.join(", "))),
+        message: Some(message),
     }
 }
 
-async fn handle_log(
-    name: Option<String>,
-    lines: usize,
-    follow: bool,
-    processes: &Arc<RwLock<ProcessTable>>,
+/// What a `Log` request asks for.
+pub(crate) struct LogQuery {
+    pub(crate) name: Option<String>,
+    pub(crate) group: Option<String>,
+    pub(crate) lines: usize,
+    pub(crate) follow: bool,
+    pub(crate) timestamps: bool,
+    pub(crate) grep: Option<String>,
+    pub(crate) level: Option<LogLevel>,
+    pub(crate) since: Option<i64>,
+    pub(crate) stream: Option<LogStream>,
+    pub(crate) color: bool,
+}
+
+/// The responses a `Log` request without `follow` gets, for the HTTP API:
+/// its lines, or an error.
+pub(crate) async fn log_lines(
+    query: LogQuery,
+    processes: &Processes,
+    paths: &Paths,
+) -> color_eyre::Result<Vec<Response>> {
+    let mut out = Vec::new();
+    let query = LogQuery {
+        follow: false,
+        ..query
+    };
+    handle_log(query, processes, paths, &mut out).await?;
+    let mut responses = Vec::new();
+    for line in String::from_utf8_lossy(&out).lines() {
+        responses.push(protocol::decode_response(line)?);
+    }
+    Ok(responses)
+}
+
+/// Answer a `Log` request on `writer`; with `follow`, keep going until a
+/// write fails or every followed process is deleted.
+pub(crate) async fn handle_log(
+    query: LogQuery,
+    processes: &Processes,
     paths: &Paths,
     writer: &mut (impl AsyncWriteExt + Unpin),
 ) -> color_eyre::Result<()> {
-    let table = processes.read().await;
-
-    // Determine which processes to show logs for
-    let targets: Vec<String> = match name {
-        Some(ref n) => {
-            if !table.contains_key(n) {
-                let resp = Response::Error {
-                    message: format!("process not found: {n}"),
-                };
-                let encoded = protocol::encode_response(&resp)?;
-                writer.write_all(&encoded).await?;
-                return Ok(());
-            }
-            vec![n.clone()]
+    let LogQuery {
+        name,
+        group,
+        lines,
+        follow,
+        timestamps,
+        grep,
+        level,
+        since,
+        stream,
+        color,
+    } = query;
+    let table = processes.snapshot().await;
+    let named = name.is_some();
+
+    // Determine which processes to show logs for, and which of their lines
+    let selected = resolve_targets(&table, name.map(|n| vec![n]), group).and_then(|targets| {
+        if stream == Some(LogStream::Stderr)
+            && let Some(merged) = targets
+                .iter()
+                .find(|target| table[*target].config.merges_logs())
+        {
+            return Err(format!(
+                "'{merged}' writes both streams to one log (merge_logs), so it has no stderr log"
+            ));
+        }
+        let filters = line_filters(&table, &targets, grep.as_deref(), level, since)?;
+        Ok((targets, filters))
+    });
+    let (targets, filters) = match selected {
+        Ok(selected) => selected,
+        Err(message) => {
+            let encoded = protocol::encode_response(&Response::Error { message })?;
+            writer.write_all(&encoded).await?;
+            return Ok(());
         }
-        None => table.keys().cloned().collect(),
     };
 
-    let multi = targets.len() > 1;
-
-    // Send tail lines
-    for target in &targets {
-        let stdout_lines = log::tail_file(&paths.stdout_log(target), lines).unwrap_or_default();
-        let stderr_lines = log::tail_file(&paths.stderr_log(target), lines).unwrap_or_default();
+    // Lines say which process wrote them unless the request named it
+    let label = |target: &String| (!named).then(|| target.clone());
+    // Escapes only reach the logs of processes with `log_strip_ansi = false`
+    let text = |line: String| match color {
+        true => line,
+        false => log::strip_ansi(&line).into_owned(),
+    };
 
-        // Interleave stdout and stderr (stdout first, then stderr for simplicity)
-        for line in stdout_lines {
-            let resp = Response::LogLine {
-                name: if multi { Some(target.clone()) } else { None },
-                line,
-            };
-            let encoded = protocol::encode_response(&resp)?;
-            writer.write_all(&encoded).await?;
-        }
-        for line in stderr_lines {
-            let resp = Response::LogLine {
-                name: if multi { Some(target.clone()) } else { None },
-                line,
+    // Send tail lines, keeping a follower per log that picks up right after
+    let mut followed = Vec::new();
+    for (target, filter) in targets.iter().zip(filters) {
+        let managed = &table[target];
+        // Lines already stamped by `log_date_format` don't need another
+        let stamp = timestamps && managed.config.log_date_format.is_none();
+        // Interleave stdout and stderr (stdout first, then stderr for
+        // simplicity); a merged log already holds both
+        let streams: &[bool] = match stream {
+            None if managed.config.merges_logs() => &[false],
+            None => &[false, true],
+            Some(LogStream::Stdout) => &[false],
+            Some(LogStream::Stderr) => &[true],
+        };
+        for &stderr in streams {
+            let line_stream = match (managed.config.merges_logs(), stderr) {
+                (true, _) => None,
+                (false, false) => Some(LogStream::Stdout),
+                (false, true) => Some(LogStream::Stderr),
             };
-            let encoded = protocol::encode_response(&resp)?;
-            writer.write_all(&encoded).await?;
+            let path = followed_log(managed, stderr, paths);
+            let (tail, follower) = log::LogFollower::tail(path.clone(), lines, &filter)
+                .unwrap_or_else(|_| (Vec::new(), log::LogFollower::new(path)));
+            for line in tail {
+                let resp = Response::LogLine {
+                    name: label(target),
+                    line: text(line),
+                    stream: line_stream,
+                    timestamp: None,
+                };
+                let encoded = protocol::encode_response(&resp)?;
+                writer.write_all(&encoded).await?;
+            }
+            followed.push(FollowedLog {
+                target: target.clone(),
+                stderr,
+                stream: line_stream,
+                stamp,
+                filter: filter.clone(),
+                follower,
+            });
         }
     }
 
@@ -482,51 +3636,423 @@ async fn handle_log(
         return Ok(());
     }
 
-    // Subscribe to broadcasters for follow mode
-    let mut receivers = Vec::new();
-    for target in &targets {
-        if let Some(managed) = table.get(target) {
-            let rx = managed.log_broadcaster.subscribe();
-            receivers.push((target.clone(), rx));
+    writer.flush().await?;
+
+    // Follow loop: poll every log, moving on to the new file whenever one is
+    // rotated, truncated or replaced by a restart's per-generation log
+    loop {
+        let mut responses = Vec::new();
+        {
+            let table = processes.snapshot().await;
+            followed.retain(|log| table.contains_key(&log.target));
+            if followed.is_empty() {
+                return Ok(()); // Every followed process was deleted
+            }
+            for log in &mut followed {
+                let current = followed_log(&table[&log.target], log.stderr, paths);
+                let read = if log.follower.path() == current {
+                    log.follower.read_lines()
+                } else {
+                    log.follower.set_path(current)
+                };
+                let at = log.stamp.then(|| clock::now_utc().timestamp_millis());
+                let read = read.unwrap_or_default();
+                for line in read.into_iter().filter(|line| log.filter.matches(line)) {
+                    responses.push(Response::LogLine {
+                        name: label(&log.target),
+                        line: text(line),
+                        stream: log.stream,
+                        timestamp: at,
+                    });
+                }
+            }
+        }
+
+        for resp in &responses {
+            let encoded = protocol::encode_response(resp)?;
+            if writer.write_all(&encoded).await.is_err() {
+                return Ok(()); // Client disconnected
+            }
+        }
+
+        if responses.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        if writer.flush().await.is_err() {
+            return Ok(()); // Client disconnected
         }
     }
+}
+
+/// One of a followed process's two logs.
+struct FollowedLog {
+    target: String,
+    stderr: bool,
+    /// The stream its lines come from; unknown for a merged log.
+    stream: Option<LogStream>,
+    stamp: bool,
+    filter: log::LineFilter,
+    follower: log::LogFollower,
+}
+
+/// The `--grep`/`--level`/`--since` filter for each target's lines.
+/// `--since` reads the `log_date_format` stamps, so every target needs one.
+fn line_filters(
+    table: &ProcessTable,
+    targets: &[String],
+    grep: Option<&str>,
+    level: Option<LogLevel>,
+    since: Option<i64>,
+) -> Result<Vec<log::LineFilter>, String> {
+    let grep = grep
+        .map(regex::Regex::new)
+        .transpose()
+        .map_err(|e| format!("invalid --grep pattern: {e}"))?;
+    targets
+        .iter()
+        .map(|target| {
+            let since = match (since, &table[target].config.log_date_format) {
+                (Some(since), Some(format)) => Some((since, format.clone())),
+                (Some(_), None) => {
+                    return Err(format!(
+                        "--since needs a log_date_format to read line times from, and '{target}' has none"
+                    ));
+                }
+                (None, _) => None,
+            };
+            Ok(log::LineFilter {
+                grep: grep.clone(),
+                level,
+                since,
+            })
+        })
+        .collect()
+}
+
+fn followed_log(managed: &ProcessState, stderr: bool, paths: &Paths) -> PathBuf {
+    if stderr {
+        managed.stderr_log(paths)
+    } else {
+        managed.stdout_log(paths)
+    }
+}
 
-    // Drop read lock before entering follow loop
-    drop(table);
+/// How often an attach session checks whether the run it is attached to
+/// has ended.
+const ATTACH_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The stdin feed of `name`'s current run, with the generation of that run.
+fn stdin_of(
+    table: &ProcessTable,
+    name: &str,
+) -> Result<(tokio::sync::mpsc::Sender<Vec<u8>>, u64), String> {
+    let Some(managed) = table.get(name) else {
+        return Err(format!("process not found: {name}"));
+    };
+    if managed.pid.is_none() {
+        return Err(format!("process '{name}' is not running"));
+    }
+    match managed.stdin {
+        Some(ref stdin) if !stdin.is_closed() => Ok((stdin.clone(), managed.generation)),
+        Some(_) => Err(format!("process '{name}' has closed its stdin")),
+        None => Err(format!(
+            "process '{name}' has no stdin to write to; set stdin = \"pipe\""
+        )),
+    }
+}
 
+async fn handle_attach(
+    name: String,
+    mut reader: ClientReader,
+    max_line: usize,
+    processes: &Processes,
+    writer: &mut (impl AsyncWriteExt + Unpin),
+) -> color_eyre::Result<()> {
+    let (stdin, generation, mut output) = {
+        let table = processes.snapshot().await;
+        match stdin_of(&table, &name) {
+            Ok((stdin, generation)) => {
+                (stdin, generation, table[&name].log_broadcaster.subscribe())
+            }
+            Err(message) => {
+                let encoded = protocol::encode_response(&Response::Error { message })?;
+                writer.write_all(&encoded).await?;
+                return Ok(());
+            }
+        }
+    };
+    let attached = Response::Success {
+        message: Some(format!("attached to {name}")),
+    };
+    writer
+        .write_all(&protocol::encode_response(&attached)?)
+        .await?;
     writer.flush().await?;
 
-    // Follow loop: receive from all broadcasters
+    // Input arrives on its own task so a half-read line is never dropped by
+    // the select below
+    let mut input = tokio::spawn(async move {
+        while let Ok(Some(line)) = read_limited_line(&mut reader, max_line).await {
+            let Ok(Request::Input { data, .. }) = protocol::decode_request(&line) else {
+                continue;
+            };
+            if stdin.send(data.into_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut check = tokio::time::interval(ATTACH_CHECK_INTERVAL);
     loop {
-        // Use a simple polling approach across receivers
-        let mut any_received = false;
-        for (target, rx) in &mut receivers {
-            match rx.try_recv() {
+        tokio::select! {
+            // The client detached
+            _ = &mut input => return Ok(()),
+            entry = output.recv() => match entry {
                 Ok(entry) => {
                     let resp = Response::LogLine {
-                        name: if multi { Some(target.clone()) } else { None },
+                        name: None,
                         line: entry.line,
+                        stream: Some(entry.stream),
+                        timestamp: None,
                     };
-                    let encoded = protocol::encode_response(&resp)?;
-                    if writer.write_all(&encoded).await.is_err() {
-                        return Ok(()); // Client disconnected
+                    if writer.write_all(&protocol::encode_response(&resp)?).await.is_err()
+                        || writer.flush().await.is_err()
+                    {
+                        break;
                     }
-                    any_received = true;
                 }
-                Err(tokio::sync::broadcast::error::TryRecvError::Empty) => {}
-                Err(tokio::sync::broadcast::error::TryRecvError::Lagged(_)) => {}
-                Err(tokio::sync::broadcast::error::TryRecvError::Closed) => {
-                    return Ok(());
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+            _ = check.tick() => {
+                let table = processes.snapshot().await;
+                let running = table
+                    .get(&name)
+                    .is_some_and(|m| m.generation == generation && m.pid.is_some());
+                if !running {
+                    let ended = Response::Success {
+                        message: Some(format!("{name} exited")),
+                    };
+                    let _ = writer.write_all(&protocol::encode_response(&ended)?).await;
+                    break;
                 }
             }
         }
+    }
+    input.abort();
+    Ok(())
+}
 
-        if !any_received {
-            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+// ---------------------------------------------------------------------------
+// Pipelines
+// ---------------------------------------------------------------------------
+
+/// How often a running pipeline checks whether its tasks have finished.
+const PIPELINE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Run `target` and the tasks its `after` lists lead to. Each task starts
+/// once everything it comes after has succeeded, so independent branches run
+/// side by side; after the first failure nothing new starts and the tasks
+/// left over are skipped. Returns the last response sent.
+async fn handle_pipeline(
+    configs: HashMap<String, ProcessConfig>,
+    target: String,
+    env: Option<String>,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+    writer: &mut (impl AsyncWriteExt + Unpin),
+) -> color_eyre::Result<Response> {
+    let order = match pipeline::plan(&configs, &target) {
+        Ok(order) => order,
+        Err(e) => {
+            let response = Response::Error {
+                message: e.to_string(),
+            };
+            writer
+                .write_all(&protocol::encode_response(&response)?)
+                .await?;
+            return Ok(response);
         }
+    };
 
-        if writer.flush().await.is_err() {
-            return Ok(()); // Client disconnected
+    let mut steps: Vec<PipelineStep> = order
+        .iter()
+        .map(|name| PipelineStep {
+            name: name.clone(),
+            status: StepStatus::Skipped,
+            exit_code: None,
+            duration_ms: None,
+        })
+        .collect();
+    let mut pending: Vec<usize> = (0..steps.len()).collect();
+    let mut running: Vec<(usize, Instant)> = Vec::new();
+    let mut failed = false;
+
+    loop {
+        if !failed {
+            let ready: Vec<usize> = pending
+                .iter()
+                .copied()
+                .filter(|&i| {
+                    configs[&steps[i].name].after.iter().flatten().all(|dep| {
+                        steps
+                            .iter()
+                            .any(|s| &s.name == dep && s.status == StepStatus::Succeeded)
+                    })
+                })
+                .collect();
+            for i in ready {
+                pending.retain(|&p| p != i);
+                let mut config = configs[&steps[i].name].clone();
+                if let Some(env) = &env {
+                    config.apply_environment(env);
+                }
+                match start_task(&steps[i].name, config, processes, paths, services).await {
+                    Ok(()) => {
+                        steps[i].status = StepStatus::Running;
+                        running.push((i, Instant::now()));
+                    }
+                    Err(message) => {
+                        let response = Response::Error { message };
+                        writer
+                            .write_all(&protocol::encode_response(&response)?)
+                            .await?;
+                        steps[i].status = StepStatus::Failed;
+                        failed = true;
+                    }
+                }
+                let response = Response::PipelineStep {
+                    step: steps[i].clone(),
+                };
+                writer
+                    .write_all(&protocol::encode_response(&response)?)
+                    .await?;
+            }
+            writer.flush().await?;
+        }
+
+        if running.is_empty() {
+            break;
+        }
+        tokio::time::sleep(PIPELINE_POLL_INTERVAL).await;
+
+        let table = processes.snapshot().await;
+        let mut finished = Vec::new();
+        running.retain(|&(i, started)| match task_outcome(&table, &steps[i].name) {
+            Some(outcome) => {
+                finished.push((i, started, outcome));
+                false
+            }
+            None => true,
+        });
+
+        for (i, started, (status, exit_code)) in finished {
+            steps[i].status = status;
+            steps[i].exit_code = exit_code;
+            steps[i].duration_ms = Some(started.elapsed().as_millis() as u64);
+            failed |= status == StepStatus::Failed;
+            let response = Response::PipelineStep {
+                step: steps[i].clone(),
+            };
+            writer
+                .write_all(&protocol::encode_response(&response)?)
+                .await?;
+        }
+        writer.flush().await?;
+    }
+
+    for i in pending {
+        let response = Response::PipelineStep {
+            step: steps[i].clone(),
+        };
+        writer
+            .write_all(&protocol::encode_response(&response)?)
+            .await?;
+    }
+    let response = Response::PipelineDone { target, steps };
+    writer
+        .write_all(&protocol::encode_response(&response)?)
+        .await?;
+    Ok(response)
+}
+
+/// Start one run of the task `name`, refusing if it is already running.
+async fn start_task(
+    name: &str,
+    config: ProcessConfig,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) -> Result<(), String> {
+    let running = |existing: &ProcessState| {
+        !matches!(
+            existing.status,
+            ProcessStatus::Stopped
+                | ProcessStatus::Completed
+                | ProcessStatus::Crashed
+                | ProcessStatus::Errored
+        )
+    };
+    let generation = match processes.state(name).await {
+        Some(existing) if running(&existing) => {
+            return Err(format!("task '{name}' is already running"));
         }
+        Some(existing) if existing.config == config => existing.generation,
+        Some(existing) => existing.generation + 1,
+        None => 1,
+    };
+    // Spawned between messages, so a slow pre_start hook holds up no one
+    // else
+    let (mut managed, child) =
+        process::spawn_process(name.to_string(), config, generation, paths, services)
+            .await
+            .map_err(|e| format!("failed to start '{name}': {e}"))?;
+    let monitor = PendingMonitor::new(&mut managed, child);
+    let placed = match processes.insert_new(managed) {
+        Ok(()) => true,
+        Err(managed) => processes
+            .place(*managed, move |existing, _| match running(existing) {
+                true => Err(()),
+                false => Ok(()),
+            })
+            .await
+            .is_ok(),
+    };
+    if !placed {
+        monitor.discard().await;
+        return Err(format!("task '{name}' is already running"));
     }
+    monitor.spawn(processes, paths, services);
+    Ok(())
+}
+
+/// How a started task ended, or `None` while it is still running or waiting
+/// to be restarted. A task stopped by hand did not succeed.
+fn task_outcome(table: &ProcessTable, name: &str) -> Option<(StepStatus, Option<i32>)> {
+    let Some(managed) = table.get(name) else {
+        return Some((StepStatus::Failed, None));
+    };
+    if managed.pid.is_some()
+        || !matches!(
+            managed.status,
+            ProcessStatus::Stopped
+                | ProcessStatus::Completed
+                | ProcessStatus::Crashed
+                | ProcessStatus::Errored
+        )
+    {
+        return None;
+    }
+    let stopped_by_hand = managed
+        .monitor_shutdown
+        .as_ref()
+        .is_some_and(|tx| *tx.borrow());
+    let status = if managed.status == ProcessStatus::Completed && !stopped_by_hand {
+        StepStatus::Succeeded
+    } else {
+        StepStatus::Failed
+    };
+    Some((status, managed.exit_code))
 }
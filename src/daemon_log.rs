@@ -0,0 +1,165 @@
+use crate::log::{LOG_ROTATION_KEEP, LOG_ROTATION_SIZE, rotate_log};
+use crate::paths::Paths;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+
+// ---------------------------------------------------------------------------
+// Daemon log
+// ---------------------------------------------------------------------------
+//
+// The daemon traces what it does (requests, spawns, exits, restart decisions
+// and anything that goes wrong) to `daemon.log` in the data directory, which
+// `pm3 daemon-log` reads back. It rotates like process logs.
+
+/// Sets the most detailed level written, e.g. `debug`; defaults to `info`.
+pub const LEVEL_ENV: &str = "PM3_LOG";
+
+/// How often `pm3 daemon-log --follow` looks for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Send the daemon's tracing to `daemon.log`.
+pub fn init(paths: &Paths) -> color_eyre::Result<()> {
+    let level = match std::env::var(LEVEL_ENV) {
+        Ok(level) => level
+            .parse()
+            .map_err(|_| color_eyre::eyre::eyre!("invalid {LEVEL_ENV}: {level}"))?,
+        Err(_) => LevelFilter::INFO,
+    };
+    std::fs::create_dir_all(paths.data_dir())?;
+    let writer = RotatingFile::open(paths.daemon_log())?;
+    tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_max_level(level)
+        .with_ansi(false)
+        .try_init()
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))
+}
+
+/// A log file that moves itself aside once it reaches `LOG_ROTATION_SIZE`.
+#[derive(Clone)]
+struct RotatingFile(Arc<Mutex<RotatingState>>);
+
+struct RotatingState {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(RotatingFile(Arc::new(Mutex::new(RotatingState {
+            path,
+            file,
+            len,
+        }))))
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if state.len + buf.len() as u64 > LOG_ROTATION_SIZE {
+            rotate_log(&state.path, LOG_ROTATION_KEEP)?;
+            state.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&state.path)?;
+            state.len = 0;
+        }
+        let written = state.file.write(buf)?;
+        state.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .file
+            .flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Viewing
+// ---------------------------------------------------------------------------
+
+/// Print the last `lines` lines of the daemon log, then with `follow` keep
+/// printing what is added until interrupted.
+pub fn show(paths: &Paths, lines: usize, follow: bool) -> color_eyre::Result<()> {
+    let path = paths.daemon_log();
+    if !path.exists() && !follow {
+        color_eyre::eyre::bail!("no daemon log at {}", path.display());
+    }
+    for line in crate::log::tail_file(&path, lines).unwrap_or_default() {
+        println!("{line}");
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut offset = file_len(&path);
+    let mut stdout = io::stdout();
+    loop {
+        std::thread::sleep(FOLLOW_INTERVAL);
+        let len = file_len(&path);
+        // Rotated: the new file starts from the top
+        if len < offset {
+            offset = 0;
+        }
+        if len == offset {
+            continue;
+        }
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut added = Vec::new();
+        file.read_to_end(&mut added)?;
+        offset += added.len() as u64;
+        stdout.write_all(&added)?;
+        stdout.flush()?;
+    }
+}
+
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotating_file_rotates_at_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.log");
+        let mut writer = RotatingFile::open(path.clone()).unwrap();
+        writer.write_all(b"first\n").unwrap();
+        writer.0.lock().unwrap().len = LOG_ROTATION_SIZE;
+        writer.write_all(b"second\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("daemon.log.1")).unwrap(),
+            "first\n"
+        );
+    }
+}
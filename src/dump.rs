@@ -0,0 +1,179 @@
+use crate::config::ProcessConfig;
+use crate::process::ProcessState;
+use crate::protocol::ProcessStatus;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::Path;
+
+// ---------------------------------------------------------------------------
+// Format
+// ---------------------------------------------------------------------------
+
+/// Bumped when the dump layout changes; `read` migrates older versions.
+pub const DUMP_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DumpError {
+    #[error("dump I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid dump file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("dump file has no format version")]
+    MissingVersion,
+    #[error("dump format version {0} is newer than this pm3 supports")]
+    UnsupportedVersion(u64),
+}
+
+/// The process table as written by `pm3 save`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dump {
+    pub version: u32,
+    /// Unix timestamp in milliseconds.
+    pub saved_at: i64,
+    pub processes: Vec<DumpedProcess>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DumpedProcess {
+    pub name: String,
+    /// The config as started, before any `env_<name>` overlay.
+    pub config: ProcessConfig,
+    /// The `env_<name>` section that was active, re-applied on resurrect.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    pub status: ProcessStatus,
+    pub restarts: u32,
+    pub memory_restarts: u32,
+    pub generation: u64,
+}
+
+impl Dump {
+    /// Snapshot `processes`, sorted by name so saves are diffable.
+    pub fn capture<'a>(processes: impl IntoIterator<Item = &'a ProcessState>) -> Self {
+        let mut processes: Vec<DumpedProcess> = processes
+            .into_iter()
+            .map(|managed| DumpedProcess {
+                name: managed.name.clone(),
+                config: managed.config.clone(),
+                environment: managed.config.active_env.clone(),
+                status: managed.status,
+                restarts: managed.restarts,
+                memory_restarts: managed.memory_restarts,
+                generation: managed.generation,
+            })
+            .collect();
+        processes.sort_by(|a, b| a.name.cmp(&b.name));
+        Self {
+            version: DUMP_VERSION,
+            saved_at: chrono::Utc::now().timestamp_millis(),
+            processes,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Reading and writing
+// ---------------------------------------------------------------------------
+
+/// Replace `path` with `dump` so that a crash leaves either the old or the
+/// new file, never a torn one: write a sibling temp file, fsync it, rename
+/// it over the target and fsync the directory.
+pub fn write(path: &Path, dump: &Dump) -> Result<(), DumpError> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp)?;
+        serde_json::to_writer_pretty(&mut file, dump)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        std::fs::rename(&temp, path)?;
+        std::fs::File::open(dir)?.sync_all()?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+pub fn read(path: &Path) -> Result<Dump, DumpError> {
+    let value: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    migrate(value)
+}
+
+/// Bring a dump of any supported version up to `DUMP_VERSION`. Each future
+/// version adds an arm rewriting the previous layout into its own.
+fn migrate(value: serde_json::Value) -> Result<Dump, DumpError> {
+    let version = value
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or(DumpError::MissingVersion)?;
+    match version {
+        1 => Ok(serde_json::from_value(value)?),
+        _ => Err(DumpError::UnsupportedVersion(version)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Dump {
+        Dump {
+            version: DUMP_VERSION,
+            saved_at: 1_700_000_000_000,
+            processes: vec![DumpedProcess {
+                name: "web".to_string(),
+                config: ProcessConfig {
+                    command: "node server.js".to_string(),
+                    ..Default::default()
+                },
+                environment: Some("production".to_string()),
+                status: ProcessStatus::Online,
+                restarts: 2,
+                memory_restarts: 1,
+                generation: 3,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_write_then_read_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("dump.json");
+        write(&path, &sample()).unwrap();
+        assert_eq!(read(&path).unwrap(), sample());
+        assert!(!dir.path().join("state").join("dump.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_write_replaces_existing_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.json");
+        std::fs::write(&path, "garbage").unwrap();
+        write(&path, &sample()).unwrap();
+        assert_eq!(read(&path).unwrap(), sample());
+    }
+
+    #[test]
+    fn test_read_rejects_unknown_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.json");
+
+        std::fs::write(&path, r#"{"version": 99, "processes": []}"#).unwrap();
+        assert!(matches!(
+            read(&path),
+            Err(DumpError::UnsupportedVersion(99))
+        ));
+
+        std::fs::write(&path, r#"{"processes": []}"#).unwrap();
+        assert!(matches!(read(&path), Err(DumpError::MissingVersion)));
+    }
+}
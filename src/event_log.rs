@@ -0,0 +1,103 @@
+use crate::log::{self, Rotation};
+use crate::paths::Paths;
+use crate::protocol::ProcessEvent;
+use std::io::{self, Write};
+use std::path::Path;
+use tokio::sync::Mutex;
+
+// ---------------------------------------------------------------------------
+// Event log
+// ---------------------------------------------------------------------------
+//
+// Every lifecycle event is appended to `events.jsonl` in the data directory,
+// whatever the storage backend keeps and whoever is notified, so a post-mortem
+// can tell when a process crashed and why long after the per-process event
+// history has moved on. The file rotates like process logs, by the `[logs]`
+// `rotate_size` and `rotate_keep` settings.
+
+/// Serializes appends, so concurrent events never interleave their lines.
+static EVENT_LOG_LOCK: Mutex<()> = Mutex::const_new(());
+
+pub async fn append(paths: &Paths, rotation: Rotation, event: &ProcessEvent) -> io::Result<()> {
+    let line = serde_json::to_string(event)?;
+    let path = paths.event_log();
+    let _appending = EVENT_LOG_LOCK.lock().await;
+    tokio::task::spawn_blocking(move || append_rotating(&path, &line, rotation)).await?
+}
+
+/// Append `line`, first rotating the file if the line would take it past
+/// `rotation.size`.
+fn append_rotating(path: &Path, line: &str, rotation: Rotation) -> io::Result<()> {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size > 0 && size + line.len() as u64 + 1 > rotation.size {
+        log::rotate_log(path, rotation.keep)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{line}")
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::EventKind;
+
+    #[tokio::test]
+    async fn test_append_writes_json_lines() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        std::fs::create_dir_all(paths.data_dir()).unwrap();
+
+        let mut exit = ProcessEvent::new(EventKind::Exit, "web");
+        exit.exit_code = Some(1);
+        let rotation = Rotation::default();
+        append(
+            &paths,
+            rotation,
+            &ProcessEvent::new(EventKind::Start, "web"),
+        )
+        .await
+        .unwrap();
+        append(&paths, rotation, &exit).await.unwrap();
+
+        let content = std::fs::read_to_string(paths.event_log()).unwrap();
+        let events: Vec<ProcessEvent> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, EventKind::Start);
+        assert_eq!(events[1], exit);
+    }
+
+    #[test]
+    fn test_rotates_past_size() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let rotation = Rotation {
+            size: 20,
+            keep: 2,
+            ..Rotation::default()
+        };
+
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            append_rotating(&path, line, rotation).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(
+            std::fs::read_to_string(log::rotated_path(&path, 1)).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(log::rotated_path(&path, 2)).unwrap(),
+            "second line\n"
+        );
+        assert!(!log::rotated_path(&path, 3).exists());
+    }
+}
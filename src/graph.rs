@@ -0,0 +1,238 @@
+use crate::config::ProcessConfig;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+// ---------------------------------------------------------------------------
+// Graph
+// ---------------------------------------------------------------------------
+
+/// The `depends_on` relationships between the processes of a config.
+pub struct DependencyGraph {
+    /// Each process and what it lists in `depends_on`.
+    deps: BTreeMap<String, Vec<String>>,
+}
+
+impl DependencyGraph {
+    pub fn new(configs: &HashMap<String, ProcessConfig>) -> Self {
+        let deps = configs
+            .iter()
+            .map(|(name, config)| (name.clone(), config.depends_on.clone().unwrap_or_default()))
+            .collect();
+        Self { deps }
+    }
+
+    /// `(process, dependency)` pairs where the dependency is not a process.
+    pub fn missing(&self) -> Vec<(&str, &str)> {
+        self.edges()
+            .filter(|(_, dep)| !self.deps.contains_key(*dep))
+            .collect()
+    }
+
+    /// Processes that depend on themselves through `depends_on`.
+    pub fn cyclic(&self) -> Vec<&str> {
+        self.deps
+            .keys()
+            .map(String::as_str)
+            .filter(|name| self.reaches(name, name))
+            .collect()
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.deps
+            .iter()
+            .flat_map(|(name, deps)| deps.iter().map(move |dep| (name.as_str(), dep.as_str())))
+    }
+
+    /// Processes listing `name` in `depends_on`, by name.
+    fn dependents(&self, name: &str) -> Vec<&str> {
+        self.deps
+            .iter()
+            .filter(|(_, deps)| deps.iter().any(|dep| dep == name))
+            .map(|(dependent, _)| dependent.as_str())
+            .collect()
+    }
+
+    /// Whether `from` depends on `to` through one or more `depends_on` steps.
+    fn reaches(&self, from: &str, to: &str) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![from];
+        while let Some(name) = stack.pop() {
+            for dep in self.deps.get(name).into_iter().flatten() {
+                if dep == to {
+                    return true;
+                }
+                if seen.insert(dep.as_str()) {
+                    stack.push(dep);
+                }
+            }
+        }
+        false
+    }
+
+    // -----------------------------------------------------------------------
+    // Output
+    // -----------------------------------------------------------------------
+
+    /// Graphviz DOT with an arrow from each dependency to the processes that
+    /// wait for it, so the graph reads in startup order. Missing processes
+    /// are dashed and edges that close a cycle are red.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph pm3 {\n    rankdir=LR;\n    node [shape=box];\n");
+        for name in self.deps.keys() {
+            let _ = writeln!(out, "    {};", quote(name));
+        }
+        let missing: BTreeSet<&str> = self.missing().into_iter().map(|(_, dep)| dep).collect();
+        for name in missing {
+            let _ = writeln!(out, "    {} [style=dashed];", quote(name));
+        }
+        for (name, dep) in self.edges() {
+            let style = if self.reaches(dep, name) {
+                " [color=red]"
+            } else {
+                ""
+            };
+            let _ = writeln!(out, "    {} -> {}{style};", quote(dep), quote(name));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Processes that depend on nothing at the left edge, each followed by
+    /// the processes that wait for it. A process with several dependencies
+    /// shows up under each of them.
+    pub fn to_tree(&self) -> String {
+        let mut out = String::new();
+        let mut shown = BTreeSet::new();
+        let roots = self
+            .deps
+            .iter()
+            .filter(|(_, deps)| deps.iter().all(|dep| !self.deps.contains_key(dep)))
+            .map(|(name, _)| name.as_str());
+        // Processes in a cycle never come up from a root; start them on
+        // their own
+        let rest: Vec<&str> = self.deps.keys().map(String::as_str).collect();
+        for root in roots.chain(rest) {
+            if !shown.contains(root) {
+                self.write_node(&mut out, root, "", None, &mut vec![], &mut shown);
+            }
+        }
+        out
+    }
+
+    fn write_node<'a>(
+        &'a self,
+        out: &mut String,
+        name: &'a str,
+        indent: &str,
+        last: Option<bool>,
+        path: &mut Vec<&'a str>,
+        shown: &mut BTreeSet<&'a str>,
+    ) {
+        let branch = match last {
+            None => "",
+            Some(true) => "└── ",
+            Some(false) => "├── ",
+        };
+        let mut label = name.to_string();
+        let missing: Vec<&str> = self.deps[name]
+            .iter()
+            .filter(|dep| !self.deps.contains_key(*dep))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            let _ = write!(label, " (missing: {})", missing.join(", "));
+        }
+        if path.contains(&name) {
+            let _ = writeln!(out, "{indent}{branch}{name} (cycle)");
+            return;
+        }
+        let _ = writeln!(out, "{indent}{branch}{label}");
+        shown.insert(name);
+
+        let child_indent = match last {
+            None => String::new(),
+            Some(true) => format!("{indent}    "),
+            Some(false) => format!("{indent}│   "),
+        };
+        path.push(name);
+        let dependents = self.dependents(name);
+        for (i, dependent) in dependents.iter().enumerate() {
+            let last = i + 1 == dependents.len();
+            self.write_node(out, dependent, &child_indent, Some(last), path, shown);
+        }
+        path.pop();
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        let configs = edges
+            .iter()
+            .map(|(name, deps)| {
+                let config = ProcessConfig {
+                    command: "x".to_string(),
+                    depends_on: (!deps.is_empty())
+                        .then(|| deps.iter().map(|d| d.to_string()).collect()),
+                    ..Default::default()
+                };
+                (name.to_string(), config)
+            })
+            .collect();
+        DependencyGraph::new(&configs)
+    }
+
+    #[test]
+    fn test_tree_in_startup_order() {
+        let g = graph(&[
+            ("db", &[]),
+            ("cache", &[]),
+            ("api", &["db", "cache"]),
+            ("web", &["api"]),
+            ("worker", &["db", "ghost"]),
+        ]);
+        assert_eq!(
+            g.to_tree(),
+            "\
+cache
+└── api
+    └── web
+db
+├── api
+│   └── web
+└── worker (missing: ghost)
+"
+        );
+        assert_eq!(g.missing(), [("worker", "ghost")]);
+        assert!(g.cyclic().is_empty());
+    }
+
+    #[test]
+    fn test_cycles_are_marked() {
+        let g = graph(&[("a", &["b"]), ("b", &["a"]), ("c", &["b"])]);
+        assert_eq!(g.cyclic(), ["a", "b"]);
+        assert_eq!(g.to_tree(), "a\n└── b\n    ├── a (cycle)\n    └── c\n");
+
+        let dot = g.to_dot();
+        assert!(dot.starts_with("digraph pm3 {\n"), "{dot}");
+        assert!(dot.contains("    \"b\" -> \"a\" [color=red];\n"), "{dot}");
+        assert!(dot.contains("    \"b\" -> \"c\";\n"), "{dot}");
+    }
+
+    #[test]
+    fn test_dot_dashes_missing_processes() {
+        let dot = graph(&[("api", &["db\"1"])]).to_dot();
+        assert!(dot.contains("    \"db\\\"1\" [style=dashed];\n"), "{dot}");
+        assert!(dot.contains("    \"db\\\"1\" -> \"api\";\n"), "{dot}");
+    }
+}
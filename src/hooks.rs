@@ -0,0 +1,231 @@
+use crate::config::{Hook, ProcessConfig};
+use crate::log;
+use crate::process;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+pub const DEFAULT_HOOK_TIMEOUT_MS: u64 = 30_000;
+
+// ---------------------------------------------------------------------------
+// Types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    PreStart,
+    PostStart,
+    PreStop,
+    PostStop,
+}
+
+impl HookKind {
+    pub fn hook(self, config: &ProcessConfig) -> Option<&Hook> {
+        match self {
+            HookKind::PreStart => config.pre_start.as_ref(),
+            HookKind::PostStart => config.post_start.as_ref(),
+            HookKind::PreStop => config.pre_stop.as_ref(),
+            HookKind::PostStop => config.post_stop.as_ref(),
+        }
+    }
+}
+
+impl std::fmt::Display for HookKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookKind::PreStart => write!(f, "pre_start"),
+            HookKind::PostStart => write!(f, "post_start"),
+            HookKind::PreStop => write!(f, "pre_stop"),
+            HookKind::PostStop => write!(f, "post_stop"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HookError {
+    #[error("{kind} hook could not run: {message}")]
+    Spawn { kind: HookKind, message: String },
+    #[error("{kind} hook exited with {status}")]
+    Failed { kind: HookKind, status: String },
+    #[error("{kind} hook timed out after {timeout_ms}ms")]
+    TimedOut { kind: HookKind, timeout_ms: u64 },
+}
+
+// ---------------------------------------------------------------------------
+// Running hooks
+// ---------------------------------------------------------------------------
+
+/// Run the `kind` hook of `config`, if any, with the process's cwd and env.
+/// Hook output is appended to `log_path` as `[pm3]` lines.
+pub async fn run_hook(
+    kind: HookKind,
+    name: &str,
+    config: &ProcessConfig,
+    log_path: &Path,
+) -> Result<(), HookError> {
+    let Some(hook) = kind.hook(config) else {
+        return Ok(());
+    };
+
+    let spawn_error = |message: String| HookError::Spawn { kind, message };
+    let (program, args) =
+        process::parse_command(hook.command()).map_err(|e| spawn_error(e.to_string()))?;
+
+    let mut cmd = Command::new(&program);
+    cmd.args(&args);
+    process::configure_command(&mut cmd, config);
+    // In a group of its own, away from the daemon's terminal signals
+    cmd.process_group(0);
+    cmd.env("PM3_NAME", name);
+    cmd.env("PM3_HOOK", kind.to_string());
+    cmd.stdin(std::process::Stdio::null());
+    cmd.kill_on_drop(true);
+
+    let timeout_ms = hook.timeout().unwrap_or(DEFAULT_HOOK_TIMEOUT_MS);
+    let output = match tokio::time::timeout(Duration::from_millis(timeout_ms), cmd.output()).await {
+        Ok(output) => output.map_err(|e| spawn_error(e.to_string()))?,
+        Err(_) => {
+            let error = HookError::TimedOut { kind, timeout_ms };
+            let _ = log::append_event(log_path, &error.to_string()).await;
+            return Err(error);
+        }
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+    {
+        let _ = log::append_event(log_path, &format!("{kind}: {line}")).await;
+    }
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let error = HookError::Failed {
+            kind,
+            status: output.status.to_string(),
+        };
+        let _ = log::append_event(log_path, &error.to_string()).await;
+        Err(error)
+    }
+}
+
+/// Run a hook whose failure should not stop the lifecycle step it belongs to;
+/// failures are already recorded in the process log, so they are only echoed
+/// to the daemon's stderr here.
+pub async fn run_hook_best_effort(
+    kind: HookKind,
+    name: &str,
+    config: &ProcessConfig,
+    log_path: &Path,
+) {
+    if let Err(e) = run_hook(kind, name, config, log_path).await {
+        tracing::warn!("{name}: {e}");
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config_with(kind: HookKind, hook: Hook) -> ProcessConfig {
+        let mut config = ProcessConfig {
+            command: "sleep 1".to_string(),
+            ..Default::default()
+        };
+        let slot = match kind {
+            HookKind::PreStart => &mut config.pre_start,
+            HookKind::PostStart => &mut config.post_start,
+            HookKind::PreStop => &mut config.pre_stop,
+            HookKind::PostStop => &mut config.post_stop,
+        };
+        *slot = Some(hook);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_missing_hook_is_ok() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ProcessConfig::default();
+        let log = dir.path().join("hook.log");
+        run_hook(HookKind::PreStart, "web", &config, &log)
+            .await
+            .unwrap();
+        assert!(!log.exists());
+    }
+
+    #[tokio::test]
+    async fn test_hook_output_and_env_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hook.log");
+        let mut config = config_with(
+            HookKind::PreStart,
+            Hook::Command("sh -c 'echo $PM3_HOOK $PM3_NAME $GREETING'".to_string()),
+        );
+        config.env = Some(HashMap::from([("GREETING".to_string(), "hi".to_string())]));
+
+        run_hook(HookKind::PreStart, "web", &config, &log)
+            .await
+            .unwrap();
+        let content = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(content, "[pm3] pre_start: pre_start web hi\n");
+    }
+
+    #[tokio::test]
+    async fn test_failing_hook_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hook.log");
+        let config = config_with(HookKind::PreStop, Hook::Command("false".to_string()));
+
+        let err = run_hook(HookKind::PreStop, "web", &config, &log)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HookError::Failed {
+                kind: HookKind::PreStop,
+                ..
+            }
+        ));
+        assert!(
+            std::fs::read_to_string(&log)
+                .unwrap()
+                .contains("pre_stop hook exited")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hook_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hook.log");
+        let config = config_with(
+            HookKind::PostStop,
+            Hook::Detailed {
+                command: "sleep 5".to_string(),
+                timeout: Some(100),
+            },
+        );
+
+        let start = std::time::Instant::now();
+        let err = run_hook(HookKind::PostStop, "web", &config, &log)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HookError::TimedOut {
+                timeout_ms: 100,
+                ..
+            }
+        ));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::{Arc, OnceLock};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+// ---------------------------------------------------------------------------
+// URLs
+// ---------------------------------------------------------------------------
+
+/// An `http://` or `https://` URL split into what a request needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+/// Parse `http[s]://host[:port][/path]`; the port defaults to 80 or 443.
+pub fn parse_url(url: &str) -> Option<Url> {
+    let (tls, rest) = match url.strip_prefix("https://") {
+        Some(rest) => (true, rest),
+        None => (false, url.strip_prefix("http://")?),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, if tls { 443 } else { 80 }),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some(Url {
+        tls,
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// TLS
+// ---------------------------------------------------------------------------
+
+/// Trusts the Mozilla roots bundled at build time, so nothing depends on
+/// the host's certificate store.
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+/// Start TLS on `stream`, verifying the server is `host`.
+pub(crate) async fn tls_connect(
+    stream: TcpStream,
+    host: &str,
+) -> Result<TlsStream<TcpStream>, String> {
+    let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    TlsConnector::from(tls_config())
+        .connect(name, stream)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// ---------------------------------------------------------------------------
+// Requests
+// ---------------------------------------------------------------------------
+
+/// POST `body` as JSON; any 2xx status counts as delivered.
+pub async fn post_json(url: &str, body: &[u8]) -> Result<(), String> {
+    let url = parse_url(url).ok_or_else(|| format!("bad url `{url}`"))?;
+    let stream = TcpStream::connect((url.host.as_str(), url.port))
+        .await
+        .map_err(|e| e.to_string())?;
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        url.path,
+        url.host,
+        body.len()
+    );
+    if url.tls {
+        let mut stream = tls_connect(stream, &url.host).await?;
+        exchange(&mut stream, head.as_bytes(), body).await
+    } else {
+        let mut stream = stream;
+        exchange(&mut stream, head.as_bytes(), body).await
+    }
+}
+
+/// Send a request and judge it by the status line of the response.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    head: &[u8],
+    body: &[u8],
+) -> Result<(), String> {
+    stream.write_all(head).await.map_err(|e| e.to_string())?;
+    stream.write_all(body).await.map_err(|e| e.to_string())?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 256];
+    while !response.contains(&b'\n') {
+        let n = stream.read(&mut buf).await.map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+    let status_line = String::from_utf8_lossy(&response);
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some(status) => Err(format!("HTTP {status}")),
+        None => Err("no response".to_string()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("https://hooks.slack.com/services/T0/B0/x"),
+            Some(Url {
+                tls: true,
+                host: "hooks.slack.com".to_string(),
+                port: 443,
+                path: "/services/T0/B0/x".to_string(),
+            })
+        );
+        let plain = parse_url("http://127.0.0.1:9000").unwrap();
+        assert!(!plain.tls);
+        assert_eq!((plain.port, plain.path.as_str()), (9000, "/"));
+        assert_eq!(parse_url("ftp://example.com"), None);
+        assert_eq!(parse_url("https://:443/"), None);
+    }
+}
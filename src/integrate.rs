@@ -0,0 +1,185 @@
+use crate::cli::Language;
+
+// ---------------------------------------------------------------------------
+// Control socket client snippets
+// ---------------------------------------------------------------------------
+
+// Each snippet is dependency-free and does nothing when the process was not
+// started by pm3, so it can be pasted into an app unconditionally.
+
+const PYTHON: &str = r#"# pm3 control socket client (stdlib only)
+import json, os, socket, threading, time
+
+def pm3_connect():
+    path = os.environ.get("PM3_CONTROL_SOCKET")
+    if not path:
+        return None
+    sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    sock.connect(path)
+    return sock
+
+def pm3_send(sock, message):
+    if sock:
+        sock.sendall((json.dumps(message) + "\n").encode())
+
+def pm3_on_shutdown(sock, drain_ms, callback):
+    """Run `callback` when pm3 announces a stop, asking for `drain_ms` to finish."""
+    def listen():
+        for line in sock.makefile():
+            if json.loads(line).get("type") == "shutdown":
+                pm3_send(sock, {"type": "stopping", "timeout_ms": drain_ms})
+                callback()
+    if sock:
+        threading.Thread(target=listen, daemon=True).start()
+
+pm3 = pm3_connect()
+pm3_send(pm3, {"type": "ready"})
+pm3_send(pm3, {"type": "metric", "name": "queue_depth", "value": 0})
+
+def heartbeat():
+    while True:
+        pm3_send(pm3, {"type": "heartbeat"})
+        time.sleep(5)
+
+threading.Thread(target=heartbeat, daemon=True).start()
+"#;
+
+const NODE: &str = r#"// pm3 control socket client (no dependencies)
+const net = require("net");
+const readline = require("readline");
+
+const pm3 = process.env.PM3_CONTROL_SOCKET
+  ? net.createConnection(process.env.PM3_CONTROL_SOCKET)
+  : null;
+
+function pm3Send(message) {
+  if (pm3) pm3.write(JSON.stringify(message) + "\n");
+}
+
+// Run `callback` when pm3 announces a stop, asking for `drainMs` to finish
+function pm3OnShutdown(drainMs, callback) {
+  if (!pm3) return;
+  readline.createInterface({ input: pm3 }).on("line", (line) => {
+    if (JSON.parse(line).type === "shutdown") {
+      pm3Send({ type: "stopping", timeout_ms: drainMs });
+      callback();
+    }
+  });
+}
+
+pm3Send({ type: "ready" });
+pm3Send({ type: "metric", name: "queue_depth", value: 0 });
+setInterval(() => pm3Send({ type: "heartbeat" }), 5000).unref();
+"#;
+
+const GO: &str = r#"// pm3 control socket client (standard library only)
+package pm3
+
+import (
+	"bufio"
+	"encoding/json"
+	"net"
+	"os"
+	"sync"
+	"time"
+)
+
+type Client struct {
+	mu   sync.Mutex
+	conn net.Conn
+}
+
+// Connect returns nil when the process was not started by pm3.
+func Connect() *Client {
+	path := os.Getenv("PM3_CONTROL_SOCKET")
+	if path == "" {
+		return nil
+	}
+	conn, err := net.Dial("unix", path)
+	if err != nil {
+		return nil
+	}
+	c := &Client{conn: conn}
+	go func() {
+		for range time.Tick(5 * time.Second) {
+			c.Send(map[string]any{"type": "heartbeat"})
+		}
+	}()
+	return c
+}
+
+func (c *Client) Send(message map[string]any) {
+	if c == nil {
+		return
+	}
+	line, _ := json.Marshal(message)
+	c.mu.Lock()
+	defer c.mu.Unlock()
+	c.conn.Write(append(line, '\n'))
+}
+
+func (c *Client) Ready() { c.Send(map[string]any{"type": "ready"}) }
+
+func (c *Client) Metric(name string, value float64) {
+	c.Send(map[string]any{"type": "metric", "name": name, "value": value})
+}
+
+// OnShutdown runs callback when pm3 announces a stop, asking for drain to finish.
+func (c *Client) OnShutdown(drain time.Duration, callback func()) {
+	if c == nil {
+		return
+	}
+	go func() {
+		scanner := bufio.NewScanner(c.conn)
+		for scanner.Scan() {
+			var message map[string]any
+			if json.Unmarshal(scanner.Bytes(), &message) == nil && message["type"] == "shutdown" {
+				c.Send(map[string]any{"type": "stopping", "timeout_ms": drain.Milliseconds()})
+				callback()
+			}
+		}
+	}()
+}
+"#;
+
+const SHELL: &str = r#"# pm3 control socket helpers (needs a netcat with -U support)
+pm3_send() {
+  [ -n "$PM3_CONTROL_SOCKET" ] || return 0
+  printf '%s\n' "$1" | nc -U -w 1 "$PM3_CONTROL_SOCKET"
+}
+
+pm3_send '{"type":"ready"}'
+pm3_send '{"type":"metric","name":"queue_depth","value":0}'
+while true; do pm3_send '{"type":"heartbeat"}'; sleep 5; done &
+"#;
+
+/// A ready-to-paste control socket client for `language`.
+pub fn snippet(language: Language) -> &'static str {
+    match language {
+        Language::Python => PYTHON,
+        Language::Node => NODE,
+        Language::Go => GO,
+        Language::Shell => SHELL,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_every_snippet_uses_the_protocol() {
+        for language in Language::value_variants() {
+            let snippet = snippet(*language);
+            assert!(snippet.contains("PM3_CONTROL_SOCKET"), "{language:?}");
+            for message in ["ready", "heartbeat", "metric"] {
+                assert!(snippet.contains(message), "{language:?} lacks {message}");
+            }
+        }
+    }
+}
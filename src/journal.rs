@@ -0,0 +1,184 @@
+use crate::config::ProcessConfig;
+use crate::paths::Paths;
+use crate::process::ProcessTable;
+use crate::stats;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+// ---------------------------------------------------------------------------
+// Errors
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("corrupt journal: {0}")]
+    Corrupt(#[from] serde_json::Error),
+}
+
+// ---------------------------------------------------------------------------
+// Entries
+// ---------------------------------------------------------------------------
+
+/// What tells a later daemon that a pid is still the process it started, and
+/// where that process's output goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunIdentity {
+    /// Start time of the pid in clock ticks since boot (see
+    /// `stats::start_time`).
+    pub start_time: u64,
+    /// Inode of the pipe on the process's stdout; none when its output is
+    /// discarded (`log = false`).
+    pub stdout_pipe: Option<u64>,
+    /// Inode of the pipe on the process's stderr.
+    pub stderr_pipe: Option<u64>,
+}
+
+/// One running process as recorded in `journal.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub name: String,
+    pub pid: u32,
+    pub identity: RunIdentity,
+    pub generation: u64,
+    pub restarts: u32,
+    /// `config_hash` of `config` as written, checked before adopting.
+    pub config_hash: u64,
+    pub config: ProcessConfig,
+    /// The `--env` overlay the config was started with, which `config` does
+    /// not serialize.
+    pub environment: Option<String>,
+}
+
+impl JournalEntry {
+    /// Whether the recorded process is still running: its pid is alive and
+    /// was started at the recorded time.
+    pub fn is_running(&self) -> bool {
+        stats::start_time(self.pid) == Some(self.identity.start_time)
+    }
+
+    /// Whether `config` is the one the entry was written with.
+    pub fn is_intact(&self) -> bool {
+        config_hash(&self.config) == self.config_hash
+    }
+}
+
+/// FNV-1a over the JSON form of `config`; stable across builds so a journal
+/// written by one daemon can be checked by the next.
+pub fn config_hash(config: &ProcessConfig) -> u64 {
+    let json = serde_json::to_vec(config).unwrap_or_default();
+    json.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Journal entries for every process in `table` that has a live run,
+/// sorted by name.
+pub fn snapshot(table: &ProcessTable) -> Vec<JournalEntry> {
+    let mut entries: Vec<JournalEntry> = table
+        .values()
+        .filter_map(|managed| {
+            Some(JournalEntry {
+                name: managed.name.clone(),
+                pid: managed.pid?,
+                identity: managed.identity?,
+                generation: managed.generation,
+                restarts: managed.restarts,
+                config_hash: config_hash(&managed.config),
+                config: managed.config.clone(),
+                environment: managed.config.active_env.clone(),
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+// ---------------------------------------------------------------------------
+// File
+// ---------------------------------------------------------------------------
+
+/// Replace `journal.json` with `entries`. The file is written next to the
+/// journal and renamed over it, so a crash mid-write leaves the old one.
+pub async fn write(paths: &Paths, entries: &[JournalEntry]) -> Result<(), JournalError> {
+    let path = paths.journal_file();
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(entries)?).await?;
+    fs::rename(&tmp, &path).await?;
+    Ok(())
+}
+
+/// Entries of the journal left behind by the previous daemon; empty if it
+/// shut down cleanly.
+pub async fn read(paths: &Paths) -> Result<Vec<JournalEntry>, JournalError> {
+    match fs::read(paths.journal_file()).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn remove(paths: &Paths) {
+    let _ = fs::remove_file(paths.journal_file()).await;
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(config: ProcessConfig) -> JournalEntry {
+        JournalEntry {
+            name: "web".to_string(),
+            pid: std::process::id(),
+            identity: RunIdentity {
+                start_time: stats::start_time(std::process::id()).unwrap(),
+                stdout_pipe: Some(1),
+                stderr_pipe: Some(2),
+            },
+            generation: 3,
+            restarts: 1,
+            config_hash: config_hash(&config),
+            config,
+            environment: Some("production".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_journal_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        assert!(read(&paths).await.unwrap().is_empty());
+
+        let config = ProcessConfig {
+            command: "node server.js".to_string(),
+            ..Default::default()
+        };
+        let entries = vec![entry(config)];
+        write(&paths, &entries).await.unwrap();
+        let back = read(&paths).await.unwrap();
+        assert_eq!(back, entries);
+        assert!(back[0].is_running());
+        assert!(back[0].is_intact());
+
+        remove(&paths).await;
+        assert!(read(&paths).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_entry_checks() {
+        let mut stale = entry(ProcessConfig {
+            command: "sleep 1".to_string(),
+            ..Default::default()
+        });
+        stale.identity.start_time += 1;
+        assert!(!stale.is_running());
+
+        stale.config.command = "sleep 2".to_string();
+        assert!(!stale.is_intact());
+    }
+}
@@ -1,9 +1,46 @@
+pub mod alert;
+pub mod api;
+pub mod audit;
+pub mod backup;
+pub mod bench;
 pub mod cli;
 pub mod client;
+pub mod clock;
+pub mod compose;
 pub mod config;
+pub mod control;
+pub mod cron;
 pub mod daemon;
+pub mod daemon_log;
+pub mod dump;
+pub mod event_log;
+pub mod graph;
+pub mod hooks;
+pub mod http;
+pub mod integrate;
+pub mod journal;
 pub mod log;
+pub mod metrics;
+pub mod migrate;
+pub mod monit;
+pub mod notify;
 pub mod paths;
 pub mod pid;
+pub mod pipeline;
+pub mod plugin;
+pub mod policy;
 pub mod process;
 pub mod protocol;
+pub mod pty;
+pub mod ready;
+pub mod remote;
+pub mod report;
+pub mod scaffold;
+pub mod settings;
+pub mod ship;
+pub mod smtp;
+pub mod startup;
+pub mod stats;
+pub mod storage;
+pub mod systemd;
+pub mod wizard;
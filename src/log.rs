@@ -1,6 +1,13 @@
+use crate::clock;
+use crate::config::RotateInterval;
+use crate::ship::{ShippedLine, Shipper};
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead};
-use std::path::Path;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as TokioBufReader};
 use tokio::sync::broadcast;
 
@@ -18,18 +25,295 @@ pub const LOG_ROTATION_KEEP: u32 = 3;
 // Types
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// When process logs rotate: size and count from the `[logs]` daemon
+/// settings, interval and compression from the process config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Rotate once a log would grow past this many bytes.
+    pub size: u64,
+    /// Rotated files kept (.1 up to .keep), and as many dated ones.
+    pub keep: u32,
+    /// Also rotate when the hour or day changes, to `<log>.<period>`.
+    pub interval: Option<RotateInterval>,
+    /// Gzip each file as it is rotated.
+    pub compress: bool,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            size: LOG_ROTATION_SIZE,
+            keep: LOG_ROTATION_KEEP,
+            interval: None,
+            compress: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum LogStream {
     Stdout,
     Stderr,
 }
 
+/// How severe a log line is, read from its first `DEBUG`, `INFO`, `WARN`
+/// (or `WARNING`) or `ERROR` token.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// The level `line` is logged at, if it carries a level token.
+    pub fn of_line(line: &str) -> Option<Self> {
+        line.split(|c: char| !c.is_ascii_alphanumeric())
+            .find_map(|token| match token {
+                "DEBUG" => Some(LogLevel::Debug),
+                "INFO" => Some(LogLevel::Info),
+                "WARN" | "WARNING" => Some(LogLevel::Warn),
+                "ERROR" => Some(LogLevel::Error),
+                _ => None,
+            })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LogEntry {
     pub stream: LogStream,
     pub line: String,
 }
 
+/// Replacement text for anything matched by a `log_scrub` pattern.
+pub const SCRUB_REPLACEMENT: &str = "[REDACTED]";
+
+/// Whether a run's output has matched its `success_pattern` and
+/// `failure_pattern`, shared by the stdout and stderr copiers of the run.
+#[derive(Debug, Default)]
+pub struct OutputMatches {
+    success: AtomicBool,
+    failure: AtomicBool,
+}
+
+impl OutputMatches {
+    pub fn success(&self) -> bool {
+        self.success.load(Ordering::Relaxed)
+    }
+
+    pub fn failure(&self) -> bool {
+        self.failure.load(Ordering::Relaxed)
+    }
+}
+
+/// Per-process transformations applied to every captured line before it is
+/// written or streamed: `log_scrub` redaction, then (for the file) ANSI
+/// stripping and the `log_date_format` timestamp prefix. Lines are checked
+/// against the result patterns first, so those see the output as the
+/// process wrote it.
+#[derive(Debug, Clone, Default)]
+pub struct LineFormatter {
+    date_format: Option<String>,
+    scrub: Vec<regex::Regex>,
+    success: Option<regex::Regex>,
+    failure: Option<regex::Regex>,
+    matches: Arc<OutputMatches>,
+    rotation: Rotation,
+    strip_ansi: bool,
+    shipper: Option<Shipper>,
+}
+
+impl LineFormatter {
+    pub fn new(date_format: Option<String>, scrub: &[String]) -> Result<Self, regex::Error> {
+        let scrub = scrub
+            .iter()
+            .map(|pattern| regex::Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            date_format,
+            scrub,
+            ..Self::default()
+        })
+    }
+
+    /// Watch the output for `success_pattern` and `failure_pattern`.
+    pub fn with_result_patterns(
+        mut self,
+        success: Option<&str>,
+        failure: Option<&str>,
+    ) -> Result<Self, regex::Error> {
+        self.success = success.map(regex::Regex::new).transpose()?;
+        self.failure = failure.map(regex::Regex::new).transpose()?;
+        Ok(self)
+    }
+
+    /// Rotate the log files written through this formatter at `rotation`.
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Strip ANSI escape sequences from the lines written to the log file.
+    pub fn with_ansi_stripped(mut self, strip: bool) -> Self {
+        self.strip_ansi = strip;
+        self
+    }
+
+    /// Also hand each line to the daemon's `[log_ship]` shipper.
+    pub fn with_shipper(mut self, shipper: Option<Shipper>) -> Self {
+        self.shipper = shipper;
+        self
+    }
+
+    /// What the lines formatted so far have matched.
+    pub fn output_matches(&self) -> Arc<OutputMatches> {
+        Arc::clone(&self.matches)
+    }
+
+    fn check(&self, line: &str) {
+        let line = line.trim_end();
+        if self.success.as_ref().is_some_and(|re| re.is_match(line)) {
+            self.matches.success.store(true, Ordering::Relaxed);
+        }
+        if self.failure.as_ref().is_some_and(|re| re.is_match(line)) {
+            self.matches.failure.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Redact every `log_scrub` match in `line`.
+    pub fn scrub(&self, line: &str) -> String {
+        let mut scrubbed = line.to_string();
+        for pattern in &self.scrub {
+            if let Cow::Owned(replaced) = pattern.replace_all(&scrubbed, SCRUB_REPLACEMENT) {
+                scrubbed = replaced;
+            }
+        }
+        scrubbed
+    }
+
+    fn strip<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        match self.strip_ansi {
+            true => strip_ansi(line),
+            false => Cow::Borrowed(line),
+        }
+    }
+
+    fn stamp(&self, line: &str) -> String {
+        match self.date_format {
+            Some(ref fmt) => format!("{} | {line}", chrono::Local::now().format(fmt)),
+            None => line.to_string(),
+        }
+    }
+}
+
+/// Which lines `pm3 log` sends: those matching `grep`, logged at `level` or
+/// above, and stamped no earlier than `since` by the process's
+/// `log_date_format`. Lines whose stamp doesn't parse are kept; lines
+/// without a level token are not when `level` is set.
+#[derive(Debug, Clone, Default)]
+pub struct LineFilter {
+    pub grep: Option<regex::Regex>,
+    pub level: Option<LogLevel>,
+    /// Unix timestamp (ms) and the format the stamps were written in.
+    pub since: Option<(i64, String)>,
+}
+
+impl LineFilter {
+    pub fn matches(&self, line: &str) -> bool {
+        if self
+            .grep
+            .as_ref()
+            .is_some_and(|grep| !grep.is_match(&strip_ansi(line)))
+        {
+            return false;
+        }
+        if self
+            .level
+            .is_some_and(|level| LogLevel::of_line(&strip_ansi(line)).is_none_or(|at| at < level))
+        {
+            return false;
+        }
+        match self.since {
+            Some((since, ref format)) => stamped_at(line, format).is_none_or(|at| at >= since),
+            None => true,
+        }
+    }
+}
+
+/// `line` without its ANSI escape sequences: CSI (colors, cursor movement),
+/// OSC (titles, hyperlinks) and the shorter ones like charset changes.
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    if !line.contains('\x1b') {
+        return Cow::Borrowed(line);
+    }
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Parameters and intermediates, up to the final byte
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Up to BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // Intermediates (e.g. the `(` choosing a charset), then the
+            // final byte
+            Some(c) if (' '..='/').contains(&c) => {
+                while chars.next_if(|c| (' '..='/').contains(c)).is_some() {}
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+/// When a line stamped by `LineFormatter` was written (unix ms).
+fn stamped_at(line: &str, format: &str) -> Option<i64> {
+    use chrono::TimeZone;
+
+    let (stamp, _) = line.split_once(" | ")?;
+    let naive = chrono::NaiveDateTime::parse_from_str(stamp, format).ok()?;
+    let at = chrono::Local.from_local_datetime(&naive).earliest()?;
+    Some(at.timestamp_millis())
+}
+
+// ---------------------------------------------------------------------------
+// append_event — note daemon-side events in a process log
+// ---------------------------------------------------------------------------
+
+/// Append a `[pm3]`-prefixed line to a process log so daemon actions (like a
+/// memory-limit restart) show up next to the process's own output.
+pub async fn append_event(path: &Path, message: &str) -> io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(format!("[pm3] {message}\n").as_bytes())
+        .await?;
+    file.flush().await
+}
+
 // ---------------------------------------------------------------------------
 // tail_file — read last N lines from a file
 // ---------------------------------------------------------------------------
@@ -55,15 +339,165 @@ pub fn tail_file(path: &Path, n: usize) -> io::Result<Vec<String>> {
     }
 }
 
+/// The last `n` lines of a log, reaching back into its rotated files (gzipped
+/// or not) when the current one holds fewer.
+pub fn tail_log(path: &Path, n: usize) -> io::Result<Vec<String>> {
+    let lines = tail_file(path, n)?;
+    prepend_rotated(path, lines, n, &LineFilter::default())
+}
+
+/// Tops `lines` (the newest of `path`) up to `n` from its rotated files,
+/// taking only the lines `filter` lets through.
+fn prepend_rotated(
+    path: &Path,
+    mut lines: Vec<String>,
+    n: usize,
+    filter: &LineFilter,
+) -> io::Result<Vec<String>> {
+    for rotated in rotated_files(path)? {
+        if lines.len() >= n {
+            break;
+        }
+        let mut older = read_log(&rotated)?;
+        older.retain(|line| filter.matches(line));
+        let wanted = n - lines.len();
+        if older.len() > wanted {
+            older.drain(..older.len() - wanted);
+        }
+        older.append(&mut lines);
+        lines = older;
+    }
+    Ok(lines)
+}
+
+/// Every line of a log file, decompressing it if it ends in `.gz`.
+pub fn read_log(path: &Path) -> io::Result<Vec<String>> {
+    let file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut content = String::new();
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        flate2::read::MultiGzDecoder::new(file).read_to_string(&mut content)?;
+    } else {
+        io::BufReader::new(file).read_to_string(&mut content)?;
+    }
+    Ok(content.lines().map(str::to_string).collect())
+}
+
+// ---------------------------------------------------------------------------
+// LogFollower — tail -F over a log that gets rotated and truncated
+// ---------------------------------------------------------------------------
+
+/// Reads the lines appended to a log file, carrying on into the new file
+/// when the old one is rotated away and from the top when it is truncated.
+#[derive(Debug)]
+pub struct LogFollower {
+    path: PathBuf,
+    file: Option<std::fs::File>,
+    offset: u64,
+    partial: String,
+}
+
+impl LogFollower {
+    /// Follows `path` from its start; the file need not exist yet.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            offset: 0,
+            partial: String::new(),
+        }
+    }
+
+    /// Like `tail -n N -F`: the last `n` lines of the log that `filter` lets
+    /// through (reaching into rotated files) and a follower that picks up
+    /// right after them.
+    pub fn tail(path: PathBuf, n: usize, filter: &LineFilter) -> io::Result<(Vec<String>, Self)> {
+        let mut follower = Self::new(path);
+        let mut lines = follower.read_lines()?;
+        lines.retain(|line| filter.matches(line));
+        if lines.len() > n {
+            lines.drain(..lines.len() - n);
+        }
+        let lines = prepend_rotated(&follower.path, lines, n, filter)?;
+        Ok((lines, follower))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Switches to another file, as when a restart starts a new
+    /// per-generation log. What the old file still held is read first.
+    pub fn set_path(&mut self, path: PathBuf) -> io::Result<Vec<String>> {
+        let lines = self.read_lines()?;
+        *self = Self::new(path);
+        Ok(lines)
+    }
+
+    /// Complete lines written since the last call.
+    pub fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        use std::io::{Seek, SeekFrom};
+        use std::os::unix::fs::MetadataExt;
+
+        let mut lines = Vec::new();
+        loop {
+            let Some(file) = self.file.as_mut() else {
+                match std::fs::File::open(&self.path) {
+                    Ok(file) => self.file = Some(file),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(lines),
+                    Err(e) => return Err(e),
+                }
+                self.offset = 0;
+                self.partial.clear();
+                continue;
+            };
+
+            // Truncated (e.g. by `pm3 flush`): start over from the top
+            let meta = file.metadata()?;
+            if meta.len() < self.offset {
+                file.seek(SeekFrom::Start(0))?;
+                self.offset = 0;
+                self.partial.clear();
+            }
+
+            let mut chunk = Vec::new();
+            self.offset += file.read_to_end(&mut chunk)? as u64;
+            self.partial.push_str(&String::from_utf8_lossy(&chunk));
+            if let Some(end) = self.partial.rfind('\n') {
+                let complete: String = self.partial.drain(..=end).collect();
+                lines.extend(complete.lines().map(str::to_string));
+            }
+
+            // Rotated: the old file is drained, so move on to the new one
+            let replaced = match std::fs::metadata(&self.path) {
+                Ok(current) => current.dev() != meta.dev() || current.ino() != meta.ino(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+                Err(e) => return Err(e),
+            };
+            if !replaced {
+                return Ok(lines);
+            }
+            self.file = None;
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // rotate_log — shift rotated files and rename current to .1
 // ---------------------------------------------------------------------------
 
 pub fn rotate_log(path: &Path, max_rotations: u32) -> io::Result<()> {
-    // Delete the oldest rotated file if it exists
-    let oldest = rotated_path(path, max_rotations);
-    if oldest.exists() {
-        std::fs::remove_file(&oldest)?;
+    // Delete the oldest rotated file if it exists, compressed or not
+    for oldest in [
+        rotated_path(path, max_rotations),
+        gz_path(&rotated_path(path, max_rotations)),
+    ] {
+        if oldest.exists() {
+            std::fs::remove_file(&oldest)?;
+        }
     }
 
     // Shift .2 -> .3, .1 -> .2, etc.
@@ -73,6 +507,9 @@ pub fn rotate_log(path: &Path, max_rotations: u32) -> io::Result<()> {
         if from.exists() {
             std::fs::rename(&from, &to)?;
         }
+        if gz_path(&from).exists() {
+            std::fs::rename(gz_path(&from), gz_path(&to))?;
+        }
     }
 
     // Rename current to .1
@@ -83,12 +520,252 @@ pub fn rotate_log(path: &Path, max_rotations: u32) -> io::Result<()> {
     Ok(())
 }
 
-fn rotated_path(path: &Path, n: u32) -> std::path::PathBuf {
+pub fn rotated_path(path: &Path, n: u32) -> std::path::PathBuf {
     let mut p = path.as_os_str().to_owned();
     p.push(format!(".{n}"));
     p.into()
 }
 
+fn gz_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".gz");
+    p.into()
+}
+
+/// The period a line written at `at` belongs to, as it appears in the name
+/// of the file it is rotated to: `2024-05-01` or `2024-05-01T13`.
+pub fn period_stamp(interval: RotateInterval, at: chrono::DateTime<chrono::Local>) -> String {
+    match interval {
+        RotateInterval::Daily => at.format("%Y-%m-%d").to_string(),
+        RotateInterval::Hourly => at.format("%Y-%m-%dT%H").to_string(),
+    }
+}
+
+/// Move the log to `<log>.<period>` (`.<period>.<n>` if that is taken), and
+/// drop all but the newest `keep` dated files.
+pub fn rotate_log_dated(path: &Path, period: &str, keep: u32) -> io::Result<PathBuf> {
+    let mut target = PathBuf::from(format!("{}.{period}", path.display()));
+    let mut n = 1;
+    while target.exists() || gz_path(&target).exists() {
+        target = PathBuf::from(format!("{}.{period}.{n}", path.display()));
+        n += 1;
+    }
+    std::fs::rename(path, &target)?;
+
+    let dated: Vec<PathBuf> = rotated_files(path)?
+        .into_iter()
+        .filter(|file| is_dated(path, file))
+        .collect();
+    for stale in dated.iter().skip(keep as usize) {
+        std::fs::remove_file(stale)?;
+    }
+    Ok(target)
+}
+
+fn is_dated(path: &Path, rotated: &Path) -> bool {
+    rotated_suffix(path, rotated).is_some_and(|suffix| suffix.contains('-'))
+}
+
+/// What follows `<log>.` in the name of one of its rotated files: a number or
+/// a period, maybe with `.gz`.
+fn rotated_suffix<'a>(path: &Path, rotated: &'a Path) -> Option<&'a str> {
+    let base = path.file_name()?.to_str()?;
+    let suffix = rotated
+        .file_name()?
+        .to_str()?
+        .strip_prefix(base)?
+        .strip_prefix('.')?;
+    suffix
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then_some(suffix)
+}
+
+/// Rotated files of a log, numbered or dated, newest first.
+pub fn rotated_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file = entry.path();
+        if rotated_suffix(path, &file).is_some() {
+            files.push((entry.metadata()?.modified()?, file));
+        }
+    }
+    // Newest first; `.1` before `.2` when written in the same instant
+    files.sort_by(|(a_time, a), (b_time, b)| b_time.cmp(a_time).then_with(|| a.cmp(b)));
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Replace `path` with `<path>.gz`, keeping its modification time so the
+/// order of rotated files is unchanged.
+pub fn compress_file(path: &Path) -> io::Result<PathBuf> {
+    let target = gz_path(path);
+    let mut source = std::fs::File::open(path)?;
+    let modified = source.metadata()?.modified()?;
+    let file = std::fs::File::create(&target)?;
+    let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    io::copy(&mut source, &mut encoder)?;
+    let file = encoder.finish()?;
+    file.set_modified(modified)?;
+    std::fs::remove_file(path)?;
+    Ok(target)
+}
+
+/// The period the existing content of a log belongs to: when it was last
+/// written, or now for an empty or missing file.
+async fn current_period(path: &Path, interval: RotateInterval) -> String {
+    let written = match tokio::fs::metadata(path).await {
+        Ok(meta) if meta.len() > 0 => meta.modified().ok().map(chrono::DateTime::from),
+        _ => None,
+    };
+    period_stamp(interval, written.unwrap_or_else(clock::now_local))
+}
+
+/// Rotate the log at `path`, to `.1` or, given `period`, to the dated file
+/// for it, compressing the result if asked to.
+async fn rotate(path: &Path, period: Option<String>, rotation: Rotation) -> io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let rotated = match period {
+            Some(period) => rotate_log_dated(&path, &period, rotation.keep)?,
+            None => {
+                rotate_log(&path, rotation.keep)?;
+                rotated_path(&path, 1)
+            }
+        };
+        if rotation.compress && rotated.exists() {
+            compress_file(&rotated)?;
+        }
+        Ok(())
+    })
+    .await?
+}
+
+// ---------------------------------------------------------------------------
+// prune_to_budget — keep the log directory under `logs.total_max`
+// ---------------------------------------------------------------------------
+
+/// What one pass of `prune_to_budget` found and removed.
+#[derive(Debug, Default)]
+pub struct BudgetPass {
+    /// Bytes the directory holds after the pass.
+    pub used: u64,
+    /// Files removed, oldest first, with their sizes.
+    pub pruned: Vec<(PathBuf, u64)>,
+}
+
+/// Logs pruned to stay under budget since the daemon started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrunedLogs {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+// Pruning since startup, keyed by log directory so `pm3 daemon status` can
+// report it
+
+fn pruned_registry() -> &'static Mutex<HashMap<PathBuf, PrunedLogs>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<PathBuf, PrunedLogs>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+pub fn record_pruned(dir: &Path, pass: &BudgetPass) {
+    let mut registry = pruned_registry().lock().unwrap();
+    let pruned = registry.entry(dir.to_path_buf()).or_default();
+    for (_, size) in &pass.pruned {
+        pruned.files += 1;
+        pruned.bytes += size;
+    }
+}
+
+pub fn pruned(dir: &Path) -> PrunedLogs {
+    pruned_registry()
+        .lock()
+        .unwrap()
+        .get(dir)
+        .copied()
+        .unwrap_or_default()
+}
+
+pub fn forget_pruned(dir: &Path) {
+    pruned_registry().lock().unwrap().remove(dir);
+}
+
+/// Bytes the files in `dir` take up.
+pub fn disk_usage(dir: &Path) -> io::Result<u64> {
+    Ok(log_files(dir)?.iter().map(|(_, size, _)| size).sum())
+}
+
+/// Remove the oldest files in `dir` other than `keep` (the logs processes
+/// are writing to) until it holds no more than `max` bytes. What goes is
+/// rotated files, earlier generations' logs and the logs of deleted
+/// processes.
+pub fn prune_to_budget(
+    dir: &Path,
+    keep: &std::collections::HashSet<PathBuf>,
+    max: u64,
+) -> io::Result<BudgetPass> {
+    let mut files = log_files(dir)?;
+    let mut used: u64 = files.iter().map(|(_, size, _)| size).sum();
+    files.retain(|(path, _, _)| !keep.contains(path));
+    files.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0)));
+
+    let mut pruned = Vec::new();
+    for (path, size, _) in files {
+        if used <= max {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        used -= size;
+        pruned.push((path, size));
+    }
+    Ok(BudgetPass { used, pruned })
+}
+
+/// The files in `dir` other than `keep`, with their sizes, by name.
+pub fn files_except(
+    dir: &Path,
+    keep: &std::collections::HashSet<PathBuf>,
+) -> io::Result<Vec<(PathBuf, u64)>> {
+    let mut files: Vec<_> = log_files(dir)?
+        .into_iter()
+        .filter(|(path, _, _)| !keep.contains(path))
+        .map(|(path, size, _)| (path, size))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// The files in `dir` with their sizes and modification times.
+fn log_files(dir: &Path) -> io::Result<Vec<(PathBuf, u64, std::time::SystemTime)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file() {
+            files.push((entry.path(), meta.len(), meta.modified()?));
+        }
+    }
+    Ok(files)
+}
+
 // ---------------------------------------------------------------------------
 // spawn_log_copier — tokio task that reads piped child output
 // ---------------------------------------------------------------------------
@@ -98,25 +775,35 @@ pub fn spawn_log_copier(
     stream: LogStream,
     reader: impl tokio::io::AsyncRead + Unpin + Send + 'static,
     log_path: std::path::PathBuf,
-    log_date_format: Option<String>,
+    formatter: LineFormatter,
     broadcaster: broadcast::Sender<LogEntry>,
-) {
+    bytes_written: Arc<AtomicU64>,
+) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        if let Err(e) =
-            run_log_copier(name, stream, reader, log_path, log_date_format, broadcaster).await
+        if let Err(e) = run_log_copier(
+            name,
+            stream,
+            reader,
+            log_path,
+            formatter,
+            broadcaster,
+            bytes_written,
+        )
+        .await
         {
-            eprintln!("log copier error: {e}");
+            tracing::error!("log copier error: {e}");
         }
-    });
+    })
 }
 
 async fn run_log_copier(
-    _name: String,
+    name: String,
     stream: LogStream,
     reader: impl tokio::io::AsyncRead + Unpin + Send + 'static,
     log_path: std::path::PathBuf,
-    log_date_format: Option<String>,
+    formatter: LineFormatter,
     broadcaster: broadcast::Sender<LogEntry>,
+    bytes_written: Arc<AtomicU64>,
 ) -> io::Result<()> {
     let mut buf_reader = TokioBufReader::new(reader);
     let mut file = tokio::fs::OpenOptions::new()
@@ -129,6 +816,11 @@ async fn run_log_copier(
         let meta = tokio::fs::metadata(&log_path).await?;
         meta.len()
     };
+    let rotation = formatter.rotation;
+    let mut period = match rotation.interval {
+        Some(interval) => Some(current_period(&log_path, interval).await),
+        None => None,
+    };
 
     let mut line = String::new();
     loop {
@@ -138,20 +830,35 @@ async fn run_log_copier(
             break; // EOF — child exited
         }
 
-        let formatted = if let Some(ref fmt) = log_date_format {
-            let ts = chrono::Local::now().format(fmt);
-            format!("{ts} | {line}")
-        } else {
-            line.clone()
-        };
+        formatter.check(&line);
+        let scrubbed = formatter.scrub(&line);
+        let text = formatter.strip(&scrubbed);
+        let formatted = formatter.stamp(&text);
 
-        // Check rotation before writing
+        // Check rotation before writing: first whether the period the file
+        // covers has ended, then its size
         let line_bytes = formatted.as_bytes();
-        if byte_count + line_bytes.len() as u64 > LOG_ROTATION_SIZE {
+        let now = rotation
+            .interval
+            .map(|interval| period_stamp(interval, clock::now_local()));
+        let ended = (now != period).then(|| period.take()).flatten();
+        if now.is_some() {
+            period = now;
+        }
+        if byte_count + line_bytes.len() as u64 > rotation.size {
+            // `pm3 flush` may have truncated the file since it was counted
+            byte_count = file.metadata().await?.len();
+        }
+        let rotate_to = match ended {
+            Some(ended) if byte_count > 0 => Some(Some(ended)),
+            _ if byte_count + line_bytes.len() as u64 > rotation.size => Some(None),
+            _ => None,
+        };
+        if let Some(dated) = rotate_to {
             // Flush and close current file, rotate, reopen
             file.flush().await?;
             drop(file);
-            rotate_log(&log_path, LOG_ROTATION_KEEP)?;
+            rotate(&log_path, dated, rotation).await?;
             file = tokio::fs::OpenOptions::new()
                 .create(true)
                 .append(true)
@@ -162,11 +869,21 @@ async fn run_log_copier(
 
         file.write_all(line_bytes).await?;
         byte_count += line_bytes.len() as u64;
+        bytes_written.fetch_add(line_bytes.len() as u64, Ordering::Relaxed);
+
+        if let Some(shipper) = &formatter.shipper {
+            shipper.send(ShippedLine {
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                process: name.clone(),
+                stream,
+                line: text.trim_end().to_string(),
+            });
+        }
 
-        // Broadcast to any follow subscribers (ignore if no receivers)
+        // Broadcast to any subscribers (ignore if none), colors and all
         let _ = broadcaster.send(LogEntry {
-            stream: stream.clone(),
-            line: line.trim_end().to_string(),
+            stream,
+            line: scrubbed.trim_end().to_string(),
         });
     }
 
@@ -183,6 +900,16 @@ mod tests {
     use super::*;
     use std::io::Write;
 
+    #[tokio::test]
+    async fn test_append_event_adds_prefixed_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        std::fs::write(&path, "app output\n").unwrap();
+        append_event(&path, "restarted").await.unwrap();
+        let lines = tail_file(&path, 10).unwrap();
+        assert_eq!(lines, vec!["app output", "[pm3] restarted"]);
+    }
+
     #[test]
     fn test_tail_file_empty() {
         let dir = tempfile::tempdir().unwrap();
@@ -247,6 +974,152 @@ mod tests {
         assert!(lines.is_empty());
     }
 
+    fn append(path: &Path, text: &str) {
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        f.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_follower_tails_then_reads_appended_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "line1\nline2\nline3\n");
+
+        let (tail, mut follower) =
+            LogFollower::tail(path.clone(), 2, &LineFilter::default()).unwrap();
+        assert_eq!(tail, vec!["line2", "line3"]);
+        assert!(follower.read_lines().unwrap().is_empty());
+
+        // A partial line waits for its newline
+        append(&path, "line4\nline");
+        assert_eq!(follower.read_lines().unwrap(), vec!["line4"]);
+        append(&path, "5\n");
+        assert_eq!(follower.read_lines().unwrap(), vec!["line5"]);
+    }
+
+    #[test]
+    fn test_follower_tail_reaches_into_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(rotated_path(&path, 1), "old1\nold2\n").unwrap();
+        append(&path, "new1\n");
+
+        let (tail, _) = LogFollower::tail(path, 2, &LineFilter::default()).unwrap();
+        assert_eq!(tail, vec!["old2", "new1"]);
+    }
+
+    #[test]
+    fn test_follower_tail_keeps_last_matching_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(rotated_path(&path, 1), "ERROR old\nfine\n").unwrap();
+        append(&path, "ERROR one\nfine\nERROR two\nfine\n");
+        let filter = LineFilter {
+            grep: Some(regex::Regex::new("ERROR").unwrap()),
+            level: None,
+            since: None,
+        };
+
+        let (tail, _) = LogFollower::tail(path.clone(), 2, &filter).unwrap();
+        assert_eq!(tail, vec!["ERROR one", "ERROR two"]);
+        let (tail, _) = LogFollower::tail(path, 5, &filter).unwrap();
+        assert_eq!(tail, vec!["ERROR old", "ERROR one", "ERROR two"]);
+    }
+
+    #[test]
+    fn test_line_filter_since_reads_stamps() {
+        let format = "%Y-%m-%d %H:%M:%S";
+        let stamp = |at: chrono::DateTime<chrono::Local>| format!("{} | hello", at.format(format));
+        let now = chrono::Local::now();
+        let filter = LineFilter {
+            grep: None,
+            level: None,
+            since: Some((
+                (now - chrono::Duration::minutes(10)).timestamp_millis(),
+                format.to_string(),
+            )),
+        };
+
+        assert!(filter.matches(&stamp(now)));
+        assert!(!filter.matches(&stamp(now - chrono::Duration::hours(1))));
+        // Lines without a readable stamp are kept
+        assert!(filter.matches("no stamp here"));
+    }
+
+    #[test]
+    fn test_line_filter_level_keeps_that_level_and_above() {
+        let filter = LineFilter {
+            level: Some(LogLevel::Warn),
+            ..LineFilter::default()
+        };
+
+        assert!(filter.matches("2024-01-01 [ERROR] disk full"));
+        assert!(filter.matches("level=WARN msg=slow"));
+        assert!(filter.matches("WARNING: deprecated"));
+        assert!(filter.matches("\x1b[33m WARN\x1b[0m retrying"));
+        assert!(!filter.matches("INFO listening on :3000"));
+        assert!(!filter.matches("[DEBUG] tick"));
+        // Only whole tokens count, and lines without one are dropped
+        assert!(!filter.matches("ERRORS: none"));
+        assert!(!filter.matches("plain output"));
+        // The first token decides
+        assert!(!filter.matches("INFO retried after ERROR"));
+    }
+
+    #[test]
+    fn test_follower_continues_across_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "before\n");
+        let (_, mut follower) =
+            LogFollower::tail(path.clone(), 10, &LineFilter::default()).unwrap();
+
+        // Written to the old file just before it is rotated away
+        append(&path, "last\n");
+        rotate_log(&path, 3).unwrap();
+        append(&path, "first\n");
+        assert_eq!(follower.read_lines().unwrap(), vec!["last", "first"]);
+
+        append(&path, "second\n");
+        assert_eq!(follower.read_lines().unwrap(), vec!["second"]);
+    }
+
+    #[test]
+    fn test_follower_restarts_after_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "a long line before the flush\n");
+        let (_, mut follower) =
+            LogFollower::tail(path.clone(), 10, &LineFilter::default()).unwrap();
+
+        std::fs::File::create(&path).unwrap();
+        append(&path, "after\n");
+        assert_eq!(follower.read_lines().unwrap(), vec!["after"]);
+    }
+
+    #[test]
+    fn test_follower_waits_for_missing_file_and_switches_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let (tail, mut follower) =
+            LogFollower::tail(path.clone(), 10, &LineFilter::default()).unwrap();
+        assert!(tail.is_empty());
+
+        append(&path, "hello\n");
+        assert_eq!(follower.read_lines().unwrap(), vec!["hello"]);
+
+        let other = dir.path().join("app-2.log");
+        append(&other, "next\n");
+        append(&path, "tail end\n");
+        assert_eq!(follower.set_path(other.clone()).unwrap(), vec!["tail end"]);
+        assert_eq!(follower.path(), other);
+        assert_eq!(follower.read_lines().unwrap(), vec!["next"]);
+    }
+
     #[test]
     fn test_rotate_log_creates_dot1() {
         let dir = tempfile::tempdir().unwrap();
@@ -342,8 +1215,9 @@ mod tests {
             LogStream::Stdout,
             reader,
             log_path.clone(),
-            fmt.map(|s| s.to_string()),
+            LineFormatter::new(fmt.map(|s| s.to_string()), &[]).unwrap(),
             tx,
+            Arc::new(AtomicU64::new(0)),
         )
         .await
         .unwrap();
@@ -401,6 +1275,188 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_copier_counts_bytes_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("count.log");
+        let (tx, _rx) = broadcast::channel(16);
+        let counter = Arc::new(AtomicU64::new(0));
+        let reader = tokio::io::BufReader::new(std::io::Cursor::new(b"abc\nde\n".to_vec()));
+        run_log_copier(
+            "test".into(),
+            LogStream::Stdout,
+            reader,
+            log_path,
+            LineFormatter::default(),
+            tx,
+            Arc::clone(&counter),
+        )
+        .await
+        .unwrap();
+        assert_eq!(counter.load(Ordering::Relaxed), 7);
+    }
+
+    // ── log_scrub ─────────────────────────────────────────────────────
+
+    #[test]
+    fn test_scrub_redacts_matches() {
+        let formatter = LineFormatter::new(
+            None,
+            &["(?i)password=\\S+".to_string(), "Bearer \\S+".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            formatter.scrub("login PASSWORD=hunter2 ok"),
+            "login [REDACTED] ok"
+        );
+        assert_eq!(
+            formatter.scrub("auth: Bearer abc.def, password=x"),
+            "auth: [REDACTED] [REDACTED]"
+        );
+        assert_eq!(formatter.scrub("nothing secret"), "nothing secret");
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m plain"), "red plain");
+        assert_eq!(strip_ansi("\x1b[2K\x1b[1Gprogress 50%"), "progress 50%");
+        assert_eq!(
+            strip_ansi("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x07!"),
+            "link!"
+        );
+        assert_eq!(strip_ansi("\x1b(Bcharset\x1b7"), "charset");
+        assert!(matches!(strip_ansi("no escapes"), Cow::Borrowed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_copier_strips_ansi_from_the_file_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("color.log");
+        let (tx, mut rx) = broadcast::channel(16);
+        let reader =
+            tokio::io::BufReader::new(std::io::Cursor::new(b"\x1b[32mok\x1b[0m done\n".to_vec()));
+        run_log_copier(
+            "test".into(),
+            LogStream::Stdout,
+            reader,
+            log_path.clone(),
+            LineFormatter::default().with_ansi_stripped(true),
+            tx,
+            Arc::new(AtomicU64::new(0)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&log_path).unwrap(), "ok done\n");
+        assert_eq!(rx.recv().await.unwrap().line, "\x1b[32mok\x1b[0m done");
+    }
+
+    // ── logs.total_max ────────────────────────────────────────────────
+
+    #[test]
+    fn test_prune_to_budget_removes_oldest_unkept_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = std::time::SystemTime::now();
+        let write = |name: &str, size: usize, age_secs: u64| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, vec![b'x'; size]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - std::time::Duration::from_secs(age_secs))
+                .unwrap();
+            path
+        };
+        // The live log is the oldest file, but it is never pruned
+        let live = write("web-out.log", 400, 500);
+        write("web-out.log.2", 300, 300);
+        write("gone-out.log", 200, 200);
+        write("web-out.log.1", 100, 100);
+        assert_eq!(disk_usage(dir.path()).unwrap(), 1000);
+
+        let keep = std::collections::HashSet::from([live.clone()]);
+        let pass = prune_to_budget(dir.path(), &keep, 600).unwrap();
+        let pruned: Vec<_> = pass
+            .pruned
+            .iter()
+            .map(|(path, size)| (path.file_name().unwrap().to_str().unwrap(), *size))
+            .collect();
+        assert_eq!(pruned, [("web-out.log.2", 300), ("gone-out.log", 200)]);
+        assert_eq!(pass.used, 500);
+        assert!(live.exists() && dir.path().join("web-out.log.1").exists());
+
+        // Under budget, nothing goes
+        let pass = prune_to_budget(dir.path(), &keep, 600).unwrap();
+        assert!(pass.pruned.is_empty());
+
+        // Over it with only kept logs left, they stay
+        let pass = prune_to_budget(dir.path(), &keep, 0).unwrap();
+        assert_eq!(pass.used, 400);
+        assert!(live.exists());
+    }
+
+    // ── success_pattern / failure_pattern ─────────────────────────────
+
+    #[tokio::test]
+    async fn test_copier_records_result_pattern_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, _rx) = broadcast::channel(16);
+        // Patterns see the line before it is scrubbed
+        let formatter = LineFormatter::new(None, &["ERROR.*".to_string()])
+            .unwrap()
+            .with_result_patterns(Some("^done$"), Some("^ERROR"))
+            .unwrap();
+        let matches = formatter.output_matches();
+        let reader = tokio::io::BufReader::new(std::io::Cursor::new(
+            b"working\nERROR: disk full\n".to_vec(),
+        ));
+        run_log_copier(
+            "test".into(),
+            LogStream::Stdout,
+            reader,
+            dir.path().join("result.log"),
+            formatter,
+            tx,
+            Arc::new(AtomicU64::new(0)),
+        )
+        .await
+        .unwrap();
+        assert!(matches.failure());
+        assert!(!matches.success());
+    }
+
+    #[test]
+    fn test_invalid_scrub_pattern_errors() {
+        assert!(LineFormatter::new(None, &["(unclosed".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copier_scrubs_before_persist_and_broadcast() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("scrub.log");
+        let (tx, mut rx) = broadcast::channel(16);
+        let formatter =
+            LineFormatter::new(Some("%Y".to_string()), &["token=\\w+".to_string()]).unwrap();
+        let reader = tokio::io::BufReader::new(std::io::Cursor::new(b"token=s3cret\n".to_vec()));
+        run_log_copier(
+            "test".into(),
+            LogStream::Stdout,
+            reader,
+            log_path.clone(),
+            formatter,
+            tx,
+            Arc::new(AtomicU64::new(0)),
+        )
+        .await
+        .unwrap();
+
+        let content = std::fs::read_to_string(&log_path).unwrap();
+        assert!(content.ends_with("| [REDACTED]\n"), "content: {content}");
+        assert!(!content.contains("s3cret"));
+        assert_eq!(rx.recv().await.unwrap().line, "[REDACTED]");
+    }
+
     // ── Item 17: Log rotation threshold tests ─────────────────────────
 
     /// Helper: pipe `data` through `run_log_copier`, return `(TempDir, PathBuf)`
@@ -415,8 +1471,9 @@ mod tests {
             LogStream::Stdout,
             reader,
             log_path.clone(),
-            None,
+            LineFormatter::default(),
             tx,
+            Arc::new(AtomicU64::new(0)),
         )
         .await
         .unwrap();
@@ -487,6 +1544,188 @@ mod tests {
         );
     }
 
+    // ── Time-based rotation and compression ───────────────────────────
+
+    #[test]
+    fn test_period_stamp() {
+        let at = chrono::NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(13, 7, 0)
+            .unwrap()
+            .and_local_timezone(chrono::Local)
+            .unwrap();
+        assert_eq!(period_stamp(RotateInterval::Daily, at), "2024-05-01");
+        assert_eq!(period_stamp(RotateInterval::Hourly, at), "2024-05-01T13");
+    }
+
+    #[test]
+    fn test_rotate_log_dated_names_and_prunes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        for (period, content) in [
+            ("2024-05-01", "first"),
+            ("2024-05-01", "again"),
+            ("2024-05-02", "second"),
+            ("2024-05-03", "third"),
+        ] {
+            std::fs::write(&path, content).unwrap();
+            rotate_log_dated(&path, period, 3).unwrap();
+            // Distinct modification times, so the newest can be told apart
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        let names: Vec<String> = rotated_files(&path)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "app.log.2024-05-03",
+                "app.log.2024-05-02",
+                "app.log.2024-05-01.1"
+            ]
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_rotated_files_ignores_other_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("web-out.log");
+        for name in [
+            "web-out.log.1",
+            "web-out.log.2024-05-01.gz",
+            "web-err.log.1",
+        ] {
+            std::fs::write(dir.path().join(name), "x").unwrap();
+        }
+        std::fs::write(dir.path().join("web-out.log.bak"), "x").unwrap();
+        assert_eq!(rotated_files(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_compress_file_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log.1");
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+
+        let compressed = compress_file(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(compressed, dir.path().join("app.log.1.gz"));
+        assert_eq!(read_log(&compressed).unwrap(), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_rotate_log_shifts_compressed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "current").unwrap();
+        std::fs::write(gz_path(&rotated_path(&path, 1)), "old").unwrap();
+        std::fs::write(gz_path(&rotated_path(&path, 2)), "oldest").unwrap();
+
+        rotate_log(&path, 2).unwrap();
+
+        assert!(rotated_path(&path, 1).exists());
+        assert_eq!(
+            std::fs::read_to_string(gz_path(&rotated_path(&path, 2))).unwrap(),
+            "old"
+        );
+    }
+
+    #[test]
+    fn test_tail_log_reads_into_rotated_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "l1\nl2\n").unwrap();
+        rotate_log_dated(&path, "2024-05-01", 3).unwrap();
+        compress_file(&dir.path().join("app.log.2024-05-01")).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(&path, "l3\n").unwrap();
+        rotate_log(&path, 3).unwrap();
+        std::fs::write(&path, "l4\nl5\n").unwrap();
+
+        assert_eq!(tail_log(&path, 2).unwrap(), vec!["l4", "l5"]);
+        assert_eq!(tail_log(&path, 4).unwrap(), vec!["l2", "l3", "l4", "l5"]);
+        assert_eq!(tail_log(&path, 10).unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_copier_rotates_content_from_an_earlier_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("test.log");
+        std::fs::write(&log_path, "yesterday\n").unwrap();
+        let yesterday = std::time::SystemTime::now() - std::time::Duration::from_secs(86_400);
+        std::fs::File::options()
+            .append(true)
+            .open(&log_path)
+            .unwrap()
+            .set_modified(yesterday)
+            .unwrap();
+
+        let formatter = LineFormatter::default().with_rotation(Rotation {
+            interval: Some(RotateInterval::Daily),
+            compress: true,
+            ..Rotation::default()
+        });
+        let (tx, _rx) = broadcast::channel(16);
+        let reader = tokio::io::BufReader::new(std::io::Cursor::new(b"today\n".to_vec()));
+        run_log_copier(
+            "test".into(),
+            LogStream::Stdout,
+            reader,
+            log_path.clone(),
+            formatter,
+            tx,
+            Arc::new(AtomicU64::new(0)),
+        )
+        .await
+        .unwrap();
+
+        let stamp = period_stamp(RotateInterval::Daily, chrono::DateTime::from(yesterday));
+        let rotated = dir.path().join(format!("test.log.{stamp}.gz"));
+        assert_eq!(read_log(&rotated).unwrap(), vec!["yesterday"]);
+        assert_eq!(std::fs::read_to_string(&log_path).unwrap(), "today\n");
+        assert_eq!(tail_log(&log_path, 5).unwrap(), vec!["yesterday", "today"]);
+    }
+
+    #[tokio::test]
+    async fn test_copier_does_not_rotate_a_truncated_file() {
+        use tokio::io::AsyncWriteExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("test.log");
+        let formatter = LineFormatter::default().with_rotation(Rotation {
+            size: 20,
+            ..Rotation::default()
+        });
+        let (tx, _rx) = broadcast::channel(16);
+        let (mut input, output) = tokio::io::duplex(64);
+        let copier = tokio::spawn(run_log_copier(
+            "test".into(),
+            LogStream::Stdout,
+            output,
+            log_path.clone(),
+            formatter,
+            tx,
+            Arc::new(AtomicU64::new(0)),
+        ));
+
+        input.write_all(b"first line\n").await.unwrap();
+        while std::fs::read(&log_path).map_or(0, |c| c.len()) < 11 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        // As `pm3 flush` does, under the running copier
+        std::fs::write(&log_path, b"").unwrap();
+        input.write_all(b"next line\n").await.unwrap();
+        drop(input);
+        copier.await.unwrap().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&log_path).unwrap(), "next line\n");
+        assert!(!rotated_path(&log_path, 1).exists());
+    }
+
     #[tokio::test]
     async fn test_no_timestamp_when_format_is_none() {
         let content = run_copier_with_format(None, &["raw line one", "raw line two"]).await;
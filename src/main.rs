@@ -1,43 +1,149 @@
 use clap::{CommandFactory, Parser};
 use comfy_table::{Attribute, Cell, Color, Table, presets::UTF8_FULL_CONDENSED};
-use owo_colors::OwoColorize;
-use pm3::cli::{Cli, Command};
-use pm3::protocol::{ProcessStatus, Request, Response};
+use owo_colors::{AnsiColors, OwoColorize, Style};
+use pm3::cli::{Cli, Command, ConfigCommand, DaemonCommand, ExportFormat, GraphFormat, TopSort};
+use pm3::log::LogStream;
+use pm3::protocol::{
+    AuditEntry, DaemonStatus, EventKind, JobInfo, PipelineStep, ProcessDetail, ProcessEvent,
+    ProcessInfo, ProcessResult, ProcessStatus, Request, Response, ResultStatus, RunRecord,
+    StatsSample, StepStatus, TreeMember,
+};
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
     let cli = Cli::parse();
 
+    if let Some(name) = &cli.profile {
+        pm3::paths::select_profile(pm3::paths::Profile::named(name)?);
+    } else if cli.local {
+        pm3::paths::select_profile(pm3::paths::Profile::Local(std::env::current_dir()?));
+    }
+    if let Some(host) = &cli.host {
+        let identity = cli.tls_cert.as_deref().zip(cli.tls_key.as_deref());
+        let tls = (cli.tls || cli.tls_ca.is_some() || identity.is_some())
+            .then(|| pm3::remote::client_config(cli.tls_ca.as_deref(), identity))
+            .transpose()?;
+        pm3::client::select_remote(pm3::client::Remote {
+            host: host.clone(),
+            token: cli.token.clone(),
+            tls,
+        });
+    }
+
     if cli.daemon {
         let paths = pm3::paths::Paths::new()?;
+        pm3::daemon_log::init(&paths)?;
         pm3::daemon::run(paths).await?;
     } else if let Some(command) = cli.command {
+        if cli.host.is_some() && command.is_local_only() {
+            color_eyre::eyre::bail!("this command works on the local daemon only, not --host");
+        }
+        if run_local_command(&command, cli.json)? {
+            return Ok(());
+        }
+
         let paths = pm3::paths::Paths::new()?;
-        let request = command_to_request(command)?;
+        let wait = match &command {
+            Command::Start {
+                wait: true,
+                wait_timeout,
+                ..
+            } => Some(
+                pm3::config::parse_duration(wait_timeout)
+                    .map_err(|e| color_eyre::eyre::eyre!("{e}"))?,
+            ),
+            _ => None,
+        };
+        let log_colors = match command {
+            Command::Log { no_color, .. } => wants_color(no_color),
+            _ => wants_color(false),
+        };
+        let mut request = command_to_request(command)?;
+        // JSON log lines carry the capture time whenever the daemon knows it
+        if let Request::Log {
+            ref mut timestamps, ..
+        } = request
+        {
+            *timestamps |= cli.json;
+        }
 
-        if matches!(request, Request::Log { .. }) {
-            // Log uses streaming — read multiple responses until EOF
-            if cli.json {
-                pm3::client::send_request_streaming(&paths, &request, |resp| {
-                    print_response_json(resp);
-                })?;
+        if let Request::Attach { ref name } = request {
+            let on_response = if cli.json {
+                print_response_json
             } else {
-                pm3::client::send_request_streaming(&paths, &request, |resp| {
-                    print_response(resp);
-                })?;
+                print_response
+            };
+            pm3::client::attach(&paths, name, on_response)?;
+        } else if matches!(request, Request::Log { .. } | Request::RunPipeline { .. }) {
+            // Log and pipelines stream — read multiple responses until EOF
+            let mut failed = false;
+            pm3::client::send_request_streaming(&paths, &request, |resp| {
+                failed |= match resp {
+                    Response::Error { .. } => true,
+                    Response::PipelineDone { steps, .. } => {
+                        steps.iter().any(|s| s.status != StepStatus::Succeeded)
+                    }
+                    _ => false,
+                };
+                match (resp, &request) {
+                    (Response::LogLine { .. }, Request::Log { name, .. }) if cli.json => {
+                        print_log_line_json(resp, name.as_deref())
+                    }
+                    _ if cli.json => print_response_json(resp),
+                    (
+                        Response::LogLine {
+                            name,
+                            line,
+                            stream,
+                            timestamp,
+                        },
+                        _,
+                    ) => print_log_line(name.as_deref(), line, *stream, *timestamp, log_colors),
+                    _ => print_response(resp),
+                }
+            })?;
+            if failed && matches!(request, Request::RunPipeline { .. }) {
+                std::process::exit(1);
             }
         } else {
-            let response = pm3::client::send_request(&paths, &request)?;
+            let response = if matches!(request, Request::Start { progress: true, .. }) {
+                // Starts report each process as it launches, then answer
+                let mut last = None;
+                pm3::client::send_request_streaming(&paths, &request, |resp| match resp {
+                    Response::StartProgress { .. } if cli.json => {}
+                    Response::StartProgress { .. } => print_response(resp),
+                    resp => last = Some(resp.clone()),
+                })?;
+                last.ok_or_else(|| color_eyre::eyre::eyre!("daemon closed the connection"))?
+            } else {
+                pm3::client::send_request(&paths, &request)?
+            };
             if cli.json {
                 print_response_json(&response);
             } else {
                 print_response(&response);
-                if should_auto_list(&request) {
-                    let list_resp = pm3::client::send_request(&paths, &Request::List)?;
-                    print_response(&list_resp);
+            }
+            let failed = match &response {
+                Response::Results { results, .. } => {
+                    results.iter().any(|r| r.status == ResultStatus::Failed)
+                }
+                _ => false,
+            };
+            if let (Some(timeout), Response::Results { .. }, false) = (wait, &response, failed) {
+                let names = started_names(&request);
+                pm3::client::wait_until_online(&paths, &names, timeout)?;
+                if !cli.json {
+                    println!("{} {}", "online:".green(), names.join(", "));
                 }
             }
+            if !cli.json && should_auto_list(&request) {
+                let list_resp = pm3::client::send_request(&paths, &Request::List)?;
+                print_response(&list_resp);
+            }
+            if failed {
+                std::process::exit(1);
+            }
         }
     } else {
         Cli::command().print_help()?;
@@ -46,6 +152,425 @@ async fn main() -> color_eyre::Result<()> {
     Ok(())
 }
 
+/// Run commands that are answered client-side without contacting the daemon.
+/// Returns `true` when the command was handled.
+fn run_local_command(command: &Command, json: bool) -> color_eyre::Result<bool> {
+    match command {
+        Command::Parse { command } => {
+            print_parse(command, json)?;
+            Ok(true)
+        }
+        Command::Backup { file, logs } => {
+            let paths = pm3::paths::Paths::new()?;
+            let manifest = pm3::backup::create(&paths, file, *logs)?;
+            print_backup("backed up", &manifest, file, json);
+            Ok(true)
+        }
+        Command::Restore { file } => {
+            let paths = pm3::paths::Paths::new()?;
+            let manifest = pm3::backup::restore(&paths, file)?;
+            print_backup("restored", &manifest, file, json);
+            Ok(true)
+        }
+        Command::Export { format } => {
+            export_config(*format)?;
+            Ok(true)
+        }
+        Command::Graph { format } => {
+            print_graph(*format)?;
+            Ok(true)
+        }
+        Command::Import {
+            file,
+            services_as_processes,
+            systemd,
+            output,
+        } => {
+            import_config(file, *services_as_processes, *systemd, output)?;
+            Ok(true)
+        }
+        Command::Init {
+            template,
+            interactive,
+            output,
+        } => {
+            init_project(*template, *interactive, output)?;
+            Ok(true)
+        }
+        Command::Ping => {
+            ping(json)?;
+            Ok(true)
+        }
+        Command::Monit => {
+            pm3::monit::run(&pm3::paths::Paths::new()?)?;
+            Ok(true)
+        }
+        Command::Top {
+            sort,
+            watch,
+            interval,
+        } => {
+            let interval = pm3::config::parse_duration(interval)
+                .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+            top(*sort, *watch, interval, json)?;
+            Ok(true)
+        }
+        Command::Update => {
+            update_daemon(json)?;
+            Ok(true)
+        }
+        Command::Daemon {
+            command: DaemonCommand::Status,
+        } => {
+            let paths = pm3::paths::Paths::new()?;
+            let response = query_running_daemon(&paths, &Request::DaemonStatus);
+            if json {
+                print_response_json(&response);
+            } else {
+                print_response(&response);
+            }
+            Ok(true)
+        }
+        Command::DaemonLog { lines, follow } => {
+            let paths = pm3::paths::Paths::new()?;
+            pm3::daemon_log::show(&paths, *lines, *follow)?;
+            Ok(true)
+        }
+        Command::Integrate { language } => {
+            print!("{}", pm3::integrate::snippet(*language));
+            Ok(true)
+        }
+        Command::Bench {
+            processes,
+            duration,
+            profile,
+        } => {
+            let duration = pm3::config::parse_duration(duration)
+                .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+            let report = pm3::bench::run(*profile, *processes, duration)?;
+            print_bench(&report, json);
+            Ok(true)
+        }
+        Command::Startup {
+            platform,
+            user,
+            system,
+            install,
+        } => {
+            let scope = pm3::startup::scope(*platform, *user, *system)?;
+            startup(*platform, scope, *install)?;
+            Ok(true)
+        }
+        Command::Unstartup {
+            platform,
+            user,
+            system,
+        } => {
+            let scope = pm3::startup::scope(*platform, *user, *system)?;
+            let path = pm3::startup::uninstall(*platform, scope)?;
+            println!("{} {}", "removed:".green(), path.display());
+            Ok(true)
+        }
+        Command::Plugin(args) => {
+            let paths = pm3::paths::Paths::new()?;
+            let code = pm3::plugin::run_command_plugin(&paths, args)?;
+            std::process::exit(code);
+        }
+        _ => Ok(false),
+    }
+}
+
+fn print_parse(command: &str, json: bool) -> color_eyre::Result<()> {
+    let (program, args) =
+        pm3::process::parse_command(command).map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    let warnings = pm3::process::lint_command(command);
+
+    if json {
+        let value = serde_json::json!({
+            "program": program,
+            "args": args,
+            "warnings": warnings,
+        });
+        println!("{value}");
+        return Ok(());
+    }
+
+    println!("{} {}", "program:".dimmed(), program.cyan().bold());
+    if args.is_empty() {
+        println!("{} {}", "args:".dimmed(), "(none)".dimmed());
+    } else {
+        println!("{}", "args:".dimmed());
+        for (i, arg) in args.iter().enumerate() {
+            println!("  [{i}] {arg}");
+        }
+    }
+    for warning in &warnings {
+        println!("{} {warning}", "warning:".yellow().bold());
+    }
+    Ok(())
+}
+
+fn export_config(format: ExportFormat) -> color_eyre::Result<()> {
+    let config_path = std::env::current_dir()?.join("pm3.toml");
+    let configs =
+        pm3::config::load_config(&config_path).map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    match format {
+        ExportFormat::Compose => {
+            let exported = pm3::compose::export(&configs)?;
+            print_warnings(&exported.warnings);
+            print!("{}", exported.yaml);
+        }
+    }
+    Ok(())
+}
+
+fn print_graph(format: GraphFormat) -> color_eyre::Result<()> {
+    let config_path = std::env::current_dir()?.join("pm3.toml");
+    let configs =
+        pm3::config::load_config(&config_path).map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    let graph = pm3::graph::DependencyGraph::new(&configs);
+
+    let mut warnings: Vec<String> = graph
+        .missing()
+        .into_iter()
+        .map(|(name, dep)| format!("'{name}' depends on '{dep}', which is not in pm3.toml"))
+        .collect();
+    let cyclic = graph.cyclic();
+    if !cyclic.is_empty() {
+        warnings.push(format!("depends_on cycle among: {}", cyclic.join(", ")));
+    }
+    print_warnings(&warnings);
+
+    match format {
+        GraphFormat::Tree => print!("{}", graph.to_tree()),
+        GraphFormat::Dot => print!("{}", graph.to_dot()),
+    }
+    Ok(())
+}
+
+fn import_config(
+    file: &std::path::Path,
+    services_as_processes: bool,
+    systemd: bool,
+    output: &std::path::Path,
+) -> color_eyre::Result<()> {
+    if output.exists() {
+        color_eyre::eyre::bail!(
+            "{} already exists; pass --output to write elsewhere",
+            output.display()
+        );
+    }
+
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| color_eyre::eyre::eyre!("{}: {e}", file.display()))?;
+    let imported = if systemd {
+        let name = file
+            .file_stem()
+            .map(|stem| pm3::scaffold::sanitize_name(&stem.to_string_lossy()))
+            .unwrap_or_default();
+        pm3::migrate::import_systemd(&name, &text)?
+    } else if text
+        .lines()
+        .any(|l| l.trim_start().starts_with("[program:"))
+    {
+        pm3::migrate::import_supervisord(&text)?
+    } else {
+        if !services_as_processes {
+            color_eyre::eyre::bail!(
+                "compose import runs each service's command directly on the host; \
+                 pass --services-as-processes to confirm"
+            );
+        }
+        pm3::compose::import(&text)?
+    };
+    std::fs::write(output, &imported.toml)?;
+
+    print_warnings(&imported.warnings);
+    println!(
+        "{} {} ({})",
+        "imported:".green(),
+        imported.processes.join(", "),
+        output.display()
+    );
+    Ok(())
+}
+
+fn init_project(
+    template: Option<pm3::cli::Template>,
+    interactive: bool,
+    output: &std::path::Path,
+) -> color_eyre::Result<()> {
+    if output.exists() {
+        color_eyre::eyre::bail!(
+            "{} already exists; pass --output to write elsewhere",
+            output.display()
+        );
+    }
+
+    let dir = std::env::current_dir()?;
+    let scaffold = if interactive {
+        pm3::wizard::run(std::io::stdin().lock(), std::io::stdout(), &dir)?
+    } else {
+        pm3::scaffold::scaffold(&dir, template)?
+    };
+    std::fs::write(output, &scaffold.toml)?;
+
+    println!(
+        "{} {} ({})",
+        "created:".green(),
+        scaffold.processes.join(", "),
+        output.display()
+    );
+    Ok(())
+}
+
+fn startup(
+    platform: pm3::cli::Platform,
+    scope: pm3::startup::Scope,
+    install: bool,
+) -> color_eyre::Result<()> {
+    use clap::ValueEnum;
+    use pm3::startup::{Scope, StartupError, Unit};
+
+    let paths = pm3::paths::Paths::new()?;
+    let exe = std::env::current_exe()?;
+    // Under sudo the unit should still run as the user who asked for it
+    let user = std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .ok();
+    let unit = Unit {
+        platform,
+        scope,
+        path: pm3::startup::unit_path(platform, scope)?,
+        contents: pm3::startup::render(platform, scope, &exe, &paths, user.as_deref()),
+    };
+
+    if !install {
+        print!("{}", unit.contents);
+        eprintln!(
+            "{} save this as {} or rerun with --install",
+            "hint:".cyan(),
+            unit.path.display()
+        );
+        return Ok(());
+    }
+
+    match pm3::startup::install(&unit) {
+        Ok(()) => {
+            println!("{} {}", "installed:".green(), unit.path.display());
+            Ok(())
+        }
+        Err(StartupError::PermissionDenied(path)) => {
+            print!("{}", unit.contents);
+            let flag = match scope {
+                Scope::User => "--user",
+                Scope::System => "--system",
+            };
+            let platform = platform.to_possible_value().expect("no skipped platforms");
+            color_eyre::eyre::bail!(
+                "no permission to write {}; save the unit above there yourself or run:\n  sudo env PM3_DATA_DIR={} {} startup --platform {} {flag} --install",
+                path.display(),
+                paths.data_dir().display(),
+                exe.display(),
+                platform.get_name()
+            )
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("{} {warning}", "warning:".yellow().bold());
+    }
+}
+
+fn print_backup(
+    action: &str,
+    manifest: &pm3::backup::BackupManifest,
+    file: &std::path::Path,
+    json: bool,
+) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(manifest).expect("failed to serialize manifest")
+        );
+        return;
+    }
+    let entries = if manifest.entries.is_empty() {
+        "nothing".to_string()
+    } else {
+        manifest.entries.join(", ")
+    };
+    println!("{} {entries} ({})", action.green(), file.display());
+}
+
+fn print_bench(report: &pm3::bench::BenchReport, json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(report).expect("failed to serialize report")
+        );
+        return;
+    }
+    let field = |label: &str, value: String| {
+        println!("  {:<16} {value}", format!("{label}:").dimmed());
+    };
+    field(
+        "processes",
+        format!(
+            "{} for {}",
+            report.processes,
+            format_uptime(Some(report.elapsed_ms / 1000))
+        ),
+    );
+    field("daemon cpu", format!("{:.1}%", report.daemon_cpu_percent));
+    field(
+        "daemon memory",
+        format!("{} peak", format_bytes(Some(report.daemon_peak_rss_bytes))),
+    );
+    field("restarts", report.restarts.to_string());
+    field(
+        "restart latency",
+        match report.restart_latency {
+            Some(l) => format!(
+                "p50 {}ms, p95 {}ms, max {}ms beyond backoff",
+                l.p50_ms, l.p95_ms, l.max_ms
+            ),
+            None => "-".to_string(),
+        },
+    );
+    field(
+        "log throughput",
+        format!(
+            "{}/s ({} total)",
+            format_bytes(Some(report.log_bytes_per_sec)),
+            format_bytes(Some(report.log_bytes))
+        ),
+    );
+}
+
+/// Processes a start request targets: the named ones, or every configured one.
+fn started_names(request: &Request) -> Vec<String> {
+    match request {
+        Request::Start { configs, names, .. } => {
+            let selected = names
+                .clone()
+                .unwrap_or_else(|| configs.keys().cloned().collect())
+                .into_iter()
+                .filter_map(|name| Some((name.clone(), configs.get(&name)?.clone())));
+            let mut names: Vec<String> = pm3::config::expand_instances(selected)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            names.sort();
+            names
+        }
+        _ => Vec::new(),
+    }
+}
+
 fn should_auto_list(request: &Request) -> bool {
     matches!(
         request,
@@ -53,71 +578,543 @@ fn should_auto_list(request: &Request) -> bool {
             | Request::Stop { .. }
             | Request::Restart { .. }
             | Request::Reload { .. }
+            | Request::Resurrect
     )
 }
 
+/// `--stagger` as milliseconds for the request.
+fn stagger_millis(stagger: Option<String>) -> color_eyre::Result<Option<u64>> {
+    stagger
+        .map(|s| {
+            let delay =
+                pm3::config::parse_duration(&s).map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+            Ok(delay.as_millis() as u64)
+        })
+        .transpose()
+}
+
+/// Load `pm3.toml` from the current directory for the daemon, with each
+/// process's `cwd` anchored there.
+fn load_project_config()
+-> color_eyre::Result<std::collections::HashMap<String, pm3::config::ProcessConfig>> {
+    let dir = std::env::current_dir()?;
+    let mut configs = pm3::config::load_config(&dir.join("pm3.toml"))
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    pm3::config::resolve_cwd(&mut configs, &dir);
+    Ok(configs)
+}
+
 fn command_to_request(command: Command) -> color_eyre::Result<Request> {
     match command {
-        Command::Start { names, env } => {
-            let config_path = std::env::current_dir()?.join("pm3.toml");
-            let configs = pm3::config::load_config(&config_path)
-                .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+        Command::Start {
+            names,
+            group,
+            env,
+            strict,
+            stagger,
+            ..
+        } => {
+            let configs = load_project_config()?;
+            let names = match group {
+                Some(group) => {
+                    let mut members: Vec<String> = configs
+                        .iter()
+                        .filter(|(_, c)| c.group.as_deref() == Some(group.as_str()))
+                        .map(|(name, _)| name.clone())
+                        .collect();
+                    if members.is_empty() {
+                        color_eyre::eyre::bail!("no processes in group: {group}");
+                    }
+                    members.sort();
+                    Some(members)
+                }
+                None => Command::optional_names(names),
+            };
             Ok(Request::Start {
                 configs,
-                names: Command::optional_names(names),
+                names,
+                env,
+                strict,
+                stagger: stagger_millis(stagger)?,
+                progress: true,
+            })
+        }
+        Command::Run { pipeline, env } => {
+            let configs = load_project_config()?;
+            // Report a bad pipeline before anything starts
+            pm3::pipeline::plan(&configs, &pipeline)?;
+            Ok(Request::RunPipeline {
+                configs,
+                target: pipeline,
                 env,
             })
         }
-        Command::Stop { names } => Ok(Request::Stop {
+        Command::Stop {
+            names,
+            group,
+            cascade,
+        } => Ok(Request::Stop {
             names: Command::optional_names(names),
+            group,
+            cascade,
         }),
-        Command::Restart { names } => Ok(Request::Restart {
+        Command::Restart {
+            names,
+            group,
+            rolling,
+            stagger,
+            cascade,
+        } => Ok(Request::Restart {
             names: Command::optional_names(names),
+            group,
+            rolling,
+            stagger: stagger_millis(stagger)?,
+            cascade,
         }),
-        Command::List => Ok(Request::List),
+        Command::List { jobs: false } => Ok(Request::List),
+        Command::List { jobs: true } => Ok(Request::Jobs),
         Command::Kill => Ok(Request::Kill),
         Command::Reload { names } => Ok(Request::Reload {
             names: Command::optional_names(names),
         }),
-        Command::Info { name } => Ok(Request::Info { name }),
-        Command::Signal { name, signal } => Ok(Request::Signal { name, signal }),
+        Command::Info { name, tree } => Ok(Request::Info { name, tree }),
+        Command::Signal {
+            name,
+            signal,
+            group_leader,
+        } => Ok(Request::Signal {
+            name,
+            signal,
+            group_leader,
+        }),
+        Command::Attach { name } => Ok(Request::Attach { name }),
+        Command::Scale { name, instances } => Ok(Request::Scale { name, instances }),
+        Command::AdvanceClock { by } => {
+            let by =
+                pm3::config::parse_duration(&by).map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+            Ok(Request::AdvanceClock {
+                by_ms: by.as_millis() as u64,
+            })
+        }
         Command::Save => Ok(Request::Save),
         Command::Resurrect => Ok(Request::Resurrect),
         Command::Flush { names } => Ok(Request::Flush {
             names: Command::optional_names(names),
         }),
+        Command::Prune { yes } => Ok(Request::Prune { apply: yes }),
         Command::Log {
             name,
+            group,
             lines,
             follow,
+            timestamps,
+            grep,
+            level,
+            since,
+            err,
+            out,
+            color,
+            no_color: _,
         } => Ok(Request::Log {
             name,
             lines,
             follow,
+            group,
+            timestamps,
+            grep,
+            level,
+            since: since_timestamp(since)?,
+            stream: match (out, err) {
+                (true, _) => Some(LogStream::Stdout),
+                (_, true) => Some(LogStream::Stderr),
+                _ => None,
+            },
+            color,
+        }),
+        Command::Config { command } => Ok(match command {
+            ConfigCommand::Get { key } => Request::ConfigGet { key: Some(key) },
+            ConfigCommand::List => Request::ConfigGet { key: None },
+            ConfigCommand::Set { key, value } => Request::ConfigSet {
+                key,
+                value: Some(value),
+            },
+            ConfigCommand::Unset { key } => Request::ConfigSet { key, value: None },
+        }),
+        Command::History {
+            name,
+            since,
+            events: false,
+        } => Ok(Request::History {
+            name,
+            since: since_timestamp(since)?,
+        }),
+        Command::History {
+            name,
+            since,
+            events: true,
+        } => Ok(Request::Events {
+            name,
+            since: since_timestamp(since)?,
+        }),
+        Command::Report { name, last } => Ok(Request::Samples {
+            name,
+            since: since_timestamp(Some(last))?,
+        }),
+        Command::Audit { since } => Ok(Request::Audit {
+            since: since_timestamp(since)?,
         }),
+        Command::Parse { .. }
+        | Command::Backup { .. }
+        | Command::Restore { .. }
+        | Command::Export { .. }
+        | Command::Graph { .. }
+        | Command::Import { .. }
+        | Command::Integrate { .. }
+        | Command::DaemonLog { .. }
+        | Command::Ping
+        | Command::Monit
+        | Command::Top { .. }
+        | Command::Update
+        | Command::Daemon { .. }
+        | Command::Bench { .. }
+        | Command::Init { .. }
+        | Command::Startup { .. }
+        | Command::Unstartup { .. }
+        | Command::Plugin(_) => unreachable!("handled by run_local_command"),
     }
 }
 
+/// The unix timestamp (ms) a `--since` window like `1h` reaches back to.
+fn since_timestamp(since: Option<String>) -> color_eyre::Result<Option<i64>> {
+    since
+        .map(|s| {
+            let window =
+                pm3::config::parse_duration(&s).map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+            Ok(chrono::Utc::now().timestamp_millis() - window.as_millis() as i64)
+        })
+        .transpose()
+}
+
 fn print_response_json(response: &Response) {
     let json = serde_json::to_string(response).expect("failed to serialize response");
     println!("{json}");
 }
 
+/// One `pm3 --json log` line: `{process, stream, timestamp, line}`, with
+/// `null` for what the daemon doesn't know.
+fn print_log_line_json(response: &Response, requested: Option<&str>) {
+    let Response::LogLine {
+        name,
+        line,
+        stream,
+        timestamp,
+    } = response
+    else {
+        return;
+    };
+    let json = serde_json::json!({
+        "process": name.as_deref().or(requested),
+        "stream": stream,
+        "timestamp": timestamp,
+        "line": line,
+    });
+    println!("{json}");
+}
+
+/// Whether to color output: not with `--no-color`, nor when NO_COLOR is set
+/// to anything.
+fn wants_color(no_color: bool) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Colors for the `[name]` of interleaved log lines; red is kept for stderr.
+const PROCESS_COLORS: [AnsiColors; 10] = [
+    AnsiColors::Cyan,
+    AnsiColors::Green,
+    AnsiColors::Yellow,
+    AnsiColors::Blue,
+    AnsiColors::Magenta,
+    AnsiColors::BrightCyan,
+    AnsiColors::BrightGreen,
+    AnsiColors::BrightYellow,
+    AnsiColors::BrightBlue,
+    AnsiColors::BrightMagenta,
+];
+
+/// The same color for a process every time, from an FNV-1a hash of its name.
+fn process_color(name: &str) -> AnsiColors {
+    let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    PROCESS_COLORS[hash as usize % PROCESS_COLORS.len()]
+}
+
+fn print_log_line(
+    name: Option<&str>,
+    line: &str,
+    stream: Option<LogStream>,
+    timestamp: Option<i64>,
+    colors: bool,
+) {
+    let paint = |text: String, style: Style| match colors {
+        true => text.style(style).to_string(),
+        false => text,
+    };
+    let mut out = String::new();
+    if let Some(name) = name {
+        let style = Style::new().color(process_color(name)).bold();
+        out += &paint(format!("[{name}] "), style);
+    }
+    if let Some(at) = timestamp.and_then(chrono::DateTime::from_timestamp_millis) {
+        let at = at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S");
+        out += &paint(format!("{at} "), Style::new().dimmed());
+    }
+    out += &match stream {
+        Some(LogStream::Stderr) => paint(line.to_string(), Style::new().red()),
+        _ => line.to_string(),
+    };
+    println!("{out}");
+}
+
 fn status_color(status: &ProcessStatus) -> Color {
     match status {
         ProcessStatus::Online => Color::Green,
         ProcessStatus::Starting => Color::Yellow,
         ProcessStatus::Unhealthy => Color::Magenta,
+        ProcessStatus::Stopping => Color::Yellow,
         ProcessStatus::Stopped => Color::Reset,
+        ProcessStatus::Completed => Color::Blue,
+        ProcessStatus::Crashed => Color::Red,
         ProcessStatus::Errored => Color::Red,
     }
 }
 
+/// Ask a running daemon, exiting 1 when there is none to ask: health checks
+/// must not start one.
+fn query_running_daemon(paths: &pm3::paths::Paths, request: &Request) -> Response {
+    match pm3::client::send_request_if_running(paths, request) {
+        Ok(Some(response)) => response,
+        Ok(None) => {
+            eprintln!("{} daemon is not running", "error:".red().bold());
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{} {e}", "error:".red().bold());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print the process table sorted by `sort`, once or, with `watch`, every
+/// `interval` until interrupted.
+fn top(
+    sort: TopSort,
+    watch: bool,
+    interval: std::time::Duration,
+    json: bool,
+) -> color_eyre::Result<()> {
+    let paths = pm3::paths::Paths::new()?;
+    loop {
+        let mut processes = match query_running_daemon(&paths, &Request::List) {
+            Response::ProcessList { processes } => processes,
+            other => {
+                print_response(&other);
+                std::process::exit(1);
+            }
+        };
+        sort_for_top(&mut processes, sort);
+        if json {
+            println!("{}", serde_json::to_string(&processes)?);
+        } else {
+            if watch {
+                // Clear the screen and home the cursor for the next frame
+                print!("\x1b[2J\x1b[H");
+            }
+            print_top(&processes, sort);
+        }
+        if !watch {
+            return Ok(());
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Busiest first; processes without a sample yet go last, by name.
+fn sort_for_top(processes: &mut [ProcessInfo], sort: TopSort) {
+    let key = |p: &ProcessInfo| match sort {
+        TopSort::Cpu => p.cpu_percent,
+        TopSort::Mem => p.memory_bytes.map(|b| b as f64),
+    };
+    processes.sort_by(|a, b| {
+        key(b)
+            .partial_cmp(&key(a))
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+fn print_top(processes: &[ProcessInfo], sort: TopSort) {
+    let sorted_by = match sort {
+        TopSort::Cpu => "cpu",
+        TopSort::Mem => "mem",
+    };
+    println!(
+        "{} {} {}",
+        chrono::Local::now().format("%H:%M:%S").to_string().dimmed(),
+        format!("{} processes,", processes.len()).dimmed(),
+        format!("sorted by {sorted_by}").dimmed()
+    );
+    if processes.is_empty() {
+        println!("{}", "no processes running".yellow());
+        return;
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(
+        [
+            "name", "pid", "status", "cpu", "mem", "threads", "fds", "procs", "uptime",
+        ]
+        .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+    );
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    for p in processes {
+        let mut cpu = Cell::new(or_dash(p.cpu_percent.map(|c| format!("{c:.1}%"))));
+        let mut mem = Cell::new(format_bytes(p.memory_bytes));
+        match sort {
+            TopSort::Cpu => cpu = cpu.add_attribute(Attribute::Bold),
+            TopSort::Mem => mem = mem.add_attribute(Attribute::Bold),
+        }
+        table.add_row(vec![
+            Cell::new(&p.name).fg(Color::Cyan),
+            Cell::new(or_dash(p.pid.map(|id| id.to_string()))),
+            Cell::new(p.status.to_string()).fg(status_color(&p.status)),
+            cpu,
+            mem,
+            Cell::new(or_dash(p.thread_count.map(|n| n.to_string()))),
+            Cell::new(or_dash(p.fd_count.map(|n| n.to_string()))),
+            Cell::new(or_dash(p.process_count.map(|n| n.to_string()))),
+            Cell::new(format_uptime(p.uptime)),
+        ]);
+    }
+    println!("{table}");
+}
+
+fn ping(json: bool) -> color_eyre::Result<()> {
+    let paths = pm3::paths::Paths::new()?;
+    let sent = std::time::Instant::now();
+    let response = query_running_daemon(&paths, &Request::Ping);
+    let elapsed = sent.elapsed();
+    if json {
+        print_response_json(&response);
+    } else if response == Response::Pong {
+        println!(
+            "{} ({:.1}ms)",
+            "pong".green(),
+            elapsed.as_secs_f64() * 1000.0
+        );
+    } else {
+        print_response(&response);
+    }
+    Ok(())
+}
+
+/// How long `pm3 update` waits for the new daemon to answer.
+const UPDATE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+fn update_daemon(json: bool) -> color_eyre::Result<()> {
+    let paths = pm3::paths::Paths::new()?;
+    let exe = std::env::current_exe()?;
+    let sent = std::time::Instant::now();
+    let response = query_running_daemon(
+        &paths,
+        &Request::Update {
+            exe: exe.to_string_lossy().into_owned(),
+        },
+    );
+    if matches!(response, Response::Error { .. }) {
+        if json {
+            print_response_json(&response);
+        } else {
+            print_response(&response);
+        }
+        return Ok(());
+    }
+
+    // Until the exec the old daemon may still answer; the new one has been
+    // up for less time than has passed since the request
+    while sent.elapsed() < UPDATE_TIMEOUT {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let Ok(Some(status)) = pm3::client::send_request_if_running(&paths, &Request::DaemonStatus)
+        else {
+            continue;
+        };
+        match status {
+            Response::DaemonStatus { status }
+                if u128::from(status.uptime_ms) < sent.elapsed().as_millis() =>
+            {
+                if json {
+                    print_response_json(&Response::DaemonStatus { status });
+                } else {
+                    println!(
+                        "{}",
+                        format!(
+                            "daemon updated to pm3 {} (pid {}, {} processes kept)",
+                            status.version, status.pid, status.processes
+                        )
+                        .green()
+                    );
+                }
+                return Ok(());
+            }
+            _ => continue,
+        }
+    }
+    color_eyre::eyre::bail!(
+        "the daemon did not come back after {}s; see `pm3 daemon-log`",
+        UPDATE_TIMEOUT.as_secs()
+    )
+}
+
+fn print_daemon_status(status: &DaemonStatus) {
+    println!("{}: {}", "daemon".cyan().bold(), "running".green());
+    let field = |label: &str, value: &dyn std::fmt::Display| {
+        println!("  {:<13} {value}", format!("{label}:").dimmed());
+    };
+    field("version", &status.version);
+    field("pid", &status.pid);
+    field("uptime", &format_uptime(Some(status.uptime_ms / 1000)));
+    field(
+        "processes",
+        &format!("{} ({} running)", status.processes, status.running),
+    );
+    field("socket", &status.socket);
+    field("data dir", &status.data_dir);
+    let mut logs = format_bytes(Some(status.log_bytes));
+    if let Some(budget) = status.log_budget {
+        logs += &format!(" of {}", format_bytes(Some(budget)));
+    }
+    if status.logs_pruned > 0 {
+        logs += &format!(
+            " ({} files pruned, {})",
+            status.logs_pruned,
+            format_bytes(Some(status.log_bytes_pruned))
+        );
+    }
+    field("logs", &logs);
+}
+
 fn print_response(response: &Response) {
     match response {
         Response::Success { message } => {
             if let Some(msg) = message {
-                println!("{}", msg.green());
+                // Daemon warnings ride along as `warning: ` lines
+                for line in msg.lines() {
+                    match line.strip_prefix("warning: ") {
+                        Some(warning) => print_warnings(&[warning.to_string()]),
+                        None => println!("{}", line.green()),
+                    }
+                }
             } else {
                 println!("{}", "ok".green());
             }
@@ -126,6 +1123,9 @@ fn print_response(response: &Response) {
             eprintln!("{} {}", "error:".red().bold(), message);
         }
         Response::ProcessList { processes } => {
+            if let Some(profile) = pm3::paths::active_profile() {
+                println!("{} {profile}", "profile:".dimmed());
+            }
             if processes.is_empty() {
                 println!("{}", "no processes running".yellow());
             } else {
@@ -133,63 +1133,645 @@ fn print_response(response: &Response) {
                 table.load_preset(UTF8_FULL_CONDENSED);
                 table.set_header(vec![
                     Cell::new("name").add_attribute(Attribute::Bold),
+                    Cell::new("group").add_attribute(Attribute::Bold),
                     Cell::new("pid").add_attribute(Attribute::Bold),
                     Cell::new("status").add_attribute(Attribute::Bold),
                     Cell::new("uptime").add_attribute(Attribute::Bold),
+                    Cell::new("mem").add_attribute(Attribute::Bold),
                     Cell::new("restarts").add_attribute(Attribute::Bold),
+                    Cell::new("last exit").add_attribute(Attribute::Bold),
+                    Cell::new("gen").add_attribute(Attribute::Bold),
                 ]);
+                let now = chrono::Utc::now().timestamp_millis();
                 for p in processes {
                     let pid = p
                         .pid
                         .map(|id| id.to_string())
                         .unwrap_or_else(|| "-".to_string());
-                    let uptime = format_uptime(p.uptime);
-                    let status = p.status.to_string();
-                    let restarts = p.restarts.to_string();
+                    // A finished task shows how it ended instead of an uptime
+                    let (status, uptime) = match (p.status, p.exit_code, p.duration_ms) {
+                        (ProcessStatus::Completed, Some(code), Some(ms)) => (
+                            format!("{} (exit {code})", p.status),
+                            format!("took {:.1}s", ms as f64 / 1000.0),
+                        ),
+                        _ => (p.status.to_string(), format_uptime(p.uptime)),
+                    };
+                    let memory = format_bytes(p.memory_bytes);
+                    let restarts = if p.memory_restarts > 0 {
+                        format!("{} ({} mem)", p.restarts, p.memory_restarts)
+                    } else {
+                        p.restarts.to_string()
+                    };
                     let restarts_cell = if p.restarts > 0 {
                         Cell::new(&restarts).fg(Color::Yellow)
                     } else {
                         Cell::new(&restarts)
                     };
+                    let last_exit = format_last_exit(
+                        p.last_exit_code,
+                        p.last_exit_signal.as_deref(),
+                        p.exited_at,
+                        now,
+                    );
+                    let last_exit_cell = match (&last_exit, p.last_exit_code) {
+                        (None, _) => Cell::new("-"),
+                        (Some(text), Some(0)) => Cell::new(text),
+                        (Some(text), _) => Cell::new(text).fg(Color::Red),
+                    };
                     table.add_row(vec![
                         Cell::new(&p.name).fg(Color::Cyan),
+                        Cell::new(p.group.as_deref().unwrap_or("-")),
                         Cell::new(&pid),
                         Cell::new(&status).fg(status_color(&p.status)),
                         Cell::new(&uptime),
+                        Cell::new(&memory),
                         restarts_cell,
+                        last_exit_cell,
+                        Cell::new(p.generation),
                     ]);
                 }
                 println!("{table}");
             }
         }
-        Response::ProcessDetail { info } => {
-            let status_str = info.status.to_string();
-            let colored_status = match info.status {
-                ProcessStatus::Online => status_str.green().to_string(),
-                ProcessStatus::Starting => status_str.yellow().to_string(),
-                ProcessStatus::Unhealthy => status_str.magenta().to_string(),
-                ProcessStatus::Stopped => status_str.to_string(),
-                ProcessStatus::Errored => status_str.red().to_string(),
-            };
-            println!("{}: {}", info.name.cyan().bold(), colored_status);
-            println!("  {} {}", "command:".dimmed(), info.command);
-            if let Some(pid) = info.pid {
-                println!("  {} {pid}", "pid:".dimmed());
+        Response::JobList { jobs } => print_jobs(jobs),
+        Response::Pong => println!("{}", "pong".green()),
+        Response::DaemonStatus { status } => print_daemon_status(status),
+        Response::Config { settings } => {
+            for setting in settings {
+                match &setting.value {
+                    Some(value) => println!("{} = {value}", setting.key),
+                    None => println!(
+                        "{} = {} {}",
+                        setting.key,
+                        setting.default,
+                        "(default)".dimmed()
+                    ),
+                }
             }
-            if let Some(cwd) = &info.cwd {
-                println!("  {} {cwd}", "cwd:".dimmed());
+        }
+        Response::ProcessDetail { info } => print_detail(info),
+        Response::LogLine {
+            name,
+            line,
+            stream,
+            timestamp,
+        } => print_log_line(
+            name.as_deref(),
+            line,
+            *stream,
+            *timestamp,
+            wants_color(false),
+        ),
+        Response::History { runs } => print_history(runs),
+        Response::Events { events } => print_events(events),
+        Response::Samples { samples } => print_report(samples),
+        Response::Audit { entries } => print_audit(entries),
+        Response::PipelineStep { step } => print_pipeline_step(step),
+        Response::StartProgress { result } => {
+            let status = match result.status {
+                ResultStatus::Succeeded => "started".green().to_string(),
+                ResultStatus::Skipped => "skipped".dimmed().to_string(),
+                ResultStatus::Failed => "failed".red().bold().to_string(),
+            };
+            match &result.detail {
+                Some(detail) => {
+                    println!("{} {status} ({detail})", format!("{}:", result.name).bold())
+                }
+                None => println!("{} {status}", format!("{}:", result.name).bold()),
             }
         }
-        Response::LogLine { name, line } => {
-            if let Some(name) = name {
-                println!("{} {line}", format!("[{name}]").cyan().bold());
+        Response::Results {
+            action,
+            results,
+            warnings,
+        } => print_results(action, results, warnings),
+        Response::PipelineDone { target, steps } => {
+            let count = |status| steps.iter().filter(|s| s.status == status).count();
+            let failed = count(StepStatus::Failed);
+            if failed == 0 && count(StepStatus::Skipped) == 0 {
+                println!(
+                    "{} {target} ({} task{})",
+                    "pipeline succeeded:".green(),
+                    steps.len(),
+                    if steps.len() == 1 { "" } else { "s" }
+                );
             } else {
-                println!("{line}");
+                eprintln!(
+                    "{} {target} ({} succeeded, {failed} failed, {} skipped)",
+                    "pipeline failed:".red().bold(),
+                    count(StepStatus::Succeeded),
+                    count(StepStatus::Skipped)
+                );
             }
         }
     }
 }
 
+/// Summarize a bulk operation: what succeeded and what was skipped on a line
+/// each, then every failure with its reason.
+fn print_results(action: &str, results: &[ProcessResult], warnings: &[String]) {
+    let labels = |status| {
+        results
+            .iter()
+            .filter(|r| r.status == status)
+            .map(|r| match &r.detail {
+                Some(detail) => format!("{} ({detail})", r.name),
+                None => r.name.clone(),
+            })
+            .collect::<Vec<_>>()
+    };
+    let succeeded = labels(ResultStatus::Succeeded);
+    let skipped = labels(ResultStatus::Skipped);
+    if !succeeded.is_empty() {
+        println!("{}", format!("{action}: {}", succeeded.join(", ")).green());
+    }
+    if !skipped.is_empty() {
+        println!("{} {}", "skipped:".yellow(), skipped.join(", "));
+    }
+    if results.is_empty() {
+        println!("{}", format!("{action}: nothing").green());
+    }
+    for failed in results.iter().filter(|r| r.status == ResultStatus::Failed) {
+        let reason = failed.detail.as_deref().unwrap_or("unknown error");
+        eprintln!("{} {}: {reason}", "failed:".red().bold(), failed.name);
+    }
+    print_warnings(warnings);
+}
+
+fn print_pipeline_step(step: &PipelineStep) {
+    let mut details = Vec::new();
+    if step.status == StepStatus::Failed
+        && let Some(code) = step.exit_code
+    {
+        details.push(format!("exit {code}"));
+    }
+    if let Some(ms) = step.duration_ms {
+        details.push(format!("{:.1}s", ms as f64 / 1000.0));
+    }
+    let details = if details.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", details.join(", "))
+    };
+    let status = step.status.to_string();
+    let status = match step.status {
+        StepStatus::Running => status.cyan().to_string(),
+        StepStatus::Succeeded => status.green().to_string(),
+        StepStatus::Failed => status.red().bold().to_string(),
+        StepStatus::Skipped => status.dimmed().to_string(),
+    };
+    println!("{} {status}{details}", format!("{}:", step.name).bold());
+}
+
+fn print_detail(info: &ProcessDetail) {
+    let status = info.status.to_string();
+    let status = match info.status {
+        ProcessStatus::Online => status.green().to_string(),
+        ProcessStatus::Starting => status.yellow().to_string(),
+        ProcessStatus::Unhealthy => status.magenta().to_string(),
+        ProcessStatus::Stopping => status.yellow().to_string(),
+        ProcessStatus::Stopped => status.to_string(),
+        ProcessStatus::Completed => status.blue().to_string(),
+        ProcessStatus::Crashed | ProcessStatus::Errored => status.red().to_string(),
+    };
+    println!("{}: {status}", info.name.cyan().bold());
+
+    let field = |label: &str, value: &dyn std::fmt::Display| {
+        println!("  {:<13} {value}", format!("{label}:").dimmed());
+    };
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+    field("command", &info.command);
+    field("cwd", &optional(info.cwd.clone()));
+    field("pid", &optional(info.pid.map(|p| p.to_string())));
+    field("uptime", &format_uptime(info.uptime));
+    if let Some(group) = &info.group {
+        field("group", group);
+    }
+    field("generation", &info.generation);
+    if let Some(environment) = &info.environment {
+        field("environment", environment);
+    }
+    let restarts = if info.memory_restarts > 0 {
+        format!("{} ({} mem)", info.restarts, info.memory_restarts)
+    } else {
+        info.restarts.to_string()
+    };
+    field("restarts", &restarts);
+    if let Some(reason) = &info.last_restart {
+        field("last restart", reason);
+    }
+    if let Some(ms) = info.restart_throttle_ms {
+        field(
+            "throttled",
+            &format!(
+                "next restart waits {:.1}s for restart_window",
+                ms as f64 / 1000.0
+            ),
+        );
+    }
+    let last_exit = format_last_exit(
+        info.exit_code,
+        info.exit_signal.as_deref(),
+        info.exited_at,
+        chrono::Utc::now().timestamp_millis(),
+    );
+    field("last exit", &optional(last_exit));
+
+    let resources = &info.resources;
+    field(
+        "memory",
+        &format!(
+            "{} (peak {})",
+            format_bytes(info.memory_bytes.or(resources.rss_bytes)),
+            format_bytes(resources.peak_rss_bytes)
+        ),
+    );
+    field(
+        "cpu",
+        &match info.cpu_percent {
+            Some(percent) => format!("{percent:.1}%"),
+            None => "-".to_string(),
+        },
+    );
+    field(
+        "cpu time",
+        &optional(
+            resources
+                .cpu_time_ms
+                .map(|ms| format!("{:.2}s", ms as f64 / 1000.0)),
+        ),
+    );
+    field("fds", &optional(resources.fd_count.map(|n| n.to_string())));
+    field(
+        "threads",
+        &optional(resources.thread_count.map(|n| n.to_string())),
+    );
+    field(
+        "processes",
+        &optional(resources.process_count.map(|n| n.to_string())),
+    );
+    for (name, value) in &info.metrics {
+        field(name, value);
+    }
+
+    if let Some(health_check) = &info.health_check {
+        field("health check", health_check);
+    }
+    if let Some(depends_on) = &info.depends_on {
+        field("depends on", &depends_on.join(", "));
+    }
+    field("stdout log", &optional(info.stdout_log.clone()));
+    field("stderr log", &optional(info.stderr_log.clone()));
+
+    if let Some(env) = info.env.as_ref().filter(|env| !env.is_empty()) {
+        println!("  {}", "env:".dimmed());
+        let mut env: Vec<_> = env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            println!("    {key}={value}");
+        }
+    }
+
+    if !info.tree.is_empty() {
+        print_tree(&info.tree);
+    }
+}
+
+/// Each process of a tree with its share of the tree's memory and CPU time.
+fn print_tree(tree: &[TreeMember]) {
+    let total_rss: u64 = tree.iter().map(|m| m.rss_bytes).sum();
+    let total_cpu: u64 = tree.iter().map(|m| m.cpu_time_ms).sum();
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(
+        [
+            "pid", "ppid", "command", "memory", "mem %", "cpu time", "cpu %",
+        ]
+        .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+    );
+    for member in tree {
+        table.add_row(vec![
+            Cell::new(member.pid),
+            Cell::new(
+                member
+                    .ppid
+                    .map(|p| p.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+            Cell::new(&member.command),
+            Cell::new(format_bytes(Some(member.rss_bytes))),
+            Cell::new(format_share(member.rss_bytes, total_rss)),
+            Cell::new(format!("{:.2}s", member.cpu_time_ms as f64 / 1000.0)),
+            Cell::new(format_share(member.cpu_time_ms, total_cpu)),
+        ]);
+    }
+    println!("{table}");
+}
+
+/// `part` as a percentage of `total`; `-` when there is nothing to share.
+fn format_share(part: u64, total: u64) -> String {
+    if total == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", part as f64 * 100.0 / total as f64)
+}
+
+fn print_jobs(jobs: &[JobInfo]) {
+    if jobs.is_empty() {
+        println!("{}", "no jobs scheduled".yellow());
+        return;
+    }
+
+    let local_time = |millis: Option<i64>| {
+        millis
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(
+        [
+            "name", "schedule", "next run", "last run", "status", "exit", "took",
+        ]
+        .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+    );
+    for job in jobs {
+        let (status, color) = match job.last_run {
+            None => ("never run".to_string(), Color::Reset),
+            Some(_) => (job.status.to_string(), status_color(&job.status)),
+        };
+        table.add_row(vec![
+            Cell::new(&job.name).fg(Color::Cyan),
+            Cell::new(&job.schedule),
+            Cell::new(match job.next_run {
+                Some(_) => local_time(job.next_run),
+                None => "stopped".to_string(),
+            }),
+            Cell::new(local_time(job.last_run)),
+            Cell::new(status).fg(color),
+            Cell::new(
+                job.exit_code
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+            Cell::new(
+                job.duration_ms
+                    .map(|ms| format!("{:.1}s", ms as f64 / 1000.0))
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+        ]);
+    }
+    println!("{table}");
+}
+
+fn print_audit(entries: &[AuditEntry]) {
+    if entries.is_empty() {
+        println!("{}", "no requests recorded".yellow());
+        return;
+    }
+
+    // Only daemons with `listen_tcp` have remote clients to show
+    let remote = entries.iter().any(|entry| entry.remote.is_some());
+    let mut header = vec!["time", "uid", "pid"];
+    if remote {
+        header.push("remote");
+    }
+    header.extend(["request", "args", "outcome"]);
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(
+        header
+            .into_iter()
+            .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+    );
+    for entry in entries {
+        let at = chrono::DateTime::from_timestamp_millis(entry.at)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".to_string());
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        let args = match entry.args.as_object() {
+            Some(args) if args.is_empty() => String::new(),
+            _ => entry.args.to_string(),
+        };
+        let outcome_color = if entry.outcome == "ok" {
+            Color::Green
+        } else {
+            Color::Red
+        };
+        let mut row = vec![
+            Cell::new(at),
+            Cell::new(or_dash(entry.uid.map(|uid| uid.to_string()))),
+            Cell::new(or_dash(entry.pid.map(|pid| pid.to_string()))),
+        ];
+        if remote {
+            row.push(Cell::new(or_dash(entry.remote.clone())));
+        }
+        row.extend([
+            Cell::new(&entry.request).fg(Color::Cyan),
+            Cell::new(args),
+            Cell::new(&entry.outcome).fg(outcome_color),
+        ]);
+        table.add_row(row);
+    }
+    println!("{table}");
+}
+
+fn print_events(events: &[ProcessEvent]) {
+    if events.is_empty() {
+        println!("{}", "no events recorded".yellow());
+        return;
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(
+        ["time", "name", "event", "pid", "exit", "detail"]
+            .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+    );
+    for event in events {
+        let at = chrono::DateTime::from_timestamp_millis(event.timestamp)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".to_string());
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        let color = match event.event {
+            EventKind::Start => Color::Green,
+            EventKind::Exit | EventKind::Deferred => Color::Yellow,
+            EventKind::Restart => Color::Cyan,
+            EventKind::Unhealthy
+            | EventKind::MemoryLimit
+            | EventKind::FdLimit
+            | EventKind::Errored
+            | EventKind::Alert => Color::Red,
+        };
+        // An exit's detail is how it ended: its status and any failure reason
+        let detail = match (event.status, event.reason) {
+            (Some(status), Some(reason)) => Some(format!("{status} ({reason})")),
+            (Some(status), None) if event.event == EventKind::Exit => Some(status.to_string()),
+            _ => event.detail.clone(),
+        };
+        table.add_row(vec![
+            Cell::new(at),
+            Cell::new(&event.name).fg(Color::Cyan),
+            Cell::new(event.event).fg(color),
+            Cell::new(or_dash(event.pid.map(|pid| pid.to_string()))),
+            Cell::new(or_dash(event.exit_code.map(|code| code.to_string()))),
+            Cell::new(or_dash(detail)),
+        ]);
+    }
+    println!("{table}");
+}
+
+fn print_report(samples: &[StatsSample]) {
+    let report = pm3::report::Report::new(samples);
+    let Some((from, to)) = report.span else {
+        println!("{}", "no samples recorded".yellow());
+        return;
+    };
+
+    let at = |ms: i64| {
+        chrono::DateTime::from_timestamp_millis(ms)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".to_string())
+    };
+    println!(
+        "{} {} samples from {} to {}",
+        format!("{}:", samples[0].name).bold(),
+        report.samples,
+        at(from),
+        at(to)
+    );
+
+    let row = |label: &str, [min, avg, max]: [String; 3], sparkline: &str| {
+        println!(
+            "  {:<6}  min {min:>7}  avg {avg:>7}  max {max:>7}  {}",
+            label.bold(),
+            sparkline.cyan()
+        );
+    };
+    if let Some(memory) = report.memory {
+        let bytes = |value: f64| format_bytes(Some(value as u64));
+        row(
+            "memory",
+            [bytes(memory.min), bytes(memory.avg), bytes(memory.max)],
+            &report.memory_sparkline,
+        );
+    }
+    if let Some(cpu) = report.cpu {
+        let percent = |value: f64| format!("{value:.1}%");
+        row(
+            "cpu",
+            [percent(cpu.min), percent(cpu.avg), percent(cpu.max)],
+            &report.cpu_sparkline,
+        );
+    }
+}
+
+fn print_history(runs: &[RunRecord]) {
+    if runs.is_empty() {
+        println!("{}", "no history recorded".yellow());
+        return;
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(
+        [
+            "name", "gen", "ended", "runtime", "status", "exit", "peak mem", "cpu", "fds", "logs",
+        ]
+        .map(|h| Cell::new(h).add_attribute(Attribute::Bold)),
+    );
+
+    let mut previous_peak: std::collections::HashMap<&str, u64> = Default::default();
+    for run in runs {
+        let snapshot = &run.snapshot;
+        let peak = snapshot.peak_rss_bytes.or(snapshot.rss_bytes);
+        let mut memory = format_bytes(peak);
+        if let (Some(prev), Some(cur)) = (previous_peak.get(run.name.as_str()), peak)
+            && let Some(ratio) = format_ratio(*prev, cur)
+        {
+            memory = format!("{memory} ({ratio})");
+        }
+        if let Some(cur) = peak {
+            previous_peak.insert(&run.name, cur);
+        }
+
+        let ended = chrono::DateTime::from_timestamp_millis(run.ended_at)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".to_string());
+        let status = match run.reason {
+            Some(reason) if reason.held_back() => reason.to_string(),
+            Some(reason) => format!("{} ({reason})", run.status),
+            None => run.status.to_string(),
+        };
+
+        table.add_row(vec![
+            Cell::new(&run.name).fg(Color::Cyan),
+            Cell::new(run.generation),
+            Cell::new(ended),
+            Cell::new(format_uptime(Some(snapshot.runtime_ms / 1000))),
+            Cell::new(&status).fg(status_color(&run.status)),
+            Cell::new(
+                run.exit_code
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+            Cell::new(memory),
+            Cell::new(
+                snapshot
+                    .cpu_time_ms
+                    .map(|ms| format!("{:.2}s", ms as f64 / 1000.0))
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+            Cell::new(
+                snapshot
+                    .fd_count
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+            Cell::new(format_bytes(Some(snapshot.log_bytes))),
+        ]);
+    }
+    println!("{table}");
+}
+
+/// Relative change against the previous run, shown only when it is notable.
+fn format_ratio(previous: u64, current: u64) -> Option<String> {
+    if previous == 0 {
+        return None;
+    }
+    let ratio = current as f64 / previous as f64;
+    if (0.9..=1.1).contains(&ratio) {
+        None
+    } else {
+        Some(format!("{ratio:.1}×"))
+    }
+}
+
 fn format_uptime(seconds: Option<u64>) -> String {
     match seconds {
         None => "-".to_string(),
@@ -200,10 +1782,55 @@ fn format_uptime(seconds: Option<u64>) -> String {
     }
 }
 
+/// How the last run ended and how long ago, e.g. `code 1, 5m 3s ago` or
+/// `SIGKILL, 12s ago`.
+fn format_last_exit(
+    code: Option<i32>,
+    signal: Option<&str>,
+    exited_at: Option<i64>,
+    now: i64,
+) -> Option<String> {
+    let how = match (code, signal) {
+        (Some(code), _) => format!("code {code}"),
+        (None, Some(signal)) => signal.to_string(),
+        (None, None) => return None,
+    };
+    Some(match exited_at {
+        Some(at) => {
+            let ago = (now - at).max(0) as u64 / 1000;
+            format!("{how}, {} ago", format_uptime(Some(ago)))
+        }
+        None => how,
+    })
+}
+
+fn format_bytes(bytes: Option<u64>) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+    match bytes {
+        None => "-".to_string(),
+        Some(b) if b < MB => format!("{}K", b / KB),
+        Some(b) if b < GB => format!("{:.1}M", b as f64 / MB as f64),
+        Some(b) => format!("{:.1}G", b as f64 / GB as f64),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_process_color_is_stable_and_never_red() {
+        assert_eq!(process_color("web"), process_color("web"));
+        let colors: std::collections::HashSet<_> = ["web", "worker", "api", "db", "cron"]
+            .iter()
+            .map(|name| format!("{:?}", process_color(name)))
+            .collect();
+        assert!(colors.len() > 1);
+        assert!(!PROCESS_COLORS.contains(&AnsiColors::Red));
+    }
+
     #[test]
     fn test_format_uptime_none() {
         assert_eq!(format_uptime(None), "-");
@@ -236,4 +1863,71 @@ mod tests {
         assert_eq!(format_uptime(Some(90000)), "1d 1h");
         assert_eq!(format_uptime(Some(172800)), "2d 0h");
     }
+
+    #[test]
+    fn test_format_last_exit() {
+        let now = 1_700_000_000_000;
+        assert_eq!(format_last_exit(None, None, None, now), None);
+        assert_eq!(
+            format_last_exit(Some(3), None, Some(now - 90_000), now).as_deref(),
+            Some("code 3, 1m 30s ago")
+        );
+        assert_eq!(
+            format_last_exit(None, Some("SIGKILL"), Some(now - 5_000), now).as_deref(),
+            Some("SIGKILL, 5s ago")
+        );
+        assert_eq!(
+            format_last_exit(Some(0), None, None, now).as_deref(),
+            Some("code 0")
+        );
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(None), "-");
+        assert_eq!(format_bytes(Some(512 * 1024)), "512K");
+        assert_eq!(format_bytes(Some(52_428_800)), "50.0M");
+        assert_eq!(format_bytes(Some(3 * 1024 * 1024 * 1024)), "3.0G");
+    }
+
+    #[test]
+    fn test_sort_for_top() {
+        let process = |name: &str, cpu: Option<f64>, mem: Option<u64>| -> ProcessInfo {
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "status": "online",
+                "cpu_percent": cpu,
+                "memory_bytes": mem,
+            }))
+            .unwrap()
+        };
+        let mut processes = vec![
+            process("idle", Some(0.5), Some(900)),
+            process("new", None, None),
+            process("busy", Some(80.0), Some(100)),
+            process("also-new", None, None),
+        ];
+        let names = |processes: &[ProcessInfo]| {
+            processes.iter().map(|p| p.name.clone()).collect::<Vec<_>>()
+        };
+
+        sort_for_top(&mut processes, TopSort::Cpu);
+        assert_eq!(names(&processes), ["busy", "idle", "also-new", "new"]);
+        sort_for_top(&mut processes, TopSort::Mem);
+        assert_eq!(names(&processes), ["idle", "busy", "also-new", "new"]);
+    }
+
+    #[test]
+    fn test_format_share() {
+        assert_eq!(format_share(1, 4), "25.0%");
+        assert_eq!(format_share(0, 0), "-");
+    }
+
+    #[test]
+    fn test_format_ratio() {
+        assert_eq!(format_ratio(100, 200).as_deref(), Some("2.0×"));
+        assert_eq!(format_ratio(200, 100).as_deref(), Some("0.5×"));
+        assert_eq!(format_ratio(100, 105), None);
+        assert_eq!(format_ratio(0, 100), None);
+    }
 }
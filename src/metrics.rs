@@ -0,0 +1,393 @@
+use crate::config::ConfigError;
+use crate::http;
+use crate::process::Processes;
+use crate::protocol::{ProcessInfo, ProcessStatus};
+use crate::settings::MetricsSection;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// StatsD lines share a packet up to this size, which stays under the usual
+/// Ethernet MTU.
+const MAX_PACKET: usize = 1432;
+
+/// Longest an OTLP push may take; a slower one is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ---------------------------------------------------------------------------
+// Types
+// ---------------------------------------------------------------------------
+
+/// One gauge reading of one process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    /// Name after the prefix, e.g. `memory_bytes`.
+    pub metric: String,
+    /// OTLP unit, e.g. `By`; empty for the process's own gauges.
+    pub unit: &'static str,
+    pub process: String,
+    pub group: Option<String>,
+    pub value: f64,
+}
+
+/// Pushes the process table's metrics to the `[metrics]` targets.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    statsd: Option<String>,
+    dogstatsd: bool,
+    otlp: Option<String>,
+    interval: Duration,
+    prefix: String,
+}
+
+/// Whether a target's last push failed, so a target that is down is
+/// reported once rather than on every push.
+#[derive(Debug, Default)]
+struct Failing {
+    statsd: bool,
+    otlp: bool,
+}
+
+// ---------------------------------------------------------------------------
+// Exporting
+// ---------------------------------------------------------------------------
+
+impl Exporter {
+    /// The exporter `section` sets up, or `None` when it names no target.
+    pub fn new(section: &MetricsSection) -> Result<Option<Self>, ConfigError> {
+        let statsd = section.statsd()?.map(str::to_string);
+        let otlp = section.otlp()?.map(str::to_string);
+        if statsd.is_none() && otlp.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            statsd,
+            dogstatsd: section.dogstatsd,
+            otlp,
+            interval: section.interval()?,
+            prefix: section.prefix().to_string(),
+        }))
+    }
+
+    /// Push every `interval` until aborted.
+    pub async fn run(self, processes: Processes) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut failing = Failing::default();
+        loop {
+            interval.tick().await;
+            let points: Vec<Point> = {
+                let table = processes.snapshot().await;
+                let mut infos: Vec<ProcessInfo> =
+                    table.values().map(|m| m.to_process_info()).collect();
+                infos.sort_by(|a, b| a.name.cmp(&b.name));
+                infos.iter().flat_map(points).collect()
+            };
+            if points.is_empty() {
+                continue;
+            }
+
+            if let Some(address) = &self.statsd {
+                let packets = statsd_packets(&points, &self.prefix, self.dogstatsd);
+                report(
+                    &mut failing.statsd,
+                    "statsd",
+                    address,
+                    send_statsd(address, &packets).await,
+                );
+            }
+            if let Some(url) = &self.otlp {
+                let now = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+                let body = otlp_body(&points, &self.prefix, now).to_string();
+                let sent =
+                    tokio::time::timeout(REQUEST_TIMEOUT, http::post_json(url, body.as_bytes()))
+                        .await
+                        .unwrap_or_else(|_| Err("timed out".to_string()));
+                report(&mut failing.otlp, "otlp", url, sent);
+            }
+        }
+    }
+}
+
+fn report(failing: &mut bool, kind: &str, target: &str, result: Result<(), String>) {
+    match result {
+        Ok(()) => *failing = false,
+        Err(e) if !*failing => {
+            warn!("metrics: {kind} push to {target} failed: {e}");
+            *failing = true;
+        }
+        Err(_) => {}
+    }
+}
+
+async fn send_statsd(address: &str, packets: &[String]) -> Result<(), String> {
+    let target = tokio::net::lookup_host(address)
+        .await
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{address} did not resolve"))?;
+    let local = if target.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(local).await.map_err(|e| e.to_string())?;
+    for packet in packets {
+        socket
+            .send_to(packet.as_bytes(), target)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// The gauges of one process. A process that is not running only reports
+/// that it is down and how often it has restarted.
+pub fn points(info: &ProcessInfo) -> Vec<Point> {
+    let point = |metric: &str, unit, value: f64| Point {
+        metric: metric.to_string(),
+        unit,
+        process: info.name.clone(),
+        group: info.group.clone(),
+        value,
+    };
+    let up = matches!(
+        info.status,
+        ProcessStatus::Online | ProcessStatus::Unhealthy
+    );
+    let mut points = vec![
+        point("up", "1", if up { 1.0 } else { 0.0 }),
+        point("restarts", "1", info.restarts as f64),
+    ];
+    if info.pid.is_none() {
+        return points;
+    }
+    let optional = [
+        ("uptime_seconds", "s", info.uptime.map(|s| s as f64)),
+        ("memory_bytes", "By", info.memory_bytes.map(|b| b as f64)),
+        ("cpu_percent", "%", info.cpu_percent),
+        ("fds", "1", info.fd_count.map(f64::from)),
+        ("threads", "1", info.thread_count.map(f64::from)),
+        ("processes", "1", info.process_count.map(f64::from)),
+    ];
+    for (metric, unit, value) in optional {
+        if let Some(value) = value {
+            points.push(point(metric, unit, value));
+        }
+    }
+    for (name, value) in &info.metrics {
+        points.push(point(name, "", *value));
+    }
+    points
+}
+
+// ---------------------------------------------------------------------------
+// StatsD
+// ---------------------------------------------------------------------------
+
+/// Gauge lines, packed into as few packets as fit. Plain StatsD names the
+/// process in the metric (`pm3.web.cpu_percent:1.5|g`); DogStatsD tags it
+/// (`pm3.cpu_percent:1.5|g|#process:web`).
+pub fn statsd_packets(points: &[Point], prefix: &str, dogstatsd: bool) -> Vec<String> {
+    let mut packets: Vec<String> = Vec::new();
+    for point in points {
+        let metric = sanitize_metric(&point.metric);
+        let line = if dogstatsd {
+            let mut tags = format!("process:{}", sanitize_tag(&point.process));
+            if let Some(group) = &point.group {
+                tags.push_str(&format!(",group:{}", sanitize_tag(group)));
+            }
+            format!("{prefix}.{metric}:{}|g|#{tags}", point.value)
+        } else {
+            let process = sanitize_metric(&point.process).replace('.', "_");
+            format!("{prefix}.{process}.{metric}:{}|g", point.value)
+        };
+        match packets.last_mut() {
+            Some(packet) if packet.len() + 1 + line.len() <= MAX_PACKET => {
+                packet.push('\n');
+                packet.push_str(&line);
+            }
+            _ => packets.push(line),
+        }
+    }
+    packets
+}
+
+/// Keep what StatsD takes in a metric name; `:`, `|`, `@` and the like
+/// would end it early.
+fn sanitize_metric(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn sanitize_tag(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if matches!(c, ',' | '|' | '#') { '_' } else { c })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// OTLP
+// ---------------------------------------------------------------------------
+
+/// An OTLP/HTTP JSON `ExportMetricsServiceRequest` with one gauge per
+/// metric and a data point per process, taken at `now` (unix nanoseconds).
+pub fn otlp_body(points: &[Point], prefix: &str, now: i64) -> serde_json::Value {
+    let mut metrics: BTreeMap<&str, (&str, Vec<serde_json::Value>)> = BTreeMap::new();
+    for point in points {
+        let mut attributes = vec![attribute("process", &point.process)];
+        if let Some(group) = &point.group {
+            attributes.push(attribute("group", group));
+        }
+        metrics
+            .entry(&point.metric)
+            .or_insert((point.unit, Vec::new()))
+            .1
+            .push(serde_json::json!({
+                "asDouble": point.value,
+                "timeUnixNano": now.to_string(),
+                "attributes": attributes,
+            }));
+    }
+    let metrics: Vec<_> = metrics
+        .into_iter()
+        .map(|(metric, (unit, data_points))| {
+            serde_json::json!({
+                "name": format!("{prefix}.{metric}"),
+                "unit": unit,
+                "gauge": { "dataPoints": data_points },
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "resourceMetrics": [{
+            "resource": { "attributes": [attribute("service.name", "pm3")] },
+            "scopeMetrics": [{
+                "scope": { "name": "pm3", "version": env!("CARGO_PKG_VERSION") },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &str) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": { "stringValue": value } })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(status: &str, pid: Option<u32>) -> ProcessInfo {
+        serde_json::from_value(serde_json::json!({
+            "name": "web.api",
+            "status": status,
+            "pid": pid,
+            "group": "backend",
+            "restarts": 2,
+            "uptime": 60,
+            "memory_bytes": 1048576,
+            "cpu_percent": 1.5,
+            "metrics": { "queue depth": 12.0 },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_points_of_running_and_stopped() {
+        let running = points(&info("online", Some(42)));
+        let names: Vec<_> = running.iter().map(|p| p.metric.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "up",
+                "restarts",
+                "uptime_seconds",
+                "memory_bytes",
+                "cpu_percent",
+                "queue depth"
+            ]
+        );
+        assert_eq!(running[0].value, 1.0);
+
+        let stopped = points(&info("stopped", None));
+        assert_eq!(stopped.len(), 2);
+        assert_eq!((stopped[0].value, stopped[1].value), (0.0, 2.0));
+    }
+
+    #[test]
+    fn test_statsd_lines() {
+        let points = points(&info("online", Some(42)));
+        let plain = statsd_packets(&points, "pm3", false);
+        assert_eq!(plain.len(), 1);
+        let lines: Vec<_> = plain[0].lines().collect();
+        assert_eq!(lines[0], "pm3.web_api.up:1|g");
+        assert_eq!(lines[3], "pm3.web_api.memory_bytes:1048576|g");
+        assert_eq!(lines[5], "pm3.web_api.queue_depth:12|g");
+
+        let dog = statsd_packets(&points, "pm3", true);
+        assert_eq!(
+            dog[0].lines().nth(4),
+            Some("pm3.cpu_percent:1.5|g|#process:web.api,group:backend")
+        );
+    }
+
+    #[test]
+    fn test_statsd_packets_stay_small() {
+        let many: Vec<Point> = (0..200)
+            .map(|i| Point {
+                metric: "memory_bytes".to_string(),
+                unit: "By",
+                process: format!("worker-{i}"),
+                group: None,
+                value: 1e9,
+            })
+            .collect();
+        let packets = statsd_packets(&many, "pm3", false);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= MAX_PACKET));
+        let lines: usize = packets.iter().map(|p| p.lines().count()).sum();
+        assert_eq!(lines, 200);
+    }
+
+    #[test]
+    fn test_otlp_body() {
+        let body = otlp_body(&points(&info("online", Some(42))), "pm3", 1_700_000_000);
+        let scope = &body["resourceMetrics"][0]["scopeMetrics"][0];
+        let metrics = scope["metrics"].as_array().unwrap();
+        let memory = metrics
+            .iter()
+            .find(|m| m["name"] == "pm3.memory_bytes")
+            .unwrap();
+        assert_eq!(memory["unit"], "By");
+        let point = &memory["gauge"]["dataPoints"][0];
+        assert_eq!(point["asDouble"], 1048576.0);
+        assert_eq!(point["timeUnixNano"], "1700000000");
+        assert_eq!(
+            point["attributes"][0],
+            serde_json::json!({ "key": "process", "value": { "stringValue": "web.api" } })
+        );
+    }
+
+    #[test]
+    fn test_no_target_no_exporter() {
+        assert!(Exporter::new(&MetricsSection::default()).unwrap().is_none());
+    }
+}
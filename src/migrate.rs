@@ -0,0 +1,663 @@
+use crate::compose::Imported;
+use std::collections::BTreeMap;
+
+// ---------------------------------------------------------------------------
+// INI-style files
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrateError {
+    #[error("failed to write pm3.toml: {0}")]
+    Toml(#[from] toml::ser::Error),
+    #[error("{0}")]
+    InvalidValue(String),
+}
+
+/// A `[section]` and its `key=value` lines in file order; keys may repeat.
+#[derive(Debug)]
+struct Section {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    fn last(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// How continuation lines are written: supervisord (like Python's
+/// configparser) continues a value on indented lines, systemd with a
+/// trailing backslash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dialect {
+    Supervisord,
+    Systemd,
+}
+
+fn parse_ini(text: &str, dialect: Dialect) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut pending = String::new();
+
+    for raw in text.lines() {
+        let is_continuation = dialect == Dialect::Supervisord
+            && raw.starts_with([' ', '\t'])
+            && !raw.trim().is_empty();
+        let line = raw.trim();
+
+        if dialect == Dialect::Systemd && !pending.is_empty() {
+            pending.push(' ');
+        }
+        if is_continuation {
+            if let Some((_, value)) = sections.last_mut().and_then(|s| s.entries.last_mut()) {
+                value.push(' ');
+                value.push_str(line);
+            }
+            continue;
+        }
+        if line.starts_with(['#', ';']) && pending.is_empty() {
+            continue;
+        }
+        if dialect == Dialect::Systemd
+            && let Some(head) = line.strip_suffix('\\')
+        {
+            pending.push_str(head.trim_end());
+            continue;
+        }
+        let line = if pending.is_empty() {
+            line.to_string()
+        } else {
+            std::mem::take(&mut pending) + line
+        };
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push(Section {
+                name: name.trim().to_string(),
+                entries: Vec::new(),
+            });
+        } else if let Some((key, value)) = line.split_once('=')
+            && let Some(section) = sections.last_mut()
+        {
+            section
+                .entries
+                .push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    sections
+}
+
+fn seconds_to_ms(value: u64) -> toml::Value {
+    toml::Value::Integer((value * 1000) as i64)
+}
+
+// ---------------------------------------------------------------------------
+// supervisord
+// ---------------------------------------------------------------------------
+
+/// `[program:x]` keys that have no pm3 equivalent but are harmless to drop
+/// because pm3 handles the concern itself.
+const SUPERVISORD_MANAGED: &[&str] = &[
+    "stdout_logfile",
+    "stderr_logfile",
+    "stdout_logfile_maxbytes",
+    "stderr_logfile_maxbytes",
+    "stdout_logfile_backups",
+    "stderr_logfile_backups",
+    "redirect_stderr",
+    "process_name",
+    "priority",
+];
+
+/// Turn every `[program:x]` of a supervisord config into a process, and
+/// every `[group:x]` into the `group` of its programs.
+pub fn import_supervisord(text: &str) -> Result<Imported, MigrateError> {
+    let sections = parse_ini(text, Dialect::Supervisord);
+    let mut warnings = Vec::new();
+    let mut document = toml::Table::new();
+
+    let mut groups: BTreeMap<String, String> = BTreeMap::new();
+    for section in &sections {
+        if let Some(group) = section.name.strip_prefix("group:")
+            && let Some(programs) = section.last("programs")
+        {
+            for program in programs.split(',') {
+                groups.insert(program.trim().to_string(), group.trim().to_string());
+            }
+        }
+    }
+
+    for section in &sections {
+        let Some(name) = section.name.strip_prefix("program:") else {
+            continue;
+        };
+        let name = name.trim();
+        let context = |message: String| format!("program `{name}`: {message}");
+        let Some(command) = section.last("command") else {
+            warnings.push(context("skipped, no command".to_string()));
+            continue;
+        };
+
+        let mut table = toml::Table::new();
+        if command.contains("%(") {
+            warnings.push(context(
+                "command uses %(...)s expansions; replace them by hand".to_string(),
+            ));
+        }
+        table.insert("command".into(), command.into());
+        if let Some(group) = groups.get(name) {
+            table.insert("group".into(), group.as_str().into());
+        }
+
+        let mut ignored = Vec::new();
+        for (key, value) in &section.entries {
+            let number = || {
+                value.parse::<u64>().map_err(|_| {
+                    MigrateError::InvalidValue(context(format!("`{key}` must be a number")))
+                })
+            };
+            match key.as_str() {
+                "command" => {}
+                "directory" => {
+                    table.insert("cwd".into(), value.as_str().into());
+                }
+                "environment" => {
+                    table.insert("env".into(), supervisord_environment(value).into());
+                }
+                "autorestart" => {
+                    let policy = match value.to_ascii_lowercase().as_str() {
+                        "true" => "always",
+                        "false" => "never",
+                        "unexpected" => "on_failure",
+                        _ => {
+                            return Err(MigrateError::InvalidValue(context(format!(
+                                "unsupported autorestart `{value}`"
+                            ))));
+                        }
+                    };
+                    table.insert("restart".into(), policy.into());
+                }
+                "startretries" => {
+                    table.insert("max_restarts".into(), (number()? as i64).into());
+                }
+                "startsecs" => {
+                    table.insert("min_uptime".into(), seconds_to_ms(number()?));
+                }
+                "stopwaitsecs" => {
+                    table.insert("kill_timeout".into(), seconds_to_ms(number()?));
+                }
+                "stopsignal" => {
+                    let signal = value.to_ascii_uppercase();
+                    let signal = if signal.starts_with("SIG") {
+                        signal
+                    } else {
+                        format!("SIG{signal}")
+                    };
+                    table.insert("kill_signal".into(), signal.into());
+                }
+                "exitcodes" => {
+                    let codes: Result<Vec<toml::Value>, _> = value
+                        .split(',')
+                        .map(|c| c.trim().parse::<i64>().map(toml::Value::from))
+                        .collect();
+                    let codes = codes.map_err(|_| {
+                        MigrateError::InvalidValue(context(format!("invalid exitcodes `{value}`")))
+                    })?;
+                    table.insert("stop_exit_codes".into(), codes.into());
+                }
+                "user" => warnings.push(context(format!(
+                    "user `{value}` dropped; pm3 runs processes as the daemon's user"
+                ))),
+                "autostart" if value.eq_ignore_ascii_case("false") => warnings.push(context(
+                    "autostart=false dropped; `pm3 start` starts every process".to_string(),
+                )),
+                "numprocs" if value != "1" => warnings.push(context(format!(
+                    "numprocs={value} dropped; define one process per instance"
+                ))),
+                key if SUPERVISORD_MANAGED.contains(&key) => {}
+                "autostart" | "numprocs" => {}
+                other => ignored.push(other.to_string()),
+            }
+        }
+        if !ignored.is_empty() {
+            warnings.push(context(format!("ignoring {}", ignored.join(", "))));
+        }
+        document.insert(name.to_string(), table.into());
+    }
+
+    finish(document, warnings, "no [program:x] sections to import")
+}
+
+/// Parse `KEY="value",OTHER=value`; quoted values may contain commas.
+fn supervisord_environment(value: &str) -> toml::Table {
+    let mut env = toml::Table::new();
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        let Some((key, after)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, remainder) = match after.strip_prefix(['"', '\'']) {
+            Some(quoted) => {
+                let quote = after.chars().next().unwrap();
+                let end = quoted.find(quote).unwrap_or(quoted.len());
+                let remainder = quoted.get(end + 1..).unwrap_or("");
+                (&quoted[..end], remainder)
+            }
+            None => after.split_once(',').map_or((after, ""), |(v, r)| (v, r)),
+        };
+        env.insert(key.trim().to_string(), value.into());
+        rest = remainder.trim_start_matches([',', ' ']);
+    }
+    env
+}
+
+// ---------------------------------------------------------------------------
+// systemd
+// ---------------------------------------------------------------------------
+
+/// `[Service]` keys pm3 does the equivalent of by itself.
+const SYSTEMD_MANAGED: &[&str] = &["StandardOutput", "StandardError", "SyslogIdentifier"];
+
+/// Turn the `[Service]` of a systemd unit into a process named `name`.
+pub fn import_systemd(name: &str, text: &str) -> Result<Imported, MigrateError> {
+    let sections = parse_ini(text, Dialect::Systemd);
+    let context = |message: String| format!("unit `{name}`: {message}");
+    let service = sections
+        .iter()
+        .find(|s| s.name == "Service")
+        .ok_or_else(|| MigrateError::InvalidValue(context("no [Service] section".to_string())))?;
+    let mut warnings = Vec::new();
+
+    let exec_start: Vec<&str> = service.all("ExecStart").filter(|v| !v.is_empty()).collect();
+    let Some(command) = exec_start.last() else {
+        return Err(MigrateError::InvalidValue(context(
+            "no ExecStart to run".to_string(),
+        )));
+    };
+    if exec_start.len() > 1 {
+        warnings.push(context(
+            "several ExecStart lines; only the last one is kept".to_string(),
+        ));
+    }
+    let dependencies: Vec<&str> = sections
+        .iter()
+        .filter(|s| s.name == "Unit")
+        .flat_map(|s| s.all("Requires").chain(s.all("After")))
+        .flat_map(str::split_whitespace)
+        .filter(|unit| unit.ends_with(".service"))
+        .collect();
+    if !dependencies.is_empty() {
+        warnings.push(context(format!(
+            "depends on {}; add depends_on once they are imported",
+            dependencies.join(", ")
+        )));
+    }
+
+    let mut table = toml::Table::new();
+    table.insert("command".into(), systemd_command(command).into());
+    if command.contains('%') {
+        warnings.push(context(
+            "ExecStart uses % specifiers; replace them by hand".to_string(),
+        ));
+    }
+
+    let mut env = toml::Table::new();
+    let mut env_files = Vec::new();
+    let mut ignored = Vec::new();
+    for (key, value) in &service.entries {
+        let invalid = || MigrateError::InvalidValue(context(format!("invalid {key} `{value}`")));
+        match key.as_str() {
+            "ExecStart" => {}
+            "WorkingDirectory" => {
+                table.insert("cwd".into(), value.trim_start_matches('-').into());
+            }
+            "Environment" => {
+                let words = shell_words::split(value).map_err(|_| invalid())?;
+                for word in words {
+                    if let Some((k, v)) = word.split_once('=') {
+                        env.insert(k.to_string(), v.into());
+                    }
+                }
+            }
+            "EnvironmentFile" => env_files.push(value.trim_start_matches('-').to_string()),
+            "Restart" => {
+                let policy = match value.as_str() {
+                    "no" => "never",
+                    "always" => "always",
+                    "on-failure" | "on-abnormal" | "on-abort" | "on-watchdog" => "on_failure",
+                    "on-success" => {
+                        warnings.push(context(
+                            "Restart=on-success mapped to always; add stop_exit_codes for crashes"
+                                .to_string(),
+                        ));
+                        "always"
+                    }
+                    _ => return Err(invalid()),
+                };
+                table.insert("restart".into(), policy.into());
+            }
+            "StartLimitBurst" => {
+                let burst: i64 = value.parse().map_err(|_| invalid())?;
+                table.insert("max_restarts".into(), burst.into());
+            }
+            "TimeoutStopSec" => {
+                let timeout = systemd_timespan(value).ok_or_else(invalid)?;
+                table.insert("kill_timeout".into(), (timeout as i64).into());
+            }
+            "KillSignal" => {
+                table.insert("kill_signal".into(), value.as_str().into());
+            }
+            "ExecReload" => match systemd_reload_signal(value) {
+                Some(signal) => {
+                    table.insert("reload_signal".into(), signal.into());
+                }
+                None => warnings.push(context(format!(
+                    "ExecReload `{value}` dropped; pm3 reloads with a signal or a restart"
+                ))),
+            },
+            "MemoryMax" | "MemoryLimit" => {
+                if value != "infinity" {
+                    table.insert("max_memory".into(), value.as_str().into());
+                }
+            }
+            "SuccessExitStatus" => {
+                let codes: Vec<toml::Value> = value
+                    .split_whitespace()
+                    .filter_map(|c| c.parse::<i64>().ok())
+                    .map(toml::Value::from)
+                    .collect();
+                if !codes.is_empty() {
+                    table.insert("stop_exit_codes".into(), codes.into());
+                }
+            }
+            "ExecStartPre" => {
+                table.insert("pre_start".into(), systemd_command(value).into());
+            }
+            "ExecStartPost" => {
+                table.insert("post_start".into(), systemd_command(value).into());
+            }
+            "ExecStopPost" => {
+                table.insert("post_stop".into(), systemd_command(value).into());
+            }
+            "Type" if matches!(value.as_str(), "simple" | "exec") => {}
+            "Type" => warnings.push(context(format!(
+                "Type={value} dropped; pm3 expects the process to stay in the foreground"
+            ))),
+            "User" | "Group" => warnings.push(context(format!(
+                "{key}={value} dropped; pm3 runs processes as the daemon's user"
+            ))),
+            key if SYSTEMD_MANAGED.contains(&key) => {}
+            other => ignored.push(other.to_string()),
+        }
+    }
+    if !env.is_empty() {
+        table.insert("env".into(), env.into());
+    }
+    match env_files.len() {
+        0 => {}
+        1 => {
+            table.insert("env_file".into(), env_files.remove(0).into());
+        }
+        _ => {
+            table.insert("env_file".into(), env_files.into());
+        }
+    }
+    if !ignored.is_empty() {
+        warnings.push(context(format!("ignoring {}", ignored.join(", "))));
+    }
+
+    let mut document = toml::Table::new();
+    document.insert(name.to_string(), table.into());
+    finish(document, warnings, "nothing to import")
+}
+
+/// Drop the `@-:+!` prefixes systemd allows on `Exec*` lines.
+fn systemd_command(value: &str) -> &str {
+    value.trim_start_matches(['@', '-', ':', '+', '!'])
+}
+
+/// `ExecReload=/bin/kill -HUP $MAINPID` becomes `reload_signal = "SIGHUP"`.
+fn systemd_reload_signal(value: &str) -> Option<String> {
+    let words = shell_words::split(systemd_command(value)).ok()?;
+    match words.as_slice() {
+        [kill, flag, pid] if kill.ends_with("kill") && pid.contains("MAINPID") => {
+            let signal = flag.strip_prefix('-')?.trim_start_matches("s ");
+            let signal = signal.to_ascii_uppercase();
+            Some(if signal.starts_with("SIG") {
+                signal
+            } else {
+                format!("SIG{signal}")
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parse a systemd time span such as `90`, `10s`, `1min 30s` or `500ms` into
+/// milliseconds; a bare number is seconds.
+fn systemd_timespan(value: &str) -> Option<u64> {
+    let mut total = 0;
+    for token in value.split_whitespace() {
+        let split = token
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(token.len());
+        let (number, unit) = token.split_at(split);
+        let number: u64 = number.parse().ok()?;
+        let factor = match unit {
+            "ms" | "msec" => 1,
+            "" | "s" | "sec" | "second" | "seconds" => 1000,
+            "m" | "min" | "minute" | "minutes" => 60_000,
+            "h" | "hr" | "hour" | "hours" => 3_600_000,
+            _ => return None,
+        };
+        total += number * factor;
+    }
+    Some(total)
+}
+
+fn finish(
+    document: toml::Table,
+    warnings: Vec<String>,
+    empty: &str,
+) -> Result<Imported, MigrateError> {
+    if document.is_empty() {
+        return Err(MigrateError::InvalidValue(empty.to_string()));
+    }
+    Ok(Imported {
+        processes: document.keys().cloned().collect(),
+        toml: toml::to_string(&document)?,
+        warnings,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EnvFile, Hook, RestartPolicy, parse_config};
+
+    const SUPERVISORD: &str = r#"
+[supervisord]
+logfile=/var/log/supervisord.log
+
+; the API server
+[program:api]
+command=/usr/bin/node server.js --port 3000
+directory=/srv/api
+environment=NODE_ENV="production",GREETING="hello, world",PORT=3000
+user=www-data
+autorestart=unexpected
+exitcodes=0,2
+startretries=5
+startsecs=3
+stopsignal=INT
+stopwaitsecs=20
+stdout_logfile=/var/log/api.log
+
+[program:worker]
+command=python worker.py
+    --queue high
+autorestart=true
+autostart=false
+killasgroup=true
+
+[group:backend]
+programs=api,worker
+"#;
+
+    #[test]
+    fn test_import_supervisord_programs() {
+        let imported = import_supervisord(SUPERVISORD).unwrap();
+        assert_eq!(imported.processes, vec!["api", "worker"]);
+
+        let configs = parse_config(&imported.toml).unwrap();
+        let api = &configs["api"];
+        assert_eq!(api.command, "/usr/bin/node server.js --port 3000");
+        assert_eq!(api.cwd.as_deref(), Some("/srv/api"));
+        let env = api.env.as_ref().unwrap();
+        assert_eq!(env["NODE_ENV"], "production");
+        assert_eq!(env["GREETING"], "hello, world");
+        assert_eq!(env["PORT"], "3000");
+        assert_eq!(api.restart, Some(RestartPolicy::OnFailure));
+        assert_eq!(api.stop_exit_codes, Some(vec![0, 2]));
+        assert_eq!(api.max_restarts, Some(5));
+        assert_eq!(api.min_uptime, Some(3000));
+        assert_eq!(api.kill_signal.as_deref(), Some("SIGINT"));
+        assert_eq!(api.kill_timeout, Some(20000));
+        assert_eq!(api.group.as_deref(), Some("backend"));
+
+        let worker = &configs["worker"];
+        assert_eq!(worker.command, "python worker.py --queue high");
+        assert_eq!(worker.restart, Some(RestartPolicy::Always));
+        assert_eq!(worker.group.as_deref(), Some("backend"));
+    }
+
+    #[test]
+    fn test_import_supervisord_warnings() {
+        let warnings = import_supervisord(SUPERVISORD).unwrap().warnings;
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.contains("user `www-data` dropped"))
+        );
+        assert!(warnings.iter().any(|w| w.contains("autostart=false")));
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.contains("`worker`: ignoring killasgroup"))
+        );
+        assert!(!warnings.iter().any(|w| w.contains("stdout_logfile")));
+    }
+
+    #[test]
+    fn test_import_supervisord_errors() {
+        assert!(import_supervisord("[supervisord]\n").is_err());
+        let err = import_supervisord("[program:a]\ncommand=x\nautorestart=maybe\n").unwrap_err();
+        assert!(err.to_string().contains("maybe"), "{err}");
+    }
+
+    const UNIT: &str = r#"
+[Unit]
+Description=My app
+After=network.target postgresql.service
+
+[Service]
+Type=simple
+User=app
+WorkingDirectory=/srv/myapp
+Environment="RUST_LOG=info" PORT=8080
+Environment=GREETING="hello world"
+EnvironmentFile=-/etc/myapp.env
+ExecStartPre=/srv/myapp/migrate
+ExecStart=/srv/myapp/bin/server \
+    --config /etc/myapp.toml
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+StartLimitBurst=4
+TimeoutStopSec=1min 30s
+KillSignal=SIGINT
+MemoryMax=512M
+SuccessExitStatus=143 SIGTERM
+LimitNOFILE=65536
+
+[Install]
+WantedBy=multi-user.target
+"#;
+
+    #[test]
+    fn test_import_systemd_unit() {
+        let imported = import_systemd("myapp", UNIT).unwrap();
+        assert_eq!(imported.processes, vec!["myapp"]);
+
+        let config = &parse_config(&imported.toml).unwrap()["myapp"];
+        assert_eq!(
+            config.command,
+            "/srv/myapp/bin/server --config /etc/myapp.toml"
+        );
+        assert_eq!(config.cwd.as_deref(), Some("/srv/myapp"));
+        let env = config.env.as_ref().unwrap();
+        assert_eq!(env["RUST_LOG"], "info");
+        assert_eq!(env["PORT"], "8080");
+        assert_eq!(env["GREETING"], "hello world");
+        assert_eq!(
+            config.env_file,
+            Some(EnvFile::Single("/etc/myapp.env".to_string()))
+        );
+        assert_eq!(
+            config.pre_start,
+            Some(Hook::Command("/srv/myapp/migrate".to_string()))
+        );
+        assert_eq!(config.reload_signal.as_deref(), Some("SIGHUP"));
+        assert_eq!(config.restart, Some(RestartPolicy::OnFailure));
+        assert_eq!(config.max_restarts, Some(4));
+        assert_eq!(config.kill_timeout, Some(90_000));
+        assert_eq!(config.kill_signal.as_deref(), Some("SIGINT"));
+        assert_eq!(config.max_memory.as_deref(), Some("512M"));
+        assert_eq!(config.stop_exit_codes, Some(vec![143]));
+
+        let warnings = imported.warnings;
+        assert!(warnings.iter().any(|w| w.contains("User=app dropped")));
+        assert!(warnings.iter().any(|w| w.contains("ignoring LimitNOFILE")));
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.contains("depends on postgresql.service"))
+        );
+    }
+
+    #[test]
+    fn test_import_systemd_errors() {
+        assert!(import_systemd("x", "[Unit]\nDescription=x\n").is_err());
+        assert!(import_systemd("x", "[Service]\nType=oneshot\n").is_err());
+        let err =
+            import_systemd("x", "[Service]\nExecStart=/bin/x\nRestart=sometimes\n").unwrap_err();
+        assert!(err.to_string().contains("sometimes"), "{err}");
+    }
+
+    #[test]
+    fn test_systemd_helpers() {
+        assert_eq!(systemd_timespan("90"), Some(90_000));
+        assert_eq!(systemd_timespan("500ms"), Some(500));
+        assert_eq!(systemd_timespan("2min 5s"), Some(125_000));
+        assert_eq!(systemd_timespan("soon"), None);
+        assert_eq!(systemd_command("-@/bin/app"), "/bin/app");
+        assert_eq!(
+            systemd_reload_signal("/bin/kill -USR2 $MAINPID").as_deref(),
+            Some("SIGUSR2")
+        );
+        assert_eq!(systemd_reload_signal("/srv/app/reload"), None);
+    }
+}
@@ -0,0 +1,450 @@
+use crate::client;
+use crate::paths::Paths;
+use crate::protocol::{ProcessInfo, ProcessStatus, Request, Response};
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// How often the process table and the log pane are fetched again.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for a key before checking whether a refresh is due.
+const INPUT_POLL: Duration = Duration::from_millis(100);
+
+/// Lines of the selected process's log kept for scrolling back.
+const LOG_LINES: usize = 500;
+
+const HELP: &str = "↑/↓ select  PgUp/PgDn scroll log  r restart  s stop  q quit";
+
+// ---------------------------------------------------------------------------
+// App state
+// ---------------------------------------------------------------------------
+
+/// What the dashboard shows, refreshed from the daemon.
+#[derive(Debug, Default)]
+struct App {
+    processes: Vec<ProcessInfo>,
+    table: TableState,
+    /// The selected process's last `LOG_LINES` lines.
+    logs: Vec<String>,
+    /// Lines scrolled back from the end of the log.
+    scroll: usize,
+    /// The outcome of the last action, or why the daemon could not be asked.
+    message: Option<String>,
+    quit: bool,
+}
+
+/// A request to carry out for the selected process.
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Restart(String),
+    Stop(String),
+}
+
+impl App {
+    fn selected(&self) -> Option<&ProcessInfo> {
+        self.processes.get(self.table.selected()?)
+    }
+
+    /// Take a new process list, keeping the same process selected.
+    fn set_processes(&mut self, mut processes: Vec<ProcessInfo>) {
+        processes.sort_by(|a, b| a.name.cmp(&b.name));
+        let selected = self.selected().map(|info| info.name.clone());
+        let index = selected
+            .and_then(|name| processes.iter().position(|info| info.name == name))
+            .or((!processes.is_empty()).then_some(0))
+            .map(|index| index.min(processes.len().saturating_sub(1)));
+        self.processes = processes;
+        self.table.select(index);
+    }
+
+    fn select(&mut self, index: usize) {
+        if !self.processes.is_empty() {
+            self.table.select(Some(index.min(self.processes.len() - 1)));
+            self.logs.clear();
+            self.scroll = 0;
+        }
+    }
+
+    /// Handle a key press; an action comes back for the keys that ask the
+    /// daemon to do something.
+    fn on_key(&mut self, key: KeyEvent, log_height: usize) -> Option<Action> {
+        let current = self.table.selected().unwrap_or_default();
+        let name = || self.selected().map(|info| info.name.clone());
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.select(current.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(current + 1),
+            KeyCode::PageUp => {
+                let most = self.logs.len().saturating_sub(log_height);
+                self.scroll = (self.scroll + log_height).min(most);
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(log_height),
+            KeyCode::Char('r') => return name().map(Action::Restart),
+            KeyCode::Char('s') => return name().map(Action::Stop),
+            _ => {}
+        }
+        None
+    }
+
+    /// Fetch the process list and the selected process's log.
+    fn refresh(&mut self, paths: &Paths) {
+        match client::send_request(paths, &Request::List) {
+            Ok(Response::ProcessList { processes }) => self.set_processes(processes),
+            Ok(Response::Error { message }) => self.message = Some(message),
+            Ok(_) => {}
+            Err(e) => self.message = Some(format!("daemon unreachable: {e}")),
+        }
+
+        let Some(name) = self.selected().map(|info| info.name.clone()) else {
+            self.logs.clear();
+            return;
+        };
+        let request = Request::Log {
+            name: Some(name),
+            lines: LOG_LINES,
+            follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
+        };
+        let mut logs = Vec::new();
+        let fetched = client::send_request_streaming(paths, &request, |response| {
+            if let Response::LogLine { line, .. } = response {
+                logs.push(line.clone());
+            }
+        });
+        if fetched.is_ok() {
+            self.logs = logs;
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Running
+// ---------------------------------------------------------------------------
+
+/// Show the dashboard until the user quits, then give the terminal back as
+/// it was (also when this fails or panics).
+pub fn run(paths: &Paths) -> color_eyre::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = run_app(&mut terminal, paths);
+    ratatui::try_restore()?;
+    result
+}
+
+fn run_app(terminal: &mut DefaultTerminal, paths: &Paths) -> color_eyre::Result<()> {
+    let mut app = App::default();
+    // Actions run on their own thread, so a slow stop doesn't freeze the
+    // screen; their outcomes come back here
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut next_refresh = Instant::now();
+
+    while !app.quit {
+        if Instant::now() >= next_refresh {
+            app.refresh(paths);
+            next_refresh = Instant::now() + REFRESH_INTERVAL;
+        }
+        while let Ok(message) = done_rx.try_recv() {
+            app.message = Some(message);
+            next_refresh = Instant::now();
+        }
+
+        let mut log_height = 0;
+        terminal.draw(|frame| log_height = draw(frame, &mut app))?;
+
+        if !event::poll(INPUT_POLL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let selected = app.table.selected();
+        if let Some(action) = app.on_key(key, log_height) {
+            app.message = Some(match &action {
+                Action::Restart(name) => format!("restarting {name}..."),
+                Action::Stop(name) => format!("stopping {name}..."),
+            });
+            let (paths, done_tx) = (paths.clone(), done_tx.clone());
+            std::thread::spawn(move || {
+                let _ = done_tx.send(perform(&paths, action));
+            });
+        }
+        if app.table.selected() != selected {
+            next_refresh = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+/// Carry out `action`, describing how it went.
+fn perform(paths: &Paths, action: Action) -> String {
+    let request = match &action {
+        Action::Restart(name) => Request::Restart {
+            names: Some(vec![name.clone()]),
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: false,
+        },
+        Action::Stop(name) => Request::Stop {
+            names: Some(vec![name.clone()]),
+            group: None,
+            cascade: false,
+        },
+    };
+    match client::send_request(paths, &request) {
+        Ok(Response::Error { message }) => format!("error: {message}"),
+        Ok(_) => match action {
+            Action::Restart(name) => format!("restarted {name}"),
+            Action::Stop(name) => format!("stopped {name}"),
+        },
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Drawing
+// ---------------------------------------------------------------------------
+
+/// Draw the process table over the log pane, returning how many log lines
+/// fit.
+fn draw(frame: &mut Frame, app: &mut App) -> usize {
+    let rows = app.processes.len() as u16 + 3;
+    let [table_area, log_area, footer_area] = Layout::vertical([
+        Constraint::Max(rows.max(4)),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let header = Row::new(["name", "status", "pid", "cpu", "mem", "restarts", "uptime"])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+    let body = app.processes.iter().map(|info| {
+        Row::new([
+            Cell::from(info.name.clone()),
+            Cell::from(info.status.to_string()).style(Style::new().fg(status_color(info.status))),
+            Cell::from(info.pid.map_or("-".to_string(), |pid| pid.to_string())),
+            Cell::from(
+                info.cpu_percent
+                    .map_or("-".to_string(), |cpu| format!("{cpu:.1}%")),
+            ),
+            Cell::from(format_memory(info.memory_bytes)),
+            Cell::from(info.restarts.to_string()),
+            Cell::from(format_uptime(info.uptime)),
+        ])
+    });
+    let widths = [
+        Constraint::Fill(2),
+        Constraint::Length(10),
+        Constraint::Length(8),
+        Constraint::Length(7),
+        Constraint::Length(8),
+        Constraint::Length(9),
+        Constraint::Length(8),
+    ];
+    let table = Table::new(body, widths)
+        .header(header)
+        .block(Block::bordered().title(" pm3 monit "))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, table_area, &mut app.table);
+
+    // The log pane ends `scroll` lines before the end
+    let height = log_area.height.saturating_sub(2) as usize;
+    let end = app.logs.len().saturating_sub(app.scroll);
+    let start = end.saturating_sub(height);
+    let title = match (app.selected(), app.scroll) {
+        (Some(info), 0) => format!(" {} ", info.name),
+        (Some(info), scroll) => format!(" {} (-{scroll}) ", info.name),
+        (None, _) => " logs ".to_string(),
+    };
+    let lines: Vec<Line> = app.logs[start..end]
+        .iter()
+        .map(|line| Line::raw(line.as_str()))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        log_area,
+    );
+
+    let footer = app.message.as_deref().unwrap_or(HELP);
+    frame.render_widget(
+        Paragraph::new(footer).style(Style::new().fg(Color::DarkGray)),
+        footer_area,
+    );
+    height
+}
+
+fn status_color(status: ProcessStatus) -> Color {
+    match status {
+        ProcessStatus::Online => Color::Green,
+        ProcessStatus::Starting | ProcessStatus::Stopping => Color::Yellow,
+        ProcessStatus::Unhealthy => Color::Magenta,
+        ProcessStatus::Stopped => Color::Reset,
+        ProcessStatus::Completed => Color::Blue,
+        ProcessStatus::Crashed | ProcessStatus::Errored => Color::Red,
+    }
+}
+
+fn format_memory(bytes: Option<u64>) -> String {
+    match bytes {
+        None => "-".to_string(),
+        Some(bytes) => format!("{:.1}M", bytes as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+fn format_uptime(seconds: Option<u64>) -> String {
+    match seconds {
+        None => "-".to_string(),
+        Some(s) if s < 60 => format!("{s}s"),
+        Some(s) if s < 3600 => format!("{}m {}s", s / 60, s % 60),
+        Some(s) => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+
+    fn info(name: &str, status: ProcessStatus) -> ProcessInfo {
+        ProcessInfo {
+            name: name.to_string(),
+            pid: None,
+            status,
+            uptime: None,
+            restarts: 0,
+            cpu_percent: None,
+            memory_bytes: None,
+            group: None,
+            generation: 0,
+            memory_restarts: 0,
+            fd_count: None,
+            thread_count: None,
+            process_count: None,
+            metrics: Default::default(),
+            exit_code: None,
+            duration_ms: None,
+            last_exit_code: None,
+            last_exit_signal: None,
+            exited_at: None,
+        }
+    }
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_selection_follows_the_process_across_refreshes() {
+        let mut app = App::default();
+        app.set_processes(vec![
+            info("worker", ProcessStatus::Online),
+            info("api", ProcessStatus::Online),
+        ]);
+        assert_eq!(app.selected().unwrap().name, "api");
+
+        app.on_key(press(KeyCode::Down), 10);
+        assert_eq!(app.selected().unwrap().name, "worker");
+        app.on_key(press(KeyCode::Down), 10);
+        assert_eq!(app.selected().unwrap().name, "worker");
+
+        // A new process sorts in before it; the selection stays on worker
+        app.set_processes(vec![
+            info("worker", ProcessStatus::Online),
+            info("api", ProcessStatus::Online),
+            info("cron", ProcessStatus::Stopped),
+        ]);
+        assert_eq!(app.selected().unwrap().name, "worker");
+
+        // And when it goes away, the selection stays in range
+        app.set_processes(vec![info("api", ProcessStatus::Online)]);
+        assert_eq!(app.selected().unwrap().name, "api");
+        app.set_processes(Vec::new());
+        assert!(app.selected().is_none());
+    }
+
+    #[test]
+    fn test_keys_ask_for_actions_scroll_and_quit() {
+        let mut app = App::default();
+        app.set_processes(vec![info("web", ProcessStatus::Online)]);
+        assert_eq!(
+            app.on_key(press(KeyCode::Char('r')), 10),
+            Some(Action::Restart("web".to_string()))
+        );
+        assert_eq!(
+            app.on_key(press(KeyCode::Char('s')), 10),
+            Some(Action::Stop("web".to_string()))
+        );
+
+        app.logs = (0..25).map(|i| format!("line {i}")).collect();
+        app.on_key(press(KeyCode::PageUp), 10);
+        app.on_key(press(KeyCode::PageUp), 10);
+        app.on_key(press(KeyCode::PageUp), 10);
+        assert_eq!(app.scroll, 15);
+        app.on_key(press(KeyCode::PageDown), 10);
+        assert_eq!(app.scroll, 5);
+
+        assert!(!app.quit);
+        app.on_key(press(KeyCode::Char('q')), 10);
+        assert!(app.quit);
+    }
+
+    #[test]
+    fn test_draw_shows_processes_and_selected_log() {
+        let mut app = App::default();
+        let mut web = info("web", ProcessStatus::Online);
+        web.pid = Some(4242);
+        web.memory_bytes = Some(12 * 1024 * 1024);
+        app.set_processes(vec![web, info("batch", ProcessStatus::Crashed)]);
+        app.on_key(press(KeyCode::Down), 10);
+        app.logs = vec!["listening on :3000".to_string()];
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 16)).unwrap();
+        terminal
+            .draw(|frame| {
+                draw(frame, &mut app);
+            })
+            .unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        for text in [
+            "batch",
+            "crashed",
+            "4242",
+            "12.0M",
+            " web ",
+            "listening on :3000",
+        ] {
+            assert!(screen.contains(text), "missing {text:?} in {screen}");
+        }
+    }
+}
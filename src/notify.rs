@@ -0,0 +1,743 @@
+use crate::config::{Channel, NotifyEvent, NotifyTarget};
+use crate::http;
+use crate::log;
+use crate::paths::Paths;
+use crate::process::Processes;
+use crate::protocol::{EventKind, ProcessEvent, ProcessStatus};
+use crate::settings::{ChatChannel, DaemonSettings, EmailChannel, NotificationsSection};
+use crate::smtp::{self, Mail};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tracing::warn;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Tries per delivery before a notification is given up on.
+const ATTEMPTS: u32 = 4;
+
+/// Wait after the first failed try; each further failure doubles it.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What channel templates can fill in.
+pub const PLACEHOLDERS: &[&str] = &[
+    "name",
+    "event",
+    "status",
+    "exit_code",
+    "detail",
+    "time",
+    "host",
+    "logs",
+];
+
+/// Lines of the process's log that `{logs}` shows.
+const LOG_LINES: usize = 10;
+
+/// Discord rejects longer messages.
+const DISCORD_LIMIT: usize = 2000;
+
+const SLACK_TEMPLATE: &str = "*{name}* {event} on {host}\n\
+    status: {status}, exit code: {exit_code}\n{detail}\n```\n{logs}\n```";
+
+const DISCORD_TEMPLATE: &str = "**{name}** {event} on {host}\n\
+    status: {status}, exit code: {exit_code}\n{detail}\n```\n{logs}\n```";
+
+const EMAIL_SUBJECT: &str = "[pm3] {name} {event} on {host}";
+
+const EMAIL_TEMPLATE: &str = "{name} {event} on {host} at {time}.\n\n\
+    status: {status}\nexit code: {exit_code}\ndetail: {detail}\n\n\
+    Last log lines:\n\n{logs}\n";
+
+// ---------------------------------------------------------------------------
+// Notifier
+// ---------------------------------------------------------------------------
+
+/// What notifications need from the daemon. Events are recorded from wherever
+/// they happen, often without the process's config at hand, so the `notify`
+/// field is looked up in the process table.
+#[derive(Clone)]
+pub struct Notifier {
+    processes: Processes,
+    settings: Arc<RwLock<DaemonSettings>>,
+    coalescer: Arc<Mutex<Coalescer>>,
+}
+
+impl Notifier {
+    pub fn new(processes: Processes, settings: Arc<RwLock<DaemonSettings>>) -> Self {
+        Self {
+            processes,
+            settings,
+            coalescer: Arc::default(),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Coalescing
+// ---------------------------------------------------------------------------
+
+/// Holds back repeats of a notification while `notifications.cooldown`
+/// runs. The first event for a process and kind goes out and opens a
+/// window; later ones in it are counted, and the window closes with one
+/// summary of them, opening the next.
+#[derive(Debug, Default)]
+pub struct Coalescer {
+    windows: HashMap<(String, NotifyEvent), Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    ends: Instant,
+    held: u32,
+    latest: Option<ProcessEvent>,
+}
+
+/// What to do with an event offered to a `Coalescer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Send,
+    /// Held back. The first event held in a window asks for `close` to be
+    /// called when the window ends.
+    Hold {
+        close_at: Option<Instant>,
+    },
+}
+
+/// Events held back in a window, sent on as one notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    /// The most recent of them.
+    pub event: ProcessEvent,
+    pub count: u32,
+}
+
+impl Coalescer {
+    pub fn offer(&mut self, event: &ProcessEvent, cooldown: Duration, now: Instant) -> Verdict {
+        let key = (event.name.clone(), notify_event(event));
+        match self.windows.get_mut(&key) {
+            // A window holding events stays open until `close` has run
+            Some(window) if now < window.ends || window.held > 0 => {
+                window.held += 1;
+                window.latest = Some(event.clone());
+                Verdict::Hold {
+                    close_at: (window.held == 1).then_some(window.ends),
+                }
+            }
+            _ => {
+                self.windows.insert(
+                    key,
+                    Window {
+                        ends: now + cooldown,
+                        held: 0,
+                        latest: None,
+                    },
+                );
+                Verdict::Send
+            }
+        }
+    }
+
+    /// End the window of `name`'s `kind` events, returning what it held. A
+    /// window that held anything is followed by another, so a process that
+    /// keeps at it gets a summary per cooldown.
+    pub fn close(
+        &mut self,
+        name: &str,
+        kind: NotifyEvent,
+        cooldown: Duration,
+        now: Instant,
+    ) -> Option<Summary> {
+        let key = (name.to_string(), kind);
+        let window = self.windows.remove(&key)?;
+        let event = window.latest?;
+        self.windows.insert(
+            key,
+            Window {
+                ends: now + cooldown,
+                held: 0,
+                latest: None,
+            },
+        );
+        Some(Summary {
+            event,
+            count: window.held,
+        })
+    }
+}
+
+impl Summary {
+    /// E.g. `web restarted 14 more times in the last 10m`.
+    pub fn text(&self, cooldown: &str) -> String {
+        let kind = notify_event(&self.event);
+        let verb = match kind {
+            NotifyEvent::Start => "started",
+            NotifyEvent::Exit => "exited",
+            NotifyEvent::Crash => "crashed",
+            NotifyEvent::Errored => "errored",
+            NotifyEvent::Restart => "restarted",
+            NotifyEvent::Unhealthy => "turned unhealthy",
+            NotifyEvent::MemoryLimit => "went over max_memory",
+            NotifyEvent::FdLimit => "went over fd_limit",
+            NotifyEvent::Deferred => "had a restart deferred",
+            NotifyEvent::Alert => "raised an alert",
+        };
+        let times = if self.count == 1 { "time" } else { "times" };
+        format!(
+            "{} {verb} {} more {times} in the last {cooldown}",
+            self.event.name, self.count
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Templates
+// ---------------------------------------------------------------------------
+
+/// The `{placeholder}`s in `template`, with where each starts and ends.
+/// Braces around anything but a lowercase word are left as text.
+fn placeholders(template: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    template.match_indices('{').filter_map(|(start, _)| {
+        let len = template[start + 1..].find('}')?;
+        let word = &template[start + 1..start + 1 + len];
+        let is_word = !word.is_empty() && word.bytes().all(|b| b.is_ascii_lowercase() || b == b'_');
+        is_word.then_some((start, start + len + 2, word))
+    })
+}
+
+/// Reject templates using placeholders pm3 doesn't fill in.
+pub fn check_template(template: &str) -> Result<(), String> {
+    match placeholders(template).find(|(_, _, word)| !PLACEHOLDERS.contains(word)) {
+        Some((_, _, word)) => Err(format!(
+            "unknown placeholder `{{{word}}}` (expected one of {})",
+            PLACEHOLDERS.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Fill in `template` from `values`.
+pub fn render(template: &str, values: &HashMap<&str, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = 0;
+    for (start, end, word) in placeholders(template) {
+        if let Some(value) = values.get(word) {
+            out.push_str(&template[rest..start]);
+            out.push_str(value);
+            rest = end;
+        }
+    }
+    out.push_str(&template[rest..]);
+    out
+}
+
+/// What the placeholders stand for in a message about `event`; anything the
+/// event doesn't say renders as `-`.
+pub fn values(event: &ProcessEvent, host: &str, logs: &str) -> HashMap<&'static str, String> {
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let time = chrono::DateTime::from_timestamp_millis(event.timestamp).map(|time| {
+        time.with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    });
+    let detail = event
+        .detail
+        .clone()
+        .or_else(|| event.reason.map(|reason| reason.to_string()));
+    HashMap::from([
+        ("name", event.name.clone()),
+        ("event", notify_event(event).to_string()),
+        ("status", or_dash(event.status.map(|s| s.to_string()))),
+        ("exit_code", or_dash(event.exit_code.map(|c| c.to_string()))),
+        ("detail", or_dash(detail)),
+        ("time", or_dash(time)),
+        ("host", host.to_string()),
+        (
+            "logs",
+            or_dash((!logs.is_empty()).then(|| logs.to_string())),
+        ),
+    ])
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// The last `LOG_LINES` of the process's stderr, or of its stdout when
+/// stderr is empty.
+async fn last_log_lines(stderr_log: PathBuf, stdout_log: PathBuf) -> String {
+    tokio::task::spawn_blocking(move || {
+        let lines = log::tail_log(&stderr_log, LOG_LINES).unwrap_or_default();
+        if lines.is_empty() {
+            log::tail_log(&stdout_log, LOG_LINES).unwrap_or_default()
+        } else {
+            lines
+        }
+        .join("\n")
+    })
+    .await
+    .unwrap_or_default()
+}
+
+// ---------------------------------------------------------------------------
+// Sending
+// ---------------------------------------------------------------------------
+
+/// The `notify` event `event` counts as.
+pub fn notify_event(event: &ProcessEvent) -> NotifyEvent {
+    match event.event {
+        EventKind::Start => NotifyEvent::Start,
+        EventKind::Exit if event.status == Some(ProcessStatus::Crashed) => NotifyEvent::Crash,
+        EventKind::Exit => NotifyEvent::Exit,
+        EventKind::Restart => NotifyEvent::Restart,
+        EventKind::Deferred => NotifyEvent::Deferred,
+        EventKind::Unhealthy => NotifyEvent::Unhealthy,
+        EventKind::MemoryLimit => NotifyEvent::MemoryLimit,
+        EventKind::FdLimit => NotifyEvent::FdLimit,
+        EventKind::Errored => NotifyEvent::Errored,
+        EventKind::Alert => NotifyEvent::Alert,
+    }
+}
+
+/// The JSON a webhook receives: the event as plugins see it, with `type`
+/// naming it as `notify` does (`crash` rather than an `exit`). A summary of
+/// held-back events adds how many there were and says so in `summary`.
+pub fn payload(event: &ProcessEvent, summary: Option<(u32, &str)>) -> Vec<u8> {
+    let mut body = serde_json::to_value(event).expect("events serialize");
+    body["type"] = notify_event(event).to_string().into();
+    if let Some((count, text)) = summary {
+        body["count"] = count.into();
+        body["summary"] = text.into();
+    }
+    serde_json::to_vec(&body).expect("events serialize")
+}
+
+/// One message on its way to one target.
+#[derive(Debug, Clone)]
+enum Delivery {
+    Post { url: String, body: Vec<u8> },
+    Email { channel: EmailChannel, mail: Mail },
+}
+
+impl Delivery {
+    /// A message for `channel` as set up under `[notifications]`, or `None`
+    /// if it isn't.
+    fn to_channel(
+        channel: Channel,
+        settings: &NotificationsSection,
+        values: &HashMap<&str, String>,
+    ) -> Option<Self> {
+        let chat = |chat: &ChatChannel, default: &str, key: &str, limit: Option<usize>| {
+            let mut text = render(chat.template.as_deref().unwrap_or(default), values);
+            if let Some(limit) = limit {
+                text = text.chars().take(limit).collect();
+            }
+            Delivery::Post {
+                url: chat.webhook_url.clone(),
+                body: serde_json::to_vec(&serde_json::json!({ key: text }))
+                    .expect("strings serialize"),
+            }
+        };
+        match channel {
+            Channel::Slack => Some(chat(settings.slack.as_ref()?, SLACK_TEMPLATE, "text", None)),
+            Channel::Discord => Some(chat(
+                settings.discord.as_ref()?,
+                DISCORD_TEMPLATE,
+                "content",
+                Some(DISCORD_LIMIT),
+            )),
+            Channel::Email => {
+                let email = settings.email.as_ref()?;
+                let subject = email.subject.as_deref().unwrap_or(EMAIL_SUBJECT);
+                let body = email.template.as_deref().unwrap_or(EMAIL_TEMPLATE);
+                Some(Delivery::Email {
+                    channel: email.clone(),
+                    mail: Mail {
+                        subject: render(subject, values),
+                        body: render(body, values),
+                    },
+                })
+            }
+        }
+    }
+
+    async fn send(&self) -> Result<(), String> {
+        match self {
+            Delivery::Post { url, body } => http::post_json(url, body).await,
+            Delivery::Email { channel, mail } => smtp::send(channel, mail).await,
+        }
+    }
+
+    fn target(&self) -> &str {
+        match self {
+            Delivery::Post { url, .. } => url,
+            Delivery::Email { .. } => "email",
+        }
+    }
+}
+
+/// Send `event` to the targets of its process's `notify`, if it asks for
+/// this kind of event: webhooks get the event as JSON, channels a message
+/// from their template. With `notifications.cooldown` set, repeats are
+/// coalesced into summaries. Deliveries run in the background and are
+/// retried; failures only reach the daemon log.
+pub fn emit(notifier: &Notifier, paths: &Paths, event: &ProcessEvent) {
+    let (notifier, paths, event) = (notifier.clone(), paths.clone(), event.clone());
+    tokio::spawn(async move {
+        let wanted = notifier
+            .processes
+            .state(&event.name)
+            .await
+            .and_then(|managed| managed.config.notify)
+            .is_some_and(|notify| notify.wants(notify_event(&event)));
+        if !wanted {
+            return;
+        }
+
+        let notifications = notifier.settings.read().await.notifications.clone();
+        if let Ok(Some(cooldown)) = notifications.cooldown() {
+            let verdict =
+                notifier
+                    .coalescer
+                    .lock()
+                    .unwrap()
+                    .offer(&event, cooldown, Instant::now());
+            match verdict {
+                Verdict::Send => {}
+                Verdict::Hold { close_at: None } => return,
+                Verdict::Hold {
+                    close_at: Some(close_at),
+                } => {
+                    tokio::time::sleep_until(close_at).await;
+                    let summary = notifier.coalescer.lock().unwrap().close(
+                        &event.name,
+                        notify_event(&event),
+                        cooldown,
+                        Instant::now(),
+                    );
+                    if let Some(summary) = summary {
+                        let text = summary.text(notifications.cooldown.as_deref().unwrap_or("-"));
+                        let held = (summary.count, text.as_str());
+                        dispatch(
+                            &notifier.processes,
+                            &paths,
+                            &notifications,
+                            &summary.event,
+                            Some(held),
+                        )
+                        .await;
+                    }
+                    return;
+                }
+            }
+        }
+        dispatch(&notifier.processes, &paths, &notifications, &event, None).await;
+    });
+}
+
+/// Build the deliveries for `event`, or for a summary of held-back events
+/// ending with it, and start them.
+async fn dispatch(
+    processes: &Processes,
+    paths: &Paths,
+    notifications: &NotificationsSection,
+    event: &ProcessEvent,
+    summary: Option<(u32, &str)>,
+) {
+    let found = processes.state(&event.name).await.and_then(|managed| {
+        let notify = managed.config.notify.clone()?;
+        Some((notify, managed.stderr_log(paths), managed.stdout_log(paths)))
+    });
+    let Some((notify, stderr_log, stdout_log)) = found else {
+        return;
+    };
+
+    let mut deliveries = Vec::new();
+    let mut channels = Vec::new();
+    for target in notify.targets() {
+        match target {
+            NotifyTarget::Webhook(url) => deliveries.push(Delivery::Post {
+                url: url.to_string(),
+                body: payload(event, summary),
+            }),
+            NotifyTarget::Channel(channel) => channels.push(channel),
+        }
+    }
+    if !channels.is_empty() {
+        let logs = last_log_lines(stderr_log, stdout_log).await;
+        let mut values = values(event, &hostname(), &logs);
+        if let Some((_, text)) = summary {
+            values.insert("detail", text.to_string());
+        }
+        for channel in channels {
+            match Delivery::to_channel(channel, notifications, &values) {
+                Some(delivery) => deliveries.push(delivery),
+                None => warn!(
+                    "'{}' notifies {channel}, but daemon.toml has no [notifications.{channel}]",
+                    event.name
+                ),
+            }
+        }
+    }
+
+    for delivery in deliveries {
+        let name = event.name.clone();
+        tokio::spawn(async move {
+            if let Err(e) = deliver(&delivery, FIRST_BACKOFF).await {
+                warn!("failed to notify {} about '{name}': {e}", delivery.target());
+            }
+        });
+    }
+}
+
+/// Send `delivery`, trying up to `ATTEMPTS` times with a doubling wait from
+/// `backoff`.
+async fn deliver(delivery: &Delivery, mut backoff: Duration) -> Result<(), String> {
+    let mut attempt = 1;
+    loop {
+        let result = tokio::time::timeout(REQUEST_TIMEOUT, delivery.send())
+            .await
+            .unwrap_or_else(|_| Err("timed out".to_string()));
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt == ATTEMPTS => return Err(format!("{e} ({ATTEMPTS} tries)")),
+            Err(_) => {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_exits_are_told_apart_by_status() {
+        let exit = |status| ProcessEvent {
+            status: Some(status),
+            ..ProcessEvent::new(EventKind::Exit, "web")
+        };
+        assert_eq!(
+            notify_event(&exit(ProcessStatus::Crashed)),
+            NotifyEvent::Crash
+        );
+        assert_eq!(
+            notify_event(&exit(ProcessStatus::Stopped)),
+            NotifyEvent::Exit
+        );
+        assert_eq!(
+            notify_event(&ProcessEvent::new(EventKind::Errored, "web")),
+            NotifyEvent::Errored
+        );
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&payload(&exit(ProcessStatus::Crashed), None)).unwrap();
+        assert_eq!(body["type"], "crash");
+        assert_eq!(body["event"], "exit");
+        assert_eq!(body["name"], "web");
+    }
+
+    #[test]
+    fn test_coalescer_holds_repeats_for_the_cooldown() {
+        let cooldown = Duration::from_secs(600);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let restart = |name| ProcessEvent::new(EventKind::Restart, name);
+        let mut coalescer = Coalescer::default();
+
+        assert_eq!(
+            coalescer.offer(&restart("web"), cooldown, at(0)),
+            Verdict::Send
+        );
+        assert_eq!(
+            coalescer.offer(&restart("web"), cooldown, at(5)),
+            Verdict::Hold {
+                close_at: Some(at(600))
+            }
+        );
+        for secs in 6..19 {
+            assert_eq!(
+                coalescer.offer(&restart("web"), cooldown, at(secs)),
+                Verdict::Hold { close_at: None }
+            );
+        }
+        // Other processes and other kinds have windows of their own
+        assert_eq!(
+            coalescer.offer(&restart("db"), cooldown, at(20)),
+            Verdict::Send
+        );
+        assert_eq!(
+            coalescer.offer(
+                &ProcessEvent::new(EventKind::Errored, "web"),
+                cooldown,
+                at(20)
+            ),
+            Verdict::Send
+        );
+
+        let summary = coalescer
+            .close("web", NotifyEvent::Restart, cooldown, at(600))
+            .unwrap();
+        assert_eq!(summary.count, 14);
+        assert_eq!(
+            summary.text("10m"),
+            "web restarted 14 more times in the last 10m"
+        );
+
+        // The next window opened with the summary; once it passes quietly,
+        // events go straight out again
+        assert_eq!(
+            coalescer.offer(&restart("web"), cooldown, at(700)),
+            Verdict::Hold {
+                close_at: Some(at(1200))
+            }
+        );
+        assert_eq!(
+            coalescer
+                .close("web", NotifyEvent::Restart, cooldown, at(1200))
+                .map(|summary| summary.count),
+            Some(1)
+        );
+        assert_eq!(
+            coalescer.close("web", NotifyEvent::Restart, cooldown, at(1800)),
+            None
+        );
+        assert_eq!(
+            coalescer.offer(&restart("web"), cooldown, at(1900)),
+            Verdict::Send
+        );
+    }
+
+    #[test]
+    fn test_templates_fill_in_the_event() {
+        let event = ProcessEvent {
+            status: Some(ProcessStatus::Crashed),
+            exit_code: Some(137),
+            ..ProcessEvent::new(EventKind::Exit, "web")
+        };
+        let values = values(&event, "box1", "panic: out of memory");
+        assert_eq!(
+            render(
+                "{name} {event} on {host}: {exit_code} ({detail}) {json}",
+                &values
+            ),
+            "web crash on box1: 137 (-) {json}"
+        );
+        assert_eq!(
+            render("```\n{logs}\n```", &values),
+            "```\npanic: out of memory\n```"
+        );
+        assert_eq!(render("{ name }", &values), "{ name }");
+
+        assert!(check_template("{name} restarted {exit_code}").is_ok());
+        let err = check_template("{name} {uptime}").unwrap_err();
+        assert!(err.contains("{uptime}"), "{err}");
+    }
+
+    #[test]
+    fn test_channel_messages() {
+        let settings = settings::parse_settings(
+            r#"
+[notifications.slack]
+webhook_url = "https://hooks.slack.com/services/T0/B0/x"
+
+[notifications.discord]
+webhook_url = "https://discord.com/api/webhooks/1/x"
+template = "{name}: {logs}"
+"#,
+        )
+        .unwrap()
+        .notifications;
+        let values = values(
+            &ProcessEvent::new(EventKind::Restart, "web"),
+            "box1",
+            &"x".repeat(3000),
+        );
+
+        let Some(Delivery::Post { url, body }) =
+            Delivery::to_channel(Channel::Slack, &settings, &values)
+        else {
+            panic!("slack is set up");
+        };
+        assert_eq!(url, "https://hooks.slack.com/services/T0/B0/x");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(
+            body["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("*web* restart on box1")
+        );
+
+        let Some(Delivery::Post { body, .. }) =
+            Delivery::to_channel(Channel::Discord, &settings, &values)
+        else {
+            panic!("discord is set up");
+        };
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let content = body["content"].as_str().unwrap();
+        assert!(content.starts_with("web: xxx"));
+        assert_eq!(content.chars().count(), DISCORD_LIMIT);
+
+        assert!(Delivery::to_channel(Channel::Email, &settings, &values).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delivery_retries_until_accepted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            for status in ["500 Internal Server Error", "204 No Content"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await.unwrap();
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let delivery = Delivery::Post {
+            url,
+            body: b"{}".to_vec(),
+        };
+        deliver(&delivery, Duration::from_millis(10)).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delivery_gives_up() {
+        // Nothing listens here once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+
+        let delivery = Delivery::Post {
+            url,
+            body: b"{}".to_vec(),
+        };
+        let err = deliver(&delivery, Duration::from_millis(1))
+            .await
+            .unwrap_err();
+        assert!(err.contains("4 tries"), "{err}");
+    }
+}
@@ -1,5 +1,70 @@
 use color_eyre::eyre::bail;
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// ---------------------------------------------------------------------------
+// Profiles
+// ---------------------------------------------------------------------------
+//
+// Each data directory has a daemon of its own, so pointing a command at
+// another directory gives it an isolated daemon, process table and logs.
+
+/// An isolated daemon chosen with `--profile` or `--local`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Profile {
+    /// Lives in `profiles/<name>` under the default data directory.
+    Named(String),
+    /// Lives in `.pm3/` under a project directory.
+    Local(PathBuf),
+}
+
+impl Profile {
+    pub fn named(name: &str) -> color_eyre::Result<Self> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            bail!("profile names may only use letters, digits, `-` and `_`, got `{name}`");
+        }
+        Ok(Profile::Named(name.to_string()))
+    }
+
+    /// The data directory of this profile, given the default one.
+    fn data_dir(&self, default: &Path) -> PathBuf {
+        match self {
+            Profile::Named(name) => default.join("profiles").join(name),
+            Profile::Local(project) => project.join(".pm3"),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Named(name) => f.write_str(name),
+            Profile::Local(project) => write!(f, "local ({})", project.display()),
+        }
+    }
+}
+
+static PROFILE: OnceLock<Profile> = OnceLock::new();
+
+/// Make every `Paths::new` in this process use `profile`. Only the first
+/// call counts.
+pub fn select_profile(profile: Profile) {
+    let _ = PROFILE.set(profile);
+}
+
+/// The profile selected for this process, if any.
+pub fn active_profile() -> Option<&'static Profile> {
+    PROFILE.get()
+}
+
+// ---------------------------------------------------------------------------
+// Paths
+// ---------------------------------------------------------------------------
 
 #[derive(Clone)]
 pub struct Paths {
@@ -7,18 +72,23 @@ pub struct Paths {
 }
 
 impl Paths {
+    /// The selected profile's data directory, else `PM3_DATA_DIR`, else
+    /// `pm3` in the platform's data directory.
     pub fn new() -> color_eyre::Result<Self> {
-        if let Ok(path) = std::env::var("PM3_DATA_DIR") {
-            return Ok(Self {
-                data_dir: PathBuf::from(path),
-            });
-        }
-        let Some(base) = dirs::data_dir() else {
-            bail!("could not determine data directory");
+        let default = match std::env::var("PM3_DATA_DIR") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => {
+                let Some(base) = dirs::data_dir() else {
+                    bail!("could not determine data directory");
+                };
+                base.join("pm3")
+            }
         };
-        Ok(Self {
-            data_dir: base.join("pm3"),
-        })
+        let data_dir = match active_profile() {
+            Some(profile) => profile.data_dir(&default),
+            None => default,
+        };
+        Ok(Self { data_dir })
     }
 
     pub fn with_base(base: PathBuf) -> Self {
@@ -37,10 +107,72 @@ impl Paths {
         self.data_dir.join("pm3.sock")
     }
 
+    pub fn history_dir(&self) -> PathBuf {
+        self.data_dir.join("history")
+    }
+
+    pub fn history_file(&self, name: &str) -> PathBuf {
+        self.history_dir().join(format!("{name}.jsonl"))
+    }
+
+    pub fn events_dir(&self) -> PathBuf {
+        self.data_dir.join("events")
+    }
+
+    pub fn events_file(&self, name: &str) -> PathBuf {
+        self.events_dir().join(format!("{name}.jsonl"))
+    }
+
+    pub fn samples_dir(&self) -> PathBuf {
+        self.data_dir.join("samples")
+    }
+
+    pub fn samples_file(&self, name: &str) -> PathBuf {
+        self.samples_dir().join(format!("{name}.jsonl"))
+    }
+
+    pub fn storage_db(&self) -> PathBuf {
+        self.data_dir.join("pm3.db")
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.data_dir.join("daemon.toml")
+    }
+
+    pub fn bootstrap_log(&self) -> PathBuf {
+        self.data_dir.join("bootstrap.log")
+    }
+
+    pub fn daemon_log(&self) -> PathBuf {
+        self.data_dir.join("daemon.log")
+    }
+
+    /// Log lines waiting for the `log_ship` endpoint to come back.
+    pub fn ship_buffer(&self) -> PathBuf {
+        self.data_dir.join("log-ship.jsonl")
+    }
+
+    pub fn audit_log(&self) -> PathBuf {
+        self.data_dir.join("audit.jsonl")
+    }
+
+    /// Every lifecycle event of every process, as JSON Lines.
+    pub fn event_log(&self) -> PathBuf {
+        self.data_dir.join("events.jsonl")
+    }
+
     pub fn dump_file(&self) -> PathBuf {
         self.data_dir.join("dump.json")
     }
 
+    pub fn journal_file(&self) -> PathBuf {
+        self.data_dir.join("journal.json")
+    }
+
+    pub fn control_dir(&self) -> PathBuf {
+        self.data_dir.join("control")
+    }
+
     pub fn log_dir(&self) -> PathBuf {
         self.data_dir.join("logs")
     }
@@ -53,6 +185,18 @@ impl Paths {
         self.data_dir.join("logs").join(format!("{name}-err.log"))
     }
 
+    pub fn generation_stdout_log(&self, name: &str, generation: u64) -> PathBuf {
+        self.data_dir
+            .join("logs")
+            .join(format!("{name}-gen{generation}-out.log"))
+    }
+
+    pub fn generation_stderr_log(&self, name: &str, generation: u64) -> PathBuf {
+        self.data_dir
+            .join("logs")
+            .join(format!("{name}-gen{generation}-err.log"))
+    }
+
     pub fn rotated_stdout_log(&self, name: &str, n: u32) -> PathBuf {
         self.data_dir
             .join("logs")
@@ -92,6 +236,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_profile_data_dirs() {
+        let default = Path::new("/home/ada/.local/share/pm3");
+        let profile = Profile::named("api-v2").unwrap();
+        assert_eq!(
+            profile.data_dir(default),
+            PathBuf::from("/home/ada/.local/share/pm3/profiles/api-v2")
+        );
+        assert_eq!(profile.to_string(), "api-v2");
+
+        let local = Profile::Local(PathBuf::from("/srv/shop"));
+        assert_eq!(local.data_dir(default), PathBuf::from("/srv/shop/.pm3"));
+        assert_eq!(local.to_string(), "local (/srv/shop)");
+
+        for bad in ["", "../etc", "a b", "x/y"] {
+            assert!(Profile::named(bad).is_err(), "{bad:?}");
+        }
+    }
+
     #[test]
     fn test_pid_file_under_data_dir() {
         let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
@@ -108,6 +271,41 @@ mod tests {
         assert!(sock.ends_with("pm3.sock"));
     }
 
+    #[test]
+    fn test_history_file_per_process() {
+        let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
+        let history = paths.history_file("web");
+        assert!(history.starts_with(paths.history_dir()));
+        assert!(history.ends_with("web.jsonl"));
+    }
+
+    #[test]
+    fn test_storage_paths_under_data_dir() {
+        let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
+        assert!(paths.samples_file("web").starts_with(paths.samples_dir()));
+        assert!(paths.samples_file("web").ends_with("web.jsonl"));
+        assert!(paths.events_file("web").starts_with(paths.events_dir()));
+        assert_eq!(paths.event_log(), paths.data_dir().join("events.jsonl"));
+        assert!(paths.storage_db().starts_with(paths.data_dir()));
+        assert!(paths.storage_db().ends_with("pm3.db"));
+    }
+
+    #[test]
+    fn test_settings_file_under_data_dir() {
+        let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
+        let settings = paths.settings_file();
+        assert!(settings.starts_with(paths.data_dir()));
+        assert!(settings.ends_with("daemon.toml"));
+    }
+
+    #[test]
+    fn test_bootstrap_log_under_data_dir() {
+        let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
+        let log = paths.bootstrap_log();
+        assert!(log.starts_with(paths.data_dir()));
+        assert!(log.ends_with("bootstrap.log"));
+    }
+
     #[test]
     fn test_dump_file_under_data_dir() {
         let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
@@ -138,6 +336,21 @@ mod tests {
         assert!(log.ends_with("logs/web-err.log"));
     }
 
+    #[test]
+    fn test_generation_logs_include_generation() {
+        let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
+        assert!(
+            paths
+                .generation_stdout_log("web", 3)
+                .ends_with("logs/web-gen3-out.log")
+        );
+        assert!(
+            paths
+                .generation_stderr_log("web", 3)
+                .ends_with("logs/web-gen3-err.log")
+        );
+    }
+
     #[test]
     fn test_rotated_stdout_log_format() {
         let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
@@ -1,4 +1,5 @@
 use crate::paths::Paths;
+use crate::stats;
 use nix::sys::signal;
 use nix::unistd::Pid;
 use std::io;
@@ -31,6 +32,11 @@ pub fn is_daemon_running_sync(paths: &Paths) -> io::Result<bool> {
     };
 
     match signal::kill(Pid::from_raw(pid as i32), None) {
+        // A daemon that crashed may linger as a zombie
+        Ok(()) if stats::is_zombie(pid) => {
+            let _ = std::fs::remove_file(paths.pid_file());
+            Ok(false)
+        }
         Ok(()) => Ok(true),
         Err(nix::errno::Errno::ESRCH) => {
             let _ = std::fs::remove_file(paths.pid_file());
@@ -48,6 +54,11 @@ pub async fn is_daemon_running(paths: &Paths) -> io::Result<bool> {
     };
 
     match signal::kill(Pid::from_raw(pid as i32), None) {
+        // A daemon that crashed may linger as a zombie
+        Ok(()) if stats::is_zombie(pid) => {
+            remove_pid_file(paths).await;
+            Ok(false)
+        }
         Ok(()) => Ok(true),
         Err(nix::errno::Errno::ESRCH) => {
             // Process doesn't exist — stale PID file
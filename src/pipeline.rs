@@ -0,0 +1,162 @@
+use crate::config::ProcessConfig;
+use std::collections::HashMap;
+
+// ---------------------------------------------------------------------------
+// Graph
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum PipelineError {
+    #[error("no process named `{0}` in the config")]
+    UnknownTarget(String),
+    #[error("process `{name}`: `after` names unknown process `{missing}`")]
+    UnknownDependency { name: String, missing: String },
+    #[error("process `{0}` is not a task; only kind = \"task\" processes can run in pipelines")]
+    NotATask(String),
+    #[error("pipeline cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+/// Check every `after` list in `configs`: only tasks declare one, it names
+/// other tasks, and following them never leads back to where it started.
+pub fn check(configs: &HashMap<String, ProcessConfig>) -> Result<(), PipelineError> {
+    let mut names: Vec<&String> = configs.keys().collect();
+    names.sort();
+    for name in names {
+        let config = &configs[name];
+        if config.after.is_some() {
+            if !config.is_task() {
+                return Err(PipelineError::NotATask(name.clone()));
+            }
+            plan(configs, name)?;
+        }
+    }
+    Ok(())
+}
+
+/// The tasks `target` needs, ordered so each comes after everything in its
+/// `after` list; `target` itself is last.
+pub fn plan(
+    configs: &HashMap<String, ProcessConfig>,
+    target: &str,
+) -> Result<Vec<String>, PipelineError> {
+    if !configs.contains_key(target) {
+        return Err(PipelineError::UnknownTarget(target.to_string()));
+    }
+    let mut order = Vec::new();
+    let mut path = Vec::new();
+    visit(configs, target, &mut path, &mut order)?;
+    Ok(order)
+}
+
+/// Depth-first post-order walk; `path` holds the tasks being visited so a
+/// cycle can be reported in full.
+fn visit(
+    configs: &HashMap<String, ProcessConfig>,
+    name: &str,
+    path: &mut Vec<String>,
+    order: &mut Vec<String>,
+) -> Result<(), PipelineError> {
+    if order.iter().any(|done| done == name) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|seen| seen == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name.to_string());
+        return Err(PipelineError::Cycle(cycle));
+    }
+    let config = &configs[name];
+    if !config.is_task() {
+        return Err(PipelineError::NotATask(name.to_string()));
+    }
+
+    path.push(name.to_string());
+    for dep in config.after.iter().flatten() {
+        if !configs.contains_key(dep) {
+            return Err(PipelineError::UnknownDependency {
+                name: name.to_string(),
+                missing: dep.clone(),
+            });
+        }
+        visit(configs, dep, path, order)?;
+    }
+    path.pop();
+    order.push(name.to_string());
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessKind;
+
+    fn task(after: &[&str]) -> ProcessConfig {
+        ProcessConfig {
+            command: "true".to_string(),
+            kind: Some(ProcessKind::Task),
+            after: (!after.is_empty()).then(|| after.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    fn configs(entries: &[(&str, ProcessConfig)]) -> HashMap<String, ProcessConfig> {
+        entries
+            .iter()
+            .map(|(name, config)| (name.to_string(), config.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_orders_dependencies_first() {
+        let configs = configs(&[
+            ("build", task(&[])),
+            ("migrate", task(&[])),
+            ("test", task(&["build"])),
+            ("deploy", task(&["test", "migrate", "build"])),
+            ("unrelated", task(&[])),
+        ]);
+        assert_eq!(
+            plan(&configs, "deploy").unwrap(),
+            ["build", "test", "migrate", "deploy"]
+        );
+        assert_eq!(plan(&configs, "build").unwrap(), ["build"]);
+        assert!(check(&configs).is_ok());
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        let cyclic = configs(&[
+            ("a", task(&["c"])),
+            ("b", task(&["a"])),
+            ("c", task(&["b"])),
+        ]);
+        assert_eq!(
+            plan(&cyclic, "a").unwrap_err().to_string(),
+            "pipeline cycle: a -> c -> b -> a"
+        );
+
+        let service = ProcessConfig {
+            command: "web".to_string(),
+            ..Default::default()
+        };
+        let mixed = configs(&[("web", service), ("deploy", task(&["web", "gone"]))]);
+        assert_eq!(
+            plan(&mixed, "deploy").unwrap_err(),
+            PipelineError::NotATask("web".to_string())
+        );
+        assert_eq!(
+            plan(&mixed, "nope").unwrap_err(),
+            PipelineError::UnknownTarget("nope".to_string())
+        );
+
+        let missing = configs(&[("deploy", task(&["gone"]))]);
+        assert!(matches!(
+            check(&missing).unwrap_err(),
+            PipelineError::UnknownDependency { missing, .. } if missing == "gone"
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::paths::Paths;
+use crate::process;
+use crate::protocol::ProcessEvent;
+use crate::settings::PluginSection;
+use std::ffi::OsString;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Executables named `pm3-<name>` on `PATH` are run as `pm3 <name>`.
+pub const PLUGIN_PREFIX: &str = "pm3-";
+
+/// Event plugins are killed if they run longer than this, so a stuck notifier
+/// cannot pile up behind a crash-looping process.
+pub const EVENT_PLUGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum PluginError {
+    #[error("unknown command '{0}' (no {PLUGIN_PREFIX}{0} found on PATH)")]
+    NotFound(String),
+    #[error("failed to run plugin {}: {message}", path.display())]
+    Spawn { path: PathBuf, message: String },
+}
+
+// ---------------------------------------------------------------------------
+// Command plugins
+// ---------------------------------------------------------------------------
+
+/// Environment handed to every plugin so it can talk to the daemon.
+pub fn plugin_env(paths: &Paths) -> Vec<(&'static str, OsString)> {
+    let mut env = vec![
+        ("PM3_DATA_DIR", paths.data_dir().as_os_str().to_owned()),
+        ("PM3_SOCKET", paths.socket_file().into_os_string()),
+    ];
+    if let Ok(exe) = std::env::current_exe() {
+        env.push(("PM3_BIN", exe.into_os_string()));
+    }
+    env
+}
+
+/// Find the executable for `pm3 <name>` in the directories of `path_var`.
+pub fn find_plugin(name: &str, path_var: Option<&std::ffi::OsStr>) -> Option<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    let file = format!("{PLUGIN_PREFIX}{name}");
+    std::env::split_paths(path_var?)
+        .map(|dir| dir.join(&file))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Run `pm3-<args[0]>` with the remaining arguments, inheriting stdio, and
+/// return its exit code.
+pub fn run_command_plugin(paths: &Paths, args: &[OsString]) -> Result<i32, PluginError> {
+    let name = args
+        .first()
+        .map(|a| a.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = find_plugin(&name, std::env::var_os("PATH").as_deref())
+        .ok_or_else(|| PluginError::NotFound(name.clone()))?;
+
+    let status = std::process::Command::new(&path)
+        .args(&args[1..])
+        .envs(plugin_env(paths))
+        .status()
+        .map_err(|e| PluginError::Spawn {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+    // A plugin killed by a signal reports like a shell would
+    Ok(status.code().unwrap_or_else(|| {
+        128 + std::os::unix::process::ExitStatusExt::signal(&status).unwrap_or(0)
+    }))
+}
+
+// ---------------------------------------------------------------------------
+// Event plugins
+// ---------------------------------------------------------------------------
+
+/// Deliver `event` to every plugin in `plugins` subscribed to it. Plugins run
+/// in the background; failures are only echoed to the daemon's stderr.
+pub fn emit(plugins: &[PluginSection], paths: &Paths, event: ProcessEvent) {
+    for plugin in plugins.iter().filter(|p| p.subscribes_to(event.event)) {
+        let plugin = plugin.clone();
+        let paths = paths.clone();
+        let event = event.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_event_plugin(&plugin.command, &paths, &event).await {
+                tracing::warn!(
+                    "plugin '{}' failed on {} event: {e}",
+                    plugin.command,
+                    event.event
+                );
+            }
+        });
+    }
+}
+
+async fn run_event_plugin(
+    command: &str,
+    paths: &Paths,
+    event: &ProcessEvent,
+) -> Result<(), String> {
+    let (program, args) = process::parse_command(command).map_err(|e| e.to_string())?;
+    let mut cmd = tokio::process::Command::new(&program);
+    cmd.args(&args)
+        .envs(plugin_env(paths))
+        .env("PM3_EVENT", event.event.to_string())
+        .env("PM3_NAME", &event.name)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true);
+    let mut child = cmd.spawn().map_err(|e| e.to_string())?;
+
+    let mut line = serde_json::to_string(event).map_err(|e| e.to_string())?;
+    line.push('\n');
+    if let Some(mut stdin) = child.stdin.take() {
+        // A plugin that ignores its input may close stdin early
+        let _ = stdin.write_all(line.as_bytes()).await;
+    }
+
+    match tokio::time::timeout(EVENT_PLUGIN_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if status.success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("exited with {status}")),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!(
+            "timed out after {}s",
+            EVENT_PLUGIN_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{EventKind, ProcessStatus};
+
+    fn write_script(dir: &Path, name: &str, mode: u32) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, "#!/bin/sh\nexit 0\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_find_plugin_searches_path_in_order() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let expected = write_script(second.path(), "pm3-notify", 0o755);
+        write_script(first.path(), "pm3-other", 0o755);
+
+        let path_var = std::env::join_paths([first.path(), second.path()]).unwrap();
+        assert_eq!(find_plugin("notify", Some(&path_var)), Some(expected));
+        assert_eq!(find_plugin("missing", Some(&path_var)), None);
+        assert_eq!(find_plugin("notify", None), None);
+    }
+
+    #[test]
+    fn test_find_plugin_skips_non_executables() {
+        let dir = tempfile::tempdir().unwrap();
+        write_script(dir.path(), "pm3-notify", 0o644);
+        let path_var = dir.path().as_os_str();
+        assert_eq!(find_plugin("notify", Some(path_var)), None);
+        assert_eq!(find_plugin("../notify", Some(path_var)), None);
+        assert_eq!(find_plugin("", Some(path_var)), None);
+    }
+
+    #[test]
+    fn test_event_serialization_omits_missing_fields() {
+        let event = ProcessEvent {
+            timestamp: 1,
+            ..ProcessEvent::new(EventKind::Start, "web")
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"start","name":"web","timestamp":1}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_plugin_receives_event_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        let out = dir.path().join("out");
+        let command = format!(
+            "sh -c 'cat > {} && echo $PM3_EVENT:$PM3_NAME >> {}'",
+            out.display(),
+            out.display()
+        );
+        let event = ProcessEvent {
+            status: Some(ProcessStatus::Crashed),
+            exit_code: Some(1),
+            ..ProcessEvent::new(EventKind::Exit, "web")
+        };
+
+        run_event_plugin(&command, &paths, &event).await.unwrap();
+
+        let written = std::fs::read_to_string(&out).unwrap();
+        let mut lines = written.lines();
+        let received: ProcessEvent = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(received, event);
+        assert_eq!(lines.next(), Some("exit:web"));
+    }
+
+    #[tokio::test]
+    async fn test_event_plugin_failure_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        let event = ProcessEvent::new(EventKind::Start, "web");
+        let err = run_event_plugin("false", &paths, &event).await.unwrap_err();
+        assert!(err.contains("exited"), "{err}");
+    }
+}
@@ -0,0 +1,226 @@
+use crate::config::ProcessConfig;
+use crate::stats::ResourceSample;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "wasm")]
+pub use wasm::Policy;
+
+// ---------------------------------------------------------------------------
+// Guest API
+// ---------------------------------------------------------------------------
+//
+// A policy is a WASM module with no imports. Every export is optional except
+// the version, and each hook returns 1 (yes), 0 (no) or -1 (no opinion, use
+// pm3's built-in behaviour):
+//
+//   pm3_api_version() -> i32                      must return GUEST_API_VERSION
+//   pm3_should_restart(exit_code: i32, exited: i32, uptime_ms: i64,
+//                      restarts: i32) -> i32
+//       `exited` is 0 when the process was killed by a signal, in which case
+//       `exit_code` is meaningless.
+//   pm3_check_health(uptime_ms: i64, rss_bytes: i64, cpu_time_ms: i64,
+//                    fd_count: i32, restarts: i32) -> i32
+//       Called about once a second while the process runs.
+
+pub const GUEST_API_VERSION: i32 = 1;
+
+/// Instructions a single hook call may execute before it is aborted.
+pub const FUEL_LIMIT: u64 = 10_000_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("policy {}: pm3 was built without the `wasm` feature", .0.display())]
+    Unsupported(PathBuf),
+    #[error("failed to read policy {}: {message}", path.display())]
+    Io { path: PathBuf, message: String },
+    #[error("invalid policy {}: {message}", path.display())]
+    Invalid { path: PathBuf, message: String },
+    #[error("policy {} failed in {export}: {message}", path.display())]
+    Trap {
+        path: PathBuf,
+        export: &'static str,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Yes,
+    No,
+    /// The hook is not exported or declined to decide.
+    Abstain,
+}
+
+impl From<i32> for Verdict {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => Verdict::Yes,
+            0 => Verdict::No,
+            _ => Verdict::Abstain,
+        }
+    }
+}
+
+impl Verdict {
+    /// Apply the verdict to pm3's own decision.
+    pub fn or(self, default: bool) -> bool {
+        match self {
+            Verdict::Yes => true,
+            Verdict::No => false,
+            Verdict::Abstain => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RestartInput {
+    pub exit_code: Option<i32>,
+    pub uptime: Duration,
+    pub restarts: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HealthInput {
+    pub uptime: Duration,
+    pub sample: ResourceSample,
+    pub restarts: u32,
+}
+
+// Stand-in when WASM support is compiled out: no policy can ever be loaded,
+// so the hook methods are unreachable.
+#[cfg(not(feature = "wasm"))]
+#[derive(Debug)]
+pub enum Policy {}
+
+#[cfg(not(feature = "wasm"))]
+impl Policy {
+    pub fn from_file(path: &Path) -> Result<Self, PolicyError> {
+        Err(PolicyError::Unsupported(path.to_path_buf()))
+    }
+
+    pub fn should_restart(&self, _input: RestartInput) -> Result<Verdict, PolicyError> {
+        match *self {}
+    }
+
+    pub fn check_health(&self, _input: HealthInput) -> Result<Verdict, PolicyError> {
+        match *self {}
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Loading
+// ---------------------------------------------------------------------------
+
+/// The module path of `config`'s policy; relative paths are resolved against
+/// the process's `cwd`, like its command.
+pub fn policy_path(config: &ProcessConfig) -> Option<PathBuf> {
+    let path = Path::new(config.policy.as_deref()?);
+    Some(match &config.cwd {
+        Some(cwd) if path.is_relative() => Path::new(cwd).join(path),
+        _ => path.to_path_buf(),
+    })
+}
+
+// Compiled modules keyed by path, recompiled when the file changes so a new
+// policy takes effect on the next restart without restarting the daemon.
+type Cache = HashMap<PathBuf, (Option<SystemTime>, Arc<Policy>)>;
+
+fn cache() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// The compiled policy for `config`, or `None` if it has none. Compiling can
+/// take a while, so async callers should make the first call off the runtime.
+pub fn load(config: &ProcessConfig) -> Result<Option<Arc<Policy>>, PolicyError> {
+    let Some(path) = policy_path(config) else {
+        return Ok(None);
+    };
+    let modified = std::fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .map_err(|e| PolicyError::Io {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+
+    if let Some((cached_at, policy)) = cache().lock().unwrap().get(&path)
+        && *cached_at == Some(modified)
+    {
+        return Ok(Some(Arc::clone(policy)));
+    }
+
+    let policy = Arc::new(Policy::from_file(&path)?);
+    cache()
+        .lock()
+        .unwrap()
+        .insert(path, (Some(modified), Arc::clone(&policy)));
+    Ok(Some(policy))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict_from_guest_value() {
+        assert_eq!(Verdict::from(1), Verdict::Yes);
+        assert_eq!(Verdict::from(0), Verdict::No);
+        assert_eq!(Verdict::from(-1), Verdict::Abstain);
+        assert_eq!(Verdict::from(42), Verdict::Abstain);
+        assert!(Verdict::Abstain.or(true));
+        assert!(!Verdict::No.or(true));
+    }
+
+    #[test]
+    fn test_policy_path_resolves_against_cwd() {
+        let mut config = ProcessConfig {
+            policy: Some("policy.wasm".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(policy_path(&config), Some(PathBuf::from("policy.wasm")));
+        config.cwd = Some("/srv/app".to_string());
+        assert_eq!(
+            policy_path(&config),
+            Some(PathBuf::from("/srv/app/policy.wasm"))
+        );
+        config.policy = Some("/etc/pm3/policy.wasm".to_string());
+        assert_eq!(
+            policy_path(&config),
+            Some(PathBuf::from("/etc/pm3/policy.wasm"))
+        );
+        assert_eq!(policy_path(&ProcessConfig::default()), None);
+    }
+
+    #[test]
+    fn test_load_missing_module_errors() {
+        let config = ProcessConfig {
+            policy: Some("/nonexistent/policy.wasm".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(load(&config), Err(PolicyError::Io { .. })));
+        assert!(load(&ProcessConfig::default()).unwrap().is_none());
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    #[test]
+    fn test_load_without_wasm_feature_is_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.wasm");
+        std::fs::write(&path, b"\0asm").unwrap();
+        let config = ProcessConfig {
+            policy: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(load(&config), Err(PolicyError::Unsupported(_))));
+    }
+}
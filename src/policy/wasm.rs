@@ -0,0 +1,279 @@
+use super::{FUEL_LIMIT, GUEST_API_VERSION, HealthInput, PolicyError, RestartInput, Verdict};
+use std::path::{Path, PathBuf};
+use wasmtime::{Config, Engine, Instance, Module, Store, WasmParams, WasmResults};
+
+/// A compiled policy module. Each hook call runs in a fresh instance with a
+/// fuel budget, so guests keep no state between calls and cannot hang the
+/// daemon.
+pub struct Policy {
+    path: PathBuf,
+    engine: Engine,
+    module: Module,
+}
+
+impl std::fmt::Debug for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Policy").field("path", &self.path).finish()
+    }
+}
+
+impl Policy {
+    pub fn from_file(path: &Path) -> Result<Self, PolicyError> {
+        let bytes = std::fs::read(path).map_err(|e| PolicyError::Io {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        Self::from_bytes(path, &bytes)
+    }
+
+    /// Compile a module (binary or text format) and check it speaks the
+    /// current guest API.
+    pub fn from_bytes(path: &Path, bytes: &[u8]) -> Result<Self, PolicyError> {
+        let invalid = |message: String| PolicyError::Invalid {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| invalid(e.to_string()))?;
+        let module = Module::new(&engine, bytes).map_err(|e| invalid(e.to_string()))?;
+
+        if let Some(import) = module.imports().next() {
+            return Err(invalid(format!(
+                "imports are not allowed (found {}::{})",
+                import.module(),
+                import.name()
+            )));
+        }
+
+        let policy = Self {
+            path: path.to_path_buf(),
+            engine,
+            module,
+        };
+        match policy.call::<(), i32>("pm3_api_version", ())? {
+            Some(GUEST_API_VERSION) => Ok(policy),
+            Some(version) => Err(invalid(format!(
+                "guest API version {version} is not supported (expected {GUEST_API_VERSION})"
+            ))),
+            None => Err(invalid("missing export pm3_api_version".to_string())),
+        }
+    }
+
+    pub fn should_restart(&self, input: RestartInput) -> Result<Verdict, PolicyError> {
+        let result = self.call::<(i32, i32, i64, i32), i32>(
+            "pm3_should_restart",
+            (
+                input.exit_code.unwrap_or(0),
+                input.exit_code.is_some() as i32,
+                input.uptime.as_millis() as i64,
+                input.restarts as i32,
+            ),
+        )?;
+        Ok(result.map_or(Verdict::Abstain, Verdict::from))
+    }
+
+    pub fn check_health(&self, input: HealthInput) -> Result<Verdict, PolicyError> {
+        let result = self.call::<(i64, i64, i64, i32, i32), i32>(
+            "pm3_check_health",
+            (
+                input.uptime.as_millis() as i64,
+                input.sample.rss_bytes as i64,
+                input.sample.cpu_time_ms as i64,
+                input.sample.fd_count as i32,
+                input.restarts as i32,
+            ),
+        )?;
+        Ok(result.map_or(Verdict::Abstain, Verdict::from))
+    }
+
+    /// Call `export` in a fresh instance; `None` if the module does not
+    /// export it.
+    fn call<P: WasmParams, R: WasmResults>(
+        &self,
+        export: &'static str,
+        params: P,
+    ) -> Result<Option<R>, PolicyError> {
+        let trap = |message: String| PolicyError::Trap {
+            path: self.path.clone(),
+            export,
+            message,
+        };
+
+        let mut store = Store::new(&self.engine, ());
+        store
+            .set_fuel(FUEL_LIMIT)
+            .map_err(|e| trap(e.to_string()))?;
+        let instance =
+            Instance::new(&mut store, &self.module, &[]).map_err(|e| trap(e.to_string()))?;
+        let Some(func) = instance.get_func(&mut store, export) else {
+            return Ok(None);
+        };
+        let func = func
+            .typed::<P, R>(&store)
+            .map_err(|e| PolicyError::Invalid {
+                path: self.path.clone(),
+                message: format!("{export} has the wrong signature: {e}"),
+            })?;
+        func.call(&mut store, params)
+            .map(Some)
+            .map_err(|e| trap(e.to_string()))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::ResourceSample;
+    use std::time::Duration;
+
+    const RESTART_POLICY: &str = r#"
+(module
+  (func (export "pm3_api_version") (result i32) i32.const 1)
+  ;; Never restart on exit code 3, restart everything else while uptime is
+  ;; under a second, and leave the rest to pm3
+  (func (export "pm3_should_restart")
+        (param $code i32) (param $exited i32) (param $uptime i64) (param $restarts i32)
+        (result i32)
+    (if (i32.and (local.get $exited) (i32.eq (local.get $code) (i32.const 3)))
+      (then (return (i32.const 0))))
+    (if (i64.lt_u (local.get $uptime) (i64.const 1000))
+      (then (return (i32.const 1))))
+    i32.const -1))
+"#;
+
+    fn policy(wat: &str) -> Result<Policy, PolicyError> {
+        Policy::from_bytes(Path::new("test.wat"), wat.as_bytes())
+    }
+
+    fn restart(exit_code: Option<i32>, uptime_ms: u64) -> RestartInput {
+        RestartInput {
+            exit_code,
+            uptime: Duration::from_millis(uptime_ms),
+            restarts: 0,
+        }
+    }
+
+    #[test]
+    fn test_should_restart_verdicts() {
+        let policy = policy(RESTART_POLICY).unwrap();
+        assert_eq!(
+            policy.should_restart(restart(Some(3), 10)).unwrap(),
+            Verdict::No
+        );
+        assert_eq!(
+            policy.should_restart(restart(Some(1), 10)).unwrap(),
+            Verdict::Yes
+        );
+        assert_eq!(
+            policy.should_restart(restart(None, 5000)).unwrap(),
+            Verdict::Abstain
+        );
+    }
+
+    #[test]
+    fn test_missing_hook_abstains() {
+        let policy = policy(RESTART_POLICY).unwrap();
+        let input = HealthInput {
+            uptime: Duration::from_secs(1),
+            sample: ResourceSample {
+                rss_bytes: 1024,
+                cpu_time_ms: 0,
+                fd_count: 3,
+                thread_count: 1,
+                process_count: 1,
+            },
+            restarts: 0,
+        };
+        assert_eq!(policy.check_health(input).unwrap(), Verdict::Abstain);
+    }
+
+    #[test]
+    fn test_health_hook_sees_sample() {
+        let policy = policy(
+            r#"
+(module
+  (func (export "pm3_api_version") (result i32) i32.const 1)
+  (func (export "pm3_check_health")
+        (param i64) (param $rss i64) (param i64) (param i32) (param i32) (result i32)
+    (i64.lt_u (local.get $rss) (i64.const 1000000))))
+"#,
+        )
+        .unwrap();
+        let input = |rss_bytes| HealthInput {
+            uptime: Duration::from_secs(1),
+            sample: ResourceSample {
+                rss_bytes,
+                cpu_time_ms: 0,
+                fd_count: 3,
+                thread_count: 1,
+                process_count: 1,
+            },
+            restarts: 0,
+        };
+        assert_eq!(policy.check_health(input(512)).unwrap(), Verdict::Yes);
+        assert_eq!(policy.check_health(input(2_000_000)).unwrap(), Verdict::No);
+    }
+
+    #[test]
+    fn test_rejects_bad_modules() {
+        let missing_version = policy("(module)").unwrap_err();
+        assert!(missing_version.to_string().contains("pm3_api_version"));
+
+        let wrong_version =
+            policy(r#"(module (func (export "pm3_api_version") (result i32) i32.const 2))"#)
+                .unwrap_err();
+        assert!(wrong_version.to_string().contains("version 2"));
+
+        let imports = policy(
+            r#"(module (import "env" "clock" (func)) (func (export "pm3_api_version") (result i32) i32.const 1))"#,
+        )
+        .unwrap_err();
+        assert!(imports.to_string().contains("env::clock"));
+
+        assert!(matches!(
+            policy("not wasm"),
+            Err(PolicyError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_wrong_signature_is_invalid() {
+        let policy = policy(
+            r#"
+(module
+  (func (export "pm3_api_version") (result i32) i32.const 1)
+  (func (export "pm3_should_restart") (result i32) i32.const 1))
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            policy.should_restart(restart(Some(1), 0)),
+            Err(PolicyError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_runaway_guest_runs_out_of_fuel() {
+        let policy = policy(
+            r#"
+(module
+  (func (export "pm3_api_version") (result i32) i32.const 1)
+  (func (export "pm3_should_restart")
+        (param i32) (param i32) (param i64) (param i32) (result i32)
+    (loop $forever (br $forever))
+    i32.const 1))
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            policy.should_restart(restart(Some(1), 0)),
+            Err(PolicyError::Trap { .. })
+        ));
+    }
+}
@@ -1,14 +1,45 @@
-use crate::config::{ProcessConfig, RestartPolicy};
-use crate::log::{self, LogEntry, LogStream};
+use crate::clock;
+use crate::config::{ProcessConfig, ReadyCheck, RestartPolicy, StdinMode};
+use crate::control::{self, ControlChannel};
+use crate::event_log;
+use crate::hooks::{self, HookError, HookKind};
+use crate::journal::{JournalEntry, RunIdentity};
+use crate::log::{self, LineFormatter, LogEntry, LogStream, OutputMatches, Rotation};
+use crate::notify::{self, Notifier};
 use crate::paths::Paths;
-use crate::protocol::{ProcessInfo, ProcessStatus};
+use crate::plugin;
+use crate::policy::{self, HealthInput, PolicyError, RestartInput};
+use crate::protocol::{
+    EventKind, JobInfo, ProcessDetail, ProcessEvent, ProcessInfo, ProcessStatus, ResourceSnapshot,
+    RunReason, RunRecord,
+};
+use crate::pty;
+use crate::ready::{self, ReadySources};
+use crate::settings::{DaemonSettings, PluginSection};
+use crate::ship::Shipper;
+use crate::stats::{self, ResourceSample};
+use crate::storage::{self, Storage};
+use crate::systemd;
+use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
+use nix::unistd::Pid;
 use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::net::unix::pipe;
 use tokio::process::{Child, Command};
-use tokio::sync::{RwLock, broadcast, watch};
+use tokio::sync::{RwLock, broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
 
 // ---------------------------------------------------------------------------
 // Constants
@@ -16,11 +47,31 @@ use tokio::sync::{RwLock, broadcast, watch};
 
 pub const DEFAULT_KILL_TIMEOUT_MS: u64 = 5000;
 pub const DEFAULT_KILL_SIGNAL: &str = "SIGTERM";
+/// Tells each instance of a process with `instances` set which one it is.
+pub const INSTANCE_ENV: &str = "PM3_INSTANCE";
 pub const DEFAULT_MAX_RESTARTS: u32 = 15;
 pub const BACKOFF_BASE_MS: u64 = 100;
 pub const BACKOFF_CAP_MS: u64 = 30_000;
 pub const DEFAULT_MIN_UPTIME_MS: u64 = 1000;
 
+/// How long a monitor waits for output copiers to reach EOF after the process
+/// exits. Bounded because a detached grandchild can hold the pipes open.
+const LOG_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often a restart held back by host pressure looks at it again, and the
+/// longest it is held back before going ahead anyway.
+const PRESSURE_RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+const PRESSURE_MAX_DEFERRAL: Duration = Duration::from_secs(300);
+
+/// How often a monitor checks whether an adopted process is still running.
+const ADOPTED_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Writes to a run's stdin that may wait while it is not reading.
+const STDIN_QUEUE: usize = 64;
+
+/// `ioprio_set` target meaning a single process; libc does not export it.
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
 // ---------------------------------------------------------------------------
 // Error
 // ---------------------------------------------------------------------------
@@ -35,6 +86,14 @@ pub enum ProcessError {
     NotFound(String),
     #[error("invalid signal: {0}")]
     InvalidSignal(String),
+    #[error("invalid log_scrub pattern: {0}")]
+    InvalidLogScrub(String),
+    #[error("invalid success_pattern or failure_pattern: {0}")]
+    InvalidResultPattern(String),
+    #[error(transparent)]
+    Hook(#[from] HookError),
+    #[error(transparent)]
+    Policy(#[from] PolicyError),
 }
 
 // ---------------------------------------------------------------------------
@@ -54,61 +113,349 @@ pub fn parse_command(command: &str) -> Result<(String, Vec<String>), ProcessErro
     Ok((program, args))
 }
 
+/// Shell operators that only have meaning when a shell interprets the command.
+const SHELL_OPERATORS: &[&str] = &["&&", "||", ">>", "|", ";", "&", ">", "<", "$(", "`"];
+
+/// Inspect a command string for constructs that look like shell syntax but
+/// will be passed literally to the program, since pm3 does not spawn a shell.
+pub fn lint_command(command: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut seen_ops: Vec<&str> = Vec::new();
+    let mut seen_vars: Vec<String> = Vec::new();
+
+    // Operators, quotes and variable names are all ASCII, so scanning bytes
+    // never misreads part of a multi-byte character as syntax.
+    let bytes = command.as_bytes();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\\' && !in_single {
+            i += 2;
+            continue;
+        }
+        if c == b'\'' && !in_double {
+            in_single = !in_single;
+        } else if c == b'"' && !in_single {
+            in_double = !in_double;
+        } else if c == b'$' && !in_single {
+            let var: String = bytes[i + 1..]
+                .iter()
+                .skip_while(|b| **b == b'{')
+                .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_')
+                .map(|b| *b as char)
+                .collect();
+            if !var.is_empty() && !seen_vars.contains(&var) {
+                seen_vars.push(var);
+            }
+        }
+        if !in_single
+            && !in_double
+            && let Some(op) = SHELL_OPERATORS
+                .iter()
+                .find(|op| bytes[i..].starts_with(op.as_bytes()))
+        {
+            if !seen_ops.contains(op) {
+                seen_ops.push(op);
+            }
+            i += op.len();
+            continue;
+        }
+        i += 1;
+    }
+
+    for op in seen_ops {
+        warnings.push(format!(
+            "unquoted `{op}` is passed to the program as a literal argument; \
+             pm3 does not run commands through a shell (wrap it in `sh -c '...'`)"
+        ));
+    }
+    for var in seen_vars {
+        warnings.push(format!(
+            "`${var}` is not expanded; use the `env` config field or wrap the command in `sh -c '...'`"
+        ));
+    }
+
+    warnings
+}
+
 // ---------------------------------------------------------------------------
 // Signal parsing
 // ---------------------------------------------------------------------------
 
+/// Resolve a signal given as a name (`SIGUSR2`, `USR2`, `usr2`) or a number
+/// (`12`).
 pub fn parse_signal(name: &str) -> Result<nix::sys::signal::Signal, ProcessError> {
-    let normalized = if name.starts_with("SIG") {
-        name.to_string()
+    let invalid = || ProcessError::InvalidSignal(name.to_string());
+    let name_or_number = name.trim();
+    if let Ok(number) = name_or_number.parse::<i32>() {
+        return nix::sys::signal::Signal::try_from(number).map_err(|_| invalid());
+    }
+    let upper = name_or_number.to_ascii_uppercase();
+    let normalized = if upper.starts_with("SIG") {
+        upper
     } else {
-        format!("SIG{name}")
+        format!("SIG{upper}")
     };
-    nix::sys::signal::Signal::from_str(&normalized)
-        .map_err(|_| ProcessError::InvalidSignal(name.to_string()))
+    nix::sys::signal::Signal::from_str(&normalized).map_err(|_| invalid())
+}
+
+/// `SIGKILL` for 9; the bare number for signals nix does not know.
+pub fn signal_name(signal: i32) -> String {
+    nix::sys::signal::Signal::try_from(signal)
+        .map(|s| s.as_str().to_string())
+        .unwrap_or_else(|_| signal.to_string())
 }
 
 // ---------------------------------------------------------------------------
 // ManagedProcess
 // ---------------------------------------------------------------------------
 
-pub struct ManagedProcess {
+/// Env var names whose values are hidden in `pm3 info`.
+const SECRET_ENV_MARKERS: &[&str] = &[
+    "KEY",
+    "SECRET",
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "AUTH",
+    "PRIVATE",
+];
+
+/// Copy of `env` with values of secret-looking variables replaced by `****`.
+pub fn mask_env(env: &HashMap<String, String>) -> HashMap<String, String> {
+    env.iter()
+        .map(|(key, value)| {
+            let upper = key.to_ascii_uppercase();
+            let secret = SECRET_ENV_MARKERS.iter().any(|m| upper.contains(m));
+            let value = if secret { "****" } else { value.as_str() };
+            (key.clone(), value.to_string())
+        })
+        .collect()
+}
+
+/// How a stop ended a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOutcome {
+    /// There was no live process to stop.
+    NotRunning,
+    /// The process exited after `kill_signal` within `kill_timeout`.
+    Graceful,
+    /// The process outlived `kill_timeout` and was sent SIGKILL.
+    ForceKilled,
+}
+
+impl StopOutcome {
+    /// What a stop/restart result notes about how the run ended.
+    pub fn note(self) -> Option<String> {
+        match self {
+            StopOutcome::ForceKilled => Some("force-killed".to_string()),
+            StopOutcome::NotRunning | StopOutcome::Graceful => None,
+        }
+    }
+}
+
+/// What a process's actor knows about it, short of the resources only the
+/// actor itself holds. `Processes::snapshot` copies it out whole for
+/// requests that read across processes.
+#[derive(Clone)]
+pub struct ProcessState {
     pub name: String,
     pub config: ProcessConfig,
     pub pid: Option<u32>,
     pub status: ProcessStatus,
     pub started_at: tokio::time::Instant,
     pub restarts: u32,
+    /// Bumped every time a changed config is applied to this process name.
+    pub generation: u64,
+    /// Exit code of the most recent run, if it exited normally.
+    pub exit_code: Option<i32>,
+    /// Signal that ended the most recent run, if it was killed.
+    pub exit_signal: Option<i32>,
+    /// When the most recent run ended (unix ms).
+    pub exited_at: Option<i64>,
+    /// How long the most recent run lasted, once it settled without a
+    /// restart.
+    pub ran_for: Option<Duration>,
+    /// Why the current run replaced the previous one, if it did.
+    pub last_restart: Option<String>,
+    /// Latest RSS sample for the process tree, filled in by the daemon sampler.
+    pub memory_bytes: Option<u64>,
+    /// Times the process was restarted for exceeding `max_memory`.
+    pub memory_restarts: u32,
     pub log_broadcaster: broadcast::Sender<LogEntry>,
     pub monitor_shutdown: Option<watch::Sender<bool>>,
+    /// Where hook output and hook failures for this run are recorded.
+    pub hook_log: PathBuf,
+    pub run: Arc<RunStats>,
+    /// The run's `$PM3_CONTROL_SOCKET`, if it could be bound.
+    pub control: Option<Arc<ControlChannel>>,
+    /// Feeds the run's stdin, with `stdin = "pipe"`.
+    pub stdin: Option<mpsc::Sender<Vec<u8>>>,
+    /// Set while the process is `Unhealthy` for missing `heartbeat_timeout`.
+    pub heartbeat_lost: bool,
+    /// `cron_restart` runs deferred by `overlap_policy = "queue"`, started
+    /// one after another as each run exits.
+    pub queued_runs: u32,
+    /// What the journal records so a later daemon can adopt this run.
+    pub identity: Option<RunIdentity>,
+}
+
+/// A process as its actor owns it: its state, and what a new run hands over
+/// to the monitor that watches it.
+pub struct ManagedProcess {
+    pub state: ProcessState,
+    /// Log subscription for a `ready_check = { log = ... }`, taken before the
+    /// output copiers start so no early line is missed.
+    pub ready_logs: Option<broadcast::Receiver<LogEntry>>,
+    /// Output copier tasks, drained by the monitor before the run is recorded.
+    pub log_copiers: Vec<JoinHandle<()>>,
+}
+
+impl Deref for ManagedProcess {
+    type Target = ProcessState;
+
+    fn deref(&self) -> &ProcessState {
+        &self.state
+    }
+}
+
+impl DerefMut for ManagedProcess {
+    fn deref_mut(&mut self) -> &mut ProcessState {
+        &mut self.state
+    }
 }
 
 impl ManagedProcess {
+    /// Streams the ready check reads from; the log subscription can only be
+    /// taken once.
+    pub fn ready_sources(&mut self) -> ReadySources {
+        ReadySources {
+            logs: self.ready_logs.take(),
+            control: self.control.as_ref().map(|c| c.state.subscribe_ready()),
+        }
+    }
+}
+
+impl ProcessState {
     pub fn to_process_info(&self) -> ProcessInfo {
+        let sample = self.pid.and(self.run.last_sample());
         ProcessInfo {
             name: self.name.clone(),
             pid: self.pid,
             status: self.status,
-            uptime: Some(self.started_at.elapsed().as_secs()),
+            uptime: Some(clock::elapsed(self.started_at).as_secs()),
             restarts: self.restarts,
-            cpu_percent: None,
-            memory_bytes: None,
+            cpu_percent: self.pid.and(self.run.cpu_percent()),
+            memory_bytes: self.memory_bytes,
             group: self.config.group.clone(),
+            generation: self.generation,
+            memory_restarts: self.memory_restarts,
+            fd_count: sample.map(|s| s.fd_count),
+            thread_count: sample.map(|s| s.thread_count),
+            process_count: sample.map(|s| s.process_count),
+            metrics: self
+                .control
+                .as_ref()
+                .map(|c| c.state.metrics())
+                .unwrap_or_default(),
+            exit_code: self.pid.is_none().then_some(self.exit_code).flatten(),
+            duration_ms: self.ran_for.map(|d| d.as_millis() as u64),
+            last_exit_code: self.exit_code,
+            last_exit_signal: self.exit_signal.map(signal_name),
+            exited_at: self.exited_at,
         }
     }
 
-    pub async fn graceful_stop(&mut self) -> Result<(), ProcessError> {
+    pub fn to_job_info(&self) -> JobInfo {
+        let schedule = self.config.cron_restart.clone().unwrap_or_default();
+        let paused = self
+            .monitor_shutdown
+            .as_ref()
+            .is_some_and(|tx| *tx.borrow());
+        let next_run = schedule
+            .parse::<crate::cron::Schedule>()
+            .ok()
+            .filter(|_| !paused)
+            .and_then(|s| s.next_after(clock::now_local().naive_local()))
+            .and_then(|t| t.and_local_timezone(chrono::Local).earliest())
+            .map(|t| t.timestamp_millis());
+        let ran = self.pid.is_some() || self.ran_for.is_some();
+        JobInfo {
+            name: self.name.clone(),
+            schedule,
+            status: self.status,
+            next_run,
+            last_run: ran.then(|| self.run.started_at.timestamp_millis()),
+            exit_code: self.pid.is_none().then_some(self.exit_code).flatten(),
+            duration_ms: self.ran_for.map(|d| d.as_millis() as u64),
+        }
+    }
+
+    /// What is left of `restart_window` since this run was started as a
+    /// restart, while there is any.
+    pub fn restart_throttle(&self) -> Option<Duration> {
+        let window = self.config.restart_window()?;
+        self.last_restart.as_ref()?;
+        let left = window.saturating_sub(clock::elapsed(self.started_at));
+        (!left.is_zero()).then_some(left)
+    }
+
+    pub fn to_process_detail(&self, paths: &Paths) -> ProcessDetail {
+        let info = self.to_process_info();
+        ProcessDetail {
+            name: info.name,
+            pid: info.pid,
+            status: info.status,
+            uptime: info.uptime,
+            restarts: info.restarts,
+            cpu_percent: info.cpu_percent,
+            memory_bytes: info.memory_bytes,
+            group: info.group,
+            generation: info.generation,
+            memory_restarts: info.memory_restarts,
+            command: self.config.command.clone(),
+            cwd: self.config.cwd.clone(),
+            env: self.config.effective_env().as_ref().map(mask_env),
+            exit_code: self.exit_code,
+            stdout_log: Some(self.stdout_log(paths).display().to_string()),
+            stderr_log: Some(self.stderr_log(paths).display().to_string()),
+            health_check: self.config.health_check.clone(),
+            depends_on: self.config.depends_on.clone(),
+            exit_signal: self.exit_signal.map(signal_name),
+            exited_at: self.exited_at,
+            last_restart: self.last_restart.clone(),
+            restart_throttle_ms: self.restart_throttle().map(|d| d.as_millis() as u64),
+            environment: self.config.active_env.clone(),
+            resources: self.run.snapshot(),
+            metrics: info.metrics,
+            tree: Vec::new(),
+        }
+    }
+
+    pub fn stdout_log(&self, paths: &Paths) -> PathBuf {
+        log_paths(&self.name, &self.config, self.generation, paths).0
+    }
+
+    pub fn stderr_log(&self, paths: &Paths) -> PathBuf {
+        log_paths(&self.name, &self.config, self.generation, paths).1
+    }
+
+    /// Start stopping the process: keep its monitor from restarting it and
+    /// mark it `Stopping`. The returned `Stopper` carries out the rest and
+    /// needs nothing from the process, so the process's actor is free for
+    /// other messages while the run dies. `None` if nothing is running.
+    pub fn begin_stop(&mut self) -> Result<Option<Stopper>, ProcessError> {
         // Signal the monitor not to auto-restart
         if let Some(ref tx) = self.monitor_shutdown {
             let _ = tx.send(true);
         }
 
-        let raw_pid = match self.pid {
-            Some(pid) => pid,
-            None => {
-                self.status = ProcessStatus::Stopped;
-                return Ok(());
-            }
+        let Some(pid) = self.pid else {
+            self.status = ProcessStatus::Stopped;
+            return Ok(None);
         };
 
         let signal_name = self
@@ -117,19 +464,85 @@ impl ManagedProcess {
             .as_deref()
             .unwrap_or(DEFAULT_KILL_SIGNAL);
         let signal = parse_signal(signal_name)?;
+        self.status = ProcessStatus::Stopping;
 
-        let timeout_ms = self.config.kill_timeout.unwrap_or(DEFAULT_KILL_TIMEOUT_MS);
+        Ok(Some(Stopper {
+            name: self.name.clone(),
+            pid,
+            signal,
+            config: self.config.clone(),
+            hook_log: self.hook_log.clone(),
+            run: Arc::clone(&self.run),
+            control: self.control.as_ref().map(|c| Arc::clone(&c.state)),
+        }))
+    }
+
+    /// Record that the stop of the run with `pid` is over. The monitor may
+    /// have recorded it already, and a run started since is left alone.
+    pub fn finish_stop(&mut self, pid: u32) {
+        if self.pid == Some(pid) {
+            self.pid = None;
+            self.status = ProcessStatus::Stopped;
+        }
+    }
+}
+
+/// A stop begun by `ProcessState::begin_stop`: signals the run with
+/// `kill_signal` (default SIGTERM), waits up to `kill_timeout`, then
+/// escalates to SIGKILL, all without touching the process.
+pub struct Stopper {
+    name: String,
+    pid: u32,
+    signal: nix::sys::signal::Signal,
+    config: ProcessConfig,
+    hook_log: PathBuf,
+    run: Arc<RunStats>,
+    control: Option<Arc<control::ControlState>>,
+}
+
+impl Stopper {
+    /// Pid of the run being stopped, to hand to `finish_stop`.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub async fn run(self) -> StopOutcome {
+        let Stopper {
+            name,
+            pid,
+            signal,
+            config,
+            hook_log,
+            run,
+            control,
+        } = self;
+        let timeout_ms = config.kill_timeout.unwrap_or(DEFAULT_KILL_TIMEOUT_MS);
         let duration = Duration::from_millis(timeout_ms);
 
-        let pid = nix::unistd::Pid::from_raw(raw_pid as i32);
-        let _ = nix::sys::signal::kill(pid, signal);
+        hooks::run_hook_best_effort(HookKind::PreStop, &name, &config, &hook_log).await;
+
+        // Last look at resource usage while the process is still alive
+        if let Some(sample) = stats::sample_tree(&stats::ProcessTree::read(), pid) {
+            run.record_sample(sample);
+        }
+
+        if let Some(ref control) = control {
+            control.request_shutdown();
+        }
+
+        let _ = signal_run(pid, &config, signal);
 
-        // Poll for process exit
+        // Poll for process exit (with `kill_tree`, for the whole group to
+        // exit); a `stopping` message on the control socket can push SIGKILL
+        // back
         let deadline = tokio::time::Instant::now() + duration;
-        while nix::sys::signal::kill(pid, None).is_ok() {
-            if tokio::time::Instant::now() >= deadline {
+        let mut outcome = StopOutcome::Graceful;
+        while signal_run(pid, &config, None).is_ok() {
+            let drain = control.as_ref().and_then(|c| c.drain_deadline());
+            if tokio::time::Instant::now() >= drain.map_or(deadline, |d| d.max(deadline)) {
                 // Timeout — escalate to SIGKILL
-                let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL);
+                let _ = signal_run(pid, &config, nix::sys::signal::Signal::SIGKILL);
+                outcome = StopOutcome::ForceKilled;
                 // Brief wait for SIGKILL to take effect
                 tokio::time::sleep(Duration::from_millis(100)).await;
                 break;
@@ -137,255 +550,1936 @@ impl ManagedProcess {
             tokio::time::sleep(Duration::from_millis(50)).await;
         }
 
-        self.pid = None;
-        self.status = ProcessStatus::Stopped;
-        Ok(())
+        info!(process = %name, pid, ?outcome, "stopped");
+        outcome
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Run accounting
+// ---------------------------------------------------------------------------
+
+/// Resource accounting for a single run, shared by the table entry, its log
+/// copiers and its monitor so the final snapshot survives the entry being
+/// replaced.
+#[derive(Debug)]
+pub struct RunStats {
+    pub generation: u64,
+    started_at: chrono::DateTime<chrono::Utc>,
+    started: tokio::time::Instant,
+    pub log_bytes: Arc<AtomicU64>,
+    /// What the run's output matched of `success_pattern` / `failure_pattern`.
+    pub output: Arc<OutputMatches>,
+    samples: std::sync::Mutex<RunSamples>,
+}
+
+#[derive(Debug, Default)]
+struct RunSamples {
+    last: Option<ResourceSample>,
+    /// When `last` was taken.
+    taken: Option<std::time::Instant>,
+    /// CPU use between the last two samples, in percent of one core.
+    cpu_percent: Option<f64>,
+    peak_rss_bytes: Option<u64>,
+}
+
+impl RunStats {
+    pub fn new(generation: u64) -> Self {
+        Self {
+            generation,
+            started_at: clock::now_utc(),
+            started: clock::now(),
+            log_bytes: Arc::new(AtomicU64::new(0)),
+            output: Arc::default(),
+            samples: std::sync::Mutex::new(RunSamples::default()),
+        }
+    }
+
+    /// Stats for a run that started `elapsed` ago under another daemon.
+    fn resumed(generation: u64, elapsed: Duration) -> Self {
+        let now = clock::now();
+        Self {
+            started_at: clock::now_utc() - chrono::Duration::from_std(elapsed).unwrap_or_default(),
+            started: now.checked_sub(elapsed).unwrap_or(now),
+            ..Self::new(generation)
+        }
+    }
+
+    pub fn record_sample(&self, sample: ResourceSample) {
+        let now = std::time::Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        if let (Some(last), Some(taken)) = (samples.last, samples.taken) {
+            let elapsed = now.duration_since(taken).as_millis() as u64;
+            samples.cpu_percent = sample
+                .cpu_time_ms
+                .checked_sub(last.cpu_time_ms)
+                .filter(|_| elapsed > 0)
+                .map(|used| used as f64 * 100.0 / elapsed as f64);
+        }
+        samples.last = Some(sample);
+        samples.taken = Some(now);
+        samples.peak_rss_bytes = samples.peak_rss_bytes.max(Some(sample.rss_bytes));
+    }
+
+    pub fn last_sample(&self) -> Option<ResourceSample> {
+        self.samples.lock().unwrap().last
+    }
+
+    /// CPU use between the last two samples, once there are two.
+    pub fn cpu_percent(&self) -> Option<f64> {
+        self.samples.lock().unwrap().cpu_percent
+    }
+
+    pub fn snapshot(&self) -> ResourceSnapshot {
+        let samples = self.samples.lock().unwrap();
+        ResourceSnapshot {
+            rss_bytes: samples.last.map(|s| s.rss_bytes),
+            peak_rss_bytes: samples.peak_rss_bytes,
+            cpu_time_ms: samples.last.map(|s| s.cpu_time_ms),
+            fd_count: samples.last.map(|s| s.fd_count),
+            thread_count: samples.last.map(|s| s.thread_count),
+            process_count: samples.last.map(|s| s.process_count),
+            runtime_ms: clock::elapsed(self.started).as_millis() as u64,
+            log_bytes: self.log_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn finish(
+        &self,
+        name: &str,
+        status: ProcessStatus,
+        exit_code: Option<i32>,
+        reason: Option<RunReason>,
+    ) -> RunRecord {
+        RunRecord {
+            name: name.to_string(),
+            generation: self.generation,
+            started_at: self.started_at.timestamp_millis(),
+            ended_at: clock::now_utc().timestamp_millis(),
+            status,
+            exit_code,
+            reason,
+            snapshot: self.snapshot(),
+        }
     }
 }
 
 // ---------------------------------------------------------------------------
-// ProcessTable
+// Process actors
 // ---------------------------------------------------------------------------
+//
+// Every process is owned by an actor: a task of its own that holds the
+// process's state and changes it only in answer to the messages in its
+// mailbox, one at a time. Requests, monitors and the daemon's background
+// tasks reach it by name through `Processes`, which maps names to mailboxes
+// and is never held across an await, so a process that is slow to stop,
+// spawn or answer holds up no other. Messages are quick by construction:
+// stopping a run, spawning one and running hooks happen between messages,
+// with a message to begin and another to record the end.
+
+/// What a process's actor is asked to do with the process it owns.
+enum Message {
+    /// Look at or change the process.
+    Run(Box<dyn FnOnce(&mut ManagedProcess) + Send>),
+    /// Hand over a copy of the state, then answer no other message until
+    /// released.
+    Hold(oneshot::Sender<ProcessState>, oneshot::Receiver<()>),
+}
+
+/// Every process's actor holding still, as `Processes::hold` left them. They
+/// are released when this is dropped.
+pub struct Held {
+    pub table: ProcessTable,
+    _release: Vec<oneshot::Sender<()>>,
+}
+
+/// A copy of every process's state, for requests that read across
+/// processes: listings, dependencies, groups and instances.
+pub type ProcessTable = HashMap<String, ProcessState>;
+
+/// The mailbox of one process's actor.
+#[derive(Clone)]
+pub struct Actor {
+    mailbox: mpsc::UnboundedSender<Message>,
+}
+
+impl Actor {
+    /// Start an actor owning `managed`. It runs until the last handle to its
+    /// mailbox is gone, then drops the process.
+    fn spawn(mut managed: ManagedProcess) -> Self {
+        let (mailbox, mut inbox) = mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            while let Some(message) = inbox.recv().await {
+                match message {
+                    Message::Run(f) => f(&mut managed),
+                    Message::Hold(state, release) => {
+                        let _ = state.send(managed.state.clone());
+                        let _ = release.await;
+                    }
+                }
+            }
+        });
+        Self { mailbox }
+    }
+
+    /// Have the actor run `f` on its process and hand back the result, or
+    /// `None` if the actor is gone.
+    pub async fn with<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut ManagedProcess) -> R + Send + 'static,
+    ) -> Option<R> {
+        let (reply, answer) = oneshot::channel();
+        let message = Message::Run(Box::new(move |managed| {
+            let _ = reply.send(f(managed));
+        }));
+        self.mailbox.send(message).ok()?;
+        answer.await.ok()
+    }
+
+    /// Stop the process's current run: the actor begins the stop, the run is
+    /// waited out between messages, and the actor then records the end.
+    pub async fn stop(&self) -> Result<StopOutcome, ProcessError> {
+        let begun = self.with(|managed| managed.begin_stop()).await;
+        let Some(stopper) = begun.transpose()?.flatten() else {
+            return Ok(StopOutcome::NotRunning);
+        };
+        let pid = stopper.pid();
+        let outcome = stopper.run().await;
+        self.with(move |managed| managed.finish_stop(pid)).await;
+        Ok(outcome)
+    }
+}
+
+/// The daemon's processes: the actor of each, by name.
+#[derive(Clone, Default)]
+pub struct Processes {
+    actors: Arc<Mutex<HashMap<String, Actor>>>,
+}
+
+impl Processes {
+    pub fn contains(&self, name: &str) -> bool {
+        self.actors.lock().unwrap().contains_key(name)
+    }
+
+    pub fn actor(&self, name: &str) -> Option<Actor> {
+        self.actors.lock().unwrap().get(name).cloned()
+    }
+
+    /// Give `managed` an actor under its name, in place of whatever process
+    /// had it.
+    pub fn insert(&self, managed: ManagedProcess) {
+        let name = managed.name.clone();
+        let actor = Actor::spawn(managed);
+        self.actors.lock().unwrap().insert(name, actor);
+    }
 
-pub type ProcessTable = HashMap<String, ManagedProcess>;
+    /// Like `insert`, but only while the name is free; `managed` comes back
+    /// otherwise.
+    pub fn insert_new(&self, managed: ManagedProcess) -> Result<(), Box<ManagedProcess>> {
+        let mut actors = self.actors.lock().unwrap();
+        if actors.contains_key(&managed.name) {
+            return Err(Box::new(managed));
+        }
+        actors.insert(managed.name.clone(), Actor::spawn(managed));
+        Ok(())
+    }
+
+    /// Swap the fresh run `managed` in for the current run of its process.
+    /// The process's actor hands `replace` the current run and the fresh one
+    /// and swaps if it returns `Ok`. The run comes back with `replace`'s
+    /// error if it was turned away, or with none if there is no such process.
+    pub async fn place<R, E>(
+        &self,
+        mut managed: ManagedProcess,
+        replace: impl FnOnce(&mut ManagedProcess, &mut ManagedProcess) -> Result<R, E> + Send + 'static,
+    ) -> Result<R, (Box<ManagedProcess>, Option<E>)>
+    where
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        let Some(actor) = self.actor(&managed.name) else {
+            return Err((Box::new(managed), None));
+        };
+        let (processes, mailbox) = (self.clone(), actor.mailbox.clone());
+        actor
+            .with(move |current| {
+                // Not once the process has been taken out of the table
+                let listed = processes
+                    .actors
+                    .lock()
+                    .unwrap()
+                    .get(&current.name)
+                    .is_some_and(|actor| actor.mailbox.same_channel(&mailbox));
+                if !listed {
+                    return Err((Box::new(managed), None));
+                }
+                match replace(current, &mut managed) {
+                    Ok(replaced) => {
+                        *current = managed;
+                        Ok(replaced)
+                    }
+                    Err(e) => Err((Box::new(managed), Some(e))),
+                }
+            })
+            .await
+            .expect("a process actor outlives its mailbox")
+    }
+
+    /// Take `name` out of the table. Its actor still answers through the
+    /// returned mailbox until that is dropped.
+    pub fn remove(&self, name: &str) -> Option<Actor> {
+        self.actors.lock().unwrap().remove(name)
+    }
+
+    /// Run `f` on the process `name` on its actor; `None` if there is none.
+    pub async fn with<R: Send + 'static>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut ManagedProcess) -> R + Send + 'static,
+    ) -> Option<R> {
+        self.actor(name)?.with(f).await
+    }
+
+    /// Stop the current run of `name`, as `Actor::stop` does. `None` if
+    /// there is no such process.
+    pub async fn stop(&self, name: &str) -> Option<Result<StopOutcome, ProcessError>> {
+        Some(self.actor(name)?.stop().await)
+    }
+
+    /// A copy of the state of `name`.
+    pub async fn state(&self, name: &str) -> Option<ProcessState> {
+        self.with(name, |managed| managed.state.clone()).await
+    }
+
+    /// Have every process's actor hold still: each hands over a copy of its
+    /// process's state, then changes nothing until the `Held` is dropped.
+    pub async fn hold(&self) -> Held {
+        let actors: Vec<(String, Actor)> = self
+            .actors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, actor)| (name.clone(), actor.clone()))
+            .collect();
+        let mut held = Held {
+            table: HashMap::with_capacity(actors.len()),
+            _release: Vec::with_capacity(actors.len()),
+        };
+        for (name, actor) in actors {
+            let (state, copy) = oneshot::channel();
+            let (release, released) = oneshot::channel();
+            if actor.mailbox.send(Message::Hold(state, released)).is_err() {
+                continue;
+            }
+            held._release.push(release);
+            if let Ok(state) = copy.await {
+                held.table.insert(name, state);
+            }
+        }
+        held
+    }
+
+    /// A copy of every process's state. Each actor answers for itself, so the
+    /// copy is not taken at one instant across processes.
+    pub async fn snapshot(&self) -> ProcessTable {
+        let actors: Vec<(String, Actor)> = self
+            .actors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, actor)| (name.clone(), actor.clone()))
+            .collect();
+        let mut table = HashMap::with_capacity(actors.len());
+        for (name, actor) in actors {
+            if let Some(state) = actor.with(|managed| managed.state.clone()).await {
+                table.insert(name, state);
+            }
+        }
+        table
+    }
+}
 
 // ---------------------------------------------------------------------------
 // Spawning
 // ---------------------------------------------------------------------------
 
+/// Resolve the (stdout, stderr) log files for a process generation:
+/// `out_file` and `error_file` when set, else the data dir's. When the logs
+/// are merged both are the stdout log.
+pub fn log_paths(
+    name: &str,
+    config: &ProcessConfig,
+    generation: u64,
+    paths: &Paths,
+) -> (PathBuf, PathBuf) {
+    let (stdout, stderr) = if config.log_per_generation == Some(true) {
+        (
+            paths.generation_stdout_log(name, generation),
+            paths.generation_stderr_log(name, generation),
+        )
+    } else {
+        (paths.stdout_log(name), paths.stderr_log(name))
+    };
+    let stdout = config.out_file.as_ref().map_or(stdout, PathBuf::from);
+    let stderr = config.error_file.as_ref().map_or(stderr, PathBuf::from);
+    if config.merges_logs() {
+        (stdout.clone(), stdout)
+    } else {
+        (stdout, stderr)
+    }
+}
+
+/// Apply the process's working directory and environment to `cmd`. Shared by
+/// the process itself and its lifecycle hooks.
+pub fn configure_command(cmd: &mut Command, config: &ProcessConfig) {
+    for var in systemd::DAEMON_ENV {
+        cmd.env_remove(var);
+    }
+    if let Some(ref cwd) = config.cwd {
+        cmd.current_dir(cwd);
+    }
+    if let Some(env) = config.effective_env() {
+        cmd.envs(env);
+    }
+}
+
+fn line_formatter(
+    config: &ProcessConfig,
+    settings: &DaemonSettings,
+    shipper: Option<Shipper>,
+) -> Result<LineFormatter, ProcessError> {
+    let rotation = Rotation {
+        interval: config.log_rotate_interval,
+        compress: config.log_compress == Some(true),
+        ..settings.logs.rotation().unwrap_or_default()
+    };
+    LineFormatter::new(
+        config.log_date_format.clone(),
+        config.log_scrub.as_deref().unwrap_or_default(),
+    )
+    .map_err(|e| ProcessError::InvalidLogScrub(e.to_string()))?
+    .with_result_patterns(
+        config.success_pattern.as_deref(),
+        config.failure_pattern.as_deref(),
+    )
+    .map_err(|e| ProcessError::InvalidResultPattern(e.to_string()))
+    .map(|formatter| {
+        formatter
+            .with_rotation(rotation)
+            .with_ansi_stripped(config.log_strip_ansi != Some(false))
+            .with_shipper(shipper)
+    })
+}
+
+/// Send `signal` to a run, or with `None` check that it is still there. With
+/// `kill_tree` this reaches the run's whole process group, which it leads
+/// from its own session.
+pub fn signal_run(
+    pid: u32,
+    config: &ProcessConfig,
+    signal: impl Into<Option<nix::sys::signal::Signal>>,
+) -> nix::Result<()> {
+    let pid = nix::unistd::Pid::from_raw(pid as i32);
+    if config.kill_tree() {
+        nix::sys::signal::killpg(pid, signal)
+    } else {
+        nix::sys::signal::kill(pid, signal)
+    }
+}
+
+/// Start the child in a session of its own. It leads the session's process
+/// group, so `pm3 signal --group-leader` and stopping with `kill_tree` reach
+/// the children it forks without touching the daemon, and it has no
+/// controlling terminal to take signals from.
+fn start_session(cmd: &mut Command) {
+    // SAFETY: setsid is async-signal-safe
+    unsafe {
+        cmd.pre_exec(|| {
+            nix::unistd::setsid()?;
+            Ok(())
+        });
+    }
+}
+
+/// Let the child inherit the read ends of its own output pipes. They keep the
+/// pipes open if the daemon dies, so the child's next write does not kill it
+/// with SIGPIPE and the next daemon can reopen them from `/proc`.
+fn keep_output_readers(cmd: &mut Command, readers: Vec<RawFd>) {
+    use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+    // SAFETY: fcntl is async-signal-safe, and clearing close-on-exec after
+    // the fork only affects the child's copies of the descriptors
+    unsafe {
+        cmd.pre_exec(move || {
+            for &fd in &readers {
+                let fd = std::os::fd::BorrowedFd::borrow_raw(fd);
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Start feeding what `pm3 attach` sends into a run's stdin. Input stops
+/// being accepted once the run closes its end.
+fn spawn_stdin_writer(writer: std::io::PipeWriter) -> std::io::Result<mpsc::Sender<Vec<u8>>> {
+    let mut writer = pipe::Sender::from_owned_fd(OwnedFd::from(writer))?;
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(STDIN_QUEUE);
+    tokio::spawn(async move {
+        while let Some(data) = rx.recv().await {
+            if writer.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(tx)
+}
+
+/// Where a spawned run's output is read from.
+enum Outputs {
+    /// Read ends of separate stdout and stderr pipes.
+    Pipes(OwnedFd, OwnedFd),
+    /// The read end of the one pipe both are written to, with `merge_logs`.
+    Merged(OwnedFd),
+    /// Nothing: both go to /dev/null, with `log = false`.
+    Discarded,
+    /// The master end of the terminal both are written to, with `tty`.
+    Tty(pty::PtyReader),
+}
+
+/// Start copying a run's stdout and stderr into its log files and broadcaster.
+/// A run on a terminal has no stderr of its own.
+fn spawn_log_copiers(
+    name: &str,
+    (stdout, stderr): (
+        impl tokio::io::AsyncRead + Unpin + Send + 'static,
+        Option<pipe::Receiver>,
+    ),
+    (stdout_log, stderr_log): (PathBuf, PathBuf),
+    formatter: LineFormatter,
+    log_tx: &broadcast::Sender<LogEntry>,
+    run: &RunStats,
+) -> Vec<JoinHandle<()>> {
+    let mut copiers = vec![log::spawn_log_copier(
+        name.to_string(),
+        LogStream::Stdout,
+        stdout,
+        stdout_log,
+        formatter.clone(),
+        log_tx.clone(),
+        Arc::clone(&run.log_bytes),
+    )];
+    if let Some(stderr) = stderr {
+        copiers.push(log::spawn_log_copier(
+            name.to_string(),
+            LogStream::Stderr,
+            stderr,
+            stderr_log,
+            formatter,
+            log_tx.clone(),
+            Arc::clone(&run.log_bytes),
+        ));
+    }
+    copiers
+}
+
+fn pipe_inode(fd: &OwnedFd) -> std::io::Result<u64> {
+    Ok(std::fs::metadata(format!("/proc/self/fd/{}", fd.as_raw_fd()))?.ino())
+}
+
+/// Apply `nice` and `ionice` in the child before it execs, so the priorities
+/// hold from the first instruction and pass on to what it forks.
+fn apply_priorities(cmd: &mut Command, config: &ProcessConfig) {
+    let (nice, ioprio) = (config.nice, config.ionice());
+    if nice.is_none() && ioprio.is_none() {
+        return;
+    }
+    // SAFETY: setpriority and ioprio_set are plain system calls and only
+    // change the child's own scheduling
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(nice) = nice
+                && libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            if let Some(ioprio) = ioprio
+                && libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) == -1
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Apply the `[name.rlimits]` table in the child before it execs.
+fn apply_rlimits(cmd: &mut Command, config: &ProcessConfig) {
+    use nix::sys::resource::{Resource, setrlimit};
+    let limits: Vec<(Resource, u64, u64)> = config
+        .rlimits
+        .iter()
+        .flatten()
+        .filter_map(|(name, limit)| {
+            let resource = match name.as_str() {
+                "as" => Resource::RLIMIT_AS,
+                "core" => Resource::RLIMIT_CORE,
+                "cpu" => Resource::RLIMIT_CPU,
+                "data" => Resource::RLIMIT_DATA,
+                "fsize" => Resource::RLIMIT_FSIZE,
+                "locks" => Resource::RLIMIT_LOCKS,
+                "memlock" => Resource::RLIMIT_MEMLOCK,
+                "msgqueue" => Resource::RLIMIT_MSGQUEUE,
+                "nice" => Resource::RLIMIT_NICE,
+                "nofile" => Resource::RLIMIT_NOFILE,
+                "nproc" => Resource::RLIMIT_NPROC,
+                "rss" => Resource::RLIMIT_RSS,
+                "rtprio" => Resource::RLIMIT_RTPRIO,
+                "sigpending" => Resource::RLIMIT_SIGPENDING,
+                "stack" => Resource::RLIMIT_STACK,
+                _ => return None,
+            };
+            let (soft, hard) = limit.limits().ok()?;
+            let infinite = |limit: Option<u64>| limit.unwrap_or(libc::RLIM_INFINITY);
+            Some((resource, infinite(soft), infinite(hard)))
+        })
+        .collect();
+    if limits.is_empty() {
+        return;
+    }
+    // SAFETY: setrlimit is async-signal-safe, and the limits were worked out
+    // before the fork so the child does not allocate
+    unsafe {
+        cmd.pre_exec(move || {
+            for &(resource, soft, hard) in &limits {
+                setrlimit(resource, soft, hard)?;
+            }
+            Ok(())
+        });
+    }
+}
+
 pub async fn spawn_process(
     name: String,
     config: ProcessConfig,
+    generation: u64,
     paths: &Paths,
+    services: &Services,
 ) -> Result<(ManagedProcess, Child), ProcessError> {
     let (program, args) = parse_command(&config.command)?;
+    let formatter = line_formatter(
+        &config,
+        &*services.settings.read().await,
+        services.shipper.clone(),
+    )?;
+
+    // Compile the policy up front so a broken module fails the start instead
+    // of the first restart decision
+    if config.policy.is_some() {
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || policy::load(&config))
+            .await
+            .map_err(|e| ProcessError::SpawnFailed(std::io::Error::other(e)))??;
+    }
 
     fs::create_dir_all(paths.log_dir()).await?;
 
+    let (stdout_log, stderr_log) = log_paths(&name, &config, generation, paths);
+    for log in [&stdout_log, &stderr_log] {
+        if let Some(dir) = log.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+    }
+    hooks::run_hook(HookKind::PreStart, &name, &config, &stderr_log).await?;
+
+    let control = match ControlChannel::bind(&paths.control_dir(), &name) {
+        Ok(control) => Some(control),
+        Err(e) if config.ready_check == Some(ReadyCheck::Control) => {
+            return Err(ProcessError::SpawnFailed(e));
+        }
+        Err(e) => {
+            warn!("{name}: no control socket: {e}");
+            None
+        }
+    };
+
     let mut cmd = Command::new(&program);
     cmd.args(&args);
-
-    if let Some(ref cwd) = config.cwd {
-        cmd.current_dir(cwd);
+    configure_command(&mut cmd, &config);
+    if let Some(ref control) = control {
+        cmd.env(control::CONTROL_SOCKET_ENV, control.path());
+    }
+    if let Some((_, index)) = crate::config::instance_of(&name, &config) {
+        cmd.env(INSTANCE_ENV, index.to_string());
     }
 
-    cmd.stdin(std::process::Stdio::null());
-    cmd.stdout(std::process::Stdio::piped());
-    cmd.stderr(std::process::Stdio::piped());
-
-    let mut child = cmd.spawn().map_err(ProcessError::SpawnFailed)?;
+    let stdin = if config.stdin == Some(StdinMode::Pipe) {
+        let (reader, writer) = std::io::pipe()?;
+        cmd.stdin(reader);
+        Some(writer)
+    } else {
+        cmd.stdin(std::process::Stdio::null());
+        None
+    };
+    let outputs = if config.log == Some(false) {
+        cmd.stdout(std::process::Stdio::null());
+        cmd.stderr(std::process::Stdio::null());
+        Outputs::Discarded
+    } else if config.tty == Some(true) {
+        let (reader, terminal) = pty::open()?;
+        cmd.stdout(terminal.try_clone()?);
+        cmd.stderr(terminal);
+        Outputs::Tty(reader)
+    } else if config.merges_logs() {
+        let (output, writer) = std::io::pipe()?;
+        let output = OwnedFd::from(output);
+        cmd.stdout(writer.try_clone()?);
+        cmd.stderr(writer);
+        keep_output_readers(&mut cmd, vec![output.as_raw_fd()]);
+        Outputs::Merged(output)
+    } else {
+        let (stdout, stdout_writer) = std::io::pipe()?;
+        let (stderr, stderr_writer) = std::io::pipe()?;
+        let (stdout, stderr) = (OwnedFd::from(stdout), OwnedFd::from(stderr));
+        cmd.stdout(stdout_writer);
+        cmd.stderr(stderr_writer);
+        keep_output_readers(&mut cmd, vec![stdout.as_raw_fd(), stderr.as_raw_fd()]);
+        Outputs::Pipes(stdout, stderr)
+    };
+    start_session(&mut cmd);
+    apply_priorities(&mut cmd, &config);
+    apply_rlimits(&mut cmd, &config);
+
+    let child = cmd.spawn().map_err(ProcessError::SpawnFailed)?;
+    // The command holds the write ends; the outputs only reach EOF once the
+    // child is their last writer
+    drop(cmd);
+    let stdin = stdin.map(spawn_stdin_writer).transpose()?;
     let pid = child.id();
+    info!(process = %name, pid = ?pid, generation, command = %config.command, "spawned");
+    if let (Some(pid), Some(adj)) = (pid, config.class().oom_score_adj()) {
+        // Lowering the score needs CAP_SYS_RESOURCE; without it the child
+        // keeps the daemon's
+        let _ = fs::write(format!("/proc/{pid}/oom_score_adj"), adj.to_string()).await;
+    }
+    // A terminal cannot be reopened from `/proc` like a pipe, so runs on one
+    // are left out of the journal
+    let identity = match &outputs {
+        Outputs::Pipes(stdout, stderr) => pid
+            .and_then(stats::start_time)
+            .map(|start_time| -> std::io::Result<RunIdentity> {
+                Ok(RunIdentity {
+                    start_time,
+                    stdout_pipe: Some(pipe_inode(stdout)?),
+                    stderr_pipe: Some(pipe_inode(stderr)?),
+                })
+            })
+            .transpose()?,
+        // Both ends of the identity name the same pipe
+        Outputs::Merged(output) => pid
+            .and_then(stats::start_time)
+            .map(|start_time| -> std::io::Result<RunIdentity> {
+                let pipe = Some(pipe_inode(output)?);
+                Ok(RunIdentity {
+                    start_time,
+                    stdout_pipe: pipe,
+                    stderr_pipe: pipe,
+                })
+            })
+            .transpose()?,
+        Outputs::Discarded => pid
+            .and_then(stats::start_time)
+            .map(|start_time| RunIdentity {
+                start_time,
+                stdout_pipe: None,
+                stderr_pipe: None,
+            }),
+        Outputs::Tty(_) => None,
+    };
 
     let (log_tx, _) = broadcast::channel(1024);
     let (monitor_tx, _monitor_rx) = watch::channel(false);
 
-    // Take ownership of piped streams
-    let stdout = child.stdout.take();
-    let stderr = child.stderr.take();
+    let run = Arc::new(RunStats {
+        output: formatter.output_matches(),
+        ..RunStats::new(generation)
+    });
+    let ready_logs =
+        matches!(config.ready_check, Some(ReadyCheck::Log(_))).then(|| log_tx.subscribe());
+
+    let logs = (stdout_log, stderr_log.clone());
+    let log_copiers = match outputs {
+        Outputs::Pipes(stdout, stderr) => spawn_log_copiers(
+            &name,
+            (
+                pipe::Receiver::from_owned_fd(stdout)?,
+                Some(pipe::Receiver::from_owned_fd(stderr)?),
+            ),
+            logs,
+            formatter,
+            &log_tx,
+            &run,
+        ),
+        Outputs::Merged(output) => spawn_log_copiers(
+            &name,
+            (pipe::Receiver::from_owned_fd(output)?, None),
+            logs,
+            formatter,
+            &log_tx,
+            &run,
+        ),
+        Outputs::Tty(reader) => {
+            spawn_log_copiers(&name, (reader, None), logs, formatter, &log_tx, &run)
+        }
+        Outputs::Discarded => Vec::new(),
+    };
 
-    let log_date_format = config.log_date_format.clone();
+    // Processes with a ready check stay Starting until it passes
+    let status = if config.ready_check.is_some() {
+        ProcessStatus::Starting
+    } else {
+        ProcessStatus::Online
+    };
 
-    // Spawn log copiers
-    if let Some(stdout) = stdout {
-        log::spawn_log_copier(
-            name.clone(),
-            LogStream::Stdout,
-            stdout,
-            paths.stdout_log(&name),
-            log_date_format.clone(),
-            log_tx.clone(),
-        );
-    }
+    let managed = ManagedProcess {
+        state: ProcessState {
+            name,
+            config,
+            pid,
+            status,
+            started_at: clock::now(),
+            restarts: 0,
+            generation,
+            exit_code: None,
+            exit_signal: None,
+            exited_at: None,
+            last_restart: None,
+            memory_bytes: None,
+            memory_restarts: 0,
+            log_broadcaster: log_tx,
+            monitor_shutdown: Some(monitor_tx),
+            hook_log: stderr_log,
+            run,
+            control: control.map(Arc::new),
+            stdin,
+            heartbeat_lost: false,
+            queued_runs: 0,
+            ran_for: None,
+            identity,
+        },
+        ready_logs,
+        log_copiers,
+    };
 
-    if let Some(stderr) = stderr {
-        log::spawn_log_copier(
-            name.clone(),
-            LogStream::Stderr,
-            stderr,
-            paths.stderr_log(&name),
-            log_date_format,
-            log_tx.clone(),
-        );
+    if managed.config.post_start.is_some() {
+        let name = managed.name.clone();
+        let config = managed.config.clone();
+        let hook_log = managed.hook_log.clone();
+        tokio::spawn(async move {
+            hooks::run_hook_best_effort(HookKind::PostStart, &name, &config, &hook_log).await;
+        });
     }
 
-    let managed = ManagedProcess {
+    Ok((managed, child))
+}
+
+/// Take over a process a crashed daemon left running, as recorded in its
+/// journal: reopen the output pipes the process kept open and resume copying
+/// them into the logs. The process runs on without a control socket.
+pub fn adopt_process(
+    entry: JournalEntry,
+    paths: &Paths,
+    settings: &DaemonSettings,
+    shipper: Option<Shipper>,
+) -> Result<ManagedProcess, ProcessError> {
+    let JournalEntry {
         name,
-        config,
         pid,
-        status: ProcessStatus::Online,
-        started_at: tokio::time::Instant::now(),
-        restarts: 0,
-        log_broadcaster: log_tx,
-        monitor_shutdown: Some(monitor_tx),
+        identity,
+        generation,
+        restarts,
+        mut config,
+        environment,
+        ..
+    } = entry;
+    config.active_env = environment;
+    let formatter = line_formatter(&config, settings, shipper)?;
+
+    let reopen = |fd: u32, inode: u64| -> std::io::Result<pipe::Receiver> {
+        let path = format!("/proc/{pid}/fd/{fd}");
+        let file = std::fs::File::open(&path)?;
+        if file.metadata()?.ino() != inode {
+            return Err(std::io::Error::other(format!(
+                "{path} is no longer the pipe pm3 gave it"
+            )));
+        }
+        pipe::Receiver::from_file(file)
+    };
+    // A run with `merge_logs` writes both streams to one pipe, and one with
+    // `log = false` to none
+    let stdout = identity
+        .stdout_pipe
+        .map(|inode| reopen(1, inode))
+        .transpose()?;
+    let stderr = match identity.stderr_pipe {
+        Some(inode) if identity.stdout_pipe != Some(inode) => Some(reopen(2, inode)?),
+        _ => None,
+    };
+
+    let elapsed = stats::running_for(identity.start_time).unwrap_or_default();
+    let now = clock::now();
+    let (log_tx, _) = broadcast::channel(1024);
+    let (monitor_tx, _monitor_rx) = watch::channel(false);
+    let run = Arc::new(RunStats {
+        output: formatter.output_matches(),
+        ..RunStats::resumed(generation, elapsed)
+    });
+    let logs = log_paths(&name, &config, generation, paths);
+    let log_copiers = match stdout {
+        Some(stdout) => spawn_log_copiers(
+            &name,
+            (stdout, stderr),
+            logs.clone(),
+            formatter,
+            &log_tx,
+            &run,
+        ),
+        None => Vec::new(),
+    };
+
+    Ok(ManagedProcess {
+        state: ProcessState {
+            name,
+            config,
+            pid: Some(pid),
+            status: ProcessStatus::Online,
+            started_at: now.checked_sub(elapsed).unwrap_or(now),
+            restarts,
+            generation,
+            exit_code: None,
+            exit_signal: None,
+            exited_at: None,
+            last_restart: None,
+            memory_bytes: None,
+            memory_restarts: 0,
+            log_broadcaster: log_tx,
+            monitor_shutdown: Some(monitor_tx),
+            hook_log: logs.1,
+            run,
+            control: None,
+            stdin: None,
+            heartbeat_lost: false,
+            queued_runs: 0,
+            ran_for: None,
+            identity: Some(identity),
+        },
+        ready_logs: None,
+        log_copiers,
+    })
+}
+
+/// Register a `[jobs]` entry without running it. The daemon's cron scheduler
+/// starts each run when the job's schedule fires.
+pub fn schedule_job(
+    name: String,
+    config: ProcessConfig,
+    generation: u64,
+    paths: &Paths,
+) -> ManagedProcess {
+    let (log_tx, _) = broadcast::channel(1024);
+    let (monitor_tx, _monitor_rx) = watch::channel(false);
+    let (_, stderr_log) = log_paths(&name, &config, generation, paths);
+    ManagedProcess {
+        state: ProcessState {
+            name,
+            config,
+            pid: None,
+            status: ProcessStatus::Stopped,
+            started_at: clock::now(),
+            restarts: 0,
+            generation,
+            exit_code: None,
+            exit_signal: None,
+            exited_at: None,
+            last_restart: None,
+            memory_bytes: None,
+            memory_restarts: 0,
+            log_broadcaster: log_tx,
+            monitor_shutdown: Some(monitor_tx),
+            hook_log: stderr_log,
+            run: Arc::new(RunStats::new(generation)),
+            control: None,
+            stdin: None,
+            heartbeat_lost: false,
+            queued_runs: 0,
+            ran_for: None,
+            identity: None,
+        },
+        ready_logs: None,
+        log_copiers: Vec::new(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Restart policy evaluation
+// ---------------------------------------------------------------------------
+
+pub fn evaluate_restart_policy(
+    config: &ProcessConfig,
+    exit_code: Option<i32>,
+    _uptime: Duration,
+    restarts: u32,
+) -> bool {
+    let policy = config.restart_policy();
+    let max_restarts = config.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS);
+
+    // Check if we've exceeded max restarts (reset logic handled by caller for min_uptime)
+    if restarts >= max_restarts {
+        return false;
+    }
+
+    // stop_exit_codes signal an intentional stop, regardless of policy
+    if is_stop_exit_code(config, exit_code) {
+        return false;
+    }
+
+    // A task or job that exited 0 is done, whatever `restart` says
+    if config.runs_to_completion() && exit_code == Some(0) {
+        return false;
+    }
+
+    match policy {
+        RestartPolicy::Never => false,
+        RestartPolicy::Always => true,
+        RestartPolicy::OnFailure => {
+            match exit_code {
+                Some(0) => false,
+                Some(_) => true,
+                None => true, // Signal-killed — treat as failure
+            }
+        }
+    }
+}
+
+/// Let the process's WASM `policy`, if any, override the built-in restart
+/// decision. `max_restarts` still caps restarts, and a policy that fails is
+/// logged and ignored.
+pub async fn consult_restart_policy(
+    hook_log: &Path,
+    config: &ProcessConfig,
+    exit_code: Option<i32>,
+    uptime: Duration,
+    restarts: u32,
+    builtin: bool,
+) -> bool {
+    let input = RestartInput {
+        exit_code,
+        uptime,
+        restarts,
     };
+    let verdict = match policy::load(config)
+        .and_then(|policy| policy.map(|p| p.should_restart(input)).transpose())
+    {
+        Ok(Some(verdict)) => verdict,
+        Ok(None) => return builtin,
+        Err(e) => {
+            let _ = log::append_event(hook_log, &e.to_string()).await;
+            return builtin;
+        }
+    };
+
+    let max_restarts = config.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS);
+    let decision = verdict.or(builtin) && restarts < max_restarts;
+    if decision != builtin {
+        let action = if decision {
+            "restarting"
+        } else {
+            "not restarting"
+        };
+        let _ = log::append_event(hook_log, &format!("policy override: {action}")).await;
+    }
+    decision
+}
+
+/// Run the `pm3_check_health` hook of the process's policy on a fresh sample.
+/// Returns the status to switch to when the verdict moves a running process
+/// between `Online` and `Unhealthy`.
+pub fn policy_health_status(
+    managed: &ProcessState,
+    sample: ResourceSample,
+) -> Result<Option<ProcessStatus>, PolicyError> {
+    if !matches!(
+        managed.status,
+        ProcessStatus::Online | ProcessStatus::Unhealthy
+    ) {
+        return Ok(None);
+    }
+    let Some(policy) = policy::load(&managed.config)? else {
+        return Ok(None);
+    };
+    let healthy = match policy.check_health(HealthInput {
+        uptime: clock::elapsed(managed.started_at),
+        sample,
+        restarts: managed.restarts,
+    })? {
+        policy::Verdict::Yes => ProcessStatus::Online,
+        policy::Verdict::No => ProcessStatus::Unhealthy,
+        policy::Verdict::Abstain => return Ok(None),
+    };
+    Ok((healthy != managed.status).then_some(healthy))
+}
+
+/// Status change for a running process with `heartbeat_timeout`: `Unhealthy`
+/// once its control socket has been quiet for longer than the timeout, and
+/// back to `Online` when heartbeats resume.
+pub fn heartbeat_status(managed: &ProcessState) -> Option<ProcessStatus> {
+    let timeout = Duration::from_millis(managed.config.heartbeat_timeout?);
+    let silent = managed.control.as_ref()?.state.silence() > timeout;
+    match managed.status {
+        ProcessStatus::Online if silent => Some(ProcessStatus::Unhealthy),
+        ProcessStatus::Unhealthy if managed.heartbeat_lost && !silent => {
+            Some(ProcessStatus::Online)
+        }
+        _ => None,
+    }
+}
+
+/// Status a process settles in once the restart policy declined to restart it:
+/// clean exits are `Stopped` (`Completed` for tasks and jobs), exhausting
+/// `max_restarts` is `Errored`, and any other failure is `Crashed`.
+pub fn settled_status(
+    config: &ProcessConfig,
+    exit_code: Option<i32>,
+    restarts: u32,
+) -> ProcessStatus {
+    let max_restarts = config.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS);
+    if config.runs_to_completion() && exit_code == Some(0) {
+        ProcessStatus::Completed
+    } else if exit_code == Some(0) || is_stop_exit_code(config, exit_code) {
+        ProcessStatus::Stopped
+    } else if restarts >= max_restarts {
+        ProcessStatus::Errored
+    } else {
+        ProcessStatus::Crashed
+    }
+}
+
+/// Whether `exit_code` is listed in the process's `stop_exit_codes`.
+pub fn is_stop_exit_code(config: &ProcessConfig, exit_code: Option<i32>) -> bool {
+    match (exit_code, config.stop_exit_codes.as_ref()) {
+        (Some(code), Some(stop_codes)) => stop_codes.contains(&code),
+        _ => false,
+    }
+}
+
+/// Restart count to carry into the restart decision. A run that stayed up for
+/// at least `min_uptime` was stable, so the crash-loop counter starts over;
+/// shorter runs keep counting toward `max_restarts`.
+pub fn crash_loop_count(config: &ProcessConfig, uptime: Duration, restarts: u32) -> u32 {
+    let min_uptime_ms = config.min_uptime.unwrap_or(DEFAULT_MIN_UPTIME_MS);
+    if uptime >= Duration::from_millis(min_uptime_ms) {
+        0
+    } else {
+        restarts
+    }
+}
+
+/// Compute exponential backoff delay: 100ms * 2^count, capped at 30s
+pub fn compute_backoff(restart_count: u32) -> Duration {
+    let ms = BACKOFF_BASE_MS.saturating_mul(2u64.saturating_pow(restart_count));
+    Duration::from_millis(ms.min(BACKOFF_CAP_MS))
+}
+
+/// How long an automatic restart waits: the crash backoff, stretched to
+/// `restart_delay` and to whatever is left of `restart_window`.
+pub fn restart_wait(
+    config: &ProcessConfig,
+    restart_count: u32,
+    throttle: Option<Duration>,
+) -> Duration {
+    compute_backoff(restart_count)
+        .max(config.restart_delay().unwrap_or_default())
+        .max(throttle.unwrap_or_default())
+}
+
+// ---------------------------------------------------------------------------
+// Daemon services
+// ---------------------------------------------------------------------------
+
+/// What the daemon sets up once at startup and hands down to everything that
+/// records a process's runs and events.
+#[derive(Clone)]
+pub struct Services {
+    pub storage: Arc<dyn Storage>,
+    /// The `[[plugins]]` of `daemon.toml`, run on the events they subscribe to.
+    pub plugins: Arc<[PluginSection]>,
+    /// Where captured lines go with `[log_ship]` set.
+    pub shipper: Option<Shipper>,
+    pub notifier: Notifier,
+    /// Every event as it is recorded, for the HTTP API's `/ws/events`.
+    pub events: broadcast::Sender<ProcessEvent>,
+    /// `daemon.toml` as `pm3 config set` last left it.
+    pub settings: Arc<RwLock<DaemonSettings>>,
+}
+
+// ---------------------------------------------------------------------------
+// Process monitor task
+// ---------------------------------------------------------------------------
+
+/// The process a monitor waits on.
+pub enum Tracked {
+    /// A child this daemon spawned.
+    Child(Child),
+    /// A process adopted from an earlier daemon. After a crash it is not our
+    /// child, so its exit can only be noticed by polling and its exit status
+    /// is lost; after `pm3 update` it still is, and is reaped as usual.
+    Adopted { pid: u32, start_time: u64 },
+}
+
+impl From<Child> for Tracked {
+    fn from(child: Child) -> Self {
+        Tracked::Child(child)
+    }
+}
+
+impl Tracked {
+    fn id(&self) -> Option<u32> {
+        match self {
+            Tracked::Child(child) => child.id(),
+            Tracked::Adopted { pid, .. } => Some(*pid),
+        }
+    }
+
+    async fn wait(&mut self) -> Option<std::process::ExitStatus> {
+        match self {
+            Tracked::Child(child) => child.wait().await.ok(),
+            Tracked::Adopted { pid, start_time } => loop {
+                match waitpid(Pid::from_raw(*pid as i32), Some(WaitPidFlag::WNOHANG)) {
+                    Ok(WaitStatus::Exited(_, code)) => {
+                        return Some(ExitStatus::from_raw(code << 8));
+                    }
+                    Ok(WaitStatus::Signaled(_, signal, _)) => {
+                        return Some(ExitStatus::from_raw(signal as i32));
+                    }
+                    Ok(_) => {}
+                    // Not our child: it was orphaned by a daemon crash
+                    Err(_) if stats::start_time(*pid) != Some(*start_time) => return None,
+                    Err(_) => {}
+                }
+                tokio::time::sleep(ADOPTED_POLL_INTERVAL).await;
+            },
+        }
+    }
+
+    fn kill(&mut self) {
+        match self {
+            Tracked::Child(child) => {
+                let _ = child.start_kill();
+            }
+            Tracked::Adopted { pid, .. } => {
+                let _ = nix::sys::signal::kill(
+                    Pid::from_raw(*pid as i32),
+                    nix::sys::signal::Signal::SIGKILL,
+                );
+            }
+        }
+    }
+}
+
+/// A freshly spawned child whose monitor is started once the run is in its
+/// process's actor (the monitor reports to the actor).
+pub struct PendingMonitor {
+    name: String,
+    child: Tracked,
+    pid: Option<u32>,
+    config: ProcessConfig,
+    hook_log: PathBuf,
+    run: Arc<RunStats>,
+    ready_sources: ReadySources,
+    log_copiers: Vec<JoinHandle<()>>,
+    /// Whether the ready check still has to run (a reload checks it up front).
+    check_ready: bool,
+    shutdown_rx: watch::Receiver<bool>,
+}
+
+impl PendingMonitor {
+    pub fn new(managed: &mut ManagedProcess, child: impl Into<Tracked>) -> Self {
+        Self {
+            name: managed.name.clone(),
+            child: child.into(),
+            pid: managed.pid,
+            config: managed.config.clone(),
+            hook_log: managed.hook_log.clone(),
+            run: Arc::clone(&managed.run),
+            ready_sources: managed.ready_sources(),
+            log_copiers: std::mem::take(&mut managed.log_copiers),
+            check_ready: managed.status == ProcessStatus::Starting,
+            shutdown_rx: managed
+                .monitor_shutdown
+                .as_ref()
+                .map(|tx| tx.subscribe())
+                .unwrap(),
+        }
+    }
+
+    /// Kill and reap the run instead of monitoring it: it will not go in the
+    /// table after all, because its process was stopped or started by
+    /// another request while the spawn ran between messages.
+    pub async fn discard(mut self) {
+        if let Some(pid) = self.pid {
+            let _ = signal_run(pid, &self.config, nix::sys::signal::Signal::SIGKILL);
+            info!(process = %self.name, pid, "discarded a run started meanwhile");
+        }
+        let _ = self.child.wait().await;
+    }
+
+    pub fn spawn(self, processes: &Processes, paths: &Paths, services: &Services) {
+        let Self {
+            name,
+            mut child,
+            pid,
+            config,
+            hook_log,
+            run,
+            ready_sources,
+            log_copiers,
+            check_ready,
+            shutdown_rx,
+        } = self;
+        let processes = processes.clone();
+        let paths = paths.clone();
+        let services = services.clone();
+
+        if check_ready && let Some(check) = config.ready_check.clone() {
+            let timeout = config
+                .ready_timeout
+                .unwrap_or(ready::DEFAULT_READY_TIMEOUT_MS);
+            let (name, processes, paths, services) = (
+                name.clone(),
+                processes.clone(),
+                paths.clone(),
+                services.clone(),
+            );
+            tokio::spawn(async move {
+                let result = ready::wait_ready(&check, timeout, ready_sources).await;
+                settle_ready(&name, pid, result, &processes, &paths, &services).await;
+            });
+        }
+
+        tokio::spawn(async move {
+            record_event(
+                &paths,
+                &services,
+                ProcessEvent {
+                    pid,
+                    ..ProcessEvent::new(EventKind::Start, &name)
+                },
+            )
+            .await;
+
+            // Wait for child to exit (a Stopper handles killing via PID signals)
+            let (status, reason) =
+                wait_for_exit(&mut child, &config, &hook_log, &shutdown_rx).await;
+            let exit_code = status.and_then(|s| s.code());
+            let exit_signal =
+                status.and_then(|s| std::os::unix::process::ExitStatusExt::signal(&s));
+
+            // Let the copiers flush what the process wrote before exiting, so
+            // the recorded log volume is complete
+            let deadline = tokio::time::Instant::now() + LOG_DRAIN_TIMEOUT;
+            for copier in log_copiers {
+                let _ = tokio::time::timeout_at(deadline, copier).await;
+            }
+
+            let stopped = *shutdown_rx.borrow();
+            let reason = reason.or_else(|| {
+                let clean = exit_code == Some(0) || is_stop_exit_code(&config, exit_code);
+                (clean && !stopped)
+                    .then(|| output_verdict(&config, &run.output))
+                    .flatten()
+            });
+            let exit = RunExit {
+                code: exit_code,
+                signal: exit_signal,
+                reason,
+            };
+            let status = run_status(&name, &exit, stopped, &processes).await;
+
+            // Settle whether it restarts before recording the run, so its
+            // history entry says where the process came to rest: `errored`
+            // once it has used up `max_restarts`
+            let decision = decide_exit(&name, pid, &exit, &processes).await;
+            let settled = decision.as_ref().and_then(|decision| decision.settled);
+            record_run(
+                &name,
+                &run,
+                &exit,
+                settled.unwrap_or(status),
+                &services.storage,
+            )
+            .await;
+            record_event(
+                &paths,
+                &services,
+                ProcessEvent {
+                    pid,
+                    status: Some(status),
+                    exit_code,
+                    reason,
+                    ..ProcessEvent::new(EventKind::Exit, &name)
+                },
+            )
+            .await;
+            hooks::run_hook_best_effort(HookKind::PostStop, &name, &config, &hook_log).await;
+            if let Some(decision) = decision {
+                follow_exit(&name, exit, decision, &processes, &paths, &services).await;
+            }
+        });
+    }
+}
+
+/// Wait for the run to end. A run that outlives the process's `timeout` is
+/// sent `timeout_signal` (default `kill_signal`), then SIGKILL once
+/// `kill_timeout` passes, and is reported as ended by the timeout.
+async fn wait_for_exit(
+    child: &mut Tracked,
+    config: &ProcessConfig,
+    hook_log: &Path,
+    shutdown_rx: &watch::Receiver<bool>,
+) -> (Option<std::process::ExitStatus>, Option<RunReason>) {
+    let Some(limit) = config.timeout() else {
+        return (child.wait().await, None);
+    };
+    if let Some(status) = clock::timeout(limit, child.wait()).await {
+        return (status, None);
+    }
+    // A stop already under way ends the run on its own terms
+    if *shutdown_rx.borrow() {
+        return (child.wait().await, None);
+    }
+
+    let signal_name = config
+        .timeout_signal
+        .as_deref()
+        .or(config.kill_signal.as_deref())
+        .unwrap_or(DEFAULT_KILL_SIGNAL);
+    let _ = log::append_event(
+        hook_log,
+        &format!(
+            "run exceeded timeout {}, sending {signal_name}",
+            config.timeout.as_deref().unwrap_or_default()
+        ),
+    )
+    .await;
+    let pid = child.id();
+    if let (Some(pid), Ok(signal)) = (pid, parse_signal(signal_name)) {
+        let _ = signal_run(pid, config, signal);
+    }
 
-    Ok((managed, child))
+    let grace = Duration::from_millis(config.kill_timeout.unwrap_or(DEFAULT_KILL_TIMEOUT_MS));
+    let status = match tokio::time::timeout(grace, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            if let Some(pid) = pid {
+                let _ = signal_run(pid, config, nix::sys::signal::Signal::SIGKILL);
+            }
+            child.kill();
+            child.wait().await
+        }
+    };
+    (status, Some(RunReason::Timeout))
 }
 
-// ---------------------------------------------------------------------------
-// Restart policy evaluation
-// ---------------------------------------------------------------------------
+/// Whether a run that exited cleanly failed by its output: it printed a
+/// `failure_pattern` line, or never printed a `success_pattern` line.
+fn output_verdict(config: &ProcessConfig, output: &OutputMatches) -> Option<RunReason> {
+    if config.failure_pattern.is_some() && output.failure() {
+        Some(RunReason::FailureOutput)
+    } else if config.success_pattern.is_some() && !output.success() {
+        Some(RunReason::MissingSuccessOutput)
+    } else {
+        None
+    }
+}
 
-pub fn evaluate_restart_policy(
-    config: &ProcessConfig,
-    exit_code: Option<i32>,
-    _uptime: Duration,
-    restarts: u32,
-) -> bool {
-    let policy = config.restart.clone().unwrap_or(RestartPolicy::OnFailure);
-    let max_restarts = config.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS);
+/// Move a `Starting` process to `Online` once its ready check has passed,
+/// or to `Unhealthy` if it did not pass in time. Does nothing if the run has
+/// already been replaced or stopped.
+async fn settle_ready(
+    name: &str,
+    pid: Option<u32>,
+    result: Result<(), ready::ReadyError>,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) {
+    let failed = processes
+        .with(name, move |managed| {
+            if managed.pid != pid || managed.status != ProcessStatus::Starting {
+                return None;
+            }
+            match result {
+                Ok(()) => {
+                    managed.status = ProcessStatus::Online;
+                    None
+                }
+                Err(e) => {
+                    managed.status = ProcessStatus::Unhealthy;
+                    Some((managed.hook_log.clone(), e.to_string()))
+                }
+            }
+        })
+        .await
+        .flatten();
+    let Some((hook_log, failure)) = failed else {
+        return;
+    };
+    let _ = log::append_event(&hook_log, &failure).await;
+    record_event(
+        paths,
+        services,
+        ProcessEvent {
+            pid,
+            status: Some(ProcessStatus::Unhealthy),
+            detail: Some(failure),
+            ..ProcessEvent::new(EventKind::Unhealthy, name)
+        },
+    )
+    .await;
+}
 
-    // Check if we've exceeded max restarts (reset logic handled by caller for min_uptime)
-    if restarts >= max_restarts {
-        return false;
+/// Add `event` to the process's event history and hand it to the event
+/// plugins subscribed to it.
+pub async fn record_event(paths: &Paths, services: &Services, event: ProcessEvent) {
+    let rotation = services.settings.read().await.logs.rotation();
+    if let Err(e) = event_log::append(paths, rotation.unwrap_or_default(), &event).await {
+        error!(
+            "failed to append {} event for '{}' to the event log: {e}",
+            event.event, event.name
+        );
     }
+    if let Err(e) = storage::record_event(&services.storage, event.clone()).await {
+        error!(
+            "failed to record {} event for '{}': {e}",
+            event.event, event.name
+        );
+    }
+    // No receivers just means nobody is watching
+    let _ = services.events.send(event.clone());
+    notify::emit(&services.notifier, paths, &event);
+    plugin::emit(&services.plugins, paths, event);
+}
 
-    match policy {
-        RestartPolicy::Never => false,
-        RestartPolicy::Always => true,
-        RestartPolicy::OnFailure => {
-            match exit_code {
-                Some(0) => false,
-                Some(code) => {
-                    // Check stop_exit_codes
-                    if let Some(ref stop_codes) = config.stop_exit_codes
-                        && stop_codes.contains(&code)
-                    {
-                        return false;
-                    }
-                    true
-                }
-                None => true, // Signal-killed — treat as failure
+/// The status the run itself ended with, whatever becomes of the process.
+async fn run_status(
+    name: &str,
+    exit: &RunExit,
+    stopped: bool,
+    processes: &Processes,
+) -> ProcessStatus {
+    let exit_code = exit.code;
+    if exit.reason.is_some_and(RunReason::failed) {
+        ProcessStatus::Crashed
+    } else if stopped {
+        ProcessStatus::Stopped
+    } else {
+        match processes.state(name).await {
+            Some(managed) if exit_code == Some(0) && managed.config.runs_to_completion() => {
+                ProcessStatus::Completed
             }
+            _ if exit_code == Some(0) => ProcessStatus::Stopped,
+            Some(managed) if is_stop_exit_code(&managed.config, exit_code) => {
+                ProcessStatus::Stopped
+            }
+            _ => ProcessStatus::Crashed,
         }
     }
 }
 
-/// Compute exponential backoff delay: 100ms * 2^count, capped at 30s
-pub fn compute_backoff(restart_count: u32) -> Duration {
-    let ms = BACKOFF_BASE_MS.saturating_mul(2u64.saturating_pow(restart_count));
-    Duration::from_millis(ms.min(BACKOFF_CAP_MS))
+/// Persist the finished run to the configured storage backend.
+async fn record_run(
+    name: &str,
+    run: &RunStats,
+    exit: &RunExit,
+    status: ProcessStatus,
+    storage: &Arc<dyn Storage>,
+) {
+    let record = run.finish(name, status, exit.code, exit.reason);
+    if let Err(e) = storage::record_run(storage, record).await {
+        error!("failed to record history for '{name}': {e}");
+    }
 }
 
-// ---------------------------------------------------------------------------
-// Process monitor task
-// ---------------------------------------------------------------------------
+/// How a monitored run ended.
+struct RunExit {
+    code: Option<i32>,
+    signal: Option<i32>,
+    reason: Option<RunReason>,
+}
 
-pub fn spawn_monitor(
-    name: String,
-    mut child: Child,
-    monitored_pid: Option<u32>,
-    processes: Arc<RwLock<ProcessTable>>,
-    paths: Paths,
-    _shutdown_rx: watch::Receiver<bool>,
-) {
-    tokio::spawn(async move {
-        // Wait for child to exit (graceful_stop handles killing via PID signals)
-        let status = child.wait().await;
-        let exit_code = status.ok().and_then(|s| s.code());
-        handle_child_exit(&name, monitored_pid, exit_code, &processes, &paths).await;
-    });
+impl RunExit {
+    /// The exit code the restart policy judges the run by: a run killed for
+    /// its timeout or failed by its output failed, whatever code it exited
+    /// with.
+    fn policy_code(&self) -> Option<i32> {
+        match self.reason {
+            Some(reason) if reason.failed() => None,
+            _ => self.code,
+        }
+    }
+
+    fn describe(&self, config: &ProcessConfig) -> String {
+        match (self.reason, self.code, self.signal) {
+            (Some(RunReason::Timeout), _, _) => format!(
+                "timed out after {}",
+                config.timeout.as_deref().unwrap_or_default()
+            ),
+            (Some(RunReason::FailureOutput), _, _) => "printed failure output".to_string(),
+            (Some(RunReason::MissingSuccessOutput), _, _) => {
+                "exited without success output".to_string()
+            }
+            (_, Some(code), _) => format!("exited with code {code}"),
+            (_, None, Some(signal)) => format!("killed by {}", signal_name(signal)),
+            (_, None, None) => "exited".to_string(),
+        }
+    }
 }
 
-async fn handle_child_exit(
+/// Hold back an automatic restart while the host is under severe CPU or
+/// memory pressure, so crash loops do not pile onto a struggling machine.
+/// Returns once the pressure eases, the process is stopped or removed, or
+/// `PRESSURE_MAX_DEFERRAL` has passed. Critical processes are never held.
+async fn defer_under_pressure(
     name: &str,
-    monitored_pid: Option<u32>,
-    exit_code: Option<i32>,
-    processes: &Arc<RwLock<ProcessTable>>,
+    config: &ProcessConfig,
+    hook_log: &Path,
+    processes: &Processes,
     paths: &Paths,
+    services: &Services,
 ) {
-    let (config, uptime, restarts, should_restart);
-
-    {
-        let mut table = processes.write().await;
-        let Some(managed) = table.get_mut(name) else {
-            return;
-        };
-
-        // If the process has been replaced (e.g., by a manual restart), skip
-        if managed.pid != monitored_pid || (managed.pid.is_some() && monitored_pid.is_none()) {
+    if !config.class().defers_under_pressure() {
+        return;
+    }
+    let since = tokio::time::Instant::now();
+    let mut deferred = false;
+    while let Some(why) = stats::Pressure::read().and_then(|p| p.severe()) {
+        if since.elapsed() >= PRESSURE_MAX_DEFERRAL {
+            let _ = log::append_event(hook_log, &format!("restarting despite {why}")).await;
             return;
         }
+        if !deferred {
+            deferred = true;
+            let _ = log::append_event(hook_log, &format!("restart deferred: {why}")).await;
+            record_event(
+                paths,
+                services,
+                ProcessEvent {
+                    detail: Some(why),
+                    ..ProcessEvent::new(EventKind::Deferred, name)
+                },
+            )
+            .await;
+        }
+        tokio::time::sleep(PRESSURE_RECHECK_INTERVAL).await;
 
-        // If shutdown was already signaled (manual stop), don't restart
-        if let Some(ref tx) = managed.monitor_shutdown
-            && *tx.borrow()
-        {
-            managed.status = ProcessStatus::Stopped;
-            managed.pid = None;
+        let stopped = processes
+            .state(name)
+            .await
+            .is_none_or(|managed| stop_requested(&managed));
+        if stopped {
             return;
         }
+    }
+    if deferred {
+        let _ = log::append_event(hook_log, "pressure eased, restarting").await;
+    }
+}
 
-        let uptime_dur = managed.started_at.elapsed();
-        let min_uptime_ms = managed.config.min_uptime.unwrap_or(DEFAULT_MIN_UPTIME_MS);
+/// Whether a stop of the process's current run has been signaled.
+fn stop_requested(managed: &ProcessState) -> bool {
+    managed
+        .monitor_shutdown
+        .as_ref()
+        .is_some_and(|tx| *tx.borrow())
+}
 
-        // If uptime >= min_uptime, process was stable — reset restart counter
-        if uptime_dur >= Duration::from_millis(min_uptime_ms) {
-            managed.restarts = 0;
-        }
+/// What `decide_exit` learns about the process from its actor once the
+/// run has ended.
+struct EndedRun {
+    config: ProcessConfig,
+    uptime: Duration,
+    restarts: u32,
+    generation: u64,
+    memory_restarts: u32,
+    queued_runs: u32,
+    throttle: Option<Duration>,
+    hook_log: PathBuf,
+}
+
+/// What becomes of a process whose run ended: it rests at `settled`, or is
+/// restarted when that is `None`.
+struct ExitDecision {
+    monitored_pid: Option<u32>,
+    ended: EndedRun,
+    settled: Option<ProcessStatus>,
+}
+
+/// Whether the actor still holds the run a monitor watched, not one that
+/// replaced it or a stop begun meanwhile.
+fn holds_run(managed: &ProcessState, monitored_pid: Option<u32>) -> bool {
+    !(managed.pid != monitored_pid || (managed.pid.is_some() && monitored_pid.is_none()))
+}
+
+/// Note the ended run on the process and decide whether it restarts,
+/// consulting its restart policy between messages. `None` when the run was
+/// replaced or stopped, and nothing more is to be done.
+async fn decide_exit(
+    name: &str,
+    monitored_pid: Option<u32>,
+    exit: &RunExit,
+    processes: &Processes,
+) -> Option<ExitDecision> {
+    let (exit_code, exit_signal) = (exit.code, exit.signal);
+    let policy_code = exit.policy_code();
+
+    let ended = processes
+        .with(name, move |managed| {
+            // If the process has been replaced (e.g., by a manual restart), skip
+            if !holds_run(managed, monitored_pid) {
+                return None;
+            }
 
-        config = managed.config.clone();
-        uptime = uptime_dur;
-        restarts = managed.restarts;
-        should_restart = evaluate_restart_policy(&config, exit_code, uptime, restarts);
+            managed.exit_code = exit_code;
+            managed.exit_signal = exit_signal;
+            managed.exited_at = Some(clock::now_utc().timestamp_millis());
+            info!(process = managed.name.as_str(), pid = ?monitored_pid, code = ?exit_code, signal = ?exit_signal, "exited");
 
-        if !should_restart {
-            if exit_code == Some(0) {
+            // If shutdown was already signaled (manual stop), don't restart
+            if stop_requested(managed) {
                 managed.status = ProcessStatus::Stopped;
-            } else {
-                managed.status = ProcessStatus::Errored;
+                managed.pid = None;
+                return None;
+            }
+
+            let uptime = clock::elapsed(managed.started_at);
+            managed.restarts = crash_loop_count(&managed.config, uptime, managed.restarts);
+            Some(EndedRun {
+                config: managed.config.clone(),
+                uptime,
+                restarts: managed.restarts,
+                generation: managed.generation,
+                memory_restarts: managed.memory_restarts,
+                queued_runs: managed.queued_runs,
+                throttle: managed.restart_throttle(),
+                hook_log: managed.hook_log.clone(),
+            })
+        })
+        .await
+        .flatten()?;
+    let EndedRun {
+        ref config,
+        uptime,
+        restarts,
+        queued_runs,
+        ref hook_log,
+        ..
+    } = ended;
+
+    // A queued scheduled run starts whatever the restart policy says; the
+    // policy is consulted between messages, as loading it may take a while
+    let should_restart = queued_runs > 0
+        || consult_restart_policy(
+            hook_log,
+            config,
+            policy_code,
+            uptime,
+            restarts,
+            evaluate_restart_policy(config, policy_code, uptime, restarts),
+        )
+        .await;
+
+    let settled = (!should_restart).then(|| settled_status(config, policy_code, restarts));
+    Some(ExitDecision {
+        monitored_pid,
+        ended,
+        settled,
+    })
+}
+
+/// Carry out `decision` for the run that ended with `exit`: settle the
+/// process, reporting one that used up `max_restarts`, or restart it after
+/// its backoff. Nothing is done if it was stopped or replaced meanwhile.
+async fn follow_exit(
+    name: &str,
+    exit: RunExit,
+    decision: ExitDecision,
+    processes: &Processes,
+    paths: &Paths,
+    services: &Services,
+) {
+    let (exit_code, exit_signal) = (exit.code, exit.signal);
+    let ExitDecision {
+        monitored_pid,
+        ended:
+            EndedRun {
+                config,
+                uptime,
+                restarts,
+                generation,
+                memory_restarts,
+                queued_runs,
+                throttle,
+                hook_log,
+            },
+        settled,
+    } = decision;
+
+    let decided = processes
+        .with(name, move |managed| {
+            // Stopped or replaced while the policy was consulted or the run
+            // recorded
+            let current = holds_run(managed, monitored_pid);
+            if !current || stop_requested(managed) {
+                if current {
+                    managed.status = ProcessStatus::Stopped;
+                    managed.pid = None;
+                }
+                return false;
             }
             managed.pid = None;
-            return;
+            match settled {
+                Some(status) => {
+                    managed.status = status;
+                    managed.ran_for = Some(uptime);
+                    info!(process = managed.name.as_str(), status = %managed.status, restarts, "not restarting");
+                }
+                // Waiting out the backoff — not online until the replacement
+                // spawns
+                None => managed.status = ProcessStatus::Starting,
+            }
+            true
+        })
+        .await
+        .unwrap_or(false);
+    if !decided {
+        return;
+    }
+    if let Some(status) = settled {
+        if status == ProcessStatus::Errored {
+            record_event(
+                paths,
+                services,
+                ProcessEvent {
+                    status: Some(ProcessStatus::Errored),
+                    exit_code,
+                    detail: Some(format!("used up max_restarts ({restarts})")),
+                    ..ProcessEvent::new(EventKind::Errored, name)
+                },
+            )
+            .await;
         }
-
-        // Mark as restarting
-        managed.pid = None;
+        return;
     }
 
-    // Compute backoff and sleep outside the lock
-    let backoff = compute_backoff(restarts);
-    tokio::time::sleep(backoff).await;
+    // Compute backoff and sleep between messages; a queued run is not a
+    // restart and starts right away
+    if queued_runs == 0 {
+        let detail = if exit_code == Some(0) {
+            "exited"
+        } else {
+            "crashed"
+        };
+        record_event(
+            paths,
+            services,
+            ProcessEvent {
+                exit_code,
+                detail: Some(detail.to_string()),
+                ..ProcessEvent::new(EventKind::Restart, name)
+            },
+        )
+        .await;
+        if let Some(left) = throttle {
+            let message = format!("restart held {:.1}s by restart_window", left.as_secs_f64());
+            let _ = log::append_event(&hook_log, &message).await;
+        }
+        let wait = restart_wait(&config, restarts, throttle);
+        info!(
+            process = name,
+            restarts,
+            wait_ms = wait.as_millis() as u64,
+            "restarting"
+        );
+        clock::sleep(wait).await;
+        defer_under_pressure(name, &config, &hook_log, processes, paths, services).await;
+    }
 
-    // Re-acquire lock and spawn new process
-    let mut table = processes.write().await;
-    let Some(managed) = table.get_mut(name) else {
-        return;
+    // Whether the run is still to be replaced: not stopped, nor started
+    // again by another request, while we were sleeping or spawning
+    let pending = move |managed: &ProcessState| {
+        managed.generation == generation && managed.pid.is_none() && !stop_requested(managed)
     };
-
-    // Re-check shutdown wasn't signaled while we were sleeping
-    if let Some(ref tx) = managed.monitor_shutdown
-        && *tx.borrow()
-    {
-        managed.status = ProcessStatus::Stopped;
+    let proceed = processes
+        .with(name, move |managed| {
+            if pending(managed) {
+                return true;
+            }
+            if managed.generation == generation && managed.pid.is_none() {
+                managed.status = ProcessStatus::Stopped;
+            }
+            false
+        })
+        .await
+        .unwrap_or(false);
+    if !proceed {
         return;
     }
 
-    match spawn_process(name.to_string(), config, paths).await {
-        Ok((mut new_managed, new_child)) => {
-            new_managed.restarts = restarts + 1;
-            let new_pid = new_managed.pid;
-            let shutdown_rx = new_managed
-                .monitor_shutdown
-                .as_ref()
-                .map(|tx| tx.subscribe())
-                .unwrap();
-
-            *managed = new_managed;
-
-            // Must drop the lock before spawning monitor (it needs lock access)
-            let procs = Arc::clone(processes);
-            let p = paths.clone();
-            let n = name.to_string();
-            drop(table);
-            spawn_monitor(n, new_child, new_pid, procs, p, shutdown_rx);
-        }
-        Err(e) => {
-            eprintln!("failed to restart '{name}': {e}");
-            managed.status = ProcessStatus::Errored;
-            managed.pid = None;
-        }
+    // Spawn between messages, so a slow pre_start hook holds up no one, then
+    // have the actor put the new run in place
+    let (mut new_managed, new_child) =
+        match spawn_process(name.to_string(), config, generation, paths, services).await {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                error!("failed to restart '{name}': {e}");
+                processes
+                    .with(name, move |managed| {
+                        if pending(managed) {
+                            managed.status = ProcessStatus::Errored;
+                            managed.pid = None;
+                        }
+                    })
+                    .await;
+                return;
+            }
+        };
+    let monitor = PendingMonitor::new(&mut new_managed, new_child);
+    let last_restart = exit.describe(&new_managed.config);
+    let placed = processes
+        .place(new_managed, move |managed, new_managed| {
+            if !pending(managed) {
+                return Err(());
+            }
+            new_managed.exit_code = exit_code;
+            new_managed.exit_signal = exit_signal;
+            new_managed.exited_at = managed.exited_at;
+            new_managed.memory_restarts = memory_restarts;
+            new_managed.queued_runs = managed.queued_runs;
+            if queued_runs > 0 {
+                new_managed.restarts = restarts;
+                new_managed.queued_runs -= 1;
+                new_managed.last_restart = Some("queued cron run".to_string());
+            } else {
+                new_managed.restarts = restarts + 1;
+                new_managed.last_restart = Some(last_restart);
+            }
+            Ok(())
+        })
+        .await;
+    if placed.is_err() {
+        monitor.discard().await;
+        return;
     }
+    monitor.spawn(processes, paths, services);
 }
 
 // ---------------------------------------------------------------------------
@@ -395,6 +2489,7 @@ async fn handle_child_exit(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ProcessKind;
 
     #[test]
     fn test_parse_simple_command() {
@@ -431,6 +2526,75 @@ mod tests {
         assert_eq!(args, vec!["hello world"]);
     }
 
+    #[test]
+    fn test_lint_plain_command_has_no_warnings() {
+        assert!(lint_command("node server.js --port 3000").is_empty());
+    }
+
+    #[test]
+    fn test_lint_unquoted_pipe() {
+        let warnings = lint_command("cat access.log | grep 500");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`|`"));
+    }
+
+    #[test]
+    fn test_lint_quoted_operators_are_fine() {
+        assert!(lint_command(r#"sh -c "echo hi && echo bye | cat""#).is_empty());
+        assert!(lint_command("sh -c 'echo $HOME > out.txt'").is_empty());
+    }
+
+    #[test]
+    fn test_lint_double_ampersand_reported_once() {
+        let warnings = lint_command("npm run build && npm start && echo done");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`&&`"));
+    }
+
+    #[test]
+    fn test_lint_env_var_not_expanded() {
+        let warnings = lint_command("doppler run -- surreal start --user $DB_USER");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("$DB_USER"));
+
+        let warnings = lint_command(r#"echo "${HOME}""#);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("$HOME"));
+    }
+
+    #[test]
+    fn test_lint_escaped_operator_ignored() {
+        assert!(lint_command(r"echo a \| b").is_empty());
+    }
+
+    #[test]
+    fn test_log_paths_default_and_per_generation() {
+        let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
+        let mut config = test_config(None);
+        let (out, err) = log_paths("web", &config, 4, &paths);
+        assert_eq!(out, paths.stdout_log("web"));
+        assert_eq!(err, paths.stderr_log("web"));
+
+        config.log_per_generation = Some(true);
+        let (out, err) = log_paths("web", &config, 4, &paths);
+        assert_eq!(out, paths.generation_stdout_log("web", 4));
+        assert_eq!(err, paths.generation_stderr_log("web", 4));
+
+        config.merge_logs = Some(true);
+        let (out, err) = log_paths("web", &config, 4, &paths);
+        assert_eq!(out, paths.generation_stdout_log("web", 4));
+        assert_eq!(err, out);
+
+        // Overrides win over the data dir's logs
+        config.merge_logs = None;
+        config.error_file = Some("/var/log/web-errors.log".to_string());
+        let (out, err) = log_paths("web", &config, 4, &paths);
+        assert_eq!(out, paths.generation_stdout_log("web", 4));
+        assert_eq!(err, PathBuf::from("/var/log/web-errors.log"));
+        config.out_file = config.error_file.clone();
+        assert_eq!(log_paths("web", &config, 4, &paths), (err.clone(), err));
+    }
+
     #[test]
     fn test_parse_empty_command() {
         let result = parse_command("");
@@ -465,6 +2629,16 @@ mod tests {
         assert_eq!(DEFAULT_KILL_SIGNAL, "SIGTERM");
     }
 
+    #[test]
+    fn test_stop_outcome_note() {
+        assert_eq!(StopOutcome::Graceful.note(), None);
+        assert_eq!(StopOutcome::NotRunning.note(), None);
+        assert_eq!(
+            StopOutcome::ForceKilled.note(),
+            Some("force-killed".to_string())
+        );
+    }
+
     #[test]
     fn test_parse_signal_sigterm() {
         let sig = parse_signal("SIGTERM").unwrap();
@@ -501,6 +2675,52 @@ mod tests {
         assert_eq!(sig, nix::sys::signal::Signal::SIGTERM);
     }
 
+    #[test]
+    fn test_parse_signal_lowercase() {
+        let sig = parse_signal("usr2").unwrap();
+        assert_eq!(sig, nix::sys::signal::Signal::SIGUSR2);
+        let sig = parse_signal("sigterm").unwrap();
+        assert_eq!(sig, nix::sys::signal::Signal::SIGTERM);
+    }
+
+    #[test]
+    fn test_parse_signal_number() {
+        let sig = parse_signal("12").unwrap();
+        assert_eq!(sig, nix::sys::signal::Signal::SIGUSR2);
+        let sig = parse_signal("9").unwrap();
+        assert_eq!(sig, nix::sys::signal::Signal::SIGKILL);
+        assert!(matches!(
+            parse_signal("0"),
+            Err(ProcessError::InvalidSignal(_))
+        ));
+        assert!(matches!(
+            parse_signal("999"),
+            Err(ProcessError::InvalidSignal(_))
+        ));
+    }
+
+    #[test]
+    fn test_signal_name() {
+        assert_eq!(signal_name(9), "SIGKILL");
+        assert_eq!(signal_name(15), "SIGTERM");
+        assert_eq!(signal_name(999), "999");
+    }
+
+    #[test]
+    fn test_mask_env_hides_secrets() {
+        let env = HashMap::from([
+            ("PORT".to_string(), "3000".to_string()),
+            ("API_KEY".to_string(), "abc".to_string()),
+            ("db_password".to_string(), "hunter2".to_string()),
+            ("GITHUB_TOKEN".to_string(), "ghp_x".to_string()),
+        ]);
+        let masked = mask_env(&env);
+        assert_eq!(masked["PORT"], "3000");
+        assert_eq!(masked["API_KEY"], "****");
+        assert_eq!(masked["db_password"], "****");
+        assert_eq!(masked["GITHUB_TOKEN"], "****");
+    }
+
     #[test]
     fn test_parse_signal_invalid() {
         let result = parse_signal("BOGUS");
@@ -531,6 +2751,70 @@ mod tests {
         assert_eq!(DEFAULT_KILL_SIGNAL, "SIGTERM");
     }
 
+    // -------------------------------------------------------------------
+    // Process actors
+    // -------------------------------------------------------------------
+
+    fn test_job(name: &str, generation: u64) -> ManagedProcess {
+        let paths = Paths::with_base(PathBuf::from("/tmp/pm3-test"));
+        schedule_job(
+            name.to_string(),
+            ProcessConfig::default(),
+            generation,
+            &paths,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_place_swaps_only_what_replace_accepts() {
+        let processes = Processes::default();
+        processes.insert(test_job("web", 1));
+
+        let turned_away = processes
+            .place(test_job("web", 2), |_, _| Err::<(), _>("busy"))
+            .await;
+        let (run, why) = turned_away.err().unwrap();
+        assert_eq!((run.generation, why), (2, Some("busy")));
+        assert_eq!(processes.state("web").await.unwrap().generation, 1);
+
+        let placed = processes
+            .place(test_job("web", 2), |current, fresh| {
+                fresh.restarts = current.restarts + 1;
+                Ok::<_, ()>(current.generation)
+            })
+            .await;
+        assert_eq!(placed.ok(), Some(1));
+        let state = processes.state("web").await.unwrap();
+        assert_eq!((state.generation, state.restarts), (2, 1));
+
+        // Nothing to replace once the process is gone
+        processes.remove("web");
+        let gone = processes
+            .place(test_job("web", 3), |_, _| Ok::<_, ()>(()))
+            .await;
+        assert!(matches!(gone, Err((_, None))));
+        assert!(!processes.contains("web"));
+    }
+
+    #[tokio::test]
+    async fn test_held_actors_answer_nothing_until_released() {
+        let processes = Processes::default();
+        processes.insert(test_job("web", 1));
+        let held = processes.hold().await;
+        assert_eq!(held.table["web"].generation, 1);
+
+        let change = tokio::spawn({
+            let processes = processes.clone();
+            async move { processes.with("web", |managed| managed.restarts = 7).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!change.is_finished());
+
+        drop(held);
+        assert!(change.await.unwrap().is_some());
+        assert_eq!(processes.state("web").await.unwrap().restarts, 7);
+    }
+
     // -------------------------------------------------------------------
     // Restart policy
     // -------------------------------------------------------------------
@@ -538,27 +2822,8 @@ mod tests {
     fn test_config(restart: Option<RestartPolicy>) -> ProcessConfig {
         ProcessConfig {
             command: "echo test".to_string(),
-            cwd: None,
-            env: None,
-            env_file: None,
-            health_check: None,
-            kill_timeout: None,
-            kill_signal: None,
-            max_restarts: None,
-            max_memory: None,
-            min_uptime: None,
-            stop_exit_codes: None,
-            watch: None,
-            watch_ignore: None,
-            depends_on: None,
             restart,
-            group: None,
-            pre_start: None,
-            post_stop: None,
-            notify: None,
-            cron_restart: None,
-            log_date_format: None,
-            environments: HashMap::new(),
+            ..Default::default()
         }
     }
 
@@ -659,6 +2924,40 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_restart_stop_exit_codes_override_always() {
+        let mut config = test_config(Some(RestartPolicy::Always));
+        config.stop_exit_codes = Some(vec![0, 3]);
+        assert!(!evaluate_restart_policy(
+            &config,
+            Some(0),
+            Duration::from_secs(0),
+            0
+        ));
+        assert!(!evaluate_restart_policy(
+            &config,
+            Some(3),
+            Duration::from_secs(0),
+            0
+        ));
+        assert!(evaluate_restart_policy(
+            &config,
+            Some(1),
+            Duration::from_secs(0),
+            0
+        ));
+    }
+
+    #[test]
+    fn test_is_stop_exit_code() {
+        let mut config = test_config(None);
+        assert!(!is_stop_exit_code(&config, Some(42)));
+        config.stop_exit_codes = Some(vec![42]);
+        assert!(is_stop_exit_code(&config, Some(42)));
+        assert!(!is_stop_exit_code(&config, Some(1)));
+        assert!(!is_stop_exit_code(&config, None));
+    }
+
     #[test]
     fn test_restart_max_restarts_exceeded() {
         let mut config = test_config(Some(RestartPolicy::Always));
@@ -694,6 +2993,52 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_settled_status_clean_exit_is_stopped() {
+        let mut config = test_config(Some(RestartPolicy::Never));
+        assert_eq!(settled_status(&config, Some(0), 0), ProcessStatus::Stopped);
+        config.stop_exit_codes = Some(vec![3]);
+        assert_eq!(settled_status(&config, Some(3), 0), ProcessStatus::Stopped);
+    }
+
+    #[test]
+    fn test_task_that_exits_cleanly_completes() {
+        let mut config = test_config(Some(RestartPolicy::Always));
+        config.kind = Some(ProcessKind::Task);
+        assert_eq!(
+            settled_status(&config, Some(0), 0),
+            ProcessStatus::Completed
+        );
+        assert_eq!(settled_status(&config, Some(1), 0), ProcessStatus::Crashed);
+        assert!(!evaluate_restart_policy(
+            &config,
+            Some(0),
+            Duration::from_secs(0),
+            0
+        ));
+        assert!(evaluate_restart_policy(
+            &config,
+            Some(1),
+            Duration::from_secs(0),
+            0
+        ));
+    }
+
+    #[test]
+    fn test_settled_status_failure_is_crashed() {
+        let config = test_config(Some(RestartPolicy::Never));
+        assert_eq!(settled_status(&config, Some(1), 0), ProcessStatus::Crashed);
+        assert_eq!(settled_status(&config, None, 0), ProcessStatus::Crashed);
+    }
+
+    #[test]
+    fn test_settled_status_exhausted_restarts_is_errored() {
+        let mut config = test_config(Some(RestartPolicy::OnFailure));
+        config.max_restarts = Some(2);
+        assert_eq!(settled_status(&config, Some(1), 2), ProcessStatus::Errored);
+        assert_eq!(settled_status(&config, Some(1), 1), ProcessStatus::Crashed);
+    }
+
     // -------------------------------------------------------------------
     // Backoff
     // -------------------------------------------------------------------
@@ -714,6 +3059,21 @@ mod tests {
         assert_eq!(compute_backoff(30), Duration::from_millis(BACKOFF_CAP_MS));
     }
 
+    #[test]
+    fn test_restart_wait() {
+        let mut config = test_config(Some(RestartPolicy::Always));
+        assert_eq!(restart_wait(&config, 2, None), Duration::from_millis(400));
+
+        config.restart_delay = Some("1s".to_string());
+        assert_eq!(restart_wait(&config, 2, None), Duration::from_secs(1));
+        // The backoff still wins once it grows past the delay
+        assert_eq!(restart_wait(&config, 5, None), Duration::from_millis(3200));
+        assert_eq!(
+            restart_wait(&config, 0, Some(Duration::from_secs(7))),
+            Duration::from_secs(7)
+        );
+    }
+
     // -------------------------------------------------------------------
     // min_uptime
     // -------------------------------------------------------------------
@@ -725,20 +3085,24 @@ mod tests {
         config.min_uptime = Some(500);
 
         // Uptime exceeds min_uptime: counter resets, restart is allowed
-        let mut restarts: u32 = 3;
         let uptime = Duration::from_millis(600);
-        let min_uptime_ms = config.min_uptime.unwrap_or(DEFAULT_MIN_UPTIME_MS);
-        if uptime >= Duration::from_millis(min_uptime_ms) {
-            restarts = 0;
-        }
+        let restarts = crash_loop_count(&config, uptime, 3);
+        assert_eq!(restarts, 0);
         assert!(evaluate_restart_policy(&config, Some(1), uptime, restarts));
 
         // Uptime below min_uptime: counter stays at max, restart is blocked
-        let mut restarts: u32 = 3;
         let uptime = Duration::from_millis(100);
-        if uptime >= Duration::from_millis(min_uptime_ms) {
-            restarts = 0;
-        }
+        let restarts = crash_loop_count(&config, uptime, 3);
+        assert_eq!(restarts, 3);
         assert!(!evaluate_restart_policy(&config, Some(1), uptime, restarts));
     }
+
+    #[test]
+    fn test_crash_loop_count_uses_default_min_uptime() {
+        let config = test_config(None);
+        let just_below = Duration::from_millis(DEFAULT_MIN_UPTIME_MS - 1);
+        let at_threshold = Duration::from_millis(DEFAULT_MIN_UPTIME_MS);
+        assert_eq!(crash_loop_count(&config, just_below, 4), 4);
+        assert_eq!(crash_loop_count(&config, at_threshold, 4), 0);
+    }
 }
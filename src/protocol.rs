@@ -1,12 +1,13 @@
 use crate::config::ProcessConfig;
+use crate::log::{LogLevel, LogStream};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 // ---------------------------------------------------------------------------
 // Request
 // ---------------------------------------------------------------------------
 
-fn default_log_lines() -> usize {
+pub(crate) fn default_log_lines() -> usize {
     15
 }
 
@@ -19,16 +20,72 @@ pub enum Request {
         names: Option<Vec<String>>,
         #[serde(default, skip_serializing_if = "Option::is_none")]
         env: Option<String>,
+        /// Refuse to start when the processes' `expect_memory` exceeds the
+        /// memory available, instead of warning.
+        #[serde(default)]
+        strict: bool,
+        /// Milliseconds to wait between launches; unset uses the daemon's
+        /// `stagger` setting.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stagger: Option<u64>,
+        /// Stream a `StartProgress` per process as it launches before the
+        /// final response.
+        #[serde(default)]
+        progress: bool,
     },
     Stop {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         names: Option<Vec<String>>,
+        /// Target every process whose config has this `group`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Stop the processes that depend on the targets too.
+        #[serde(default)]
+        cascade: bool,
     },
     Restart {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         names: Option<Vec<String>>,
+        /// Target every process whose config has this `group`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Restart instances one at a time, each once the previous is ready,
+        /// as `rolling_restart = true` does for a process.
+        #[serde(default)]
+        rolling: bool,
+        /// Milliseconds to wait between launches; unset uses the daemon's
+        /// `stagger` setting.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stagger: Option<u64>,
+        /// Restart the processes that depend on the targets too, each once
+        /// what it depends on is back.
+        #[serde(default)]
+        cascade: bool,
     },
     List,
+    /// The `[jobs]` entries, which `List` leaves out.
+    Jobs,
+    /// Answered with `Pong` straight away, for health checks.
+    Ping,
+    /// Facts about the daemon itself.
+    DaemonStatus,
+    /// The daemon settings, or with `key` just that one.
+    ConfigGet {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    /// Write a daemon setting to `daemon.toml`; no `value` removes it, so
+    /// its default applies.
+    ConfigSet {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<String>,
+    },
+    /// Replace the daemon by executing `exe` in its place; the new daemon
+    /// adopts the running processes.
+    Update {
+        exe: String,
+    },
     Kill,
     Reload {
         #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -36,10 +93,36 @@ pub enum Request {
     },
     Info {
         name: String,
+        /// Break the process's usage down by each process in its tree.
+        #[serde(default)]
+        tree: bool,
     },
     Signal {
         name: String,
         signal: String,
+        /// Signal the process's whole process group instead of just its pid.
+        #[serde(default)]
+        group_leader: bool,
+    },
+    /// Stream a process's output and pass the `Input` requests that follow
+    /// on the same connection to its stdin, until the client disconnects or
+    /// the run ends.
+    Attach {
+        name: String,
+    },
+    /// Write `data` to the stdin of a process with `stdin = "pipe"`.
+    Input {
+        name: String,
+        data: String,
+    },
+    /// Grow or shrink the running instances of a process with `instances`.
+    Scale {
+        name: String,
+        instances: u32,
+    },
+    /// Move the daemon's virtual clock forward (see `clock::VIRTUAL_TIME_ENV`).
+    AdvanceClock {
+        by_ms: u64,
     },
     Save,
     Resurrect,
@@ -47,6 +130,12 @@ pub enum Request {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         names: Option<Vec<String>>,
     },
+    /// Logs and dump entries of processes no longer in the table; only
+    /// listed unless `apply`.
+    Prune {
+        #[serde(default)]
+        apply: bool,
+    },
     Log {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         name: Option<String>,
@@ -54,6 +143,67 @@ pub enum Request {
         lines: usize,
         #[serde(default)]
         follow: bool,
+        /// Target every process whose config has this `group`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Send when each followed line was captured, for processes whose
+        /// lines carry no `log_date_format` stamp of their own.
+        #[serde(default)]
+        timestamps: bool,
+        /// Only lines matching this regex.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        grep: Option<String>,
+        /// Only lines logged at this level or above.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        level: Option<LogLevel>,
+        /// Only lines stamped at or after this unix timestamp (ms); needs
+        /// the process to have a `log_date_format`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<i64>,
+        /// Only this stream's log; both when unset.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stream: Option<LogStream>,
+        /// Send lines with their ANSI escape sequences; otherwise they are
+        /// stripped.
+        #[serde(default)]
+        color: bool,
+    },
+    History {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        /// Only runs that ended at or after this unix timestamp (ms).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<i64>,
+    },
+    /// Lifecycle events kept for one process, or for all of them.
+    Events {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        /// Only events at or after this unix timestamp (ms).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<i64>,
+    },
+    /// Persisted resource samples of one process.
+    Samples {
+        name: String,
+        /// Only samples taken at or after this unix timestamp (ms).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<i64>,
+    },
+    /// Requests recorded in the audit log.
+    Audit {
+        /// Only requests made at or after this unix timestamp (ms).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<i64>,
+    },
+    /// Run the task `target` after the tasks its `after` lists lead to. The
+    /// daemon streams a `PipelineStep` per change and ends with
+    /// `PipelineDone`.
+    RunPipeline {
+        configs: HashMap<String, ProcessConfig>,
+        target: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        env: Option<String>,
     },
 }
 
@@ -74,6 +224,9 @@ pub enum Response {
     ProcessList {
         processes: Vec<ProcessInfo>,
     },
+    JobList {
+        jobs: Vec<JobInfo>,
+    },
     ProcessDetail {
         info: Box<ProcessDetail>,
     },
@@ -81,6 +234,52 @@ pub enum Response {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         name: Option<String>,
         line: String,
+        /// The stream it was written to, unless it came from a log holding
+        /// both.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stream: Option<LogStream>,
+        /// When the line was captured (unix ms), if asked for.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<i64>,
+    },
+    History {
+        runs: Vec<RunRecord>,
+    },
+    Events {
+        events: Vec<ProcessEvent>,
+    },
+    Samples {
+        samples: Vec<StatsSample>,
+    },
+    Audit {
+        entries: Vec<AuditEntry>,
+    },
+    PipelineStep {
+        step: PipelineStep,
+    },
+    PipelineDone {
+        target: String,
+        steps: Vec<PipelineStep>,
+    },
+    StartProgress {
+        result: ProcessResult,
+    },
+    /// What a `start`, `stop`, `restart`, `reload` or `scale` did to each
+    /// process it targeted.
+    Results {
+        /// What succeeding meant: `"started"`, `"stopped"`, `"restarted"`,
+        /// `"reloaded"` or `"scaled web to 4"`.
+        action: String,
+        results: Vec<ProcessResult>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<String>,
+    },
+    Pong,
+    DaemonStatus {
+        status: DaemonStatus,
+    },
+    Config {
+        settings: Vec<SettingInfo>,
     },
 }
 
@@ -94,7 +293,12 @@ pub enum ProcessStatus {
     Starting,
     Online,
     Unhealthy,
+    /// Sent its stop signal and not yet gone.
+    Stopping,
     Stopped,
+    /// A task that ran to completion and exited 0.
+    Completed,
+    Crashed,
     Errored,
 }
 
@@ -104,12 +308,93 @@ impl std::fmt::Display for ProcessStatus {
             ProcessStatus::Starting => write!(f, "starting"),
             ProcessStatus::Online => write!(f, "online"),
             ProcessStatus::Unhealthy => write!(f, "unhealthy"),
+            ProcessStatus::Stopping => write!(f, "stopping"),
             ProcessStatus::Stopped => write!(f, "stopped"),
+            ProcessStatus::Completed => write!(f, "completed"),
+            ProcessStatus::Crashed => write!(f, "crashed"),
             ProcessStatus::Errored => write!(f, "errored"),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Running,
+    Succeeded,
+    Failed,
+    /// Not run because an earlier task failed.
+    Skipped,
+}
+
+impl std::fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepStatus::Running => write!(f, "running"),
+            StepStatus::Succeeded => write!(f, "succeeded"),
+            StepStatus::Failed => write!(f, "failed"),
+            StepStatus::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultStatus {
+    Succeeded,
+    /// Left alone, e.g. already running or waiting on one that failed.
+    Skipped,
+    Failed,
+}
+
+/// How one process of a bulk operation fared.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessResult {
+    pub name: String,
+    pub status: ResultStatus,
+    /// Why it was skipped or failed, or a note on a success such as
+    /// `"force-killed"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ProcessResult {
+    pub fn succeeded(name: impl Into<String>, detail: Option<String>) -> Self {
+        ProcessResult {
+            name: name.into(),
+            status: ResultStatus::Succeeded,
+            detail,
+        }
+    }
+
+    pub fn skipped(name: impl Into<String>, reason: impl Into<String>) -> Self {
+        ProcessResult {
+            name: name.into(),
+            status: ResultStatus::Skipped,
+            detail: Some(reason.into()),
+        }
+    }
+
+    pub fn failed(name: impl Into<String>, reason: impl Into<String>) -> Self {
+        ProcessResult {
+            name: name.into(),
+            status: ResultStatus::Failed,
+            detail: Some(reason.into()),
+        }
+    }
+}
+
+/// One task of a pipeline run and how far it got.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineStep {
+    pub name: String,
+    pub status: StepStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProcessInfo {
     pub name: String,
@@ -126,6 +411,119 @@ pub struct ProcessInfo {
     pub memory_bytes: Option<u64>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub group: Option<String>,
+    #[serde(default)]
+    pub generation: u64,
+    #[serde(default)]
+    pub memory_restarts: u32,
+    /// Open file descriptors of the process tree at its latest sample.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fd_count: Option<u32>,
+    /// Threads of the process tree at its latest sample.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_count: Option<u32>,
+    /// Processes in the tree at its latest sample: the process itself, its
+    /// descendants and anything else left in its process group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_count: Option<u32>,
+    /// Custom gauges the process reported on its control socket.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, f64>,
+    /// Exit code of the last run, while the process is not running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// How long the last run took, once it has finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// How the most recent run that ended did, kept after a restart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_exit_code: Option<i32>,
+    /// Name of the signal that ended it, e.g. `SIGKILL`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_exit_signal: Option<String>,
+    /// When it ended, in milliseconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exited_at: Option<i64>,
+}
+
+/// What `pm3 daemon status` shows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub version: String,
+    pub pid: u32,
+    pub uptime_ms: u64,
+    /// Processes in the table, including stopped ones and jobs.
+    pub processes: usize,
+    /// Processes with a live run.
+    pub running: usize,
+    pub socket: String,
+    pub data_dir: String,
+    /// Bytes the process logs under the data dir take up.
+    #[serde(default)]
+    pub log_bytes: u64,
+    /// The `logs.total_max` budget, if one is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_budget: Option<u64>,
+    /// Log files pruned to stay under budget since the daemon started, and
+    /// the bytes they held.
+    #[serde(default)]
+    pub logs_pruned: u64,
+    #[serde(default)]
+    pub log_bytes_pruned: u64,
+}
+
+/// A request the daemon carried out, as the audit log records it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When it was received, in milliseconds since the epoch.
+    pub at: i64,
+    /// The user and process on the other end of the socket, when the kernel
+    /// says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    /// The client's address, for a request over `listen_tcp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// The request type, e.g. `"stop"`.
+    pub request: String,
+    /// Its arguments, with process configs reduced to their names.
+    pub args: serde_json::Value,
+    /// `"ok"`, `"error: ..."` or `"failed: ..."` naming the processes that
+    /// failed.
+    pub outcome: String,
+}
+
+/// A daemon setting as `pm3 config` shows it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingInfo {
+    /// Dotted, e.g. `daemon.auto_save`.
+    pub key: String,
+    /// What `daemon.toml` sets it to; unset means the default applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    pub default: String,
+}
+
+/// A `[jobs]` entry and how its last run went.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub name: String,
+    pub schedule: String,
+    /// `Stopped` until the first run, `Online` while a run is going, then
+    /// how the last run ended.
+    pub status: ProcessStatus,
+    /// When the schedule fires next, in milliseconds since the epoch; unset
+    /// while the job is stopped by hand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<i64>,
+    /// When the last run started, in milliseconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -144,6 +542,10 @@ pub struct ProcessDetail {
     pub memory_bytes: Option<u64>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub group: Option<String>,
+    #[serde(default)]
+    pub generation: u64,
+    #[serde(default)]
+    pub memory_restarts: u32,
     pub command: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub cwd: Option<String>,
@@ -159,6 +561,219 @@ pub struct ProcessDetail {
     pub health_check: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub depends_on: Option<Vec<String>>,
+    /// Name of the signal that ended the last run, e.g. `SIGKILL`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_signal: Option<String>,
+    /// When the last run ended, in milliseconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exited_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_restart: Option<String>,
+    /// How long `restart_window` holds off another restart, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_throttle_ms: Option<u64>,
+    /// The `env_<name>` section applied with `pm3 start --env <name>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    #[serde(default)]
+    pub resources: ResourceSnapshot,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, f64>,
+    /// Each process of the tree, filled in for `Info` with `tree`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tree: Vec<TreeMember>,
+}
+
+/// One process in a managed process's tree: the process itself, one of its
+/// descendants, or a process left in its process group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeMember {
+    pub pid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppid: Option<u32>,
+    pub command: String,
+    pub rss_bytes: u64,
+    pub cpu_time_ms: u64,
+}
+
+/// One finished run of a process, with its resource usage at the end.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub name: String,
+    #[serde(default)]
+    pub generation: u64,
+    /// Unix timestamps in milliseconds.
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub status: ProcessStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Why pm3 ended the run, or held back a scheduled one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<RunReason>,
+    pub snapshot: ResourceSnapshot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunReason {
+    /// The run outlived the process's `timeout` and was killed.
+    Timeout,
+    /// The run exited cleanly but printed a line matching `failure_pattern`.
+    FailureOutput,
+    /// The run exited cleanly without printing a line matching
+    /// `success_pattern`.
+    MissingSuccessOutput,
+    /// A `cron_restart` run was dropped by `overlap_policy = "skip"`; the
+    /// record has no runtime of its own.
+    Skipped,
+    /// A `cron_restart` run was deferred by `overlap_policy = "queue"` until
+    /// the running instance exits.
+    Queued,
+}
+
+impl RunReason {
+    /// Whether the record marks a scheduled run that did not start then,
+    /// rather than a run that happened.
+    pub fn held_back(self) -> bool {
+        matches!(self, RunReason::Skipped | RunReason::Queued)
+    }
+
+    /// Whether the run counts as failed whatever its exit code was.
+    pub fn failed(self) -> bool {
+        matches!(
+            self,
+            RunReason::Timeout | RunReason::FailureOutput | RunReason::MissingSuccessOutput
+        )
+    }
+}
+
+impl std::fmt::Display for RunReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunReason::Timeout => write!(f, "timeout"),
+            RunReason::FailureOutput => write!(f, "failure_output"),
+            RunReason::MissingSuccessOutput => write!(f, "missing_success_output"),
+            RunReason::Skipped => write!(f, "skipped"),
+            RunReason::Queued => write!(f, "queued"),
+        }
+    }
+}
+
+/// A lifecycle event of a process, kept in its event history and handed to
+/// event plugins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A new instance of a process was spawned.
+    Start,
+    /// An instance exited; `status` tells a clean stop from a crash.
+    Exit,
+    /// A replacement was started or scheduled; `detail` says why.
+    Restart,
+    /// A scheduled restart is held back while the host is under pressure.
+    Deferred,
+    /// A health check marked the process unhealthy.
+    Unhealthy,
+    /// The process went over `max_memory` and is being restarted.
+    MemoryLimit,
+    /// The process went over `fd_limit` and is being restarted.
+    FdLimit,
+    /// The process used up its `max_restarts` and is left down.
+    Errored,
+    /// Usage stayed over a `[name.alert]` threshold; `detail` says which.
+    Alert,
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Start => write!(f, "start"),
+            EventKind::Exit => write!(f, "exit"),
+            EventKind::Restart => write!(f, "restart"),
+            EventKind::Deferred => write!(f, "deferred"),
+            EventKind::Unhealthy => write!(f, "unhealthy"),
+            EventKind::MemoryLimit => write!(f, "memory_limit"),
+            EventKind::FdLimit => write!(f, "fd_limit"),
+            EventKind::Errored => write!(f, "errored"),
+            EventKind::Alert => write!(f, "alert"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessEvent {
+    pub event: EventKind,
+    pub name: String,
+    /// Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ProcessStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Why an exited run failed despite its exit code, e.g. a timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<RunReason>,
+    /// What triggered a `restart`, what a `deferred` restart is waiting out,
+    /// or which check found the process unhealthy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ProcessEvent {
+    pub fn new(event: EventKind, name: &str) -> Self {
+        Self {
+            event,
+            name: name.to_string(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            pid: None,
+            status: None,
+            exit_code: None,
+            reason: None,
+            detail: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceSnapshot {
+    /// RSS of the process tree at its last sample before exit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rss_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fd_count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_count: Option<u32>,
+    pub runtime_ms: u64,
+    #[serde(default)]
+    pub log_bytes: u64,
+}
+
+/// A periodic resource sample for one process, as persisted by the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsSample {
+    pub name: String,
+    /// Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    pub rss_bytes: u64,
+    pub cpu_time_ms: u64,
+    pub fd_count: u32,
+}
+
+/// First line a TCP client sends when the daemon has an `auth_token`,
+/// before its request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Auth {
+    pub token: String,
 }
 
 // ---------------------------------------------------------------------------
@@ -201,6 +816,17 @@ pub fn decode_response(line: &str) -> Result<Response, ProtocolError> {
     Ok(serde_json::from_str(trimmed)?)
 }
 
+pub fn encode_auth(auth: &Auth) -> Result<Vec<u8>, ProtocolError> {
+    let mut buf = serde_json::to_vec(auth)?;
+    buf.push(b'\n');
+    Ok(buf)
+}
+
+pub fn decode_auth(line: &str) -> Result<Auth, ProtocolError> {
+    let trimmed = line.trim_end();
+    Ok(serde_json::from_str(trimmed)?)
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -235,32 +861,16 @@ mod tests {
             ProcessConfig {
                 command: "node server.js".to_string(),
                 cwd: Some("/app".to_string()),
-                env: None,
-                env_file: None,
-                health_check: None,
-                kill_timeout: None,
-                kill_signal: None,
-                max_restarts: None,
-                max_memory: None,
-                min_uptime: None,
-                stop_exit_codes: None,
-                watch: None,
-                watch_ignore: None,
-                depends_on: None,
-                restart: None,
-                group: None,
-                pre_start: None,
-                post_stop: None,
-                notify: None,
-                cron_restart: None,
-                log_date_format: None,
-                environments: HashMap::new(),
+                ..Default::default()
             },
         );
         let req = Request::Start {
             configs,
             names: Some(vec!["web".to_string()]),
             env: Some("production".to_string()),
+            strict: true,
+            stagger: None,
+            progress: true,
         };
         assert_eq!(roundtrip_request(&req), req);
     }
@@ -269,13 +879,21 @@ mod tests {
     fn test_request_stop_roundtrip() {
         let req = Request::Stop {
             names: Some(vec!["web".to_string(), "api".to_string()]),
+            group: None,
+            cascade: false,
         };
         assert_eq!(roundtrip_request(&req), req);
     }
 
     #[test]
     fn test_request_restart_roundtrip() {
-        let req = Request::Restart { names: None };
+        let req = Request::Restart {
+            names: None,
+            group: Some("backend".to_string()),
+            rolling: true,
+            stagger: None,
+            cascade: false,
+        };
         assert_eq!(roundtrip_request(&req), req);
     }
 
@@ -303,6 +921,7 @@ mod tests {
     fn test_request_info_roundtrip() {
         let req = Request::Info {
             name: "web".to_string(),
+            tree: true,
         };
         assert_eq!(roundtrip_request(&req), req);
     }
@@ -312,16 +931,234 @@ mod tests {
         let req = Request::Signal {
             name: "web".to_string(),
             signal: "SIGHUP".to_string(),
+            group_leader: true,
+        };
+        assert_eq!(roundtrip_request(&req), req);
+    }
+
+    #[test]
+    fn test_daemon_status_roundtrip() {
+        assert_eq!(roundtrip_request(&Request::Ping), Request::Ping);
+        assert_eq!(roundtrip_response(&Response::Pong), Response::Pong);
+        let resp = Response::DaemonStatus {
+            status: DaemonStatus {
+                version: "0.1.0".to_string(),
+                pid: 42,
+                uptime_ms: 1500,
+                processes: 3,
+                running: 2,
+                socket: "/tmp/pm3/pm3.sock".to_string(),
+                data_dir: "/tmp/pm3".to_string(),
+                log_bytes: 4096,
+                log_budget: Some(1 << 30),
+                logs_pruned: 2,
+                log_bytes_pruned: 2048,
+            },
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+        let remote = Response::Audit {
+            entries: vec![AuditEntry {
+                at: 1_700_000_000_000,
+                uid: None,
+                pid: None,
+                remote: Some("10.0.0.5:51234".to_string()),
+                request: "stop".to_string(),
+                args: serde_json::json!({}),
+                outcome: "ok".to_string(),
+            }],
+        };
+        assert_eq!(roundtrip_response(&remote), remote);
+    }
+
+    #[test]
+    fn test_request_update_roundtrip() {
+        let req = Request::Update {
+            exe: "/usr/local/bin/pm3".to_string(),
         };
         assert_eq!(roundtrip_request(&req), req);
     }
 
+    #[test]
+    fn test_request_attach_roundtrip() {
+        let req = Request::Attach {
+            name: "repl".to_string(),
+        };
+        assert_eq!(roundtrip_request(&req), req);
+        let req = Request::Input {
+            name: "repl".to_string(),
+            data: "1 + 1\n".to_string(),
+        };
+        assert_eq!(roundtrip_request(&req), req);
+    }
+
+    #[test]
+    fn test_request_scale_roundtrip() {
+        let req = Request::Scale {
+            name: "web".to_string(),
+            instances: 4,
+        };
+        assert_eq!(roundtrip_request(&req), req);
+    }
+
+    #[test]
+    fn test_request_advance_clock_roundtrip() {
+        let req = Request::AdvanceClock { by_ms: 86_400_000 };
+        assert_eq!(roundtrip_request(&req), req);
+    }
+
     #[test]
     fn test_request_save_roundtrip() {
         let req = Request::Save;
         assert_eq!(roundtrip_request(&req), req);
     }
 
+    #[test]
+    fn test_request_run_pipeline_roundtrip() {
+        let req = Request::RunPipeline {
+            configs: HashMap::from([(
+                "deploy".to_string(),
+                ProcessConfig {
+                    command: "./deploy.sh".to_string(),
+                    kind: Some(crate::config::ProcessKind::Task),
+                    after: Some(vec!["migrate".to_string()]),
+                    ..Default::default()
+                },
+            )]),
+            target: "deploy".to_string(),
+            env: None,
+        };
+        assert_eq!(roundtrip_request(&req), req);
+    }
+
+    #[test]
+    fn test_response_pipeline_roundtrip() {
+        let step = PipelineStep {
+            name: "migrate".to_string(),
+            status: StepStatus::Failed,
+            exit_code: Some(2),
+            duration_ms: Some(1500),
+        };
+        let resp = Response::PipelineStep { step: step.clone() };
+        assert_eq!(roundtrip_response(&resp), resp);
+        let resp = Response::PipelineDone {
+            target: "deploy".to_string(),
+            steps: vec![
+                step,
+                PipelineStep {
+                    name: "deploy".to_string(),
+                    status: StepStatus::Skipped,
+                    exit_code: None,
+                    duration_ms: None,
+                },
+            ],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+    }
+
+    #[test]
+    fn test_response_results_roundtrip() {
+        let failed = ProcessResult::failed("db", "command not found");
+        let resp = Response::StartProgress {
+            result: failed.clone(),
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+        let resp = Response::Results {
+            action: "started".to_string(),
+            results: vec![
+                ProcessResult::succeeded("web", None),
+                ProcessResult::skipped("api", "'db' failed"),
+                failed,
+            ],
+            warnings: vec!["processes expect 8.0G of memory".to_string()],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+    }
+
+    #[test]
+    fn test_audit_roundtrip() {
+        let req = Request::Audit {
+            since: Some(1_700_000_000_000),
+        };
+        assert_eq!(roundtrip_request(&req), req);
+        let resp = Response::Audit {
+            entries: vec![AuditEntry {
+                at: 1_700_000_000_000,
+                uid: Some(1000),
+                pid: Some(4242),
+                remote: None,
+                request: "restart".to_string(),
+                args: serde_json::json!({"names": ["web"]}),
+                outcome: "ok".to_string(),
+            }],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+    }
+
+    #[test]
+    fn test_events_roundtrip() {
+        let req = Request::Events {
+            name: Some("web".to_string()),
+            since: None,
+        };
+        assert_eq!(roundtrip_request(&req), req);
+        let resp = Response::Events {
+            events: vec![
+                ProcessEvent {
+                    pid: Some(4242),
+                    status: Some(ProcessStatus::Errored),
+                    exit_code: Some(1),
+                    ..ProcessEvent::new(EventKind::Exit, "web")
+                },
+                ProcessEvent {
+                    detail: Some("crashed".to_string()),
+                    ..ProcessEvent::new(EventKind::Restart, "web")
+                },
+            ],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+
+        let json = serde_json::to_value(ProcessEvent::new(EventKind::MemoryLimit, "web")).unwrap();
+        assert_eq!(json["event"], "memory_limit");
+    }
+
+    #[test]
+    fn test_samples_roundtrip() {
+        let req = Request::Samples {
+            name: "web".to_string(),
+            since: Some(1_700_000_000_000),
+        };
+        assert_eq!(roundtrip_request(&req), req);
+        let resp = Response::Samples {
+            samples: vec![StatsSample {
+                name: "web".to_string(),
+                timestamp: 1_700_000_060_000,
+                rss_bytes: 64 * 1024 * 1024,
+                cpu_time_ms: 1500,
+                fd_count: 12,
+            }],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let req = Request::ConfigSet {
+            key: "daemon.auto_save".to_string(),
+            value: Some("true".to_string()),
+        };
+        assert_eq!(roundtrip_request(&req), req);
+        let req = Request::ConfigGet { key: None };
+        assert_eq!(roundtrip_request(&req), req);
+        let resp = Response::Config {
+            settings: vec![SettingInfo {
+                key: "logs.rotate_keep".to_string(),
+                value: None,
+                default: "3".to_string(),
+            }],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+    }
+
     #[test]
     fn test_request_resurrect_roundtrip() {
         let req = Request::Resurrect;
@@ -334,14 +1171,41 @@ mod tests {
         assert_eq!(roundtrip_request(&req), req);
     }
 
+    #[test]
+    fn test_request_prune_roundtrip() {
+        let req = Request::Prune { apply: true };
+        assert_eq!(roundtrip_request(&req), req);
+    }
+
     #[test]
     fn test_request_log_roundtrip() {
         let req = Request::Log {
             name: Some("web".to_string()),
             lines: 30,
             follow: true,
+            group: None,
+            timestamps: true,
+            grep: Some("ERROR|panic".to_string()),
+            level: Some(LogLevel::Warn),
+            since: Some(1_700_000_000_000),
+            stream: Some(LogStream::Stderr),
+            color: true,
+        };
+        assert_eq!(roundtrip_request(&req), req);
+    }
+
+    #[test]
+    fn test_request_history_roundtrip() {
+        let req = Request::History {
+            name: Some("web".to_string()),
+            since: Some(1_700_000_000_000),
         };
         assert_eq!(roundtrip_request(&req), req);
+        let all = Request::History {
+            name: None,
+            since: None,
+        };
+        assert_eq!(roundtrip_request(&all), all);
     }
 
     // -----------------------------------------------------------------------
@@ -380,22 +1244,60 @@ mod tests {
                     cpu_percent: Some(1.5),
                     memory_bytes: Some(52_428_800),
                     group: Some("backend".to_string()),
+                    generation: 3,
+                    memory_restarts: 1,
+                    fd_count: Some(24),
+                    thread_count: Some(8),
+                    process_count: Some(3),
+                    metrics: BTreeMap::from([("queue_depth".to_string(), 12.0)]),
+                    exit_code: None,
+                    duration_ms: None,
+                    last_exit_code: None,
+                    last_exit_signal: Some("SIGKILL".to_string()),
+                    exited_at: Some(1_700_000_000_000),
                 },
                 ProcessInfo {
                     name: "worker".to_string(),
                     pid: None,
-                    status: ProcessStatus::Stopped,
+                    status: ProcessStatus::Completed,
                     uptime: None,
                     restarts: 0,
                     cpu_percent: None,
                     memory_bytes: None,
                     group: None,
+                    generation: 1,
+                    memory_restarts: 0,
+                    fd_count: None,
+                    thread_count: None,
+                    process_count: None,
+                    metrics: BTreeMap::new(),
+                    exit_code: Some(0),
+                    duration_ms: Some(1500),
+                    last_exit_code: Some(0),
+                    last_exit_signal: None,
+                    exited_at: Some(1_700_000_001_500),
                 },
             ],
         };
         assert_eq!(roundtrip_response(&resp), resp);
     }
 
+    #[test]
+    fn test_response_job_list_roundtrip() {
+        let resp = Response::JobList {
+            jobs: vec![JobInfo {
+                name: "rotate".to_string(),
+                schedule: "0 * * * *".to_string(),
+                status: ProcessStatus::Completed,
+                next_run: Some(1_714_532_400_000),
+                last_run: Some(1_714_528_800_000),
+                exit_code: Some(0),
+                duration_ms: Some(420),
+            }],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+    }
+
     #[test]
     fn test_response_process_detail_roundtrip() {
         let resp = Response::ProcessDetail {
@@ -408,6 +1310,8 @@ mod tests {
                 cpu_percent: Some(2.3),
                 memory_bytes: Some(104_857_600),
                 group: Some("backend".to_string()),
+                generation: 2,
+                memory_restarts: 0,
                 command: "node server.js".to_string(),
                 cwd: Some("/app".to_string()),
                 env: Some(HashMap::from([("PORT".to_string(), "3000".to_string())])),
@@ -416,6 +1320,29 @@ mod tests {
                 stderr_log: Some("/home/user/.local/share/pm3/logs/web-err.log".to_string()),
                 health_check: Some("http://localhost:3000/health".to_string()),
                 depends_on: Some(vec!["db".to_string()]),
+                exit_signal: Some("SIGKILL".to_string()),
+                exited_at: Some(1_700_000_000_000),
+                last_restart: Some("exited with code 1".to_string()),
+                restart_throttle_ms: Some(12_000),
+                environment: Some("production".to_string()),
+                resources: ResourceSnapshot {
+                    rss_bytes: Some(104_857_600),
+                    peak_rss_bytes: Some(125_829_120),
+                    cpu_time_ms: Some(1500),
+                    fd_count: Some(12),
+                    thread_count: Some(4),
+                    process_count: Some(2),
+                    runtime_ms: 3_600_000,
+                    log_bytes: 2048,
+                },
+                metrics: BTreeMap::from([("queue_depth".to_string(), 3.0)]),
+                tree: vec![TreeMember {
+                    pid: 1234,
+                    ppid: Some(1),
+                    command: "node server.js".to_string(),
+                    rss_bytes: 104_857_600,
+                    cpu_time_ms: 1500,
+                }],
             }),
         };
         assert_eq!(roundtrip_response(&resp), resp);
@@ -426,16 +1353,46 @@ mod tests {
         let resp = Response::LogLine {
             name: Some("web".to_string()),
             line: "Server started on port 3000".to_string(),
+            stream: Some(LogStream::Stderr),
+            timestamp: Some(1_700_000_000_000),
         };
         assert_eq!(roundtrip_response(&resp), resp);
 
         let resp_no_name = Response::LogLine {
             name: None,
             line: "some output".to_string(),
+            stream: None,
+            timestamp: None,
         };
         assert_eq!(roundtrip_response(&resp_no_name), resp_no_name);
     }
 
+    #[test]
+    fn test_response_history_roundtrip() {
+        let resp = Response::History {
+            runs: vec![RunRecord {
+                name: "web".to_string(),
+                generation: 2,
+                started_at: 1_700_000_000_000,
+                ended_at: 1_700_000_060_000,
+                status: ProcessStatus::Crashed,
+                exit_code: Some(1),
+                reason: Some(RunReason::Timeout),
+                snapshot: ResourceSnapshot {
+                    rss_bytes: Some(52_428_800),
+                    peak_rss_bytes: Some(104_857_600),
+                    cpu_time_ms: Some(1250),
+                    fd_count: Some(12),
+                    thread_count: Some(4),
+                    process_count: Some(2),
+                    runtime_ms: 60_000,
+                    log_bytes: 4096,
+                },
+            }],
+        };
+        assert_eq!(roundtrip_response(&resp), resp);
+    }
+
     // -----------------------------------------------------------------------
     // Malformed JSON (3)
     // -----------------------------------------------------------------------
@@ -481,6 +1438,8 @@ mod tests {
         assert_eq!(ProcessStatus::Online.to_string(), "online");
         assert_eq!(ProcessStatus::Unhealthy.to_string(), "unhealthy");
         assert_eq!(ProcessStatus::Stopped.to_string(), "stopped");
+        assert_eq!(ProcessStatus::Completed.to_string(), "completed");
+        assert_eq!(ProcessStatus::Crashed.to_string(), "crashed");
         assert_eq!(ProcessStatus::Errored.to_string(), "errored");
     }
 
@@ -496,4 +1455,20 @@ mod tests {
         let padded = format!("{line}  \r\n");
         assert_eq!(decode_request(&padded).unwrap(), req);
     }
+
+    #[test]
+    fn test_auth_line() {
+        let auth = Auth {
+            token: "s3cret".to_string(),
+        };
+        let bytes = encode_auth(&auth).unwrap();
+        assert_eq!(bytes, b"{\"token\":\"s3cret\"}\n");
+        assert_eq!(
+            decode_auth(std::str::from_utf8(&bytes).unwrap()).unwrap(),
+            auth
+        );
+        // A request sent in its place is not mistaken for one
+        let request = encode_request(&Request::List).unwrap();
+        assert!(decode_auth(std::str::from_utf8(&request).unwrap()).is_err());
+    }
 }
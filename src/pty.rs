@@ -0,0 +1,104 @@
+use nix::fcntl::{FcntlArg, FdFlag, OFlag, fcntl};
+use nix::pty::Winsize;
+use nix::sys::termios::{self, OutputFlags, SetArg};
+use std::io;
+use std::os::fd::OwnedFd;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, ReadBuf};
+
+// ---------------------------------------------------------------------------
+// Pseudo-terminals
+// ---------------------------------------------------------------------------
+//
+// A process with `tty = true` writes its stdout and stderr to the slave end
+// of a pseudo-terminal, so it keeps its colors and line buffering, and pm3
+// reads both from the master end into the stdout log.
+
+/// Size the terminal reports to the process.
+const WINSIZE: Winsize = Winsize {
+    ws_row: 24,
+    ws_col: 80,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+/// Open a pseudo-terminal for a child, returning the reader for its output
+/// and the slave end to hand it as stdout and stderr.
+pub fn open() -> io::Result<(PtyReader, OwnedFd)> {
+    let pty = nix::pty::openpty(&WINSIZE, None)?;
+    // Keep the terminal from turning each "\n" into "\r\n" in the logs
+    let mut attrs = termios::tcgetattr(&pty.slave)?;
+    attrs.output_flags.remove(OutputFlags::ONLCR);
+    termios::tcsetattr(&pty.slave, SetArg::TCSANOW, &attrs)?;
+
+    // Neither end may leak into the child beyond its stdout and stderr
+    for fd in [&pty.master, &pty.slave] {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    }
+    let flags = OFlag::from_bits_truncate(fcntl(&pty.master, FcntlArg::F_GETFL)?);
+    fcntl(&pty.master, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+
+    Ok((
+        PtyReader {
+            master: AsyncFd::new(pty.master)?,
+        },
+        pty.slave,
+    ))
+}
+
+/// The master end of a pseudo-terminal, read until the child and everything
+/// it forked have closed the slave end.
+pub struct PtyReader {
+    master: AsyncFd<OwnedFd>,
+}
+
+impl AsyncRead for PtyReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.master.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard
+                .try_io(|fd| nix::unistd::read(fd.get_ref(), unfilled).map_err(io::Error::from))
+            {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                // Linux reports the last slave closing as EIO rather than EOF
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => {
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_pty_output_reaches_reader() {
+        let (mut reader, slave) = open().unwrap();
+        assert!(nix::unistd::isatty(&slave).unwrap());
+
+        nix::unistd::write(&slave, b"one\ntwo\n").unwrap();
+        drop(slave);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "one\ntwo\n");
+    }
+}
@@ -0,0 +1,284 @@
+use crate::config::{ProcessConfig, ReadyCheck};
+use crate::log::LogEntry;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, watch};
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+pub const DEFAULT_READY_TIMEOUT_MS: u64 = 30_000;
+
+/// Delay between probes for the polling checks (port, file, http).
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Upper bound for a single connect + request, so a hung endpoint cannot eat
+/// the whole ready timeout in one probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadyError {
+    #[error("ready check did not pass within {0}ms")]
+    TimedOut(u64),
+    #[error("process output closed before the ready check matched")]
+    OutputClosed,
+    #[error("control socket closed before the process reported ready")]
+    ControlClosed,
+}
+
+/// Event streams a check may wait on, taken from the instance being checked
+/// before it starts writing.
+#[derive(Debug, Default)]
+pub struct ReadySources {
+    pub logs: Option<broadcast::Receiver<LogEntry>>,
+    pub control: Option<watch::Receiver<bool>>,
+}
+
+/// The check that proves a replacement instance is serving: the process's
+/// `ready_check`, or its `health_check` URL probed over HTTP.
+pub fn readiness_check(config: &ProcessConfig) -> Option<ReadyCheck> {
+    config.ready_check.clone().or_else(|| {
+        config
+            .health_check
+            .as_ref()
+            .filter(|url| url.starts_with("http://"))
+            .map(|url| ReadyCheck::Http(url.clone()))
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Waiting
+// ---------------------------------------------------------------------------
+
+/// Wait until `check` passes or `timeout_ms` elapses. Log and control checks
+/// read from `sources`.
+pub async fn wait_ready(
+    check: &ReadyCheck,
+    timeout_ms: u64,
+    sources: ReadySources,
+) -> Result<(), ReadyError> {
+    let wait = async {
+        match check {
+            ReadyCheck::Log(pattern) => match (regex::Regex::new(pattern), sources.logs) {
+                (Ok(re), Some(logs)) => wait_for_line(&re, logs).await,
+                // Validated at config load; nothing to match against otherwise
+                _ => Err(ReadyError::OutputClosed),
+            },
+            ReadyCheck::Control => match sources.control {
+                Some(mut ready) => ready
+                    .wait_for(|ready| *ready)
+                    .await
+                    .map(|_| ())
+                    .map_err(|_| ReadyError::ControlClosed),
+                None => Err(ReadyError::ControlClosed),
+            },
+            _ => {
+                while !probe(check).await {
+                    tokio::time::sleep(PROBE_INTERVAL).await;
+                }
+                Ok(())
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_millis(timeout_ms), wait)
+        .await
+        .unwrap_or(Err(ReadyError::TimedOut(timeout_ms)))
+}
+
+async fn wait_for_line(
+    re: &regex::Regex,
+    mut logs: broadcast::Receiver<LogEntry>,
+) -> Result<(), ReadyError> {
+    loop {
+        match logs.recv().await {
+            Ok(entry) if re.is_match(&entry.line) => return Ok(()),
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return Err(ReadyError::OutputClosed),
+        }
+    }
+}
+
+/// Run one probe of a polling check.
+pub async fn probe(check: &ReadyCheck) -> bool {
+    match check {
+        ReadyCheck::Port(port) => {
+            tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(("127.0.0.1", *port)))
+                .await
+                .is_ok_and(|r| r.is_ok())
+        }
+        ReadyCheck::File(path) => tokio::fs::try_exists(path).await.unwrap_or(false),
+        ReadyCheck::Http(url) => tokio::time::timeout(PROBE_TIMEOUT, http_status(url))
+            .await
+            .is_ok_and(|status| status == Some(200)),
+        ReadyCheck::Log(_) | ReadyCheck::Control => false,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Minimal HTTP probe
+// ---------------------------------------------------------------------------
+
+/// Split an `http://host[:port][/path]` URL into its address and path.
+pub(crate) fn parse_http_url(url: &str) -> Option<(String, u16, String)> {
+    let url = crate::http::parse_url(url).filter(|url| !url.tls)?;
+    Some((url.host, url.port, url.path))
+}
+
+/// Status code of a plain `GET`, or `None` if the request failed.
+async fn http_status(url: &str) -> Option<u16> {
+    let (host, port, path) = parse_http_url(url)?;
+    let mut stream = TcpStream::connect((host.as_str(), port)).await.ok()?;
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.ok()?;
+
+    let mut head = Vec::new();
+    let mut buf = [0u8; 256];
+    while !head.contains(&b'\n') {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let status_line = String::from_utf8_lossy(&head);
+    status_line.split_whitespace().nth(1)?.parse().ok()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::LogStream;
+
+    fn logs(rx: broadcast::Receiver<LogEntry>) -> ReadySources {
+        ReadySources {
+            logs: Some(rx),
+            control: None,
+        }
+    }
+
+    #[test]
+    fn test_readiness_check_prefers_ready_check() {
+        let mut config = ProcessConfig {
+            health_check: Some("http://localhost:3000/health".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            readiness_check(&config),
+            Some(ReadyCheck::Http("http://localhost:3000/health".to_string()))
+        );
+        config.ready_check = Some(ReadyCheck::Port(3000));
+        assert_eq!(readiness_check(&config), Some(ReadyCheck::Port(3000)));
+        assert_eq!(readiness_check(&ProcessConfig::default()), None);
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url("http://localhost:3000/health"),
+            Some(("localhost".to_string(), 3000, "/health".to_string()))
+        );
+        assert_eq!(
+            parse_http_url("http://example.com"),
+            Some(("example.com".to_string(), 80, "/".to_string()))
+        );
+        assert_eq!(parse_http_url("https://example.com"), None);
+        assert_eq!(parse_http_url("http://:80/"), None);
+        assert_eq!(parse_http_url("http://host:notaport/"), None);
+    }
+
+    #[tokio::test]
+    async fn test_file_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        let check = ReadyCheck::File(path.to_string_lossy().to_string());
+        assert!(!probe(&check).await);
+        std::fs::write(&path, "").unwrap();
+        assert!(probe(&check).await);
+    }
+
+    #[tokio::test]
+    async fn test_port_check() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(probe(&ReadyCheck::Port(port)).await);
+        drop(listener);
+        assert!(!probe(&ReadyCheck::Port(port)).await);
+    }
+
+    #[tokio::test]
+    async fn test_http_check() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let check = ReadyCheck::Http(format!("http://127.0.0.1:{port}/health"));
+        assert!(!probe(&check).await);
+        assert!(probe(&check).await);
+    }
+
+    #[tokio::test]
+    async fn test_log_check_matches_line() {
+        let (tx, rx) = broadcast::channel(16);
+        let check = ReadyCheck::Log("listening on \\d+".to_string());
+        let waiter = tokio::spawn(async move { wait_ready(&check, 2000, logs(rx)).await });
+
+        for line in ["booting", "listening on 8080"] {
+            tx.send(LogEntry {
+                stream: LogStream::Stdout,
+                line: line.to_string(),
+            })
+            .unwrap();
+        }
+        assert!(waiter.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_log_check_fails_when_output_closes() {
+        let (tx, rx) = broadcast::channel::<LogEntry>(16);
+        drop(tx);
+        let check = ReadyCheck::Log("ready".to_string());
+        let err = wait_ready(&check, 2000, logs(rx)).await.unwrap_err();
+        assert!(matches!(err, ReadyError::OutputClosed));
+    }
+
+    #[tokio::test]
+    async fn test_control_check_waits_for_ready_message() {
+        let (tx, rx) = watch::channel(false);
+        let sources = ReadySources {
+            logs: None,
+            control: Some(rx),
+        };
+        let waiter =
+            tokio::spawn(async move { wait_ready(&ReadyCheck::Control, 2000, sources).await });
+        tx.send_replace(true);
+        assert!(waiter.await.unwrap().is_ok());
+
+        let err = wait_ready(&ReadyCheck::Control, 2000, ReadySources::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ReadyError::ControlClosed));
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready_times_out() {
+        let check = ReadyCheck::File("/nonexistent/pm3-ready".to_string());
+        let err = wait_ready(&check, 150, ReadySources::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ReadyError::TimedOut(150)));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::settings::TlsFiles;
+use color_eyre::eyre::{Context, eyre};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+// ---------------------------------------------------------------------------
+// Remote clients
+// ---------------------------------------------------------------------------
+//
+// A daemon with `listen_tcp` takes requests from other machines, so every TCP
+// client proves itself before its request is read: with a certificate signed
+// by `tls_client_ca`, or by sending `auth_token` as its first line. TLS, from
+// `tls_cert` and `tls_key`, keeps that token and the traffic after it private.
+
+/// TLS for the daemon's TCP listener. With a client CA, clients may present
+/// a certificate it signed; those without one fall back to the token.
+pub fn server_config(files: &TlsFiles) -> color_eyre::Result<Arc<ServerConfig>> {
+    let certs = load_certs(&files.cert)?;
+    let key = load_key(&files.key)?;
+    let builder = match &files.client_ca {
+        Some(ca) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(ca)?))
+                .allow_unauthenticated()
+                .build()
+                .wrap_err_with(|| format!("unusable tls_client_ca {}", ca.display()))?;
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certs, key)
+        .wrap_err("tls_key does not match tls_cert")?;
+    Ok(Arc::new(config))
+}
+
+/// TLS for `pm3 --host`: trust the daemon by the certificates in `ca`, or the
+/// bundled Mozilla roots, and present `identity` for mutual TLS.
+pub fn client_config(
+    ca: Option<&Path>,
+    identity: Option<(&Path, &Path)>,
+) -> color_eyre::Result<Arc<ClientConfig>> {
+    let roots = match ca {
+        Some(ca) => load_roots(ca)?,
+        None => RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        },
+    };
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let config = match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .wrap_err_with(|| format!("{} does not match {}", key.display(), cert.display()))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+/// The name a daemon's certificate must carry: the host of `host:port`.
+pub fn server_name(host: &str) -> color_eyre::Result<ServerName<'static>> {
+    let name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(name.to_string()).map_err(|_| eyre!("`{name}` is not a host name"))
+}
+
+/// Compare tokens in time that does not depend on where they differ, so
+/// response times give nothing of the secret away.
+pub fn token_matches(expected: &str, given: &str) -> bool {
+    let (expected, given) = (expected.as_bytes(), given.as_bytes());
+    expected.len() == given.len()
+        && expected
+            .iter()
+            .zip(given)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn load_certs(path: &Path) -> color_eyre::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| eyre!("failed to read certificates from {}: {e}", path.display()))?;
+    if certs.is_empty() {
+        return Err(eyre!("no certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> color_eyre::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| eyre!("failed to read a private key from {}: {e}", path.display()))
+}
+
+fn load_roots(path: &Path) -> color_eyre::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .wrap_err_with(|| format!("unusable CA certificate in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    /// A CA, a server certificate for `localhost` and a client certificate,
+    /// all signed by it, written as PEM files into `dir`.
+    fn write_certs(dir: &Path) -> (PathBuf, TlsFiles, (PathBuf, PathBuf)) {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let issuer = rcgen::Issuer::new(ca_params, ca_key);
+
+        let write = |name: &str, names: Vec<String>| {
+            let key = rcgen::KeyPair::generate().unwrap();
+            let cert = rcgen::CertificateParams::new(names)
+                .unwrap()
+                .signed_by(&key, &issuer)
+                .unwrap();
+            let (cert_path, key_path) = (
+                dir.join(format!("{name}.pem")),
+                dir.join(format!("{name}-key.pem")),
+            );
+            std::fs::write(&cert_path, cert.pem()).unwrap();
+            std::fs::write(&key_path, key.serialize_pem()).unwrap();
+            (cert_path, key_path)
+        };
+        let (cert, key) = write("server", vec!["localhost".to_string()]);
+        let client = write("client", vec!["client".to_string()]);
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+        let files = TlsFiles {
+            cert,
+            key,
+            client_ca: Some(ca_path.clone()),
+        };
+        (ca_path, files, client)
+    }
+
+    /// Whether the server saw a client certificate after a handshake between
+    /// the two configs.
+    async fn handshake(
+        server: Arc<ServerConfig>,
+        client: Arc<ClientConfig>,
+    ) -> Result<bool, std::io::Error> {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let accept = tokio::spawn(async move {
+            let stream = TlsAcceptor::from(server).accept(server_io).await?;
+            Ok::<_, std::io::Error>(stream.get_ref().1.peer_certificates().is_some())
+        });
+        let connected = TlsConnector::from(client)
+            .connect(server_name("localhost:9617").unwrap(), client_io)
+            .await;
+        let presented = accept.await.unwrap();
+        connected?;
+        presented
+    }
+
+    #[tokio::test]
+    async fn test_tls_with_and_without_client_certificate() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (ca, files, (client_cert, client_key)) = write_certs(dir.path());
+        let server = server_config(&files).unwrap();
+
+        let anonymous = client_config(Some(&ca), None).unwrap();
+        assert!(!handshake(server.clone(), anonymous).await.unwrap());
+        let identified = client_config(
+            Some(&ca),
+            Some((client_cert.as_path(), client_key.as_path())),
+        )
+        .unwrap();
+        assert!(handshake(server.clone(), identified).await.unwrap());
+
+        // The bundled roots do not vouch for a private CA
+        let untrusting = client_config(None, None).unwrap();
+        assert!(handshake(server, untrusting).await.is_err());
+    }
+
+    #[test]
+    fn test_unreadable_files_error() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (_, files, _) = write_certs(dir.path());
+        let swapped = TlsFiles {
+            cert: files.key.clone(),
+            key: files.cert.clone(),
+            client_ca: None,
+        };
+        assert!(server_config(&swapped).is_err());
+        let missing = TlsFiles {
+            cert: dir.path().join("missing.pem"),
+            ..files
+        };
+        assert!(server_config(&missing).is_err());
+    }
+
+    #[test]
+    fn test_server_name() {
+        assert_eq!(
+            server_name("pm3.example.com:9617").unwrap(),
+            ServerName::try_from("pm3.example.com").unwrap()
+        );
+        assert_eq!(
+            server_name("10.0.0.5:9617").unwrap(),
+            ServerName::try_from("10.0.0.5").unwrap()
+        );
+        assert_eq!(
+            server_name("[::1]:9617").unwrap(),
+            ServerName::try_from("::1").unwrap()
+        );
+        assert!(server_name("bad host:9617").is_err());
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cret", "s3creT"));
+        assert!(!token_matches("s3cret", "s3cre"));
+        assert!(!token_matches("s3cret", ""));
+    }
+}
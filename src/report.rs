@@ -0,0 +1,180 @@
+use crate::protocol::StatsSample;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Columns a sparkline is squeezed into.
+pub const SPARKLINE_WIDTH: usize = 60;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// ---------------------------------------------------------------------------
+// Report
+// ---------------------------------------------------------------------------
+
+/// The smallest, mean and largest of a series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+/// What `pm3 report` shows for one process's persisted samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub samples: usize,
+    /// When the first and last samples were taken (unix ms).
+    pub span: Option<(i64, i64)>,
+    /// Resident memory in bytes.
+    pub memory: Option<Summary>,
+    pub memory_sparkline: String,
+    /// CPU use in percent of one core between consecutive samples.
+    pub cpu: Option<Summary>,
+    pub cpu_sparkline: String,
+}
+
+impl Report {
+    /// Summarize `samples`, oldest first.
+    pub fn new(samples: &[StatsSample]) -> Self {
+        let memory: Vec<f64> = samples.iter().map(|s| s.rss_bytes as f64).collect();
+        let cpu = cpu_percentages(samples);
+        Self {
+            samples: samples.len(),
+            span: samples
+                .first()
+                .zip(samples.last())
+                .map(|(first, last)| (first.timestamp, last.timestamp)),
+            memory: summarize(&memory),
+            memory_sparkline: sparkline(&memory, SPARKLINE_WIDTH),
+            cpu: summarize(&cpu),
+            cpu_sparkline: sparkline(&cpu, SPARKLINE_WIDTH),
+        }
+    }
+}
+
+pub fn summarize(values: &[f64]) -> Option<Summary> {
+    if values.is_empty() {
+        return None;
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let avg = values.iter().sum::<f64>() / values.len() as f64;
+    Some(Summary { min, avg, max })
+}
+
+/// CPU use between each pair of consecutive samples, in percent of one core.
+/// Pairs across a restart, where the CPU time starts over, are skipped.
+pub fn cpu_percentages(samples: &[StatsSample]) -> Vec<f64> {
+    samples
+        .windows(2)
+        .filter_map(|pair| {
+            let elapsed = pair[1].timestamp - pair[0].timestamp;
+            let used = pair[1].cpu_time_ms.checked_sub(pair[0].cpu_time_ms)?;
+            (elapsed > 0).then(|| used as f64 * 100.0 / elapsed as f64)
+        })
+        .collect()
+}
+
+/// Draw `values` as at most `width` bars scaled between their smallest and
+/// largest. Longer series are split into `width` buckets, each drawn at its
+/// peak so short spikes still show.
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+    let buckets: Vec<f64> = if values.len() <= width {
+        values.to_vec()
+    } else {
+        (0..width)
+            .map(|i| {
+                let start = i * values.len() / width;
+                let end = (i + 1) * values.len() / width;
+                values[start..end]
+                    .iter()
+                    .copied()
+                    .fold(f64::NEG_INFINITY, f64::max)
+            })
+            .collect()
+    };
+    let Some(Summary { min, max, .. }) = summarize(&buckets) else {
+        return String::new();
+    };
+    let range = max - min;
+    buckets
+        .iter()
+        .map(|value| {
+            if range <= 0.0 {
+                return BARS[0];
+            }
+            let level = ((value - min) / range * (BARS.len() - 1) as f64).round() as usize;
+            BARS[level.min(BARS.len() - 1)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: i64, rss_bytes: u64, cpu_time_ms: u64) -> StatsSample {
+        StatsSample {
+            name: "web".to_string(),
+            timestamp,
+            rss_bytes,
+            cpu_time_ms,
+            fd_count: 4,
+        }
+    }
+
+    #[test]
+    fn test_report_summarizes_memory_and_cpu() {
+        let samples = [
+            sample(0, 100, 0),
+            sample(1000, 200, 500),
+            sample(2000, 300, 500),
+            // Restarted: the CPU time starts over
+            sample(3000, 600, 100),
+            sample(4000, 400, 1100),
+        ];
+        let report = Report::new(&samples);
+
+        assert_eq!(report.samples, 5);
+        assert_eq!(report.span, Some((0, 4000)));
+        assert_eq!(
+            report.memory,
+            Some(Summary {
+                min: 100.0,
+                avg: 320.0,
+                max: 600.0
+            })
+        );
+        assert_eq!(cpu_percentages(&samples), vec![50.0, 0.0, 100.0]);
+        assert_eq!(report.cpu.unwrap().avg, 50.0);
+        assert_eq!(report.memory_sparkline.chars().count(), 5);
+    }
+
+    #[test]
+    fn test_report_of_nothing_is_empty() {
+        let report = Report::new(&[]);
+        assert_eq!(report.samples, 0);
+        assert_eq!(report.span, None);
+        assert_eq!(report.memory, None);
+        assert_eq!(report.cpu, None);
+        assert!(report.memory_sparkline.is_empty());
+    }
+
+    #[test]
+    fn test_sparkline_scales_and_keeps_spikes() {
+        assert_eq!(sparkline(&[0.0, 1.0, 2.0, 7.0], 10), "▁▂▃█");
+        assert_eq!(sparkline(&[5.0, 5.0, 5.0], 10), "▁▁▁");
+
+        let mut values = vec![0.0; 100];
+        values[42] = 9.0;
+        let line = sparkline(&values, 10);
+        assert_eq!(line.chars().count(), 10);
+        assert_eq!(line.chars().nth(4), Some('█'));
+        assert_eq!(line.chars().filter(|c| *c == '█').count(), 1);
+    }
+}
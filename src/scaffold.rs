@@ -0,0 +1,526 @@
+use crate::cli::Template;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+// ---------------------------------------------------------------------------
+// Detection
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScaffoldError {
+    #[error("could not detect the project type; pass --template node|python|rust|static")]
+    Undetected,
+    #[error("failed to read {}: {message}", path.display())]
+    Read { path: PathBuf, message: String },
+}
+
+/// Guess the template from the manifest files in `dir`.
+pub fn detect(dir: &Path) -> Option<Template> {
+    let has = |file: &str| dir.join(file).is_file();
+    if has("package.json") {
+        Some(Template::Node)
+    } else if has("pyproject.toml") || has("requirements.txt") || has("manage.py") {
+        Some(Template::Python)
+    } else if has("Cargo.toml") {
+        Some(Template::Rust)
+    } else if has("index.html") || STATIC_ROOTS.iter().any(|d| dir.join(d).is_dir()) {
+        Some(Template::Static)
+    } else {
+        None
+    }
+}
+
+/// A process the generated config will define.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    name: String,
+    command: String,
+    /// Where the entry came from, written above its table.
+    origin: String,
+    cwd: Option<String>,
+    port: Option<u16>,
+}
+
+impl Entry {
+    fn new(name: &str, command: impl Into<String>, origin: impl Into<String>) -> Self {
+        Self {
+            name: sanitize_name(name),
+            command: command.into(),
+            origin: origin.into(),
+            cwd: None,
+            port: None,
+        }
+    }
+
+    fn serving(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+}
+
+/// Process names become TOML table keys; keep them bare.
+pub fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn read_manifest<T: Default>(
+    path: &Path,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+) -> Result<T, ScaffoldError> {
+    if !path.is_file() {
+        return Ok(T::default());
+    }
+    let read_error = |message: String| ScaffoldError::Read {
+        path: path.to_path_buf(),
+        message,
+    };
+    let content = std::fs::read_to_string(path).map_err(|e| read_error(e.to_string()))?;
+    parse(&content).map_err(read_error)
+}
+
+// ---------------------------------------------------------------------------
+// Templates
+// ---------------------------------------------------------------------------
+
+/// `package.json` scripts that are run as extra background processes.
+const NODE_WORKER_SCRIPTS: &[&str] = &["worker", "queue", "jobs", "scheduler", "cron"];
+
+#[derive(Debug, Default, Deserialize)]
+struct PackageJson {
+    #[serde(default)]
+    main: Option<String>,
+    #[serde(default)]
+    scripts: BTreeMap<String, String>,
+}
+
+fn node_entries(dir: &Path) -> Result<Vec<Entry>, ScaffoldError> {
+    let package: PackageJson = read_manifest(&dir.join("package.json"), |content| {
+        serde_json::from_str(content).map_err(|e| e.to_string())
+    })?;
+
+    let mut entries = Vec::new();
+    if package.scripts.contains_key("start") {
+        entries.push(Entry::new("web", "npm start", "package.json script `start`").serving(3000));
+    }
+    for script in package.scripts.keys() {
+        if NODE_WORKER_SCRIPTS
+            .iter()
+            .any(|worker| script.contains(worker))
+        {
+            entries.push(Entry::new(
+                script,
+                format!("npm run {script}"),
+                format!("package.json script `{script}`"),
+            ));
+        }
+    }
+    if entries.is_empty() {
+        let main = package.main.as_deref().unwrap_or("index.js");
+        entries.push(Entry::new(
+            "app",
+            format!("node {main}"),
+            "package.json entry point",
+        ));
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Pyproject {
+    #[serde(default)]
+    project: PyprojectProject,
+    #[serde(default)]
+    tool: PyprojectTool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PyprojectProject {
+    #[serde(default)]
+    scripts: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PyprojectTool {
+    #[serde(default)]
+    poetry: PyprojectProject,
+}
+
+fn python_entries(dir: &Path) -> Result<Vec<Entry>, ScaffoldError> {
+    let pyproject: Pyproject = read_manifest(&dir.join("pyproject.toml"), |content| {
+        toml::from_str(content).map_err(|e| e.to_string())
+    })?;
+
+    let scripts: BTreeSet<&String> = pyproject
+        .project
+        .scripts
+        .keys()
+        .chain(pyproject.tool.poetry.scripts.keys())
+        .collect();
+    let mut entries: Vec<Entry> = scripts
+        .into_iter()
+        .map(|script| {
+            Entry::new(
+                script,
+                script.clone(),
+                format!("pyproject.toml script `{script}` (install the project first)"),
+            )
+        })
+        .collect();
+
+    if entries.is_empty() {
+        if dir.join("manage.py").is_file() {
+            entries.push(
+                Entry::new(
+                    "web",
+                    "python manage.py runserver 0.0.0.0:8000",
+                    "Django manage.py",
+                )
+                .serving(8000),
+            );
+        } else {
+            let main = ["app.py", "main.py"]
+                .into_iter()
+                .find(|file| dir.join(file).is_file())
+                .unwrap_or("main.py");
+            entries.push(Entry::new("app", format!("python {main}"), main));
+        }
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CargoToml {
+    #[serde(default)]
+    package: Option<CargoPackage>,
+    #[serde(default)]
+    bin: Vec<CargoBin>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoPackage {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoBin {
+    name: String,
+}
+
+fn rust_entries(dir: &Path) -> Result<Vec<Entry>, ScaffoldError> {
+    let manifest: CargoToml = read_manifest(&dir.join("Cargo.toml"), |content| {
+        toml::from_str(content).map_err(|e| e.to_string())
+    })?;
+
+    let mut bins: Vec<(String, &str)> = manifest
+        .bin
+        .into_iter()
+        .map(|bin| (bin.name, "Cargo.toml [[bin]]"))
+        .collect();
+    if let Some(package) = &manifest.package
+        && dir.join("src/main.rs").is_file()
+    {
+        bins.push((package.name.clone(), "src/main.rs"));
+    }
+    if let Ok(files) = std::fs::read_dir(dir.join("src/bin")) {
+        let mut extra: Vec<String> = files
+            .filter_map(|f| f.ok())
+            .filter_map(|f| {
+                let path = f.path();
+                let stem = path.file_stem()?.to_str()?.to_string();
+                (path.extension()? == "rs").then_some(stem)
+            })
+            .collect();
+        extra.sort();
+        bins.extend(extra.into_iter().map(|name| (name, "src/bin")));
+    }
+    let mut seen = HashSet::new();
+    bins.retain(|(name, _)| seen.insert(name.clone()));
+
+    if bins.is_empty() {
+        return Ok(vec![Entry::new("app", "cargo run --release", "Cargo.toml")]);
+    }
+    Ok(bins
+        .into_iter()
+        .map(|(name, origin)| {
+            Entry::new(
+                &name,
+                format!("cargo run --release --bin {name}"),
+                format!("binary `{name}` ({origin})"),
+            )
+        })
+        .collect())
+}
+
+/// Build output directories served in preference to the project root.
+const STATIC_ROOTS: &[&str] = &["dist", "build", "public"];
+
+fn static_entries(dir: &Path) -> Vec<Entry> {
+    let root = STATIC_ROOTS.iter().find(|d| dir.join(d).is_dir());
+    let mut entry = Entry::new(
+        "web",
+        "python3 -m http.server 8080",
+        match root {
+            Some(root) => format!("static files in {root}/"),
+            None => "static files".to_string(),
+        },
+    )
+    .serving(8080);
+    entry.cwd = root.map(|root| root.to_string());
+    vec![entry]
+}
+
+// ---------------------------------------------------------------------------
+// Rendering
+// ---------------------------------------------------------------------------
+
+/// A generated `pm3.toml` for the project in a directory.
+#[derive(Debug)]
+pub struct Scaffold {
+    /// `None` when the config came from `pm3 init --interactive`.
+    pub template: Option<Template>,
+    /// The generated `pm3.toml` content, with explanatory comments.
+    pub toml: String,
+    pub processes: Vec<String>,
+}
+
+/// Generate a commented `pm3.toml` for the project in `dir`, using
+/// `template` or, when it is `None`, the detected project type.
+pub fn scaffold(dir: &Path, template: Option<Template>) -> Result<Scaffold, ScaffoldError> {
+    let template = template
+        .or_else(|| detect(dir))
+        .ok_or(ScaffoldError::Undetected)?;
+    let entries = match template {
+        Template::Node => node_entries(dir)?,
+        Template::Python => python_entries(dir)?,
+        Template::Rust => rust_entries(dir)?,
+        Template::Static => static_entries(dir),
+    };
+
+    Ok(Scaffold {
+        template: Some(template),
+        processes: entries.iter().map(|e| e.name.clone()).collect(),
+        toml: render(template, &entries),
+    })
+}
+
+/// TOML-quote a string value.
+fn quoted(value: &str) -> String {
+    toml::Value::from(value).to_string()
+}
+
+fn render(template: Template, entries: &[Entry]) -> String {
+    let mut out = String::new();
+    let template_name = template
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default();
+    let _ = writeln!(out, "# Generated by `pm3 init --template {template_name}`.");
+    let _ = writeln!(
+        out,
+        "# Start everything with `pm3 start`, or one process with `pm3 start <name>`."
+    );
+
+    for entry in entries {
+        let _ = writeln!(out);
+        let _ = writeln!(out, "# From {}", entry.origin);
+        let _ = writeln!(out, "[{}]", entry.name);
+        let _ = writeln!(out, "command = {}", quoted(&entry.command));
+        match &entry.cwd {
+            Some(cwd) => {
+                let _ = writeln!(out, "cwd = {}", quoted(cwd));
+            }
+            None => {
+                let _ = writeln!(out, "# cwd = \"./subdir\"");
+            }
+        }
+
+        let mut env: Vec<(&str, String)> = match template {
+            Template::Node => vec![("NODE_ENV", "production".to_string())],
+            Template::Python => vec![("PYTHONUNBUFFERED", "1".to_string())],
+            Template::Rust => vec![("RUST_LOG", "info".to_string())],
+            Template::Static => Vec::new(),
+        };
+        if template == Template::Node
+            && let Some(port) = entry.port
+        {
+            env.push(("PORT", port.to_string()));
+        }
+        if !env.is_empty() {
+            let pairs: Vec<String> = env
+                .iter()
+                .map(|(key, value)| format!("{key} = {}", quoted(value)))
+                .collect();
+            let _ = writeln!(out, "env = {{ {} }}", pairs.join(", "));
+        }
+        let _ = writeln!(out, "# env_file = \".env\"");
+
+        let _ = writeln!(out);
+        let _ = writeln!(
+            out,
+            "# Restart after crashes, giving up after 10 quick failures in a row;"
+        );
+        let _ = writeln!(out, "# a run that lasts min_uptime (ms) resets the count");
+        let _ = writeln!(out, "restart = \"on_failure\"");
+        let _ = writeln!(out, "max_restarts = 10");
+        let _ = writeln!(out, "min_uptime = 5000");
+        let _ = writeln!(out, "# Milliseconds between SIGTERM and SIGKILL on stop");
+        let _ = writeln!(out, "kill_timeout = 5000");
+        let _ = writeln!(out, "# max_memory = \"512M\"");
+
+        let _ = writeln!(out);
+        match entry.port {
+            Some(port) => {
+                let _ = writeln!(out, "# Online once the port accepts connections");
+                let _ = writeln!(out, "ready_check = {{ port = {port} }}");
+                let _ = writeln!(out, "# health_check = \"http://localhost:{port}/health\"");
+            }
+            None => {
+                let _ = writeln!(out, "# Online once the process says so, e.g.:");
+                let _ = writeln!(out, "# ready_check = {{ log = \"ready\" }}");
+            }
+        }
+
+        let _ = writeln!(out);
+        let _ = writeln!(out, "# Timestamp each log line");
+        let _ = writeln!(out, "log_date_format = \"%Y-%m-%d %H:%M:%S\"");
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, ReadyCheck, RestartPolicy};
+
+    fn project(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_detect_project_type() {
+        let cases = [
+            ("package.json", Some(Template::Node)),
+            ("pyproject.toml", Some(Template::Python)),
+            ("Cargo.toml", Some(Template::Rust)),
+            ("index.html", Some(Template::Static)),
+            ("README.md", None),
+        ];
+        for (file, expected) in cases {
+            let dir = project(&[(file, "")]);
+            assert_eq!(detect(dir.path()), expected, "{file}");
+        }
+    }
+
+    #[test]
+    fn test_node_scripts_become_processes() {
+        let dir = project(&[(
+            "package.json",
+            r#"{"scripts": {"start": "node server.js", "worker:emails": "node worker.js", "test": "jest"}}"#,
+        )]);
+        let generated = scaffold(dir.path(), None).unwrap();
+        assert_eq!(generated.template, Some(Template::Node));
+        assert_eq!(generated.processes, vec!["web", "worker-emails"]);
+
+        let configs = config::parse_config(&generated.toml).unwrap();
+        let web = &configs["web"];
+        assert_eq!(web.command, "npm start");
+        assert_eq!(web.ready_check, Some(ReadyCheck::Port(3000)));
+        assert_eq!(web.env.as_ref().unwrap()["PORT"], "3000");
+        assert_eq!(web.restart, Some(RestartPolicy::OnFailure));
+        assert_eq!(configs["worker-emails"].command, "npm run worker:emails");
+        assert_eq!(configs["worker-emails"].ready_check, None);
+    }
+
+    #[test]
+    fn test_node_without_start_runs_main() {
+        let dir = project(&[("package.json", r#"{"main": "src/app.js"}"#)]);
+        let configs = config::parse_config(&scaffold(dir.path(), None).unwrap().toml).unwrap();
+        assert_eq!(configs["app"].command, "node src/app.js");
+    }
+
+    #[test]
+    fn test_python_scripts_and_django() {
+        let dir = project(&[(
+            "pyproject.toml",
+            "[project.scripts]\napi = \"app.main:run\"\n\n[tool.poetry.scripts]\nbeat = \"app.beat:run\"\n",
+        )]);
+        let generated = scaffold(dir.path(), None).unwrap();
+        assert_eq!(generated.processes, vec!["api", "beat"]);
+        let configs = config::parse_config(&generated.toml).unwrap();
+        assert_eq!(configs["api"].command, "api");
+
+        let dir = project(&[("manage.py", "")]);
+        let configs = config::parse_config(&scaffold(dir.path(), None).unwrap().toml).unwrap();
+        assert_eq!(configs["web"].ready_check, Some(ReadyCheck::Port(8000)));
+    }
+
+    #[test]
+    fn test_rust_bins() {
+        let dir = project(&[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"shop\"\n\n[[bin]]\nname = \"migrate\"\npath = \"tools/migrate.rs\"\n",
+            ),
+            ("src/main.rs", ""),
+            ("src/bin/worker.rs", ""),
+        ]);
+        let generated = scaffold(dir.path(), None).unwrap();
+        assert_eq!(generated.processes, vec!["migrate", "shop", "worker"]);
+        let configs = config::parse_config(&generated.toml).unwrap();
+        assert_eq!(configs["shop"].command, "cargo run --release --bin shop");
+    }
+
+    #[test]
+    fn test_static_serves_build_dir() {
+        let dir = project(&[("dist/index.html", "")]);
+        let configs = config::parse_config(&scaffold(dir.path(), None).unwrap().toml).unwrap();
+        assert_eq!(configs["web"].cwd.as_deref(), Some("dist"));
+        assert_eq!(configs["web"].ready_check, Some(ReadyCheck::Port(8080)));
+    }
+
+    #[test]
+    fn test_forced_template_and_undetected() {
+        let dir = project(&[]);
+        assert!(matches!(
+            scaffold(dir.path(), None),
+            Err(ScaffoldError::Undetected)
+        ));
+        let generated = scaffold(dir.path(), Some(Template::Rust)).unwrap();
+        assert!(
+            generated
+                .toml
+                .starts_with("# Generated by `pm3 init --template rust`")
+        );
+        assert!(config::parse_config(&generated.toml).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_manifest_is_reported() {
+        let dir = project(&[("package.json", "{not json")]);
+        assert!(matches!(
+            scaffold(dir.path(), None),
+            Err(ScaffoldError::Read { .. })
+        ));
+    }
+}
@@ -0,0 +1,1223 @@
+use crate::config::{self, ConfigError};
+use crate::log::{self, Rotation};
+use crate::paths::Paths;
+use crate::process;
+use crate::protocol::EventKind;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// ---------------------------------------------------------------------------
+// Types
+// ---------------------------------------------------------------------------
+
+/// Daemon-wide settings read from `daemon.toml` in the data directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonSettings {
+    pub daemon: DaemonSection,
+    pub logs: LogsSection,
+    pub storage: StorageSection,
+    pub log_ship: LogShipSection,
+    pub notifications: NotificationsSection,
+    pub metrics: MetricsSection,
+    /// Executables notified of process lifecycle events.
+    pub plugins: Vec<PluginSection>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonSection {
+    /// Exit once the daemon has had no running processes, no schedules
+    /// waiting to fire and no client connections for this long (e.g.
+    /// `"30m"`). Unset means never. Also accepted as `idle_timeout`.
+    #[serde(alias = "idle_timeout")]
+    pub auto_exit: Option<String>,
+    /// Rewrite the dump after every start, stop, restart, reload and
+    /// resurrect, so `pm3 resurrect` never depends on a manual `pm3 save`.
+    pub auto_save: bool,
+    /// Wait this long between launches when starting or restarting several
+    /// processes (e.g. `"2s"`); `--stagger` overrides it.
+    pub stagger: Option<String>,
+    /// How long a client may take to send its request, and to take the
+    /// response, before the connection is dropped (default `"10s"`).
+    pub request_timeout: Option<String>,
+    /// Largest request accepted, e.g. `"1M"` (default `"4M"`).
+    pub max_request_size: Option<String>,
+    /// How many processes a start launches at once, among those whose
+    /// `depends_on` is satisfied (default 8).
+    pub parallel_starts: Option<usize>,
+    /// How often running processes are sampled for `pm3 list` and
+    /// `max_memory` (default `"1s"`).
+    pub stats_interval: Option<String>,
+    /// Permissions of `pm3.sock` as an octal mode, e.g. `"0660"`. Unset
+    /// leaves them to the umask.
+    pub socket_mode: Option<String>,
+    /// Also accept clients over TCP at this address, e.g. `"0.0.0.0:9617"`,
+    /// for `pm3 --host`. Unset listens on the unix socket only. Needs
+    /// `auth_token` or `tls_client_ca`.
+    pub listen_tcp: Option<String>,
+    /// Serve the HTTP API at this address, e.g. `"127.0.0.1:9618"`. Needs
+    /// `auth_token`, which clients send as a bearer token, and `tls_cert`
+    /// and `tls_key` unless the address is a loopback one.
+    pub listen_http: Option<String>,
+    /// Shared secret a TCP client sends before its request (`pm3 --token`
+    /// or `PM3_TOKEN`); anything else is turned away unanswered.
+    pub auth_token: Option<String>,
+    /// PEM certificate chain the TCP listener and the HTTP API serve TLS
+    /// with.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// PEM CA certificates for mutual TLS: a client presenting a
+    /// certificate they signed needs no token.
+    pub tls_client_ca: Option<PathBuf>,
+}
+
+/// Certificate files for the TCP listener and the HTTP API, from `tls_cert`,
+/// `tls_key` and `tls_client_ca`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogsSection {
+    /// Rotate a process log once it would grow past this, e.g. `"50M"`
+    /// (default `"10M"`).
+    pub rotate_size: Option<String>,
+    /// Rotated files kept per log (default 3).
+    pub rotate_keep: Option<u32>,
+    /// Most the log directory may hold, e.g. `"2G"`; the oldest rotated
+    /// files and the logs of deleted processes are pruned to stay under it.
+    pub total_max: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// JSON Lines files under the data directory.
+    #[default]
+    Files,
+    /// A single SQLite database under the data directory.
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSection {
+    pub backend: StorageBackend,
+    /// Drop run history, events and stats samples older than this (e.g. `"30d"`).
+    pub retention: Option<String>,
+    /// How often resource samples are persisted (default `"60s"`).
+    pub sample_interval: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShipFormat {
+    /// A JSON array of line objects, as Vector's `http_server` source takes.
+    #[default]
+    Json,
+    /// Loki's push API, one stream per process and output.
+    Loki,
+}
+
+/// Where captured log lines are POSTed, in batches, for central collection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogShipSection {
+    /// The `http://` endpoint; unset ships nothing.
+    pub url: Option<String>,
+    pub format: ShipFormat,
+    /// Lines per request (default 100).
+    pub batch_size: Option<usize>,
+    /// Longest wait between attempts while the endpoint is down (default
+    /// `"1m"`). Waits double from a second up to it, and lines are kept on
+    /// disk meanwhile.
+    pub backoff: Option<String>,
+}
+
+/// Where per-process metrics are pushed on an interval, for monitoring that
+/// collects by push rather than by scraping.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSection {
+    /// A StatsD `host:port` to send UDP packets to; unset sends none.
+    pub statsd: Option<String>,
+    /// Tag StatsD metrics with the process, DogStatsD style
+    /// (`pm3.cpu_percent:1.5|g|#process:web`), instead of naming it in the
+    /// metric (`pm3.web.cpu_percent:1.5|g`).
+    pub dogstatsd: bool,
+    /// An OTLP/HTTP metrics endpoint, e.g.
+    /// `http://localhost:4318/v1/metrics`; unset sends none.
+    pub otlp: Option<String>,
+    /// How often metrics are pushed (default `"10s"`).
+    pub interval: Option<String>,
+    /// What metric names start with (default `"pm3"`).
+    pub prefix: Option<String>,
+}
+
+/// Channels a process's `notify` can name; each is set up once here.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsSection {
+    /// After a notification, further ones for the same process and event
+    /// within this window (e.g. `"10m"`) are held back and sent as one
+    /// summary when it ends.
+    pub cooldown: Option<String>,
+    pub slack: Option<ChatChannel>,
+    pub discord: Option<ChatChannel>,
+    pub email: Option<EmailChannel>,
+}
+
+/// A Slack or Discord incoming webhook.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatChannel {
+    pub webhook_url: String,
+    /// The message, with placeholders like `{name}` and `{logs}`; see
+    /// `notify::PLACEHOLDERS`.
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailChannel {
+    pub smtp_host: String,
+    /// Defaults to 587, 465 with `tls = "tls"` and 25 with `tls = "none"`.
+    pub smtp_port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Templates for the subject and body, as for chat channels.
+    pub subject: Option<String>,
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Connect in plain text and upgrade with `STARTTLS`.
+    #[default]
+    Starttls,
+    /// TLS from the first byte (SMTPS).
+    Tls,
+    /// No encryption, for a relay on the same host or network.
+    None,
+}
+
+/// An event plugin: `command` is run once per event, with the event as a JSON
+/// line on stdin.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginSection {
+    pub command: String,
+    /// Events to deliver; empty means all of them.
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+}
+
+impl PluginSection {
+    pub fn subscribes_to(&self, event: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 4 << 20;
+pub const DEFAULT_PARALLEL_STARTS: usize = 8;
+pub const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_SHIP_BATCH_SIZE: usize = 100;
+pub const DEFAULT_SHIP_BACKOFF: Duration = Duration::from_secs(60);
+pub const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+pub const DEFAULT_METRICS_PREFIX: &str = "pm3";
+
+impl StorageSection {
+    pub fn retention(&self) -> Result<Option<Duration>, ConfigError> {
+        self.retention
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()
+    }
+
+    pub fn sample_interval(&self) -> Result<Duration, ConfigError> {
+        Ok(self
+            .sample_interval
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()?
+            .unwrap_or(DEFAULT_SAMPLE_INTERVAL))
+    }
+}
+
+impl LogShipSection {
+    pub fn url(&self) -> Result<Option<&str>, ConfigError> {
+        match self.url.as_deref() {
+            Some(url) if crate::http::parse_url(url).is_none() => Err(ConfigError::InvalidValue(
+                format!("log_ship url `{url}` is not an http(s)://host[:port][/path] URL"),
+            )),
+            url => Ok(url),
+        }
+    }
+
+    pub fn batch_size(&self) -> Result<usize, ConfigError> {
+        match self.batch_size {
+            Some(0) => Err(ConfigError::InvalidValue(
+                "log_ship batch_size must be greater than 0".to_string(),
+            )),
+            Some(n) => Ok(n),
+            None => Ok(DEFAULT_SHIP_BATCH_SIZE),
+        }
+    }
+
+    pub fn backoff(&self) -> Result<Duration, ConfigError> {
+        Ok(self
+            .backoff
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()?
+            .unwrap_or(DEFAULT_SHIP_BACKOFF))
+    }
+}
+
+impl MetricsSection {
+    pub fn statsd(&self) -> Result<Option<&str>, ConfigError> {
+        match self.statsd.as_deref() {
+            Some(address)
+                if !address.rsplit_once(':').is_some_and(|(host, port)| {
+                    !host.is_empty() && port.parse::<u16>().is_ok()
+                }) =>
+            {
+                Err(ConfigError::InvalidValue(format!(
+                    "metrics statsd `{address}` is not a host:port address"
+                )))
+            }
+            address => Ok(address),
+        }
+    }
+
+    pub fn otlp(&self) -> Result<Option<&str>, ConfigError> {
+        match self.otlp.as_deref() {
+            Some(url) if crate::http::parse_url(url).is_none() => Err(ConfigError::InvalidValue(
+                format!("metrics otlp `{url}` is not an http(s)://host[:port][/path] URL"),
+            )),
+            url => Ok(url),
+        }
+    }
+
+    pub fn interval(&self) -> Result<Duration, ConfigError> {
+        match self.interval.as_deref().map(config::parse_duration) {
+            Some(Ok(interval)) if interval.is_zero() => Err(ConfigError::InvalidValue(
+                "metrics interval must be greater than 0".to_string(),
+            )),
+            Some(interval) => interval,
+            None => Ok(DEFAULT_METRICS_INTERVAL),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_METRICS_PREFIX)
+    }
+}
+
+impl NotificationsSection {
+    /// `cooldown`, `None` while unset or zero.
+    pub fn cooldown(&self) -> Result<Option<Duration>, ConfigError> {
+        Ok(self
+            .cooldown
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()?
+            .filter(|cooldown| !cooldown.is_zero()))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.cooldown()?;
+        let invalid = |channel: &str, message: String| {
+            ConfigError::InvalidValue(format!("notifications.{channel}: {message}"))
+        };
+        for (channel, chat) in [("slack", &self.slack), ("discord", &self.discord)] {
+            let Some(chat) = chat else {
+                continue;
+            };
+            if crate::http::parse_url(&chat.webhook_url).is_none() {
+                return Err(invalid(
+                    channel,
+                    format!("`{}` is not an http(s):// URL", chat.webhook_url),
+                ));
+            }
+            if let Some(template) = &chat.template {
+                crate::notify::check_template(template).map_err(|e| invalid(channel, e))?;
+            }
+        }
+        if let Some(email) = &self.email {
+            if email.to.is_empty() {
+                return Err(invalid(
+                    "email",
+                    "`to` needs at least one address".to_string(),
+                ));
+            }
+            if email.username.is_some() != email.password.is_some() {
+                return Err(invalid(
+                    "email",
+                    "username and password go together".to_string(),
+                ));
+            }
+            for template in [&email.subject, &email.template].into_iter().flatten() {
+                crate::notify::check_template(template).map_err(|e| invalid("email", e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EmailChannel {
+    pub fn port(&self) -> u16 {
+        self.smtp_port.unwrap_or(match self.tls {
+            SmtpTls::Starttls => 587,
+            SmtpTls::Tls => 465,
+            SmtpTls::None => 25,
+        })
+    }
+}
+
+impl LogsSection {
+    pub fn rotation(&self) -> Result<Rotation, ConfigError> {
+        let size = match self.rotate_size.as_deref() {
+            Some(size) => config::parse_memory(size)?,
+            None => log::LOG_ROTATION_SIZE,
+        };
+        if size == 0 {
+            return Err(ConfigError::InvalidValue(
+                "rotate_size must be greater than 0".to_string(),
+            ));
+        }
+        Ok(Rotation {
+            size,
+            keep: self.rotate_keep.unwrap_or(log::LOG_ROTATION_KEEP),
+            ..Rotation::default()
+        })
+    }
+
+    pub fn total_max(&self) -> Result<Option<u64>, ConfigError> {
+        let Some(max) = self.total_max.as_deref() else {
+            return Ok(None);
+        };
+        match config::parse_memory(max)? {
+            0 => Err(ConfigError::InvalidValue(
+                "total_max must be greater than 0".to_string(),
+            )),
+            max => Ok(Some(max)),
+        }
+    }
+}
+
+impl DaemonSettings {
+    pub fn auto_exit(&self) -> Result<Option<Duration>, ConfigError> {
+        self.daemon
+            .auto_exit
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()
+    }
+
+    pub fn stagger(&self) -> Result<Option<Duration>, ConfigError> {
+        self.daemon
+            .stagger
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()
+    }
+
+    pub fn request_timeout(&self) -> Result<Duration, ConfigError> {
+        let timeout = self
+            .daemon
+            .request_timeout
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()?
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+        if timeout.is_zero() {
+            return Err(ConfigError::InvalidValue(
+                "request_timeout must be greater than 0".to_string(),
+            ));
+        }
+        Ok(timeout)
+    }
+
+    pub fn max_request_size(&self) -> Result<usize, ConfigError> {
+        let Some(size) = self.daemon.max_request_size.as_deref() else {
+            return Ok(DEFAULT_MAX_REQUEST_SIZE);
+        };
+        match config::parse_memory(size)? {
+            0 => Err(ConfigError::InvalidValue(
+                "max_request_size must be greater than 0".to_string(),
+            )),
+            bytes => usize::try_from(bytes).map_err(|_| {
+                ConfigError::InvalidValue(format!("max_request_size `{size}` is too large"))
+            }),
+        }
+    }
+
+    pub fn parallel_starts(&self) -> Result<usize, ConfigError> {
+        match self.daemon.parallel_starts {
+            Some(0) => Err(ConfigError::InvalidValue(
+                "parallel_starts must be greater than 0".to_string(),
+            )),
+            Some(n) => Ok(n),
+            None => Ok(DEFAULT_PARALLEL_STARTS),
+        }
+    }
+
+    pub fn stats_interval(&self) -> Result<Duration, ConfigError> {
+        let interval = self
+            .daemon
+            .stats_interval
+            .as_deref()
+            .map(config::parse_duration)
+            .transpose()?
+            .unwrap_or(DEFAULT_STATS_INTERVAL);
+        if interval.is_zero() {
+            return Err(ConfigError::InvalidValue(
+                "stats_interval must be greater than 0".to_string(),
+            ));
+        }
+        Ok(interval)
+    }
+
+    pub fn socket_mode(&self) -> Result<Option<u32>, ConfigError> {
+        let Some(mode) = self.daemon.socket_mode.as_deref() else {
+            return Ok(None);
+        };
+        match u32::from_str_radix(mode, 8) {
+            Ok(bits) if bits <= 0o777 => Ok(Some(bits)),
+            _ => Err(ConfigError::InvalidValue(format!(
+                "socket_mode `{mode}` is not an octal mode like \"0660\""
+            ))),
+        }
+    }
+
+    pub fn listen_tcp(&self) -> Result<Option<std::net::SocketAddr>, ConfigError> {
+        let Some(addr) = self.daemon.listen_tcp.as_deref() else {
+            return Ok(None);
+        };
+        let addr: std::net::SocketAddr = addr.parse().map_err(|_| {
+            ConfigError::InvalidValue(format!(
+                "listen_tcp `{addr}` is not an ip:port address like \"0.0.0.0:9617\""
+            ))
+        })?;
+        let tls = self.tls()?;
+        let mutual_tls = tls.as_ref().is_some_and(|tls| tls.client_ca.is_some());
+        if self.auth_token()?.is_none() && !mutual_tls {
+            return Err(ConfigError::InvalidValue(
+                "listen_tcp needs auth_token or tls_client_ca, or anyone who can reach the port \
+                 controls the daemon"
+                    .to_string(),
+            ));
+        }
+        if !addr.ip().is_loopback() && tls.is_none() {
+            return Err(ConfigError::InvalidValue(format!(
+                "listen_tcp `{addr}` is not a loopback address, so it needs tls_cert and \
+                 tls_key, or auth_token crosses the network in the clear"
+            )));
+        }
+        Ok(Some(addr))
+    }
+
+    pub fn listen_http(&self) -> Result<Option<std::net::SocketAddr>, ConfigError> {
+        let Some(addr) = self.daemon.listen_http.as_deref() else {
+            return Ok(None);
+        };
+        let addr: std::net::SocketAddr = addr.parse().map_err(|_| {
+            ConfigError::InvalidValue(format!(
+                "listen_http `{addr}` is not an ip:port address like \"127.0.0.1:9618\""
+            ))
+        })?;
+        if self.auth_token()?.is_none() {
+            return Err(ConfigError::InvalidValue(
+                "listen_http needs auth_token, or anyone who can reach the port controls the \
+                 daemon"
+                    .to_string(),
+            ));
+        }
+        if !addr.ip().is_loopback() && self.tls()?.is_none() {
+            return Err(ConfigError::InvalidValue(format!(
+                "listen_http `{addr}` is not a loopback address, so it needs tls_cert and \
+                 tls_key, or auth_token crosses the network in the clear"
+            )));
+        }
+        Ok(Some(addr))
+    }
+
+    pub fn auth_token(&self) -> Result<Option<&str>, ConfigError> {
+        match self.daemon.auth_token.as_deref() {
+            Some("") => Err(ConfigError::InvalidValue(
+                "auth_token must not be empty".to_string(),
+            )),
+            token => Ok(token),
+        }
+    }
+
+    pub fn tls(&self) -> Result<Option<TlsFiles>, ConfigError> {
+        let daemon = &self.daemon;
+        match (&daemon.tls_cert, &daemon.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some(TlsFiles {
+                cert: cert.clone(),
+                key: key.clone(),
+                client_ca: daemon.tls_client_ca.clone(),
+            })),
+            (None, None) if daemon.tls_client_ca.is_some() => Err(ConfigError::InvalidValue(
+                "tls_client_ca needs tls_cert and tls_key".to_string(),
+            )),
+            (None, None) => Ok(None),
+            _ => Err(ConfigError::InvalidValue(
+                "tls_cert and tls_key must be set together".to_string(),
+            )),
+        }
+    }
+
+    /// Check every value up front so a bad settings file fails daemon startup
+    /// instead of surfacing later.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.auto_exit()?;
+        self.stagger()?;
+        self.request_timeout()?;
+        self.max_request_size()?;
+        self.parallel_starts()?;
+        self.stats_interval()?;
+        self.socket_mode()?;
+        self.listen_tcp()?;
+        self.listen_http()?;
+        self.auth_token()?;
+        self.tls()?;
+        self.logs.rotation()?;
+        self.logs.total_max()?;
+        self.storage.retention()?;
+        self.storage.sample_interval()?;
+        self.log_ship.url()?;
+        self.log_ship.batch_size()?;
+        self.log_ship.backoff()?;
+        self.notifications.validate()?;
+        self.metrics.statsd()?;
+        self.metrics.otlp()?;
+        self.metrics.interval()?;
+        for plugin in &self.plugins {
+            process::parse_command(&plugin.command).map_err(|e| {
+                ConfigError::InvalidValue(format!("plugin `{}`: {e}", plugin.command))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Keys
+// ---------------------------------------------------------------------------
+//
+// `pm3 config` reads and writes single settings by dotted key. Plugins and
+// notification channels are tables of their own and are left to editing
+// `daemon.toml` by hand; `notifications.cooldown` is a key like the others.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    Text,
+    Number,
+    Flag,
+}
+
+/// A setting `pm3 config` can read and write.
+#[derive(Debug, Clone, Copy)]
+pub struct SettingKey {
+    pub key: &'static str,
+    kind: ValueKind,
+    /// What applies while the key is unset.
+    pub default: &'static str,
+    /// Whether a change reaches the running daemon; the rest are read once
+    /// when it starts.
+    pub live: bool,
+}
+
+const fn key(key: &'static str, kind: ValueKind, default: &'static str, live: bool) -> SettingKey {
+    SettingKey {
+        key,
+        kind,
+        default,
+        live,
+    }
+}
+
+pub const KEYS: [SettingKey; 30] = [
+    key("daemon.auto_exit", ValueKind::Text, "never", false),
+    key("daemon.auto_save", ValueKind::Flag, "false", true),
+    key("daemon.stagger", ValueKind::Text, "none", true),
+    key("daemon.request_timeout", ValueKind::Text, "10s", true),
+    key("daemon.max_request_size", ValueKind::Text, "4M", true),
+    key("daemon.parallel_starts", ValueKind::Number, "8", true),
+    key("daemon.stats_interval", ValueKind::Text, "1s", false),
+    key("daemon.socket_mode", ValueKind::Text, "umask", false),
+    key("daemon.listen_tcp", ValueKind::Text, "none", false),
+    key("daemon.listen_http", ValueKind::Text, "none", false),
+    key("daemon.auth_token", ValueKind::Text, "none", false),
+    key("daemon.tls_cert", ValueKind::Text, "none", false),
+    key("daemon.tls_key", ValueKind::Text, "none", false),
+    key("daemon.tls_client_ca", ValueKind::Text, "none", false),
+    key("logs.rotate_size", ValueKind::Text, "10M", true),
+    key("logs.rotate_keep", ValueKind::Number, "3", true),
+    key("logs.total_max", ValueKind::Text, "none", true),
+    key("storage.backend", ValueKind::Text, "files", false),
+    key("storage.retention", ValueKind::Text, "forever", false),
+    key("storage.sample_interval", ValueKind::Text, "60s", false),
+    key("log_ship.url", ValueKind::Text, "none", false),
+    key("log_ship.format", ValueKind::Text, "json", false),
+    key("log_ship.batch_size", ValueKind::Number, "100", false),
+    key("log_ship.backoff", ValueKind::Text, "1m", false),
+    key("notifications.cooldown", ValueKind::Text, "none", true),
+    key("metrics.statsd", ValueKind::Text, "none", false),
+    key("metrics.dogstatsd", ValueKind::Flag, "false", false),
+    key("metrics.otlp", ValueKind::Text, "none", false),
+    key("metrics.interval", ValueKind::Text, "10s", false),
+    key("metrics.prefix", ValueKind::Text, "pm3", false),
+];
+
+pub fn find_key(key: &str) -> Result<&'static SettingKey, ConfigError> {
+    KEYS.iter().find(|k| k.key == key).ok_or_else(|| {
+        ConfigError::InvalidValue(format!(
+            "unknown setting `{key}` (`pm3 config list` shows them all)"
+        ))
+    })
+}
+
+/// The value `settings` gives `key`, or `None` while it is unset.
+pub fn get(settings: &DaemonSettings, key: &str) -> Result<Option<String>, ConfigError> {
+    let setting = find_key(key)?;
+    let (section, field) = setting.key.split_once('.').expect("keys are dotted");
+    let table =
+        toml::Table::try_from(settings).map_err(|e| ConfigError::TomlParse(e.to_string()))?;
+    let value = table
+        .get(section)
+        .and_then(|section| section.get(field))
+        .map(|value| match value {
+            toml::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        });
+    // `auto_save`, `backend`, `format` and `dogstatsd` are always present; at
+    // their default they count as unset
+    Ok(value.filter(|v| v != setting.default))
+}
+
+/// Set `key` to `value` (or with `None` remove it) in the settings file
+/// `content`, returning the new file and the settings it holds. The result
+/// is validated as a whole, so a bad value never reaches the file.
+pub fn set(
+    content: &str,
+    key: &str,
+    value: Option<&str>,
+) -> Result<(String, DaemonSettings), ConfigError> {
+    let setting = find_key(key)?;
+    let (section, field) = setting.key.split_once('.').expect("keys are dotted");
+    let mut table: toml::Table = content
+        .parse()
+        .map_err(|e: toml::de::Error| ConfigError::TomlParse(e.to_string()))?;
+    let entry = table
+        .entry(section)
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    let toml::Value::Table(section_table) = entry else {
+        return Err(ConfigError::InvalidValue(format!(
+            "`{section}` in daemon.toml is not a table"
+        )));
+    };
+    match value {
+        Some(value) => {
+            let parsed = match setting.kind {
+                ValueKind::Text => toml::Value::String(value.to_string()),
+                ValueKind::Number => value.parse().map(toml::Value::Integer).map_err(|_| {
+                    ConfigError::InvalidValue(format!("{key} must be a number, not `{value}`"))
+                })?,
+                ValueKind::Flag => value.parse().map(toml::Value::Boolean).map_err(|_| {
+                    ConfigError::InvalidValue(format!("{key} must be true or false, not `{value}`"))
+                })?,
+            };
+            section_table.insert(field.to_string(), parsed);
+        }
+        None => {
+            section_table.remove(field);
+        }
+    }
+    let content = toml::to_string(&table).map_err(|e| ConfigError::TomlParse(e.to_string()))?;
+    let settings = parse_settings(&content)?;
+    Ok((content, settings))
+}
+
+// ---------------------------------------------------------------------------
+// Loading
+// ---------------------------------------------------------------------------
+
+pub fn parse_settings(content: &str) -> Result<DaemonSettings, ConfigError> {
+    let settings: DaemonSettings =
+        toml::from_str(content).map_err(|e| ConfigError::TomlParse(e.to_string()))?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Load `daemon.toml`, falling back to defaults when the file does not exist.
+pub async fn load_settings(paths: &Paths) -> Result<DaemonSettings, ConfigError> {
+    let path = paths.settings_file();
+    match tokio::fs::read_to_string(&path).await {
+        Ok(content) => parse_settings(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DaemonSettings::default()),
+        Err(e) => Err(ConfigError::IoError(format!("{}: {}", path.display(), e))),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_settings_are_default() {
+        let settings = parse_settings("").unwrap();
+        assert_eq!(settings, DaemonSettings::default());
+        assert_eq!(settings.auto_exit().unwrap(), None);
+        assert!(!settings.daemon.auto_save);
+    }
+
+    #[test]
+    fn test_auto_exit_parses() {
+        let settings = parse_settings(
+            r#"
+[daemon]
+auto_exit = "30m"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.auto_exit().unwrap(),
+            Some(Duration::from_secs(30 * 60))
+        );
+    }
+
+    #[test]
+    fn test_idle_timeout_is_auto_exit() {
+        let settings = parse_settings("[daemon]\nidle_timeout = \"10m\"\n").unwrap();
+        assert_eq!(
+            settings.auto_exit().unwrap(),
+            Some(Duration::from_secs(10 * 60))
+        );
+    }
+
+    #[test]
+    fn test_stagger_parses() {
+        let settings = parse_settings("[daemon]\nstagger = \"2s\"\n").unwrap();
+        assert_eq!(settings.stagger().unwrap(), Some(Duration::from_secs(2)));
+        assert_eq!(DaemonSettings::default().stagger().unwrap(), None);
+
+        let result = parse_settings("[daemon]\nstagger = \"soon\"\n");
+        assert!(
+            matches!(result, Err(ConfigError::InvalidValue(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let settings = DaemonSettings::default();
+        assert_eq!(settings.request_timeout().unwrap(), DEFAULT_REQUEST_TIMEOUT);
+        assert_eq!(
+            settings.max_request_size().unwrap(),
+            DEFAULT_MAX_REQUEST_SIZE
+        );
+
+        let settings =
+            parse_settings("[daemon]\nrequest_timeout = \"500ms\"\nmax_request_size = \"64K\"\n")
+                .unwrap();
+        assert_eq!(
+            settings.request_timeout().unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(settings.max_request_size().unwrap(), 64 * 1024);
+        assert_eq!(settings.parallel_starts().unwrap(), DEFAULT_PARALLEL_STARTS);
+        let settings = parse_settings("[daemon]\nparallel_starts = 2\n").unwrap();
+        assert_eq!(settings.parallel_starts().unwrap(), 2);
+
+        for bad in [
+            "request_timeout = \"0s\"",
+            "parallel_starts = 0",
+            "max_request_size = \"0\"",
+            "max_request_size = \"lots\"",
+        ] {
+            let result = parse_settings(&format!("[daemon]\n{bad}\n"));
+            assert!(
+                matches!(result, Err(ConfigError::InvalidValue(_))),
+                "{bad}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_storage_settings() {
+        let settings = parse_settings(
+            r#"
+[storage]
+backend = "sqlite"
+retention = "7d"
+sample_interval = "5s"
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.storage.backend, StorageBackend::Sqlite);
+        assert_eq!(
+            settings.storage.retention().unwrap(),
+            Some(Duration::from_secs(7 * 86400))
+        );
+        assert_eq!(
+            settings.storage.sample_interval().unwrap(),
+            Duration::from_secs(5)
+        );
+
+        let defaults = DaemonSettings::default();
+        assert_eq!(defaults.storage.backend, StorageBackend::Files);
+        assert_eq!(
+            defaults.storage.sample_interval().unwrap(),
+            DEFAULT_SAMPLE_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_log_and_socket_settings() {
+        let defaults = DaemonSettings::default();
+        assert_eq!(defaults.logs.rotation().unwrap(), Rotation::default());
+        assert_eq!(defaults.stats_interval().unwrap(), DEFAULT_STATS_INTERVAL);
+        assert_eq!(defaults.socket_mode().unwrap(), None);
+        assert_eq!(defaults.listen_tcp().unwrap(), None);
+        assert_eq!(defaults.listen_http().unwrap(), None);
+        assert_eq!(defaults.logs.total_max().unwrap(), None);
+
+        let settings = parse_settings(
+            r#"
+[daemon]
+stats_interval = "5s"
+socket_mode = "0660"
+listen_tcp = "0.0.0.0:9617"
+listen_http = "127.0.0.1:9618"
+auth_token = "s3cret"
+tls_cert = "/etc/pm3/cert.pem"
+tls_key = "/etc/pm3/key.pem"
+
+[logs]
+rotate_size = "1M"
+rotate_keep = 5
+total_max = "2G"
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.stats_interval().unwrap(), Duration::from_secs(5));
+        assert_eq!(settings.socket_mode().unwrap(), Some(0o660));
+        assert_eq!(
+            settings.listen_tcp().unwrap(),
+            Some("0.0.0.0:9617".parse().unwrap())
+        );
+        assert_eq!(
+            settings.listen_http().unwrap(),
+            Some("127.0.0.1:9618".parse().unwrap())
+        );
+        assert_eq!(settings.auth_token().unwrap(), Some("s3cret"));
+        assert_eq!(
+            settings.tls().unwrap(),
+            Some(TlsFiles {
+                cert: PathBuf::from("/etc/pm3/cert.pem"),
+                key: PathBuf::from("/etc/pm3/key.pem"),
+                client_ca: None,
+            })
+        );
+        // Mutual TLS stands in for the token
+        let mutual = parse_settings(
+            "[daemon]\nlisten_tcp = \"0.0.0.0:9617\"\ntls_cert = \"c.pem\"\n\
+             tls_key = \"k.pem\"\ntls_client_ca = \"ca.pem\"\n",
+        )
+        .unwrap();
+        assert_eq!(mutual.auth_token().unwrap(), None);
+        assert!(mutual.listen_tcp().unwrap().is_some());
+        // A token alone is enough on loopback, where it never leaves the host
+        let loopback =
+            parse_settings("[daemon]\nlisten_tcp = \"127.0.0.1:9617\"\nauth_token = \"s3cret\"\n")
+                .unwrap();
+        assert!(loopback.listen_tcp().unwrap().is_some());
+        assert_eq!(
+            settings.logs.rotation().unwrap(),
+            Rotation {
+                size: 1024 * 1024,
+                keep: 5,
+                ..Rotation::default()
+            }
+        );
+        assert_eq!(settings.logs.total_max().unwrap(), Some(2 << 30));
+
+        for bad in [
+            "[daemon]\nsocket_mode = \"rw\"",
+            "[daemon]\nsocket_mode = \"1777\"",
+            "[daemon]\nstats_interval = \"0s\"",
+            "[daemon]\nlisten_tcp = \"9617\"\nauth_token = \"s3cret\"",
+            "[daemon]\nlisten_tcp = \"0.0.0.0:9617\"",
+            "[daemon]\nlisten_tcp = \"0.0.0.0:9617\"\nauth_token = \"s3cret\"",
+            "[daemon]\nauth_token = \"\"",
+            "[daemon]\nlisten_http = \"127.0.0.1:9618\"",
+            "[daemon]\nlisten_http = \"localhost\"\nauth_token = \"s3cret\"",
+            "[daemon]\nlisten_http = \"0.0.0.0:9618\"\nauth_token = \"s3cret\"",
+            "[daemon]\ntls_cert = \"c.pem\"",
+            "[daemon]\ntls_client_ca = \"ca.pem\"",
+            "[logs]\nrotate_size = \"0\"",
+            "[logs]\ntotal_max = \"0\"",
+            "[logs]\ntotal_max = \"lots\"",
+        ] {
+            let result = parse_settings(bad);
+            assert!(
+                matches!(result, Err(ConfigError::InvalidValue(_))),
+                "{bad}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_and_get_by_key() {
+        let content = "[daemon]\nauto_exit = \"30m\"\n";
+        let (content, settings) = set(content, "daemon.parallel_starts", Some("2")).unwrap();
+        assert_eq!(settings.parallel_starts().unwrap(), 2);
+        assert_eq!(
+            get(&settings, "daemon.auto_exit").unwrap().as_deref(),
+            Some("30m")
+        );
+        assert_eq!(
+            get(&settings, "daemon.parallel_starts").unwrap().as_deref(),
+            Some("2")
+        );
+        assert_eq!(get(&settings, "logs.rotate_keep").unwrap(), None);
+
+        let (content, settings) = set(&content, "logs.rotate_size", Some("1M")).unwrap();
+        assert_eq!(settings.logs.rotation().unwrap().size, 1024 * 1024);
+        let (content, settings) = set(&content, "daemon.auto_save", Some("true")).unwrap();
+        assert!(settings.daemon.auto_save);
+
+        let (_, settings) = set(&content, "daemon.auto_exit", None).unwrap();
+        assert_eq!(get(&settings, "daemon.auto_exit").unwrap(), None);
+        assert!(settings.daemon.auto_save);
+        assert_eq!(get(&settings, "storage.backend").unwrap(), None);
+
+        for (key, value) in [
+            ("daemon.bogus", "1"),
+            ("daemon.parallel_starts", "many"),
+            ("daemon.parallel_starts", "0"),
+            ("daemon.auto_save", "yes"),
+            ("daemon.stagger", "soon"),
+        ] {
+            let result = set(&content, key, Some(value));
+            assert!(
+                matches!(result, Err(ConfigError::InvalidValue(_))),
+                "{key} = {value}: {result:?}"
+            );
+        }
+        assert!(set(&content, "storage.backend", Some("postgres")).is_err());
+    }
+
+    #[test]
+    fn test_plugin_settings() {
+        let settings = parse_settings(
+            r#"
+[[plugins]]
+command = "pm3-slack --channel ops"
+events = ["exit", "restart"]
+
+[[plugins]]
+command = "pm3-audit"
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.plugins.len(), 2);
+        assert!(settings.plugins[0].subscribes_to(EventKind::Exit));
+        assert!(!settings.plugins[0].subscribes_to(EventKind::Start));
+        assert!(settings.plugins[1].subscribes_to(EventKind::Start));
+
+        let result = parse_settings("[[plugins]]\ncommand = \"\"\n");
+        assert!(matches!(result, Err(ConfigError::InvalidValue(_))));
+        let result = parse_settings("[[plugins]]\ncommand = \"x\"\nevents = [\"boot\"]\n");
+        assert!(matches!(result, Err(ConfigError::TomlParse(_))));
+    }
+
+    #[test]
+    fn test_log_ship_settings() {
+        let defaults = DaemonSettings::default();
+        assert_eq!(defaults.log_ship.url().unwrap(), None);
+        assert_eq!(
+            defaults.log_ship.batch_size().unwrap(),
+            DEFAULT_SHIP_BATCH_SIZE
+        );
+        assert_eq!(defaults.log_ship.backoff().unwrap(), DEFAULT_SHIP_BACKOFF);
+
+        let settings = parse_settings(
+            r#"
+[log_ship]
+url = "http://loki:3100/loki/api/v1/push"
+format = "loki"
+batch_size = 500
+backoff = "5m"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.log_ship.url().unwrap(),
+            Some("http://loki:3100/loki/api/v1/push")
+        );
+        assert_eq!(settings.log_ship.format, ShipFormat::Loki);
+        assert_eq!(settings.log_ship.batch_size().unwrap(), 500);
+        assert_eq!(
+            settings.log_ship.backoff().unwrap(),
+            Duration::from_secs(300)
+        );
+
+        for bad in [
+            "url = \"ftp://example.com\"",
+            "url = \"http://x\"\nbatch_size = 0",
+        ] {
+            let result = parse_settings(&format!("[log_ship]\n{bad}\n"));
+            assert!(matches!(result, Err(ConfigError::InvalidValue(_))), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_metrics_settings() {
+        let defaults = DaemonSettings::default();
+        assert_eq!(defaults.metrics.statsd().unwrap(), None);
+        assert_eq!(defaults.metrics.otlp().unwrap(), None);
+        assert_eq!(
+            defaults.metrics.interval().unwrap(),
+            DEFAULT_METRICS_INTERVAL
+        );
+        assert_eq!(defaults.metrics.prefix(), "pm3");
+
+        let settings = parse_settings(
+            r#"
+[metrics]
+statsd = "127.0.0.1:8125"
+dogstatsd = true
+otlp = "http://collector:4318/v1/metrics"
+interval = "30s"
+prefix = "apps.pm3"
+"#,
+        )
+        .unwrap();
+        let metrics = &settings.metrics;
+        assert_eq!(metrics.statsd().unwrap(), Some("127.0.0.1:8125"));
+        assert!(metrics.dogstatsd);
+        assert_eq!(
+            metrics.otlp().unwrap(),
+            Some("http://collector:4318/v1/metrics")
+        );
+        assert_eq!(metrics.interval().unwrap(), Duration::from_secs(30));
+        assert_eq!(metrics.prefix(), "apps.pm3");
+
+        for bad in [
+            "statsd = \"localhost\"",
+            "statsd = \":8125\"",
+            "otlp = \"collector:4318\"",
+            "interval = \"0s\"",
+            "interval = \"often\"",
+        ] {
+            let result = parse_settings(&format!("[metrics]\n{bad}\n"));
+            assert!(result.is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_notification_settings() {
+        let settings = parse_settings(
+            r#"
+[notifications.slack]
+webhook_url = "https://hooks.slack.com/services/T0/B0/x"
+template = "{name} {event}"
+
+[notifications.email]
+smtp_host = "smtp.example.com"
+tls = "tls"
+username = "ops"
+password = "secret"
+from = "pm3@example.com"
+to = ["ops@example.com"]
+"#,
+        )
+        .unwrap();
+        let notifications = &settings.notifications;
+        assert_eq!(
+            notifications.slack.as_ref().unwrap().template.as_deref(),
+            Some("{name} {event}")
+        );
+        assert_eq!(notifications.discord, None);
+        let email = notifications.email.as_ref().unwrap();
+        assert_eq!((email.tls, email.port()), (SmtpTls::Tls, 465));
+        assert_eq!(notifications.cooldown().unwrap(), None);
+        let settings = parse_settings("[notifications]\ncooldown = \"10m\"\n").unwrap();
+        assert_eq!(
+            settings.notifications.cooldown().unwrap(),
+            Some(Duration::from_secs(600))
+        );
+
+        for bad in [
+            "[notifications.slack]\nwebhook_url = \"slack\"",
+            "[notifications.discord]\nwebhook_url = \"http://x/\"\ntemplate = \"{uptime}\"",
+            "[notifications.email]\nsmtp_host = \"h\"\nfrom = \"a@b\"\nto = []",
+            "[notifications.email]\nsmtp_host = \"h\"\nfrom = \"a@b\"\nto = [\"c@d\"]\nusername = \"u\"",
+            "[notifications]\ncooldown = \"soon\"",
+        ] {
+            let result = parse_settings(&format!("{bad}\n"));
+            assert!(matches!(result, Err(ConfigError::InvalidValue(_))), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_unknown_storage_backend_errors() {
+        let result = parse_settings("[storage]\nbackend = \"postgres\"\n");
+        assert!(matches!(result, Err(ConfigError::TomlParse(_))));
+    }
+
+    #[test]
+    fn test_invalid_auto_exit_errors() {
+        let result = parse_settings(
+            r#"
+[daemon]
+auto_exit = "soon"
+"#,
+        );
+        assert!(matches!(result, Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_unknown_setting_errors() {
+        let result = parse_settings(
+            r#"
+[daemon]
+bogus = 1
+"#,
+        );
+        assert!(matches!(result, Err(ConfigError::TomlParse(_))));
+    }
+
+    #[tokio::test]
+    async fn test_load_missing_file_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        assert_eq!(
+            load_settings(&paths).await.unwrap(),
+            DaemonSettings::default()
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use crate::http;
+use crate::log::LogStream;
+use crate::paths::Paths;
+use crate::settings::{LogShipSection, ShipFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::warn;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Lines waiting for the shipper. Once it is this far behind, further lines
+/// are dropped rather than held up in the log copiers.
+const SHIP_QUEUE: usize = 10_000;
+
+/// A partial batch goes out after this long.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// First wait after a failed request; each further failure doubles it.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The disk buffer stops growing here, dropping new lines instead.
+const BUFFER_LIMIT: u64 = 100 * 1024 * 1024;
+
+// ---------------------------------------------------------------------------
+// Types
+// ---------------------------------------------------------------------------
+
+/// One captured line, as shipped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShippedLine {
+    /// When the line was captured (unix ms).
+    pub timestamp: i64,
+    pub process: String,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+/// Hands captured lines to the daemon's shipper task.
+#[derive(Debug, Clone)]
+pub struct Shipper {
+    tx: mpsc::Sender<ShippedLine>,
+}
+
+impl Shipper {
+    pub fn send(&self, line: ShippedLine) {
+        let _ = self.tx.try_send(line);
+    }
+}
+
+/// What the shipper task needs from `[log_ship]`, checked up front.
+#[derive(Debug, Clone)]
+struct ShipConfig {
+    url: String,
+    format: ShipFormat,
+    batch_size: usize,
+    max_backoff: Duration,
+}
+
+// ---------------------------------------------------------------------------
+// Starting and closing
+// ---------------------------------------------------------------------------
+
+/// The shipper task, which the daemon closes at shutdown. Copiers can hold a
+/// `Shipper` past that, so the task does not wait for them to let go.
+pub struct Shipping {
+    task: JoinHandle<()>,
+    close: oneshot::Sender<()>,
+}
+
+impl Shipping {
+    /// Stop taking lines and send what is queued. The task ends once that is
+    /// out (or on disk).
+    pub fn close(self) -> JoinHandle<()> {
+        let _ = self.close.send(());
+        self.task
+    }
+}
+
+/// Start shipping to the `[log_ship]` endpoint, if one is set.
+pub fn start(paths: &Paths, section: &LogShipSection) -> Option<(Shipper, Shipping)> {
+    let config = ShipConfig {
+        url: section.url().ok()??.to_string(),
+        format: section.format,
+        batch_size: section.batch_size().ok()?,
+        max_backoff: section.backoff().ok()?,
+    };
+    let (tx, rx) = mpsc::channel(SHIP_QUEUE);
+    let (close, closed) = oneshot::channel();
+    let task = tokio::spawn(run_shipper(config, paths.ship_buffer(), rx, closed));
+    Some((Shipper { tx }, Shipping { task, close }))
+}
+
+// ---------------------------------------------------------------------------
+// Shipping
+// ---------------------------------------------------------------------------
+
+async fn run_shipper(
+    config: ShipConfig,
+    buffer: PathBuf,
+    mut rx: mpsc::Receiver<ShippedLine>,
+    mut closed: oneshot::Receiver<()>,
+) {
+    let mut delivery = Delivery::new(config, buffer);
+    let mut batch = Vec::new();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            // What is already queued still comes out of `rx`, then `None`
+            _ = &mut closed, if !closing => {
+                closing = true;
+                rx.close();
+                continue;
+            }
+            line = rx.recv() => match line {
+                Some(line) => {
+                    batch.push(line);
+                    if batch.len() < delivery.config.batch_size {
+                        continue;
+                    }
+                }
+                None => {
+                    // Last chance: whatever can't be sent waits on disk for
+                    // the next daemon
+                    delivery.deliver(std::mem::take(&mut batch)).await;
+                    return;
+                }
+            },
+            _ = flush.tick() => {}
+        }
+        delivery.deliver(std::mem::take(&mut batch)).await;
+    }
+}
+
+/// Sends batches, keeping those the endpoint could not take in the disk
+/// buffer until it is back. Buffered lines always go out first, so the
+/// endpoint sees them in order.
+struct Delivery {
+    config: ShipConfig,
+    buffer: PathBuf,
+    /// When to try again, and how long the wait after that will be.
+    retry: Option<(Instant, Duration)>,
+}
+
+impl Delivery {
+    fn new(config: ShipConfig, buffer: PathBuf) -> Self {
+        Self {
+            config,
+            buffer,
+            retry: None,
+        }
+    }
+
+    async fn deliver(&mut self, batch: Vec<ShippedLine>) {
+        if self.retry.is_some_and(|(at, _)| Instant::now() < at) {
+            self.spill(&batch).await;
+            return;
+        }
+        if !self.drain_buffer().await {
+            self.spill(&batch).await;
+            return;
+        }
+        if batch.is_empty() {
+            return;
+        }
+        match self.post(&batch).await {
+            Ok(()) => self.retry = None,
+            Err(e) => {
+                self.back_off(&e);
+                self.spill(&batch).await;
+            }
+        }
+    }
+
+    /// Send what the buffer holds; false if the endpoint is still down.
+    async fn drain_buffer(&mut self) -> bool {
+        let lines = match read_buffer(&self.buffer).await {
+            Ok(lines) => lines,
+            Err(e) => {
+                warn!("log_ship: cannot read {}: {e}", self.buffer.display());
+                return true;
+            }
+        };
+        let mut sent = 0;
+        for chunk in lines.chunks(self.config.batch_size) {
+            if let Err(e) = self.post(chunk).await {
+                self.back_off(&e);
+                let _ = write_buffer(&self.buffer, &lines[sent..]).await;
+                return false;
+            }
+            sent += chunk.len();
+        }
+        if sent > 0 {
+            let _ = tokio::fs::remove_file(&self.buffer).await;
+        }
+        true
+    }
+
+    fn back_off(&mut self, error: &str) {
+        let wait = match self.retry {
+            Some((_, wait)) => wait,
+            None => FIRST_BACKOFF,
+        };
+        warn!(
+            "log_ship: {} failed ({error}); retrying in {}s",
+            self.config.url,
+            wait.as_secs()
+        );
+        let next = (wait * 2).min(self.config.max_backoff);
+        self.retry = Some((Instant::now() + wait, next));
+    }
+
+    async fn spill(&self, batch: &[ShippedLine]) {
+        if batch.is_empty() {
+            return;
+        }
+        let size = tokio::fs::metadata(&self.buffer)
+            .await
+            .map_or(0, |meta| meta.len());
+        if size >= BUFFER_LIMIT {
+            warn!("log_ship: buffer is full, dropping {} lines", batch.len());
+            return;
+        }
+        if let Err(e) = append_buffer(&self.buffer, batch).await {
+            warn!("log_ship: cannot write {}: {e}", self.buffer.display());
+        }
+    }
+
+    async fn post(&self, batch: &[ShippedLine]) -> Result<(), String> {
+        let body = encode_batch(self.config.format, batch);
+        tokio::time::timeout(REQUEST_TIMEOUT, http::post_json(&self.config.url, &body))
+            .await
+            .map_err(|_| "timed out".to_string())?
+    }
+}
+
+/// The request body for `batch` in `format`.
+fn encode_batch(format: ShipFormat, batch: &[ShippedLine]) -> Vec<u8> {
+    let body = match format {
+        ShipFormat::Json => serde_json::to_value(batch),
+        ShipFormat::Loki => {
+            let mut streams: BTreeMap<(&str, LogStream), Vec<[String; 2]>> = BTreeMap::new();
+            for line in batch {
+                // Loki wants nanoseconds, as a string
+                let at = (line.timestamp as i128 * 1_000_000).to_string();
+                streams
+                    .entry((&line.process, line.stream))
+                    .or_default()
+                    .push([at, line.line.clone()]);
+            }
+            let streams: Vec<_> = streams
+                .into_iter()
+                .map(|((process, stream), values)| {
+                    serde_json::json!({
+                        "stream": { "process": process, "stream": stream },
+                        "values": values,
+                    })
+                })
+                .collect();
+            Ok(serde_json::json!({ "streams": streams }))
+        }
+    };
+    serde_json::to_vec(&body.expect("log lines serialize")).expect("log lines serialize")
+}
+
+// ---------------------------------------------------------------------------
+// Disk buffer (JSON Lines)
+// ---------------------------------------------------------------------------
+
+async fn read_buffer(path: &Path) -> std::io::Result<Vec<ShippedLine>> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    // A line cut short by a crash is skipped
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+fn to_jsonl(lines: &[ShippedLine]) -> String {
+    lines
+        .iter()
+        .map(|line| serde_json::to_string(line).expect("log lines serialize") + "\n")
+        .collect()
+}
+
+async fn append_buffer(path: &Path, lines: &[ShippedLine]) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(to_jsonl(lines).as_bytes()).await
+}
+
+/// Replace the buffer with `lines`, atomically.
+async fn write_buffer(path: &Path, lines: &[ShippedLine]) -> std::io::Result<()> {
+    let tmp = path.with_extension("jsonl.tmp");
+    tokio::fs::write(&tmp, to_jsonl(lines)).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn line(process: &str, stream: LogStream, text: &str) -> ShippedLine {
+        ShippedLine {
+            timestamp: 1_700_000_000_000,
+            process: process.to_string(),
+            stream,
+            line: text.to_string(),
+        }
+    }
+
+    fn config(url: String) -> ShipConfig {
+        ShipConfig {
+            url,
+            format: ShipFormat::Json,
+            batch_size: 2,
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    /// Accept `requests` POSTs, answering each with `status`, and return
+    /// their bodies.
+    async fn serve(listener: &TcpListener, requests: usize, status: u16) -> Vec<String> {
+        let mut bodies = Vec::new();
+        for _ in 0..requests {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            let body = loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length {
+                        break body.to_string();
+                    }
+                }
+            };
+            let response = format!("HTTP/1.1 {status} X\r\nContent-Length: 0\r\n\r\n");
+            stream.write_all(response.as_bytes()).await.unwrap();
+            bodies.push(body);
+        }
+        bodies
+    }
+
+    #[test]
+    fn test_loki_body_groups_streams() {
+        let batch = [
+            line("web", LogStream::Stdout, "one"),
+            line("web", LogStream::Stderr, "oops"),
+            line("web", LogStream::Stdout, "two"),
+        ];
+        let body: serde_json::Value =
+            serde_json::from_slice(&encode_batch(ShipFormat::Loki, &batch)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "streams": [
+                {
+                    "stream": { "process": "web", "stream": "stdout" },
+                    "values": [
+                        ["1700000000000000000", "one"],
+                        ["1700000000000000000", "two"],
+                    ],
+                },
+                {
+                    "stream": { "process": "web", "stream": "stderr" },
+                    "values": [["1700000000000000000", "oops"]],
+                },
+            ]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_batches_wait_on_disk_and_go_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = dir.path().join("log-ship.jsonl");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let mut delivery = Delivery::new(config(url), buffer.clone());
+
+        // The endpoint fails the first batch, which is kept
+        let (_, bodies) = tokio::join!(
+            delivery.deliver(vec![line("web", LogStream::Stdout, "early")]),
+            serve(&listener, 1, 503),
+        );
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(read_buffer(&buffer).await.unwrap().len(), 1);
+        assert!(delivery.retry.is_some());
+
+        // While backing off, new batches join it without a request
+        delivery
+            .deliver(vec![line("web", LogStream::Stdout, "meanwhile")])
+            .await;
+        assert_eq!(read_buffer(&buffer).await.unwrap().len(), 2);
+
+        // Once it is back, the buffer goes out first, in batches
+        delivery.retry = None;
+        let (_, bodies) = tokio::join!(
+            delivery.deliver(vec![line("web", LogStream::Stdout, "late")]),
+            serve(&listener, 2, 200),
+        );
+        let shipped: Vec<Vec<String>> = bodies
+            .iter()
+            .map(|body| {
+                serde_json::from_str::<Vec<ShippedLine>>(body)
+                    .unwrap()
+                    .into_iter()
+                    .map(|l| l.line)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(shipped, vec![vec!["early", "meanwhile"], vec!["late"]]);
+        assert!(!buffer.exists());
+        assert!(delivery.retry.is_none());
+    }
+}
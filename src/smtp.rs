@@ -0,0 +1,281 @@
+use crate::http;
+use crate::settings::{EmailChannel, SmtpTls};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+// ---------------------------------------------------------------------------
+// Sending
+// ---------------------------------------------------------------------------
+
+/// A plain-text message to everyone in the channel's `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mail {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Deliver `mail` through the channel's SMTP server.
+pub async fn send(channel: &EmailChannel, mail: &Mail) -> Result<(), String> {
+    let host = channel.smtp_host.as_str();
+    let stream = TcpStream::connect((host, channel.port()))
+        .await
+        .map_err(|e| format!("cannot reach {host}: {e}"))?;
+    match channel.tls {
+        SmtpTls::Tls => {
+            let mut session = Session::new(http::tls_connect(stream, host).await?);
+            session.reply(220).await?;
+            session.deliver(channel, mail).await
+        }
+        SmtpTls::Starttls => {
+            let mut session = Session::new(stream);
+            session.reply(220).await?;
+            session.command("EHLO pm3", 250).await?;
+            session.command("STARTTLS", 220).await?;
+            let stream = session.stream.into_inner();
+            let mut session = Session::new(http::tls_connect(stream, host).await?);
+            session.deliver(channel, mail).await
+        }
+        SmtpTls::None => {
+            let mut session = Session::new(stream);
+            session.reply(220).await?;
+            session.deliver(channel, mail).await
+        }
+    }
+}
+
+struct Session<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// Read a reply, which may span `250-` continuation lines, and check its
+    /// code.
+    async fn reply(&mut self, expected: u16) -> Result<(), String> {
+        loop {
+            let mut line = String::new();
+            let n = self
+                .stream
+                .read_line(&mut line)
+                .await
+                .map_err(|e| e.to_string())?;
+            if n == 0 {
+                return Err("connection closed".to_string());
+            }
+            let code: Option<u16> = line.get(..3).and_then(|code| code.parse().ok());
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            return match code {
+                Some(code) if code == expected => Ok(()),
+                _ => Err(format!("unexpected reply `{}`", line.trim_end())),
+            };
+        }
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<(), String> {
+        self.write(&format!("{command}\r\n")).await?;
+        self.reply(expected)
+            .await
+            .map_err(|e| format!("{}: {e}", command.split(' ').next().unwrap_or(command)))
+    }
+
+    async fn write(&mut self, data: &str) -> Result<(), String> {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(data.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        stream.flush().await.map_err(|e| e.to_string())
+    }
+
+    /// Everything after the greeting (and `STARTTLS`): log in, hand over the
+    /// message and say goodbye.
+    async fn deliver(&mut self, channel: &EmailChannel, mail: &Mail) -> Result<(), String> {
+        self.command("EHLO pm3", 250).await?;
+        if let (Some(username), Some(password)) = (&channel.username, &channel.password) {
+            let credentials = base64(format!("\0{username}\0{password}").as_bytes());
+            self.command(&format!("AUTH PLAIN {credentials}"), 235)
+                .await?;
+        }
+        self.command(&format!("MAIL FROM:<{}>", channel.from), 250)
+            .await?;
+        for to in &channel.to {
+            self.command(&format!("RCPT TO:<{to}>"), 250).await?;
+        }
+        self.command("DATA", 354).await?;
+        self.write(&message(channel, mail)).await?;
+        self.command(".", 250).await?;
+        // The message is accepted; a server that hangs up first is fine
+        let _ = self.command("QUIT", 221).await;
+        Ok(())
+    }
+}
+
+/// Headers and body of `mail`, with CRLF line endings and lines starting
+/// with a dot doubled so none ends the message early.
+fn message(channel: &EmailChannel, mail: &Mail) -> String {
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        channel.from,
+        channel.to.join(", "),
+        mail.subject.replace(['\r', '\n'], " "),
+        chrono::Local::now().to_rfc2822(),
+    );
+    for line in mail.body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"\0ops\0secret"), "AG9wcwBzZWNyZXQ=");
+    }
+
+    #[tokio::test]
+    async fn test_send_talks_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let (mut commands, mut data) = (Vec::new(), Vec::new());
+            let mut in_data = false;
+            stream.get_mut().write_all(b"220 ready\r\n").await.unwrap();
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        stream.get_mut().write_all(b"250 queued\r\n").await.unwrap();
+                    } else {
+                        data.push(line);
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match line.as_str() {
+                    "EHLO pm3" => b"250-mail.test\r\n250 AUTH PLAIN\r\n",
+                    "DATA" => b"354 go ahead\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    _ if line.starts_with("AUTH") => b"235 ok\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                in_data = line == "DATA";
+                commands.push(line);
+                stream.get_mut().write_all(reply).await.unwrap();
+            }
+            (commands, data)
+        });
+
+        let channel = EmailChannel {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: Some(port),
+            tls: SmtpTls::None,
+            username: Some("ops".to_string()),
+            password: Some("secret".to_string()),
+            from: "pm3@example.com".to_string(),
+            to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+            subject: None,
+            template: None,
+        };
+        let mail = Mail {
+            subject: "web crashed".to_string(),
+            body: "exit code 1\n.hidden\n".to_string(),
+        };
+        send(&channel, &mail).await.unwrap();
+
+        let (commands, data) = server.await.unwrap();
+        assert_eq!(
+            commands,
+            [
+                "EHLO pm3",
+                "AUTH PLAIN AG9wcwBzZWNyZXQ=",
+                "MAIL FROM:<pm3@example.com>",
+                "RCPT TO:<a@example.com>",
+                "RCPT TO:<b@example.com>",
+                "DATA",
+                "QUIT",
+            ]
+        );
+        assert!(data.contains(&"Subject: web crashed".to_string()));
+        assert!(data.contains(&"To: a@example.com, b@example.com".to_string()));
+        assert!(data.contains(&"..hidden".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_send_reports_rejection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"554 go away\r\n").await.unwrap();
+        });
+
+        let channel = EmailChannel {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: Some(port),
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: "pm3@example.com".to_string(),
+            to: vec!["a@example.com".to_string()],
+            subject: None,
+            template: None,
+        };
+        let mail = Mail {
+            subject: String::new(),
+            body: String::new(),
+        };
+        let err = send(&channel, &mail).await.unwrap_err();
+        assert!(err.contains("554 go away"), "{err}");
+    }
+}
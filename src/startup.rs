@@ -0,0 +1,539 @@
+use crate::cli::Platform;
+use crate::paths::Paths;
+use std::path::{Path, PathBuf};
+
+// ---------------------------------------------------------------------------
+// Units
+// ---------------------------------------------------------------------------
+
+pub const UNIT_NAME: &str = "pm3.service";
+
+/// Name of the OpenRC and SysV init scripts in `/etc/init.d`.
+pub const SCRIPT_NAME: &str = "pm3";
+
+/// How long systemd waits without a keepalive before restarting the daemon.
+const WATCHDOG_SEC: u32 = 30;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StartupError {
+    #[error("could not determine the systemd user unit directory")]
+    NoConfigDir,
+    #[error("{0} has no per-user services; use --system")]
+    NoUserScope(&'static str),
+    #[error("no permission to write {0}")]
+    PermissionDenied(PathBuf),
+    #[error("no startup unit at {0}")]
+    NotInstalled(PathBuf),
+    #[error("{path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("`{command}` failed: {message}")]
+    Command { command: String, message: String },
+}
+
+/// Whether the unit runs in the user's systemd instance or the system one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    User,
+    System,
+}
+
+/// A rendered unit or init script and where it belongs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub platform: Platform,
+    pub scope: Scope,
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+fn platform_name(platform: Platform) -> &'static str {
+    match platform {
+        Platform::Systemd => "systemd",
+        Platform::Openrc => "OpenRC",
+        Platform::Sysv => "SysV init",
+    }
+}
+
+/// Resolve the `--user` / `--system` flags: systemd defaults to a user unit,
+/// the init-script platforms only have system services.
+pub fn scope(platform: Platform, user: bool, system: bool) -> Result<Scope, StartupError> {
+    match platform {
+        Platform::Systemd if system => Ok(Scope::System),
+        Platform::Systemd => Ok(Scope::User),
+        _ if user => Err(StartupError::NoUserScope(platform_name(platform))),
+        _ => Ok(Scope::System),
+    }
+}
+
+/// Where the unit or script of `platform` and `scope` is installed.
+pub fn unit_path(platform: Platform, scope: Scope) -> Result<PathBuf, StartupError> {
+    match (platform, scope) {
+        (Platform::Systemd, Scope::User) => dirs::config_dir()
+            .map(|dir| dir.join("systemd/user").join(UNIT_NAME))
+            .ok_or(StartupError::NoConfigDir),
+        (Platform::Systemd, Scope::System) => Ok(Path::new("/etc/systemd/system").join(UNIT_NAME)),
+        (_, Scope::User) => Err(StartupError::NoUserScope(platform_name(platform))),
+        (_, Scope::System) => Ok(Path::new("/etc/init.d").join(SCRIPT_NAME)),
+    }
+}
+
+/// Render the unit or init script of `platform` for `exe` and the data
+/// directory of `paths`.
+pub fn render(
+    platform: Platform,
+    scope: Scope,
+    exe: &Path,
+    paths: &Paths,
+    user: Option<&str>,
+) -> String {
+    match platform {
+        Platform::Systemd => systemd_unit(scope, exe, paths, user),
+        Platform::Openrc => openrc_script(exe, paths, user),
+        Platform::Sysv => sysv_script(exe, paths, user),
+    }
+}
+
+/// Render a unit that runs `exe --daemon` on the data directory of `paths`
+/// and resurrects the last saved process list once the daemon is listening.
+/// The daemon reports readiness and watchdog keepalives itself, however long
+/// it takes to start. System units run as `user`.
+pub fn systemd_unit(scope: Scope, exe: &Path, paths: &Paths, user: Option<&str>) -> String {
+    let exe = quote(&exe.display().to_string());
+    let mut unit = String::from("# Generated by `pm3 startup`.\n");
+    unit.push_str("[Unit]\nDescription=pm3 process manager\nAfter=network.target\n\n");
+    unit.push_str("[Service]\nType=notify\nNotifyAccess=main\n");
+    if scope == Scope::System
+        && let Some(user) = user
+    {
+        unit.push_str(&format!("User={user}\n"));
+    }
+    unit.push_str(&format!(
+        "Environment={}\n",
+        quote(&format!("PM3_DATA_DIR={}", paths.data_dir().display()))
+    ));
+    unit.push_str(&format!("ExecStart={exe} --daemon\n"));
+    // Runs once the daemon has sent READY=1, so it never finds the daemon
+    // missing and starts one of its own
+    unit.push_str(&format!("ExecStartPost={exe} resurrect\n"));
+    unit.push_str(&format!("ExecStop={exe} kill\n"));
+    unit.push_str("Restart=on-failure\n");
+    unit.push_str(&format!("WatchdogSec={WATCHDOG_SEC}\n\n"));
+    let target = match scope {
+        Scope::User => "default.target",
+        Scope::System => "multi-user.target",
+    };
+    unit.push_str(&format!("[Install]\nWantedBy={target}\n"));
+    unit
+}
+
+/// Render an OpenRC service that supervises `exe --daemon` as `user`. The
+/// daemon stops its processes on the SIGTERM OpenRC stops it with.
+pub fn openrc_script(exe: &Path, paths: &Paths, user: Option<&str>) -> String {
+    let exe = sh_quote(&exe.display().to_string());
+    let data_dir = sh_quote(&paths.data_dir().display().to_string());
+    let socket = sh_quote(&paths.socket_file().display().to_string());
+    let command_user = user
+        .map(|user| format!("command_user={}\n", sh_quote(user)))
+        .unwrap_or_default();
+    format!(
+        r#"#!/sbin/openrc-run
+# Generated by `pm3 startup --platform openrc`.
+
+name="pm3"
+description="pm3 process manager"
+command={exe}
+command_args="--daemon"
+command_background="yes"
+{command_user}pidfile="/run/${{RC_SVCNAME}}.pid"
+retry="TERM/30/KILL/5"
+export PM3_DATA_DIR={data_dir}
+
+depend() {{
+	need net
+	use logger
+}}
+
+start_pre() {{
+	rm -f {socket}
+}}
+
+start_post() {{
+	# `pm3 resurrect` would start a daemon of its own if it ran before this
+	# one is listening, so wait for the socket first
+	tries=0
+	while [ ! -S {socket} ]; do
+		tries=$((tries + 1))
+		if [ "$tries" -gt 100 ]; then
+			eerror "pm3 did not start listening"
+			return 1
+		fi
+		sleep 0.1
+	done
+	{exe} resurrect
+}}
+"#
+    )
+}
+
+/// Render an LSB init script with start, stop, restart and status verbs that
+/// runs `exe --daemon` as `user` in the background.
+pub fn sysv_script(exe: &Path, paths: &Paths, user: Option<&str>) -> String {
+    format!(
+        r#"#!/bin/sh
+### BEGIN INIT INFO
+# Provides:          pm3
+# Required-Start:    $remote_fs $network
+# Required-Stop:     $remote_fs $network
+# Default-Start:     2 3 4 5
+# Default-Stop:      0 1 6
+# Short-Description: pm3 process manager
+### END INIT INFO
+# Generated by `pm3 startup --platform sysv`.
+
+PM3={exe}
+PM3_USER={user}
+PM3_DATA_DIR={data_dir}
+export PM3_DATA_DIR
+PIDFILE="$PM3_DATA_DIR/pm3.pid"
+SOCKET="$PM3_DATA_DIR/pm3.sock"
+
+as_user() {{
+	if [ -n "$PM3_USER" ] && [ "$(id -un)" != "$PM3_USER" ]; then
+		su -s /bin/sh -c "$1" "$PM3_USER"
+	else
+		sh -c "$1"
+	fi
+}}
+
+running() {{
+	[ -f "$PIDFILE" ] && kill -0 "$(cat "$PIDFILE")" 2>/dev/null
+}}
+
+start() {{
+	if running; then
+		echo "pm3 is already running"
+		return 0
+	fi
+	echo "Starting pm3"
+	rm -f "$SOCKET"
+	as_user "nohup '$PM3' --daemon >/dev/null 2>&1 &"
+	# `pm3 resurrect` would start a daemon of its own if it ran before this
+	# one is listening, so wait for the socket first
+	tries=0
+	until [ -S "$SOCKET" ]; do
+		tries=$((tries + 1))
+		if [ "$tries" -gt 100 ]; then
+			echo "pm3 did not start listening"
+			return 1
+		fi
+		sleep 0.1
+	done
+	as_user "'$PM3' resurrect"
+}}
+
+stop() {{
+	# `pm3 kill` would start a daemon just to stop it
+	if ! running; then
+		echo "pm3 is not running"
+		return 0
+	fi
+	echo "Stopping pm3"
+	as_user "'$PM3' kill"
+}}
+
+case "$1" in
+	start) start ;;
+	stop) stop ;;
+	restart)
+		stop
+		start
+		;;
+	status)
+		if running; then
+			echo "pm3 is running"
+		else
+			echo "pm3 is not running"
+			exit 3
+		fi
+		;;
+	*)
+		echo "Usage: $0 {{start|stop|restart|status}}"
+		exit 2
+		;;
+esac
+"#,
+        exe = sh_quote(&exe.display().to_string()),
+        user = sh_quote(user.unwrap_or_default()),
+        data_dir = sh_quote(&paths.data_dir().display().to_string()),
+    )
+}
+
+/// Quote a value for a unit file if it contains whitespace.
+fn quote(value: &str) -> String {
+    if value.contains(char::is_whitespace) {
+        format!("\"{value}\"")
+    } else {
+        value.to_string()
+    }
+}
+
+/// Quote a value for a shell script.
+fn sh_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// ---------------------------------------------------------------------------
+// Installing
+// ---------------------------------------------------------------------------
+
+fn run(program: &str, args: &[&str]) -> Result<(), StartupError> {
+    let command = std::iter::once(program)
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| StartupError::Command {
+            command: command.clone(),
+            message: e.to_string(),
+        })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(StartupError::Command {
+            command,
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+fn systemctl(scope: Scope, args: &[&str]) -> Result<(), StartupError> {
+    match scope {
+        Scope::User => run("systemctl", &[&["--user"], args].concat()),
+        Scope::System => run("systemctl", args),
+    }
+}
+
+/// Whether `program` can be found on `PATH`.
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+fn io_error(path: &Path, source: std::io::Error) -> StartupError {
+    if source.kind() == std::io::ErrorKind::PermissionDenied {
+        StartupError::PermissionDenied(path.to_path_buf())
+    } else {
+        StartupError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// Write `unit` to its path, then register it with the init system and start
+/// it.
+pub fn install(unit: &Unit) -> Result<(), StartupError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(dir) = unit.path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+    }
+    std::fs::write(&unit.path, &unit.contents).map_err(|e| io_error(&unit.path, e))?;
+    if unit.platform != Platform::Systemd {
+        std::fs::set_permissions(&unit.path, std::fs::Permissions::from_mode(0o755))
+            .map_err(|e| io_error(&unit.path, e))?;
+    }
+
+    let script = unit.path.display().to_string();
+    match unit.platform {
+        Platform::Systemd => {
+            systemctl(unit.scope, &["daemon-reload"])?;
+            systemctl(unit.scope, &["enable", "--now", UNIT_NAME])
+        }
+        Platform::Openrc => {
+            run("rc-update", &["add", SCRIPT_NAME, "default"])?;
+            run("rc-service", &[SCRIPT_NAME, "start"])
+        }
+        // Debian-style systems register scripts with update-rc.d, Red Hat
+        // style ones with chkconfig
+        Platform::Sysv if on_path("update-rc.d") => {
+            run("update-rc.d", &[SCRIPT_NAME, "defaults"])?;
+            run(&script, &["start"])
+        }
+        Platform::Sysv => {
+            run("chkconfig", &["--add", SCRIPT_NAME])?;
+            run(&script, &["start"])
+        }
+    }
+}
+
+/// Stop and deregister the unit or script of `platform` and `scope` and
+/// remove its file. Returns the path that was removed.
+pub fn uninstall(platform: Platform, scope: Scope) -> Result<PathBuf, StartupError> {
+    let path = unit_path(platform, scope)?;
+    if !path.exists() {
+        return Err(StartupError::NotInstalled(path));
+    }
+    let script = path.display().to_string();
+    match platform {
+        Platform::Systemd => systemctl(scope, &["disable", "--now", UNIT_NAME])?,
+        Platform::Openrc => {
+            run("rc-service", &[SCRIPT_NAME, "stop"])?;
+            run("rc-update", &["del", SCRIPT_NAME, "default"])?;
+        }
+        Platform::Sysv => {
+            run(&script, &["stop"])?;
+            if on_path("update-rc.d") {
+                run("update-rc.d", &["-f", SCRIPT_NAME, "remove"])?;
+            } else {
+                run("chkconfig", &["--del", SCRIPT_NAME])?;
+            }
+        }
+    }
+    std::fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+    if platform == Platform::Systemd {
+        systemctl(scope, &["daemon-reload"])?;
+    }
+    Ok(path)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_unit() {
+        let unit = systemd_unit(
+            Scope::User,
+            Path::new("/usr/local/bin/pm3"),
+            &Paths::with_base("/home/ada/.local/share/pm3".into()),
+            Some("ada"),
+        );
+        assert!(unit.contains("Environment=PM3_DATA_DIR=/home/ada/.local/share/pm3\n"));
+        assert!(unit.contains("ExecStart=/usr/local/bin/pm3 --daemon\n"));
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains("ExecStartPost=/usr/local/bin/pm3 resurrect\n"));
+        assert!(unit.contains("WatchdogSec=30\n"));
+        assert!(unit.contains("WantedBy=default.target"));
+        // User units already run as the user
+        assert!(!unit.contains("User="));
+    }
+
+    #[test]
+    fn test_system_unit_runs_as_user_and_quotes_paths() {
+        let unit = systemd_unit(
+            Scope::System,
+            Path::new("/opt/my tools/pm3"),
+            &Paths::with_base("/srv/pm3 data".into()),
+            Some("deploy"),
+        );
+        assert!(unit.contains("User=deploy\n"));
+        assert!(unit.contains("Environment=\"PM3_DATA_DIR=/srv/pm3 data\"\n"));
+        assert!(unit.contains("ExecStart=\"/opt/my tools/pm3\" --daemon\n"));
+        assert!(unit.contains("WantedBy=multi-user.target"));
+    }
+
+    #[test]
+    fn test_unit_paths() {
+        assert_eq!(
+            unit_path(Platform::Systemd, Scope::System).unwrap(),
+            PathBuf::from("/etc/systemd/system/pm3.service")
+        );
+        assert!(
+            unit_path(Platform::Systemd, Scope::User)
+                .unwrap()
+                .ends_with("systemd/user/pm3.service")
+        );
+        assert_eq!(
+            unit_path(Platform::Openrc, Scope::System).unwrap(),
+            PathBuf::from("/etc/init.d/pm3")
+        );
+    }
+
+    #[test]
+    fn test_init_scripts_are_system_only() {
+        assert_eq!(scope(Platform::Systemd, false, false).unwrap(), Scope::User);
+        assert_eq!(scope(Platform::Sysv, false, false).unwrap(), Scope::System);
+        let err = scope(Platform::Openrc, true, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "OpenRC has no per-user services; use --system"
+        );
+    }
+
+    #[test]
+    fn test_openrc_script() {
+        let script = openrc_script(
+            Path::new("/usr/bin/pm3"),
+            &Paths::with_base("/home/ada/.pm3".into()),
+            Some("ada"),
+        );
+        assert!(script.starts_with("#!/sbin/openrc-run\n"));
+        assert!(script.contains("command='/usr/bin/pm3'\ncommand_args=\"--daemon\"\n"));
+        assert!(script.contains("command_user='ada'\npidfile=\"/run/${RC_SVCNAME}.pid\"\n"));
+        assert!(script.contains("export PM3_DATA_DIR='/home/ada/.pm3'\n"));
+        assert!(script.contains("while [ ! -S '/home/ada/.pm3/pm3.sock' ]; do"));
+        assert!(script.contains("\t'/usr/bin/pm3' resurrect\n"));
+    }
+
+    #[test]
+    fn test_sysv_script() {
+        let script = sysv_script(
+            Path::new("/usr/bin/pm3"),
+            &Paths::with_base("/srv/it's pm3".into()),
+            Some("deploy"),
+        );
+        assert!(script.starts_with("#!/bin/sh\n### BEGIN INIT INFO\n"));
+        assert!(script.contains("PM3_USER='deploy'\n"));
+        assert!(script.contains("PM3_DATA_DIR='/srv/it'\\''s pm3'\n"));
+        assert!(script.contains("Usage: $0 {start|stop|restart|status}"));
+        for verb in [
+            "\tstart) start ;;",
+            "\tstop) stop ;;",
+            "\trestart)",
+            "\tstatus)",
+        ] {
+            assert!(script.contains(verb), "missing {verb}");
+        }
+        assert!(script.contains("as_user \"'$PM3' resurrect\""));
+    }
+
+    #[test]
+    fn test_sysv_script_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pm3");
+        let script = sysv_script(
+            Path::new("/usr/bin/pm3"),
+            &Paths::with_base(dir.path().join("data")),
+            None,
+        );
+        std::fs::write(&path, script).unwrap();
+        let syntax = std::process::Command::new("sh")
+            .arg("-n")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(syntax.success());
+
+        // Nothing is running, so status says so without starting anything
+        let output = std::process::Command::new("sh")
+            .arg(&path)
+            .arg("status")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "pm3 is not running\n"
+        );
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+// ---------------------------------------------------------------------------
+// Process tree
+// ---------------------------------------------------------------------------
+
+/// Snapshot of parent → children relationships and process groups for every
+/// process in `/proc`.
+#[derive(Debug, Default)]
+pub struct ProcessTree {
+    children: HashMap<u32, Vec<u32>>,
+    groups: HashMap<u32, Vec<u32>>,
+}
+
+impl ProcessTree {
+    pub fn read() -> Self {
+        Self::read_from(Path::new("/proc"))
+    }
+
+    fn read_from(proc_root: &Path) -> Self {
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut groups: HashMap<u32, Vec<u32>> = HashMap::new();
+        let Ok(entries) = std::fs::read_dir(proc_root) else {
+            return Self::default();
+        };
+        for entry in entries.flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else {
+                continue;
+            };
+            let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+            if let Some(ppid) = parse_ppid(&stat) {
+                children.entry(ppid).or_default().push(pid);
+            }
+            if let Some(pgid) = parse_pgid(&stat) {
+                groups.entry(pgid).or_default().push(pid);
+            }
+        }
+        Self { children, groups }
+    }
+
+    /// `pid` followed by all of its descendants.
+    pub fn with_descendants(&self, pid: u32) -> Vec<u32> {
+        self.walk(vec![pid])
+    }
+
+    /// `pid` followed by its descendants and anything else left in the
+    /// process group it leads. pm3 starts every process in its own session,
+    /// so a worker that double-forked away from its parent still counts.
+    pub fn members(&self, pid: u32) -> Vec<u32> {
+        let mut start = vec![pid];
+        for &member in self.groups.get(&pid).into_iter().flatten() {
+            if !start.contains(&member) {
+                start.push(member);
+            }
+        }
+        self.walk(start)
+    }
+
+    fn walk(&self, mut out: Vec<u32>) -> Vec<u32> {
+        let mut i = 0;
+        while i < out.len() {
+            for &kid in self.children.get(&out[i]).into_iter().flatten() {
+                if !out.contains(&kid) {
+                    out.push(kid);
+                }
+            }
+            i += 1;
+        }
+        out
+    }
+}
+
+/// Parent pid from the contents of `/proc/<pid>/stat`. The command name can
+/// contain spaces and parentheses, so fields are read after the last `)`.
+fn parse_ppid(stat: &str) -> Option<u32> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Process group id from the contents of `/proc/<pid>/stat`.
+fn parse_pgid(stat: &str) -> Option<u32> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(2)?.parse().ok()
+}
+
+// ---------------------------------------------------------------------------
+// Memory
+// ---------------------------------------------------------------------------
+
+/// Resident set size of a single process, in bytes.
+pub fn rss_bytes(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    parse_vm_rss(&status)
+}
+
+// ---------------------------------------------------------------------------
+// Resource samples
+// ---------------------------------------------------------------------------
+
+/// Clock ticks per second used by `/proc/<pid>/stat` (USER_HZ, fixed at 100
+/// by the Linux ABI).
+const CLOCK_TICKS_PER_SEC: u64 = 100;
+
+/// Point-in-time resource usage of a process tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceSample {
+    pub rss_bytes: u64,
+    pub cpu_time_ms: u64,
+    pub fd_count: u32,
+    pub thread_count: u32,
+    /// How many processes the sample adds up.
+    pub process_count: u32,
+}
+
+/// Sample RSS, CPU time, open file descriptors and threads of `pid` and the
+/// rest of its tree (see [`ProcessTree::members`]). Returns `None` once the
+/// process is gone.
+pub fn sample_tree(tree: &ProcessTree, pid: u32) -> Option<ResourceSample> {
+    let mut sample = ResourceSample {
+        rss_bytes: rss_bytes(pid)?,
+        cpu_time_ms: cpu_time_ms(pid).unwrap_or(0),
+        fd_count: fd_count(pid).unwrap_or(0),
+        thread_count: thread_count(pid).unwrap_or(0),
+        process_count: 1,
+    };
+    for member in tree.members(pid).into_iter().skip(1) {
+        let Some(rss) = rss_bytes(member) else {
+            continue;
+        };
+        sample.rss_bytes += rss;
+        sample.cpu_time_ms += cpu_time_ms(member).unwrap_or(0);
+        sample.fd_count += fd_count(member).unwrap_or(0);
+        sample.thread_count += thread_count(member).unwrap_or(0);
+        sample.process_count += 1;
+    }
+    Some(sample)
+}
+
+/// One process of a tree with its own usage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub pid: u32,
+    pub ppid: Option<u32>,
+    pub command: String,
+    pub rss_bytes: u64,
+    pub cpu_time_ms: u64,
+}
+
+/// Each live process of `pid`'s tree, in the order [`ProcessTree::members`]
+/// finds them.
+pub fn tree_members(tree: &ProcessTree, pid: u32) -> Vec<Member> {
+    tree.members(pid)
+        .into_iter()
+        .filter_map(|pid| {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+            Some(Member {
+                pid,
+                ppid: parse_ppid(&stat),
+                command: command_line(pid).unwrap_or_else(|| parse_comm(&stat).to_string()),
+                rss_bytes: rss_bytes(pid)?,
+                cpu_time_ms: parse_cpu_ticks(&stat)? * 1000 / CLOCK_TICKS_PER_SEC,
+            })
+        })
+        .collect()
+}
+
+/// Arguments of a process joined by spaces. `None` for kernel threads and
+/// zombies, whose `cmdline` is empty.
+fn command_line(pid: u32) -> Option<String> {
+    let raw = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    parse_cmdline(&raw)
+}
+
+fn parse_cmdline(raw: &[u8]) -> Option<String> {
+    let args: Vec<_> = raw
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    (!args.is_empty()).then(|| args.join(" "))
+}
+
+/// The command name between the parentheses of `/proc/<pid>/stat`.
+fn parse_comm(stat: &str) -> &str {
+    match (stat.find('('), stat.rfind(')')) {
+        (Some(open), Some(close)) if open < close => &stat[open + 1..close],
+        _ => "",
+    }
+}
+
+/// User plus system CPU time consumed by a single process.
+pub fn cpu_time_ms(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    parse_cpu_ticks(&stat).map(|ticks| ticks * 1000 / CLOCK_TICKS_PER_SEC)
+}
+
+/// Number of open file descriptors of a single process.
+pub fn fd_count(pid: u32) -> Option<u32> {
+    let entries = std::fs::read_dir(format!("/proc/{pid}/fd")).ok()?;
+    Some(entries.count() as u32)
+}
+
+/// Number of threads of a single process.
+pub fn thread_count(pid: u32) -> Option<u32> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    parse_threads(&status)
+}
+
+/// The `Threads:` line of `/proc/<pid>/status`.
+fn parse_threads(status: &str) -> Option<u32> {
+    let line = status.lines().find(|l| l.starts_with("Threads:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// utime + stime (fields 14 and 15) from `/proc/<pid>/stat`.
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let mut fields = rest.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(utime + stime)
+}
+
+// ---------------------------------------------------------------------------
+// Pressure
+// ---------------------------------------------------------------------------
+
+/// `avg10` stall percentages at or above which the host counts as under
+/// severe pressure.
+const CPU_SOME_SEVERE: f64 = 80.0;
+const MEMORY_SOME_SEVERE: f64 = 40.0;
+const MEMORY_FULL_SEVERE: f64 = 10.0;
+
+/// Share of the last ten seconds tasks spent stalled on CPU or memory, in
+/// percent, from Linux pressure stall information (`/proc/pressure`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pressure {
+    pub cpu_some: f64,
+    pub memory_some: f64,
+    pub memory_full: f64,
+}
+
+impl Pressure {
+    /// `None` on kernels without PSI.
+    pub fn read() -> Option<Self> {
+        let cpu = std::fs::read_to_string("/proc/pressure/cpu").ok()?;
+        let memory = std::fs::read_to_string("/proc/pressure/memory").ok()?;
+        Some(Self {
+            cpu_some: parse_psi_avg10(&cpu, "some")?,
+            memory_some: parse_psi_avg10(&memory, "some")?,
+            memory_full: parse_psi_avg10(&memory, "full").unwrap_or(0.0),
+        })
+    }
+
+    /// What makes the pressure severe, if it is.
+    pub fn severe(&self) -> Option<String> {
+        if self.memory_full >= MEMORY_FULL_SEVERE {
+            Some(format!(
+                "memory pressure (all tasks stalled {:.0}% of the time)",
+                self.memory_full
+            ))
+        } else if self.memory_some >= MEMORY_SOME_SEVERE {
+            Some(format!(
+                "memory pressure (tasks stalled {:.0}% of the time)",
+                self.memory_some
+            ))
+        } else if self.cpu_some >= CPU_SOME_SEVERE {
+            Some(format!(
+                "CPU pressure (tasks stalled {:.0}% of the time)",
+                self.cpu_some
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// The `avg10` of the `some` or `full` line of a `/proc/pressure` file.
+fn parse_psi_avg10(text: &str, kind: &str) -> Option<f64> {
+    let line = text.lines().find(|l| l.starts_with(kind))?;
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+// ---------------------------------------------------------------------------
+// Identity
+// ---------------------------------------------------------------------------
+
+/// Start time of a live process, in clock ticks since boot (field 22 of
+/// `/proc/<pid>/stat`). Together with the pid it tells a process apart from
+/// a later one that reused the pid. `None` once the process has exited, even
+/// while it lingers as a zombie.
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    parse_start_time(&stat)
+}
+
+/// How long ago a process with the given `start_time` started.
+pub fn running_for(start_time: u64) -> Option<std::time::Duration> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let uptime: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    let started = start_time as f64 / CLOCK_TICKS_PER_SEC as f64;
+    Some(std::time::Duration::from_secs_f64(
+        (uptime - started).max(0.0),
+    ))
+}
+
+/// Whether `pid` has exited but not been reaped by its parent yet.
+pub fn is_zombie(pid: u32) -> bool {
+    std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .is_ok_and(|stat| parse_state(&stat) == Some("Z"))
+}
+
+fn parse_state(stat: &str) -> Option<&str> {
+    stat[stat.rfind(')')? + 1..].split_whitespace().next()
+}
+
+fn parse_start_time(stat: &str) -> Option<u64> {
+    if parse_state(stat)? == "Z" {
+        return None;
+    }
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Memory the host can give new processes without swapping (`MemAvailable`
+/// in `/proc/meminfo`), in bytes.
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    parse_meminfo_field(&meminfo, "MemAvailable:")
+}
+
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    parse_meminfo_field(status, "VmRSS:")
+}
+
+/// A `Name:   1234 kB` line from `/proc/<pid>/status` or `/proc/meminfo`.
+fn parse_meminfo_field(text: &str, field: &str) -> Option<u64> {
+    let line = text.lines().find(|l| l.starts_with(field))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ppid_handles_odd_names() {
+        assert_eq!(parse_ppid("42 (sleep) S 7 42 42 0"), Some(7));
+        assert_eq!(parse_ppid("42 (my (weird) app) S 9 42 42 0"), Some(9));
+        assert_eq!(parse_ppid("garbage"), None);
+        assert_eq!(parse_pgid("42 (my (weird) app) S 9 40 40 0"), Some(40));
+        assert_eq!(
+            parse_comm("42 (my (weird) app) S 9 42 42 0"),
+            "my (weird) app"
+        );
+    }
+
+    #[test]
+    fn test_parse_cmdline() {
+        assert_eq!(
+            parse_cmdline(b"bash\0-c\0node server.js\0").as_deref(),
+            Some("bash -c node server.js")
+        );
+        assert_eq!(parse_cmdline(b""), None);
+    }
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\tsleep\nVmPeak:\t 8000 kB\nVmRSS:\t    1234 kB\n";
+        assert_eq!(parse_vm_rss(status), Some(1234 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tkthreadd\n"), None);
+        assert_eq!(parse_threads("Name:\tjava\nThreads:\t42\n"), Some(42));
+        assert_eq!(parse_threads(status), None);
+        let meminfo =
+            "MemTotal:       16000000 kB\nMemFree:  100 kB\nMemAvailable:    8000000 kB\n";
+        assert_eq!(
+            parse_meminfo_field(meminfo, "MemAvailable:"),
+            Some(8_000_000 * 1024)
+        );
+    }
+
+    #[test]
+    fn test_parse_cpu_ticks() {
+        let stat = "42 (my app) S 1 42 42 0 -1 4194304 100 0 0 0 250 50 0 0 20 0 1 0";
+        assert_eq!(parse_cpu_ticks(stat), Some(300));
+        assert_eq!(parse_cpu_ticks("42 (short) S 1"), None);
+    }
+
+    #[test]
+    fn test_parse_psi_and_severity() {
+        let memory = "some avg10=45.10 avg60=20.00 avg300=5.00 total=123\n\
+                      full avg10=3.50 avg60=1.00 avg300=0.20 total=45\n";
+        assert_eq!(parse_psi_avg10(memory, "some"), Some(45.1));
+        assert_eq!(parse_psi_avg10(memory, "full"), Some(3.5));
+        assert_eq!(parse_psi_avg10("", "full"), None);
+
+        let calm = Pressure::default();
+        assert_eq!(calm.severe(), None);
+        let swapping = Pressure {
+            memory_some: 45.1,
+            memory_full: 3.5,
+            ..calm
+        };
+        assert_eq!(
+            swapping.severe().unwrap(),
+            "memory pressure (tasks stalled 45% of the time)"
+        );
+        let busy = Pressure {
+            cpu_some: 95.0,
+            ..calm
+        };
+        assert!(busy.severe().unwrap().starts_with("CPU pressure"));
+    }
+
+    #[test]
+    fn test_parse_start_time() {
+        let stat = "42 (my app) S 1 42 42 0 -1 4194304 100 0 0 0 250 50 0 0 20 0 1 0 8675309 1000";
+        assert_eq!(parse_start_time(stat), Some(8675309));
+        let zombie = stat.replace(") S ", ") Z ");
+        assert_eq!(parse_start_time(&zombie), None);
+        assert!(start_time(std::process::id()).is_some());
+    }
+
+    #[test]
+    fn test_sample_own_process() {
+        let tree = ProcessTree::read();
+        let sample = sample_tree(&tree, std::process::id()).unwrap();
+        assert!(sample.rss_bytes > 0);
+        assert!(sample.fd_count > 0);
+        assert!(sample.thread_count > 0);
+        assert!(sample.process_count >= 1);
+
+        let members = tree_members(&tree, std::process::id());
+        assert_eq!(members[0].pid, std::process::id());
+        assert!(!members[0].command.is_empty());
+    }
+
+    #[test]
+    fn test_with_descendants_walks_tree() {
+        let tree = ProcessTree {
+            children: HashMap::from([(1, vec![2, 3]), (2, vec![4]), (9, vec![10])]),
+            ..Default::default()
+        };
+        let mut all = tree.with_descendants(1);
+        all.sort();
+        assert_eq!(all, vec![1, 2, 3, 4]);
+        assert_eq!(tree.with_descendants(5), vec![5]);
+    }
+
+    #[test]
+    fn test_members_include_reparented_group() {
+        // 3 double-forked away from 2 and now belongs to init, but stays in
+        // the group 2 leads; its child 4 comes along
+        let tree = ProcessTree {
+            children: HashMap::from([(1, vec![2, 3, 7]), (3, vec![4])]),
+            groups: HashMap::from([(2, vec![2, 3, 4]), (7, vec![7])]),
+        };
+        let mut all = tree.members(2);
+        all.sort();
+        assert_eq!(all, vec![2, 3, 4]);
+        assert_eq!(tree.with_descendants(2), vec![2]);
+    }
+
+    #[test]
+    fn test_own_process_has_rss() {
+        assert!(rss_bytes(std::process::id()).unwrap() > 0);
+        assert_eq!(rss_bytes(u32::MAX), None);
+    }
+}
@@ -0,0 +1,533 @@
+use crate::paths::Paths;
+use crate::protocol::{ProcessEvent, RunRecord, StatsSample};
+use crate::settings::{StorageBackend, StorageSection};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+mod sqlite;
+
+pub use sqlite::SqliteStorage;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Runs kept per process by the file backend; older entries are dropped on
+/// append. The SQLite backend relies on `retention` instead.
+pub const HISTORY_LIMIT: usize = 100;
+
+/// Lifecycle events kept per process by every backend; the oldest are
+/// dropped as new ones arrive.
+pub const EVENT_LIMIT: usize = 200;
+
+/// Resource samples kept per process by every backend, a week of them at the
+/// default `sample_interval`; the oldest are dropped as new ones arrive.
+pub const SAMPLE_LIMIT: usize = 7 * 24 * 60;
+
+// ---------------------------------------------------------------------------
+// Storage trait
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("storage I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("storage encoding error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// Where the daemon keeps run history, lifecycle events and resource samples. Timestamps are
+/// unix milliseconds; query results are ordered oldest first.
+pub trait Storage: Send + Sync {
+    fn record_run(&self, run: &RunRecord) -> Result<(), StorageError>;
+
+    /// Runs for one process (or all when `name` is `None`) that ended at or
+    /// after `since`.
+    fn runs(&self, name: Option<&str>, since: Option<i64>) -> Result<Vec<RunRecord>, StorageError>;
+
+    /// Append an event, dropping the process's oldest beyond `EVENT_LIMIT`.
+    fn record_event(&self, event: &ProcessEvent) -> Result<(), StorageError>;
+
+    /// Events for one process (or all when `name` is `None`) at or after
+    /// `since`.
+    fn events(
+        &self,
+        name: Option<&str>,
+        since: Option<i64>,
+    ) -> Result<Vec<ProcessEvent>, StorageError>;
+
+    /// Append samples, dropping each process's oldest beyond `SAMPLE_LIMIT`.
+    fn record_samples(&self, samples: &[StatsSample]) -> Result<(), StorageError>;
+
+    /// Samples for one process taken at or after `since`.
+    fn samples(&self, name: &str, since: Option<i64>) -> Result<Vec<StatsSample>, StorageError>;
+
+    /// Delete runs, events and samples older than `before`.
+    fn prune(&self, before: i64) -> Result<(), StorageError>;
+}
+
+/// Open the backend selected in `daemon.toml`.
+pub fn open(paths: &Paths, settings: &StorageSection) -> Result<Arc<dyn Storage>, StorageError> {
+    Ok(match settings.backend {
+        StorageBackend::Files => Arc::new(FileStorage::new(paths)),
+        StorageBackend::Sqlite => Arc::new(SqliteStorage::open(&paths.storage_db())?),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Async helpers
+// ---------------------------------------------------------------------------
+
+pub async fn record_run(storage: &Arc<dyn Storage>, run: RunRecord) -> Result<(), StorageError> {
+    let storage = Arc::clone(storage);
+    blocking(move || storage.record_run(&run)).await
+}
+
+pub async fn runs(
+    storage: &Arc<dyn Storage>,
+    name: Option<String>,
+    since: Option<i64>,
+) -> Result<Vec<RunRecord>, StorageError> {
+    let storage = Arc::clone(storage);
+    blocking(move || storage.runs(name.as_deref(), since)).await
+}
+
+pub async fn record_event(
+    storage: &Arc<dyn Storage>,
+    event: ProcessEvent,
+) -> Result<(), StorageError> {
+    let storage = Arc::clone(storage);
+    blocking(move || storage.record_event(&event)).await
+}
+
+pub async fn events(
+    storage: &Arc<dyn Storage>,
+    name: Option<String>,
+    since: Option<i64>,
+) -> Result<Vec<ProcessEvent>, StorageError> {
+    let storage = Arc::clone(storage);
+    blocking(move || storage.events(name.as_deref(), since)).await
+}
+
+pub async fn record_samples(
+    storage: &Arc<dyn Storage>,
+    samples: Vec<StatsSample>,
+) -> Result<(), StorageError> {
+    let storage = Arc::clone(storage);
+    blocking(move || storage.record_samples(&samples)).await
+}
+
+pub async fn samples(
+    storage: &Arc<dyn Storage>,
+    name: String,
+    since: Option<i64>,
+) -> Result<Vec<StatsSample>, StorageError> {
+    let storage = Arc::clone(storage);
+    blocking(move || storage.samples(&name, since)).await
+}
+
+pub async fn prune(storage: &Arc<dyn Storage>, before: i64) -> Result<(), StorageError> {
+    let storage = Arc::clone(storage);
+    blocking(move || storage.prune(before)).await
+}
+
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, StorageError> + Send + 'static,
+) -> Result<T, StorageError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| StorageError::Io(io::Error::other(e)))?
+}
+
+// ---------------------------------------------------------------------------
+// File backend
+// ---------------------------------------------------------------------------
+
+/// JSON Lines per process: `history/<name>.jsonl`, `events/<name>.jsonl` and
+/// `samples/<name>.jsonl`.
+pub struct FileStorage {
+    paths: Paths,
+    lock: Mutex<()>,
+}
+
+impl FileStorage {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            paths: paths.clone(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn record_run(&self, run: &RunRecord) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().unwrap();
+        let path = self.paths.history_file(&run.name);
+        append_lines(&path, std::slice::from_ref(run))?;
+
+        let mut runs: Vec<RunRecord> = read_lines(&path)?;
+        if runs.len() > HISTORY_LIMIT {
+            runs.drain(..runs.len() - HISTORY_LIMIT);
+            write_lines(&path, &runs)?;
+        }
+        Ok(())
+    }
+
+    fn runs(&self, name: Option<&str>, since: Option<i64>) -> Result<Vec<RunRecord>, StorageError> {
+        let files = match name {
+            Some(name) => vec![self.paths.history_file(name)],
+            None => jsonl_files(&self.paths.history_dir())?,
+        };
+        let mut runs = Vec::new();
+        for file in files {
+            runs.extend(read_lines::<RunRecord>(&file)?);
+        }
+        runs.retain(|r| since.is_none_or(|since| r.ended_at >= since));
+        runs.sort_by_key(|r| r.ended_at);
+        Ok(runs)
+    }
+
+    fn record_event(&self, event: &ProcessEvent) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().unwrap();
+        let path = self.paths.events_file(&event.name);
+        append_lines(&path, std::slice::from_ref(event))?;
+
+        let mut events: Vec<ProcessEvent> = read_lines(&path)?;
+        if events.len() > EVENT_LIMIT {
+            events.drain(..events.len() - EVENT_LIMIT);
+            write_lines(&path, &events)?;
+        }
+        Ok(())
+    }
+
+    fn events(
+        &self,
+        name: Option<&str>,
+        since: Option<i64>,
+    ) -> Result<Vec<ProcessEvent>, StorageError> {
+        let files = match name {
+            Some(name) => vec![self.paths.events_file(name)],
+            None => jsonl_files(&self.paths.events_dir())?,
+        };
+        let mut events = Vec::new();
+        for file in files {
+            events.extend(read_lines::<ProcessEvent>(&file)?);
+        }
+        events.retain(|e| since.is_none_or(|since| e.timestamp >= since));
+        // Stable, so events of the same millisecond keep their order
+        events.sort_by_key(|e| e.timestamp);
+        Ok(events)
+    }
+
+    fn record_samples(&self, samples: &[StatsSample]) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().unwrap();
+        let mut by_name: HashMap<&str, Vec<StatsSample>> = HashMap::new();
+        for sample in samples {
+            by_name
+                .entry(sample.name.as_str())
+                .or_default()
+                .push(sample.clone());
+        }
+        for (name, samples) in by_name {
+            let path = self.paths.samples_file(name);
+            append_lines(&path, &samples)?;
+
+            let mut kept: Vec<StatsSample> = read_lines(&path)?;
+            if kept.len() > SAMPLE_LIMIT {
+                kept.drain(..kept.len() - SAMPLE_LIMIT);
+                write_lines(&path, &kept)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn samples(&self, name: &str, since: Option<i64>) -> Result<Vec<StatsSample>, StorageError> {
+        let mut samples: Vec<StatsSample> = read_lines(&self.paths.samples_file(name))?;
+        samples.retain(|s| since.is_none_or(|since| s.timestamp >= since));
+        Ok(samples)
+    }
+
+    fn prune(&self, before: i64) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().unwrap();
+        for file in jsonl_files(&self.paths.history_dir())? {
+            let mut runs: Vec<RunRecord> = read_lines(&file)?;
+            let len = runs.len();
+            runs.retain(|r| r.ended_at >= before);
+            if runs.len() != len {
+                write_lines(&file, &runs)?;
+            }
+        }
+        for file in jsonl_files(&self.paths.events_dir())? {
+            let mut events: Vec<ProcessEvent> = read_lines(&file)?;
+            let len = events.len();
+            events.retain(|e| e.timestamp >= before);
+            if events.len() != len {
+                write_lines(&file, &events)?;
+            }
+        }
+        for file in jsonl_files(&self.paths.samples_dir())? {
+            let mut samples: Vec<StatsSample> = read_lines(&file)?;
+            let len = samples.len();
+            samples.retain(|s| s.timestamp >= before);
+            if samples.len() != len {
+                write_lines(&file, &samples)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn jsonl_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "jsonl") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Read every parseable line; unreadable lines are skipped.
+fn read_lines<T: serde::de::DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn append_lines<T: serde::Serialize>(path: &Path, items: &[T]) -> Result<(), StorageError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    for item in items {
+        writeln!(file, "{}", serde_json::to_string(item)?)?;
+    }
+    Ok(())
+}
+
+fn write_lines<T: serde::Serialize>(path: &Path, items: &[T]) -> Result<(), StorageError> {
+    let mut content = String::new();
+    for item in items {
+        content.push_str(&serde_json::to_string(item)?);
+        content.push('\n');
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::protocol::{EventKind, ProcessStatus, ResourceSnapshot};
+
+    pub(crate) fn record(name: &str, ended_at: i64) -> RunRecord {
+        RunRecord {
+            name: name.to_string(),
+            generation: 1,
+            started_at: ended_at - 1000,
+            ended_at,
+            status: ProcessStatus::Stopped,
+            exit_code: Some(0),
+            reason: None,
+            snapshot: ResourceSnapshot::default(),
+        }
+    }
+
+    pub(crate) fn sample(name: &str, timestamp: i64) -> StatsSample {
+        StatsSample {
+            name: name.to_string(),
+            timestamp,
+            rss_bytes: 1024,
+            cpu_time_ms: 10,
+            fd_count: 3,
+        }
+    }
+
+    pub(crate) fn event(name: &str, kind: EventKind, timestamp: i64) -> ProcessEvent {
+        ProcessEvent {
+            timestamp,
+            ..ProcessEvent::new(kind, name)
+        }
+    }
+
+    /// Behaviour every backend must share.
+    pub(crate) fn exercise_backend(storage: &dyn Storage) {
+        storage.record_run(&record("web", 2000)).unwrap();
+        storage.record_run(&record("worker", 1500)).unwrap();
+        storage.record_run(&record("web", 3000)).unwrap();
+
+        let web = storage.runs(Some("web"), None).unwrap();
+        assert_eq!(web.len(), 2);
+        assert!(web.iter().all(|r| r.name == "web"));
+
+        let all = storage.runs(None, None).unwrap();
+        let order: Vec<i64> = all.iter().map(|r| r.ended_at).collect();
+        assert_eq!(order, vec![1500, 2000, 3000]);
+
+        let recent = storage.runs(None, Some(2000)).unwrap();
+        assert_eq!(recent.len(), 2);
+
+        storage
+            .record_samples(&[sample("web", 100), sample("web", 200), sample("db", 150)])
+            .unwrap();
+        assert_eq!(storage.samples("web", None).unwrap().len(), 2);
+        assert_eq!(storage.samples("web", Some(150)).unwrap().len(), 1);
+        assert!(storage.samples("missing", None).unwrap().is_empty());
+
+        storage
+            .record_event(&event("web", EventKind::Start, 1000))
+            .unwrap();
+        storage
+            .record_event(&event("db", EventKind::Start, 1100))
+            .unwrap();
+        storage
+            .record_event(&event("web", EventKind::Exit, 2500))
+            .unwrap();
+        storage
+            .record_event(&event("web", EventKind::Restart, 2500))
+            .unwrap();
+        let web: Vec<EventKind> = storage
+            .events(Some("web"), None)
+            .unwrap()
+            .iter()
+            .map(|e| e.event)
+            .collect();
+        assert_eq!(
+            web,
+            vec![EventKind::Start, EventKind::Exit, EventKind::Restart]
+        );
+        assert_eq!(storage.events(None, None).unwrap().len(), 4);
+        assert_eq!(storage.events(None, Some(1100)).unwrap().len(), 3);
+
+        storage.prune(1800).unwrap();
+        let kept: Vec<i64> = storage
+            .runs(None, None)
+            .unwrap()
+            .iter()
+            .map(|r| r.ended_at)
+            .collect();
+        assert_eq!(kept, vec![2000, 3000]);
+        assert!(storage.samples("web", None).unwrap().is_empty());
+        assert_eq!(storage.events(None, None).unwrap().len(), 2);
+    }
+
+    /// Both backends keep only the newest `EVENT_LIMIT` events per process.
+    pub(crate) fn exercise_event_limit(storage: &dyn Storage) {
+        for i in 0..(EVENT_LIMIT as i64 + 5) {
+            storage
+                .record_event(&event("web", EventKind::Start, i))
+                .unwrap();
+        }
+        storage
+            .record_event(&event("db", EventKind::Start, 0))
+            .unwrap();
+
+        let events = storage.events(Some("web"), None).unwrap();
+        assert_eq!(events.len(), EVENT_LIMIT);
+        assert_eq!(events[0].timestamp, 5);
+        assert_eq!(storage.events(Some("db"), None).unwrap().len(), 1);
+    }
+
+    /// Both backends keep only the newest `SAMPLE_LIMIT` samples per process.
+    pub(crate) fn exercise_sample_limit(storage: &dyn Storage) {
+        let batch: Vec<StatsSample> = (0..(SAMPLE_LIMIT as i64 + 5))
+            .map(|i| sample("web", i))
+            .collect();
+        storage.record_samples(&batch).unwrap();
+        storage.record_samples(&[sample("db", 0)]).unwrap();
+
+        let samples = storage.samples("web", None).unwrap();
+        assert_eq!(samples.len(), SAMPLE_LIMIT);
+        assert_eq!(samples[0].timestamp, 5);
+        assert_eq!(storage.samples("db", None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_file_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        exercise_backend(&FileStorage::new(&paths));
+    }
+
+    #[test]
+    fn test_file_history_is_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        let storage = FileStorage::new(&paths);
+
+        for i in 0..(HISTORY_LIMIT as i64 + 5) {
+            storage.record_run(&record("web", i)).unwrap();
+        }
+
+        let runs = storage.runs(Some("web"), None).unwrap();
+        assert_eq!(runs.len(), HISTORY_LIMIT);
+        assert_eq!(runs[0].ended_at, 5);
+    }
+
+    #[test]
+    fn test_file_events_are_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        exercise_event_limit(&FileStorage::new(&paths));
+    }
+
+    #[test]
+    fn test_file_samples_are_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        exercise_sample_limit(&FileStorage::new(&paths));
+    }
+
+    #[test]
+    fn test_missing_files_are_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        let storage = FileStorage::new(&paths);
+        assert!(storage.runs(None, None).unwrap().is_empty());
+        assert!(storage.runs(Some("web"), None).unwrap().is_empty());
+        storage.prune(i64::MAX).unwrap();
+    }
+
+    #[test]
+    fn test_open_selects_the_configured_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_base(dir.path().to_path_buf());
+        let mut settings = StorageSection {
+            backend: StorageBackend::Sqlite,
+            ..StorageSection::default()
+        };
+
+        let sqlite = open(&paths, &settings).unwrap();
+        sqlite.record_run(&record("web", 1)).unwrap();
+        assert!(paths.storage_db().exists());
+        assert!(!paths.history_file("web").exists());
+
+        settings.backend = StorageBackend::Files;
+        let files = open(&paths, &settings).unwrap();
+        assert!(files.runs(None, None).unwrap().is_empty());
+        files.record_run(&record("web", 2)).unwrap();
+        assert!(paths.history_file("web").exists());
+    }
+}
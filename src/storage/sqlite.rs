@@ -0,0 +1,228 @@
+use super::{EVENT_LIMIT, SAMPLE_LIMIT, Storage, StorageError};
+use crate::protocol::{ProcessEvent, RunRecord, StatsSample};
+use rusqlite::{Connection, params};
+use std::path::Path;
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id       INTEGER PRIMARY KEY,
+    name     TEXT NOT NULL,
+    ended_at INTEGER NOT NULL,
+    record   TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_by_time ON runs (ended_at);
+CREATE INDEX IF NOT EXISTS runs_by_name ON runs (name, ended_at);
+
+CREATE TABLE IF NOT EXISTS events (
+    id        INTEGER PRIMARY KEY,
+    name      TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    record    TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_by_name ON events (name, id);
+CREATE INDEX IF NOT EXISTS events_by_time ON events (timestamp);
+
+CREATE TABLE IF NOT EXISTS samples (
+    name        TEXT NOT NULL,
+    timestamp   INTEGER NOT NULL,
+    rss_bytes   INTEGER NOT NULL,
+    cpu_time_ms INTEGER NOT NULL,
+    fd_count    INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS samples_by_name ON samples (name, timestamp);
+CREATE INDEX IF NOT EXISTS samples_by_time ON samples (timestamp);
+";
+
+/// Runs, events and samples in a single SQLite database (`pm3.db`).
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn record_run(&self, run: &RunRecord) -> Result<(), StorageError> {
+        let record = serde_json::to_string(run)?;
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO runs (name, ended_at, record) VALUES (?1, ?2, ?3)",
+            params![run.name, run.ended_at, record],
+        )?;
+        Ok(())
+    }
+
+    fn runs(&self, name: Option<&str>, since: Option<i64>) -> Result<Vec<RunRecord>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT record FROM runs
+             WHERE (?1 IS NULL OR name = ?1) AND ended_at >= ?2
+             ORDER BY ended_at, id",
+        )?;
+        let rows = stmt.query_map(params![name, since.unwrap_or(i64::MIN)], |row| {
+            row.get::<_, String>(0)
+        })?;
+        let mut runs = Vec::new();
+        for row in rows {
+            if let Ok(run) = serde_json::from_str(&row?) {
+                runs.push(run);
+            }
+        }
+        Ok(runs)
+    }
+
+    fn record_event(&self, event: &ProcessEvent) -> Result<(), StorageError> {
+        let record = serde_json::to_string(event)?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO events (name, timestamp, record) VALUES (?1, ?2, ?3)",
+            params![event.name, event.timestamp, record],
+        )?;
+        tx.execute(
+            "DELETE FROM events WHERE name = ?1 AND id NOT IN
+             (SELECT id FROM events WHERE name = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![event.name, EVENT_LIMIT as i64],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn events(
+        &self,
+        name: Option<&str>,
+        since: Option<i64>,
+    ) -> Result<Vec<ProcessEvent>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT record FROM events
+             WHERE (?1 IS NULL OR name = ?1) AND timestamp >= ?2
+             ORDER BY timestamp, id",
+        )?;
+        let rows = stmt.query_map(params![name, since.unwrap_or(i64::MIN)], |row| {
+            row.get::<_, String>(0)
+        })?;
+        let mut events = Vec::new();
+        for row in rows {
+            if let Ok(event) = serde_json::from_str(&row?) {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    fn record_samples(&self, samples: &[StatsSample]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO samples (name, timestamp, rss_bytes, cpu_time_ms, fd_count)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for s in samples {
+                stmt.execute(params![
+                    s.name,
+                    s.timestamp,
+                    s.rss_bytes as i64,
+                    s.cpu_time_ms as i64,
+                    s.fd_count
+                ])?;
+            }
+        }
+        let mut names: Vec<&str> = samples.iter().map(|s| s.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            tx.execute(
+                "DELETE FROM samples WHERE name = ?1 AND rowid NOT IN
+                 (SELECT rowid FROM samples WHERE name = ?1
+                  ORDER BY timestamp DESC, rowid DESC LIMIT ?2)",
+                params![name, SAMPLE_LIMIT as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn samples(&self, name: &str, since: Option<i64>) -> Result<Vec<StatsSample>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT name, timestamp, rss_bytes, cpu_time_ms, fd_count FROM samples
+             WHERE name = ?1 AND timestamp >= ?2
+             ORDER BY timestamp",
+        )?;
+        let rows = stmt.query_map(params![name, since.unwrap_or(i64::MIN)], |row| {
+            Ok(StatsSample {
+                name: row.get(0)?,
+                timestamp: row.get(1)?,
+                rss_bytes: row.get::<_, i64>(2)? as u64,
+                cpu_time_ms: row.get::<_, i64>(3)? as u64,
+                fd_count: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn prune(&self, before: i64) -> Result<(), StorageError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM runs WHERE ended_at < ?1", params![before])?;
+        conn.execute("DELETE FROM events WHERE timestamp < ?1", params![before])?;
+        conn.execute("DELETE FROM samples WHERE timestamp < ?1", params![before])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{
+        exercise_backend, exercise_event_limit, exercise_sample_limit, record,
+    };
+
+    #[test]
+    fn test_sqlite_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SqliteStorage::open(&dir.path().join("pm3.db")).unwrap();
+        exercise_backend(&storage);
+    }
+
+    #[test]
+    fn test_sqlite_events_are_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SqliteStorage::open(&dir.path().join("pm3.db")).unwrap();
+        exercise_event_limit(&storage);
+    }
+
+    #[test]
+    fn test_sqlite_samples_are_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SqliteStorage::open(&dir.path().join("pm3.db")).unwrap();
+        exercise_sample_limit(&storage);
+    }
+
+    #[test]
+    fn test_sqlite_persists_across_opens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pm3.db");
+        SqliteStorage::open(&path)
+            .unwrap()
+            .record_run(&record("web", 42))
+            .unwrap();
+
+        let reopened = SqliteStorage::open(&path).unwrap();
+        let runs = reopened.runs(Some("web"), None).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].ended_at, 42);
+    }
+}
@@ -0,0 +1,174 @@
+use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+use std::io;
+use std::os::fd::{BorrowedFd, FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::time::Duration;
+
+// ---------------------------------------------------------------------------
+// systemd integration
+// ---------------------------------------------------------------------------
+//
+// Under systemd the daemon can take its listening socket from a socket unit
+// instead of binding `pm3.sock` itself, tells a `Type=notify` service when it
+// is ready for requests, and sends the keepalives `WatchdogSec=` asks for.
+// Outside systemd none of the variables are set and all of this does nothing.
+
+/// The first descriptor systemd passes sockets from (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Variables systemd sets for the daemon alone, which processes and hooks
+/// must not inherit.
+pub const DAEMON_ENV: [&str; 6] = [
+    "LISTEN_PID",
+    "LISTEN_FDS",
+    "LISTEN_FDNAMES",
+    "NOTIFY_SOCKET",
+    "WATCHDOG_PID",
+    "WATCHDOG_USEC",
+];
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Whether `LISTEN_PID` and `LISTEN_FDS` pass at least one socket to `pid`.
+fn passes_sockets(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> bool {
+    listen_pid.and_then(|p| p.parse::<u32>().ok()) == Some(pid)
+        && listen_fds
+            .and_then(|n| n.parse::<u32>().ok())
+            .is_some_and(|n| n >= 1)
+}
+
+/// The socket a socket unit passed in, if the daemon was started by one.
+/// Only the first is used.
+pub fn take_listener() -> io::Result<Option<UnixListener>> {
+    let activated = passes_sockets(
+        var("LISTEN_PID").as_deref(),
+        var("LISTEN_FDS").as_deref(),
+        std::process::id(),
+    );
+    if !activated {
+        return Ok(None);
+    }
+    // SAFETY: systemd hands its sockets over from fd 3 up and nothing else
+    // in the daemon owns them
+    let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    listener
+        .local_addr()
+        .map_err(|e| io::Error::other(format!("socket passed by systemd: {e}")))?;
+    fcntl(&listener, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+/// Let the passed socket survive the exec of `pm3 update`, whose new daemon
+/// finds it where systemd put it through the same variables.
+pub fn keep_listener_across_exec() -> io::Result<()> {
+    if passes_sockets(
+        var("LISTEN_PID").as_deref(),
+        var("LISTEN_FDS").as_deref(),
+        std::process::id(),
+    ) {
+        // SAFETY: the descriptor is the daemon's listener and stays open
+        let fd = unsafe { BorrowedFd::borrow_raw(LISTEN_FDS_START) };
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+    }
+    Ok(())
+}
+
+/// Send `state` (e.g. `READY=1`) to systemd, if it is listening.
+pub fn notify(state: &str) {
+    if let Some(socket) = var("NOTIFY_SOCKET")
+        && let Err(e) = notify_to(&socket, state)
+    {
+        tracing::warn!("failed to notify systemd: {e}");
+    }
+}
+
+fn notify_to(socket: &str, state: &str) -> io::Result<()> {
+    // A leading `@` names a socket in the abstract namespace
+    let addr = match socket.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// How often to send `WATCHDOG=1`, when systemd expects it: twice per
+/// `WatchdogSec=`, as sd_watchdog_enabled(3) recommends.
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog_interval_of(
+        var("WATCHDOG_USEC").as_deref(),
+        var("WATCHDOG_PID").as_deref(),
+        std::process::id(),
+    )
+}
+
+fn watchdog_interval_of(
+    usec: Option<&str>,
+    watchdog_pid: Option<&str>,
+    pid: u32,
+) -> Option<Duration> {
+    // Without WATCHDOG_PID the watchdog is meant for whoever reads it
+    if let Some(watchdog_pid) = watchdog_pid
+        && watchdog_pid.parse::<u32>().ok() != Some(pid)
+    {
+        return None;
+    }
+    let usec = usec?.parse::<u64>().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes_sockets_only_to_the_named_pid() {
+        assert!(passes_sockets(Some("42"), Some("1"), 42));
+        assert!(!passes_sockets(Some("41"), Some("1"), 42));
+        assert!(!passes_sockets(Some("42"), Some("0"), 42));
+        assert!(!passes_sockets(None, Some("1"), 42));
+        assert!(!passes_sockets(Some("42"), None, 42));
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(
+            watchdog_interval_of(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval_of(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval_of(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(watchdog_interval_of(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval_of(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_notify_sends_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+
+        let name = format!("pm3-test-notify-{}", std::process::id());
+        let systemd =
+            UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+        notify_to(&format!("@{name}"), "WATCHDOG=1").unwrap();
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"WATCHDOG=1");
+    }
+}
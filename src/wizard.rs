@@ -0,0 +1,375 @@
+use crate::config;
+use crate::process;
+use crate::scaffold::{Scaffold, sanitize_name};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+// ---------------------------------------------------------------------------
+// Prompts
+// ---------------------------------------------------------------------------
+
+/// Line-based question and answer over any reader and writer, so the wizard
+/// can be driven by a terminal or scripted in tests.
+struct Prompter<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompter<R, W> {
+    /// Ask `question`, returning the trimmed answer or `default` when the
+    /// answer is blank.
+    fn ask(&mut self, question: &str, default: Option<&str>) -> io::Result<String> {
+        match default {
+            Some(default) if !default.is_empty() => {
+                write!(self.output, "{question} [{default}]: ")?
+            }
+            _ => write!(self.output, "{question}: ")?,
+        }
+        self.output.flush()?;
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "input ended before the wizard finished",
+            ));
+        }
+        let answer = line.trim();
+        Ok(match (answer.is_empty(), default) {
+            (true, Some(default)) => default.to_string(),
+            _ => answer.to_string(),
+        })
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        let hint = if default { "Y/n" } else { "y/N" };
+        loop {
+            let answer = self.ask(&format!("{question} ({hint})"), None)?;
+            match answer.to_ascii_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.warn("please answer y or n")?,
+            }
+        }
+    }
+
+    fn warn(&mut self, message: &str) -> io::Result<()> {
+        writeln!(self.output, "  ! {message}")
+    }
+
+    /// Ask until `parse` accepts the answer, showing its error each time.
+    fn ask_valid<T>(
+        &mut self,
+        question: &str,
+        default: Option<&str>,
+        mut parse: impl FnMut(&str) -> Result<T, String>,
+    ) -> io::Result<T> {
+        loop {
+            let answer = self.ask(question, default)?;
+            match parse(&answer) {
+                Ok(value) => return Ok(value),
+                Err(message) => self.warn(&message)?,
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Checks
+// ---------------------------------------------------------------------------
+
+/// Whether the program of `command` can be found: as a path relative to
+/// `cwd`, or by name on `PATH`.
+fn program_exists(program: &str, cwd: &Path) -> bool {
+    if program.contains('/') {
+        return cwd.join(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+fn port_is_free(port: u16) -> bool {
+    std::net::TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
+
+enum HealthAnswer {
+    None,
+    Port(u16),
+    Http(String),
+}
+
+fn parse_health(answer: &str) -> Result<HealthAnswer, String> {
+    if answer.is_empty() || answer == "none" {
+        Ok(HealthAnswer::None)
+    } else if answer.starts_with("http://") {
+        Ok(HealthAnswer::Http(answer.to_string()))
+    } else {
+        answer
+            .parse()
+            .map(HealthAnswer::Port)
+            .map_err(|_| "expected a port number, an http:// URL or blank".to_string())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Wizard
+// ---------------------------------------------------------------------------
+
+/// Walk through one or more processes on `input`, checking answers against
+/// the project in `dir`, and build the resulting `pm3.toml`.
+pub fn run(input: impl BufRead, output: impl Write, dir: &Path) -> io::Result<Scaffold> {
+    let mut prompter = Prompter { input, output };
+    let mut document = toml::Table::new();
+
+    writeln!(
+        prompter.output,
+        "Describe each process pm3 should run. Press enter to accept [defaults]."
+    )?;
+    loop {
+        writeln!(prompter.output)?;
+        let suggested = if document.is_empty() { "web" } else { "" };
+        let name = prompter.ask_valid("Process name", Some(suggested), |answer| {
+            let name = sanitize_name(answer);
+            if name.is_empty() {
+                Err("a name is required".to_string())
+            } else if document.contains_key(&name) {
+                Err(format!("`{name}` is already defined"))
+            } else {
+                Ok(name)
+            }
+        })?;
+
+        let cwd = prompter.ask_valid("Working directory (blank for here)", None, |answer| {
+            if answer.is_empty() || dir.join(answer).is_dir() {
+                Ok(answer.to_string())
+            } else {
+                Err(format!("{answer} is not a directory"))
+            }
+        })?;
+        let run_dir = dir.join(&cwd);
+
+        let command = loop {
+            let (command, program) = prompter.ask_valid("Command", None, |answer| {
+                process::parse_command(answer)
+                    .map(|(program, _)| (answer.to_string(), program))
+                    .map_err(|e| e.to_string())
+            })?;
+            if program_exists(&program, &run_dir)
+                || prompter.confirm(&format!("`{program}` was not found; keep it?"), false)?
+            {
+                break command;
+            }
+        };
+
+        let env_file = loop {
+            let env_file = prompter.ask("Env file (blank for none)", None)?;
+            if env_file.is_empty()
+                || run_dir.join(&env_file).is_file()
+                || prompter.confirm(&format!("{env_file} does not exist yet; keep it?"), false)?
+            {
+                break env_file;
+            }
+        };
+
+        let restart = prompter.ask_valid(
+            "Restart policy: on_failure, always or never",
+            Some("on_failure"),
+            |answer| match answer {
+                "on_failure" | "on-failure" => Ok("on_failure"),
+                "always" => Ok("always"),
+                "never" => Ok("never"),
+                _ => Err("expected on_failure, always or never".to_string()),
+            },
+        )?;
+        let max_restarts = if restart == "never" {
+            None
+        } else {
+            Some(
+                prompter.ask_valid("Max restarts in a crash loop", Some("10"), |answer| {
+                    answer
+                        .parse::<u32>()
+                        .map_err(|_| "expected a number".to_string())
+                })?,
+            )
+        };
+
+        let health = loop {
+            let health = prompter.ask_valid(
+                "Ready check: port, http:// URL, or blank for none",
+                None,
+                parse_health,
+            )?;
+            if let HealthAnswer::Port(port) = health
+                && !port_is_free(port)
+                && !prompter.confirm(
+                    &format!(
+                        "port {port} is in use right now, so the check would pass early; keep it?"
+                    ),
+                    false,
+                )?
+            {
+                continue;
+            }
+            break health;
+        };
+
+        let mut table = toml::Table::new();
+        table.insert("command".into(), command.into());
+        if !cwd.is_empty() {
+            table.insert("cwd".into(), cwd.into());
+        }
+        if !env_file.is_empty() {
+            table.insert("env_file".into(), env_file.into());
+        }
+        table.insert("restart".into(), restart.into());
+        if let Some(max) = max_restarts {
+            table.insert("max_restarts".into(), i64::from(max).into());
+        }
+        match health {
+            HealthAnswer::None => {}
+            HealthAnswer::Port(port) => {
+                let check = toml::Table::from_iter([("port".to_string(), i64::from(port).into())]);
+                table.insert("ready_check".into(), check.into());
+            }
+            HealthAnswer::Http(url) => {
+                let check = toml::Table::from_iter([("http".to_string(), url.clone().into())]);
+                table.insert("ready_check".into(), check.into());
+                table.insert("health_check".into(), url.into());
+            }
+        }
+        document.insert(name, table.into());
+
+        if !prompter.confirm("Add another process?", false)? {
+            break;
+        }
+    }
+
+    let body = toml::to_string(&document).map_err(io::Error::other)?;
+    let toml = format!("# Generated by `pm3 init --interactive`.\n\n{body}");
+    // The answers were validated one by one; make sure the whole file is too
+    config::parse_config(&toml).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(Scaffold {
+        template: None,
+        processes: document.keys().cloned().collect(),
+        toml,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EnvFile, ReadyCheck, RestartPolicy};
+
+    fn answer(lines: &[&str], dir: &Path) -> (io::Result<Scaffold>, String) {
+        let input = lines.join("\n") + "\n";
+        let mut output = Vec::new();
+        let result = run(input.as_bytes(), &mut output, dir);
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_wizard_builds_config() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("api")).unwrap();
+        std::fs::write(dir.path().join("api/.env"), "").unwrap();
+
+        let (result, _) = answer(
+            &[
+                "",                 // name: web
+                "api",              // cwd
+                "sh -c 'exec app'", // command
+                ".env",             // env file
+                "always",           // restart
+                "",                 // max restarts: 10
+                "http://localhost:8080/health",
+                "y", // another
+                "worker",
+                "",
+                "sleep 999",
+                "",
+                "never",
+                "", // no ready check
+                "n",
+            ],
+            dir.path(),
+        );
+        let scaffold = result.unwrap();
+        assert_eq!(scaffold.processes, vec!["web", "worker"]);
+
+        let configs = config::parse_config(&scaffold.toml).unwrap();
+        let web = &configs["web"];
+        assert_eq!(web.command, "sh -c 'exec app'");
+        assert_eq!(web.cwd.as_deref(), Some("api"));
+        assert_eq!(web.env_file, Some(EnvFile::Single(".env".to_string())));
+        assert_eq!(web.restart, Some(RestartPolicy::Always));
+        assert_eq!(web.max_restarts, Some(10));
+        assert_eq!(
+            web.ready_check,
+            Some(ReadyCheck::Http("http://localhost:8080/health".to_string()))
+        );
+        let worker = &configs["worker"];
+        assert_eq!(worker.restart, Some(RestartPolicy::Never));
+        assert_eq!(worker.max_restarts, None);
+        assert_eq!(worker.ready_check, None);
+    }
+
+    #[test]
+    fn test_wizard_reprompts_invalid_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy = listener.local_addr().unwrap().port().to_string();
+
+        let (result, output) = answer(
+            &[
+                "web",
+                "missing-dir", // rejected
+                "",
+                "definitely-not-a-program-pm3", // not found
+                "n",                            // ask again
+                "sleep 999",
+                "",
+                "sometimes", // rejected
+                "on_failure",
+                "lots", // rejected
+                "0",
+                "localhost", // rejected
+                &busy,       // in use
+                "n",
+                "",
+                "n",
+            ],
+            dir.path(),
+        );
+        let scaffold = result.unwrap();
+        assert!(
+            output.contains("missing-dir is not a directory"),
+            "{output}"
+        );
+        assert!(output.contains("was not found"), "{output}");
+        assert!(output.contains("expected on_failure"), "{output}");
+        assert!(output.contains("expected a number"), "{output}");
+        assert!(output.contains("expected a port number"), "{output}");
+        assert!(
+            output.contains(&format!("port {busy} is in use")),
+            "{output}"
+        );
+
+        let web = &config::parse_config(&scaffold.toml).unwrap()["web"];
+        assert_eq!(web.command, "sleep 999");
+        assert_eq!(web.max_restarts, Some(0));
+        assert_eq!(web.ready_check, None);
+    }
+
+    #[test]
+    fn test_wizard_fails_on_early_eof() {
+        let dir = tempfile::tempdir().unwrap();
+        let (result, _) = answer(&["web"], dir.path());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -1,8 +1,11 @@
-use pm3::config::{self, ProcessConfig, RestartPolicy};
+use pm3::config::{
+    self, Notify, NotifyEvent, OverlapPolicy, ProcessClass, ProcessConfig, ProcessKind, ReadyCheck,
+    RestartPolicy, StdinMode,
+};
 use pm3::daemon;
-use pm3::log::LOG_ROTATION_SIZE;
+use pm3::log::{LOG_ROTATION_SIZE, LogLevel, LogStream};
 use pm3::paths::Paths;
-use pm3::protocol::{self, ProcessStatus, Request, Response};
+use pm3::protocol::{self, ProcessStatus, Request, Response, ResultStatus, RunReason, StepStatus};
 use regex::Regex;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Write};
@@ -13,27 +16,7 @@ use tempfile::TempDir;
 fn test_config(command: &str) -> ProcessConfig {
     ProcessConfig {
         command: command.to_string(),
-        cwd: None,
-        env: None,
-        env_file: None,
-        health_check: None,
-        kill_timeout: None,
-        kill_signal: None,
-        max_restarts: None,
-        max_memory: None,
-        min_uptime: None,
-        stop_exit_codes: None,
-        watch: None,
-        watch_ignore: None,
-        depends_on: None,
-        restart: None,
-        group: None,
-        pre_start: None,
-        post_stop: None,
-        notify: None,
-        cron_restart: None,
-        log_date_format: None,
-        environments: HashMap::new(),
+        ..Default::default()
     }
 }
 
@@ -111,6 +94,22 @@ async fn send_raw_request(paths: &Paths, request: &Request) -> Response {
         .unwrap()
 }
 
+/// The processes a `Results` response reports with `status`, each with its
+/// detail in parentheses the way `pm3` prints them.
+fn results_of(resp: &Response, status: ResultStatus) -> Vec<String> {
+    match resp {
+        Response::Results { results, .. } => results
+            .iter()
+            .filter(|r| r.status == status)
+            .map(|r| match &r.detail {
+                Some(detail) => format!("{} ({detail})", r.name),
+                None => r.name.clone(),
+            })
+            .collect(),
+        other => panic!("expected Results, got: {other:?}"),
+    }
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn test_daemon_creates_pid_and_socket() {
     let dir = TempDir::new().unwrap();
@@ -205,12 +204,15 @@ async fn test_spawn_process_tracks_pid() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     // List and verify the process appears
@@ -259,12 +261,15 @@ async fn test_spawn_with_cwd() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     // Wait for the child to finish writing
@@ -297,12 +302,15 @@ async fn test_log_capture_stdout() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     tokio::time::sleep(Duration::from_millis(500)).await;
@@ -337,12 +345,15 @@ async fn test_log_capture_stderr() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     tokio::time::sleep(Duration::from_millis(500)).await;
@@ -377,12 +388,15 @@ async fn test_log_directory_created() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     assert!(
@@ -419,12 +433,15 @@ command = "sleep 999"
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     let list_resp = send_raw_request(&paths, &Request::List).await;
@@ -469,12 +486,15 @@ command = "sleep 999"
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     let list_resp = send_raw_request(&paths, &Request::List).await;
@@ -520,12 +540,15 @@ command = "sleep 999"
             configs,
             names: Some(vec!["web".to_string()]),
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     let list_resp = send_raw_request(&paths, &Request::List).await;
@@ -588,12 +611,15 @@ async fn test_list_process_info_fields() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -639,12 +665,15 @@ async fn test_list_multiple_processes_all_fields() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -695,6 +724,9 @@ async fn test_stop_process_handles_sigterm() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -716,12 +748,14 @@ async fn test_stop_process_handles_sigterm() {
         &paths,
         &Request::Stop {
             names: Some(vec!["sleeper".to_string()]),
+            group: None,
+            cascade: false,
         },
     )
     .await;
     assert!(
-        matches!(&stop_resp, Response::Success { .. }),
-        "expected Success, got: {stop_resp:?}"
+        results_of(&stop_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {stop_resp:?}"
     );
 
     // Verify process is dead
@@ -769,6 +803,9 @@ async fn test_stop_process_ignores_sigterm_gets_sigkill() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -794,12 +831,14 @@ async fn test_stop_process_ignores_sigterm_gets_sigkill() {
         &paths,
         &Request::Stop {
             names: Some(vec!["stubborn".to_string()]),
+            group: None,
+            cascade: false,
         },
     )
     .await;
-    assert!(
-        matches!(&stop_resp, Response::Success { .. }),
-        "expected Success, got: {stop_resp:?}"
+    assert_eq!(
+        results_of(&stop_resp, ResultStatus::Succeeded),
+        ["stubborn (force-killed)"]
     );
 
     let elapsed = start.elapsed();
@@ -848,6 +887,9 @@ async fn test_stop_custom_kill_signal_sigint() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -860,12 +902,19 @@ async fn test_stop_custom_kill_signal_sigint() {
         &paths,
         &Request::Stop {
             names: Some(vec!["sigint-handler".to_string()]),
+            group: None,
+            cascade: false,
         },
     )
     .await;
     assert!(
-        matches!(&stop_resp, Response::Success { .. }),
-        "expected Success, got: {stop_resp:?}"
+        results_of(&stop_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {stop_resp:?}"
+    );
+
+    assert_eq!(
+        results_of(&stop_resp, ResultStatus::Succeeded),
+        ["sigint-handler"]
     );
 
     // Verify marker file exists — proves SIGINT was received
@@ -896,12 +945,15 @@ async fn test_restart_preserves_process_config() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     // Get PID before restart
@@ -920,12 +972,16 @@ async fn test_restart_preserves_process_config() {
         &paths,
         &Request::Restart {
             names: Some(vec!["worker".to_string()]),
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: false,
         },
     )
     .await;
     assert!(
-        matches!(&restart_resp, Response::Success { .. }),
-        "expected Success, got: {restart_resp:?}"
+        results_of(&restart_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {restart_resp:?}"
     );
 
     // Verify: online, new PID, restarts == 1, group preserved
@@ -977,6 +1033,9 @@ command = "sleep 999"
             configs,
             names: Some(vec!["nonexistent".to_string()]),
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1014,6 +1073,9 @@ async fn test_log_shows_stdout_lines() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1027,6 +1089,13 @@ async fn test_log_shows_stdout_lines() {
             name: Some("echoer".to_string()),
             lines: 15,
             follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
         },
     )
     .await;
@@ -1074,6 +1143,9 @@ async fn test_log_lines_param_limits_output() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1086,6 +1158,13 @@ async fn test_log_lines_param_limits_output() {
             name: Some("counter".to_string()),
             lines: 5,
             follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
         },
     )
     .await;
@@ -1113,6 +1192,469 @@ async fn test_log_lines_param_limits_output() {
     let _ = handle.await;
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_grep_level_and_since_filter_in_the_daemon() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut stamped = test_config("sh -c 'echo ERROR one; echo fine; echo panic two; echo fine'");
+    stamped.log_date_format = Some("%Y-%m-%d %H:%M:%S".to_string());
+    let mut configs = HashMap::new();
+    configs.insert("stamped".to_string(), stamped);
+    configs.insert("plain".to_string(), test_config("sh -c 'echo fine'"));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let log = |name: &str, grep: Option<&str>, level: Option<LogLevel>, since: Option<i64>| {
+        Request::Log {
+            name: Some(name.to_string()),
+            lines: 1,
+            follow: false,
+            group: None,
+            timestamps: false,
+            grep: grep.map(str::to_string),
+            level,
+            since,
+            stream: None,
+            color: false,
+        }
+    };
+    let log_lines = |responses: &[Response]| -> Vec<String> {
+        responses
+            .iter()
+            .filter_map(|r| match r {
+                Response::LogLine { line, .. } => Some(line.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+
+    // --lines counts matching lines, not lines read
+    let responses =
+        send_streaming_request(&paths, &log("stamped", Some("ERROR|panic"), None, None)).await;
+    let lines = log_lines(&responses);
+    assert_eq!(lines.len(), 1, "got: {lines:?}");
+    assert!(lines[0].ends_with("panic two"), "got: {lines:?}");
+
+    // --level goes by the line's level token, dropping lines without one
+    let responses =
+        send_streaming_request(&paths, &log("stamped", None, Some(LogLevel::Error), None)).await;
+    let lines = log_lines(&responses);
+    assert_eq!(lines.len(), 1, "got: {lines:?}");
+    assert!(lines[0].ends_with("ERROR one"), "got: {lines:?}");
+
+    let hour_ago = chrono::Utc::now().timestamp_millis() - 3_600_000;
+    let in_an_hour = hour_ago + 2 * 3_600_000;
+    let recent = send_streaming_request(&paths, &log("stamped", None, None, Some(hour_ago))).await;
+    assert_eq!(log_lines(&recent).len(), 1, "got: {recent:?}");
+    let future =
+        send_streaming_request(&paths, &log("stamped", None, None, Some(in_an_hour))).await;
+    assert!(log_lines(&future).is_empty(), "got: {future:?}");
+
+    // Nothing to read times from without a log_date_format
+    let responses = send_streaming_request(&paths, &log("plain", None, None, Some(hour_ago))).await;
+    assert!(
+        matches!(&responses[..], [Response::Error { message }] if message.contains("log_date_format")),
+        "got: {responses:?}"
+    );
+    let responses = send_streaming_request(&paths, &log("plain", Some("("), None, None)).await;
+    assert!(
+        matches!(&responses[..], [Response::Error { message }] if message.contains("--grep")),
+        "got: {responses:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_stream_selection_and_merged_logs() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let cmd = "sh -c 'echo out1; echo err1 >&2; echo out2; echo err2 >&2'";
+    let mut merged = test_config(cmd);
+    merged.merge_logs = Some(true);
+    let mut configs = HashMap::new();
+    configs.insert("split".to_string(), test_config(cmd));
+    configs.insert("merged".to_string(), merged);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // One pipe, one file, in the order the lines were written
+    assert_eq!(
+        std::fs::read_to_string(paths.stdout_log("merged")).unwrap(),
+        "out1\nerr1\nout2\nerr2\n"
+    );
+    assert!(!paths.stderr_log("merged").exists());
+
+    let log = |name: &str, stream: Option<LogStream>| Request::Log {
+        name: Some(name.to_string()),
+        lines: 15,
+        follow: false,
+        group: None,
+        timestamps: false,
+        grep: None,
+        level: None,
+        since: None,
+        stream,
+        color: false,
+    };
+    let log_lines = |responses: &[Response]| -> Vec<String> {
+        responses
+            .iter()
+            .filter_map(|r| match r {
+                Response::LogLine { line, .. } => Some(line.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let err = send_streaming_request(&paths, &log("split", Some(LogStream::Stderr))).await;
+    assert_eq!(log_lines(&err), ["err1", "err2"]);
+    let out = send_streaming_request(&paths, &log("split", Some(LogStream::Stdout))).await;
+    assert_eq!(log_lines(&out), ["out1", "out2"]);
+
+    // The merged log is shown once, and has no stderr-only view
+    let both = send_streaming_request(&paths, &log("merged", None)).await;
+    assert_eq!(log_lines(&both), ["out1", "err1", "out2", "err2"]);
+    let responses = send_streaming_request(&paths, &log("merged", Some(LogStream::Stderr))).await;
+    assert!(
+        matches!(&responses[..], [Response::Error { message }] if message.contains("merge_logs")),
+        "got: {responses:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_destinations_outside_the_data_dir() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().join("data"));
+    let elsewhere = dir.path().join("var/log");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let cmd = "sh -c 'echo out1; echo err1 >&2; echo out2'";
+    let path_of = |file: &str| Some(elsewhere.join(file).display().to_string());
+    let mut split = test_config(cmd);
+    split.out_file = path_of("split.out");
+    split.error_file = path_of("split.err");
+    let mut shared = test_config(cmd);
+    shared.out_file = path_of("shared.log");
+    shared.error_file = path_of("shared.log");
+    let mut discarded = test_config(cmd);
+    discarded.log = Some(false);
+    let configs = HashMap::from([
+        ("split".to_string(), split),
+        ("shared".to_string(), shared),
+        ("discarded".to_string(), discarded),
+    ]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let read = |file: &str| std::fs::read_to_string(elsewhere.join(file)).unwrap();
+    assert_eq!(read("split.out"), "out1\nout2\n");
+    assert_eq!(read("split.err"), "err1\n");
+    // A shared file takes both streams in the order they were written
+    assert_eq!(read("shared.log"), "out1\nerr1\nout2\n");
+
+    // Nothing of these lands in the data dir, and nothing of the discarded one
+    // anywhere
+    let in_data_dir: Vec<_> = std::fs::read_dir(paths.log_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert!(in_data_dir.is_empty(), "got: {in_data_dir:?}");
+
+    // `pm3 log` reads the overrides
+    let responses = send_streaming_request(
+        &paths,
+        &Request::Log {
+            name: Some("shared".to_string()),
+            lines: 15,
+            follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
+        },
+    )
+    .await;
+    let lines: Vec<_> = responses
+        .iter()
+        .filter_map(|r| match r {
+            Response::LogLine { line, .. } => Some(line.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lines, ["out1", "err1", "out2"]);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_ansi_stripped_from_files_unless_kept() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let cmd = r"printf '\033[31mred\033[0m\n'";
+    let mut kept = test_config(cmd);
+    kept.log_strip_ansi = Some(false);
+    let configs = HashMap::from([
+        ("stripped".to_string(), test_config(cmd)),
+        ("kept".to_string(), kept),
+    ]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let colored = "\x1b[31mred\x1b[0m";
+    let read = |name: &str| std::fs::read_to_string(paths.stdout_log(name)).unwrap();
+    assert_eq!(read("stripped"), "red\n");
+    assert_eq!(read("kept"), format!("{colored}\n"));
+
+    let log = |name: &str, color: bool| Request::Log {
+        name: Some(name.to_string()),
+        lines: 15,
+        follow: false,
+        group: None,
+        timestamps: false,
+        grep: Some("^red$".to_string()),
+        level: None,
+        since: None,
+        stream: None,
+        color,
+    };
+    let line_of = |responses: Vec<Response>| match &responses[..] {
+        [Response::LogLine { line, .. }] => line.clone(),
+        other => panic!("got: {other:?}"),
+    };
+    // `--grep` sees the text without escapes; `--color` gets them back
+    assert_eq!(
+        line_of(send_streaming_request(&paths, &log("kept", false)).await),
+        "red"
+    );
+    assert_eq!(
+        line_of(send_streaming_request(&paths, &log("kept", true)).await),
+        colored
+    );
+    assert_eq!(
+        line_of(send_streaming_request(&paths, &log("stripped", true)).await),
+        "red"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+/// A collector that takes every POST on the returned port and hands over
+/// its body.
+async fn spawn_http_collector() -> (u16, tokio::sync::mpsc::UnboundedReceiver<String>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (body_tx, body_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length {
+                        let _ = body_tx.send(body.to_string());
+                        break;
+                    }
+                }
+            }
+            let _ = stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await;
+        }
+    });
+    (port, body_rx)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_ship_posts_captured_lines() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let (port, mut body_rx) = spawn_http_collector().await;
+    write_daemon_settings(
+        &paths,
+        &format!("[log_ship]\nurl = \"http://127.0.0.1:{port}/ingest\"\n"),
+    );
+    let handle = start_test_daemon(&paths).await;
+
+    let configs = HashMap::from([(
+        "web".to_string(),
+        test_config("sh -c 'echo hello; echo oops >&2; sleep 60'"),
+    )]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let mut shipped = Vec::new();
+    while shipped.len() < 2 {
+        let body = tokio::time::timeout(Duration::from_secs(5), body_rx.recv())
+            .await
+            .expect("lines were not shipped")
+            .unwrap();
+        let batch: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        shipped.extend(
+            batch
+                .into_iter()
+                .map(|l| (l["process"].clone(), l["stream"].clone(), l["line"].clone())),
+        );
+    }
+    shipped.sort_by_key(|(_, stream, _)| stream.to_string());
+    assert_eq!(
+        shipped,
+        [
+            ("web".into(), "stderr".into(), "oops".into()),
+            ("web".into(), "stdout".into(), "hello".into()),
+        ]
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_metrics_pushed_to_statsd_and_otlp() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let statsd = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (port, mut body_rx) = spawn_http_collector().await;
+    write_daemon_settings(
+        &paths,
+        &format!(
+            "[metrics]\nstatsd = \"{}\"\ndogstatsd = true\n\
+             otlp = \"http://127.0.0.1:{port}/v1/metrics\"\ninterval = \"1s\"\n",
+            statsd.local_addr().unwrap()
+        ),
+    );
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), test_config("sleep 999"))]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    // Pushes go out every second; wait for one that saw the sampled memory
+    let mut packet = String::new();
+    while !packet.contains("pm3.memory_bytes:") {
+        let mut buf = [0u8; 1500];
+        let (n, _) = tokio::time::timeout(Duration::from_secs(5), statsd.recv_from(&mut buf))
+            .await
+            .expect("no statsd packet")
+            .unwrap();
+        packet = String::from_utf8_lossy(&buf[..n]).to_string();
+    }
+    assert!(
+        packet.lines().any(|l| l == "pm3.up:1|g|#process:web"),
+        "{packet}"
+    );
+
+    let body = tokio::time::timeout(Duration::from_secs(5), body_rx.recv())
+        .await
+        .expect("no otlp push")
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let metrics = body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+        .as_array()
+        .unwrap();
+    let up = metrics.iter().find(|m| m["name"] == "pm3.up").unwrap();
+    let point = &up["gauge"]["dataPoints"][0];
+    assert_eq!(point["asDouble"], 1.0);
+    assert_eq!(point["attributes"][0]["value"]["stringValue"], "web");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn test_log_no_name_interleaves_all_processes() {
     let dir = TempDir::new().unwrap();
@@ -1132,6 +1674,9 @@ async fn test_log_no_name_interleaves_all_processes() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1144,6 +1689,13 @@ async fn test_log_no_name_interleaves_all_processes() {
             name: None,
             lines: 15,
             follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
         },
     )
     .await;
@@ -1151,7 +1703,7 @@ async fn test_log_no_name_interleaves_all_processes() {
     let log_lines: Vec<(&Option<String>, &str)> = responses
         .iter()
         .filter_map(|r| match r {
-            Response::LogLine { name, line } => Some((name, line.as_str())),
+            Response::LogLine { name, line, .. } => Some((name, line.as_str())),
             _ => None,
         })
         .collect();
@@ -1189,6 +1741,9 @@ async fn test_log_single_process_no_name_prefix() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1201,6 +1756,13 @@ async fn test_log_single_process_no_name_prefix() {
             name: Some("solo".to_string()),
             lines: 15,
             follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
         },
     )
     .await;
@@ -1208,7 +1770,7 @@ async fn test_log_single_process_no_name_prefix() {
     let log_lines: Vec<(&Option<String>, &str)> = responses
         .iter()
         .filter_map(|r| match r {
-            Response::LogLine { name, line } => Some((name, line.as_str())),
+            Response::LogLine { name, line, .. } => Some((name, line.as_str())),
             _ => None,
         })
         .collect();
@@ -1235,6 +1797,13 @@ async fn test_log_nonexistent_process_returns_error() {
             name: Some("nope".to_string()),
             lines: 15,
             follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
         },
     )
     .await;
@@ -1273,6 +1842,9 @@ async fn test_log_follow_streams_new_lines() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1292,6 +1864,13 @@ async fn test_log_follow_streams_new_lines() {
             name: Some("slow".to_string()),
             lines: 15,
             follow: true,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
         };
         let encoded = protocol::encode_request(&request).unwrap();
         stream.write_all(&encoded).unwrap();
@@ -1358,6 +1937,9 @@ async fn test_flush_empties_log_file() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1426,6 +2008,9 @@ async fn test_flush_all_empties_all_logs() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1479,6 +2064,9 @@ async fn test_flush_deletes_rotated_files() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1536,59 +2124,255 @@ async fn test_flush_deletes_rotated_files() {
 }
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn test_flush_nonexistent_process_returns_error() {
+async fn test_flush_removes_earlier_generations_and_reports_bytes_freed() {
     let dir = TempDir::new().unwrap();
     let paths = Paths::with_base(dir.path().to_path_buf());
 
     let handle = start_test_daemon(&paths).await;
 
+    // A changed config starts a new generation, with logs of its own
+    let start = |message: &str| {
+        let mut config = test_config(&format!("sh -c 'echo {message}; echo oops >&2; sleep 999'"));
+        config.log_per_generation = Some(true);
+        Request::Start {
+            configs: HashMap::from([("worker".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        }
+    };
+    send_raw_request(&paths, &start("first-run")).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    send_raw_request(&paths, &start("second-run")).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let logs: Vec<_> = std::fs::read_dir(paths.log_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("worker-gen")
+        })
+        .collect();
+    assert_eq!(logs.len(), 4, "two generations of two logs, got: {logs:?}");
+    let total: u64 = logs
+        .iter()
+        .map(|p| std::fs::metadata(p).unwrap().len())
+        .sum();
+
     let resp = send_raw_request(
         &paths,
         &Request::Flush {
-            names: Some(vec!["nope".to_string()]),
+            names: Some(vec!["worker".to_string()]),
         },
     )
     .await;
-    match &resp {
-        Response::Error { message } => {
-            assert!(
-                message.contains("not found"),
-                "error should mention 'not found', got: {message}"
-            );
-        }
-        other => panic!("expected Error, got: {other:?}"),
+    let Response::Success {
+        message: Some(message),
+    } = &resp
+    else {
+        panic!("expected Success, got: {resp:?}");
+    };
+    assert!(
+        message.contains(&format!("({total} bytes freed)")),
+        "got: {message}"
+    );
+
+    // Only the current generation's logs remain, and they are empty
+    let left: Vec<_> = logs.iter().filter(|p| p.exists()).collect();
+    assert_eq!(left.len(), 2, "got: {left:?}");
+    for path in left {
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 0);
     }
 
     send_raw_request(&paths, &Request::Kill).await;
     let _ = handle.await;
 }
 
-// ── Item 16: Log timestamp tests ────────────────────────────────────
-
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn test_log_timestamp_prefix() {
+async fn test_log_budget_prunes_oldest_logs_at_startup() {
     let dir = TempDir::new().unwrap();
     let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[logs]\ntotal_max = \"1K\"\n");
+
+    // Logs left behind by processes this daemon doesn't know
+    std::fs::create_dir_all(paths.log_dir()).unwrap();
+    let now = std::time::SystemTime::now();
+    let write = |name: &str, size: usize, age: Duration| {
+        let path = paths.log_dir().join(name);
+        std::fs::write(&path, vec![b'x'; size]).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(now - age)
+            .unwrap();
+        path
+    };
+    let older = write("gone-out.log.1", 1500, Duration::from_secs(3600));
+    let newer = write("gone-out.log", 500, Duration::from_secs(60));
 
     let handle = start_test_daemon(&paths).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
 
-    let mut config = test_config("sh -c 'echo line1; echo line2; echo line3'");
-    config.log_date_format = Some("%Y-%m-%d %H:%M:%S".to_string());
+    assert!(!older.exists());
+    assert!(newer.exists());
+    let resp = send_raw_request(&paths, &Request::DaemonStatus).await;
+    let Response::DaemonStatus { status } = resp else {
+        panic!("expected DaemonStatus, got: {resp:?}");
+    };
+    assert_eq!(status.log_budget, Some(1024));
+    assert_eq!(status.log_bytes, 500);
+    assert_eq!((status.logs_pruned, status.log_bytes_pruned), (1, 1500));
 
-    let mut configs = HashMap::new();
-    configs.insert("ts-echo".to_string(), config);
-    let start_resp = send_raw_request(
-        &paths,
-        &Request::Start {
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_prune_lists_then_removes_what_deleted_processes_left() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let start = |names: &[&str], message: &str| {
+        let mut config = test_config(&format!("sh -c 'echo {message}; sleep 999'"));
+        config.log_per_generation = Some(true);
+        Request::Start {
+            configs: names
+                .iter()
+                .map(|name| (name.to_string(), config.clone()))
+                .collect(),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        }
+    };
+
+    // A daemon that ran `gone`, saved and went away
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(&paths, &start(&["keep", "gone"], "first")).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    send_raw_request(&paths, &Request::Save).await;
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+
+    // The next one only runs `keep`, which moves on to a new generation
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(&paths, &start(&["keep"], "first")).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    send_raw_request(&paths, &start(&["keep"], "second")).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let log_names = || {
+        let mut names: Vec<String> = std::fs::read_dir(paths.log_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    let before = log_names();
+
+    let resp = send_raw_request(&paths, &Request::Prune { apply: false }).await;
+    let Response::Success {
+        message: Some(message),
+    } = &resp
+    else {
+        panic!("expected Success, got: {resp:?}");
+    };
+    assert!(message.starts_with("would prune:"), "got: {message}");
+    assert!(message.contains("gone-gen1-out.log"), "got: {message}");
+    assert!(message.contains("keep-gen1-out.log"), "got: {message}");
+    assert!(message.contains("dump entry 'gone'"), "got: {message}");
+    assert!(!message.contains("'keep'"), "got: {message}");
+    assert_eq!(log_names(), before);
+
+    let resp = send_raw_request(&paths, &Request::Prune { apply: true }).await;
+    assert!(
+        matches!(&resp, Response::Success { message: Some(m) } if m.starts_with("pruned:")),
+        "got: {resp:?}"
+    );
+    // Only the current generation's logs are left, and only `keep` is saved
+    assert_eq!(log_names(), ["keep-gen2-err.log", "keep-gen2-out.log"]);
+    let saved = pm3::dump::read(&paths.dump_file()).unwrap();
+    let saved: Vec<_> = saved.processes.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(saved, ["keep"]);
+
+    let resp = send_raw_request(&paths, &Request::Prune { apply: false }).await;
+    assert_eq!(
+        resp,
+        Response::Success {
+            message: Some("nothing to prune".to_string())
+        }
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_flush_nonexistent_process_returns_error() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Flush {
+            names: Some(vec!["nope".to_string()]),
+        },
+    )
+    .await;
+    match &resp {
+        Response::Error { message } => {
+            assert!(
+                message.contains("not found"),
+                "error should mention 'not found', got: {message}"
+            );
+        }
+        other => panic!("expected Error, got: {other:?}"),
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Item 16: Log timestamp tests ────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_timestamp_prefix() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'echo line1; echo line2; echo line3'");
+    config.log_date_format = Some("%Y-%m-%d %H:%M:%S".to_string());
+
+    let mut configs = HashMap::new();
+    configs.insert("ts-echo".to_string(), config);
+    let start_resp = send_raw_request(
+        &paths,
+        &Request::Start {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     tokio::time::sleep(Duration::from_millis(500)).await;
@@ -1634,12 +2418,15 @@ async fn test_log_no_timestamp_without_format() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     tokio::time::sleep(Duration::from_millis(500)).await;
@@ -1681,12 +2468,15 @@ async fn test_log_timestamp_stderr() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
     assert!(
-        matches!(&start_resp, Response::Success { .. }),
-        "expected Success, got: {start_resp:?}"
+        results_of(&start_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {start_resp:?}"
     );
 
     tokio::time::sleep(Duration::from_millis(500)).await;
@@ -1711,6 +2501,190 @@ async fn test_log_timestamp_stderr() {
     let _ = handle.await;
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_follow_tails_lines_then_survives_flush_and_restart() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut configs = HashMap::new();
+    configs.insert(
+        "chatty".to_string(),
+        test_config("sh -c 'echo one; echo two; echo three; sleep 0.5; echo tick; sleep 60'"),
+    );
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let paths_clone = paths.clone();
+    let follow_handle = tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(paths_clone.socket_file()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(3)))
+            .unwrap();
+        let request = Request::Log {
+            name: Some("chatty".to_string()),
+            lines: 2,
+            follow: true,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
+        };
+        stream
+            .write_all(&protocol::encode_request(&request).unwrap())
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let mut lines = Vec::new();
+        for line_result in BufReader::new(stream).lines() {
+            match line_result {
+                Ok(line) if !line.is_empty() => {
+                    if let Response::LogLine { line, .. } =
+                        protocol::decode_response(&line).unwrap()
+                    {
+                        lines.push(line);
+                    }
+                }
+                _ => break,
+            }
+        }
+        lines
+    });
+
+    // Truncate the log under the follower, then restart into a fresh run
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    send_raw_request(
+        &paths,
+        &Request::Flush {
+            names: Some(vec!["chatty".to_string()]),
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    send_raw_request(
+        &paths,
+        &Request::Restart {
+            names: Some(vec!["chatty".to_string()]),
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: false,
+        },
+    )
+    .await;
+
+    let lines = follow_handle.await.unwrap();
+    assert_eq!(lines[..2], ["two", "three"], "got: {lines:?}");
+    assert_eq!(lines[2], "tick", "got: {lines:?}");
+    assert!(
+        lines[3..].iter().any(|l| l == "one"),
+        "follow should carry on into the restarted run, got: {lines:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_follow_timestamps_skip_stamped_processes() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let cmd = "sh -c 'sleep 0.5; echo tick; sleep 0.3; echo tock'";
+    let mut stamped = test_config(cmd);
+    stamped.log_date_format = Some("%Y-%m-%d %H:%M:%S".to_string());
+    let mut configs = HashMap::new();
+    configs.insert("plain".to_string(), test_config(cmd));
+    configs.insert("stamped".to_string(), stamped);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let paths_clone = paths.clone();
+    let responses = tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(paths_clone.socket_file()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let request = Request::Log {
+            name: None,
+            lines: 15,
+            follow: true,
+            group: None,
+            timestamps: true,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
+        };
+        stream
+            .write_all(&protocol::encode_request(&request).unwrap())
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let mut responses = Vec::new();
+        for line_result in BufReader::new(stream).lines() {
+            match line_result {
+                Ok(line) if !line.is_empty() => {
+                    responses.push(protocol::decode_response(&line).unwrap());
+                }
+                _ => break,
+            }
+        }
+        responses
+    })
+    .await
+    .unwrap();
+
+    let tock_of = |target: &str| {
+        responses
+            .iter()
+            .find_map(|r| match r {
+                Response::LogLine {
+                    name: Some(name),
+                    line,
+                    timestamp,
+                    ..
+                } if name == target && line.ends_with("tock") => Some(*timestamp),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no followed line for {target}: {responses:?}"))
+    };
+    // Only lines the writer didn't already stamp carry a capture time
+    assert!(tock_of("plain").is_some());
+    assert_eq!(tock_of("stamped"), None);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
 // ── Item 17: Log rotation ───────────────────────────────────────────
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -1732,6 +2706,9 @@ async fn test_log_rotation_creates_rotated_file() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1768,6 +2745,9 @@ async fn test_log_rotation_only_keeps_three_files() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1780,6 +2760,8 @@ async fn test_log_rotation_only_keeps_three_files() {
         &paths,
         &Request::Stop {
             names: Some(vec!["rotator".to_string()]),
+            group: None,
+            cascade: false,
         },
     )
     .await;
@@ -1805,12 +2787,16 @@ async fn test_log_rotation_only_keeps_three_files() {
         &paths,
         &Request::Restart {
             names: Some(vec!["rotator".to_string()]),
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: false,
         },
     )
     .await;
     assert!(
-        matches!(&restart_resp, Response::Success { .. }),
-        "expected Success, got: {restart_resp:?}"
+        results_of(&restart_resp, ResultStatus::Failed).is_empty(),
+        "expected no failures, got: {restart_resp:?}"
     );
 
     // 8. Wait for rotation to happen
@@ -1852,6 +2838,9 @@ async fn test_restart_policy_never_does_not_restart() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1863,7 +2852,7 @@ async fn test_restart_policy_never_does_not_restart() {
         Response::ProcessList { processes } => {
             assert_eq!(processes.len(), 1);
             let info = &processes[0];
-            assert_eq!(info.status, ProcessStatus::Errored);
+            assert_eq!(info.status, ProcessStatus::Crashed);
             assert_eq!(info.restarts, 0);
             assert!(info.pid.is_none(), "pid should be None after exit");
         }
@@ -1893,6 +2882,9 @@ async fn test_restart_policy_always_restarts_on_clean_exit() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1933,6 +2925,9 @@ async fn test_restart_policy_on_failure_exit_zero_not_restarted() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -1974,6 +2969,9 @@ async fn test_restart_policy_on_failure_exit_nonzero_restarts() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -2015,6 +3013,9 @@ async fn test_restart_policy_stop_exit_codes_prevents_restart() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -2026,7 +3027,7 @@ async fn test_restart_policy_stop_exit_codes_prevents_restart() {
         Response::ProcessList { processes } => {
             assert_eq!(processes.len(), 1);
             let info = &processes[0];
-            assert_eq!(info.status, ProcessStatus::Errored);
+            assert_eq!(info.status, ProcessStatus::Stopped);
             assert_eq!(info.restarts, 0);
             assert!(info.pid.is_none(), "pid should be None after exit");
         }
@@ -2037,46 +3038,42 @@ async fn test_restart_policy_stop_exit_codes_prevents_restart() {
     let _ = handle.await;
 }
 
-// ── Item 19: Auto-restart ───────────────────────────────────────────
-
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn test_auto_restart_recovers_after_crash() {
+async fn test_restart_policy_always_honors_stop_exit_codes() {
     let dir = TempDir::new().unwrap();
     let paths = Paths::with_base(dir.path().to_path_buf());
 
     let handle = start_test_daemon(&paths).await;
 
-    let marker = dir.path().join("marker");
-    let cmd = format!(
-        "sh -c 'MARKER={}; if [ ! -f $MARKER ]; then touch $MARKER; exit 1; fi; sleep 999'",
-        marker.display()
-    );
-    let mut config = test_config(&cmd);
-    config.restart = Some(RestartPolicy::OnFailure);
-    config.max_restarts = Some(5);
+    let mut config = test_config("sh -c 'exit 7'");
+    config.restart = Some(RestartPolicy::Always);
+    config.stop_exit_codes = Some(vec![7]);
 
     let mut configs = HashMap::new();
-    configs.insert("crash-once".to_string(), config);
+    configs.insert("batch-done".to_string(), config);
     send_raw_request(
         &paths,
         &Request::Start {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
 
-    tokio::time::sleep(Duration::from_millis(1000)).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
 
     let list_resp = send_raw_request(&paths, &Request::List).await;
     match &list_resp {
         Response::ProcessList { processes } => {
             assert_eq!(processes.len(), 1);
             let info = &processes[0];
-            assert_eq!(info.status, ProcessStatus::Online);
-            assert_eq!(info.restarts, 1);
-            assert!(info.pid.is_some(), "pid should be present after recovery");
+            assert_eq!(info.status, ProcessStatus::Stopped);
+            assert_eq!(info.restarts, 0);
+            assert!(info.pid.is_none(), "pid should be None after exit");
         }
         other => panic!("expected ProcessList, got: {other:?}"),
     }
@@ -2085,39 +3082,52 @@ async fn test_auto_restart_recovers_after_crash() {
     let _ = handle.await;
 }
 
+// ── Item 19: Auto-restart ───────────────────────────────────────────
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn test_auto_restart_stops_after_max_restarts() {
+async fn test_external_kill_detected_as_crash() {
     let dir = TempDir::new().unwrap();
     let paths = Paths::with_base(dir.path().to_path_buf());
 
     let handle = start_test_daemon(&paths).await;
 
-    let mut config = test_config("sh -c 'exit 1'");
-    config.restart = Some(RestartPolicy::OnFailure);
-    config.max_restarts = Some(3);
+    let mut config = test_config("sleep 999");
+    config.restart = Some(RestartPolicy::Never);
 
     let mut configs = HashMap::new();
-    configs.insert("max-restart".to_string(), config);
+    configs.insert("victim".to_string(), config);
     send_raw_request(
         &paths,
         &Request::Start {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
 
-    tokio::time::sleep(Duration::from_millis(2000)).await;
+    let pid = match send_raw_request(&paths, &Request::List).await {
+        Response::ProcessList { processes } => processes[0].pid.unwrap(),
+        other => panic!("expected ProcessList, got: {other:?}"),
+    };
 
-    let list_resp = send_raw_request(&paths, &Request::List).await;
-    match &list_resp {
+    // Kill the child behind the daemon's back
+    nix::sys::signal::kill(
+        nix::unistd::Pid::from_raw(pid as i32),
+        nix::sys::signal::Signal::SIGKILL,
+    )
+    .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    match send_raw_request(&paths, &Request::List).await {
         Response::ProcessList { processes } => {
-            assert_eq!(processes.len(), 1);
             let info = &processes[0];
-            assert_eq!(info.status, ProcessStatus::Errored);
-            assert_eq!(info.restarts, 3);
-            assert!(info.pid.is_none(), "pid should be None after max restarts");
+            assert_eq!(info.status, ProcessStatus::Crashed);
+            assert!(info.pid.is_none(), "dead pid should be cleared");
         }
         other => panic!("expected ProcessList, got: {other:?}"),
     }
@@ -2127,37 +3137,133 @@ async fn test_auto_restart_stops_after_max_restarts() {
 }
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn test_auto_restart_list_shows_restart_count() {
+async fn test_auto_restart_recovers_after_crash() {
     let dir = TempDir::new().unwrap();
     let paths = Paths::with_base(dir.path().to_path_buf());
 
     let handle = start_test_daemon(&paths).await;
 
-    let stable_config = test_config("sleep 999");
-
-    let mut crasher_config = test_config("sh -c 'exit 1'");
-    crasher_config.restart = Some(RestartPolicy::OnFailure);
-    crasher_config.max_restarts = Some(2);
+    let marker = dir.path().join("marker");
+    let cmd = format!(
+        "sh -c 'MARKER={}; if [ ! -f $MARKER ]; then touch $MARKER; exit 1; fi; sleep 999'",
+        marker.display()
+    );
+    let mut config = test_config(&cmd);
+    config.restart = Some(RestartPolicy::OnFailure);
+    config.max_restarts = Some(5);
 
     let mut configs = HashMap::new();
-    configs.insert("stable".to_string(), stable_config);
-    configs.insert("crasher".to_string(), crasher_config);
+    configs.insert("crash-once".to_string(), config);
     send_raw_request(
         &paths,
         &Request::Start {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
 
-    tokio::time::sleep(Duration::from_millis(1500)).await;
+    tokio::time::sleep(Duration::from_millis(1000)).await;
 
     let list_resp = send_raw_request(&paths, &Request::List).await;
     match &list_resp {
         Response::ProcessList { processes } => {
-            assert_eq!(processes.len(), 2);
+            assert_eq!(processes.len(), 1);
+            let info = &processes[0];
+            assert_eq!(info.status, ProcessStatus::Online);
+            assert_eq!(info.restarts, 1);
+            assert!(info.pid.is_some(), "pid should be present after recovery");
+        }
+        other => panic!("expected ProcessList, got: {other:?}"),
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_auto_restart_stops_after_max_restarts() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 1'");
+    config.restart = Some(RestartPolicy::OnFailure);
+    config.max_restarts = Some(3);
+
+    let mut configs = HashMap::new();
+    configs.insert("max-restart".to_string(), config);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(2000)).await;
+
+    let list_resp = send_raw_request(&paths, &Request::List).await;
+    match &list_resp {
+        Response::ProcessList { processes } => {
+            assert_eq!(processes.len(), 1);
+            let info = &processes[0];
+            assert_eq!(info.status, ProcessStatus::Errored);
+            assert_eq!(info.restarts, 3);
+            assert!(info.pid.is_none(), "pid should be None after max restarts");
+        }
+        other => panic!("expected ProcessList, got: {other:?}"),
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_auto_restart_list_shows_restart_count() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let stable_config = test_config("sleep 999");
+
+    let mut crasher_config = test_config("sh -c 'exit 1'");
+    crasher_config.restart = Some(RestartPolicy::OnFailure);
+    crasher_config.max_restarts = Some(2);
+
+    let mut configs = HashMap::new();
+    configs.insert("stable".to_string(), stable_config);
+    configs.insert("crasher".to_string(), crasher_config);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let list_resp = send_raw_request(&paths, &Request::List).await;
+    match &list_resp {
+        Response::ProcessList { processes } => {
+            assert_eq!(processes.len(), 2);
             let stable = processes.iter().find(|p| p.name == "stable").unwrap();
             let crasher = processes.iter().find(|p| p.name == "crasher").unwrap();
 
@@ -2197,6 +3303,9 @@ async fn test_exponential_backoff_increases_delay_between_restarts() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -2227,6 +3336,91 @@ async fn test_exponential_backoff_increases_delay_between_restarts() {
     let _ = handle.await;
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_restart_window_spaces_out_restarts() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 1'");
+    config.restart = Some(RestartPolicy::Always);
+    config.min_uptime = Some(3_600_000);
+    config.restart_window = Some("2s".to_string());
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("flappy".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    // The first restart is not throttled; the second waits out the window
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let info = info_of(&paths, "flappy").await;
+    assert_eq!(info.restarts, 1);
+    assert!(
+        info.restart_throttle_ms.is_some_and(|ms| ms <= 2000),
+        "{info:?}"
+    );
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(info_of(&paths, "flappy").await.restarts, 2);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_backoff_shows_starting_while_waiting() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 1'");
+    config.restart = Some(RestartPolicy::OnFailure);
+    config.max_restarts = Some(10);
+
+    let mut configs = HashMap::new();
+    configs.insert("crash-loop".to_string(), config);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    // Backoffs: 100 + 200 + 400 + 800 = 1.5s, then a 1.6s wait until ~3.1s
+    tokio::time::sleep(Duration::from_millis(2200)).await;
+
+    let list_resp = send_raw_request(&paths, &Request::List).await;
+    match &list_resp {
+        Response::ProcessList { processes } => {
+            let info = &processes[0];
+            assert_eq!(info.status, ProcessStatus::Starting);
+            assert!(info.pid.is_none(), "no pid while waiting to restart");
+            assert_eq!(info.restarts, 4);
+        }
+        other => panic!("expected ProcessList, got: {other:?}"),
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
 // ── Item 21: min_uptime ─────────────────────────────────────────────
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -2265,6 +3459,9 @@ async fn test_min_uptime_stable_run_resets_restart_count() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -2310,6 +3507,9 @@ async fn test_min_uptime_quick_crash_increments_restart_count() {
             configs,
             names: None,
             env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
         },
     )
     .await;
@@ -2331,3 +3531,4912 @@ async fn test_min_uptime_quick_crash_increments_restart_count() {
     send_raw_request(&paths, &Request::Kill).await;
     let _ = handle.await;
 }
+
+// ── Process generations ─────────────────────────────────────────────
+
+fn list_one(response: &Response, name: &str) -> pm3::protocol::ProcessInfo {
+    match response {
+        Response::ProcessList { processes } => processes
+            .iter()
+            .find(|p| p.name == name)
+            .unwrap_or_else(|| panic!("process '{name}' not in list"))
+            .clone(),
+        other => panic!("expected ProcessList, got: {other:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_generation_bumps_only_when_config_changes() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let start = |command: &str| Request::Start {
+        configs: HashMap::from([("web".to_string(), test_config(command))]),
+        names: None,
+        env: None,
+        strict: false,
+        stagger: None,
+        progress: false,
+    };
+
+    send_raw_request(&paths, &start("sleep 999")).await;
+    let first = list_one(&send_raw_request(&paths, &Request::List).await, "web");
+    assert_eq!(first.generation, 1);
+
+    // Same config again — nothing happens
+    let resp = send_raw_request(&paths, &start("sleep 999")).await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Skipped),
+        ["web (already running)"]
+    );
+    let same = list_one(&send_raw_request(&paths, &Request::List).await, "web");
+    assert_eq!(same.generation, 1);
+    assert_eq!(same.pid, first.pid);
+
+    // Changed config — the process is replaced with a new generation
+    let resp = send_raw_request(&paths, &start("sleep 998")).await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["web (generation 2)"]
+    );
+    let second = list_one(&send_raw_request(&paths, &Request::List).await, "web");
+    assert_eq!(second.generation, 2);
+    assert_eq!(second.status, ProcessStatus::Online);
+    assert_ne!(second.pid, first.pid);
+
+    // A plain restart keeps the generation
+    send_raw_request(
+        &paths,
+        &Request::Restart {
+            names: Some(vec!["web".to_string()]),
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: false,
+        },
+    )
+    .await;
+    let restarted = list_one(&send_raw_request(&paths, &Request::List).await, "web");
+    assert_eq!(restarted.generation, 2);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_per_generation_writes_separate_files() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let start = |message: &str| {
+        let mut config = test_config(&format!("sh -c 'echo {message}; sleep 999'"));
+        config.log_per_generation = Some(true);
+        Request::Start {
+            configs: HashMap::from([("gen-logs".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        }
+    };
+
+    send_raw_request(&paths, &start("first-run")).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    send_raw_request(&paths, &start("second-run")).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let gen1 = std::fs::read_to_string(paths.generation_stdout_log("gen-logs", 1)).unwrap();
+    let gen2 = std::fs::read_to_string(paths.generation_stdout_log("gen-logs", 2)).unwrap();
+    assert!(gen1.contains("first-run") && !gen1.contains("second-run"));
+    assert!(gen2.contains("second-run") && !gen2.contains("first-run"));
+    assert!(!paths.stdout_log("gen-logs").exists());
+
+    // `pm3 log` reads the current generation
+    let responses = send_streaming_request(
+        &paths,
+        &Request::Log {
+            name: Some("gen-logs".to_string()),
+            lines: 10,
+            follow: false,
+            group: None,
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
+        },
+    )
+    .await;
+    let lines: Vec<_> = responses
+        .iter()
+        .filter_map(|r| match r {
+            Response::LogLine { line, .. } => Some(line.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lines, vec!["second-run"]);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_list_keeps_last_exit_across_restarts() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut flaky = test_config("sh -c 'sleep 0.3; exit 3'");
+    flaky.restart = Some(RestartPolicy::OnFailure);
+    let mut killed = test_config("sh -c 'kill -9 $$'");
+    killed.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("flaky".to_string(), flaky), ("killed".to_string(), killed)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let mut info = list_one(&send_raw_request(&paths, &Request::List).await, "flaky");
+    for _ in 0..50 {
+        if info.restarts >= 1 && info.pid.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        info = list_one(&send_raw_request(&paths, &Request::List).await, "flaky");
+    }
+    // Running again, but the crash that caused the restart is still shown
+    assert!(info.pid.is_some(), "info: {info:?}");
+    assert_eq!(info.exit_code, None);
+    assert_eq!(info.last_exit_code, Some(3));
+    assert!(info.exited_at.is_some());
+
+    wait_for_status(&paths, "killed", ProcessStatus::Crashed).await;
+    let info = list_one(&send_raw_request(&paths, &Request::List).await, "killed");
+    assert_eq!(info.last_exit_code, None);
+    assert_eq!(info.last_exit_signal.as_deref(), Some("SIGKILL"));
+    assert!(info.exited_at.is_some());
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Daemon auto_exit ────────────────────────────────────────────────
+
+fn write_daemon_settings(paths: &Paths, content: &str) {
+    std::fs::create_dir_all(paths.data_dir()).unwrap();
+    std::fs::write(paths.settings_file(), content).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_auto_exit_when_idle() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[daemon]\nauto_exit = \"500ms\"\n");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let result = tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("idle daemon should exit on its own");
+    assert!(result.unwrap().is_ok());
+    assert!(!paths.socket_file().exists());
+    assert!(!paths.pid_file().exists());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_auto_exit_waits_for_running_processes() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[daemon]\nauto_exit = \"500ms\"\n");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let configs = HashMap::from([("busy".to_string(), test_config("sleep 999"))]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(
+        !handle.is_finished(),
+        "daemon exited with a running process"
+    );
+
+    // Once the process is stopped the idle timer starts counting
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["busy".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+
+    let result = tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("daemon should exit after its processes stop");
+    assert!(result.unwrap().is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_idle_timeout_waits_for_scheduled_jobs() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[daemon]\nidle_timeout = \"500ms\"\n");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let configs =
+        pm3::config::parse_config("[jobs.yearly]\nschedule = \"@yearly\"\ncommand = \"true\"\n")
+            .unwrap();
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(
+        !handle.is_finished(),
+        "daemon exited with a job waiting on its schedule"
+    );
+
+    // Pausing the job leaves nothing to wait for
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["yearly".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+
+    let result = tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("daemon should exit once the job is paused");
+    assert!(result.unwrap().is_ok());
+    assert!(!paths.socket_file().exists());
+    assert!(!paths.pid_file().exists());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_invalid_daemon_settings_fail_startup() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[daemon]\nauto_exit = \"whenever\"\n");
+
+    let err = daemon::run(paths.clone()).await.unwrap_err();
+    assert!(err.to_string().contains("invalid duration"), "{err}");
+    assert!(!paths.socket_file().exists());
+}
+
+// ── max_memory enforcement ──────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_max_memory_restarts_process() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    // Any real process exceeds a 1K limit
+    let mut config = test_config("sleep 999");
+    config.max_memory = Some("1K".to_string());
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("hog".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let first_pid = list_one(&send_raw_request(&paths, &Request::List).await, "hog").pid;
+
+    let mut info = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let current = list_one(&send_raw_request(&paths, &Request::List).await, "hog");
+        if current.memory_restarts > 0 {
+            info = Some(current);
+            break;
+        }
+    }
+    let info = info.expect("process was never restarted for memory");
+    assert!(info.restarts >= 1);
+    assert_ne!(info.pid, first_pid);
+
+    let log = std::fs::read_to_string(paths.stderr_log("hog")).unwrap();
+    assert!(log.contains("[pm3] memory limit exceeded"), "log: {log}");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_fd_limit_restarts_process() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    // stdin, stdout and stderr alone are over a limit of 2
+    let mut config = test_config("sleep 999");
+    config.fd_limit = Some(2);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("leaky".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let first_pid = list_one(&send_raw_request(&paths, &Request::List).await, "leaky").pid;
+
+    let mut info = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let current = list_one(&send_raw_request(&paths, &Request::List).await, "leaky");
+        if current.restarts > 0 && current.pid.is_some() {
+            info = Some(current);
+            break;
+        }
+    }
+    let info = info.expect("process was never restarted for its fds");
+    assert_ne!(info.pid, first_pid);
+    assert_eq!(info.memory_restarts, 0);
+
+    let events = process_events(&paths, "leaky").await;
+    let over = events
+        .iter()
+        .find(|event| event.event == protocol::EventKind::FdLimit)
+        .expect("no fd_limit event");
+    assert_eq!(over.pid, first_pid);
+    assert!(over.detail.as_deref().unwrap().ends_with("> 2"));
+
+    let log = std::fs::read_to_string(paths.stderr_log("leaky")).unwrap();
+    assert!(log.contains("[pm3] fd limit exceeded"), "log: {log}");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_memory_alert_records_event_and_restarts() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.alert = Some(
+        toml::from_str(r#"memory = { above = "1K", for = "1s", action = "restart" }"#).unwrap(),
+    );
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("hog".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let first_pid = list_one(&send_raw_request(&paths, &Request::List).await, "hog").pid;
+
+    let mut info = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let current = list_one(&send_raw_request(&paths, &Request::List).await, "hog");
+        if current.restarts > 0 && current.pid.is_some() {
+            info = Some(current);
+            break;
+        }
+    }
+    let info = info.expect("process was never restarted for its alert");
+    assert_ne!(info.pid, first_pid);
+    assert_eq!(info.memory_restarts, 0);
+
+    let events = process_events(&paths, "hog").await;
+    let alert = events
+        .iter()
+        .find(|event| event.event == protocol::EventKind::Alert)
+        .expect("no alert event");
+    assert_eq!(alert.pid, first_pid);
+    let detail = alert.detail.as_deref().unwrap();
+    assert!(
+        detail.starts_with("memory ") && detail.ends_with("> 1K for 1s"),
+        "{detail}"
+    );
+
+    let log = std::fs::read_to_string(paths.stderr_log("hog")).unwrap();
+    assert!(log.contains("[pm3] alert: memory"), "log: {log}");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_memory_sampled_without_limit() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("calm".to_string(), test_config("sleep 999"))]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let info = list_one(&send_raw_request(&paths, &Request::List).await, "calm");
+    assert!(info.memory_bytes.unwrap_or(0) > 0);
+    assert_eq!(info.memory_restarts, 0);
+    assert!(info.fd_count.unwrap_or(0) > 0);
+    assert_eq!(info.thread_count, Some(1));
+    assert_eq!(info.process_count, Some(1));
+    assert_eq!(info.restarts, 0);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Run history ─────────────────────────────────────────────────────
+
+async fn history_runs(paths: &Paths, name: &str) -> Vec<pm3::protocol::RunRecord> {
+    match send_raw_request(
+        paths,
+        &Request::History {
+            name: Some(name.to_string()),
+            since: None,
+        },
+    )
+    .await
+    {
+        Response::History { runs } => runs,
+        other => panic!("expected History, got: {other:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_history_records_crash_with_snapshot() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'echo boom; exit 3'");
+    config.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("flaky".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let mut runs = Vec::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        runs = history_runs(&paths, "flaky").await;
+        if !runs.is_empty() {
+            break;
+        }
+    }
+    assert_eq!(runs.len(), 1, "runs: {runs:?}");
+    let run = &runs[0];
+    assert_eq!(run.status, ProcessStatus::Crashed);
+    assert_eq!(run.exit_code, Some(3));
+    assert_eq!(run.generation, 1);
+    assert_eq!(run.snapshot.log_bytes, "boom\n".len() as u64);
+    assert!(run.ended_at >= run.started_at);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_history_records_errored_once_max_restarts_is_used_up() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 2'");
+    config.restart = Some(RestartPolicy::OnFailure);
+    config.max_restarts = Some(2);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("flaky".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    assert_eq!(
+        wait_for_status(&paths, "flaky", ProcessStatus::Errored).await,
+        ProcessStatus::Errored
+    );
+    // The run that used up max_restarts is on record by the time it shows
+    let runs = history_runs(&paths, "flaky").await;
+    let statuses: Vec<ProcessStatus> = runs.iter().map(|run| run.status).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ProcessStatus::Crashed,
+            ProcessStatus::Crashed,
+            ProcessStatus::Errored
+        ]
+    );
+    assert!(runs.iter().all(|run| run.exit_code == Some(2)));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_timeout_kills_run_and_counts_as_failure() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    // Exits cleanly on SIGINT, but a timed-out run is still a failure
+    let mut config = test_config("sh -c 'trap \"exit 0\" INT; while true; do sleep 0.1; done'");
+    config.timeout = Some("500ms".to_string());
+    config.timeout_signal = Some("SIGINT".to_string());
+    config.max_restarts = Some(1);
+    config.min_uptime = Some(60_000);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("job".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    assert_eq!(
+        wait_for_status(&paths, "job", ProcessStatus::Errored).await,
+        ProcessStatus::Errored
+    );
+    let detail = info_of(&paths, "job").await;
+    assert_eq!(detail.restarts, 1);
+    assert_eq!(
+        detail.last_restart.as_deref(),
+        Some("timed out after 500ms")
+    );
+    assert_eq!(detail.exit_code, Some(0));
+
+    let runs = history_runs(&paths, "job").await;
+    assert_eq!(runs.len(), 2, "runs: {runs:?}");
+    for run in &runs {
+        assert_eq!(run.reason, Some(RunReason::Timeout));
+        assert!(run.snapshot.runtime_ms >= 500);
+    }
+    assert_eq!(runs[0].status, ProcessStatus::Crashed);
+    assert_eq!(runs[1].status, ProcessStatus::Errored);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_output_patterns_decide_run_result() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    // Exits 0 but reports an error, so it is retried and then gives up
+    let mut legacy = test_config("sh -c 'echo working; echo ERROR: disk full >&2'");
+    legacy.failure_pattern = Some("^ERROR".to_string());
+    legacy.max_restarts = Some(1);
+    legacy.min_uptime = Some(60_000);
+    let mut done = test_config("sh -c 'echo Backup complete'");
+    done.success_pattern = Some("^Backup complete$".to_string());
+    let mut silent = test_config("true");
+    silent.success_pattern = Some("^Backup complete$".to_string());
+    silent.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([
+                ("legacy".to_string(), legacy),
+                ("done".to_string(), done),
+                ("silent".to_string(), silent),
+            ]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    assert_eq!(
+        wait_for_status(&paths, "legacy", ProcessStatus::Errored).await,
+        ProcessStatus::Errored
+    );
+    let detail = info_of(&paths, "legacy").await;
+    assert_eq!(detail.restarts, 1);
+    assert_eq!(
+        detail.last_restart.as_deref(),
+        Some("printed failure output")
+    );
+    let runs = history_runs(&paths, "legacy").await;
+    assert_eq!(runs.len(), 2, "runs: {runs:?}");
+    for run in &runs {
+        assert_eq!(run.exit_code, Some(0));
+        assert_eq!(run.reason, Some(RunReason::FailureOutput));
+    }
+    assert_eq!(runs[0].status, ProcessStatus::Crashed);
+    assert_eq!(runs[1].status, ProcessStatus::Errored);
+
+    assert_eq!(
+        wait_for_status(&paths, "silent", ProcessStatus::Crashed).await,
+        ProcessStatus::Crashed
+    );
+    let runs = history_runs(&paths, "silent").await;
+    assert_eq!(runs.len(), 1, "runs: {runs:?}");
+    assert_eq!(runs[0].reason, Some(RunReason::MissingSuccessOutput));
+
+    assert_eq!(
+        wait_for_status(&paths, "done", ProcessStatus::Stopped).await,
+        ProcessStatus::Stopped
+    );
+    let runs = history_runs(&paths, "done").await;
+    assert_eq!(runs.len(), 1, "runs: {runs:?}");
+    assert_eq!(runs[0].status, ProcessStatus::Stopped);
+    assert_eq!(runs[0].reason, None);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cron_restart_starts_scheduled_runs_until_stopped() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("true");
+    config.restart = Some(RestartPolicy::Never);
+    config.cron_restart = Some("* * * * * *".to_string());
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("tick".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let mut runs = Vec::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        runs = history_runs(&paths, "tick").await;
+        if runs.len() >= 3 {
+            break;
+        }
+    }
+    assert!(runs.len() >= 3, "runs: {runs:?}");
+    assert_eq!(
+        info_of(&paths, "tick").await.last_restart.as_deref(),
+        Some("cron schedule")
+    );
+
+    // A manual stop keeps the schedule from starting it again
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["tick".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let stopped = history_runs(&paths, "tick").await.len();
+    tokio::time::sleep(Duration::from_millis(2000)).await;
+    assert_eq!(history_runs(&paths, "tick").await.len(), stopped);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cron_overlap_policy_skip_and_queue() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut slow = test_config("sleep 999");
+    slow.cron_restart = Some("* * * * * *".to_string());
+    slow.overlap_policy = Some(OverlapPolicy::Skip);
+    let mut queued = test_config("sleep 4");
+    queued.restart = Some(RestartPolicy::Never);
+    queued.cron_restart = Some("*/3 * * * * *".to_string());
+    queued.overlap_policy = Some(OverlapPolicy::Queue);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("slow".to_string(), slow), ("queued".to_string(), queued)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let slow_pid = info_of(&paths, "slow").await.pid;
+
+    // The queued run starts as soon as the first one exits
+    let mut detail = info_of(&paths, "queued").await;
+    for _ in 0..60 {
+        if detail.last_restart.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        detail = info_of(&paths, "queued").await;
+    }
+    assert_eq!(detail.last_restart.as_deref(), Some("queued cron run"));
+    assert_eq!(detail.restarts, 0);
+    let runs = history_runs(&paths, "queued").await;
+    assert!(
+        runs.iter().any(|r| r.reason == Some(RunReason::Queued)),
+        "runs: {runs:?}"
+    );
+    assert!(
+        runs.iter()
+            .any(|r| r.reason.is_none() && r.status == ProcessStatus::Stopped),
+        "runs: {runs:?}"
+    );
+
+    // Skipped ticks leave the running instance alone
+    let detail = info_of(&paths, "slow").await;
+    assert_eq!(detail.pid, slow_pid);
+    assert_eq!(detail.restarts, 0);
+    let runs = history_runs(&paths, "slow").await;
+    assert!(!runs.is_empty());
+    assert!(runs.iter().all(|r| r.reason == Some(RunReason::Skipped)));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_history_records_stop_with_resource_usage() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("svc".to_string(), test_config("sleep 999"))]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["svc".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+
+    let mut runs = Vec::new();
+    for _ in 0..50 {
+        runs = history_runs(&paths, "svc").await;
+        if !runs.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(runs.len(), 1, "runs: {runs:?}");
+    let run = &runs[0];
+    assert_eq!(run.status, ProcessStatus::Stopped);
+    assert!(run.snapshot.rss_bytes.unwrap_or(0) > 0);
+    assert!(run.snapshot.peak_rss_bytes.unwrap_or(0) > 0);
+    assert!(run.snapshot.fd_count.is_some());
+    assert!(run.snapshot.runtime_ms >= 200);
+
+    // Unfiltered history includes it too
+    let all = send_raw_request(
+        &paths,
+        &Request::History {
+            name: None,
+            since: None,
+        },
+    )
+    .await;
+    assert!(matches!(&all, Response::History { runs } if runs.len() == 1));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_history_sqlite_backend_with_since() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(
+        &paths,
+        "[storage]\nbackend = \"sqlite\"\nsample_interval = \"1s\"\n",
+    );
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'sleep 1.5; exit 1'");
+    config.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("job".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let mut runs = Vec::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        runs = history_runs(&paths, "job").await;
+        if !runs.is_empty() {
+            break;
+        }
+    }
+    assert_eq!(runs.len(), 1, "runs: {runs:?}");
+    assert_eq!(runs[0].status, ProcessStatus::Crashed);
+    assert!(paths.storage_db().exists());
+    assert!(!paths.history_file("job").exists());
+
+    let future = send_raw_request(
+        &paths,
+        &Request::History {
+            name: None,
+            since: Some(runs[0].ended_at + 1),
+        },
+    )
+    .await;
+    assert!(matches!(&future, Response::History { runs } if runs.is_empty()));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+
+    let storage = pm3::storage::SqliteStorage::open(&paths.storage_db()).unwrap();
+    let samples = pm3::storage::Storage::samples(&storage, "job", None).unwrap();
+    assert!(!samples.is_empty(), "expected persisted resource samples");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_samples_request_returns_persisted_samples() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[storage]\nsample_interval = \"1s\"\n");
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), test_config("sleep 30"))]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let started = chrono::Utc::now().timestamp_millis();
+    let mut samples = Vec::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let response = send_raw_request(
+            &paths,
+            &Request::Samples {
+                name: "web".to_string(),
+                since: Some(started - 60_000),
+            },
+        )
+        .await;
+        let Response::Samples { samples: found } = response else {
+            panic!("expected Samples, got {response:?}");
+        };
+        samples = found;
+        if samples.len() >= 2 {
+            break;
+        }
+    }
+    assert!(samples.len() >= 2, "samples: {samples:?}");
+    assert!(samples.iter().all(|s| s.name == "web" && s.rss_bytes > 0));
+    assert!(samples.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+    let future = send_raw_request(
+        &paths,
+        &Request::Samples {
+            name: "web".to_string(),
+            since: Some(chrono::Utc::now().timestamp_millis() + 60_000),
+        },
+    )
+    .await;
+    assert!(matches!(future, Response::Samples { samples } if samples.is_empty()));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_notify_posts_chosen_events_to_webhook() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let (port, mut body_rx) = spawn_http_collector().await;
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 3'");
+    config.restart = Some(RestartPolicy::OnFailure);
+    config.max_restarts = Some(1);
+    config.notify = Some(Notify::Detailed {
+        urls: vec![format!("http://127.0.0.1:{port}/hook")],
+        channels: Vec::new(),
+        events: vec![NotifyEvent::Crash, NotifyEvent::Errored],
+    });
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("worker".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    // Two crashes, then it gives up; its restart isn't asked for
+    let mut kinds = Vec::new();
+    while kinds.len() < 3 {
+        let body = tokio::time::timeout(Duration::from_secs(5), body_rx.recv())
+            .await
+            .expect("no notification arrived")
+            .unwrap();
+        let event: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(event["name"], "worker");
+        kinds.push(event["type"].as_str().unwrap().to_string());
+    }
+    kinds.sort();
+    assert_eq!(kinds, ["crash", "crash", "errored"]);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), body_rx.recv())
+            .await
+            .is_err(),
+        "nothing else should be sent"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_notify_cooldown_coalesces_repeats() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    std::fs::create_dir_all(paths.data_dir()).unwrap();
+    std::fs::write(
+        paths.settings_file(),
+        "[notifications]\ncooldown = \"3s\"\n",
+    )
+    .unwrap();
+    let (port, mut body_rx) = spawn_http_collector().await;
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 3'");
+    config.restart = Some(RestartPolicy::OnFailure);
+    config.max_restarts = Some(4);
+    config.notify = Some(Notify::Detailed {
+        urls: vec![format!("http://127.0.0.1:{port}/hook")],
+        channels: Vec::new(),
+        events: vec![NotifyEvent::Crash],
+    });
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("worker".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    // Five crashes in quick succession: the first goes out at once, the
+    // rest as one summary when the cooldown ends
+    let mut next = async || {
+        let body = tokio::time::timeout(Duration::from_secs(5), body_rx.recv())
+            .await
+            .expect("no notification arrived")
+            .unwrap();
+        serde_json::from_str::<serde_json::Value>(&body).unwrap()
+    };
+    let first = next().await;
+    assert_eq!(first["type"], "crash");
+    assert!(first.get("count").is_none());
+    let summary = next().await;
+    assert_eq!(summary["type"], "crash");
+    assert_eq!(summary["count"], 4);
+    assert_eq!(
+        summary["summary"],
+        "worker crashed 4 more times in the last 3s"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+async fn process_events(paths: &Paths, name: &str) -> Vec<protocol::ProcessEvent> {
+    match send_raw_request(
+        paths,
+        &Request::Events {
+            name: Some(name.to_string()),
+            since: None,
+        },
+    )
+    .await
+    {
+        Response::Events { events } => events,
+        other => panic!("expected Events, got: {other:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_events_record_crash_restarts_and_survive_daemon_restart() {
+    use protocol::EventKind;
+
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 3'");
+    config.restart = Some(RestartPolicy::OnFailure);
+    config.max_restarts = Some(1);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("flaky".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let mut events = Vec::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        events = process_events(&paths, "flaky").await;
+        if events.len() >= 6 {
+            break;
+        }
+    }
+    let kinds: Vec<EventKind> = events.iter().map(|e| e.event).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            EventKind::Start,
+            EventKind::Exit,
+            EventKind::Restart,
+            EventKind::Start,
+            EventKind::Exit,
+            EventKind::Errored,
+        ],
+        "events: {events:?}"
+    );
+    assert_eq!(events[1].exit_code, Some(3));
+    assert_eq!(events[2].detail.as_deref(), Some("crashed"));
+    assert_eq!(events[4].status, Some(ProcessStatus::Crashed));
+    assert_eq!(
+        events[5].detail.as_deref(),
+        Some("used up max_restarts (1)")
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+
+    // Each event was also appended to the event log as it happened
+    let logged: Vec<protocol::ProcessEvent> = std::fs::read_to_string(paths.event_log())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .filter(|event: &protocol::ProcessEvent| event.name == "flaky")
+        .collect();
+    assert_eq!(logged, events);
+
+    // The history is read back from storage by the next daemon
+    let handle = start_test_daemon(&paths).await;
+    assert_eq!(process_events(&paths, "flaky").await, events);
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── log_scrub ───────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_scrub_redacts_before_disk() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config =
+        test_config("sh -c 'echo login password=hunter2; echo Bearer abc.def >&2; sleep 999'");
+    config.log_scrub = Some(vec![
+        r"(?i)password=\S+".to_string(),
+        r"Bearer \S+".to_string(),
+    ]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("secretive".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let stdout = std::fs::read_to_string(paths.stdout_log("secretive")).unwrap();
+    let stderr = std::fs::read_to_string(paths.stderr_log("secretive")).unwrap();
+    assert_eq!(stdout, "login [REDACTED]\n");
+    assert_eq!(stderr, "[REDACTED]\n");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_scrub_invalid_pattern_rejects_start() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.log_scrub = Some(vec!["(unclosed".to_string()]);
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("bad".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let failed = results_of(&resp, ResultStatus::Failed);
+    assert!(
+        failed.len() == 1 && failed[0].contains("log_scrub"),
+        "unexpected response: {resp:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Lifecycle hooks ─────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_hooks_run_in_lifecycle_order() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let workdir = dir.path().join("work");
+    std::fs::create_dir_all(&workdir).unwrap();
+
+    let handle = start_test_daemon(&paths).await;
+
+    let hook = |label: &str| {
+        Some(pm3::config::Hook::Command(format!(
+            "sh -c 'echo {label} $PM3_NAME >> hooks.txt'"
+        )))
+    };
+    let mut config = test_config("sleep 999");
+    config.cwd = Some(workdir.to_str().unwrap().to_string());
+    config.pre_start = hook("pre_start");
+    config.post_start = hook("post_start");
+    config.pre_stop = hook("pre_stop");
+    config.post_stop = hook("post_stop");
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("hooked".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["hooked".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+
+    let hooks_file = workdir.join("hooks.txt");
+    let mut lines = Vec::new();
+    for _ in 0..50 {
+        lines = std::fs::read_to_string(&hooks_file)
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect();
+        if lines.len() == 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(
+        lines,
+        vec![
+            "pre_start hooked",
+            "post_start hooked",
+            "pre_stop hooked",
+            "post_stop hooked"
+        ]
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_failing_pre_start_aborts_start() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.pre_start = Some(pm3::config::Hook::Command(
+        "sh -c 'echo migration failed; exit 2'".to_string(),
+    ));
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("guarded".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let failed = results_of(&resp, ResultStatus::Failed);
+    assert!(
+        failed.len() == 1 && failed[0].contains("pre_start hook exited"),
+        "unexpected response: {resp:?}"
+    );
+
+    let list = send_raw_request(&paths, &Request::List).await;
+    assert!(matches!(&list, Response::ProcessList { processes } if processes.is_empty()));
+
+    let log = std::fs::read_to_string(paths.stderr_log("guarded")).unwrap();
+    assert!(
+        log.contains("[pm3] pre_start: migration failed"),
+        "log: {log}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_slow_pre_start_on_restart_blocks_no_requests() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'sleep 0.2; exit 1'");
+    config.pre_start = Some(pm3::config::Hook::Command("sleep 2".to_string()));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("flaky".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    // The crash restart is now running its pre_start hook
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let asked = Instant::now();
+    let list = send_raw_request(&paths, &Request::List).await;
+    assert!(
+        asked.elapsed() < Duration::from_millis(500),
+        "{:?}",
+        asked.elapsed()
+    );
+    assert_eq!(list_one(&list, "flaky").status, ProcessStatus::Starting);
+
+    // Stopped meanwhile, the run the hook was for never takes its place
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["flaky".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(1800)).await;
+    let list = send_raw_request(&paths, &Request::List).await;
+    let flaky = list_one(&list, "flaky");
+    assert_eq!(flaky.status, ProcessStatus::Stopped);
+    assert_eq!(flaky.pid, None);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_hook_timeout_aborts_start() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.pre_start = Some(pm3::config::Hook::Detailed {
+        command: "sleep 10".to_string(),
+        timeout: Some(200),
+    });
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("slow".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let failed = results_of(&resp, ResultStatus::Failed);
+    assert!(
+        failed.len() == 1 && failed[0].contains("timed out after 200ms"),
+        "unexpected response: {resp:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_process_env_is_applied() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'echo greeting=$GREETING; sleep 999'");
+    config.env = Some(HashMap::from([(
+        "GREETING".to_string(),
+        "hello".to_string(),
+    )]));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("envy".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let log = std::fs::read_to_string(paths.stdout_log("envy")).unwrap();
+    assert_eq!(log, "greeting=hello\n");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Readiness gating ────────────────────────────────────────────────
+
+async fn status_of(paths: &Paths, name: &str) -> ProcessStatus {
+    list_one(&send_raw_request(paths, &Request::List).await, name).status
+}
+
+async fn wait_for_status(paths: &Paths, name: &str, want: ProcessStatus) -> ProcessStatus {
+    let mut status = status_of(paths, name).await;
+    for _ in 0..50 {
+        if status == want {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        status = status_of(paths, name).await;
+    }
+    status
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_ready_check_file_gates_online() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let marker = dir.path().join("ready");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config(&format!(
+        "sh -c 'sleep 0.6; touch {}; sleep 999'",
+        marker.display()
+    ));
+    config.ready_check = Some(ReadyCheck::File(marker.to_string_lossy().to_string()));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    assert_eq!(status_of(&paths, "web").await, ProcessStatus::Starting);
+    assert_eq!(
+        wait_for_status(&paths, "web", ProcessStatus::Online).await,
+        ProcessStatus::Online
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_ready_check_log_line() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config =
+        test_config("sh -c 'echo booting; sleep 0.5; echo listening on 8080; sleep 999'");
+    config.ready_check = Some(ReadyCheck::Log("listening on \\d+".to_string()));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("api".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    assert_eq!(status_of(&paths, "api").await, ProcessStatus::Starting);
+    assert_eq!(
+        wait_for_status(&paths, "api", ProcessStatus::Online).await,
+        ProcessStatus::Online
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_ready_check_timeout_marks_unhealthy() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.ready_check = Some(ReadyCheck::File(
+        dir.path().join("never").to_string_lossy().to_string(),
+    ));
+    config.ready_timeout = Some(300);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("stuck".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    assert_eq!(
+        wait_for_status(&paths, "stuck", ProcessStatus::Unhealthy).await,
+        ProcessStatus::Unhealthy
+    );
+    let log = std::fs::read_to_string(paths.stderr_log("stuck")).unwrap();
+    assert!(
+        log.contains("ready check did not pass within 300ms"),
+        "{log}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Groups ──────────────────────────────────────────────────────────
+
+fn grouped_configs() -> HashMap<String, ProcessConfig> {
+    let mut api = test_config("sh -c 'echo api up; sleep 999'");
+    api.group = Some("backend".to_string());
+    let mut worker = test_config("sh -c 'echo worker up; sleep 999'");
+    worker.group = Some("backend".to_string());
+    let web = test_config("sleep 999");
+    HashMap::from([
+        ("api".to_string(), api),
+        ("worker".to_string(), worker),
+        ("web".to_string(), web),
+    ])
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_stop_and_restart_by_group() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: grouped_configs(),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Restart {
+            names: None,
+            group: Some("backend".to_string()),
+            rolling: false,
+            stagger: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["api", "worker"]
+    );
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: None,
+            group: Some("backend".to_string()),
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["api", "worker"]
+    );
+
+    let list = send_raw_request(&paths, &Request::List).await;
+    assert_eq!(list_one(&list, "api").status, ProcessStatus::Stopped);
+    assert_eq!(list_one(&list, "worker").status, ProcessStatus::Stopped);
+    let web = list_one(&list, "web");
+    assert_eq!(web.status, ProcessStatus::Online);
+    assert_eq!(web.group, None);
+    assert_eq!(list_one(&list, "api").group.as_deref(), Some("backend"));
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: None,
+            group: Some("frontend".to_string()),
+            cascade: false,
+        },
+    )
+    .await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message == "no processes in group: frontend"),
+        "unexpected response: {resp:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_by_group() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: grouped_configs(),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let responses = send_streaming_request(
+        &paths,
+        &Request::Log {
+            name: None,
+            lines: 15,
+            follow: false,
+            group: Some("backend".to_string()),
+            timestamps: false,
+            grep: None,
+            level: None,
+            since: None,
+            stream: None,
+            color: false,
+        },
+    )
+    .await;
+    let mut names: Vec<String> = responses
+        .iter()
+        .filter_map(|r| match r {
+            Response::LogLine { name, .. } => name.clone(),
+            _ => None,
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names, vec!["api", "worker"]);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Zero-downtime reload ────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_reload_swaps_in_ready_replacement() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'sleep 0.3; echo ready; sleep 999'");
+    config.ready_check = Some(ReadyCheck::Log("^ready$".to_string()));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    assert_eq!(
+        wait_for_status(&paths, "web", ProcessStatus::Online).await,
+        ProcessStatus::Online
+    );
+    let old_pid = list_one(&send_raw_request(&paths, &Request::List).await, "web")
+        .pid
+        .unwrap();
+
+    // The old instance keeps serving while the replacement boots
+    let reload_paths = paths.clone();
+    let reload = tokio::spawn(async move {
+        send_raw_request(
+            &reload_paths,
+            &Request::Reload {
+                names: Some(vec!["web".to_string()]),
+            },
+        )
+        .await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let during = list_one(&send_raw_request(&paths, &Request::List).await, "web");
+    assert_eq!(during.pid, Some(old_pid));
+    assert_eq!(during.status, ProcessStatus::Online);
+
+    let resp = reload.await.unwrap();
+    assert!(
+        results_of(&resp, ResultStatus::Succeeded) == ["web"],
+        "unexpected response: {resp:?}"
+    );
+    let after = list_one(&send_raw_request(&paths, &Request::List).await, "web");
+    assert_eq!(after.status, ProcessStatus::Online);
+    assert_ne!(after.pid, Some(old_pid));
+    assert!(
+        nix::sys::signal::kill(nix::unistd::Pid::from_raw(old_pid as i32), None).is_err(),
+        "old instance should be stopped"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_reload_keeps_old_instance_when_replacement_fails() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let marker = dir.path().join("booted");
+
+    let handle = start_test_daemon(&paths).await;
+
+    // Only the first instance comes up; any later one exits immediately
+    let mut config = test_config(&format!(
+        "sh -c '[ -e {m} ] && exit 1; touch {m}; echo ready; sleep 999'",
+        m = marker.display()
+    ));
+    config.ready_check = Some(ReadyCheck::Log("^ready$".to_string()));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([
+                ("api".to_string(), test_config("sleep 999")),
+                ("web".to_string(), config),
+                ("worker".to_string(), test_config("sleep 999")),
+            ]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "web", ProcessStatus::Online).await;
+    let old_pid = list_one(&send_raw_request(&paths, &Request::List).await, "web")
+        .pid
+        .unwrap();
+    let worker_pid = list_one(&send_raw_request(&paths, &Request::List).await, "worker").pid;
+
+    // Processes reload in name order and the roll stops at "web"
+    let resp = send_raw_request(&paths, &Request::Reload { names: None }).await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["api (restarted)"]
+    );
+    let failed = results_of(&resp, ResultStatus::Failed);
+    assert!(
+        matches!(failed.as_slice(), [f] if f.starts_with("web (") && f.contains("old instance kept running")),
+        "unexpected response: {resp:?}"
+    );
+    assert_eq!(
+        results_of(&resp, ResultStatus::Skipped),
+        ["worker (not attempted, 'web' failed to reload)"]
+    );
+    let after = list_one(&send_raw_request(&paths, &Request::List).await, "web");
+    assert_eq!(after.pid, Some(old_pid));
+    assert_eq!(after.status, ProcessStatus::Online);
+    let worker = list_one(&send_raw_request(&paths, &Request::List).await, "worker");
+    assert_eq!(worker.pid, worker_pid);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_reload_without_check_restarts() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("worker".to_string(), test_config("sleep 999"))]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let old_pid = list_one(&send_raw_request(&paths, &Request::List).await, "worker").pid;
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Reload {
+            names: Some(vec!["worker".to_string()]),
+        },
+    )
+    .await;
+    assert!(
+        results_of(&resp, ResultStatus::Succeeded) == ["worker (restarted)"],
+        "unexpected response: {resp:?}"
+    );
+    let after = list_one(&send_raw_request(&paths, &Request::List).await, "worker");
+    assert_ne!(after.pid, old_pid);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Event plugins ───────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_event_plugins_receive_lifecycle_events() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let events_file = dir.path().join("plugin-events.jsonl");
+    let exits_file = dir.path().join("exits.jsonl");
+    write_daemon_settings(
+        &paths,
+        &format!(
+            "[[plugins]]\ncommand = \"sh -c 'cat >> {}'\"\n\n\
+             [[plugins]]\ncommand = \"sh -c 'cat >> {}'\"\nevents = [\"exit\"]\n",
+            events_file.display(),
+            exits_file.display()
+        ),
+    );
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 3'");
+    config.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("once".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "once", ProcessStatus::Crashed).await;
+
+    let read_events = |path: &std::path::Path| -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while (read_events(&events_file).len() < 2 || read_events(&exits_file).is_empty())
+        && Instant::now() < deadline
+    {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let mut events = read_events(&events_file);
+    events.sort_by_key(|e| e["event"].as_str().unwrap().to_string());
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert_eq!(events[0]["event"], "exit");
+    assert_eq!(events[0]["status"], "crashed");
+    assert_eq!(events[0]["exit_code"], 3);
+    assert_eq!(events[1]["event"], "start");
+    assert_eq!(events[1]["name"], "once");
+
+    let exits = read_events(&exits_file);
+    assert_eq!(exits.len(), 1, "{exits:?}");
+    assert_eq!(exits[0]["event"], "exit");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_reload_sends_reload_signal() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let marker = dir.path().join("reloaded");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config(&format!(
+        "sh -c 'trap \"touch {}\" HUP; while true; do sleep 0.1; done'",
+        marker.display()
+    ));
+    config.reload_signal = Some("SIGHUP".to_string());
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("nginx".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    // Give the shell time to install its trap
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let old_pid = list_one(&send_raw_request(&paths, &Request::List).await, "nginx").pid;
+
+    let resp = send_raw_request(&paths, &Request::Reload { names: None }).await;
+    assert!(
+        results_of(&resp, ResultStatus::Succeeded) == ["nginx (SIGHUP)"],
+        "unexpected response: {resp:?}"
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !marker.exists() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(marker.exists(), "process did not receive SIGHUP");
+    let after = list_one(&send_raw_request(&paths, &Request::List).await, "nginx");
+    assert_eq!(after.pid, old_pid);
+    assert_eq!(after.status, ProcessStatus::Online);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── WASM policies ───────────────────────────────────────────────────
+
+#[cfg(feature = "wasm")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_policy_declines_restart() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let policy = dir.path().join("policy.wat");
+    std::fs::write(
+        &policy,
+        r#"
+(module
+  (func (export "pm3_api_version") (result i32) i32.const 1)
+  (func (export "pm3_should_restart")
+        (param i32) (param i32) (param i64) (param i32) (result i32)
+    i32.const 0))
+"#,
+    )
+    .unwrap();
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'exit 1'");
+    config.restart = Some(RestartPolicy::Always);
+    config.policy = Some(policy.to_string_lossy().to_string());
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("flaky".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    wait_for_status(&paths, "flaky", ProcessStatus::Crashed).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let info = list_one(&send_raw_request(&paths, &Request::List).await, "flaky");
+    assert_eq!(info.status, ProcessStatus::Crashed);
+    assert_eq!(info.restarts, 0);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_start_fails_on_broken_policy() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.policy = Some(
+        dir.path()
+            .join("missing.wasm")
+            .to_string_lossy()
+            .to_string(),
+    );
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("guarded".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let failed = results_of(&resp, ResultStatus::Failed);
+    assert!(
+        failed.len() == 1 && failed[0].contains("missing.wasm"),
+        "unexpected response: {resp:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Signal ──────────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_signal_delivers_to_process() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let marker = dir.path().join("usr2");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let config = test_config(&format!(
+        "sh -c 'trap \"touch {}\" USR2; while true; do sleep 0.1; done'",
+        marker.display()
+    ));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Signal {
+            name: "web".to_string(),
+            signal: "usr2".to_string(),
+            group_leader: false,
+        },
+    )
+    .await;
+    assert!(
+        matches!(&resp, Response::Success { message: Some(m) } if m == "sent SIGUSR2 to web"),
+        "unexpected response: {resp:?}"
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !marker.exists() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(marker.exists(), "process did not receive SIGUSR2");
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Signal {
+            name: "web".to_string(),
+            signal: "SIGBOGUS".to_string(),
+            group_leader: false,
+        },
+    )
+    .await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("SIGBOGUS")),
+        "unexpected response: {resp:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_signal_group_leader_reaches_children() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let marker = dir.path().join("child-usr1");
+    let child = dir.path().join("child.sh");
+    std::fs::write(
+        &child,
+        format!(
+            "trap 'touch {}' USR1\nwhile true; do sleep 0.1; done\n",
+            marker.display()
+        ),
+    )
+    .unwrap();
+
+    let handle = start_test_daemon(&paths).await;
+
+    let config = test_config(&format!(
+        "sh -c 'trap true USR1; sh {} & while true; do sleep 0.1; done'",
+        child.display()
+    ));
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("parent".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Signal {
+            name: "parent".to_string(),
+            signal: "10".to_string(),
+            group_leader: true,
+        },
+    )
+    .await;
+    assert!(
+        matches!(&resp, Response::Success { .. }),
+        "unexpected response: {resp:?}"
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !marker.exists() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(marker.exists(), "child did not receive SIGUSR1");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_signal_stopped_process_errors() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("true");
+    config.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("once".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "once", ProcessStatus::Stopped).await;
+
+    for name in ["once", "ghost"] {
+        let resp = send_raw_request(
+            &paths,
+            &Request::Signal {
+                name: name.to_string(),
+                signal: "SIGHUP".to_string(),
+                group_leader: false,
+            },
+        )
+        .await;
+        assert!(
+            matches!(&resp, Response::Error { .. }),
+            "unexpected response for {name}: {resp:?}"
+        );
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Control socket ──────────────────────────────────────────────────
+
+fn control_socket(paths: &Paths, name: &str) -> std::path::PathBuf {
+    let prefix = format!("{name}-");
+    std::fs::read_dir(paths.control_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with(&prefix)
+        })
+        .expect("no control socket")
+}
+
+fn send_control(socket: &std::path::Path, message: &str) {
+    let mut stream = UnixStream::connect(socket).unwrap();
+    stream.write_all(message.as_bytes()).unwrap();
+    stream.write_all(b"\n").unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_control_socket_ready_and_metrics() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sh -c 'echo socket=$PM3_CONTROL_SOCKET; sleep 999'");
+    config.ready_check = Some(ReadyCheck::Control);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("app".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    assert_eq!(status_of(&paths, "app").await, ProcessStatus::Starting);
+
+    let socket = control_socket(&paths, "app");
+    send_control(
+        &socket,
+        r#"{"type":"metric","name":"queue_depth","value":4}"#,
+    );
+    send_control(&socket, r#"{"type":"ready"}"#);
+    wait_for_status(&paths, "app", ProcessStatus::Online).await;
+
+    let info = list_one(&send_raw_request(&paths, &Request::List).await, "app");
+    assert_eq!(info.metrics.get("queue_depth"), Some(&4.0));
+    let stdout = std::fs::read_to_string(paths.stdout_log("app")).unwrap();
+    assert!(
+        stdout.contains(&format!("socket={}", socket.display())),
+        "{stdout}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+    assert!(!socket.exists());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_control_socket_heartbeat_timeout() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.heartbeat_timeout = Some(500);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("app".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    wait_for_status(&paths, "app", ProcessStatus::Unhealthy).await;
+    let socket = control_socket(&paths, "app");
+    send_control(&socket, r#"{"type":"heartbeat"}"#);
+    wait_for_status(&paths, "app", ProcessStatus::Online).await;
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_control_socket_announces_shutdown() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("app".to_string(), test_config("sleep 999"))]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let stream = UnixStream::connect(control_socket(&paths, "app")).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        line
+    });
+
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: None,
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(reader.join().unwrap().trim(), r#"{"type":"shutdown"}"#);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Info ────────────────────────────────────────────────────────────
+
+async fn info_of(paths: &Paths, name: &str) -> pm3::protocol::ProcessDetail {
+    match send_raw_request(
+        paths,
+        &Request::Info {
+            name: name.to_string(),
+            tree: false,
+        },
+    )
+    .await
+    {
+        Response::ProcessDetail { info } => *info,
+        other => panic!("expected ProcessDetail, got: {other:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_info_tree_lists_shell_children() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([(
+                "wrapped".to_string(),
+                test_config("sh -c 'sleep 997 & sleep 998'"),
+            )]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let info = list_one(&send_raw_request(&paths, &Request::List).await, "wrapped");
+    assert_eq!(info.process_count, Some(3));
+
+    // Without `tree` the breakdown is left out
+    assert!(info_of(&paths, "wrapped").await.tree.is_empty());
+
+    let detail = match send_raw_request(
+        &paths,
+        &Request::Info {
+            name: "wrapped".to_string(),
+            tree: true,
+        },
+    )
+    .await
+    {
+        Response::ProcessDetail { info } => *info,
+        other => panic!("expected ProcessDetail, got: {other:?}"),
+    };
+    assert_eq!(detail.tree.len(), 3, "{:?}", detail.tree);
+    assert_eq!(Some(detail.tree[0].pid), info.pid);
+    for sleeper in ["sleep 997", "sleep 998"] {
+        let member = detail
+            .tree
+            .iter()
+            .find(|member| member.command == sleeper)
+            .unwrap_or_else(|| panic!("{sleeper} missing from {:?}", detail.tree));
+        assert_eq!(member.ppid, info.pid);
+        assert!(member.rss_bytes > 0);
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_info_reports_detail_with_masked_env() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.env = Some(HashMap::from([
+        ("PORT".to_string(), "3000".to_string()),
+        ("API_KEY".to_string(), "dev-key".to_string()),
+    ]));
+    config.environments = HashMap::from([(
+        "production".to_string(),
+        HashMap::from([
+            ("PORT".to_string(), "80".to_string()),
+            ("DB_PASSWORD".to_string(), "hunter2".to_string()),
+        ]),
+    )]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), config)]),
+            names: None,
+            env: Some("production".to_string()),
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let info = info_of(&paths, "web").await;
+    assert_eq!(info.name, "web");
+    assert_eq!(info.command, "sleep 999");
+    assert_eq!(info.status, ProcessStatus::Online);
+    assert!(info.pid.is_some());
+    assert_eq!(info.environment.as_deref(), Some("production"));
+    let env = info.env.unwrap();
+    assert_eq!(env["PORT"], "80");
+    assert_eq!(env["API_KEY"], "****");
+    assert_eq!(env["DB_PASSWORD"], "****");
+    assert_eq!(
+        info.stdout_log.as_deref(),
+        Some(paths.stdout_log("web").to_str().unwrap())
+    );
+    assert_eq!(info.last_restart, None);
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Info {
+            name: "missing".to_string(),
+            tree: false,
+        },
+    )
+    .await;
+    match resp {
+        Response::Error { message } => assert_eq!(message, "process not found: missing"),
+        other => panic!("expected Error, got: {other:?}"),
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_info_reports_last_exit_and_restart_reason() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut config = test_config("sleep 999");
+    config.restart = Some(RestartPolicy::Always);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), config)]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    let pid = info_of(&paths, "web").await.pid.unwrap();
+    nix::sys::signal::kill(
+        nix::unistd::Pid::from_raw(pid as i32),
+        nix::sys::signal::Signal::SIGKILL,
+    )
+    .unwrap();
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    let info = loop {
+        let info = info_of(&paths, "web").await;
+        if info.restarts > 0 && info.pid.is_some_and(|p| p != pid) {
+            break info;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "never restarted");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(info.exit_signal.as_deref(), Some("SIGKILL"));
+    assert_eq!(info.exit_code, None);
+    assert_eq!(info.last_restart.as_deref(), Some("killed by SIGKILL"));
+
+    send_raw_request(
+        &paths,
+        &Request::Restart {
+            names: Some(vec!["web".to_string()]),
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: false,
+        },
+    )
+    .await;
+    let info = info_of(&paths, "web").await;
+    assert_eq!(info.last_restart.as_deref(), Some("manual restart"));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Save ────────────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_save_writes_versioned_dump() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let mut web = test_config("sleep 999");
+    web.env = Some(HashMap::from([("PORT".to_string(), "3000".to_string())]));
+    web.environments = HashMap::from([(
+        "production".to_string(),
+        HashMap::from([("PORT".to_string(), "80".to_string())]),
+    )]);
+    let mut once = test_config("true");
+    once.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), web), ("once".to_string(), once)]),
+            names: None,
+            env: Some("production".to_string()),
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "once", ProcessStatus::Stopped).await;
+
+    match send_raw_request(&paths, &Request::Save).await {
+        Response::Success { message } => {
+            assert!(message.unwrap().starts_with("saved 2 processes to"));
+        }
+        other => panic!("expected Success, got: {other:?}"),
+    }
+
+    let saved = pm3::dump::read(&paths.dump_file()).unwrap();
+    assert_eq!(saved.version, pm3::dump::DUMP_VERSION);
+    let names: Vec<&str> = saved.processes.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, ["once", "web"]);
+    let web = &saved.processes[1];
+    assert_eq!(web.status, ProcessStatus::Online);
+    assert_eq!(web.environment.as_deref(), Some("production"));
+    assert_eq!(web.config.env.as_ref().unwrap()["PORT"], "3000");
+    assert_eq!(saved.processes[0].status, ProcessStatus::Stopped);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_resurrect_revives_saved_processes() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let resp = {
+        let handle = start_test_daemon(&paths).await;
+        let resp = send_raw_request(&paths, &Request::Resurrect).await;
+        send_raw_request(&paths, &Request::Kill).await;
+        let _ = handle.await;
+        resp
+    };
+    match resp {
+        Response::Error { message } => assert!(message.contains("pm3 save"), "{message}"),
+        other => panic!("expected Error, got: {other:?}"),
+    }
+
+    let handle = start_test_daemon(&paths).await;
+    let mut web = test_config("sh -c 'echo port=$PORT; exec sleep 999'");
+    web.environments = HashMap::from([(
+        "production".to_string(),
+        HashMap::from([("PORT".to_string(), "80".to_string())]),
+    )]);
+    let mut once = test_config("true");
+    once.restart = Some(RestartPolicy::Never);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), web), ("once".to_string(), once)]),
+            names: None,
+            env: Some("production".to_string()),
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "once", ProcessStatus::Stopped).await;
+    send_raw_request(&paths, &Request::Save).await;
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+    std::fs::remove_file(paths.stdout_log("web")).unwrap();
+
+    let handle = start_test_daemon(&paths).await;
+    match send_raw_request(&paths, &Request::Resurrect).await {
+        Response::Success { message } => {
+            let message = message.unwrap();
+            assert!(message.contains("resurrected 1 of 2"), "{message}");
+            assert!(message.contains("web: revived"), "{message}");
+            assert!(message.contains("once: skipped (was stopped)"), "{message}");
+        }
+        other => panic!("expected Success, got: {other:?}"),
+    }
+    assert_eq!(
+        wait_for_status(&paths, "web", ProcessStatus::Online).await,
+        ProcessStatus::Online
+    );
+    assert!(matches!(
+        send_raw_request(
+            &paths,
+            &Request::Info {
+                name: "once".to_string(),
+                tree: false,
+            }
+        )
+        .await,
+        Response::Error { .. }
+    ));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let stdout = std::fs::read_to_string(paths.stdout_log("web")).unwrap_or_default();
+        if stdout.contains("port=80") {
+            break;
+        }
+        assert!(Instant::now() < deadline, "env not restored: {stdout}");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    match send_raw_request(&paths, &Request::Resurrect).await {
+        Response::Success { message } => {
+            assert!(message.unwrap().contains("web: skipped (already running)"));
+        }
+        other => panic!("expected Success, got: {other:?}"),
+    }
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_auto_save_rewrites_dump_after_each_change() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[daemon]\nauto_save = true\n");
+
+    let handle = start_test_daemon(&paths).await;
+
+    // Reads leave the dump alone
+    send_raw_request(&paths, &Request::List).await;
+    assert!(!paths.dump_file().exists());
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: HashMap::from([("web".to_string(), test_config("sleep 999"))]),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let saved = pm3::dump::read(&paths.dump_file()).unwrap();
+    assert_eq!(saved.processes.len(), 1);
+    assert_eq!(saved.processes[0].status, ProcessStatus::Online);
+
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["web".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    let saved = pm3::dump::read(&paths.dump_file()).unwrap();
+    assert_eq!(saved.processes[0].status, ProcessStatus::Stopped);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Pipelines ───────────────────────────────────────────────────────
+
+fn task_config(command: &str, after: &[&str]) -> ProcessConfig {
+    ProcessConfig {
+        kind: Some(ProcessKind::Task),
+        after: (!after.is_empty()).then(|| after.iter().map(|s| s.to_string()).collect()),
+        ..test_config(command)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_pipeline_runs_tasks_in_order_and_stops_at_failure() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let marker = dir.path().join("built");
+
+    let handle = start_test_daemon(&paths).await;
+
+    let configs = HashMap::from([
+        (
+            "build".to_string(),
+            task_config(
+                &format!("sh -c 'sleep 0.3; touch {}'", marker.display()),
+                &[],
+            ),
+        ),
+        (
+            "test".to_string(),
+            task_config(
+                &format!("sh -c 'test -f {} && exit 3'", marker.display()),
+                &["build"],
+            ),
+        ),
+        ("lint".to_string(), task_config("sleep 0.5", &["build"])),
+        ("deploy".to_string(), task_config("true", &["test", "lint"])),
+        ("docs".to_string(), task_config("true", &[])),
+    ]);
+    let responses = send_streaming_request(
+        &paths,
+        &Request::RunPipeline {
+            configs: configs.clone(),
+            target: "deploy".to_string(),
+            env: None,
+        },
+    )
+    .await;
+
+    let Some(Response::PipelineDone { target, steps }) = responses.last() else {
+        panic!("expected PipelineDone, got: {responses:?}");
+    };
+    assert_eq!(target, "deploy");
+    let status_of = |name: &str| steps.iter().find(|s| s.name == name).unwrap().status;
+    assert_eq!(status_of("build"), StepStatus::Succeeded);
+    // `test` only fails once `build` has finished, so they ran in order
+    assert_eq!(status_of("test"), StepStatus::Failed);
+    assert_eq!(
+        steps.iter().find(|s| s.name == "test").unwrap().exit_code,
+        Some(3)
+    );
+    // `lint` was already running and finishes; `deploy` never starts
+    assert_eq!(status_of("lint"), StepStatus::Succeeded);
+    assert_eq!(status_of("deploy"), StepStatus::Skipped);
+    assert!(steps.iter().all(|s| s.name != "docs"));
+
+    let running: Vec<&str> = responses
+        .iter()
+        .filter_map(|r| match r {
+            Response::PipelineStep { step } if step.status == StepStatus::Running => {
+                Some(step.name.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(running.len(), 3, "responses: {responses:?}");
+    assert_eq!(running[0], "build");
+
+    // Tasks are not restarted after failing
+    let detail = info_of(&paths, "test").await;
+    assert_eq!(detail.status, ProcessStatus::Crashed);
+    assert_eq!(detail.restarts, 0);
+
+    // A pipeline whose tasks all succeed, run again over the finished tasks
+    let responses = send_streaming_request(
+        &paths,
+        &Request::RunPipeline {
+            configs,
+            target: "lint".to_string(),
+            env: None,
+        },
+    )
+    .await;
+    let Some(Response::PipelineDone { steps, .. }) = responses.last() else {
+        panic!("expected PipelineDone, got: {responses:?}");
+    };
+    assert!(
+        steps.iter().all(|s| s.status == StepStatus::Succeeded),
+        "{steps:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_task_that_exits_cleanly_is_completed() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    // `restart = "always"` does not bring back a task that succeeded
+    let configs = HashMap::from([
+        (
+            "migrate".to_string(),
+            ProcessConfig {
+                restart: Some(RestartPolicy::Always),
+                ..task_config("sleep 0.3", &[])
+            },
+        ),
+        ("broken".to_string(), task_config("sh -c 'exit 2'", &[])),
+    ]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    assert_eq!(
+        wait_for_status(&paths, "migrate", ProcessStatus::Completed).await,
+        ProcessStatus::Completed
+    );
+    assert_eq!(
+        wait_for_status(&paths, "broken", ProcessStatus::Crashed).await,
+        ProcessStatus::Crashed
+    );
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let list = send_raw_request(&paths, &Request::List).await;
+    let migrate = list_one(&list, "migrate");
+    assert_eq!(migrate.status, ProcessStatus::Completed);
+    assert_eq!(migrate.restarts, 0);
+    assert_eq!(migrate.pid, None);
+    assert_eq!(migrate.exit_code, Some(0));
+    assert!(
+        migrate.duration_ms.is_some_and(|ms| ms >= 250),
+        "{migrate:?}"
+    );
+    assert_eq!(list_one(&list, "broken").exit_code, Some(2));
+
+    let runs = history_runs(&paths, "migrate").await;
+    assert_eq!(runs.len(), 1, "runs: {runs:?}");
+    assert_eq!(runs[0].status, ProcessStatus::Completed);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Memory reservations ─────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_start_checks_expected_memory() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let configs = HashMap::from([
+        (
+            "huge".to_string(),
+            ProcessConfig {
+                expect_memory: Some("1000000T".to_string()),
+                ..test_config("sleep 999")
+            },
+        ),
+        ("small".to_string(), test_config("sleep 999")),
+    ]);
+
+    let handle = start_test_daemon(&paths).await;
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: configs.clone(),
+            names: None,
+            env: None,
+            strict: true,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    match &resp {
+        Response::Error { message } => {
+            assert!(message.contains("huge 1000000T"), "{message}");
+        }
+        other => panic!("expected Error, got: {other:?}"),
+    }
+    assert!(matches!(
+        send_raw_request(&paths, &Request::List).await,
+        Response::ProcessList { processes } if processes.is_empty()
+    ));
+
+    // Without --strict the start goes ahead with a warning
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    match &resp {
+        Response::Results {
+            results, warnings, ..
+        } => {
+            assert!(results.iter().all(|r| r.status == ResultStatus::Succeeded));
+            assert!(warnings[0].starts_with("processes expect"), "{warnings:?}");
+        }
+        other => panic!("expected Results, got: {other:?}"),
+    }
+    assert_eq!(info_of(&paths, "huge").await.status, ProcessStatus::Online);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Instances and scaling ───────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_scale_instances() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let configs = HashMap::from([(
+        "web".to_string(),
+        ProcessConfig {
+            instances: Some(2),
+            ..test_config("sh -c 'echo instance $PM3_INSTANCE; sleep 999'")
+        },
+    )]);
+
+    let handle = start_test_daemon(&paths).await;
+    let listed = || async {
+        match send_raw_request(&paths, &Request::List).await {
+            Response::ProcessList { processes } => {
+                let mut names: Vec<String> = processes.into_iter().map(|p| p.name).collect();
+                names.sort();
+                names
+            }
+            other => panic!("expected ProcessList, got: {other:?}"),
+        }
+    };
+
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    assert_eq!(listed().await, ["web-0", "web-1"]);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let log = std::fs::read_to_string(paths.stdout_log("web-1")).unwrap();
+    assert!(log.contains("instance 1"), "{log}");
+
+    // Growing fills in new instances and a saved dump follows along
+    send_raw_request(&paths, &Request::Save).await;
+    let resp = send_raw_request(
+        &paths,
+        &Request::Scale {
+            name: "web".to_string(),
+            instances: 4,
+        },
+    )
+    .await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["web-2 (started)", "web-3 (started)"]
+    );
+    assert_eq!(listed().await, ["web-0", "web-1", "web-2", "web-3"]);
+
+    // Shrinking stops the highest-numbered instances
+    let resp = send_raw_request(
+        &paths,
+        &Request::Scale {
+            name: "web".to_string(),
+            instances: 1,
+        },
+    )
+    .await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["web-3 (stopped)", "web-2 (stopped)", "web-1 (stopped)"]
+    );
+    assert_eq!(listed().await, ["web-0"]);
+    let saved = pm3::dump::read(&paths.dump_file()).unwrap();
+    let saved: Vec<&str> = saved.processes.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(saved, ["web-0"]);
+
+    // The process name targets every instance
+    let resp = send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["web".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(results_of(&resp, ResultStatus::Succeeded), ["web-0"]);
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Scale {
+            name: "nope".to_string(),
+            instances: 2,
+        },
+    )
+    .await;
+    assert!(matches!(resp, Response::Error { .. }), "{resp:?}");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_scale_reports_instances_that_fail_to_start() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let script = dir.path().join("serve.sh");
+    std::fs::write(&script, "#!/bin/sh\nsleep 999\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let configs = HashMap::from([(
+        "web".to_string(),
+        ProcessConfig {
+            instances: Some(1),
+            ..test_config(&script.display().to_string())
+        },
+    )]);
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    send_raw_request(&paths, &Request::Save).await;
+
+    // New instances cannot spawn once the script is gone; each is reported
+    // and the running one is left as it was
+    std::fs::remove_file(&script).unwrap();
+    let resp = send_raw_request(
+        &paths,
+        &Request::Scale {
+            name: "web".to_string(),
+            instances: 3,
+        },
+    )
+    .await;
+    let failed: Vec<String> = results_of(&resp, ResultStatus::Failed)
+        .into_iter()
+        .map(|failure| failure.split(' ').next().unwrap().to_string())
+        .collect();
+    assert_eq!(failed, ["web-1", "web-2"]);
+    assert!(results_of(&resp, ResultStatus::Succeeded).is_empty());
+
+    let saved = pm3::dump::read(&paths.dump_file()).unwrap();
+    let saved: Vec<&str> = saved.processes.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(saved, ["web-0"]);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_rolling_restart_waits_for_each_instance() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let gate = dir.path().join("ready");
+    let config = ProcessConfig {
+        instances: Some(3),
+        rolling_restart: Some(true),
+        ready_check: Some(ReadyCheck::File(gate.display().to_string())),
+        ready_timeout: Some(500),
+        ..test_config("sleep 999")
+    };
+    let configs = HashMap::from([("web".to_string(), config)]);
+    std::fs::write(&gate, "").unwrap();
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    for name in ["web-0", "web-1", "web-2"] {
+        wait_for_status(&paths, name, ProcessStatus::Online).await;
+    }
+
+    let restart = Request::Restart {
+        names: Some(vec!["web".to_string()]),
+        group: None,
+        rolling: false,
+        stagger: None,
+        cascade: false,
+    };
+    let resp = send_raw_request(&paths, &restart).await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["web-0", "web-1", "web-2"]
+    );
+    for name in ["web-0", "web-1", "web-2"] {
+        assert_eq!(info_of(&paths, name).await.restarts, 1);
+    }
+
+    // An instance that never becomes ready stops the roll, leaving the rest
+    // of the fleet running
+    std::fs::remove_file(&gate).unwrap();
+    let resp = send_raw_request(&paths, &restart).await;
+    let failed = results_of(&resp, ResultStatus::Failed);
+    assert!(
+        failed.len() == 1 && failed[0].starts_with("web-0 (not ready"),
+        "{failed:?}"
+    );
+    assert_eq!(
+        results_of(&resp, ResultStatus::Skipped),
+        [
+            "web-1 (rolling restart stopped at 'web-0')",
+            "web-2 (rolling restart stopped at 'web-0')"
+        ]
+    );
+    let web1 = info_of(&paths, "web-1").await;
+    assert_eq!(web1.restarts, 1);
+    assert_eq!(web1.status, ProcessStatus::Online);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Process classes ─────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_class_orders_stops_and_sets_oom_score() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let configs = HashMap::from([
+        (
+            "db".to_string(),
+            ProcessConfig {
+                class: Some(ProcessClass::Critical),
+                ..test_config("sleep 999")
+            },
+        ),
+        ("web".to_string(), test_config("sleep 999")),
+        (
+            "jobs".to_string(),
+            ProcessConfig {
+                class: Some(ProcessClass::Batch),
+                ..test_config("sleep 999")
+            },
+        ),
+    ]);
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    for name in ["db", "web", "jobs"] {
+        wait_for_status(&paths, name, ProcessStatus::Online).await;
+    }
+
+    let pid = info_of(&paths, "jobs").await.pid.unwrap();
+    let adj = std::fs::read_to_string(format!("/proc/{pid}/oom_score_adj")).unwrap();
+    assert_eq!(adj.trim(), "500");
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: None,
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["jobs", "web", "db"]
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_nice_and_ionice_apply_to_the_process() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let configs = HashMap::from([(
+        "worker".to_string(),
+        ProcessConfig {
+            nice: Some(7),
+            ionice: Some("idle".to_string()),
+            ..test_config("sleep 999")
+        },
+    )]);
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "worker", ProcessStatus::Online).await;
+
+    let pid = info_of(&paths, "worker").await.pid.unwrap();
+    // Field 19 of /proc/<pid>/stat, counting after the parenthesised comm
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+    let after_comm = stat.rsplit_once(')').unwrap().1;
+    let nice: i32 = after_comm
+        .split_whitespace()
+        .nth(16)
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(nice, 7);
+    // ioprio_get(IOPRIO_WHO_PROCESS, pid): the idle class is 3
+    let ioprio = unsafe { libc::syscall(libc::SYS_ioprio_get, 1, pid as libc::c_int) };
+    assert_eq!(ioprio >> 13, 3);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_rlimits_apply_to_the_process() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let configs = config::parse_config(
+        "[worker]\ncommand = \"sleep 999\"\n\
+         [worker.rlimits]\nnofile = { soft = 100, hard = 200 }\ncore = 0\n",
+    )
+    .unwrap();
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "worker", ProcessStatus::Online).await;
+
+    let pid = info_of(&paths, "worker").await.pid.unwrap();
+    let limits = std::fs::read_to_string(format!("/proc/{pid}/limits")).unwrap();
+    let limit = |name: &str| -> Vec<String> {
+        let line = limits.lines().find(|l| l.starts_with(name)).unwrap();
+        line[name.len()..]
+            .split_whitespace()
+            .take(2)
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(limit("Max open files"), ["100", "200"]);
+    assert_eq!(limit("Max core file size"), ["0", "0"]);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Process trees ───────────────────────────────────────────────────
+
+/// Whether `pid` is still running (a zombie waiting to be reaped is not).
+fn is_running(pid: u32) -> bool {
+    std::fs::read_to_string(format!("/proc/{pid}/stat"))
+        .is_ok_and(|stat| stat.rsplit(')').next().unwrap().split_whitespace().next() != Some("Z"))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_stop_kills_the_whole_process_tree() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let tree = |pid_file: &str, kill_tree: bool| ProcessConfig {
+        kill_tree: Some(kill_tree),
+        ..test_config(&format!(
+            "sh -c 'sleep 987 & echo $! > {}; wait'",
+            dir.path().join(pid_file).display()
+        ))
+    };
+    let mut configs = HashMap::new();
+    configs.insert("tree".to_string(), tree("tree.pid", true));
+    configs.insert("loose".to_string(), tree("loose.pid", false));
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    let grandchild = |path: std::path::PathBuf| async move {
+        for _ in 0..100 {
+            if let Some(pid) = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|pid| pid.trim().parse().ok())
+            {
+                return pid;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("{} never written", path.display());
+    };
+    let tree_sleep = grandchild(dir.path().join("tree.pid")).await;
+    let loose_sleep = grandchild(dir.path().join("loose.pid")).await;
+    assert!(is_running(tree_sleep) && is_running(loose_sleep));
+
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: None,
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert!(
+        !is_running(tree_sleep),
+        "grandchild outlived kill_tree stop"
+    );
+    // Without kill_tree only the shell is signaled
+    assert!(is_running(loose_sleep));
+    let _ = nix::sys::signal::kill(
+        nix::unistd::Pid::from_raw(loose_sleep as i32),
+        nix::sys::signal::Signal::SIGKILL,
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Terminals ───────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tty_runs_the_process_on_a_terminal() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let mut configs = HashMap::new();
+    configs.insert(
+        "colors".to_string(),
+        ProcessConfig {
+            tty: Some(true),
+            ..task_config(
+                "sh -c 'test -t 1 && echo out-is-tty; test -t 2 && echo err-is-tty >&2'",
+                &[],
+            )
+        },
+    );
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "colors", ProcessStatus::Completed).await;
+
+    let mut content = String::new();
+    for _ in 0..50 {
+        content = std::fs::read_to_string(paths.stdout_log("colors")).unwrap_or_default();
+        if content.contains("err-is-tty") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    // Both streams land in the stdout log, without the terminal's "\r\n"
+    assert_eq!(content, "out-is-tty\nerr-is-tty\n");
+    let stderr = std::fs::read_to_string(paths.stderr_log("colors")).unwrap_or_default();
+    assert!(stderr.is_empty(), "stderr log: {stderr}");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Attach ──────────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_attach_forwards_input_and_streams_output() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let mut configs = HashMap::new();
+    configs.insert(
+        "repl".to_string(),
+        ProcessConfig {
+            stdin: Some(StdinMode::Pipe),
+            ..test_config(
+                "sh -c 'while read line; do [ \"$line\" = quit ] && exit 0; echo \"got $line\"; done'",
+            )
+        },
+    );
+    configs.insert("plain".to_string(), test_config("sleep 999"));
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "repl", ProcessStatus::Online).await;
+
+    // Only processes with stdin = "pipe" take input
+    let resp = send_raw_request(
+        &paths,
+        &Request::Attach {
+            name: "plain".to_string(),
+        },
+    )
+    .await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("stdin = \"pipe\"")),
+        "got: {resp:?}"
+    );
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Input {
+            name: "repl".to_string(),
+            data: "one\n".to_string(),
+        },
+    )
+    .await;
+    assert!(matches!(resp, Response::Success { .. }), "got: {resp:?}");
+    let log = paths.stdout_log("repl");
+    for _ in 0..50 {
+        if std::fs::read_to_string(&log).unwrap_or_default() == "got one\n" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "got one\n");
+
+    let p = paths.clone();
+    let responses = tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(p.socket_file()).unwrap();
+        let send = |stream: &mut UnixStream, request: Request| {
+            let encoded = protocol::encode_request(&request).unwrap();
+            stream.write_all(&encoded).unwrap();
+        };
+        let input = |data: &str| Request::Input {
+            name: "repl".to_string(),
+            data: data.to_string(),
+        };
+        send(
+            &mut stream,
+            Request::Attach {
+                name: "repl".to_string(),
+            },
+        );
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut read = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            protocol::decode_response(&line).unwrap()
+        };
+        let mut responses = vec![read()];
+        send(&mut stream, input("two\n"));
+        responses.push(read());
+        // Ending the run ends the session
+        send(&mut stream, input("quit\n"));
+        responses.push(read());
+        responses
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        responses,
+        [
+            Response::Success {
+                message: Some("attached to repl".to_string())
+            },
+            Response::LogLine {
+                name: None,
+                line: "got two".to_string(),
+                stream: Some(LogStream::Stdout),
+                timestamp: None,
+            },
+            Response::Success {
+                message: Some("repl exited".to_string())
+            },
+        ]
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Staggered launches ──────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_stagger_spaces_out_launches() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let configs: HashMap<String, ProcessConfig> = ["a", "b", "c"]
+        .into_iter()
+        .map(|name| (name.to_string(), test_config("sleep 999")))
+        .collect();
+
+    let handle = start_test_daemon(&paths).await;
+    let started = Instant::now();
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: configs.clone(),
+            names: None,
+            env: None,
+            strict: false,
+            stagger: Some(300),
+            progress: false,
+        },
+    )
+    .await;
+    assert_eq!(results_of(&resp, ResultStatus::Succeeded), ["a", "b", "c"]);
+    assert!(started.elapsed() >= Duration::from_millis(600));
+
+    // Nothing launches, so nothing waits
+    let started = Instant::now();
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: Some(300),
+            progress: false,
+        },
+    )
+    .await;
+    assert!(started.elapsed() < Duration::from_millis(300));
+
+    let started = Instant::now();
+    let resp = send_raw_request(
+        &paths,
+        &Request::Restart {
+            names: Some(vec!["a".to_string(), "b".to_string()]),
+            group: None,
+            rolling: false,
+            stagger: Some(300),
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(results_of(&resp, ResultStatus::Succeeded), ["a", "b"]);
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Parallel starts ─────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_start_runs_independent_launches_together_after_dependencies() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let marker = dir.path().join("db-ready");
+    let mut configs = HashMap::new();
+    configs.insert(
+        "db".to_string(),
+        ProcessConfig {
+            ready_check: Some(ReadyCheck::File(marker.display().to_string())),
+            ..test_config(&format!(
+                "sh -c 'sleep 1; touch {}; exec sleep 999'",
+                marker.display()
+            ))
+        },
+    );
+    // Exits straight away unless db is ready when it starts
+    configs.insert(
+        "app".to_string(),
+        ProcessConfig {
+            depends_on: Some(vec!["db".to_string()]),
+            ..test_config(&format!(
+                "sh -c 'test -e {} && exec sleep 999'",
+                marker.display()
+            ))
+        },
+    );
+    configs.insert(
+        "cache".to_string(),
+        ProcessConfig {
+            instances: Some(3),
+            pre_start: Some(config::Hook::Command("sleep 1".to_string())),
+            ..test_config("sleep 999")
+        },
+    );
+
+    let handle = start_test_daemon(&paths).await;
+    let started = Instant::now();
+    let responses = send_streaming_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: true,
+        },
+    )
+    .await;
+    let elapsed = started.elapsed();
+    // One after another, the cache hooks alone would take three seconds
+    assert!(elapsed < Duration::from_millis(2800), "{elapsed:?}");
+
+    let (last, progress) = responses.split_last().unwrap();
+    assert_eq!(
+        results_of(last, ResultStatus::Succeeded),
+        ["app", "cache-0", "cache-1", "cache-2", "db"]
+    );
+    let reported: Vec<&str> = progress
+        .iter()
+        .map(|resp| match resp {
+            Response::StartProgress { result } if result.status == ResultStatus::Succeeded => {
+                result.name.as_str()
+            }
+            other => panic!("unexpected {other:?}"),
+        })
+        .collect();
+    assert_eq!(reported.len(), 5, "{reported:?}");
+    let position = |name| reported.iter().position(|&n| n == name).unwrap();
+    assert!(position("db") < position("app"), "{reported:?}");
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(status_of(&paths, "app").await, ProcessStatus::Online);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Partial failures ────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_start_reports_each_process_and_carries_on_past_failures() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let mut broken = test_config("sleep 999");
+    broken.pre_start = Some(config::Hook::Command("false".to_string()));
+    let configs = HashMap::from([
+        ("broken".to_string(), broken),
+        (
+            "needs-broken".to_string(),
+            ProcessConfig {
+                depends_on: Some(vec!["broken".to_string()]),
+                ..test_config("sleep 999")
+            },
+        ),
+        ("web".to_string(), test_config("sleep 999")),
+    ]);
+    let start = Request::Start {
+        configs,
+        names: None,
+        env: None,
+        strict: false,
+        stagger: None,
+        progress: false,
+    };
+
+    let handle = start_test_daemon(&paths).await;
+    let resp = send_raw_request(&paths, &start).await;
+    assert_eq!(results_of(&resp, ResultStatus::Succeeded), ["web"]);
+    assert_eq!(
+        results_of(&resp, ResultStatus::Skipped),
+        ["needs-broken ('broken' did not start)"]
+    );
+    let failed = results_of(&resp, ResultStatus::Failed);
+    assert!(
+        failed.len() == 1 && failed[0].starts_with("broken (pre_start hook exited"),
+        "{failed:?}"
+    );
+    assert_eq!(status_of(&paths, "web").await, ProcessStatus::Online);
+
+    // Starting again leaves web alone and says so
+    let resp = send_raw_request(&paths, &start).await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Skipped),
+        [
+            "needs-broken ('broken' did not start)",
+            "web (already running)"
+        ]
+    );
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: None,
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(results_of(&resp, ResultStatus::Succeeded), ["web"]);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Dependency cascades ─────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cascade_follows_dependencies() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let depends = |deps: &[&str]| ProcessConfig {
+        depends_on: Some(deps.iter().map(|d| d.to_string()).collect()),
+        ..test_config("sleep 999")
+    };
+    let configs = HashMap::from([
+        ("db".to_string(), test_config("sleep 999")),
+        ("api".to_string(), depends(&["db"])),
+        ("web".to_string(), depends(&["api"])),
+        ("cron".to_string(), test_config("sleep 999")),
+    ]);
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    for name in ["db", "api", "web", "cron"] {
+        wait_for_status(&paths, name, ProcessStatus::Online).await;
+    }
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Restart {
+            names: Some(vec!["db".to_string()]),
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: true,
+        },
+    )
+    .await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["db", "api", "web"]
+    );
+    assert_eq!(info_of(&paths, "web").await.restarts, 1);
+    assert_eq!(info_of(&paths, "cron").await.restarts, 0);
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["db".to_string()]),
+            group: None,
+            cascade: true,
+        },
+    )
+    .await;
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["web", "api", "db"]
+    );
+    assert_eq!(info_of(&paths, "cron").await.status, ProcessStatus::Online);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Scheduled jobs ──────────────────────────────────────────────────
+
+async fn jobs(paths: &Paths) -> Vec<pm3::protocol::JobInfo> {
+    match send_raw_request(paths, &Request::Jobs).await {
+        Response::JobList { jobs } => jobs,
+        other => panic!("expected JobList, got: {other:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_jobs_run_on_their_schedule_only() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+
+    let handle = start_test_daemon(&paths).await;
+
+    let configs = pm3::config::parse_config(
+        r#"
+[web]
+command = "sleep 30"
+
+[jobs.tick]
+schedule = "* * * * * *"
+command = "echo tick"
+
+[jobs.yearly]
+schedule = "@yearly"
+command = "echo never today"
+"#,
+    )
+    .unwrap();
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+
+    // Jobs stay out of the process list and do not run at start
+    let list = send_raw_request(&paths, &Request::List).await;
+    let Response::ProcessList { processes } = &list else {
+        panic!("expected ProcessList, got: {list:?}");
+    };
+    assert_eq!(processes.len(), 1, "{processes:?}");
+    let yearly = jobs(&paths).await.pop().unwrap();
+    assert_eq!(yearly.name, "yearly");
+    assert_eq!(yearly.last_run, None);
+    assert!(yearly.next_run.is_some());
+
+    let mut tick = None;
+    for _ in 0..50 {
+        let current = jobs(&paths).await.remove(0);
+        if current.status == ProcessStatus::Completed {
+            tick = Some(current);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let tick = tick.expect("tick never completed a run");
+    assert!(tick.last_run.is_some());
+    assert_eq!(tick.exit_code, Some(0));
+    assert!(tick.duration_ms.is_some());
+    let log = std::fs::read_to_string(paths.stdout_log("tick")).unwrap();
+    assert!(log.contains("tick"), "{log}");
+
+    // Restarting everything leaves jobs alone; stopping one pauses it
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["tick".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(jobs(&paths).await[0].next_run, None);
+    send_raw_request(
+        &paths,
+        &Request::Restart {
+            names: None,
+            group: None,
+            rolling: false,
+            stagger: None,
+            cascade: false,
+        },
+    )
+    .await;
+    assert_eq!(jobs(&paths).await[1].last_run, None);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Update ──────────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_update_refuses_what_cannot_be_handed_over() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let mut configs = HashMap::new();
+    configs.insert(
+        "term".to_string(),
+        ProcessConfig {
+            tty: Some(true),
+            ..test_config("sleep 999")
+        },
+    );
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "term", ProcessStatus::Online).await;
+
+    let missing = dir.path().join("pm3-new");
+    let resp = send_raw_request(
+        &paths,
+        &Request::Update {
+            exe: missing.to_string_lossy().into_owned(),
+        },
+    )
+    .await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("is not a file")),
+        "got: {resp:?}"
+    );
+
+    // A terminal belongs to this daemon, so the process would lose it
+    let resp = send_raw_request(
+        &paths,
+        &Request::Update {
+            exe: "/bin/sh".to_string(),
+        },
+    )
+    .await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("cannot hand over term")),
+        "got: {resp:?}"
+    );
+    assert!(paths.pid_file().exists());
+    assert!(matches!(
+        send_raw_request(&paths, &Request::Ping).await,
+        Response::Pong
+    ));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Request limits ──────────────────────────────────────────────────
+
+/// Send `bytes` as they are, optionally leaving the connection open, and
+/// read the daemon's answer.
+async fn send_raw_bytes(paths: &Paths, bytes: &[u8], close: bool) -> Response {
+    let socket = paths.socket_file();
+    let bytes = bytes.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(socket).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // The daemon may stop reading, and hang up, before all of it is sent
+        let _ = stream.write_all(&bytes);
+        if close {
+            let _ = stream.shutdown(std::net::Shutdown::Write);
+        }
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        protocol::decode_response(&line).unwrap()
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_daemon_rejects_stalled_oversized_and_malformed_requests() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(
+        &paths,
+        "[daemon]\nrequest_timeout = \"300ms\"\nmax_request_size = \"1K\"\n",
+    );
+    let handle = start_test_daemon(&paths).await;
+
+    let started = Instant::now();
+    let resp = send_raw_bytes(&paths, b"{\"type\":", false).await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("no request received")),
+        "got: {resp:?}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    let resp = send_raw_bytes(&paths, &[b'x'; 4096], false).await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("exceeds 1024 bytes")),
+        "got: {resp:?}"
+    );
+
+    let resp = send_raw_bytes(&paths, b"{\"type\":\"launch\"}\n", true).await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.starts_with("malformed request")),
+        "got: {resp:?}"
+    );
+
+    let resp = send_raw_bytes(&paths, b"\xff\xfe\n", true).await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("not UTF-8")),
+        "got: {resp:?}"
+    );
+
+    // The daemon is none the worse for it
+    assert_eq!(
+        send_raw_request(&paths, &Request::Ping).await,
+        Response::Pong
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Daemon settings ─────────────────────────────────────────────────
+
+fn set_setting(key: &str, value: Option<&str>) -> Request {
+    Request::ConfigSet {
+        key: key.to_string(),
+        value: value.map(str::to_string),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_config_set_writes_settings_and_applies_them() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    write_daemon_settings(&paths, "[daemon]\nsocket_mode = \"0600\"\n");
+    let handle = start_test_daemon(&paths).await;
+    let mode = std::fs::metadata(paths.socket_file())
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    for (key, value) in [("logs.rotate_size", "1K"), ("logs.rotate_keep", "1")] {
+        let resp = send_raw_request(&paths, &set_setting(key, Some(value))).await;
+        assert_eq!(
+            resp,
+            Response::Success {
+                message: Some(format!("{key} = {value}"))
+            }
+        );
+    }
+    let resp = send_raw_request(&paths, &set_setting("daemon.socket_mode", Some("0660"))).await;
+    assert!(
+        matches!(&resp, Response::Success { message: Some(m) } if m.contains("when the daemon restarts")),
+        "got: {resp:?}"
+    );
+    for (key, value) in [("daemon.bogus", "1"), ("logs.rotate_keep", "few")] {
+        let resp = send_raw_request(&paths, &set_setting(key, Some(value))).await;
+        assert!(matches!(resp, Response::Error { .. }), "got: {resp:?}");
+    }
+
+    let saved = std::fs::read_to_string(paths.settings_file()).unwrap();
+    assert!(saved.contains("rotate_size = \"1K\""), "{saved}");
+    assert!(saved.contains("socket_mode = \"0660\""), "{saved}");
+    let resp = send_raw_request(
+        &paths,
+        &Request::ConfigGet {
+            key: Some("logs.rotate_keep".to_string()),
+        },
+    )
+    .await;
+    let Response::Config { settings } = resp else {
+        panic!("expected Config, got: {resp:?}");
+    };
+    assert_eq!(settings.len(), 1);
+    assert_eq!(settings[0].value.as_deref(), Some("1"));
+    assert_eq!(settings[0].default, "3");
+
+    // New runs rotate their logs at the new size and keep one file
+    let mut configs = HashMap::new();
+    configs.insert(
+        "chatty".to_string(),
+        test_config("sh -c 'for i in $(seq 100); do echo line-$i-$(printf \"%060d\" 0); done'"),
+    );
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "chatty", ProcessStatus::Stopped).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(paths.rotated_stdout_log("chatty", 1).exists());
+    assert!(!paths.rotated_stdout_log("chatty", 2).exists());
+    assert!(std::fs::metadata(paths.stdout_log("chatty")).unwrap().len() <= 1024);
+
+    // Request limits apply from the next connection
+    let resp = send_raw_request(&paths, &set_setting("daemon.max_request_size", Some("1K"))).await;
+    assert!(matches!(resp, Response::Success { .. }), "got: {resp:?}");
+    let resp = send_raw_bytes(&paths, &[b'x'; 4096], false).await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.contains("exceeds 1024 bytes")),
+        "got: {resp:?}"
+    );
+    let resp = send_raw_request(&paths, &set_setting("daemon.max_request_size", None)).await;
+    assert!(matches!(resp, Response::Success { .. }), "got: {resp:?}");
+    let resp = send_raw_bytes(&paths, &[b'x'; 4096], true).await;
+    assert!(
+        matches!(&resp, Response::Error { message } if message.starts_with("malformed request")),
+        "got: {resp:?}"
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Audit log ───────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_audit_records_requests_with_sender_and_outcome() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let handle = start_test_daemon(&paths).await;
+
+    let mut web = test_config("sleep 999");
+    web.env = Some(HashMap::from([(
+        "API_TOKEN".to_string(),
+        "hunter2".to_string(),
+    )]));
+    let configs = HashMap::from([("web".to_string(), web)]);
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    send_raw_request(&paths, &Request::List).await;
+    send_raw_request(
+        &paths,
+        &Request::Signal {
+            name: "ghost".to_string(),
+            signal: "HUP".to_string(),
+            group_leader: false,
+        },
+    )
+    .await;
+
+    let resp = send_raw_request(&paths, &Request::Audit { since: None }).await;
+    let Response::Audit { entries } = resp else {
+        panic!("expected Audit, got: {resp:?}");
+    };
+    // Queries are left out
+    let requests: Vec<&str> = entries.iter().map(|e| e.request.as_str()).collect();
+    assert_eq!(requests, ["start", "signal"]);
+    // The test made the temp dir, so it is owned by the uid talking to the daemon
+    let uid = std::os::unix::fs::MetadataExt::uid(&std::fs::metadata(dir.path()).unwrap());
+    assert_eq!(entries[0].uid, Some(uid));
+    assert_eq!(entries[0].pid, Some(std::process::id() as i32));
+    assert_eq!(entries[0].args["configs"], serde_json::json!(["web"]));
+    assert_eq!(entries[0].outcome, "ok");
+    assert!(entries[1].outcome.starts_with("error: "), "{entries:?}");
+    let log = std::fs::read_to_string(paths.audit_log()).unwrap();
+    assert!(!log.contains("hunter2"), "{log}");
+
+    let resp = send_raw_request(
+        &paths,
+        &Request::Audit {
+            since: Some(entries[1].at + 1),
+        },
+    )
+    .await;
+    assert_eq!(resp, Response::Audit { entries: vec![] });
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_listen_tcp_serves_the_same_protocol() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    write_daemon_settings(
+        &paths,
+        &format!("[daemon]\nlisten_tcp = \"{addr}\"\nauth_token = \"s3cret\"\n"),
+    );
+    let handle = start_test_daemon(&paths).await;
+
+    let exchange = move |request: Request| {
+        tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            let auth = protocol::Auth {
+                token: "s3cret".to_string(),
+            };
+            stream
+                .write_all(&protocol::encode_auth(&auth).unwrap())
+                .unwrap();
+            stream
+                .write_all(&protocol::encode_request(&request).unwrap())
+                .unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            protocol::decode_response(&line).unwrap()
+        })
+    };
+
+    assert_eq!(exchange(Request::Ping).await.unwrap(), Response::Pong);
+    let configs = HashMap::from([("web".to_string(), test_config("sleep 999"))]);
+    let resp = exchange(Request::Start {
+        configs,
+        names: None,
+        env: None,
+        strict: false,
+        stagger: None,
+        progress: false,
+    })
+    .await
+    .unwrap();
+    assert!(matches!(resp, Response::Results { .. }), "{resp:?}");
+    // The unix socket keeps working alongside
+    let list = send_raw_request(&paths, &Request::List).await;
+    assert_eq!(list_one(&list, "web").name, "web");
+
+    let resp = exchange(Request::Audit { since: None }).await.unwrap();
+    let Response::Audit { entries } = resp else {
+        panic!("expected Audit, got: {resp:?}");
+    };
+    assert_eq!(entries[0].request, "start");
+    assert_eq!(entries[0].uid, None);
+    let remote = entries[0].remote.as_deref().unwrap();
+    assert!(remote.starts_with("127.0.0.1:"), "{remote}");
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+/// Make an HTTP/1.1 call to the daemon's API, returning the status and the
+/// JSON body.
+async fn http_call(
+    addr: std::net::SocketAddr,
+    method: &'static str,
+    path: &str,
+    token: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> (u16, serde_json::Value) {
+    use std::io::Read;
+    let mut head = format!("{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n");
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    if !body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    let request = format!("{head}Content-Length: {}\r\n\r\n{body}", body.len());
+    tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http_api_mirrors_the_socket_protocol() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    write_daemon_settings(
+        &paths,
+        &format!("[daemon]\nlisten_http = \"{addr}\"\nauth_token = \"s3cret\"\n"),
+    );
+    let handle = start_test_daemon(&paths).await;
+    let token = Some("s3cret");
+
+    let (status, body) = http_call(addr, "GET", "/processes", None, None).await;
+    assert_eq!(status, 401);
+    assert_eq!(body["type"], "error");
+    let (status, _) = http_call(addr, "GET", "/processes", Some("guess"), None).await;
+    assert_eq!(status, 401);
+
+    let configs = serde_json::json!({
+        "web": test_config("sh -c 'echo hello from web; sleep 999'"),
+    });
+    let (status, body) = http_call(
+        addr,
+        "POST",
+        "/processes",
+        token,
+        Some(serde_json::json!({ "configs": configs })),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["type"], "results");
+
+    let (status, body) = http_call(addr, "GET", "/processes", token, None).await;
+    assert_eq!(status, 200);
+    let list: Response = serde_json::from_value(body).unwrap();
+    assert_eq!(list_one(&list, "web").status, ProcessStatus::Online);
+    let (status, body) = http_call(addr, "GET", "/processes/web", token, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["type"], "process_detail");
+
+    let mut lines = serde_json::Value::Null;
+    for _ in 0..50 {
+        let (status, body) =
+            http_call(addr, "GET", "/processes/web/logs?lines=5", token, None).await;
+        assert_eq!(status, 200, "{body}");
+        if body.as_array().is_some_and(|lines| !lines.is_empty()) {
+            lines = body;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(lines[0]["type"], "log_line");
+    assert_eq!(lines[0]["line"], "hello from web");
+
+    let (status, body) = http_call(addr, "POST", "/processes/web/restart", token, None).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["results"][0]["name"], "web");
+    let (status, body) = http_call(addr, "POST", "/processes/ghost/stop", token, None).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["type"], "error");
+    let (status, _) = http_call(addr, "GET", "/processes/ghost/logs", token, None).await;
+    assert_eq!(status, 400);
+
+    // Changes made over HTTP are audited with the caller's address
+    let resp = send_raw_request(&paths, &Request::Audit { since: None }).await;
+    let Response::Audit { entries } = resp else {
+        panic!("expected Audit, got: {resp:?}");
+    };
+    let requests: Vec<&str> = entries.iter().map(|e| e.request.as_str()).collect();
+    assert_eq!(requests, ["start", "restart", "stop"]);
+    assert!(
+        entries[0]
+            .remote
+            .as_deref()
+            .unwrap()
+            .starts_with("127.0.0.1:")
+    );
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http_api_websockets_stream_logs_and_events() {
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::{self, Message};
+
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    write_daemon_settings(
+        &paths,
+        &format!("[daemon]\nlisten_http = \"{addr}\"\nauth_token = \"s3cret\"\n"),
+    );
+    let handle = start_test_daemon(&paths).await;
+
+    // The next text frame as JSON, or None once the socket closes
+    async fn next_frame<S>(socket: &mut S) -> Option<serde_json::Value>
+    where
+        S: futures_util::Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(10), socket.next())
+                .await
+                .expect("no frame within 10s");
+            match message {
+                Some(Ok(Message::Text(text))) => return Some(serde_json::from_str(&text).unwrap()),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    let rejected = tokio_tungstenite::connect_async(format!("ws://{addr}/ws/events")).await;
+    assert!(
+        matches!(rejected, Err(tungstenite::Error::Http(ref response)) if response.status() == 401),
+        "{rejected:?}"
+    );
+
+    // Browsers pass the token in the query string
+    let (mut events, _) =
+        tokio_tungstenite::connect_async(format!("ws://{addr}/ws/events?token=s3cret&name=web"))
+            .await
+            .unwrap();
+
+    let start = Request::Start {
+        configs: HashMap::from([
+            (
+                "web".to_string(),
+                test_config(
+                    "sh -c 'i=0; while true; do echo tick $i; i=$((i+1)); sleep 0.1; done'",
+                ),
+            ),
+            ("quiet".to_string(), test_config("sleep 999")),
+        ]),
+        names: None,
+        env: None,
+        strict: false,
+        stagger: None,
+        progress: false,
+    };
+    send_raw_request(&paths, &start).await;
+
+    let event = next_frame(&mut events).await.unwrap();
+    assert_eq!(event["event"], "start");
+    assert_eq!(event["name"], "web");
+
+    // Other clients send the usual header; only lines written from now on
+    let mut request = format!("ws://{addr}/ws/logs/web?lines=0")
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("Authorization", "Bearer s3cret".parse().unwrap());
+    let (mut logs, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    for _ in 0..3 {
+        let line = next_frame(&mut logs).await.unwrap();
+        assert_eq!(line["type"], "log_line");
+        assert!(
+            line["line"].as_str().unwrap().starts_with("tick "),
+            "{line}"
+        );
+    }
+
+    send_raw_request(
+        &paths,
+        &Request::Stop {
+            names: Some(vec!["quiet".to_string(), "web".to_string()]),
+            group: None,
+            cascade: false,
+        },
+    )
+    .await;
+    let event = next_frame(&mut events).await.unwrap();
+    assert_eq!(event["event"], "exit");
+    assert_eq!(event["name"], "web");
+
+    // A bad request gets its error, then the socket closes
+    let (mut ghost, _) =
+        tokio_tungstenite::connect_async(format!("ws://{addr}/ws/logs/ghost?token=s3cret"))
+            .await
+            .unwrap();
+    let error = next_frame(&mut ghost).await.unwrap();
+    assert_eq!(error["type"], "error");
+    assert!(next_frame(&mut ghost).await.is_none());
+
+    logs.close(None).await.unwrap();
+    events.close(None).await.unwrap();
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http_api_speaks_tls_with_tls_cert() {
+    use std::io::Read;
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = rcgen::CertificateParams::new(vec!["127.0.0.1".to_string()])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+    let (cert_file, key_file) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+    std::fs::write(&cert_file, cert.pem()).unwrap();
+    std::fs::write(&key_file, key.serialize_pem()).unwrap();
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    write_daemon_settings(
+        &paths,
+        &format!(
+            "[daemon]\nlisten_http = \"{addr}\"\nauth_token = \"s3cret\"\n\
+             tls_cert = \"{}\"\ntls_key = \"{}\"\n",
+            cert_file.display(),
+            key_file.display()
+        ),
+    );
+    let handle = start_test_daemon(&paths).await;
+
+    let response = tokio::task::spawn_blocking(move || {
+        let config = pm3::remote::client_config(Some(&cert_file), None).unwrap();
+        let name = pm3::remote::server_name(&addr.to_string()).unwrap();
+        let session = tokio_rustls::rustls::ClientConnection::new(config, name).unwrap();
+        let mut stream = tokio_rustls::rustls::StreamOwned::new(
+            session,
+            std::net::TcpStream::connect(addr).unwrap(),
+        );
+        let request = format!(
+            "GET /ping HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\
+             Authorization: Bearer s3cret\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        String::from_utf8_lossy(&response).into_owned()
+    })
+    .await
+    .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("\"pong\""), "{response}");
+
+    // Plain HTTP gets no answer, so the token never crosses in the clear
+    let plain = tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        response
+    })
+    .await
+    .unwrap();
+    assert!(!plain.starts_with(b"HTTP/"));
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_listen_tcp_turns_away_clients_without_the_token() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    write_daemon_settings(
+        &paths,
+        &format!("[daemon]\nlisten_tcp = \"{addr}\"\nauth_token = \"s3cret\"\n"),
+    );
+    let handle = start_test_daemon(&paths).await;
+
+    let send_lines = move |lines: Vec<Vec<u8>>| {
+        tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            for line in lines {
+                stream.write_all(&line).unwrap();
+            }
+            let _ = stream.shutdown(std::net::Shutdown::Write);
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            protocol::decode_response(&line).unwrap()
+        })
+    };
+    let start = protocol::encode_request(&Request::Start {
+        configs: HashMap::from([("web".to_string(), test_config("sleep 999"))]),
+        names: None,
+        env: None,
+        strict: false,
+        stagger: None,
+        progress: false,
+    })
+    .unwrap();
+    let wrong = protocol::encode_auth(&protocol::Auth {
+        token: "guess".to_string(),
+    })
+    .unwrap();
+
+    for (lines, want) in [
+        (vec![start.clone()], "authentication required"),
+        (vec![wrong, start], "wrong token"),
+    ] {
+        match send_lines(lines).await.unwrap() {
+            Response::Error { message } => assert!(message.contains(want), "{message}"),
+            other => panic!("expected Error, got: {other:?}"),
+        }
+    }
+    // Neither request was carried out
+    let resp = send_raw_request(&paths, &Request::List).await;
+    assert_eq!(resp, Response::ProcessList { processes: vec![] });
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
+
+// ── Stopping ────────────────────────────────────────────────────────
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_slow_stop_does_not_block_other_requests() {
+    let dir = TempDir::new().unwrap();
+    let paths = Paths::with_base(dir.path().to_path_buf());
+    let mut configs = HashMap::new();
+    configs.insert(
+        "stubborn".to_string(),
+        test_config_with_kill(
+            "sh -c 'trap \"\" TERM; while true; do sleep 0.1; done'",
+            Some(2000),
+            None,
+        ),
+    );
+
+    let handle = start_test_daemon(&paths).await;
+    send_raw_request(
+        &paths,
+        &Request::Start {
+            configs,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    wait_for_status(&paths, "stubborn", ProcessStatus::Online).await;
+
+    let stop = {
+        let paths = paths.clone();
+        tokio::spawn(async move {
+            send_raw_request(
+                &paths,
+                &Request::Stop {
+                    names: Some(vec!["stubborn".to_string()]),
+                    group: None,
+                    cascade: false,
+                },
+            )
+            .await
+        })
+    };
+    // Other requests are answered while the process outlives its SIGTERM
+    assert_eq!(
+        wait_for_status(&paths, "stubborn", ProcessStatus::Stopping).await,
+        ProcessStatus::Stopping
+    );
+    let started = Instant::now();
+    let mut other = HashMap::new();
+    other.insert("other".to_string(), test_config("sleep 999"));
+    let resp = send_raw_request(
+        &paths,
+        &Request::Start {
+            configs: other,
+            names: None,
+            env: None,
+            strict: false,
+            stagger: None,
+            progress: false,
+        },
+    )
+    .await;
+    assert_eq!(results_of(&resp, ResultStatus::Succeeded), ["other"]);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(!stop.is_finished());
+
+    let resp = stop.await.unwrap();
+    assert_eq!(
+        results_of(&resp, ResultStatus::Succeeded),
+        ["stubborn (force-killed)"]
+    );
+    assert_eq!(status_of(&paths, "stubborn").await, ProcessStatus::Stopped);
+
+    send_raw_request(&paths, &Request::Kill).await;
+    let _ = handle.await;
+}
@@ -346,6 +346,234 @@ command = "sleep 999"
     );
 }
 
+#[test]
+fn test_e2e_ping_and_daemon_status_never_start_the_daemon() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        "[web]\ncommand = \"sleep 999\"\n",
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .arg("ping")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("daemon is not running"));
+    pm3(&data_dir, work_dir)
+        .args(["daemon", "status"])
+        .assert()
+        .failure();
+    assert!(!data_dir.join("pm3.pid").exists());
+
+    pm3(&data_dir, work_dir).arg("start").assert().success();
+    pm3(&data_dir, work_dir)
+        .arg("ping")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pong"));
+
+    let output = pm3(&data_dir, work_dir)
+        .args(["--json", "daemon", "status"])
+        .output()
+        .unwrap();
+    let Response::DaemonStatus { status } = parse_json_response(&output) else {
+        panic!("expected DaemonStatus");
+    };
+    let daemon_pid = std::fs::read_to_string(data_dir.join("pm3.pid")).unwrap();
+    assert_eq!(status.pid.to_string(), daemon_pid.trim());
+    assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!((status.processes, status.running), (1, 1));
+    assert!(status.socket.ends_with("pm3.sock"));
+
+    kill_daemon(&data_dir, work_dir);
+}
+
+/// Wait for the daemon to send `state` to the notify socket.
+fn expect_notify(notify: &std::os::unix::net::UnixDatagram, state: &str) {
+    let mut buf = [0u8; 256];
+    loop {
+        let n = notify
+            .recv(&mut buf)
+            .unwrap_or_else(|e| panic!("no {state} from the daemon: {e}"));
+        if &buf[..n] == state.as_bytes() {
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_e2e_systemd_socket_activation_and_notify() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::{UnixDatagram, UnixListener};
+    use std::os::unix::process::CommandExt;
+
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        "[env]\ncommand = \"sh -c 'echo notify=$NOTIFY_SOCKET; sleep 999'\"\n",
+    )
+    .unwrap();
+
+    // Stand in for systemd: a socket unit's listener and the notify socket
+    let listener = UnixListener::bind(data_dir.join("pm3.sock")).unwrap();
+    let notify_path = dir.path().join("notify.sock");
+    let notify = UnixDatagram::bind(&notify_path).unwrap();
+    notify
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let fd = listener.as_raw_fd();
+    let mut daemon = std::process::Command::new("sh");
+    daemon
+        .args([
+            "-c",
+            "LISTEN_PID=$$ exec \"$0\" --daemon",
+            env!("CARGO_BIN_EXE_pm3"),
+        ])
+        .env("PM3_DATA_DIR", &data_dir)
+        .env("LISTEN_FDS", "1")
+        .env("NOTIFY_SOCKET", &notify_path)
+        .env("WATCHDOG_USEC", "200000")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // SAFETY: dup2 and fcntl are async-signal-safe
+    unsafe {
+        daemon.pre_exec(move || {
+            let ok = if fd == 3 {
+                libc::fcntl(3, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if ok < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut daemon = daemon.spawn().unwrap();
+    expect_notify(&notify, "READY=1");
+
+    // Requests arrive through the passed socket
+    pm3(&data_dir, work_dir).arg("start").assert().success();
+    expect_notify(&notify, "WATCHDOG=1");
+    std::thread::sleep(Duration::from_millis(300));
+    let stdout = std::fs::read_to_string(data_dir.join("logs").join("env-out.log")).unwrap();
+    assert_eq!(
+        stdout, "notify=\n",
+        "processes must not inherit NOTIFY_SOCKET"
+    );
+
+    pm3(&data_dir, work_dir).arg("kill").assert().success();
+    expect_notify(&notify, "STOPPING=1");
+    daemon.wait().unwrap();
+    // The socket unit still owns its socket
+    assert!(data_dir.join("pm3.sock").exists());
+}
+
+#[test]
+fn test_e2e_daemon_log_records_requests_and_process_lifecycle() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[flaky]
+command = "sh -c 'exit 3'"
+restart = "never"
+"#,
+    )
+    .unwrap();
+    pm3(&data_dir, work_dir).arg("start").assert().success();
+    std::thread::sleep(Duration::from_millis(500));
+
+    let output = pm3(&data_dir, work_dir)
+        .args(["daemon-log", "--lines", "100"])
+        .output()
+        .unwrap();
+    let log = String::from_utf8_lossy(&output.stdout);
+    for expected in [
+        "INFO",
+        "request=\"start\"",
+        "spawned process=flaky",
+        "exited process=\"flaky\"",
+        "code=Some(3)",
+        "not restarting",
+    ] {
+        assert!(log.contains(expected), "missing {expected} in:\n{log}");
+    }
+    // Queries stay out of the log at the default level
+    assert!(!log.contains("request=\"list\""), "{log}");
+
+    kill_daemon(&data_dir, work_dir);
+}
+
+#[test]
+fn test_e2e_daemon_is_detached_from_the_launching_shell() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[web]
+command = "sh -c 'pwd > where.txt; exec sleep 999'"
+"#,
+    )
+    .unwrap();
+    // Relative to the work dir on purpose: the daemon must not depend on it
+    pm3(Path::new("data"), work_dir)
+        .arg("start")
+        .assert()
+        .success();
+
+    let daemon_pid: i32 = std::fs::read_to_string(data_dir.join("pm3.pid"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let stat = std::fs::read_to_string(format!("/proc/{daemon_pid}/stat")).unwrap();
+    // state, ppid, pgrp, session, tty_nr follow the command name
+    let fields: Vec<i64> = stat
+        .rsplit(')')
+        .next()
+        .unwrap()
+        .split_whitespace()
+        .skip(1)
+        .take(4)
+        .map(|f| f.parse().unwrap())
+        .collect();
+    let (session, tty) = (fields[2], fields[3]);
+    assert_ne!(session, i64::from(daemon_pid), "daemon leads a session");
+    assert_ne!(
+        session,
+        i64::from(nix::unistd::getsid(None).unwrap().as_raw()),
+        "daemon shares the launcher's session"
+    );
+    assert_eq!(tty, 0, "daemon has a controlling terminal");
+    assert_eq!(
+        std::fs::read_link(format!("/proc/{daemon_pid}/cwd")).unwrap(),
+        Path::new("/")
+    );
+
+    // Processes still run from the directory of their pm3.toml
+    std::thread::sleep(Duration::from_millis(500));
+    let where_ = std::fs::read_to_string(work_dir.join("where.txt")).unwrap();
+    assert_eq!(
+        Path::new(where_.trim()).canonicalize().unwrap(),
+        work_dir.canonicalize().unwrap()
+    );
+
+    kill_daemon(&data_dir, work_dir);
+}
+
 #[test]
 fn test_e2e_kill_then_list_auto_starts_fresh_daemon() {
     let dir = TempDir::new().unwrap();
@@ -393,6 +621,137 @@ command = "sleep 999"
     kill_daemon(&data_dir, work_dir);
 }
 
+#[test]
+fn test_e2e_crashed_daemon_orphans_are_adopted() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[ticker]
+command = "sh -c 'while true; do echo tick; sleep 0.1; done'"
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir).arg("start").assert().success();
+    std::thread::sleep(Duration::from_millis(500));
+    let pid = get_process_list(&data_dir, work_dir)[0].pid.unwrap();
+    assert!(data_dir.join("journal.json").exists());
+
+    // Crash the daemon: no chance to stop its processes
+    let daemon_pid: i32 = std::fs::read_to_string(data_dir.join("pm3.pid"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let daemon = nix::unistd::Pid::from_raw(daemon_pid);
+    nix::sys::signal::kill(daemon, nix::sys::signal::Signal::SIGKILL).unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    // The next daemon adopts the process and keeps capturing its output
+    let processes = get_process_list(&data_dir, work_dir);
+    assert_eq!(processes.len(), 1);
+    assert_eq!(processes[0].pid, Some(pid));
+    assert_eq!(processes[0].status, ProcessStatus::Online);
+
+    let stdout_log = data_dir.join("logs").join("ticker-out.log");
+    let before = std::fs::read_to_string(&stdout_log)
+        .unwrap()
+        .lines()
+        .count();
+    std::thread::sleep(Duration::from_millis(1000));
+    let after = std::fs::read_to_string(&stdout_log)
+        .unwrap()
+        .lines()
+        .count();
+    assert!(
+        after > before,
+        "log capture should resume: {before} -> {after}"
+    );
+
+    pm3(&data_dir, work_dir)
+        .args(["stop", "ticker"])
+        .assert()
+        .success();
+    let gone = nix::unistd::Pid::from_raw(pid as i32);
+    assert!(
+        nix::sys::signal::kill(gone, None).is_err() || {
+            std::thread::sleep(Duration::from_millis(500));
+            nix::sys::signal::kill(gone, None).is_err()
+        }
+    );
+
+    kill_daemon(&data_dir, work_dir);
+    assert!(!data_dir.join("journal.json").exists());
+}
+
+#[test]
+fn test_e2e_update_keeps_processes_running() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[ticker]
+command = "sh -c 'while true; do echo tick; sleep 0.1; done'"
+
+[short]
+command = "sh -c 'sleep 2; exit 3'"
+restart = "never"
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir).arg("start").assert().success();
+    std::thread::sleep(Duration::from_millis(300));
+    let pid = find_process_pid(&get_process_list(&data_dir, work_dir), "ticker");
+    let daemon_pid = std::fs::read_to_string(data_dir.join("pm3.pid")).unwrap();
+
+    pm3(&data_dir, work_dir)
+        .arg("update")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("daemon updated"));
+
+    // The daemon re-executed in place and adopted its own children
+    assert_eq!(
+        std::fs::read_to_string(data_dir.join("pm3.pid")).unwrap(),
+        daemon_pid
+    );
+    let processes = get_process_list(&data_dir, work_dir);
+    assert_eq!(find_process_pid(&processes, "ticker"), pid);
+    let stdout_log = data_dir.join("logs").join("ticker-out.log");
+    let before = std::fs::read_to_string(&stdout_log)
+        .unwrap()
+        .lines()
+        .count();
+    std::thread::sleep(Duration::from_millis(1000));
+    let after = std::fs::read_to_string(&stdout_log)
+        .unwrap()
+        .lines()
+        .count();
+    assert!(
+        after > before,
+        "log capture should resume: {before} -> {after}"
+    );
+
+    // Still its children, so their exit codes are not lost
+    std::thread::sleep(Duration::from_millis(1500));
+    let short = get_process_list(&data_dir, work_dir)
+        .into_iter()
+        .find(|p| p.name == "short")
+        .unwrap();
+    assert_eq!(short.status, ProcessStatus::Crashed);
+    assert_eq!(short.exit_code, Some(3));
+
+    kill_daemon(&data_dir, work_dir);
+}
+
 // ── Step 8: Start command ───────────────────────────────────────────
 
 #[test]
@@ -428,6 +787,41 @@ command = "sleep 999"
     kill_daemon(&data_dir, work_dir);
 }
 
+#[test]
+fn test_e2e_start_reports_failures_per_process() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[web]
+command = "sleep 999"
+
+[broken]
+command = "sleep 999"
+pre_start = "false"
+"#,
+    )
+    .unwrap();
+
+    // web comes up, broken is named with its reason, and the exit code says
+    // something failed
+    pm3(&data_dir, work_dir)
+        .arg("start")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("started: web"))
+        .stderr(predicate::str::contains("broken: pre_start hook exited"));
+
+    let processes = get_process_list(&data_dir, work_dir);
+    let web = processes.iter().find(|p| p.name == "web").unwrap();
+    assert_eq!(web.status, ProcessStatus::Online);
+
+    kill_daemon(&data_dir, work_dir);
+}
+
 #[test]
 fn test_e2e_start_two_processes_running() {
     let dir = TempDir::new().unwrap();
@@ -606,6 +1000,144 @@ fn test_e2e_list_no_processes_shows_message() {
     kill_daemon(&data_dir, work_dir);
 }
 
+#[test]
+fn test_e2e_profiles_and_local_daemons_are_isolated() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        "[web]\ncommand = \"sleep 999\"\n",
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .args(["--profile", "shop", "start"])
+        .assert()
+        .success();
+    assert!(data_dir.join("profiles/shop/pm3.sock").exists());
+
+    // The default daemon and other profiles have their own tables
+    assert!(get_process_list(&data_dir, work_dir).is_empty());
+    pm3(&data_dir, work_dir)
+        .args(["--local", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("local ("))
+        .stdout(predicate::str::contains("no processes running"));
+    assert!(work_dir.join(".pm3/pm3.sock").exists());
+
+    pm3(&data_dir, work_dir)
+        .args(["--profile", "shop", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("shop"))
+        .stdout(predicate::str::contains("web"));
+    pm3(&data_dir, work_dir)
+        .args(["--profile", "../shop", "list"])
+        .assert()
+        .failure();
+
+    for flags in [&["--profile", "shop"][..], &["--local"]] {
+        let _ = pm3(&data_dir, work_dir).args(flags).arg("kill").output();
+    }
+    kill_daemon(&data_dir, work_dir);
+}
+
+/// A CA and, signed by it, a certificate for 127.0.0.1 and a client
+/// certificate, written as PEM files into `dir`.
+fn write_test_certs(dir: &Path) {
+    let ca_key = rcgen::KeyPair::generate().unwrap();
+    let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    std::fs::write(
+        dir.join("ca.pem"),
+        ca_params.self_signed(&ca_key).unwrap().pem(),
+    )
+    .unwrap();
+    let issuer = rcgen::Issuer::new(ca_params, ca_key);
+    for (name, san) in [("server", "127.0.0.1"), ("client", "client")] {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec![san.to_string()])
+            .unwrap()
+            .signed_by(&key, &issuer)
+            .unwrap();
+        std::fs::write(dir.join(format!("{name}.pem")), cert.pem()).unwrap();
+        std::fs::write(dir.join(format!("{name}-key.pem")), key.serialize_pem()).unwrap();
+    }
+}
+
+#[test]
+fn test_e2e_host_over_tls_with_token_or_client_certificate() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+    let client_dir = dir.path().join("client");
+    write_test_certs(work_dir);
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let host = format!("127.0.0.1:{port}");
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(
+        data_dir.join("daemon.toml"),
+        format!(
+            "[daemon]\nlisten_tcp = \"{host}\"\nauth_token = \"s3cret\"\n\
+             tls_cert = \"{0}/server.pem\"\ntls_key = \"{0}/server-key.pem\"\n\
+             tls_client_ca = \"{0}/ca.pem\"\n",
+            work_dir.display()
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        "[web]\ncommand = \"sleep 999\"\n",
+    )
+    .unwrap();
+    pm3(&data_dir, work_dir).arg("start").assert().success();
+
+    // The remote client never touches its own data directory
+    let ca = work_dir.join("ca.pem");
+    pm3(&client_dir, work_dir)
+        .env("PM3_TOKEN", "s3cret")
+        .args(["--host", &host, "--tls-ca"])
+        .arg(&ca)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("web"));
+    assert!(!client_dir.join("pm3.sock").exists());
+
+    // A client certificate stands in for the token
+    pm3(&client_dir, work_dir)
+        .args(["--host", &host, "--tls-ca"])
+        .arg(&ca)
+        .arg("--tls-cert")
+        .arg(work_dir.join("client.pem"))
+        .arg("--tls-key")
+        .arg(work_dir.join("client-key.pem"))
+        .args(["stop", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stopped: web"));
+
+    pm3(&client_dir, work_dir)
+        .args(["--host", &host, "--tls-ca"])
+        .arg(&ca)
+        .arg("list")
+        .assert()
+        .stderr(predicate::str::contains("authentication required"));
+    pm3(&client_dir, work_dir)
+        .args(["--host", &host, "--token", "s3cret", "daemon-log"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("local daemon only"));
+
+    kill_daemon(&data_dir, work_dir);
+}
+
 // ── Full lifecycle ──────────────────────────────────────────────────
 
 #[test]
@@ -776,7 +1308,7 @@ command = "sh -c 'for i in 1 2 3 4 5 6 7 8 9 10; do echo line$i; done'"
 }
 
 #[test]
-fn test_e2e_log_no_name_shows_interleaved() {
+fn test_e2e_log_json_lines() {
     let dir = TempDir::new().unwrap();
     let work_dir = dir.path();
     let data_dir = dir.path().join("data");
@@ -784,11 +1316,8 @@ fn test_e2e_log_no_name_shows_interleaved() {
     std::fs::write(
         work_dir.join("pm3.toml"),
         r#"
-[alpha]
-command = "sh -c 'echo alpha_line'"
-
-[beta]
-command = "sh -c 'echo beta_line'"
+[talker]
+command = "sh -c 'echo to_out; echo to_err >&2'"
 "#,
     )
     .unwrap();
@@ -796,10 +1325,60 @@ command = "sh -c 'echo beta_line'"
     pm3(&data_dir, work_dir).arg("start").assert().success();
     std::thread::sleep(Duration::from_millis(500));
 
-    let output = pm3(&data_dir, work_dir).arg("log").output().unwrap();
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    assert!(
+    for args in [&["--json", "log", "talker"][..], &["--json", "log"]] {
+        let output = pm3(&data_dir, work_dir).args(args).output().unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!({
+                    "process": "talker",
+                    "stream": "stdout",
+                    "timestamp": null,
+                    "line": "to_out",
+                }),
+                serde_json::json!({
+                    "process": "talker",
+                    "stream": "stderr",
+                    "timestamp": null,
+                    "line": "to_err",
+                }),
+            ],
+            "args: {args:?}"
+        );
+    }
+
+    kill_daemon(&data_dir, work_dir);
+}
+
+#[test]
+fn test_e2e_log_no_name_shows_interleaved() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[alpha]
+command = "sh -c 'echo alpha_line'"
+
+[beta]
+command = "sh -c 'echo beta_line'"
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir).arg("start").assert().success();
+    std::thread::sleep(Duration::from_millis(500));
+
+    let output = pm3(&data_dir, work_dir).arg("log").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
         stdout.contains("alpha_line"),
         "should contain alpha_line, got: {stdout}"
     );
@@ -909,3 +1488,614 @@ command = "sh -c 'echo worker_output'"
 
     kill_daemon(&data_dir, work_dir);
 }
+
+// ── Parse command ───────────────────────────────────────────────────
+
+#[test]
+fn test_e2e_parse_shows_program_and_args() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    pm3(&data_dir, work_dir)
+        .args(["parse", "bash -c 'echo hello world'"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("bash"))
+        .stdout(predicate::str::contains("[0] -c"))
+        .stdout(predicate::str::contains("[1] echo hello world"))
+        .stdout(predicate::str::contains("warning").not());
+
+    // parse never needs the daemon
+    assert!(!data_dir.join("pm3.sock").exists());
+}
+
+#[test]
+fn test_e2e_parse_warns_about_shell_operators() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    let output = pm3(&data_dir, work_dir)
+        .args([
+            "--json",
+            "parse",
+            "doppler run -- surreal start && echo $HOME",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["program"], "doppler");
+    assert_eq!(value["args"][0], "run");
+    let warnings = value["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 2);
+}
+
+#[test]
+fn test_e2e_parse_unbalanced_quote_errors() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    pm3(&data_dir, work_dir)
+        .args(["parse", "echo 'unterminated"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid command"));
+}
+
+// ── Readiness ───────────────────────────────────────────────────────
+
+#[test]
+fn test_e2e_start_wait_blocks_until_ready() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[web]
+command = "sh -c 'sleep 1; touch ready; sleep 999'"
+ready_check = { file = "ready" }
+
+[never]
+command = "sleep 999"
+ready_check = { file = "missing" }
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .args(["start", "--wait", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("online:"));
+    let processes = get_process_list(&data_dir, work_dir);
+    let web = processes.iter().find(|p| p.name == "web").unwrap();
+    assert_eq!(web.status, ProcessStatus::Online);
+
+    pm3(&data_dir, work_dir)
+        .args(["start", "--wait", "--wait-timeout", "500ms", "never"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("waiting for: never"));
+
+    kill_daemon(&data_dir, work_dir);
+}
+
+// ── Compose import/export ───────────────────────────────────────────
+
+#[test]
+fn test_e2e_compose_import_then_export() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("docker-compose.yml"),
+        r#"
+services:
+  api:
+    image: node:20
+    command: node server.js
+    environment:
+      PORT: "3000"
+    restart: always
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .args(["import", "docker-compose.yml"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--services-as-processes"));
+
+    pm3(&data_dir, work_dir)
+        .args(["import", "docker-compose.yml", "--services-as-processes"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("ignoring image"));
+    let toml = std::fs::read_to_string(work_dir.join("pm3.toml")).unwrap();
+    assert!(toml.contains("command = \"node server.js\""), "{toml}");
+
+    // Refuses to clobber an existing config
+    pm3(&data_dir, work_dir)
+        .args(["import", "docker-compose.yml", "--services-as-processes"])
+        .assert()
+        .failure();
+
+    pm3(&data_dir, work_dir)
+        .args(["export", "--format", "compose"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("command: node server.js"))
+        .stdout(predicate::str::contains("restart: always"));
+
+    // Neither command needs the daemon
+    assert!(!data_dir.join("pm3.sock").exists());
+}
+
+#[test]
+fn test_e2e_import_supervisord_and_systemd() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("supervisord.conf"),
+        "[program:worker]\ncommand=python worker.py\nuser=app\nautorestart=true\n",
+    )
+    .unwrap();
+    std::fs::write(
+        work_dir.join("myapp.service"),
+        "[Service]\nExecStart=/usr/bin/myapp --serve\nRestart=on-failure\nLimitNOFILE=4096\n",
+    )
+    .unwrap();
+
+    // supervisord configs are recognised without a flag
+    pm3(&data_dir, work_dir)
+        .args(["import", "supervisord.conf", "--output", "a.toml"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("user `app` dropped"));
+    let configs = pm3::config::load_config(&work_dir.join("a.toml")).unwrap();
+    assert_eq!(configs["worker"].command, "python worker.py");
+
+    pm3(&data_dir, work_dir)
+        .args(["import", "--systemd", "myapp.service", "--output", "b.toml"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("ignoring LimitNOFILE"));
+    let configs = pm3::config::load_config(&work_dir.join("b.toml")).unwrap();
+    assert_eq!(configs["myapp"].command, "/usr/bin/myapp --serve");
+}
+
+// ── Init ────────────────────────────────────────────────────────────
+
+#[test]
+fn test_e2e_init_detects_node_project() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("package.json"),
+        r#"{"scripts": {"start": "node server.js", "worker": "node worker.js"}}"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .arg("init")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("web, worker"));
+    let toml = std::fs::read_to_string(work_dir.join("pm3.toml")).unwrap();
+    assert!(toml.contains("command = \"npm start\""), "{toml}");
+    assert!(toml.contains("ready_check = { port = 3000 }"), "{toml}");
+    pm3::config::parse_config(&toml).unwrap();
+
+    // Refuses to clobber an existing config
+    pm3(&data_dir, work_dir)
+        .arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    pm3(&data_dir, work_dir)
+        .args(["init", "--template", "static", "--output", "static.toml"])
+        .assert()
+        .success();
+    let toml = std::fs::read_to_string(work_dir.join("static.toml")).unwrap();
+    assert!(toml.contains("http.server"), "{toml}");
+    assert!(!data_dir.join("pm3.sock").exists());
+}
+
+#[test]
+fn test_e2e_init_interactive_writes_answers() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    pm3(&data_dir, work_dir)
+        .args(["init", "--interactive"])
+        .write_stdin("api\n\nsleep 999\n\nalways\n3\n\nn\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Command:"))
+        .stdout(predicate::str::contains("api (pm3.toml)"));
+    let configs = pm3::config::load_config(&work_dir.join("pm3.toml")).unwrap();
+    assert_eq!(configs["api"].command, "sleep 999");
+    assert_eq!(configs["api"].max_restarts, Some(3));
+
+    // Running out of answers leaves no config behind
+    std::fs::remove_file(work_dir.join("pm3.toml")).unwrap();
+    pm3(&data_dir, work_dir)
+        .args(["init", "--interactive"])
+        .write_stdin("api\n")
+        .assert()
+        .failure();
+    assert!(!work_dir.join("pm3.toml").exists());
+}
+
+// ── Backup and restore ──────────────────────────────────────────────
+
+#[test]
+fn test_e2e_backup_and_restore_history() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+    let archive = dir.path().join("state.tar.zst");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[once]
+command = "true"
+restart = "never"
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir).args(["start"]).assert().success();
+    std::thread::sleep(Duration::from_millis(500));
+
+    // Backups work against a live daemon, restores do not
+    pm3(&data_dir, work_dir)
+        .args(["backup", archive.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("history"));
+    pm3(&data_dir, work_dir)
+        .args(["restore", archive.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pm3 kill"));
+    kill_daemon(&data_dir, work_dir);
+
+    let restored_dir = dir.path().join("restored");
+    pm3(&restored_dir, work_dir)
+        .args(["restore", archive.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(restored_dir.join("history").join("once.jsonl").exists());
+}
+
+// ── Daemon bootstrap failures ───────────────────────────────────────
+
+#[test]
+fn test_e2e_daemon_startup_failure_shows_daemon_output() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    // A directory squatting on the socket path makes the daemon fail to bind
+    std::fs::create_dir_all(data_dir.join("pm3.sock")).unwrap();
+
+    pm3(&data_dir, work_dir)
+        .arg("list")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("daemon output"))
+        .stderr(predicate::str::contains("Is a directory"));
+
+    assert!(data_dir.join("bootstrap.log").exists());
+}
+
+// ── Plugins ─────────────────────────────────────────────────────────
+
+#[test]
+fn test_e2e_runs_command_plugin_from_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+    let bin_dir = dir.path().join("bin");
+    std::fs::create_dir_all(&bin_dir).unwrap();
+
+    let plugin = bin_dir.join("pm3-hello");
+    std::fs::write(
+        &plugin,
+        "#!/bin/sh\necho \"hello $1 socket=$PM3_SOCKET\"\nexit 7\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = std::env::join_paths(
+        std::iter::once(bin_dir.clone())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .env("PATH", &path)
+        .args(["hello", "world"])
+        .assert()
+        .code(7)
+        .stdout(predicate::str::contains(format!(
+            "hello world socket={}",
+            data_dir.join("pm3.sock").display()
+        )));
+
+    pm3(&data_dir, work_dir)
+        .env("PATH", &path)
+        .arg("missing")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no pm3-missing found on PATH"));
+}
+
+#[test]
+fn test_e2e_startup_prints_unit() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    pm3(&data_dir, work_dir)
+        .env("XDG_CONFIG_HOME", work_dir.join("config"))
+        .arg("startup")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(" --daemon\n"))
+        .stdout(predicate::str::contains(format!(
+            "PM3_DATA_DIR={}",
+            data_dir.display()
+        )))
+        .stdout(predicate::str::contains("resurrect"))
+        .stdout(predicate::str::contains("WantedBy=default.target"))
+        .stderr(predicate::str::contains("systemd/user/pm3.service"));
+
+    pm3(&data_dir, work_dir)
+        .arg("startup")
+        .arg("--system")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("WantedBy=multi-user.target"))
+        .stderr(predicate::str::contains("/etc/systemd/system/pm3.service"));
+
+    pm3(&data_dir, work_dir)
+        .args(["startup", "--platform", "openrc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("#!/sbin/openrc-run\n"))
+        .stdout(predicate::str::contains(" resurrect\n"))
+        .stderr(predicate::str::contains("/etc/init.d/pm3"));
+    pm3(&data_dir, work_dir)
+        .args(["startup", "--platform", "sysv", "--user"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "SysV init has no per-user services",
+        ));
+
+    // Nothing was installed, so there is nothing to remove
+    pm3(&data_dir, work_dir)
+        .env("XDG_CONFIG_HOME", work_dir.join("config"))
+        .arg("unstartup")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no startup unit at"));
+    assert!(!data_dir.join("pm3.pid").exists());
+}
+
+#[test]
+fn test_e2e_run_pipeline() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[migrate]
+command = "true"
+kind = "task"
+
+[deploy]
+command = "true"
+kind = "task"
+after = ["migrate"]
+
+[broken]
+command = "false"
+kind = "task"
+after = ["migrate"]
+
+[release]
+command = "true"
+kind = "task"
+after = ["broken"]
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .args(["run", "--pipeline", "deploy"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("migrate:.*succeeded").unwrap())
+        .stdout(predicate::str::is_match("deploy:.*succeeded").unwrap())
+        .stdout(predicate::str::contains("deploy (2 tasks)"));
+
+    pm3(&data_dir, work_dir)
+        .args(["run", "--pipeline", "release"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_match("broken:.*failed").unwrap())
+        .stdout(predicate::str::is_match("release:.*skipped").unwrap())
+        .stderr(predicate::str::contains(
+            "release (1 succeeded, 1 failed, 1 skipped)",
+        ));
+
+    pm3(&data_dir, work_dir)
+        .args(["run", "--pipeline", "nope"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no process named `nope`"));
+
+    kill_daemon(&data_dir, work_dir);
+}
+
+#[test]
+fn test_e2e_bench_reports_on_a_scratch_daemon() {
+    let dir = TempDir::new().unwrap();
+    let data_dir = dir.path().join("data");
+    let work_dir = dir.path();
+
+    let output = pm3(&data_dir, work_dir)
+        .args(["--json", "bench", "--processes", "2", "--duration", "2s"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["processes"], 2);
+    assert!(report["restarts"].as_u64().unwrap() >= 1, "{report}");
+    assert!(report["log_bytes"].as_u64().unwrap() > 0, "{report}");
+
+    // The user's daemon is never started
+    assert!(!data_dir.join("pm3.pid").exists());
+}
+
+/// Poll the process list until `check` passes or five seconds go by.
+fn wait_for_list(data_dir: &Path, work_dir: &Path, check: impl Fn(&[ProcessInfo]) -> bool) {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let processes = get_process_list(data_dir, work_dir);
+        if check(&processes) {
+            return;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "condition not reached: {processes:?}"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn history_len(data_dir: &Path, work_dir: &Path, name: &str) -> usize {
+    let output = pm3(data_dir, work_dir)
+        .args(["--json", "history", name])
+        .output()
+        .unwrap();
+    match parse_json_response(&output) {
+        Response::History { runs } => runs.len(),
+        other => panic!("expected History, got: {other:?}"),
+    }
+}
+
+#[test]
+fn test_e2e_virtual_time_drives_backoff_and_cron() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    std::fs::write(
+        work_dir.join("pm3.toml"),
+        r#"
+[flaky]
+command = "sh -c 'exit 1'"
+restart = "always"
+max_restarts = 2
+min_uptime = 3600000
+
+[nightly]
+command = "echo ran"
+kind = "task"
+cron_restart = "0 0 3 * * *"
+"#,
+    )
+    .unwrap();
+
+    pm3(&data_dir, work_dir)
+        .env("PM3_VIRTUAL_TIME", "1")
+        .arg("start")
+        .assert()
+        .success();
+    let info = |processes: &[ProcessInfo], name: &str| {
+        processes.iter().find(|p| p.name == name).unwrap().clone()
+    };
+
+    // The backoff only runs out when the clock is moved past it
+    wait_for_list(&data_dir, work_dir, |list| {
+        info(list, "flaky").status == ProcessStatus::Starting
+            && info(list, "nightly").status == ProcessStatus::Completed
+    });
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        info(&get_process_list(&data_dir, work_dir), "flaky").restarts,
+        0
+    );
+
+    pm3(&data_dir, work_dir)
+        .args(["advance-clock", "100ms"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("virtual clock at +100ms"));
+    wait_for_list(&data_dir, work_dir, |list| {
+        info(list, "flaky").restarts == 1
+    });
+    pm3(&data_dir, work_dir)
+        .args(["advance-clock", "200ms"])
+        .assert()
+        .success();
+    wait_for_list(&data_dir, work_dir, |list| {
+        info(list, "flaky").status == ProcessStatus::Errored
+    });
+
+    // A nightly schedule fires once per virtual day
+    let runs = history_len(&data_dir, work_dir, "nightly");
+    for day in 1..=2 {
+        pm3(&data_dir, work_dir)
+            .args(["advance-clock", "1d"])
+            .assert()
+            .success();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while history_len(&data_dir, work_dir, "nightly") < runs + day {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "day {day} did not run"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+    assert_eq!(history_len(&data_dir, work_dir, "nightly"), runs + 2);
+
+    kill_daemon(&data_dir, work_dir);
+}
+
+#[test]
+fn test_e2e_advance_clock_needs_virtual_time() {
+    let dir = TempDir::new().unwrap();
+    let work_dir = dir.path();
+    let data_dir = dir.path().join("data");
+
+    pm3(&data_dir, work_dir)
+        .args(["advance-clock", "1s"])
+        .assert()
+        .stderr(predicate::str::contains("PM3_VIRTUAL_TIME=1"));
+
+    kill_daemon(&data_dir, work_dir);
+}